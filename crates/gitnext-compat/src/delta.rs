@@ -0,0 +1,314 @@
+//! Git delta encoding and decoding
+//!
+//! A delta is a pair of varint sizes (base, result) followed by copy and insert
+//! instructions. Copy instructions reference a range of the base object; insert
+//! instructions carry up to 127 literal bytes.
+
+use crate::pack::MAX_PREALLOCATION;
+use crate::{CompatError, Result};
+use gitnext_core::GitNextError;
+use std::collections::HashMap;
+
+/// Granularity at which base objects are indexed and matches are searched
+const BLOCK: usize = 16;
+
+/// Candidate positions kept per block, bounding the work on repetitive input
+const MAX_CANDIDATES: usize = 64;
+
+/// Longest copy emitted in one instruction (the largest size older readers accept)
+const MAX_COPY: usize = 0x10000;
+
+/// Longest literal run in one insert instruction
+const MAX_INSERT: usize = 0x7f;
+
+fn invalid(message: impl Into<String>) -> CompatError {
+    CompatError::Object(GitNextError::InvalidFormat(message.into()))
+}
+
+/// Read a little-endian base-128 size, returning the value and the bytes consumed
+pub(crate) fn read_size(data: &[u8]) -> Result<(usize, usize)> {
+    let mut value: usize = 0;
+    let mut shift = 0;
+
+    for (i, &byte) in data.iter().enumerate() {
+        if shift >= usize::BITS {
+            return Err(invalid("Delta size overflows"));
+        }
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+
+    Err(invalid("Truncated delta size"))
+}
+
+/// Apply `delta` to `base`, producing the target object content
+pub fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let (base_size, used) = read_size(delta)?;
+    let mut pos = used;
+    if base_size != base.len() {
+        return Err(invalid(format!("Delta base size mismatch: expected {}, found {}", base_size, base.len())));
+    }
+
+    let (result_size, used) = read_size(&delta[pos..])?;
+    pos += used;
+    let mut result = Vec::with_capacity(result_size.min(MAX_PREALLOCATION));
+
+    while pos < delta.len() {
+        let op = delta[pos];
+        pos += 1;
+
+        if op & 0x80 != 0 {
+            // Copy: bits 0-3 select offset bytes, bits 4-6 select size bytes
+            let mut read_field = |first_bit: u32, len: u32| -> Result<usize> {
+                let mut value = 0usize;
+                for i in 0..len {
+                    if op & (1 << (first_bit + i)) != 0 {
+                        let byte = *delta.get(pos).ok_or_else(|| invalid("Truncated delta copy"))?;
+                        pos += 1;
+                        value |= (byte as usize) << (8 * i);
+                    }
+                }
+                Ok(value)
+            };
+            let offset = read_field(0, 4)?;
+            let size = match read_field(4, 3)? {
+                0 => 0x10000,
+                size => size,
+            };
+
+            let end = offset.checked_add(size)
+                .filter(|&end| end <= base.len())
+                .ok_or_else(|| invalid("Delta copy exceeds base object"))?;
+            result.extend_from_slice(&base[offset..end]);
+        } else if op != 0 {
+            let size = op as usize;
+            let literal = delta.get(pos..pos + size)
+                .ok_or_else(|| invalid("Truncated delta insert"))?;
+            result.extend_from_slice(literal);
+            pos += size;
+        } else {
+            return Err(invalid("Reserved delta opcode 0"));
+        }
+        // Copies are cheap to encode, so stop before a forged delta grows huge
+        if result.len() > result_size {
+            return Err(invalid(format!("Delta result exceeds its size of {}", result_size)));
+        }
+    }
+
+    if result.len() != result_size {
+        return Err(invalid(format!("Delta result size mismatch: expected {}, found {}", result_size, result.len())));
+    }
+
+    Ok(result)
+}
+
+fn write_size(out: &mut Vec<u8>, mut size: usize) {
+    loop {
+        let byte = (size & 0x7f) as u8;
+        size >>= 7;
+        if size == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_insert(out: &mut Vec<u8>, literal: &[u8]) {
+    for chunk in literal.chunks(MAX_INSERT) {
+        out.push(chunk.len() as u8);
+        out.extend_from_slice(chunk);
+    }
+}
+
+fn write_copy(out: &mut Vec<u8>, mut offset: usize, mut size: usize) {
+    while size > 0 {
+        let chunk = size.min(MAX_COPY);
+        let op_pos = out.len();
+        let mut op = 0x80u8;
+        out.push(0);
+        for i in 0..4 {
+            let byte = (offset >> (8 * i)) as u8;
+            if byte != 0 {
+                op |= 1 << i;
+                out.push(byte);
+            }
+        }
+        // A size of 0x10000 is encoded by omitting every size byte
+        let encoded_size = if chunk == MAX_COPY { 0 } else { chunk };
+        for i in 0..3 {
+            let byte = (encoded_size >> (8 * i)) as u8;
+            if byte != 0 {
+                op |= 0x10 << i;
+                out.push(byte);
+            }
+        }
+        out[op_pos] = op;
+        offset += chunk;
+        size -= chunk;
+    }
+}
+
+/// Index of a base object's blocks, reusable across many delta targets
+pub struct DeltaIndex<'a> {
+    base: &'a [u8],
+    blocks: HashMap<&'a [u8], Vec<usize>>,
+}
+
+impl<'a> DeltaIndex<'a> {
+    pub fn new(base: &'a [u8]) -> Self {
+        let mut blocks: HashMap<&[u8], Vec<usize>> = HashMap::new();
+        for (i, block) in base.chunks_exact(BLOCK).enumerate() {
+            let positions = blocks.entry(block).or_default();
+            if positions.len() < MAX_CANDIDATES {
+                positions.push(i * BLOCK);
+            }
+        }
+        Self { base, blocks }
+    }
+
+    /// Encode `target` as a delta against the base, giving up once the delta would
+    /// exceed `max_size` bytes
+    pub fn encode(&self, target: &[u8], max_size: usize) -> Option<Vec<u8>> {
+        let base = self.base;
+        if u32::try_from(base.len()).is_err() {
+            return None;
+        }
+
+        let mut out = Vec::new();
+        write_size(&mut out, base.len());
+        write_size(&mut out, target.len());
+
+        let mut literal_start = 0;
+        let mut i = 0;
+        while i + BLOCK <= target.len() {
+            let mut best: Option<(usize, usize, usize)> = None; // (base offset, length, backtrack)
+            if let Some(candidates) = self.blocks.get(&target[i..i + BLOCK]) {
+                for &candidate in candidates {
+                    let forward = base[candidate..].iter()
+                        .zip(&target[i..])
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    // Grow the match backwards over bytes that would otherwise be literals
+                    let backward = base[..candidate].iter().rev()
+                        .zip(target[literal_start..i].iter().rev())
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    if best.is_none_or(|(_, length, back)| forward + backward > length + back) {
+                        best = Some((candidate, forward, backward));
+                    }
+                }
+            }
+
+            match best {
+                Some((candidate, length, back)) if length >= BLOCK => {
+                    write_insert(&mut out, &target[literal_start..i - back]);
+                    write_copy(&mut out, candidate - back, length + back);
+                    i += length;
+                    literal_start = i;
+                }
+                _ => i += 1,
+            }
+
+            if out.len() + (i - literal_start) > max_size {
+                return None;
+            }
+        }
+
+        write_insert(&mut out, &target[literal_start..]);
+        (out.len() <= max_size).then_some(out)
+    }
+}
+
+/// Encode `target` as a delta against `base`
+pub fn create_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    DeltaIndex::new(base).encode(target, usize::MAX).expect("an unbounded delta always fits")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_apply_delta_copy_and_insert() {
+        let base = b"hello world";
+        // Base size 11, result size 16
+        let delta = [
+            11, 16,
+            0x80 | 0x10, 6,          // copy offset 0, size 6: "hello "
+            5, b't', b'h', b'e', b'r', b'e', // insert "there"
+            0x80 | 0x01 | 0x10, 6, 5, // copy offset 6, size 5: "world"
+        ];
+        assert_eq!(apply_delta(base, &delta).unwrap(), b"hello thereworld");
+    }
+
+    #[test]
+    fn test_apply_delta_rejects_bad_input() {
+        // Wrong base size
+        assert!(apply_delta(b"abc", &[4, 1, 1, b'x']).is_err());
+        // Copy past the end of the base
+        assert!(apply_delta(b"abc", &[3, 4, 0x80 | 0x10, 4]).is_err());
+        // Result size mismatch
+        assert!(apply_delta(b"abc", &[3, 2, 1, b'x']).is_err());
+    }
+
+    #[test]
+    fn test_apply_delta_rejects_forged_result_size() {
+        // A result size near usize::MAX must not be reserved up front
+        let mut delta = vec![3];
+        write_size(&mut delta, usize::MAX);
+        delta.extend_from_slice(&[0x80 | 0x10, 3]);
+        assert!(apply_delta(b"abc", &delta).is_err());
+
+        // Copies past the declared size stop at the first one over
+        let mut delta = vec![3, 3];
+        for _ in 0..1_000_000 {
+            delta.extend_from_slice(&[0x80 | 0x10, 3]);
+        }
+        assert!(apply_delta(b"abc", &delta).is_err());
+    }
+
+    #[test]
+    fn test_create_delta_reuses_base() {
+        let base: Vec<u8> = (0..4096u32).flat_map(|i| i.to_le_bytes()).collect();
+        let mut target = base.clone();
+        target[1000] ^= 0xff;
+        target.extend_from_slice(b"appended");
+
+        let delta = create_delta(&base, &target);
+        assert!(delta.len() < 64, "delta of {} bytes", delta.len());
+        assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+
+        // Large copies are split into 64 KiB instructions
+        let big = vec![7u8; 200_000];
+        let delta = create_delta(&big, &big);
+        assert_eq!(apply_delta(&big, &delta).unwrap(), big);
+
+        // The size limit aborts unhelpful deltas
+        assert!(DeltaIndex::new(&base).encode(b"unrelated content here", 10).is_none());
+    }
+
+    proptest! {
+        /// Property 9: Packfile Format Compatibility
+        /// For any base and target, applying the encoded delta reproduces the target.
+        /// **Validates: Requirements 3.3**
+        #[test]
+        fn prop_delta_round_trip(
+            base in prop::collection::vec(any::<u8>(), 0..2000),
+            edits in prop::collection::vec((any::<prop::sample::Index>(), prop::collection::vec(any::<u8>(), 0..40)), 0..8),
+        ) {
+            let mut target = base.clone();
+            for (at, bytes) in edits {
+                let at = if target.is_empty() { 0 } else { at.index(target.len()) };
+                target.splice(at..at, bytes);
+            }
+
+            let delta = create_delta(&base, &target);
+            prop_assert_eq!(apply_delta(&base, &delta).unwrap(), target);
+        }
+    }
+}
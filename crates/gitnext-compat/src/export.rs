@@ -0,0 +1,360 @@
+//! GitNext → Git export (ADR-006)
+//!
+//! Writes a standard bare Git directory: every object reachable from the exported
+//! refs (as loose objects or a single pack), `refs/*` or `packed-refs`, a symbolic
+//! `HEAD` and a minimal `config`. GitNext's internal refs (`refs/logs/*`,
+//! `refs/gitnext/*`) and the metadata blobs behind them are never exported.
+
+use crate::git_format::object_type_name;
+use crate::hash_index::GitHashIndex;
+use crate::import::CURRENT_BRANCH_REF;
+use crate::pack::write_pack;
+use crate::{CompatError, Result};
+use gitnext_core::{GitHash, GitHashType, GitObject, ObjectId, ObjectType};
+use gitnext_storage::{Reference, ReferenceTarget, Storage, StorageError};
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+use std::path::{Component, Path};
+use std::sync::Arc;
+
+/// Branch `HEAD` names when the storage does not record a current branch
+const DEFAULT_BRANCH: &str = "main";
+
+/// Whether a ref is GitNext bookkeeping rather than repository content
+pub fn is_internal_ref(name: &str) -> bool {
+    name.starts_with("refs/logs/") || name.starts_with("refs/gitnext/")
+}
+
+/// Whether Git would accept `name` as the name of a ref under `refs/` that is
+/// not GitNext bookkeeping; names from outside (a push, a remote's advertisement)
+/// must pass before they are stored or used as paths
+pub fn valid_ref_name(name: &str) -> bool {
+    name.starts_with("refs/")
+        && !is_internal_ref(name)
+        && !name.ends_with('/')
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("@{")
+        && !name.contains("//")
+        && name.split('/').all(|component| !component.is_empty() && !component.starts_with('.'))
+        && !name.chars().any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+}
+
+/// How exported objects are laid out on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectLayout {
+    /// One zlib-compressed file per object under `objects/xx/`
+    Loose,
+    /// A single undeltified pack and its version 2 index under `objects/pack/`
+    Pack,
+}
+
+/// Options controlling an export
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    pub hash_type: GitHashType,
+    pub layout: ObjectLayout,
+    /// Write refs into `packed-refs` instead of one file per ref
+    pub packed_refs: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            hash_type: GitHashType::Sha1,
+            layout: ObjectLayout::Pack,
+            packed_refs: true,
+        }
+    }
+}
+
+/// Where the exported `HEAD` points
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportedHead {
+    Branch(String),
+    Detached(GitHash),
+}
+
+/// Summary of a completed export
+#[derive(Debug, Clone)]
+pub struct ExportReport {
+    pub objects_written: usize,
+    /// Exported direct refs and their Git ids
+    pub refs: BTreeMap<String, GitHash>,
+    pub head: ExportedHead,
+}
+
+/// Exports a storage backend's repository to a Git directory
+pub struct GitExporter {
+    storage: Arc<dyn Storage>,
+    options: ExportOptions,
+}
+
+impl GitExporter {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self::with_options(storage, ExportOptions::default())
+    }
+
+    pub fn with_options(storage: Arc<dyn Storage>, options: ExportOptions) -> Self {
+        Self { storage, options }
+    }
+
+    /// Export into `path`, which must be empty or not exist; it becomes a bare repository
+    pub async fn export(&self, path: impl AsRef<Path>) -> Result<ExportReport> {
+        let path = path.as_ref();
+        if path.exists() && std::fs::read_dir(path)?.next().is_some() {
+            return Err(CompatError::InvalidRepository(format!("{} is not empty", path.display())));
+        }
+
+        let hash_type = self.options.hash_type;
+        let mut index = GitHashIndex::open(self.storage.clone()).await?;
+        let all_refs = self.storage.list_refs().await?;
+
+        let mut objects = Vec::new();
+        let mut visited = HashSet::new();
+        let mut refs = BTreeMap::new();
+        let mut symbolic_refs = BTreeMap::new();
+        for reference in all_refs.iter().filter(|r| r.name.starts_with("refs/") && !is_internal_ref(&r.name)) {
+            check_ref_name(&reference.name)?;
+            match &reference.target {
+                ReferenceTarget::Direct(id) => {
+                    self.collect(&mut index, id, &mut visited, &mut objects).await?;
+                    let git_hash = index.git_hash(id, hash_type).expect("collected objects have Git hashes");
+                    refs.insert(reference.name.clone(), git_hash);
+                }
+                ReferenceTarget::Symbolic(target) => {
+                    check_ref_name(target)?;
+                    symbolic_refs.insert(reference.name.clone(), target.clone());
+                }
+            }
+        }
+
+        let head = match self.current_branch(&all_refs).await? {
+            Some(branch) => {
+                check_ref_name(&format!("refs/heads/{}", branch))?;
+                ExportedHead::Branch(branch)
+            }
+            None => match Self::direct_target(&all_refs, "HEAD") {
+                Some(id) => {
+                    self.collect(&mut index, &id, &mut visited, &mut objects).await?;
+                    ExportedHead::Detached(index.git_hash(&id, hash_type).expect("collected objects have Git hashes"))
+                }
+                None => ExportedHead::Branch(DEFAULT_BRANCH.to_string()),
+            },
+        };
+
+        self.write_skeleton(path)?;
+        match self.options.layout {
+            ObjectLayout::Loose => {
+                for (hash, object_type, content) in &objects {
+                    write_loose_object(path, hash, *object_type, content)?;
+                }
+            }
+            ObjectLayout::Pack => {
+                if !objects.is_empty() {
+                    let written = write_pack(&objects, hash_type)?;
+                    let pack_dir = path.join("objects").join("pack");
+                    let name = format!("pack-{}", written.checksum);
+                    std::fs::write(pack_dir.join(format!("{}.pack", name)), &written.pack)?;
+                    std::fs::write(pack_dir.join(format!("{}.idx", name)), &written.index)?;
+                }
+            }
+        }
+        self.write_refs(path, &refs, &symbolic_refs)?;
+
+        let head_content = match &head {
+            ExportedHead::Branch(branch) => format!("ref: refs/heads/{}\n", branch),
+            ExportedHead::Detached(hash) => format!("{}\n", hash),
+        };
+        std::fs::write(path.join("HEAD"), head_content)?;
+
+        // Keep newly derived Git hashes for later exports
+        index.save().await?;
+
+        Ok(ExportReport {
+            objects_written: objects.len(),
+            refs,
+            head,
+        })
+    }
+
+    /// Collect `root` and everything reachable from it, children before parents
+    async fn collect(
+        &self,
+        index: &mut GitHashIndex,
+        root: &ObjectId,
+        visited: &mut HashSet<ObjectId>,
+        objects: &mut Vec<(GitHash, ObjectType, Vec<u8>)>,
+    ) -> Result<()> {
+        let hash_type = self.options.hash_type;
+
+        // (object id, loaded object once its children have been pushed)
+        let mut stack: Vec<(ObjectId, Option<GitObject>)> = vec![(*root, None)];
+
+        while let Some((id, loaded)) = stack.pop() {
+            match loaded {
+                Some(object) => {
+                    let git_bytes = index.deriver().serialize_to_git_format(&object, hash_type)?;
+                    let git_hash = GitHash::from_git_bytes(&git_bytes, hash_type);
+
+                    // Objects imported from Git must reproduce their original ids
+                    if let Some(expected) = index.git_hash(&id, hash_type) {
+                        if expected != git_hash {
+                            return Err(CompatError::RoundTripMismatch { expected, actual: git_hash });
+                        }
+                    }
+                    index.record(id, git_hash);
+
+                    let header_len = git_bytes.iter().position(|&b| b == 0).expect("Git objects have a header") + 1;
+                    objects.push((git_hash, object.object_type(), git_bytes[header_len..].to_vec()));
+                }
+                None => {
+                    if !visited.insert(id) {
+                        continue;
+                    }
+                    let object = self.storage.load_object(&id).await?
+                        .ok_or(StorageError::ObjectNotFound { id })?;
+                    let children: Vec<ObjectId> = object.references()
+                        .into_iter()
+                        .filter(|child| !visited.contains(child))
+                        .collect();
+
+                    stack.push((id, Some(object)));
+                    stack.extend(children.into_iter().map(|child| (child, None)));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn direct_target(refs: &[Reference], name: &str) -> Option<ObjectId> {
+        refs.iter().find(|r| r.name == name).and_then(|r| match &r.target {
+            ReferenceTarget::Direct(id) => Some(*id),
+            ReferenceTarget::Symbolic(_) => None,
+        })
+    }
+
+    /// The branch recorded by gitnext-operations, or a symbolic `HEAD` target
+    async fn current_branch(&self, refs: &[Reference]) -> Result<Option<String>> {
+        if let Some(id) = Self::direct_target(refs, CURRENT_BRANCH_REF) {
+            if let Some(GitObject::Blob(blob)) = self.storage.load_object(&id).await? {
+                let content = blob.content.unwrap_or_default();
+                return Ok(Some(String::from_utf8_lossy(&content).into_owned()));
+            }
+        }
+
+        let symbolic_head = refs.iter().find(|r| r.name == "HEAD").and_then(|r| match &r.target {
+            ReferenceTarget::Symbolic(target) => target.strip_prefix("refs/heads/").map(str::to_string),
+            ReferenceTarget::Direct(_) => None,
+        });
+        Ok(symbolic_head)
+    }
+
+    fn write_skeleton(&self, path: &Path) -> Result<()> {
+        for dir in ["objects/info", "objects/pack", "refs/heads", "refs/tags"] {
+            std::fs::create_dir_all(path.join(dir))?;
+        }
+
+        let config = match self.options.hash_type {
+            GitHashType::Sha1 => "[core]\n\trepositoryformatversion = 0\n\tbare = true\n".to_string(),
+            GitHashType::Sha256 => "[core]\n\trepositoryformatversion = 1\n\tbare = true\n\
+                [extensions]\n\tobjectformat = sha256\n".to_string(),
+        };
+        std::fs::write(path.join("config"), config)?;
+        Ok(())
+    }
+
+    fn write_refs(
+        &self,
+        path: &Path,
+        refs: &BTreeMap<String, GitHash>,
+        symbolic_refs: &BTreeMap<String, String>,
+    ) -> Result<()> {
+        if self.options.packed_refs {
+            let mut packed = String::from("# pack-refs with: sorted \n");
+            for (name, hash) in refs {
+                packed.push_str(&format!("{} {}\n", hash, name));
+            }
+            std::fs::write(path.join("packed-refs"), packed)?;
+        } else {
+            for (name, hash) in refs {
+                write_ref_file(path, name, &format!("{}\n", hash))?;
+            }
+        }
+
+        // Symbolic refs cannot be packed
+        for (name, target) in symbolic_refs {
+            write_ref_file(path, name, &format!("ref: {}\n", target))?;
+        }
+        Ok(())
+    }
+}
+
+/// Refuse ref names Git would not accept, before they become paths or lines
+fn check_ref_name(name: &str) -> Result<()> {
+    if valid_ref_name(name) {
+        Ok(())
+    } else {
+        Err(CompatError::InvalidRefName(name.to_string()))
+    }
+}
+
+fn write_ref_file(git_dir: &Path, name: &str, content: &str) -> Result<()> {
+    check_ref_name(name)?;
+    // Only plain components, so the file stays under `git_dir`
+    if !Path::new(name).components().all(|c| matches!(c, Component::Normal(_))) {
+        return Err(CompatError::InvalidRefName(name.to_string()));
+    }
+    let ref_path = git_dir.join(name);
+    if let Some(parent) = ref_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(ref_path, content)?;
+    Ok(())
+}
+
+/// Write a zlib-compressed loose object, leaving existing objects untouched
+pub fn write_loose_object(git_dir: &Path, hash: &GitHash, object_type: ObjectType, content: &[u8]) -> Result<()> {
+    let hex = hash.to_string();
+    let dir = git_dir.join("objects").join(&hex[..2]);
+    let object_path = dir.join(&hex[2..]);
+    if object_path.exists() {
+        return Ok(());
+    }
+
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(format!("{} {}\0", object_type_name(object_type), content.len()).as_bytes())?;
+    encoder.write_all(content)?;
+
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(object_path, encoder.finish()?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_valid_ref_name() {
+        for name in ["refs/heads/main", "refs/heads/feature/x-1", "refs/tags/v1.0"] {
+            assert!(valid_ref_name(name), "{}", name);
+        }
+        for name in [
+            "HEAD",
+            "refs/heads/",
+            "refs/heads/a..b",
+            "refs/heads/.hidden",
+            "refs/heads/x.lock",
+            "refs/heads/a b",
+            "refs/heads/a//b",
+            "refs/tags/../../../../x",
+            "refs/heads/a/../../gitnext/index",
+            "refs/gitnext/current-branch",
+            "refs/logs/main",
+        ] {
+            assert!(!valid_ref_name(name), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Read-only access to an on-disk Git repository
+//!
+//! Supports bare repositories, `.git` directories and `gitdir:` link files. Objects
+//! are read from loose storage (`objects/xx/...`) and from version 2 packs; refs are
+//! read from `packed-refs` with loose refs taking precedence.
+
+use crate::git_format::parse_loose_object;
+use crate::pack::PackFile;
+use crate::{CompatError, Result};
+use gitnext_core::{GitHash, GitHashType, ObjectType};
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Maximum number of symbolic ref hops followed before giving up
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// Where `HEAD` points
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GitHead {
+    /// `ref: refs/heads/<branch>`; the branch may not exist yet (unborn)
+    Branch(String),
+    /// A detached commit
+    Detached(GitHash),
+}
+
+/// An opened Git directory
+pub struct GitDir {
+    path: PathBuf,
+    hash_type: GitHashType,
+    packs: Vec<PackFile>,
+}
+
+impl GitDir {
+    /// Open a repository given its working tree, its `.git` directory or a bare repository
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = Self::resolve_git_dir(path.as_ref())?;
+        let hash_type = Self::read_hash_type(&path)?;
+
+        let mut packs = Vec::new();
+        let pack_dir = path.join("objects").join("pack");
+        if pack_dir.is_dir() {
+            let mut pack_paths: Vec<PathBuf> = std::fs::read_dir(&pack_dir)?
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|p| p.extension().is_some_and(|ext| ext == "pack"))
+                .collect();
+            pack_paths.sort();
+            for pack_path in pack_paths {
+                packs.push(PackFile::open(&pack_path, hash_type)?);
+            }
+        }
+
+        Ok(Self { path, hash_type, packs })
+    }
+
+    fn resolve_git_dir(path: &Path) -> Result<PathBuf> {
+        let dot_git = path.join(".git");
+        if dot_git.is_dir() {
+            return Ok(dot_git);
+        }
+        if dot_git.is_file() {
+            // Worktrees and submodules use a "gitdir: <path>" link file
+            let link = std::fs::read_to_string(&dot_git)?;
+            let target = link.trim().strip_prefix("gitdir:")
+                .ok_or_else(|| CompatError::InvalidRepository(format!("{} is not a gitdir link", dot_git.display())))?
+                .trim();
+            return Ok(path.join(target));
+        }
+        if path.join("HEAD").is_file() && path.join("objects").is_dir() {
+            return Ok(path.to_path_buf());
+        }
+
+        Err(CompatError::InvalidRepository(format!("{} is not a Git repository", path.display())))
+    }
+
+    /// Read `extensions.objectformat` from the repository config
+    fn read_hash_type(git_dir: &Path) -> Result<GitHashType> {
+        let config = match std::fs::read_to_string(git_dir.join("config")) {
+            Ok(config) => config,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(GitHashType::Sha1),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut section = String::new();
+        for line in config.lines().map(str::trim) {
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                section = name.trim().to_ascii_lowercase();
+            } else if section == "extensions" {
+                if let Some((key, value)) = line.split_once('=') {
+                    if key.trim().eq_ignore_ascii_case("objectformat") {
+                        return match value.trim().to_ascii_lowercase().as_str() {
+                            "sha1" => Ok(GitHashType::Sha1),
+                            "sha256" => Ok(GitHashType::Sha256),
+                            other => Err(CompatError::Unsupported(format!("object format {}", other))),
+                        };
+                    }
+                }
+            }
+        }
+
+        Ok(GitHashType::Sha1)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn hash_type(&self) -> GitHashType {
+        self.hash_type
+    }
+
+    fn loose_object_path(&self, hash: &GitHash) -> PathBuf {
+        let hex = hash.to_string();
+        self.path.join("objects").join(&hex[..2]).join(&hex[2..])
+    }
+
+    /// Read an object's type and content (without the Git header)
+    pub fn read_object(&self, hash: &GitHash) -> Result<(ObjectType, Vec<u8>)> {
+        match std::fs::File::open(self.loose_object_path(hash)) {
+            Ok(file) => {
+                let mut data = Vec::new();
+                flate2::read::ZlibDecoder::new(file).read_to_end(&mut data)?;
+                let (object_type, content) = parse_loose_object(&data)?;
+                return Ok((object_type, content.to_vec()));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        for pack in &self.packs {
+            if let Some(object) = pack.read(hash)? {
+                return Ok(object);
+            }
+        }
+
+        Err(CompatError::MissingObject(*hash))
+    }
+
+    /// Hashes of every object in loose storage and in packs
+    pub fn object_hashes(&self) -> Result<HashSet<GitHash>> {
+        let mut hashes = HashSet::new();
+
+        let objects_dir = self.path.join("objects");
+        for fanout in std::fs::read_dir(&objects_dir)? {
+            let fanout = fanout?;
+            let prefix = fanout.file_name().to_string_lossy().into_owned();
+            if prefix.len() != 2 || !fanout.file_type()?.is_dir() {
+                continue;
+            }
+            for object in std::fs::read_dir(fanout.path())? {
+                let name = object?.file_name().to_string_lossy().into_owned();
+                if let Ok(hash) = GitHash::from_hex(&format!("{}{}", prefix, name)) {
+                    hashes.insert(hash);
+                }
+            }
+        }
+
+        for pack in &self.packs {
+            hashes.extend(pack.index().iter().map(|(hash, _)| *hash));
+        }
+
+        Ok(hashes)
+    }
+
+    /// All refs under `refs/`, with symbolic refs resolved to the object they name
+    pub fn read_refs(&self) -> Result<BTreeMap<String, GitHash>> {
+        let mut raw = BTreeMap::new();
+
+        match std::fs::read_to_string(self.path.join("packed-refs")) {
+            Ok(packed) => {
+                for line in packed.lines() {
+                    // Skip the header and peeled tag lines
+                    if line.starts_with('#') || line.starts_with('^') || line.is_empty() {
+                        continue;
+                    }
+                    let (hash, name) = line.split_once(' ')
+                        .ok_or_else(|| CompatError::InvalidRepository(format!("Malformed packed-refs line '{}'", line)))?;
+                    raw.insert(name.to_string(), hash.to_string());
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let mut pending = vec![self.path.join("refs")];
+        while let Some(dir) = pending.pop() {
+            let entries = match std::fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            for entry in entries {
+                let entry = entry?;
+                if entry.file_type()?.is_dir() {
+                    pending.push(entry.path());
+                    continue;
+                }
+                let name = entry.path().strip_prefix(&self.path)
+                    .expect("ref path lies inside the git dir")
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                raw.insert(name, std::fs::read_to_string(entry.path())?.trim().to_string());
+            }
+        }
+
+        let mut refs = BTreeMap::new();
+        for name in raw.keys() {
+            if let Some(hash) = Self::resolve(&raw, name)? {
+                refs.insert(name.clone(), hash);
+            }
+        }
+        Ok(refs)
+    }
+
+    /// Resolve a ref value, following symbolic refs; dangling symrefs resolve to `None`
+    fn resolve(raw: &BTreeMap<String, String>, name: &str) -> Result<Option<GitHash>> {
+        let mut current = name;
+        for _ in 0..MAX_SYMREF_DEPTH {
+            let Some(value) = raw.get(current) else {
+                return Ok(None);
+            };
+            match value.strip_prefix("ref:") {
+                Some(target) => current = target.trim(),
+                None => return Ok(Some(GitHash::from_hex(value)?)),
+            }
+        }
+        Err(CompatError::InvalidRepository(format!("Symbolic ref loop at {}", name)))
+    }
+
+    /// Read `HEAD`
+    pub fn read_head(&self) -> Result<GitHead> {
+        let head = std::fs::read_to_string(self.path.join("HEAD"))?;
+        let head = head.trim();
+
+        match head.strip_prefix("ref:") {
+            Some(target) => {
+                let target = target.trim();
+                let branch = target.strip_prefix("refs/heads/").ok_or_else(|| {
+                    CompatError::Unsupported(format!("HEAD pointing outside refs/heads ({})", target))
+                })?;
+                Ok(GitHead::Branch(branch.to_string()))
+            }
+            None => Ok(GitHead::Detached(GitHash::from_hex(head)?)),
+        }
+    }
+}
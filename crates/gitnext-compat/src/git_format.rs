@@ -0,0 +1,695 @@
+//! Parsing of Git's object serialization into the canonical model (ADR-002)
+//!
+//! Git objects reference their children by Git hash. Parsing therefore happens in two
+//! steps: bytes are parsed into a `ParsedObject` that still carries Git hashes, and once
+//! every referenced object has been imported the parsed object is converted into a
+//! canonical `GitObject` by resolving those hashes through a `GitHashMap`.
+
+use crate::{CompatError, Result};
+use gitnext_core::{
+    Blob, Commit, ExtraHeader, FileMode, GitHash, GitHashMap, GitHashType, GitNextError, GitObject, ObjectType,
+    Signature, Tag, Tree, TreeEntry,
+};
+
+/// A Git object whose references are still expressed as Git hashes
+#[derive(Debug, Clone)]
+pub enum ParsedObject {
+    Blob(bytes::Bytes),
+    Tree(Vec<ParsedTreeEntry>),
+    Commit(ParsedCommit),
+    Tag(ParsedTag),
+}
+
+#[derive(Debug, Clone)]
+pub struct ParsedTreeEntry {
+    pub mode: FileMode,
+    pub name: String,
+    /// Original bytes of a name that is not UTF-8
+    pub raw_name: Option<bytes::Bytes>,
+    pub hash: GitHash,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParsedCommit {
+    pub tree: GitHash,
+    pub parents: Vec<GitHash>,
+    pub author: Signature,
+    pub committer: Signature,
+    pub extra_headers: Vec<ExtraHeader>,
+    pub message: String,
+    pub raw_message: Option<bytes::Bytes>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ParsedTag {
+    pub target: GitHash,
+    pub target_type: ObjectType,
+    pub name: String,
+    pub tagger: Option<Signature>,
+    pub extra_headers: Vec<ExtraHeader>,
+    pub message: String,
+    pub signature: Option<bytes::Bytes>,
+    pub raw_message: Option<bytes::Bytes>,
+}
+
+impl ParsedObject {
+    /// Git hashes of every object this one references, apart from submodule commits
+    pub fn references(&self) -> Vec<GitHash> {
+        match self {
+            ParsedObject::Blob(_) => Vec::new(),
+            ParsedObject::Tree(entries) => entries.iter()
+                .filter(|e| e.mode != FileMode::Gitlink)
+                .map(|e| e.hash)
+                .collect(),
+            ParsedObject::Commit(commit) => {
+                let mut refs = vec![commit.tree];
+                refs.extend(commit.parents.iter().copied());
+                refs
+            }
+            ParsedObject::Tag(tag) => vec![tag.target],
+        }
+    }
+
+    /// Convert into a canonical object, resolving references through `map`
+    pub fn to_canonical(&self, map: &GitHashMap) -> Result<GitObject> {
+        let resolve = |hash: &GitHash| map.object_id(hash).ok_or(CompatError::MissingObject(*hash));
+
+        let object = match self {
+            ParsedObject::Blob(content) => GitObject::Blob(Blob::new(content.clone())),
+            ParsedObject::Tree(entries) => {
+                let mut tree_entries = Vec::with_capacity(entries.len());
+                for entry in entries {
+                    let mut tree_entry = match entry.mode {
+                        FileMode::Gitlink => TreeEntry::gitlink(entry.name.clone(), &entry.hash),
+                        mode => TreeEntry {
+                            name: entry.name.clone(),
+                            mode,
+                            hash: resolve(&entry.hash)?,
+                            entry_type: if mode == FileMode::Tree { ObjectType::Tree } else { ObjectType::Blob },
+                            raw_name: None,
+                        },
+                    };
+                    tree_entry.raw_name = entry.raw_name.clone();
+                    tree_entries.push(tree_entry);
+                }
+                GitObject::Tree(Tree::new(tree_entries))
+            }
+            ParsedObject::Commit(commit) => GitObject::Commit(Commit {
+                tree: resolve(&commit.tree)?,
+                parents: commit.parents.iter().map(resolve).collect::<Result<Vec<_>>>()?,
+                author: commit.author.clone(),
+                committer: commit.committer.clone(),
+                extra_headers: commit.extra_headers.clone(),
+                message: commit.message.clone(),
+                raw_message: commit.raw_message.clone(),
+            }),
+            ParsedObject::Tag(tag) => GitObject::Tag(Tag {
+                target: resolve(&tag.target)?,
+                target_type: tag.target_type,
+                name: tag.name.clone(),
+                tagger: tag.tagger.clone(),
+                extra_headers: tag.extra_headers.clone(),
+                message: tag.message.clone(),
+                signature: tag.signature.clone(),
+                raw_message: tag.raw_message.clone(),
+            }),
+        };
+
+        Ok(object)
+    }
+}
+
+fn invalid(message: impl Into<String>) -> CompatError {
+    CompatError::Object(GitNextError::InvalidFormat(message.into()))
+}
+
+/// Git's name for an object type, as used in object headers and tags
+pub fn object_type_name(object_type: ObjectType) -> &'static str {
+    match object_type {
+        ObjectType::Blob => "blob",
+        ObjectType::Tree => "tree",
+        ObjectType::Commit => "commit",
+        ObjectType::Tag => "tag",
+    }
+}
+
+/// Parse a Git object type name
+pub fn parse_object_type(name: &[u8]) -> Result<ObjectType> {
+    match name {
+        b"blob" => Ok(ObjectType::Blob),
+        b"tree" => Ok(ObjectType::Tree),
+        b"commit" => Ok(ObjectType::Commit),
+        b"tag" => Ok(ObjectType::Tag),
+        other => Err(invalid(format!("Unknown object type '{}'", String::from_utf8_lossy(other)))),
+    }
+}
+
+/// Split an inflated loose object into its type and content ("<type> <size>\0<content>")
+pub fn parse_loose_object(data: &[u8]) -> Result<(ObjectType, &[u8])> {
+    let nul = data.iter().position(|&b| b == 0)
+        .ok_or_else(|| invalid("Loose object header is not NUL terminated"))?;
+    let header = &data[..nul];
+    let space = header.iter().position(|&b| b == b' ')
+        .ok_or_else(|| invalid("Malformed loose object header"))?;
+
+    let object_type = parse_object_type(&header[..space])?;
+    let size: usize = std::str::from_utf8(&header[space + 1..]).ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid("Malformed loose object size"))?;
+
+    let content = &data[nul + 1..];
+    if content.len() != size {
+        return Err(invalid(format!("Loose object size mismatch: header says {}, found {}", size, content.len())));
+    }
+
+    Ok((object_type, content))
+}
+
+/// Parse the content of a Git object (without its "<type> <size>\0" header)
+pub fn parse_object(object_type: ObjectType, data: &[u8], hash_type: GitHashType) -> Result<ParsedObject> {
+    match object_type {
+        ObjectType::Blob => Ok(ParsedObject::Blob(bytes::Bytes::copy_from_slice(data))),
+        ObjectType::Tree => parse_tree(data, hash_type).map(ParsedObject::Tree),
+        ObjectType::Commit => parse_commit(data, hash_type).map(ParsedObject::Commit),
+        ObjectType::Tag => parse_tag(data, hash_type).map(ParsedObject::Tag),
+    }
+}
+
+fn parse_tree(data: &[u8], hash_type: GitHashType) -> Result<Vec<ParsedTreeEntry>> {
+    let hash_len = hash_type.digest_len();
+    let mut entries = Vec::new();
+    let mut rest = data;
+
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&b| b == b' ')
+            .ok_or_else(|| invalid("Tree entry is missing its mode"))?;
+        let nul = rest.iter().position(|&b| b == 0)
+            .ok_or_else(|| invalid("Tree entry name is not NUL terminated"))?;
+        if nul < space || rest.len() < nul + 1 + hash_len {
+            return Err(invalid("Truncated tree entry"));
+        }
+
+        let mode = match &rest[..space] {
+            b"100644" => FileMode::Normal,
+            b"100755" => FileMode::Executable,
+            b"120000" => FileMode::Symlink,
+            b"40000" => FileMode::Tree,
+            b"160000" => FileMode::Gitlink,
+            other => {
+                return Err(CompatError::Unsupported(format!(
+                    "tree entry mode {}", String::from_utf8_lossy(other)
+                )));
+            }
+        };
+        let name_bytes = &rest[space + 1..nul];
+        let name = String::from_utf8_lossy(name_bytes).into_owned();
+        let raw_name = std::str::from_utf8(name_bytes).is_err()
+            .then(|| bytes::Bytes::copy_from_slice(name_bytes));
+        if !TreeEntry::is_valid_name(&name) {
+            return Err(invalid(format!("Unsafe tree entry name '{}'", name)));
+        }
+        let hash = GitHash::from_slice(&rest[nul + 1..nul + 1 + hash_len])?;
+
+        entries.push(ParsedTreeEntry { mode, name, raw_name, hash });
+        rest = &rest[nul + 1 + hash_len..];
+    }
+
+    Ok(entries)
+}
+
+/// Header name, its value with continuation lines folded in, and whether the name
+/// stood alone on its line
+type RawHeader<'a> = (&'a [u8], Vec<u8>, bool);
+
+/// Split headers from the message that follows the first blank line
+///
+/// Continuation lines (starting with a space) are folded into the previous header's
+/// value, joined with '\n' and without the leading space.
+fn split_headers(data: &[u8]) -> Result<(Vec<RawHeader<'_>>, &[u8])> {
+    let mut headers: Vec<RawHeader> = Vec::new();
+    let mut rest = data;
+
+    loop {
+        let (line, next) = match rest.iter().position(|&b| b == b'\n') {
+            Some(0) => return Ok((headers, &rest[1..])),
+            Some(end) => (&rest[..end], &rest[end + 1..]),
+            None if rest.is_empty() => return Ok((headers, &[])),
+            None => (rest, &[][..]),
+        };
+
+        match line.strip_prefix(b" ") {
+            Some(continuation) => {
+                let (_, value, _) = headers.last_mut()
+                    .ok_or_else(|| invalid("Continuation line before any header"))?;
+                value.push(b'\n');
+                value.extend_from_slice(continuation);
+            }
+            None => {
+                let (name, value, bare) = match line.iter().position(|&b| b == b' ') {
+                    Some(space) => (&line[..space], &line[space + 1..], false),
+                    None => (line, &[][..], true),
+                };
+                headers.push((name, value.to_vec(), bare));
+            }
+        }
+        rest = next;
+    }
+}
+
+fn parse_extra_header(name: &[u8], value: Vec<u8>, bare: bool) -> Result<ExtraHeader> {
+    let name = std::str::from_utf8(name)
+        .map_err(|_| CompatError::Unsupported("non UTF-8 header names".to_string()))?;
+    Ok(if bare { ExtraHeader::bare(name, value) } else { ExtraHeader::new(name, value) })
+}
+
+fn parse_hex_hash(value: &[u8], hash_type: GitHashType) -> Result<GitHash> {
+    let hex_str = std::str::from_utf8(value).map_err(|_| invalid("Non-ASCII object hash"))?;
+    let hash = GitHash::from_hex(hex_str)?;
+    if hash.hash_type() != hash_type {
+        return Err(invalid(format!("Object hash {} does not match the repository hash type", hex_str)));
+    }
+    Ok(hash)
+}
+
+/// A message and, when it is not UTF-8, its original bytes
+fn parse_message(message: &[u8]) -> (String, Option<bytes::Bytes>) {
+    match std::str::from_utf8(message) {
+        Ok(text) => (text.to_string(), None),
+        Err(_) => (String::from_utf8_lossy(message).into_owned(), Some(bytes::Bytes::copy_from_slice(message))),
+    }
+}
+
+fn parse_commit(data: &[u8], hash_type: GitHashType) -> Result<ParsedCommit> {
+    let (headers, message) = split_headers(data)?;
+
+    let mut tree = None;
+    let mut parents = Vec::new();
+    let mut author = None;
+    let mut committer = None;
+    let mut extra_headers = Vec::new();
+
+    for (name, value, bare) in headers {
+        // Everything after the committer is kept verbatim
+        if committer.is_some() {
+            extra_headers.push(parse_extra_header(name, value, bare)?);
+            continue;
+        }
+        match name {
+            b"tree" if tree.is_none() => tree = Some(parse_hex_hash(&value, hash_type)?),
+            b"parent" if author.is_none() => parents.push(parse_hex_hash(&value, hash_type)?),
+            b"author" if author.is_none() => author = Some(parse_signature(&value)?),
+            b"committer" => committer = Some(parse_signature(&value)?),
+            _ => {
+                return Err(CompatError::Unsupported(format!(
+                    "commit header '{}' before the committer", String::from_utf8_lossy(name)
+                )));
+            }
+        }
+    }
+
+    let (message, raw_message) = parse_message(message);
+    Ok(ParsedCommit {
+        tree: tree.ok_or_else(|| invalid("Commit is missing its tree"))?,
+        parents,
+        author: author.ok_or_else(|| invalid("Commit is missing its author"))?,
+        committer: committer.ok_or_else(|| invalid("Commit is missing its committer"))?,
+        extra_headers,
+        message,
+        raw_message,
+    })
+}
+
+/// Line prefixes that start the signature block of a signed tag
+const TAG_SIGNATURE_PREFIXES: [&[u8]; 4] = [
+    b"-----BEGIN PGP SIGNATURE-----",
+    b"-----BEGIN PGP MESSAGE-----",
+    b"-----BEGIN SSH SIGNATURE-----",
+    b"-----BEGIN SIGNED MESSAGE-----",
+];
+
+/// Split a tag message from the signature block appended to it
+///
+/// As in Git, the block starts at the last line that looks like a signature, so
+/// a message quoting one keeps it.
+fn split_tag_signature(message: &[u8]) -> (&[u8], Option<&[u8]>) {
+    let mut signature_start = None;
+    let mut line_start = 0;
+    while line_start < message.len() {
+        let line = &message[line_start..];
+        if TAG_SIGNATURE_PREFIXES.iter().any(|prefix| line.starts_with(prefix)) {
+            signature_start = Some(line_start);
+        }
+        line_start += match line.iter().position(|&b| b == b'\n') {
+            Some(end) => end + 1,
+            None => line.len(),
+        };
+    }
+    match signature_start {
+        Some(start) => (&message[..start], Some(&message[start..])),
+        None => (message, None),
+    }
+}
+
+fn parse_tag(data: &[u8], hash_type: GitHashType) -> Result<ParsedTag> {
+    let (headers, message) = split_headers(data)?;
+
+    let mut target = None;
+    let mut target_type = None;
+    let mut name = None;
+    let mut tagger = None;
+    let mut extra_headers = Vec::new();
+
+    for (header, value, bare) in headers {
+        // Everything after the tagger, or after the name of tags without one, is
+        // kept verbatim
+        if tagger.is_some() || !extra_headers.is_empty() {
+            extra_headers.push(parse_extra_header(header, value, bare)?);
+            continue;
+        }
+        match header {
+            b"object" if target.is_none() => target = Some(parse_hex_hash(&value, hash_type)?),
+            b"type" if target_type.is_none() => target_type = Some(parse_object_type(&value)?),
+            b"tag" if name.is_none() => name = Some(
+                String::from_utf8(value).map_err(|_| invalid("Non UTF-8 tag name"))?
+            ),
+            b"tagger" => tagger = Some(parse_signature(&value)?),
+            _ if name.is_some() => extra_headers.push(parse_extra_header(header, value, bare)?),
+            _ => {
+                return Err(CompatError::Unsupported(format!(
+                    "tag header '{}' before the tag name", String::from_utf8_lossy(header)
+                )));
+            }
+        }
+    }
+
+    let (message, signature) = split_tag_signature(message);
+    let (message, raw_message) = parse_message(message);
+
+    Ok(ParsedTag {
+        target: target.ok_or_else(|| invalid("Tag is missing its object"))?,
+        target_type: target_type.ok_or_else(|| invalid("Tag is missing its type"))?,
+        name: name.ok_or_else(|| invalid("Tag is missing its name"))?,
+        tagger,
+        extra_headers,
+        message,
+        signature: signature.map(bytes::Bytes::copy_from_slice),
+        raw_message,
+    })
+}
+
+/// Parse a "Name <email> timestamp +HHMM" identity line
+///
+/// Name, email and time are decoded on a best-effort basis. Whenever they would not
+/// re-serialize to exactly `value` (non UTF-8 bytes, unusual spacing, a "-0000" or
+/// malformed timezone, ...) the original bytes are kept in `Signature::raw`.
+pub fn parse_signature(value: &[u8]) -> Result<Signature> {
+    Ok(Signature::from_git_bytes(value)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse_signature() {
+        let sig = parse_signature(b"Test Author <test@example.com> 1234567890 -0130").unwrap();
+        assert_eq!(sig.name, "Test Author");
+        assert_eq!(sig.email, "test@example.com");
+        assert_eq!(sig.timestamp, 1234567890);
+        assert_eq!(sig.timezone_offset, -90);
+
+        assert!(parse_signature(b"No Email 1234567890 +0000").is_err());
+    }
+
+    #[test]
+    fn test_parse_signature_keeps_raw_bytes_when_needed() {
+        // Canonical lines need no raw copy
+        let sig = parse_signature(b"A U Thor <a@example.com> 1234567890 +0530").unwrap();
+        assert_eq!(sig.timezone_offset, 330);
+        assert_eq!(sig.git_timezone(), "+0530");
+        assert!(sig.raw.is_none());
+
+        for line in [
+            &b"A U Thor <a@example.com> 1234567890 -0000"[..],
+            b"A  U Thor  <a@example.com>  1234567890 +0000",
+            b"Caf\xe9 <cafe@example.com> 1234567890 +0100",
+            b"Bad Zone <a@b.c> 1234567890 0000",
+            b"No Date <a@b.c>",
+            b"Odd Minutes <a@b.c> 1234567890 +0075",
+        ] {
+            let sig = parse_signature(line).unwrap();
+            assert_eq!(sig.raw.as_deref(), Some(line));
+            assert_eq!(sig.to_git_bytes(), line);
+        }
+    }
+
+    #[test]
+    fn test_parse_loose_object() {
+        let (object_type, content) = parse_loose_object(b"blob 5\0hello").unwrap();
+        assert_eq!(object_type, ObjectType::Blob);
+        assert_eq!(content, b"hello");
+
+        assert!(parse_loose_object(b"blob 6\0hello").is_err());
+        assert!(parse_loose_object(b"blob 5hello").is_err());
+    }
+
+    #[test]
+    fn test_parse_commit() {
+        let data = b"tree 68aba62e560c0ebc3396e8ae9335232cd93a3f60\n\
+            parent c8fa21d60f84d8b6809b13dcaf04bb22d9ec3777\n\
+            author A U Thor <author@example.com> 1234567890 +0000\n\
+            committer C O Mitter <committer@example.com> 1234567891 +0200\n\
+            \n\
+            Subject\n\nBody\n";
+        let parsed = parse_commit(data, GitHashType::Sha1).unwrap();
+
+        assert_eq!(parsed.tree.to_string(), "68aba62e560c0ebc3396e8ae9335232cd93a3f60");
+        assert_eq!(parsed.parents.len(), 1);
+        assert_eq!(parsed.author.name, "A U Thor");
+        assert_eq!(parsed.committer.timezone_offset, 120);
+        assert_eq!(parsed.message, "Subject\n\nBody\n");
+
+        // SHA-1 hashes are rejected in a SHA-256 repository
+        assert!(parse_commit(data, GitHashType::Sha256).is_err());
+    }
+
+    #[test]
+    fn test_parse_commit_extra_headers() {
+        let data = b"tree 68aba62e560c0ebc3396e8ae9335232cd93a3f60\n\
+            author A U Thor <author@example.com> 1234567890 +0000\n\
+            committer C O Mitter <committer@example.com> 1234567890 +0000\n\
+            encoding ISO-8859-1\n\
+            gpgsig -----BEGIN SSH SIGNATURE-----\n \n U1NIU0lH\n -----END SSH SIGNATURE-----\n\
+            \n\
+            Signed\n";
+        let parsed = parse_commit(data, GitHashType::Sha1).unwrap();
+
+        assert_eq!(parsed.extra_headers.len(), 2);
+        assert_eq!(parsed.extra_headers[0], ExtraHeader::new("encoding", "ISO-8859-1"));
+        assert_eq!(
+            parsed.extra_headers[1].value.as_ref(),
+            b"-----BEGIN SSH SIGNATURE-----\n\nU1NIU0lH\n-----END SSH SIGNATURE-----"
+        );
+        assert_eq!(parsed.message, "Signed\n");
+
+        // Unknown headers are only accepted after the committer
+        let early = b"tree 68aba62e560c0ebc3396e8ae9335232cd93a3f60\nx-early 1\n\n";
+        assert!(matches!(parse_commit(early, GitHashType::Sha1), Err(CompatError::Unsupported(_))));
+    }
+
+    /// Parse a commit whose tree is the empty tree and serialize it back to Git
+    fn commit_round_trip(data: &[u8]) -> (Commit, Vec<u8>) {
+        let parsed = ParsedObject::Commit(parse_commit(data, GitHashType::Sha1).unwrap());
+        let tree = GitObject::Tree(Tree::new(Vec::new()));
+        let mut map = GitHashMap::new();
+        map.insert(tree.canonical_hash(), parsed.references()[0]);
+        let GitObject::Commit(commit) = parsed.to_canonical(&map).unwrap() else { panic!("not a commit") };
+
+        let deriver = gitnext_core::CompatHashDeriver::with_map(map);
+        let git_bytes = deriver.serialize_to_git_format(&GitObject::Commit(commit.clone()), GitHashType::Sha1).unwrap();
+        let content = parse_loose_object(&git_bytes).unwrap().1.to_vec();
+        (commit, content)
+    }
+
+    #[test]
+    fn test_encoded_message_round_trips() {
+        let data = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author A U Thor <author@example.com> 1234567890 +0000\n\
+            committer C O Mitter <committer@example.com> 1234567890 +0000\n\
+            encoding ISO-8859-1\n\
+            \n\
+            Caf\xe9 cr\xe8me\n";
+        let (commit, git_bytes) = commit_round_trip(data);
+        assert_eq!(commit.message, "Caf\u{fffd} cr\u{fffd}me\n");
+        assert_eq!(git_bytes, data);
+    }
+
+    #[test]
+    fn test_headers_without_value_round_trip() {
+        let data = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+            author A U Thor <author@example.com> 1234567890 +0000\n\
+            committer C O Mitter <committer@example.com> 1234567890 +0000\n\
+            x-bare\n\
+            x-spaced \n\
+            x-folded\n continued\n\
+            \n\
+            Message\n";
+        let (commit, git_bytes) = commit_round_trip(data);
+        assert_eq!(commit.extra_headers[0], ExtraHeader::bare("x-bare", ""));
+        assert_eq!(commit.extra_headers[1], ExtraHeader::new("x-spaced", ""));
+        assert_eq!(commit.extra_headers[2], ExtraHeader::bare("x-folded", "\ncontinued"));
+        assert_eq!(git_bytes, data);
+    }
+
+    #[test]
+    fn test_parse_tag_signature() {
+        let data = b"object c8fa21d60f84d8b6809b13dcaf04bb22d9ec3777\n\
+            type commit\n\
+            tag v1.0\n\
+            tagger A U Thor <author@example.com> 1234567890 +0000\n\
+            \n\
+            Release\n\
+            -----BEGIN PGP SIGNATURE-----\n\niQEz\n-----END PGP SIGNATURE-----\n";
+        let parsed = parse_tag(data, GitHashType::Sha1).unwrap();
+
+        assert_eq!(parsed.message, "Release\n");
+        assert_eq!(
+            parsed.signature.unwrap().as_ref(),
+            b"-----BEGIN PGP SIGNATURE-----\n\niQEz\n-----END PGP SIGNATURE-----\n"
+        );
+
+        // Only the last signature block is the tag's own
+        let data = b"object c8fa21d60f84d8b6809b13dcaf04bb22d9ec3777\n\
+            type commit\n\
+            tag v1.1\n\
+            tagger A U Thor <author@example.com> 1234567890 +0000\n\
+            \n\
+            Quoting\n\
+            -----BEGIN PGP SIGNATURE-----\nquoted\n-----END PGP SIGNATURE-----\n\
+            -----BEGIN SSH SIGNATURE-----\nU1NIU0lH\n-----END SSH SIGNATURE-----\n";
+        let parsed = parse_tag(data, GitHashType::Sha1).unwrap();
+        assert_eq!(parsed.message, "Quoting\n-----BEGIN PGP SIGNATURE-----\nquoted\n-----END PGP SIGNATURE-----\n");
+        assert_eq!(
+            parsed.signature.unwrap().as_ref(),
+            b"-----BEGIN SSH SIGNATURE-----\nU1NIU0lH\n-----END SSH SIGNATURE-----\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_tree_rejects_unsafe_names() {
+        for name in ["..", ".", ".git", ".GIT", "", "a/b", "/etc", "../../x"] {
+            let mut data = format!("100644 {}\0", name).into_bytes();
+            data.extend_from_slice(&[0u8; 20]);
+            let result = parse_tree(&data, GitHashType::Sha1);
+            assert!(matches!(result, Err(CompatError::Object(_))), "{:?}", name);
+        }
+        let mut data = b"100644 .gitignore\0".to_vec();
+        data.extend_from_slice(&[0u8; 20]);
+        assert!(parse_tree(&data, GitHashType::Sha1).is_ok());
+    }
+
+    #[test]
+    fn test_parse_tree_keeps_gitlinks_and_raw_names() {
+        let blob = GitObject::Blob(Blob::new(bytes::Bytes::from_static(b"hi\n")));
+        let blob_hash = GitHash::from_hex("45b983be36b73c0788dc9cbcb76cbb80fc7bb057").unwrap();
+        let module = GitHash::from_hex("c8fa21d60f84d8b6809b13dcaf04bb22d9ec3777").unwrap();
+
+        let mut data = b"100644 caf\xe9.txt\0".to_vec();
+        data.extend_from_slice(blob_hash.as_bytes());
+        data.extend_from_slice(b"160000 module\0");
+        data.extend_from_slice(module.as_bytes());
+        let parsed = ParsedObject::Tree(parse_tree(&data, GitHashType::Sha1).unwrap());
+
+        // The submodule commit is not an object to import
+        assert_eq!(parsed.references(), vec![blob_hash]);
+
+        let mut map = GitHashMap::new();
+        map.insert(blob.canonical_hash(), blob_hash);
+        let GitObject::Tree(tree) = parsed.to_canonical(&map).unwrap() else { panic!("not a tree") };
+        assert_eq!(tree.entries[0].name, "caf\u{fffd}.txt");
+        assert_eq!(tree.entries[0].raw_name.as_deref(), Some(&b"caf\xe9.txt"[..]));
+        assert_eq!(tree.entries[1].entry_type, ObjectType::Commit);
+        assert_eq!(tree.entries[1].gitlink_target(), Some(module));
+
+        let tree = GitObject::Tree(tree);
+        assert_eq!(tree.references(), vec![blob.canonical_hash()]);
+        let deriver = gitnext_core::CompatHashDeriver::with_map(map);
+        let git_bytes = deriver.serialize_to_git_format(&tree, GitHashType::Sha1).unwrap();
+        let (_, content) = parse_loose_object(&git_bytes).unwrap();
+        assert_eq!(content, data);
+    }
+
+    #[test]
+    fn test_parse_tag_without_tagger() {
+        let data = b"object c8fa21d60f84d8b6809b13dcaf04bb22d9ec3777\n\
+            type commit\n\
+            tag v0.1\n\
+            \n\
+            Old release\n";
+        let parsed = parse_tag(data, GitHashType::Sha1).unwrap();
+        assert!(parsed.tagger.is_none());
+        assert_eq!(parsed.message, "Old release\n");
+    }
+
+    /// Identity name bytes as Git may store them: anything but the delimiters
+    fn arb_identity_bytes() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(any::<u8>().prop_filter("delimiter", |b| !b"<>\n\0".contains(b)), 0..30)
+    }
+
+    proptest! {
+        /// Property 10: Git Object Export Fidelity
+        /// Signatures built from fields serialize in Git's "+HHMM" form and parse back
+        /// to the same fields without needing raw bytes.
+        /// **Validates: Requirements 3.5**
+        #[test]
+        fn prop_signature_fields_round_trip(
+            name in "[a-zA-Z][a-zA-Z .'-]{0,30}[a-zA-Z]",
+            email in "[a-z0-9.+-]{1,20}@[a-z0-9.-]{1,20}",
+            timestamp in 0i64..=i64::from(u32::MAX),
+            timezone_offset in -1439i16..=1439i16,
+        ) {
+            let signature = Signature { name, email, timestamp, timezone_offset, raw: None };
+            let line = signature.to_git_bytes();
+
+            let timezone = &line[line.len() - 5..];
+            prop_assert!(timezone[0] == b'+' || timezone[0] == b'-');
+            prop_assert!(timezone[1..].iter().all(u8::is_ascii_digit));
+            prop_assert!(timezone[3..].iter().map(|d| d - b'0').fold(0, |acc, d| acc * 10 + d) < 60);
+
+            let parsed = parse_signature(&line).unwrap();
+            prop_assert_eq!(parsed.name, signature.name);
+            prop_assert_eq!(parsed.email, signature.email);
+            prop_assert_eq!(parsed.timestamp, signature.timestamp);
+            prop_assert_eq!(parsed.timezone_offset, signature.timezone_offset);
+            prop_assert!(parsed.raw.is_none());
+        }
+
+        /// Property 10: Git Object Export Fidelity
+        /// Any identity line Git can store re-serializes to identical bytes, including
+        /// non UTF-8 names, irregular spacing and out-of-range timezones.
+        /// **Validates: Requirements 3.5**
+        #[test]
+        fn prop_signature_bytes_round_trip(
+            name in arb_identity_bytes(),
+            email in arb_identity_bytes(),
+            gap in prop::sample::select(vec![&b""[..], b" ", b"  ", b"\t"]),
+            timestamp in any::<u32>(),
+            sign in prop::sample::select(vec![b'+', b'-']),
+            zone in 0u16..10000,
+        ) {
+            let mut line = name.clone();
+            line.extend_from_slice(b" <");
+            line.extend_from_slice(&email);
+            line.extend_from_slice(b">");
+            line.extend_from_slice(gap);
+            line.extend_from_slice(format!(" {} ", timestamp).as_bytes());
+            line.push(sign);
+            line.extend_from_slice(format!("{:04}", zone).as_bytes());
+
+            let parsed = parse_signature(&line).unwrap();
+            prop_assert_eq!(parsed.to_git_bytes(), line.clone());
+            prop_assert_eq!(parsed.timestamp, i64::from(timestamp));
+        }
+    }
+}
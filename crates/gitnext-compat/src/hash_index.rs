@@ -0,0 +1,297 @@
+//! Persistent BLAKE3 <-> Git hash index
+//!
+//! Git objects name their children by SHA-1/SHA-256, so a faithful export needs the
+//! Git hash of every object in a DAG. The index derives those hashes bottom-up and
+//! persists them through the `Storage` trait, next to the objects themselves: the
+//! mappings are kept in chunks in the order they were recorded, so saving writes
+//! only those recorded since the last save. Indexes opened on the same storage
+//! (a server's and those of fetches and pushes, say) merge each other's mappings
+//! when they save rather than overwrite them.
+
+use crate::{CompatError, Result};
+use gitnext_core::{CompatHashDeriver, GitHash, GitHashMap, GitHashType, GitObject, ObjectId};
+use gitnext_storage::metadata::{load_list, save_list, Bincode, Persisted};
+use gitnext_storage::{Storage, StorageError};
+use std::sync::Arc;
+
+/// Internal reference naming the manifest of the hash map's chunks (ignored by Git export)
+pub const GIT_HASH_MAP_REF: &str = "refs/gitnext/git-hash-map";
+
+/// Bidirectional ObjectId <-> GitHash index backed by a storage backend
+pub struct GitHashIndex {
+    storage: Arc<dyn Storage>,
+    deriver: CompatHashDeriver,
+    /// Number of recorded mappings already persisted
+    saved: usize,
+    persisted: Persisted,
+}
+
+impl GitHashIndex {
+    /// Open the index, loading any previously persisted mappings
+    pub async fn open(storage: Arc<dyn Storage>) -> Result<Self> {
+        let mut index = Self {
+            storage,
+            deriver: CompatHashDeriver::new(),
+            saved: 0,
+            persisted: Persisted::default(),
+        };
+        index.reload().await?;
+        Ok(index)
+    }
+
+    /// Load the persisted mappings, keeping those recorded here but not saved yet
+    async fn reload(&mut self) -> Result<()> {
+        let (recorded, persisted) = load_list::<Bincode, (ObjectId, GitHash)>(self.storage.as_ref(), GIT_HASH_MAP_REF).await?;
+        let mut map = GitHashMap::new();
+        for (id, git_hash) in recorded {
+            map.insert(id, git_hash);
+        }
+        let saved = map.recorded().len();
+        for &(id, git_hash) in &self.deriver.map().recorded()[self.saved..] {
+            map.insert(id, git_hash);
+        }
+        self.deriver = CompatHashDeriver::with_map(map);
+        self.saved = saved;
+        self.persisted = persisted;
+        Ok(())
+    }
+
+    /// Pick up the mappings other indexes on the same storage saved since this one
+    /// was opened or last saved
+    pub async fn refresh(&mut self) -> Result<()> {
+        if !self.persisted.is_current(self.storage.as_ref(), GIT_HASH_MAP_REF).await? {
+            self.reload().await?;
+        }
+        Ok(())
+    }
+
+    /// Persist the mappings recorded since the index was opened or last saved,
+    /// after those other indexes saved in the meantime
+    pub async fn save(&mut self) -> Result<()> {
+        loop {
+            let recorded = self.deriver.map().recorded();
+            if self.saved == recorded.len() {
+                return Ok(());
+            }
+            let storage = self.storage.as_ref();
+            if save_list::<Bincode, _>(storage, GIT_HASH_MAP_REF, recorded, self.saved..recorded.len(), &mut self.persisted).await? {
+                self.saved = recorded.len();
+                return Ok(());
+            }
+            self.reload().await?;
+        }
+    }
+
+    /// Record a mapping observed at an import boundary
+    pub fn record(&mut self, id: ObjectId, git_hash: GitHash) {
+        self.deriver.map_mut().insert(id, git_hash);
+    }
+
+    /// Look up the Git hash of a canonical object, if already known
+    pub fn git_hash(&self, id: &ObjectId, hash_type: GitHashType) -> Option<GitHash> {
+        self.deriver.map().git_hash(id, hash_type)
+    }
+
+    /// Look up the canonical object for a Git hash, if already known
+    pub fn object_id(&self, git_hash: &GitHash) -> Option<ObjectId> {
+        self.deriver.lookup_by_git_hash(git_hash)
+    }
+
+    pub fn map(&self) -> &GitHashMap {
+        self.deriver.map()
+    }
+
+    pub fn deriver(&self) -> &CompatHashDeriver {
+        &self.deriver
+    }
+
+    /// Derive the Git hash of `root` and of everything reachable from it
+    ///
+    /// Objects are visited in post-order so that every child has a Git hash
+    /// before its parent is serialized. Already-mapped objects are not reloaded.
+    pub async fn derive(&mut self, root: &ObjectId, hash_type: GitHashType) -> Result<GitHash> {
+        if let Some(git_hash) = self.git_hash(root, hash_type) {
+            return Ok(git_hash);
+        }
+
+        // (object id, loaded object once its children have been pushed). A shared child
+        // may be pushed more than once; the first entry popped derives it.
+        let mut stack: Vec<(ObjectId, Option<GitObject>)> = vec![(*root, None)];
+
+        while let Some((id, loaded)) = stack.pop() {
+            match loaded {
+                Some(object) => {
+                    self.deriver.derive_git_hash(&object, hash_type)?;
+                }
+                None => {
+                    if self.git_hash(&id, hash_type).is_some() {
+                        continue;
+                    }
+                    let object = self.storage.load_object(&id).await?
+                        .ok_or(StorageError::ObjectNotFound { id })?;
+                    let children: Vec<ObjectId> = object.references()
+                        .into_iter()
+                        .filter(|child| self.git_hash(child, hash_type).is_none())
+                        .collect();
+
+                    stack.push((id, Some(object)));
+                    stack.extend(children.into_iter().map(|child| (child, None)));
+                }
+            }
+        }
+
+        self.git_hash(root, hash_type).ok_or_else(|| {
+            CompatError::Object(gitnext_core::GitNextError::HashDerivation(format!(
+                "Failed to derive {:?} hash for {}", hash_type, root
+            )))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gitnext_core::{Blob, Commit, FileMode, ObjectType, Signature, Tree, TreeEntry};
+    use gitnext_storage::MemoryStorage;
+
+    async fn store(storage: &Arc<dyn Storage>, object: GitObject) -> ObjectId {
+        let id = object.canonical_hash();
+        storage.store_object(&id, &object).await.unwrap();
+        id
+    }
+
+    fn signature() -> Signature {
+        Signature {
+            name: "Test Author".to_string(),
+            email: "test@example.com".to_string(),
+            timestamp: 1234567890,
+            timezone_offset: 0,
+            raw: None,
+        }
+    }
+
+    /// Builds hello.txt -> a/hello.txt + a.txt -> commit -> child commit
+    async fn build_history(storage: &Arc<dyn Storage>) -> (ObjectId, ObjectId, ObjectId) {
+        let blob = store(storage, GitObject::Blob(Blob::new(bytes::Bytes::from("hello world\n")))).await;
+        let inner = store(storage, GitObject::Tree(Tree::new(vec![TreeEntry {
+            name: "hello.txt".to_string(),
+            mode: FileMode::Normal,
+            hash: blob,
+            entry_type: ObjectType::Blob,
+            raw_name: None,
+        }]))).await;
+        let root_tree = store(storage, GitObject::Tree(Tree::new(vec![
+            TreeEntry { name: "a".to_string(), mode: FileMode::Tree, hash: inner, entry_type: ObjectType::Tree, raw_name: None },
+            TreeEntry { name: "a.txt".to_string(), mode: FileMode::Normal, hash: blob, entry_type: ObjectType::Blob, raw_name: None },
+        ]))).await;
+        let first = store(storage, GitObject::Commit(Commit {
+            tree: inner,
+            parents: vec![],
+            author: signature(),
+            committer: signature(),
+            extra_headers: Vec::new(),
+            message: "Test commit\n".to_string(),
+            raw_message: None,
+        })).await;
+        let second = store(storage, GitObject::Commit(Commit {
+            tree: root_tree,
+            parents: vec![first],
+            author: signature(),
+            committer: signature(),
+            extra_headers: Vec::new(),
+            message: "Second commit\n".to_string(),
+            raw_message: None,
+        })).await;
+        (inner, first, second)
+    }
+
+    #[tokio::test]
+    async fn test_derive_whole_dag() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let (inner, first, second) = build_history(&storage).await;
+
+        let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+        index.derive(&second, GitHashType::Sha1).await.unwrap();
+
+        // Children reachable from the tip were derived along the way
+        assert_eq!(
+            index.git_hash(&inner, GitHashType::Sha1).unwrap().to_string(),
+            "68aba62e560c0ebc3396e8ae9335232cd93a3f60"
+        );
+        let first_hash = index.git_hash(&first, GitHashType::Sha1).unwrap();
+        assert_eq!(first_hash.to_string(), "c8fa21d60f84d8b6809b13dcaf04bb22d9ec3777");
+        assert_eq!(index.object_id(&first_hash), Some(first));
+    }
+
+    #[tokio::test]
+    async fn test_derive_sha256_dag() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let (_, first, _) = build_history(&storage).await;
+
+        let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+        let hash = index.derive(&first, GitHashType::Sha256).await.unwrap();
+        assert_eq!(
+            hash.to_string(),
+            "7ab754e6a1ace7277d15caff2229119e3a3647b948c1d7088f71b0c621eab8cc"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_index_persistence() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let (_, first, second) = build_history(&storage).await;
+
+        let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+        let second_hash = index.derive(&second, GitHashType::Sha1).await.unwrap();
+        index.save().await.unwrap();
+
+        let mut reopened = GitHashIndex::open(storage.clone()).await.unwrap();
+        assert_eq!(reopened.git_hash(&second, GitHashType::Sha1), Some(second_hash));
+        assert_eq!(reopened.map().len(), index.map().len());
+        assert_eq!(reopened.map().recorded(), index.map().recorded());
+        assert!(reopened.git_hash(&first, GitHashType::Sha1).is_some());
+
+        // Mappings learned later are added to those saved
+        let first_sha256 = reopened.derive(&first, GitHashType::Sha256).await.unwrap();
+        reopened.save().await.unwrap();
+        let again = GitHashIndex::open(storage.clone()).await.unwrap();
+        assert_eq!(again.git_hash(&first, GitHashType::Sha256), Some(first_sha256));
+        assert_eq!(again.git_hash(&second, GitHashType::Sha1), Some(second_hash));
+        assert_eq!(again.map().recorded(), reopened.map().recorded());
+    }
+
+    #[tokio::test]
+    async fn test_indexes_sharing_storage() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let (_, first, second) = build_history(&storage).await;
+
+        // Both open before either saves; the second to save keeps the first's mappings
+        let mut one = GitHashIndex::open(storage.clone()).await.unwrap();
+        let mut other = GitHashIndex::open(storage.clone()).await.unwrap();
+        let second_hash = one.derive(&second, GitHashType::Sha1).await.unwrap();
+        let first_sha256 = other.derive(&first, GitHashType::Sha256).await.unwrap();
+        one.save().await.unwrap();
+        other.save().await.unwrap();
+        assert_eq!(other.git_hash(&second, GitHashType::Sha1), Some(second_hash));
+
+        let reopened = GitHashIndex::open(storage.clone()).await.unwrap();
+        assert_eq!(reopened.git_hash(&second, GitHashType::Sha1), Some(second_hash));
+        assert_eq!(reopened.git_hash(&first, GitHashType::Sha256), Some(first_sha256));
+        assert_eq!(reopened.map().recorded(), other.map().recorded());
+
+        // A refresh picks up what others saved
+        assert_eq!(one.git_hash(&first, GitHashType::Sha256), None);
+        one.refresh().await.unwrap();
+        assert_eq!(one.git_hash(&first, GitHashType::Sha256), Some(first_sha256));
+    }
+
+    #[tokio::test]
+    async fn test_derive_missing_object() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let missing = ObjectId::from_canonical_bytes(b"missing");
+
+        let mut index = GitHashIndex::open(storage).await.unwrap();
+        let result = index.derive(&missing, GitHashType::Sha1).await;
+        assert!(matches!(result, Err(CompatError::Storage(StorageError::ObjectNotFound { .. }))));
+    }
+}
@@ -0,0 +1,203 @@
+//! Git → GitNext import (ADR-006)
+//!
+//! Every object reachable from the repository's refs and `HEAD` is parsed, converted
+//! to its canonical form and written through the `Storage` trait. Children are
+//! converted before their parents, since canonical objects reference children by
+//! `ObjectId`. The Git hash of each object is recorded in the persistent
+//! `GitHashIndex`, so later exports reproduce the original Git ids.
+
+use crate::git_dir::{GitDir, GitHead};
+use crate::git_format::{parse_object, ParsedObject};
+use crate::hash_index::GitHashIndex;
+use crate::{CompatError, Result};
+use gitnext_core::{GitHash, GitHashType, GitObject, ObjectId, ObjectType};
+use gitnext_storage::Storage;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Internal reference recording the checked-out branch (shared with gitnext-operations)
+pub const CURRENT_BRANCH_REF: &str = "refs/gitnext/current-branch";
+
+/// Anything Git objects can be read from by hash, such as a Git directory or a received pack
+pub trait GitObjectSource: Sync {
+    fn hash_type(&self) -> GitHashType;
+
+    /// Read an object's type and content (without the Git header)
+    fn read_object(&self, hash: &GitHash) -> Result<(ObjectType, Vec<u8>)>;
+}
+
+impl GitObjectSource for GitDir {
+    fn hash_type(&self) -> GitHashType {
+        GitDir::hash_type(self)
+    }
+
+    fn read_object(&self, hash: &GitHash) -> Result<(ObjectType, Vec<u8>)> {
+        GitDir::read_object(self, hash)
+    }
+}
+
+/// Options controlling an import
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    /// Re-serialize each canonical object and check it hashes to the original Git id
+    pub verify_round_trip: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        Self { verify_round_trip: true }
+    }
+}
+
+/// Summary of a completed import
+#[derive(Debug, Clone)]
+pub struct ImportReport {
+    pub hash_type: GitHashType,
+    /// Objects converted and stored by this import (previously imported ones excluded)
+    pub objects_imported: usize,
+    /// Imported refs and the canonical objects they point at
+    pub refs: BTreeMap<String, ObjectId>,
+    /// Branch `HEAD` pointed at, if it was not detached
+    pub head_branch: Option<String>,
+    /// Commit `HEAD` resolved to, if any
+    pub head: Option<ObjectId>,
+}
+
+/// Imports on-disk Git repositories into a storage backend
+pub struct GitImporter {
+    storage: Arc<dyn Storage>,
+    options: ImportOptions,
+}
+
+impl GitImporter {
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
+        Self::with_options(storage, ImportOptions::default())
+    }
+
+    pub fn with_options(storage: Arc<dyn Storage>, options: ImportOptions) -> Self {
+        Self { storage, options }
+    }
+
+    /// Import the repository at `path` (working tree, `.git` directory or bare repository)
+    pub async fn import(&self, path: impl AsRef<Path>) -> Result<ImportReport> {
+        let git_dir = GitDir::open(path)?;
+        let hash_type = git_dir.hash_type();
+        let mut index = GitHashIndex::open(self.storage.clone()).await?;
+
+        let git_refs = git_dir.read_refs()?;
+        let head = git_dir.read_head()?;
+        let (head_branch, head_hash) = match head {
+            GitHead::Branch(branch) => {
+                let hash = git_refs.get(&format!("refs/heads/{}", branch)).copied();
+                (Some(branch), hash)
+            }
+            GitHead::Detached(hash) => (None, Some(hash)),
+        };
+
+        let mut objects_imported = 0;
+        for root in git_refs.values().chain(head_hash.iter()) {
+            objects_imported += import_reachable(
+                self.storage.as_ref(),
+                &git_dir,
+                &mut index,
+                root,
+                self.options.verify_round_trip,
+            ).await?;
+        }
+
+        let resolve = |hash: &GitHash| index.object_id(hash).ok_or(CompatError::MissingObject(*hash));
+        let refs = git_refs.iter()
+            .map(|(name, hash)| Ok((name.clone(), resolve(hash)?)))
+            .collect::<Result<BTreeMap<_, _>>>()?;
+        let head = head_hash.as_ref().map(resolve).transpose()?;
+
+        // Persist the hash map before refs make the objects visible
+        index.save().await?;
+
+        let mut tx = self.storage.transaction().await?;
+        for (name, id) in &refs {
+            tx.update_ref(name, id).await?;
+        }
+        if let Some(head) = &head {
+            tx.update_ref("HEAD", head).await?;
+        }
+        if let Some(branch) = &head_branch {
+            let branch_object = GitObject::Blob(gitnext_core::Blob::new(bytes::Bytes::from(branch.clone())));
+            let branch_id = branch_object.canonical_hash();
+            tx.store_object(&branch_id, &branch_object).await?;
+            tx.update_ref(CURRENT_BRANCH_REF, &branch_id).await?;
+        }
+        tx.commit().await?;
+
+        Ok(ImportReport {
+            hash_type,
+            objects_imported,
+            refs,
+            head_branch,
+            head,
+        })
+    }
+}
+
+/// Import `root` and everything reachable from it that is not yet in the index,
+/// returning the number of objects stored
+///
+/// Objects already recorded in `index` are treated as present, so a source only
+/// needs to hold the objects that are new to the storage (as in a thin pack).
+pub async fn import_reachable(
+    storage: &dyn Storage,
+    source: &impl GitObjectSource,
+    index: &mut GitHashIndex,
+    root: &GitHash,
+    verify_round_trip: bool,
+) -> Result<usize> {
+    if index.object_id(root).is_some() {
+        return Ok(0);
+    }
+
+    let hash_type = source.hash_type();
+    let mut imported = 0;
+
+    // (Git hash, parsed object once its children have been pushed). A shared child
+    // may be pushed more than once; whichever entry is popped first imports it and
+    // the rest are skipped.
+    let mut stack: Vec<(GitHash, Option<ParsedObject>)> = vec![(*root, None)];
+
+    while let Some((hash, parsed)) = stack.pop() {
+        match parsed {
+            Some(parsed) => {
+                let object = parsed.to_canonical(index.map())?;
+                let id = object.canonical_hash();
+
+                if verify_round_trip {
+                    let git_bytes = index.deriver().serialize_to_git_format(&object, hash_type)?;
+                    let actual = GitHash::from_git_bytes(&git_bytes, hash_type);
+                    if actual != hash {
+                        return Err(CompatError::RoundTripMismatch { expected: hash, actual });
+                    }
+                }
+
+                storage.store_object(&id, &object).await?;
+                index.record(id, hash);
+                imported += 1;
+            }
+            None => {
+                if index.object_id(&hash).is_some() {
+                    continue;
+                }
+                let (object_type, content) = source.read_object(&hash)?;
+                let parsed = parse_object(object_type, &content, hash_type)?;
+                let children: Vec<GitHash> = parsed.references()
+                    .into_iter()
+                    .filter(|child| index.object_id(child).is_none())
+                    .collect();
+
+                stack.push((hash, Some(parsed)));
+                stack.extend(children.into_iter().map(|child| (child, None)));
+            }
+        }
+    }
+
+    Ok(imported)
+}
@@ -0,0 +1,51 @@
+//! GitNext Compat - Git import/export compatibility layer
+//!
+//! This module implements the compatibility boundary of ADR-001 (Dual-Hash Strategy)
+//! and ADR-006 (Git Import/Export Compatibility Contract).
+
+use gitnext_core::{GitHash, GitNextError};
+use gitnext_storage::StorageError;
+use thiserror::Error;
+
+/// Compatibility layer error types
+#[derive(Debug, Error)]
+pub enum CompatError {
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error("Object error: {0}")]
+    Object(#[from] GitNextError),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid Git repository: {0}")]
+    InvalidRepository(String),
+
+    #[error("Invalid ref name: {0:?}")]
+    InvalidRefName(String),
+
+    #[error("Unsupported Git feature: {0}")]
+    Unsupported(String),
+
+    #[error("Git object not found: {0}")]
+    MissingObject(GitHash),
+
+    #[error("Round-trip mismatch: Git object {expected} re-serializes as {actual}")]
+    RoundTripMismatch { expected: GitHash, actual: GitHash },
+}
+
+pub type Result<T> = std::result::Result<T, CompatError>;
+
+pub mod delta;
+pub mod export;
+pub mod git_dir;
+pub mod git_format;
+pub mod hash_index;
+pub mod import;
+pub mod pack;
+
+pub use export::{is_internal_ref, valid_ref_name, ExportOptions, ExportReport, ExportedHead, GitExporter, ObjectLayout};
+pub use git_dir::{GitDir, GitHead};
+pub use hash_index::{GitHashIndex, GIT_HASH_MAP_REF};
+pub use import::{import_reachable, GitImporter, GitObjectSource, ImportOptions, ImportReport, CURRENT_BRANCH_REF};
@@ -0,0 +1,448 @@
+//! Git packfiles and their version 2 indexes
+//!
+//! Packs imported from disk are read whole into memory; objects are located through
+//! the `.idx` file and deltas are resolved against their bases on demand. Packs
+//! written for export store every object whole (no deltas).
+
+use crate::delta::apply_delta;
+use crate::{CompatError, Result};
+use gitnext_core::{GitHash, GitHashType, GitNextError, ObjectType};
+use std::io::{Read, Write};
+use std::path::Path;
+
+const IDX_MAGIC: &[u8; 4] = b"\xfftOc";
+const PACK_MAGIC: &[u8; 4] = b"PACK";
+
+/// Upper bound on delta chain length, guarding against cycles in corrupt packs
+const MAX_DELTA_DEPTH: usize = 10_000;
+
+/// Most bytes reserved up front for an object whose size comes from a pack or
+/// delta header; sizes there are untrusted, so bigger objects grow as they
+/// inflate instead
+pub const MAX_PREALLOCATION: usize = 1 << 20;
+
+fn invalid(message: impl Into<String>) -> CompatError {
+    CompatError::Object(GitNextError::InvalidFormat(message.into()))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| invalid("Truncated pack index"))
+}
+
+/// Version 2 pack index (`.idx`)
+pub struct PackIndex {
+    hash_type: GitHashType,
+    hashes: Vec<GitHash>,
+    offsets: Vec<u64>,
+}
+
+impl PackIndex {
+    pub fn parse(data: &[u8], hash_type: GitHashType) -> Result<Self> {
+        if data.len() < 8 || &data[..4] != IDX_MAGIC {
+            return Err(CompatError::Unsupported("pack index version 1".to_string()));
+        }
+        let version = read_u32(data, 4)?;
+        if version != 2 {
+            return Err(CompatError::Unsupported(format!("pack index version {}", version)));
+        }
+
+        let hash_len = hash_type.digest_len();
+        let fanout_start = 8;
+        let count = read_u32(data, fanout_start + 255 * 4)? as usize;
+
+        let names_start = fanout_start + 256 * 4;
+        let crc_start = names_start + count * hash_len;
+        let offsets_start = crc_start + count * 4;
+        let large_start = offsets_start + count * 4;
+        if data.len() < large_start + 2 * hash_len {
+            return Err(invalid("Truncated pack index"));
+        }
+
+        let mut hashes = Vec::with_capacity(count);
+        let mut offsets = Vec::with_capacity(count);
+        for i in 0..count {
+            let name = &data[names_start + i * hash_len..names_start + (i + 1) * hash_len];
+            hashes.push(GitHash::from_slice(name)?);
+
+            let offset = read_u32(data, offsets_start + i * 4)?;
+            let offset = if offset & 0x8000_0000 != 0 {
+                let large = large_start + (offset & 0x7fff_ffff) as usize * 8;
+                let bytes = data.get(large..large + 8).ok_or_else(|| invalid("Truncated pack index"))?;
+                u64::from_be_bytes(bytes.try_into().expect("slice of length 8"))
+            } else {
+                offset as u64
+            };
+            offsets.push(offset);
+        }
+
+        if hashes.windows(2).any(|w| w[0].as_bytes() >= w[1].as_bytes()) {
+            return Err(invalid("Pack index names are not sorted"));
+        }
+
+        Ok(Self { hash_type, hashes, offsets })
+    }
+
+    pub fn hash_type(&self) -> GitHashType {
+        self.hash_type
+    }
+
+    pub fn len(&self) -> usize {
+        self.hashes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hashes.is_empty()
+    }
+
+    /// Pack offset of an object, if the pack contains it
+    pub fn lookup(&self, hash: &GitHash) -> Option<u64> {
+        self.hashes
+            .binary_search_by(|probe| probe.as_bytes().cmp(hash.as_bytes()))
+            .ok()
+            .map(|i| self.offsets[i])
+    }
+
+    /// All (hash, offset) pairs in hash order
+    pub fn iter(&self) -> impl Iterator<Item = (&GitHash, u64)> {
+        self.hashes.iter().zip(self.offsets.iter().copied())
+    }
+
+    /// Encode a version 2 index for `entries` of (hash, CRC-32 of the raw entry, offset)
+    pub fn encode(mut entries: Vec<(GitHash, u32, u64)>, pack_checksum: &GitHash) -> Vec<u8> {
+        entries.sort_by(|a, b| a.0.as_bytes().cmp(b.0.as_bytes()));
+
+        let mut out = Vec::new();
+        out.extend_from_slice(IDX_MAGIC);
+        out.extend_from_slice(&2u32.to_be_bytes());
+
+        let mut fanout = [0u32; 256];
+        for (hash, _, _) in &entries {
+            fanout[hash.as_bytes()[0] as usize] += 1;
+        }
+        let mut total = 0;
+        for count in fanout {
+            total += count;
+            out.extend_from_slice(&total.to_be_bytes());
+        }
+
+        for (hash, _, _) in &entries {
+            out.extend_from_slice(hash.as_bytes());
+        }
+        for (_, crc, _) in &entries {
+            out.extend_from_slice(&crc.to_be_bytes());
+        }
+
+        // Offsets that do not fit in 31 bits go to the large offset table
+        let mut large_offsets = Vec::new();
+        for (_, _, offset) in &entries {
+            if *offset < 0x8000_0000 {
+                out.extend_from_slice(&(*offset as u32).to_be_bytes());
+            } else {
+                out.extend_from_slice(&(0x8000_0000 | large_offsets.len() as u32).to_be_bytes());
+                large_offsets.push(*offset);
+            }
+        }
+        for offset in large_offsets {
+            out.extend_from_slice(&offset.to_be_bytes());
+        }
+
+        out.extend_from_slice(pack_checksum.as_bytes());
+        let checksum = GitHash::from_git_bytes(&out, pack_checksum.hash_type());
+        out.extend_from_slice(checksum.as_bytes());
+        out
+    }
+}
+
+/// Entry kinds stored in a pack
+enum PackEntry {
+    Base(ObjectType, Vec<u8>),
+    OfsDelta { base_offset: u64, delta: Vec<u8> },
+    RefDelta { base: GitHash, delta: Vec<u8> },
+}
+
+/// A packfile (`.pack`) paired with its index
+pub struct PackFile {
+    data: Vec<u8>,
+    index: PackIndex,
+}
+
+impl PackFile {
+    /// Open `<name>.pack` together with the `<name>.idx` next to it
+    pub fn open(pack_path: &Path, hash_type: GitHashType) -> Result<Self> {
+        let data = std::fs::read(pack_path)?;
+        let index = PackIndex::parse(&std::fs::read(pack_path.with_extension("idx"))?, hash_type)?;
+        Self::from_parts(data, index)
+    }
+
+    pub fn from_parts(data: Vec<u8>, index: PackIndex) -> Result<Self> {
+        let hash_len = index.hash_type().digest_len();
+        if data.len() < 12 + hash_len || &data[..4] != PACK_MAGIC {
+            return Err(invalid("Not a Git packfile"));
+        }
+        let version = read_u32(&data, 4)?;
+        if version != 2 && version != 3 {
+            return Err(CompatError::Unsupported(format!("pack version {}", version)));
+        }
+        let count = read_u32(&data, 8)? as usize;
+        if count != index.len() {
+            return Err(invalid(format!("Pack has {} objects but its index lists {}", count, index.len())));
+        }
+
+        let (body, trailer) = data.split_at(data.len() - hash_len);
+        let checksum = GitHash::from_git_bytes(body, index.hash_type());
+        if checksum.as_bytes() != trailer {
+            return Err(invalid("Pack checksum mismatch"));
+        }
+
+        Ok(Self { data, index })
+    }
+
+    pub fn index(&self) -> &PackIndex {
+        &self.index
+    }
+
+    pub fn contains(&self, hash: &GitHash) -> bool {
+        self.index.lookup(hash).is_some()
+    }
+
+    /// Read an object by hash, resolving deltas within this pack
+    pub fn read(&self, hash: &GitHash) -> Result<Option<(ObjectType, Vec<u8>)>> {
+        match self.index.lookup(hash) {
+            Some(offset) => self.read_at(offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Read the object stored at `offset`, resolving its delta chain
+    pub fn read_at(&self, offset: u64) -> Result<(ObjectType, Vec<u8>)> {
+        let mut deltas = Vec::new();
+        let mut current = offset;
+
+        let (object_type, mut content) = loop {
+            if deltas.len() > MAX_DELTA_DEPTH {
+                return Err(invalid("Delta chain too deep"));
+            }
+            match self.entry_at(current)? {
+                PackEntry::Base(object_type, content) => break (object_type, content),
+                PackEntry::OfsDelta { base_offset, delta } => {
+                    deltas.push(delta);
+                    current = base_offset;
+                }
+                PackEntry::RefDelta { base, delta } => {
+                    deltas.push(delta);
+                    current = self.index.lookup(&base).ok_or(CompatError::MissingObject(base))?;
+                }
+            }
+        };
+
+        for delta in deltas.iter().rev() {
+            content = apply_delta(&content, delta)?;
+        }
+
+        Ok((object_type, content))
+    }
+
+    fn entry_at(&self, offset: u64) -> Result<PackEntry> {
+        let data = &self.data;
+        let mut pos = usize::try_from(offset).map_err(|_| invalid("Pack offset out of range"))?;
+        let end = data.len() - self.index.hash_type().digest_len();
+        if pos < 12 || pos >= end {
+            return Err(invalid(format!("Pack offset {} out of range", offset)));
+        }
+
+        // Type and size header: 3 type bits, then little-endian base-128 size
+        let mut byte = data[pos];
+        pos += 1;
+        let type_code = (byte >> 4) & 0x7;
+        let mut size = (byte & 0x0f) as usize;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            byte = *data.get(pos).ok_or_else(|| invalid("Truncated pack entry header"))?;
+            pos += 1;
+            if shift >= usize::BITS {
+                return Err(invalid("Pack entry size overflows"));
+            }
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        match type_code {
+            1..=4 => {
+                let object_type = match type_code {
+                    1 => ObjectType::Commit,
+                    2 => ObjectType::Tree,
+                    3 => ObjectType::Blob,
+                    _ => ObjectType::Tag,
+                };
+                Ok(PackEntry::Base(object_type, inflate(&data[pos..end], size)?))
+            }
+            6 => {
+                // Offset encoding adds one per continuation byte so encodings are unique
+                let mut byte = *data.get(pos).ok_or_else(|| invalid("Truncated delta offset"))?;
+                pos += 1;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    byte = *data.get(pos).ok_or_else(|| invalid("Truncated delta offset"))?;
+                    pos += 1;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+                }
+                let base_offset = offset.checked_sub(distance)
+                    .filter(|_| distance > 0)
+                    .ok_or_else(|| invalid("Delta base offset out of range"))?;
+                Ok(PackEntry::OfsDelta { base_offset, delta: inflate(&data[pos..end], size)? })
+            }
+            7 => {
+                let hash_len = self.index.hash_type().digest_len();
+                let base = data.get(pos..pos + hash_len).ok_or_else(|| invalid("Truncated delta base"))?;
+                let base = GitHash::from_slice(base)?;
+                pos += hash_len;
+                Ok(PackEntry::RefDelta { base, delta: inflate(&data[pos..end], size)? })
+            }
+            other => Err(invalid(format!("Unknown pack entry type {}", other))),
+        }
+    }
+}
+
+/// Pack entry type code of a base object
+pub fn pack_type_code(object_type: ObjectType) -> u8 {
+    match object_type {
+        ObjectType::Commit => 1,
+        ObjectType::Tree => 2,
+        ObjectType::Blob => 3,
+        ObjectType::Tag => 4,
+    }
+}
+
+/// Encode a pack entry's type and inflated size header
+pub fn encode_entry_header(type_code: u8, size: usize) -> Vec<u8> {
+    let mut header = Vec::new();
+    let mut byte = (type_code << 4) | (size & 0x0f) as u8;
+    let mut rest = size >> 4;
+    while rest != 0 {
+        header.push(byte | 0x80);
+        byte = (rest & 0x7f) as u8;
+        rest >>= 7;
+    }
+    header.push(byte);
+    header
+}
+
+/// A pack and its index, as produced by `write_pack`
+pub struct WrittenPack {
+    pub pack: Vec<u8>,
+    pub index: Vec<u8>,
+    /// Trailing checksum of the pack, which also names the pack files
+    pub checksum: GitHash,
+}
+
+/// Write an undeltified pack of (hash, type, content) objects and its index
+pub fn write_pack(objects: &[(GitHash, ObjectType, Vec<u8>)], hash_type: GitHashType) -> Result<WrittenPack> {
+    let mut pack = Vec::new();
+    pack.extend_from_slice(PACK_MAGIC);
+    pack.extend_from_slice(&2u32.to_be_bytes());
+    let count = u32::try_from(objects.len()).map_err(|_| invalid("Too many objects for one pack"))?;
+    pack.extend_from_slice(&count.to_be_bytes());
+
+    let mut entries = Vec::with_capacity(objects.len());
+    for (hash, object_type, content) in objects {
+        let offset = pack.len();
+        pack.extend_from_slice(&encode_entry_header(pack_type_code(*object_type), content.len()));
+
+        let mut encoder = flate2::write::ZlibEncoder::new(&mut pack, flate2::Compression::default());
+        encoder.write_all(content)?;
+        encoder.finish()?;
+
+        let crc = crc32fast::hash(&pack[offset..]);
+        entries.push((*hash, crc, offset as u64));
+    }
+
+    let checksum = GitHash::from_git_bytes(&pack, hash_type);
+    pack.extend_from_slice(checksum.as_bytes());
+    let index = PackIndex::encode(entries, &checksum);
+
+    Ok(WrittenPack { pack, index, checksum })
+}
+
+/// Inflate a zlib stream whose decompressed length is known
+pub(crate) fn inflate(data: &[u8], size: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(size.min(MAX_PREALLOCATION));
+    flate2::read::ZlibDecoder::new(data).take((size as u64).saturating_add(1)).read_to_end(&mut out)?;
+    if out.len() != size {
+        return Err(invalid(format!("Inflated size mismatch: expected {}, found {}", size, out.len())));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_header_round_trip() {
+        for size in [0usize, 15, 16, 127, 4096, 1 << 20] {
+            let header = encode_entry_header(3, size);
+            let mut byte = header[0];
+            let mut decoded = (byte & 0x0f) as usize;
+            let mut shift = 4;
+            for &next in &header[1..] {
+                assert!(byte & 0x80 != 0);
+                decoded |= ((next & 0x7f) as usize) << shift;
+                shift += 7;
+                byte = next;
+            }
+            assert_eq!((header[0] >> 4) & 0x7, 3);
+            assert_eq!(decoded, size);
+        }
+    }
+
+    #[test]
+    fn test_write_then_read_pack() {
+        for hash_type in [GitHashType::Sha1, GitHashType::Sha256] {
+            let objects: Vec<(GitHash, ObjectType, Vec<u8>)> = ["one\n", "two\n", "three\n"]
+                .iter()
+                .map(|content| {
+                    let raw = format!("blob {}\0{}", content.len(), content);
+                    let hash = GitHash::from_git_bytes(raw.as_bytes(), hash_type);
+                    (hash, ObjectType::Blob, content.as_bytes().to_vec())
+                })
+                .collect();
+
+            let written = write_pack(&objects, hash_type).unwrap();
+            let index = PackIndex::parse(&written.index, hash_type).unwrap();
+            let pack = PackFile::from_parts(written.pack, index).unwrap();
+
+            assert_eq!(pack.index().len(), 3);
+            for (hash, object_type, content) in &objects {
+                let (read_type, read_content) = pack.read(hash).unwrap().unwrap();
+                assert_eq!(read_type, *object_type);
+                assert_eq!(&read_content, content);
+            }
+        }
+    }
+
+    #[test]
+    fn test_corrupt_pack_is_rejected() {
+        let raw = b"blob 4\0one\n";
+        let hash = GitHash::from_git_bytes(raw, GitHashType::Sha1);
+        let written = write_pack(&[(hash, ObjectType::Blob, b"one\n".to_vec())], GitHashType::Sha1).unwrap();
+
+        let mut corrupt = written.pack.clone();
+        corrupt[14] ^= 0xff;
+        let index = PackIndex::parse(&written.index, GitHashType::Sha1).unwrap();
+        assert!(PackFile::from_parts(corrupt, index).is_err());
+    }
+
+    #[test]
+    fn test_inflate_rejects_forged_size() {
+        let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"one\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(inflate(&compressed, 4).unwrap(), b"one\n");
+        for size in [3, 5, 1 << 40, usize::MAX] {
+            assert!(inflate(&compressed, size).is_err());
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Fixture repositories built with the `git` command line tool
+
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use tempfile::TempDir;
+
+/// A scratch Git repository with a fixed identity and clock
+pub struct GitFixture {
+    dir: TempDir,
+    path: PathBuf,
+}
+
+impl GitFixture {
+    /// Initialize a repository, or return `None` when `git` is not installed
+    pub fn init(extra_args: &[&str]) -> Option<Self> {
+        if Command::new("git").arg("--version").output().is_err() {
+            eprintln!("git not found, skipping");
+            return None;
+        }
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("repo");
+        let fixture = Self { dir, path };
+
+        let mut args = vec!["init", "-q", "-b", "main"];
+        args.extend_from_slice(extra_args);
+        args.push(fixture.path.to_str().unwrap());
+        fixture.git_in(fixture.dir.path(), &args);
+        Some(fixture)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A fresh directory inside the fixture's temporary directory
+    pub fn scratch(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    pub fn git(&self, args: &[&str]) -> String {
+        self.git_in(&self.path, args)
+    }
+
+    pub fn git_in(&self, cwd: &Path, args: &[&str]) -> String {
+        self.git_with_env(cwd, args, &[])
+    }
+
+    /// Run git with extra environment variables overriding the fixed identity
+    pub fn git_with_env(&self, cwd: &Path, args: &[&str], envs: &[(&str, &str)]) -> String {
+        let output = Command::new("git")
+            .args(args)
+            .current_dir(cwd)
+            .env("HOME", self.dir.path())
+            .env("GIT_CONFIG_NOSYSTEM", "1")
+            .env("GIT_CONFIG_GLOBAL", "/dev/null")
+            .env("GIT_AUTHOR_NAME", "Test Author")
+            .env("GIT_AUTHOR_EMAIL", "author@example.com")
+            .env("GIT_AUTHOR_DATE", "1234567890 +0000")
+            .env("GIT_COMMITTER_NAME", "Test Committer")
+            .env("GIT_COMMITTER_EMAIL", "committer@example.com")
+            .env("GIT_COMMITTER_DATE", "1234567890 +0000")
+            .envs(envs.iter().copied())
+            .output()
+            .expect("failed to run git");
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        String::from_utf8_lossy(&output.stdout).trim().to_string()
+    }
+
+    pub fn write(&self, path: &str, contents: &str) {
+        let full = self.path.join(path);
+        std::fs::create_dir_all(full.parent().unwrap()).unwrap();
+        std::fs::write(full, contents).unwrap();
+    }
+
+    /// Stage everything and commit
+    pub fn commit(&self, message: &str) -> String {
+        self.git(&["add", "-A"]);
+        self.git(&["commit", "-q", "-m", message]);
+        self.git(&["rev-parse", "HEAD"])
+    }
+
+    /// A small history: nested directories, an executable, a symlink, a branch,
+    /// a merge and an annotated tag
+    pub fn build_history(&self) {
+        self.write("README.md", "# Fixture\n");
+        self.write("src/lib.rs", "pub fn answer() -> u32 {\n    42\n}\n");
+        self.write("src/nested/mod.rs", "// nested\n");
+        self.commit("Initial commit");
+
+        let script = self.path.join("run.sh");
+        std::fs::write(&script, "#!/bin/sh\necho hi\n").unwrap();
+        self.git(&["update-index", "--add", "--chmod=+x", "run.sh"]);
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("README.md", self.path.join("link")).unwrap();
+        self.commit("Add script and link");
+
+        self.git(&["checkout", "-q", "-b", "feature"]);
+        self.write("src/feature.rs", "pub fn feature() {}\n");
+        self.commit("Add feature");
+
+        self.git(&["checkout", "-q", "main"]);
+        let mut readme = std::fs::read_to_string(self.path.join("README.md")).unwrap();
+        for i in 0..50 {
+            readme.push_str(&format!("Line {} of a long enough file to delta well\n", i));
+        }
+        self.write("README.md", &readme);
+        self.commit("Extend README");
+
+        self.git(&["merge", "-q", "--no-ff", "-m", "Merge feature", "feature"]);
+        self.git(&["tag", "-a", "v1.0", "-m", "Release 1.0"]);
+        self.git(&["tag", "lightweight", "HEAD~1"]);
+    }
+
+    /// Configure SSH commit and tag signing, or return false when `ssh-keygen` is missing
+    pub fn enable_ssh_signing(&self) -> bool {
+        let key = self.dir.path().join("signing_key");
+        let generated = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-C", "test", "-f"])
+            .arg(&key)
+            .output();
+        if !generated.is_ok_and(|output| output.status.success()) {
+            eprintln!("ssh-keygen not available, skipping");
+            return false;
+        }
+
+        self.git(&["config", "gpg.format", "ssh"]);
+        self.git(&["config", "user.signingkey", key.to_str().unwrap()]);
+        true
+    }
+
+    /// History with non-canonical headers: an SSH-signed commit, a signed tag, a
+    /// merge of that tag (`mergetag`), an `encoding` header and a custom header
+    pub fn build_signed_history(&self) {
+        self.write("README.md", "# Signed\n");
+        self.git(&["add", "-A"]);
+        self.git(&["commit", "-q", "-S", "-m", "Signed commit"]);
+
+        self.git(&["checkout", "-q", "-b", "release"]);
+        self.write("release.txt", "1.0\n");
+        self.git(&["add", "-A"]);
+        self.git(&["-c", "i18n.commitEncoding=ISO-8859-1", "commit", "-q", "-m", "Encoded commit"]);
+        self.git(&["tag", "-s", "v1.0-signed", "-m", "Signed release"]);
+
+        self.git(&["checkout", "-q", "main"]);
+        self.write("main.txt", "main\n");
+        self.commit("Diverge main");
+        self.git(&["merge", "-q", "--no-ff", "--no-edit", "v1.0-signed"]);
+
+        // A commit with a header Git itself never writes
+        let tree = self.git(&["rev-parse", "HEAD^{tree}"]);
+        let parent = self.git(&["rev-parse", "HEAD"]);
+        let raw = format!(
+            "tree {}\nparent {}\nauthor Test Author <author@example.com> 1234567890 +0000\n\
+             committer Test Committer <committer@example.com> 1234567890 +0000\n\
+             x-custom first line\n second line\n\nCustom header\n",
+            tree, parent
+        );
+        let raw_path = self.dir.path().join("custom-commit");
+        std::fs::write(&raw_path, raw).unwrap();
+        let custom = self.git(&["hash-object", "-t", "commit", "-w", raw_path.to_str().unwrap()]);
+        self.git(&["update-ref", "refs/heads/main", &custom]);
+    }
+
+    /// Commits with non-UTC timezones, plus a commit written byte for byte whose
+    /// identities use a non UTF-8 name, doubled spaces and a "-0000" timezone
+    pub fn build_identity_history(&self) {
+        self.write("a.txt", "a\n");
+        self.git(&["add", "-A"]);
+        self.git_with_env(&self.path, &["commit", "-q", "-m", "India"], &[
+            ("GIT_AUTHOR_DATE", "1234567890 +0530"),
+            ("GIT_COMMITTER_DATE", "1234567890 -0800"),
+        ]);
+        self.write("b.txt", "b\n");
+        self.git(&["add", "-A"]);
+        self.git_with_env(&self.path, &["commit", "-q", "-m", "Chatham"], &[
+            ("GIT_AUTHOR_DATE", "1234567890 +1245"),
+            ("GIT_COMMITTER_DATE", "1234567890 -0930"),
+        ]);
+
+        let tree = self.git(&["rev-parse", "HEAD^{tree}"]);
+        let parent = self.git(&["rev-parse", "HEAD"]);
+        let mut raw = format!("tree {}\nparent {}\nauthor Caf", tree, parent).into_bytes();
+        raw.extend_from_slice(b"\xe9 <cafe@example.com> 1234567890 -0000\n");
+        raw.extend_from_slice(b"committer Spaced  Out <spaced@example.com>  1234567890 +0000\n\nRaw identities\n");
+        let raw_path = self.dir.path().join("raw-commit");
+        std::fs::write(&raw_path, raw).unwrap();
+        let commit = self.git(&["hash-object", "-t", "commit", "-w", "--literally", raw_path.to_str().unwrap()]);
+        self.git(&["update-ref", "refs/heads/main", &commit]);
+    }
+}
@@ -0,0 +1,221 @@
+//! Export to Git directories checked with `git fsck`, and import → export round trips
+
+mod common;
+
+use common::GitFixture;
+use gitnext_compat::{CompatError, ExportOptions, ExportedHead, GitExporter, GitImporter, ObjectLayout};
+use gitnext_core::{Blob, Commit, FileMode, GitHashType, GitObject, ObjectId, ObjectType, Signature, Tree, TreeEntry};
+use gitnext_storage::{MemoryStorage, Storage};
+use std::path::Path;
+use std::sync::Arc;
+
+fn memory_storage() -> Arc<dyn Storage> {
+    Arc::new(MemoryStorage::new())
+}
+
+/// Sorted `git rev-list --objects --all` ids and `git for-each-ref` lines
+fn git_snapshot(fixture: &GitFixture, git_dir: &Path) -> (Vec<String>, String) {
+    let dir = git_dir.to_str().unwrap();
+    let mut objects: Vec<String> = fixture
+        .git(&["--git-dir", dir, "rev-list", "--objects", "--all"])
+        .lines()
+        .map(|line| line.split(' ').next().unwrap().to_string())
+        .collect();
+    objects.sort();
+    let refs = fixture.git(&["--git-dir", dir, "for-each-ref", "--format=%(objectname) %(refname)"]);
+    (objects, refs)
+}
+
+fn fsck(fixture: &GitFixture, git_dir: &Path) {
+    fixture.git(&["--git-dir", git_dir.to_str().unwrap(), "fsck", "--full", "--strict", "--no-dangling"]);
+}
+
+async fn round_trip(fixture: &GitFixture, options: ExportOptions) {
+    let storage = memory_storage();
+    GitImporter::new(storage.clone()).import(fixture.path()).await.unwrap();
+
+    let target = fixture.scratch("exported.git");
+    let report = GitExporter::with_options(storage, options).export(&target).await.unwrap();
+
+    fsck(fixture, &target);
+    assert_eq!(git_snapshot(fixture, &target), git_snapshot(fixture, &fixture.path().join(".git")));
+    assert_eq!(report.head, ExportedHead::Branch("main".to_string()));
+    assert_eq!(
+        std::fs::read_to_string(target.join("HEAD")).unwrap(),
+        "ref: refs/heads/main\n"
+    );
+}
+
+#[tokio::test]
+async fn test_round_trip_packed() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_history();
+
+    round_trip(&fixture, ExportOptions::default()).await;
+}
+
+#[tokio::test]
+async fn test_round_trip_loose() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_history();
+
+    let options = ExportOptions {
+        layout: ObjectLayout::Loose,
+        packed_refs: false,
+        ..ExportOptions::default()
+    };
+    round_trip(&fixture, options).await;
+}
+
+#[tokio::test]
+async fn test_round_trip_sha256() {
+    let Some(fixture) = GitFixture::init(&["--object-format=sha256"]) else { return };
+    fixture.build_history();
+
+    let options = ExportOptions {
+        hash_type: GitHashType::Sha256,
+        ..ExportOptions::default()
+    };
+    round_trip(&fixture, options).await;
+}
+
+#[tokio::test]
+async fn test_round_trip_signed_history() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    if !fixture.enable_ssh_signing() {
+        return;
+    }
+    fixture.build_signed_history();
+
+    round_trip(&fixture, ExportOptions::default()).await;
+    let exported = fixture.scratch("exported.git");
+    let dir = exported.to_str().unwrap();
+    let signed = fixture.git(&["--git-dir", dir, "cat-file", "commit", "main~3"]);
+    assert!(signed.contains("gpgsig -----BEGIN SSH SIGNATURE-----"));
+}
+
+#[tokio::test]
+async fn test_round_trip_identities_and_timezones() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_identity_history();
+
+    let storage = memory_storage();
+    GitImporter::new(storage.clone()).import(fixture.path()).await.unwrap();
+    let target = fixture.scratch("exported.git");
+    GitExporter::new(storage).export(&target).await.unwrap();
+
+    // The raw commit is not fsck-clean, so compare object ids only
+    assert_eq!(git_snapshot(&fixture, &target), git_snapshot(&fixture, &fixture.path().join(".git")));
+    let dir = target.to_str().unwrap();
+    let india = fixture.git(&["--git-dir", dir, "cat-file", "commit", "main~2"]);
+    assert!(india.contains("> 1234567890 +0530\ncommitter"));
+    assert!(india.contains("> 1234567890 -0800\n"));
+}
+
+#[tokio::test]
+async fn test_exported_repository_can_be_reimported() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_history();
+
+    let storage = memory_storage();
+    let imported = GitImporter::new(storage.clone()).import(fixture.path()).await.unwrap();
+    let target = fixture.scratch("exported.git");
+    GitExporter::new(storage).export(&target).await.unwrap();
+
+    // The exported pack is readable by our own importer and yields the same canonical ids
+    let reimported = GitImporter::new(memory_storage()).import(&target).await.unwrap();
+    assert_eq!(reimported.refs, imported.refs);
+    assert_eq!(reimported.head, imported.head);
+}
+
+fn signature() -> Signature {
+    Signature {
+        name: "Test Author".to_string(),
+        email: "author@example.com".to_string(),
+        timestamp: 1234567890,
+        timezone_offset: 0,
+        raw: None,
+    }
+}
+
+async fn store(storage: &Arc<dyn Storage>, object: GitObject) -> ObjectId {
+    let id = object.canonical_hash();
+    storage.store_object(&id, &object).await.unwrap();
+    id
+}
+
+#[tokio::test]
+async fn test_export_native_history_skips_internal_refs() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    let storage = memory_storage();
+
+    let blob = store(&storage, GitObject::Blob(Blob::new(bytes::Bytes::from("hello\n")))).await;
+    let tree = store(&storage, GitObject::Tree(Tree::new(vec![TreeEntry {
+        name: "hello.txt".to_string(),
+        mode: FileMode::Normal,
+        hash: blob,
+        entry_type: ObjectType::Blob,
+        raw_name: None,
+    }]))).await;
+    let commit = store(&storage, GitObject::Commit(Commit {
+        tree,
+        parents: vec![],
+        author: signature(),
+        committer: signature(),
+        extra_headers: Vec::new(),
+        message: "Native commit\n".to_string(),
+        raw_message: None,
+    })).await;
+    storage.update_ref("refs/heads/main", &commit).await.unwrap();
+    storage.update_ref("HEAD", &commit).await.unwrap();
+
+    // Bookkeeping written by the operation log must not leak into the export
+    let log = store(&storage, GitObject::Blob(Blob::new(bytes::Bytes::from("operation log")))).await;
+    storage.update_ref("refs/logs/chain", &log).await.unwrap();
+    storage.update_ref("refs/logs/operations/0000", &log).await.unwrap();
+    let branch = store(&storage, GitObject::Blob(Blob::new(bytes::Bytes::from("main")))).await;
+    storage.update_ref("refs/gitnext/current-branch", &branch).await.unwrap();
+
+    let target = fixture.scratch("native.git");
+    let report = GitExporter::new(storage).export(&target).await.unwrap();
+
+    assert_eq!(report.objects_written, 3);
+    assert_eq!(report.refs.keys().collect::<Vec<_>>(), vec!["refs/heads/main"]);
+    fsck(&fixture, &target);
+
+    let dir = target.to_str().unwrap();
+    assert_eq!(fixture.git(&["--git-dir", dir, "log", "--format=%s", "main"]), "Native commit");
+    assert_eq!(fixture.git(&["--git-dir", dir, "show", "main:hello.txt"]), "hello");
+    assert_eq!(fixture.git(&["--git-dir", dir, "count-objects", "-v"]).lines()
+        .find(|l| l.starts_with("in-pack:")).unwrap(), "in-pack: 3");
+}
+
+#[tokio::test]
+async fn test_export_refuses_non_empty_target() {
+    let dir = tempfile::TempDir::new().unwrap();
+    std::fs::write(dir.path().join("existing"), "data").unwrap();
+
+    let result = GitExporter::new(memory_storage()).export(dir.path()).await;
+    assert!(result.is_err());
+}
+
+#[tokio::test]
+async fn test_export_refuses_escaping_ref_names() {
+    let storage = memory_storage();
+    let blob = store(&storage, GitObject::Blob(Blob::new(bytes::Bytes::from("hello\n")))).await;
+    let tree = store(&storage, GitObject::Tree(Tree::new(vec![TreeEntry {
+        name: "hello.txt".to_string(),
+        mode: FileMode::Normal,
+        hash: blob,
+        entry_type: ObjectType::Blob,
+        raw_name: None,
+    }]))).await;
+    storage.update_ref("refs/tags/../../escaped", &tree).await.unwrap();
+
+    let root = tempfile::TempDir::new().unwrap();
+    let target = root.path().join("export.git");
+    let options = ExportOptions { packed_refs: false, ..ExportOptions::default() };
+    let result = GitExporter::with_options(storage, options).export(&target).await;
+    assert!(matches!(result, Err(CompatError::InvalidRefName(_))));
+    assert!(!root.path().join("escaped").exists());
+}
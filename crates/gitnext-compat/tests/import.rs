@@ -0,0 +1,260 @@
+//! Import of real Git repositories produced by the `git` command line tool
+
+mod common;
+
+use common::GitFixture;
+use gitnext_compat::{CompatError, GitHashIndex, GitImporter, ImportReport, CURRENT_BRANCH_REF};
+use gitnext_core::{GitHash, GitHashType, GitObject};
+use gitnext_storage::{MemoryStorage, ReferenceTarget, Storage};
+use std::sync::Arc;
+
+fn memory_storage() -> Arc<dyn Storage> {
+    Arc::new(MemoryStorage::new())
+}
+
+/// Every imported ref must point at an object whose derived Git id matches Git's own
+async fn assert_ids_match_git(fixture: &GitFixture, storage: &Arc<dyn Storage>, report: &ImportReport) {
+    let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+
+    for (name, id) in &report.refs {
+        let expected = fixture.git(&["rev-parse", name]);
+        let derived = index.derive(id, report.hash_type).await.unwrap();
+        assert_eq!(derived.to_string(), expected, "ref {}", name);
+    }
+
+    // Every reachable object was imported and maps back to a stored object
+    let listing = fixture.git(&["rev-list", "--objects", "--all"]);
+    for line in listing.lines() {
+        let hash = GitHash::from_hex(line.split(' ').next().unwrap()).unwrap();
+        let id = index.object_id(&hash).unwrap_or_else(|| panic!("{} was not imported", hash));
+        assert!(storage.load_object(&id).await.unwrap().is_some());
+    }
+    assert_eq!(report.objects_imported, listing.lines().count());
+}
+
+async fn ref_target(storage: &Arc<dyn Storage>, name: &str) -> Option<gitnext_core::ObjectId> {
+    storage.list_refs().await.unwrap().into_iter()
+        .find(|r| r.name == name)
+        .and_then(|r| match r.target {
+            ReferenceTarget::Direct(id) => Some(id),
+            ReferenceTarget::Symbolic(_) => None,
+        })
+}
+
+#[tokio::test]
+async fn test_import_loose_objects() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_history();
+
+    let storage = memory_storage();
+    let report = GitImporter::new(storage.clone()).import(fixture.path()).await.unwrap();
+
+    assert_eq!(report.hash_type, GitHashType::Sha1);
+    assert_eq!(report.head_branch.as_deref(), Some("main"));
+    for name in ["refs/heads/main", "refs/heads/feature", "refs/tags/v1.0", "refs/tags/lightweight"] {
+        assert!(report.refs.contains_key(name), "missing {}", name);
+    }
+    assert_ids_match_git(&fixture, &storage, &report).await;
+
+    // The annotated tag is imported as a tag object
+    let tag_id = report.refs["refs/tags/v1.0"];
+    match storage.load_object(&tag_id).await.unwrap() {
+        Some(GitObject::Tag(tag)) => {
+            assert_eq!(tag.name, "v1.0");
+            assert_eq!(Some(tag.target), report.head);
+        }
+        other => panic!("expected a tag, found {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_import_packed_repository() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_history();
+    fixture.git(&["repack", "-adq", "--depth=50", "--window=250"]);
+    fixture.git(&["pack-refs", "--all", "--prune"]);
+    fixture.git(&["prune-packed"]);
+
+    assert!(fixture.path().join(".git/packed-refs").exists());
+    let storage = memory_storage();
+    let report = GitImporter::new(storage.clone()).import(fixture.path()).await.unwrap();
+
+    assert_ids_match_git(&fixture, &storage, &report).await;
+}
+
+#[tokio::test]
+async fn test_import_bare_clone() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_history();
+
+    let bare = fixture.scratch("bare.git");
+    fixture.git(&["clone", "-q", "--bare", fixture.path().to_str().unwrap(), bare.to_str().unwrap()]);
+
+    let storage = memory_storage();
+    let report = GitImporter::new(storage.clone()).import(&bare).await.unwrap();
+
+    assert_eq!(report.head_branch.as_deref(), Some("main"));
+    assert_eq!(report.refs.len(), 4);
+    assert_ids_match_git(&fixture, &storage, &report).await;
+}
+
+#[tokio::test]
+async fn test_import_sha256_repository() {
+    let Some(fixture) = GitFixture::init(&["--object-format=sha256"]) else { return };
+    fixture.build_history();
+    fixture.git(&["gc", "-q"]);
+
+    let storage = memory_storage();
+    let report = GitImporter::new(storage.clone()).import(fixture.path()).await.unwrap();
+
+    assert_eq!(report.hash_type, GitHashType::Sha256);
+    assert_ids_match_git(&fixture, &storage, &report).await;
+}
+
+#[tokio::test]
+async fn test_import_records_head_and_current_branch() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_history();
+    fixture.git(&["checkout", "-q", "feature"]);
+
+    let storage = memory_storage();
+    let report = GitImporter::new(storage.clone()).import(fixture.path()).await.unwrap();
+
+    assert_eq!(report.head, Some(report.refs["refs/heads/feature"]));
+    assert_eq!(ref_target(&storage, "HEAD").await, report.head);
+
+    let branch_id = ref_target(&storage, CURRENT_BRANCH_REF).await.unwrap();
+    match storage.load_object(&branch_id).await.unwrap() {
+        Some(GitObject::Blob(blob)) => assert_eq!(blob.content.unwrap().as_ref(), b"feature"),
+        other => panic!("expected a blob, found {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_import_detached_head() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_history();
+    fixture.git(&["checkout", "-q", "--detach", "HEAD~1"]);
+
+    let storage = memory_storage();
+    let report = GitImporter::new(storage.clone()).import(fixture.path()).await.unwrap();
+
+    assert_eq!(report.head_branch, None);
+    assert!(ref_target(&storage, CURRENT_BRANCH_REF).await.is_none());
+
+    let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+    let head = index.derive(&report.head.unwrap(), GitHashType::Sha1).await.unwrap();
+    assert_eq!(head.to_string(), fixture.git(&["rev-parse", "HEAD"]));
+}
+
+#[tokio::test]
+async fn test_reimport_is_incremental() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_history();
+
+    let storage = memory_storage();
+    let importer = GitImporter::new(storage.clone());
+    importer.import(fixture.path()).await.unwrap();
+
+    fixture.write("new.txt", "new\n");
+    fixture.commit("Add new file");
+
+    // Only the new blob, root tree and commit are converted
+    let report = importer.import(fixture.path()).await.unwrap();
+    assert_eq!(report.objects_imported, 3);
+    assert_eq!(ref_target(&storage, "refs/heads/main").await, report.head);
+}
+
+#[tokio::test]
+async fn test_import_preserves_signatures_and_extra_headers() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    if !fixture.enable_ssh_signing() {
+        return;
+    }
+    fixture.build_signed_history();
+
+    let storage = memory_storage();
+    let report = GitImporter::new(storage.clone()).import(fixture.path()).await.unwrap();
+    assert_ids_match_git(&fixture, &storage, &report).await;
+
+    let index = GitHashIndex::open(storage.clone()).await.unwrap();
+    let load_commit = |rev: &str| {
+        let hash = GitHash::from_hex(&fixture.git(&["rev-parse", rev])).unwrap();
+        let id = index.object_id(&hash).unwrap();
+        let storage = storage.clone();
+        async move {
+            match storage.load_object(&id).await.unwrap() {
+                Some(GitObject::Commit(commit)) => commit,
+                other => panic!("expected a commit, found {:?}", other),
+            }
+        }
+    };
+
+    let custom = load_commit("main").await;
+    assert_eq!(custom.extra_headers.len(), 1);
+    assert_eq!(custom.extra_headers[0].name, "x-custom");
+    assert_eq!(custom.extra_headers[0].value.as_ref(), b"first line\nsecond line");
+
+    let merge = load_commit("main~1").await;
+    assert!(merge.extra_headers.iter().any(|h| h.name == "mergetag"));
+
+    let signed = load_commit(":/Signed commit").await;
+    assert!(signed.signature().unwrap().starts_with(b"-----BEGIN SSH SIGNATURE-----"));
+
+    let encoded = load_commit("release").await;
+    assert_eq!(encoded.extra_headers[0].name, "encoding");
+
+    let tag_hash = GitHash::from_hex(&fixture.git(&["rev-parse", "v1.0-signed"])).unwrap();
+    match storage.load_object(&index.object_id(&tag_hash).unwrap()).await.unwrap() {
+        Some(GitObject::Tag(tag)) => {
+            assert_eq!(tag.message, "Signed release\n");
+            assert!(tag.signature.unwrap().starts_with(b"-----BEGIN SSH SIGNATURE-----"));
+        }
+        other => panic!("expected a tag, found {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_import_submodules_raw_names_and_taggerless_tags() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.write("file.txt", "content\n");
+    let head = fixture.commit("Initial commit");
+    std::fs::write(fixture.path().join(std::ffi::OsStr::from_bytes(b"caf\xe9.txt")), "latin-1 name\n").unwrap();
+    fixture.git(&["add", "-A"]);
+    fixture.git(&["update-index", "--add", "--cacheinfo", &format!("160000,{},module", head)]);
+    fixture.git(&["commit", "-q", "-m", "Add submodule"]);
+
+    // Tags made before Git recorded taggers
+    let tag_file = fixture.scratch("tag");
+    std::fs::write(&tag_file, format!("object {}\ntype commit\ntag v0.1\n\nOld release\n", head)).unwrap();
+    let tag = fixture.git(&["hash-object", "-t", "tag", "-w", "--literally", tag_file.to_str().unwrap()]);
+    fixture.git(&["update-ref", "refs/tags/v0.1", &tag]);
+
+    let storage = memory_storage();
+    let report = GitImporter::new(storage.clone()).import(fixture.path()).await.unwrap();
+    assert_ids_match_git(&fixture, &storage, &report).await;
+
+    let Some(GitObject::Commit(commit)) = storage.load_object(&report.head.unwrap()).await.unwrap() else {
+        panic!("expected a commit");
+    };
+    let Some(GitObject::Tree(tree)) = storage.load_object(&commit.tree).await.unwrap() else {
+        panic!("expected a tree");
+    };
+    let module = tree.entries.iter().find(|e| e.name == "module").unwrap();
+    assert_eq!(module.gitlink_target().map(|hash| hash.to_string()), Some(head));
+    assert!(tree.entries.iter().any(|e| e.raw_name.as_deref() == Some(&b"caf\xe9.txt"[..])));
+
+    match storage.load_object(&report.refs["refs/tags/v0.1"]).await.unwrap() {
+        Some(GitObject::Tag(tag)) => assert!(tag.tagger.is_none()),
+        other => panic!("expected a tag, found {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn test_import_rejects_non_repository() {
+    let dir = tempfile::TempDir::new().unwrap();
+    let result = GitImporter::new(memory_storage()).import(dir.path()).await;
+    assert!(matches!(result, Err(CompatError::InvalidRepository(_))));
+}
@@ -47,6 +47,26 @@ impl ObjectId {
     pub fn from_blake3_bytes(bytes: [u8; 32]) -> Self {
         Self { blake3_hash: bytes }
     }
+
+    /// Id recorded by a gitlink for the submodule commit `commit`
+    ///
+    /// That commit lives in another repository and is never stored here, so the
+    /// id is its Git hash, zero padded to 32 bytes when it is a SHA-1.
+    pub fn for_gitlink(commit: &GitHash) -> Self {
+        let mut bytes = [0u8; 32];
+        bytes[..commit.as_bytes().len()].copy_from_slice(commit.as_bytes());
+        Self { blake3_hash: bytes }
+    }
+
+    /// Git hash of the submodule commit behind an id made by `for_gitlink`
+    pub fn gitlink_commit(&self) -> GitHash {
+        match self.blake3_hash.split_at(20) {
+            (sha1, padding) if padding.iter().all(|&b| b == 0) => {
+                GitHash::from_slice(sha1).expect("SHA-1 digests are 20 bytes")
+            }
+            _ => GitHash::Sha256(self.blake3_hash),
+        }
+    }
 }
 
 impl fmt::Display for ObjectId {
@@ -65,32 +85,47 @@ pub enum GitHash {
 impl GitHash {
     /// Derive Git hash from Git-formatted bytes (not from BLAKE3)
     pub fn from_git_bytes(bytes: &[u8], hash_type: GitHashType) -> Self {
-        match hash_type {
-            GitHashType::Sha1 => {
-                use sha1::{Digest, Sha1};
-                let mut hasher = Sha1::new();
-                hasher.update(bytes);
-                let result = hasher.finalize();
+        let mut hasher = GitHasher::new(hash_type);
+        hasher.update(bytes);
+        hasher.finalize()
+    }
+    
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            GitHash::Sha1(bytes) => bytes,
+            GitHash::Sha256(bytes) => bytes,
+        }
+    }
+    
+    /// Create from raw digest bytes; the length selects the hash algorithm
+    pub fn from_slice(bytes: &[u8]) -> Result<Self> {
+        match bytes.len() {
+            20 => {
                 let mut hash_bytes = [0u8; 20];
-                hash_bytes.copy_from_slice(&result);
-                GitHash::Sha1(hash_bytes)
+                hash_bytes.copy_from_slice(bytes);
+                Ok(GitHash::Sha1(hash_bytes))
             }
-            GitHashType::Sha256 => {
-                use sha2::{Digest, Sha256};
-                let mut hasher = Sha256::new();
-                hasher.update(bytes);
-                let result = hasher.finalize();
+            32 => {
                 let mut hash_bytes = [0u8; 32];
-                hash_bytes.copy_from_slice(&result);
-                GitHash::Sha256(hash_bytes)
+                hash_bytes.copy_from_slice(bytes);
+                Ok(GitHash::Sha256(hash_bytes))
             }
+            len => Err(GitNextError::InvalidFormat(format!("Invalid Git hash length: {}", len))),
         }
     }
     
-    pub fn as_bytes(&self) -> &[u8] {
+    /// Parse a 40 (SHA-1) or 64 (SHA-256) character hex string
+    pub fn from_hex(hex_str: &str) -> Result<Self> {
+        let bytes = hex::decode(hex_str)
+            .map_err(|e| GitNextError::InvalidFormat(format!("Invalid Git hash '{}': {}", hex_str, e)))?;
+        Self::from_slice(&bytes)
+    }
+    
+    /// The hash algorithm this hash was produced with
+    pub fn hash_type(&self) -> GitHashType {
         match self {
-            GitHash::Sha1(bytes) => bytes,
-            GitHash::Sha256(bytes) => bytes,
+            GitHash::Sha1(_) => GitHashType::Sha1,
+            GitHash::Sha256(_) => GitHashType::Sha256,
         }
     }
 }
@@ -101,6 +136,39 @@ impl fmt::Display for GitHash {
     }
 }
 
+/// Incremental SHA-1/SHA-256 hasher, for data such as packfiles that arrive in pieces
+#[derive(Clone)]
+pub enum GitHasher {
+    Sha1(sha1::Sha1),
+    Sha256(sha2::Sha256),
+}
+
+impl GitHasher {
+    pub fn new(hash_type: GitHashType) -> Self {
+        use sha2::Digest;
+        match hash_type {
+            GitHashType::Sha1 => GitHasher::Sha1(sha1::Sha1::new()),
+            GitHashType::Sha256 => GitHasher::Sha256(sha2::Sha256::new()),
+        }
+    }
+    
+    pub fn update(&mut self, bytes: &[u8]) {
+        use sha2::Digest;
+        match self {
+            GitHasher::Sha1(hasher) => hasher.update(bytes),
+            GitHasher::Sha256(hasher) => hasher.update(bytes),
+        }
+    }
+    
+    pub fn finalize(self) -> GitHash {
+        use sha2::Digest;
+        match self {
+            GitHasher::Sha1(hasher) => GitHash::Sha1(hasher.finalize().into()),
+            GitHasher::Sha256(hasher) => GitHash::Sha256(hasher.finalize().into()),
+        }
+    }
+}
+
 /// Git hash algorithm types
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum GitHashType {
@@ -108,51 +176,152 @@ pub enum GitHashType {
     Sha256,
 }
 
+impl GitHashType {
+    /// Length of the raw digest in bytes
+    pub fn digest_len(&self) -> usize {
+        match self {
+            GitHashType::Sha1 => 20,
+            GitHashType::Sha256 => 32,
+        }
+    }
+}
+
+/// Bidirectional map between canonical ObjectIds and their Git hashes (ADR-001)
+/// Git trees, commits and tags reference their children by Git hash, so exporting
+/// any non-blob object requires the Git hashes of everything it points at.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GitHashMap {
+    to_git: std::collections::HashMap<(ObjectId, GitHashType), GitHash>,
+    to_canonical: std::collections::HashMap<GitHash, ObjectId>,
+    /// Every insert that changed the map, in order
+    recorded: Vec<(ObjectId, GitHash)>,
+}
+
+impl GitHashMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    /// Record that `id` serializes to `git_hash` in Git format
+    pub fn insert(&mut self, id: ObjectId, git_hash: GitHash) {
+        let git = self.to_git.insert((id, git_hash.hash_type()), git_hash);
+        let canonical = self.to_canonical.insert(git_hash, id);
+        if git != Some(git_hash) || canonical != Some(id) {
+            self.recorded.push((id, git_hash));
+        }
+    }
+
+    /// Mappings in the order they were recorded; inserting them in that order
+    /// into an empty map rebuilds this one
+    pub fn recorded(&self) -> &[(ObjectId, GitHash)] {
+        &self.recorded
+    }
+    
+    /// Look up the Git hash of a canonical object
+    pub fn git_hash(&self, id: &ObjectId, hash_type: GitHashType) -> Option<GitHash> {
+        self.to_git.get(&(*id, hash_type)).copied()
+    }
+    
+    /// Reverse lookup of the canonical object for a Git hash
+    pub fn object_id(&self, git_hash: &GitHash) -> Option<ObjectId> {
+        self.to_canonical.get(git_hash).copied()
+    }
+    
+    /// Merge all mappings from another map into this one
+    pub fn extend(&mut self, other: GitHashMap) {
+        for (git_hash, id) in other.to_canonical {
+            self.insert(id, git_hash);
+        }
+    }
+    
+    /// Number of recorded (object, hash type) mappings
+    pub fn len(&self) -> usize {
+        self.to_git.len()
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.to_git.is_empty()
+    }
+}
+
 /// Hash derivation utility for Git compatibility (ADR-001)
-/// This derives Git hashes by re-serializing canonical objects to Git format
+/// This derives Git hashes by re-serializing canonical objects to Git format.
+/// Child hashes are resolved through a `GitHashMap`, so whole DAGs must be
+/// derived bottom-up: blobs, then trees, then commits and tags.
 pub struct CompatHashDeriver {
-    // Cache for performance optimization (not conversion)
-    cache: std::collections::HashMap<(ObjectId, GitHashType), GitHash>,
+    map: GitHashMap,
 }
 
 impl CompatHashDeriver {
     pub fn new() -> Self {
         Self {
-            cache: std::collections::HashMap::new(),
+            map: GitHashMap::new(),
         }
     }
     
+    /// Create a deriver seeded with previously derived mappings
+    pub fn with_map(map: GitHashMap) -> Self {
+        Self { map }
+    }
+    
+    /// The mappings derived or recorded so far
+    pub fn map(&self) -> &GitHashMap {
+        &self.map
+    }
+    
+    pub fn map_mut(&mut self) -> &mut GitHashMap {
+        &mut self.map
+    }
+    
+    pub fn into_map(self) -> GitHashMap {
+        self.map
+    }
+    
+    /// Reverse lookup of a canonical object by its Git hash (not a conversion)
+    pub fn lookup_by_git_hash(&self, git_hash: &GitHash) -> Option<ObjectId> {
+        self.map.object_id(git_hash)
+    }
+    
     /// Derive Git hash from canonical object by re-serializing to Git format
-    /// This is NOT a conversion from BLAKE3 - it's a re-serialization and hash
+    /// This is NOT a conversion from BLAKE3 - it's a re-serialization and hash.
+    /// Every object the given one references must already have a Git hash of
+    /// the same type recorded in the map.
     pub fn derive_git_hash(&mut self, object: &GitObject, hash_type: GitHashType) -> Result<GitHash> {
         let object_id = object.canonical_hash();
-        let cache_key = (object_id, hash_type);
         
-        // Check cache first
-        if let Some(cached_hash) = self.cache.get(&cache_key) {
-            return Ok(*cached_hash);
+        // Check previously derived mappings first
+        if let Some(git_hash) = self.map.git_hash(&object_id, hash_type) {
+            return Ok(git_hash);
         }
         
         // Serialize to Git format and hash
-        let git_bytes = self.serialize_to_git_format(object)?;
+        let git_bytes = self.serialize_to_git_format(object, hash_type)?;
         let git_hash = GitHash::from_git_bytes(&git_bytes, hash_type);
         
-        // Cache the result
-        self.cache.insert(cache_key, git_hash);
+        self.map.insert(object_id, git_hash);
         
         Ok(git_hash)
     }
     
     /// Serialize canonical object to Git binary format
-    pub fn serialize_to_git_format(&self, object: &GitObject) -> Result<Vec<u8>> {
+    pub fn serialize_to_git_format(&self, object: &GitObject, hash_type: GitHashType) -> Result<Vec<u8>> {
         match object {
             GitObject::Blob(blob) => self.serialize_blob_to_git(blob),
-            GitObject::Tree(tree) => self.serialize_tree_to_git(tree),
-            GitObject::Commit(commit) => self.serialize_commit_to_git(commit),
-            GitObject::Tag(tag) => self.serialize_tag_to_git(tag),
+            GitObject::Tree(tree) => self.serialize_tree_to_git(tree, hash_type),
+            GitObject::Commit(commit) => self.serialize_commit_to_git(commit, hash_type),
+            GitObject::Tag(tag) => self.serialize_tag_to_git(tag, hash_type),
         }
     }
     
+    /// Resolve the Git hash of a referenced child object
+    fn child_git_hash(&self, id: &ObjectId, hash_type: GitHashType) -> Result<GitHash> {
+        self.map.git_hash(id, hash_type).ok_or_else(|| {
+            GitNextError::HashDerivation(format!(
+                "No {:?} hash recorded for referenced object {}", hash_type, id
+            ))
+        })
+    }
+    
     fn serialize_blob_to_git(&self, blob: &Blob) -> Result<Vec<u8>> {
         let content = blob.content.as_ref().ok_or_else(|| {
             GitNextError::InvalidFormat("Blob missing content for Git export".to_string())
@@ -164,22 +333,36 @@ impl CompatHashDeriver {
         Ok(result)
     }
     
-    fn serialize_tree_to_git(&self, tree: &Tree) -> Result<Vec<u8>> {
+    fn serialize_tree_to_git(&self, tree: &Tree, hash_type: GitHashType) -> Result<Vec<u8>> {
         let mut content = Vec::new();
         
-        for entry in &tree.entries {
+        // Git sorts entries as if directory names had a trailing '/'
+        let mut entries: Vec<&TreeEntry> = tree.entries.iter().collect();
+        entries.sort_by_cached_key(|entry| git_tree_order(entry));
+        
+        for entry in entries {
             let mode_str = match entry.mode {
                 FileMode::Normal => "100644",
                 FileMode::Executable => "100755", 
                 FileMode::Symlink => "120000",
                 FileMode::Tree => "40000",
+                FileMode::Gitlink => "160000",
+            };
+            let git_hash = match entry.gitlink_target() {
+                Some(commit) if commit.hash_type() != hash_type => {
+                    return Err(GitNextError::HashDerivation(format!(
+                        "Submodule commit {} of '{}' has no {:?} hash", commit, entry.name, hash_type
+                    )));
+                }
+                Some(commit) => commit,
+                None => self.child_git_hash(&entry.hash, hash_type)?,
             };
             
             content.extend_from_slice(mode_str.as_bytes());
             content.push(b' ');
-            content.extend_from_slice(entry.name.as_bytes());
+            content.extend_from_slice(entry.name_bytes());
             content.push(0);
-            content.extend_from_slice(entry.hash.as_bytes());
+            content.extend_from_slice(git_hash.as_bytes());
         }
         
         let header = format!("tree {}\0", content.len());
@@ -188,36 +371,38 @@ impl CompatHashDeriver {
         Ok(result)
     }
     
-    fn serialize_commit_to_git(&self, commit: &Commit) -> Result<Vec<u8>> {
+    fn serialize_commit_to_git(&self, commit: &Commit, hash_type: GitHashType) -> Result<Vec<u8>> {
         let mut content = Vec::new();
         
         // Tree line
         content.extend_from_slice(b"tree ");
-        content.extend_from_slice(hex::encode(commit.tree.as_bytes()).as_bytes());
+        content.extend_from_slice(self.child_git_hash(&commit.tree, hash_type)?.to_string().as_bytes());
         content.push(b'\n');
         
         // Parent lines
         for parent in &commit.parents {
             content.extend_from_slice(b"parent ");
-            content.extend_from_slice(hex::encode(parent.as_bytes()).as_bytes());
+            content.extend_from_slice(self.child_git_hash(parent, hash_type)?.to_string().as_bytes());
             content.push(b'\n');
         }
         
         // Author line
         content.extend_from_slice(b"author ");
-        content.extend_from_slice(self.format_signature(&commit.author).as_bytes());
+        content.extend_from_slice(&commit.author.to_git_bytes());
         content.push(b'\n');
         
         // Committer line
         content.extend_from_slice(b"committer ");
-        content.extend_from_slice(self.format_signature(&commit.committer).as_bytes());
+        content.extend_from_slice(&commit.committer.to_git_bytes());
         content.push(b'\n');
         
+        write_extra_headers(&mut content, &commit.extra_headers);
+        
         // Empty line before message
         content.push(b'\n');
         
         // Message
-        content.extend_from_slice(commit.message.as_bytes());
+        content.extend_from_slice(message_bytes(&commit.message, &commit.raw_message));
         
         let header = format!("commit {}\0", content.len());
         let mut result = header.into_bytes();
@@ -225,12 +410,12 @@ impl CompatHashDeriver {
         Ok(result)
     }
     
-    fn serialize_tag_to_git(&self, tag: &Tag) -> Result<Vec<u8>> {
+    fn serialize_tag_to_git(&self, tag: &Tag, hash_type: GitHashType) -> Result<Vec<u8>> {
         let mut content = Vec::new();
         
         // Object line
         content.extend_from_slice(b"object ");
-        content.extend_from_slice(hex::encode(tag.target.as_bytes()).as_bytes());
+        content.extend_from_slice(self.child_git_hash(&tag.target, hash_type)?.to_string().as_bytes());
         content.push(b'\n');
         
         // Type line
@@ -249,30 +434,29 @@ impl CompatHashDeriver {
         content.extend_from_slice(tag.name.as_bytes());
         content.push(b'\n');
         
-        // Tagger line
-        content.extend_from_slice(b"tagger ");
-        content.extend_from_slice(self.format_signature(&tag.tagger).as_bytes());
-        content.push(b'\n');
+        // Tagger line, missing from some old tags
+        if let Some(tagger) = &tag.tagger {
+            content.extend_from_slice(b"tagger ");
+            content.extend_from_slice(&tagger.to_git_bytes());
+            content.push(b'\n');
+        }
+        
+        write_extra_headers(&mut content, &tag.extra_headers);
         
         // Empty line before message
         content.push(b'\n');
         
-        // Message
-        content.extend_from_slice(tag.message.as_bytes());
+        // Message, then the signature block of signed tags
+        content.extend_from_slice(message_bytes(&tag.message, &tag.raw_message));
+        if let Some(signature) = &tag.signature {
+            content.extend_from_slice(signature);
+        }
         
         let header = format!("tag {}\0", content.len());
         let mut result = header.into_bytes();
         result.extend_from_slice(&content);
         Ok(result)
     }
-    
-    fn format_signature(&self, sig: &Signature) -> String {
-        format!("{} <{}> {} {:+05}", 
-                sig.name, 
-                sig.email, 
-                sig.timestamp, 
-                sig.timezone_offset)
-    }
 }
 
 impl Default for CompatHashDeriver {
@@ -281,6 +465,41 @@ impl Default for CompatHashDeriver {
     }
 }
 
+/// Write extra headers, continuing multi-line values with a leading space
+fn write_extra_headers(content: &mut Vec<u8>, headers: &[ExtraHeader]) {
+    for header in headers {
+        content.extend_from_slice(header.name.as_bytes());
+        if !header.bare || (!header.value.is_empty() && !header.value.starts_with(b"\n")) {
+            content.push(b' ');
+        }
+        for (i, line) in header.value.split(|&b| b == b'\n').enumerate() {
+            if i > 0 {
+                content.extend_from_slice(b"\n ");
+            }
+            content.extend_from_slice(line);
+        }
+        content.push(b'\n');
+    }
+}
+
+/// Message as written in Git: the original bytes as long as they still decode to
+/// `message`, so editing the message takes effect
+fn message_bytes<'a>(message: &'a str, raw: &'a Option<bytes::Bytes>) -> &'a [u8] {
+    match raw {
+        Some(raw) if String::from_utf8_lossy(raw) == message => raw,
+        _ => message.as_bytes(),
+    }
+}
+
+/// Sort key for Git tree entries: directories compare as "name/"
+fn git_tree_order(entry: &TreeEntry) -> Vec<u8> {
+    let mut key = entry.name_bytes().to_vec();
+    if entry.mode == FileMode::Tree {
+        key.push(b'/');
+    }
+    key
+}
+
 /// Canonical Git object types (ADR-002)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum GitObject {
@@ -310,6 +529,25 @@ impl GitObject {
             GitObject::Tag(_) => ObjectType::Tag,
         }
     }
+    
+    /// Objects directly referenced by this one (tree entries, commit tree and
+    /// parents, tag target). Their Git hashes are needed to export this object.
+    /// Gitlinks are left out: their commits belong to another repository.
+    pub fn references(&self) -> Vec<ObjectId> {
+        match self {
+            GitObject::Blob(_) => Vec::new(),
+            GitObject::Tree(tree) => tree.entries.iter()
+                .filter(|e| e.mode != FileMode::Gitlink)
+                .map(|e| e.hash)
+                .collect(),
+            GitObject::Commit(commit) => {
+                let mut refs = vec![commit.tree];
+                refs.extend(commit.parents.iter().copied());
+                refs
+            }
+            GitObject::Tag(tag) => vec![tag.target],
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -360,6 +598,47 @@ pub struct TreeEntry {
     pub mode: FileMode,
     pub hash: ObjectId,
     pub entry_type: ObjectType,
+    /// Original name bytes of entries imported from Git whose names are not UTF-8;
+    /// `name` then holds their lossy decoding. Git serialization writes them as long
+    /// as they still decode to `name`, so renaming the entry takes effect.
+    pub raw_name: Option<bytes::Bytes>,
+}
+
+impl TreeEntry {
+    /// Entry for the submodule commit `commit` (Git mode 160000)
+    pub fn gitlink(name: impl Into<String>, commit: &GitHash) -> Self {
+        Self {
+            name: name.into(),
+            mode: FileMode::Gitlink,
+            hash: ObjectId::for_gitlink(commit),
+            entry_type: ObjectType::Commit,
+            raw_name: None,
+        }
+    }
+
+    /// Git hash of the submodule commit of a gitlink entry, `None` for other entries
+    pub fn gitlink_target(&self) -> Option<GitHash> {
+        (self.mode == FileMode::Gitlink).then(|| self.hash.gitlink_commit())
+    }
+
+    /// Name as written in Git trees
+    pub fn name_bytes(&self) -> &[u8] {
+        match &self.raw_name {
+            Some(raw) if String::from_utf8_lossy(raw) == self.name => raw,
+            _ => self.name.as_bytes(),
+        }
+    }
+
+    /// Whether `name` is safe as an entry name: a single path component that is
+    /// not `.`, `..` or `.git` in any case, as Git's fsck requires. Checking out
+    /// anything else could write outside the work tree or into the repository.
+    pub fn is_valid_name(name: &str) -> bool {
+        !name.is_empty()
+            && !name.contains(['/', '\0'])
+            && name != "."
+            && name != ".."
+            && !name.eq_ignore_ascii_case(".git")
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -368,6 +647,8 @@ pub enum FileMode {
     Executable = 0o100755,
     Symlink = 0o120000,
     Tree = 0o040000,
+    /// Submodule commit, see `TreeEntry::gitlink`
+    Gitlink = 0o160000,
 }
 
 /// Commit: Snapshot with history (ADR-002)
@@ -377,7 +658,46 @@ pub struct Commit {
     pub parents: Vec<ObjectId>,
     pub author: Signature,
     pub committer: Signature,
+    /// Headers following `committer` in Git (`encoding`, `mergetag`, `gpgsig`, ...)
+    pub extra_headers: Vec<ExtraHeader>,
     pub message: String,
+    /// Original bytes of a message that is not UTF-8, usually one in the legacy
+    /// encoding named by an `encoding` header; `message` then holds their lossy
+    /// decoding
+    pub raw_message: Option<bytes::Bytes>,
+}
+
+impl Commit {
+    /// Raw signature bytes from the `gpgsig` (or `gpgsig-sha256`) header, if signed
+    pub fn signature(&self) -> Option<&[u8]> {
+        self.extra_headers.iter()
+            .find(|h| h.name == "gpgsig" || h.name == "gpgsig-sha256")
+            .map(|h| h.value.as_ref())
+    }
+}
+
+/// A Git commit or tag header outside the canonical fields, kept verbatim and in
+/// order so the object re-serializes to identical Git bytes. Continuation lines of
+/// multi-line values are joined with '\n', without Git's leading space.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtraHeader {
+    pub name: String,
+    pub value: bytes::Bytes,
+    /// The header line is `name` alone, without the space that normally separates
+    /// it from the value. Only honoured while the first line of `value` is empty.
+    pub bare: bool,
+}
+
+impl ExtraHeader {
+    pub fn new(name: impl Into<String>, value: impl Into<bytes::Bytes>) -> Self {
+        Self { name: name.into(), value: value.into(), bare: false }
+    }
+
+    /// Header whose first line is its name alone; `value` holds any continuation
+    /// lines, each preceded by '\n'
+    pub fn bare(name: impl Into<String>, value: impl Into<bytes::Bytes>) -> Self {
+        Self { bare: true, ..Self::new(name, value) }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -385,7 +705,91 @@ pub struct Signature {
     pub name: String,
     pub email: String,
     pub timestamp: i64,
+    /// Offset from UTC in minutes
     pub timezone_offset: i16,
+    /// Original Git identity bytes ("Name <email> 1234567890 +0100"), kept only when
+    /// the fields above cannot reproduce them: non UTF-8 names, unusual spacing, a
+    /// "-0000" timezone and so on. Git serialization writes them verbatim as long
+    /// as they still parse to the fields, so editing a field takes effect.
+    pub raw: Option<bytes::Bytes>,
+}
+
+impl Signature {
+    /// Timezone in Git's "+HHMM" notation
+    pub fn git_timezone(&self) -> String {
+        let sign = if self.timezone_offset < 0 { '-' } else { '+' };
+        let minutes = self.timezone_offset.unsigned_abs();
+        format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+    }
+    
+    /// Parse an identity as written in Git commit and tag headers, keeping the
+    /// bytes in `raw` when the fields cannot reproduce them
+    pub fn from_git_bytes(value: &[u8]) -> Result<Self> {
+        let mut signature = Self::parse_fields(value)?;
+        if signature.to_git_bytes() != value {
+            signature.raw = Some(bytes::Bytes::copy_from_slice(value));
+        }
+        Ok(signature)
+    }
+
+    /// Identity as written in Git commit and tag headers
+    pub fn to_git_bytes(&self) -> Vec<u8> {
+        match &self.raw {
+            Some(raw) if Self::parse_fields(raw).is_ok_and(|parsed| parsed.same_fields(self)) => raw.to_vec(),
+            _ => format!("{} <{}> {} {}", self.name, self.email, self.timestamp, self.git_timezone()).into_bytes(),
+        }
+    }
+
+    fn same_fields(&self, other: &Signature) -> bool {
+        self.name == other.name
+            && self.email == other.email
+            && self.timestamp == other.timestamp
+            && self.timezone_offset == other.timezone_offset
+    }
+
+    /// The fields of a Git identity, without `raw`; a missing or malformed time
+    /// or timezone reads as zero
+    fn parse_fields(value: &[u8]) -> Result<Self> {
+        let invalid = |message: &str| GitNextError::InvalidFormat(message.to_string());
+        let lt = value.iter().position(|&b| b == b'<')
+            .ok_or_else(|| invalid("Signature is missing '<'"))?;
+        let gt = value.iter().rposition(|&b| b == b'>')
+            .filter(|&gt| gt > lt)
+            .ok_or_else(|| invalid("Signature is missing '>'"))?;
+
+        let name = value[..lt].trim_ascii_end();
+        let email = &value[lt + 1..gt];
+        let mut when = value[gt + 1..].split(|b| b.is_ascii_whitespace()).filter(|part| !part.is_empty());
+        let timestamp = when.next()
+            .and_then(|t| std::str::from_utf8(t).ok())
+            .and_then(|t| t.parse::<i64>().ok());
+        let timezone_offset = when.next().and_then(parse_timezone);
+
+        Ok(Signature {
+            name: String::from_utf8_lossy(name).into_owned(),
+            email: String::from_utf8_lossy(email).into_owned(),
+            timestamp: timestamp.unwrap_or(0),
+            timezone_offset: timezone_offset.unwrap_or(0),
+            raw: None,
+        })
+    }
+}
+
+/// Parse Git's "+HHMM" timezone into an offset in minutes
+fn parse_timezone(timezone: &[u8]) -> Option<i16> {
+    let (sign, digits) = match timezone.split_first()? {
+        (b'+', digits) => (1, digits),
+        (b'-', digits) => (-1, digits),
+        _ => return None,
+    };
+    if digits.len() != 4 || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+
+    let digit = |i: usize| (digits[i] - b'0') as i16;
+    let hours = digit(0) * 10 + digit(1);
+    let minutes = digit(2) * 10 + digit(3);
+    Some(sign * (hours * 60 + minutes))
 }
 
 /// Tag: Named reference to object (ADR-002)
@@ -394,8 +798,15 @@ pub struct Tag {
     pub target: ObjectId,
     pub target_type: ObjectType,
     pub name: String,
-    pub tagger: Signature,
+    /// Missing from tags made by very old versions of Git
+    pub tagger: Option<Signature>,
+    /// Headers following `tagger` in Git
+    pub extra_headers: Vec<ExtraHeader>,
     pub message: String,
+    /// Raw signature block Git appends after the message of signed tags
+    pub signature: Option<bytes::Bytes>,
+    /// Original bytes of a message that is not UTF-8, as for commits
+    pub raw_message: Option<bytes::Bytes>,
 }
 
 /// Reference types
@@ -464,6 +875,9 @@ impl fmt::Display for Commit {
         }
         writeln!(f, "author {}", self.author)?;
         writeln!(f, "committer {}", self.committer)?;
+        for header in &self.extra_headers {
+            writeln!(f, "{} {}", header.name, String::from_utf8_lossy(&header.value))?;
+        }
         writeln!(f)?;
         write!(f, "{}", self.message)
     }
@@ -471,11 +885,11 @@ impl fmt::Display for Commit {
 
 impl fmt::Display for Signature {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{} <{}> {} {:+05}", 
+        write!(f, "{} <{}> {} {}", 
                self.name, 
                self.email, 
                self.timestamp, 
-               self.timezone_offset)
+               self.git_timezone())
     }
 }
 
@@ -484,9 +898,18 @@ impl fmt::Display for Tag {
         writeln!(f, "object {}", self.target)?;
         writeln!(f, "type {:?}", self.target_type)?;
         writeln!(f, "tag {}", self.name)?;
-        writeln!(f, "tagger {}", self.tagger)?;
+        if let Some(tagger) = &self.tagger {
+            writeln!(f, "tagger {}", tagger)?;
+        }
+        for header in &self.extra_headers {
+            writeln!(f, "{} {}", header.name, String::from_utf8_lossy(&header.value))?;
+        }
         writeln!(f)?;
-        write!(f, "{}", self.message)
+        write!(f, "{}", self.message)?;
+        if let Some(signature) = &self.signature {
+            write!(f, "{}", String::from_utf8_lossy(signature))?;
+        }
+        Ok(())
     }
 }
 
@@ -522,6 +945,207 @@ pub mod tests {
         assert_eq!(git_hash, git_hash2);
     }
     
+    #[cfg(test)]
+    fn test_signature() -> Signature {
+        Signature {
+            name: "Test Author".to_string(),
+            email: "test@example.com".to_string(),
+            timestamp: 1234567890,
+            timezone_offset: 0,
+            raw: None,
+        }
+    }
+    
+    #[test]
+    fn test_git_hash_derivation_matches_git() {
+        let mut deriver = CompatHashDeriver::new();
+        
+        let blob = GitObject::Blob(Blob::new(bytes::Bytes::from("hello world\n")));
+        let blob_hash = deriver.derive_git_hash(&blob, GitHashType::Sha1).unwrap();
+        assert_eq!(blob_hash.to_string(), "3b18e512dba79e4c8300dd08aeb37f8e728b8dad");
+        
+        let tree = GitObject::Tree(Tree::new(vec![TreeEntry {
+            name: "hello.txt".to_string(),
+            mode: FileMode::Normal,
+            hash: blob.canonical_hash(),
+            entry_type: ObjectType::Blob,
+            raw_name: None,
+        }]));
+        let tree_hash = deriver.derive_git_hash(&tree, GitHashType::Sha1).unwrap();
+        assert_eq!(tree_hash.to_string(), "68aba62e560c0ebc3396e8ae9335232cd93a3f60");
+        
+        // "a.txt" sorts before the directory "a" in Git order ("a/")
+        let nested = GitObject::Tree(Tree::new(vec![
+            TreeEntry {
+                name: "a".to_string(),
+                mode: FileMode::Tree,
+                hash: tree.canonical_hash(),
+                entry_type: ObjectType::Tree,
+                raw_name: None,
+            },
+            TreeEntry {
+                name: "a.txt".to_string(),
+                mode: FileMode::Normal,
+                hash: blob.canonical_hash(),
+                entry_type: ObjectType::Blob,
+                raw_name: None,
+            },
+        ]));
+        let nested_hash = deriver.derive_git_hash(&nested, GitHashType::Sha1).unwrap();
+        assert_eq!(nested_hash.to_string(), "1a9251426ec61b02ac9f03a38eab413aa8ae27c8");
+        
+        let commit = GitObject::Commit(Commit {
+            tree: tree.canonical_hash(),
+            parents: vec![],
+            author: test_signature(),
+            committer: test_signature(),
+            extra_headers: Vec::new(),
+            message: "Test commit\n".to_string(),
+            raw_message: None,
+        });
+        let commit_hash = deriver.derive_git_hash(&commit, GitHashType::Sha1).unwrap();
+        assert_eq!(commit_hash.to_string(), "c8fa21d60f84d8b6809b13dcaf04bb22d9ec3777");
+        
+        // Reverse lookups resolve back to the canonical objects
+        assert_eq!(deriver.lookup_by_git_hash(&tree_hash), Some(tree.canonical_hash()));
+        assert_eq!(deriver.lookup_by_git_hash(&commit_hash), Some(commit.canonical_hash()));
+    }
+    
+    #[test]
+    fn test_git_hash_derivation_sha256() {
+        let mut deriver = CompatHashDeriver::new();
+        
+        let blob = GitObject::Blob(Blob::new(bytes::Bytes::from("hello world\n")));
+        let blob_hash = deriver.derive_git_hash(&blob, GitHashType::Sha256).unwrap();
+        assert_eq!(blob_hash.to_string(), "0bd69098bd9b9cc5934a610ab65da429b525361147faa7b5b922919e9a23143d");
+        
+        let tree = GitObject::Tree(Tree::new(vec![TreeEntry {
+            name: "hello.txt".to_string(),
+            mode: FileMode::Normal,
+            hash: blob.canonical_hash(),
+            entry_type: ObjectType::Blob,
+            raw_name: None,
+        }]));
+        let tree_hash = deriver.derive_git_hash(&tree, GitHashType::Sha256).unwrap();
+        assert_eq!(tree_hash.to_string(), "d699939c201274b35a5523e333ca3b0934339368fc805978f6dfd5e50ed3497a");
+        
+        let commit = GitObject::Commit(Commit {
+            tree: tree.canonical_hash(),
+            parents: vec![],
+            author: test_signature(),
+            committer: test_signature(),
+            extra_headers: Vec::new(),
+            message: "Test commit\n".to_string(),
+            raw_message: None,
+        });
+        let commit_hash = deriver.derive_git_hash(&commit, GitHashType::Sha256).unwrap();
+        assert_eq!(commit_hash.to_string(), "7ab754e6a1ace7277d15caff2229119e3a3647b948c1d7088f71b0c621eab8cc");
+        
+        // SHA-1 mappings are tracked separately from SHA-256 ones
+        assert!(deriver.map().git_hash(&tree.canonical_hash(), GitHashType::Sha1).is_none());
+    }
+    
+    #[test]
+    fn test_git_hash_derivation_extra_headers() {
+        let mut deriver = CompatHashDeriver::new();
+        
+        let blob = GitObject::Blob(Blob::new(bytes::Bytes::from("hello world\n")));
+        deriver.derive_git_hash(&blob, GitHashType::Sha1).unwrap();
+        let tree = GitObject::Tree(Tree::new(vec![TreeEntry {
+            name: "hello.txt".to_string(),
+            mode: FileMode::Normal,
+            hash: blob.canonical_hash(),
+            entry_type: ObjectType::Blob,
+            raw_name: None,
+        }]));
+        deriver.derive_git_hash(&tree, GitHashType::Sha1).unwrap();
+        
+        // Multi-line gpgsig value, including an empty continuation line
+        let signature = "-----BEGIN PGP SIGNATURE-----\n\niQEzBAABCAAdFiEE\n-----END PGP SIGNATURE-----";
+        let signed = Commit {
+            tree: tree.canonical_hash(),
+            parents: vec![],
+            author: test_signature(),
+            committer: test_signature(),
+            extra_headers: vec![
+                ExtraHeader::new("encoding", "ISO-8859-1"),
+                ExtraHeader::new("gpgsig", signature),
+            ],
+            message: "Signed commit\n".to_string(),
+            raw_message: None,
+        };
+        assert_eq!(signed.signature(), Some(signature.as_bytes()));
+        let signed = GitObject::Commit(signed);
+        let signed_hash = deriver.derive_git_hash(&signed, GitHashType::Sha1).unwrap();
+        assert_eq!(signed_hash.to_string(), "ec9c93d3f40626d58828b4c6cbff3508854befc7");
+        
+        // Extra headers are part of the canonical identity
+        let GitObject::Commit(commit) = &signed else { unreachable!() };
+        let unsigned = GitObject::Commit(Commit { extra_headers: Vec::new(), ..commit.clone() });
+        assert_ne!(unsigned.canonical_hash(), signed.canonical_hash());
+        
+        let plain = GitObject::Commit(Commit {
+            tree: tree.canonical_hash(),
+            parents: vec![],
+            author: test_signature(),
+            committer: test_signature(),
+            extra_headers: Vec::new(),
+            message: "Test commit\n".to_string(),
+            raw_message: None,
+        });
+        deriver.derive_git_hash(&plain, GitHashType::Sha1).unwrap();
+        let tag = GitObject::Tag(Tag {
+            target: plain.canonical_hash(),
+            target_type: ObjectType::Commit,
+            name: "v1.0".to_string(),
+            tagger: Some(test_signature()),
+            extra_headers: Vec::new(),
+            message: "Release\n".to_string(),
+            signature: Some(bytes::Bytes::from("-----BEGIN PGP SIGNATURE-----\n\niQEzBAABCAAdFiEE\n-----END PGP SIGNATURE-----\n")),
+            raw_message: None,
+        });
+        let tag_hash = deriver.derive_git_hash(&tag, GitHashType::Sha1).unwrap();
+        assert_eq!(tag_hash.to_string(), "44c9fbbd54fbd4e25354d1e41e7ae03729db22a0");
+    }
+    
+    #[test]
+    fn test_signature_git_format() {
+        let mut signature = test_signature();
+        for (offset, expected) in [(0, "+0000"), (60, "+0100"), (330, "+0530"), (-570, "-0930"), (765, "+1245")] {
+            signature.timezone_offset = offset;
+            assert_eq!(signature.git_timezone(), expected);
+        }
+        assert_eq!(signature.to_git_bytes(), b"Test Author <test@example.com> 1234567890 +1245");
+        
+        // Raw identity bytes take precedence over the fields while they agree
+        signature.timezone_offset = 0;
+        signature.raw = Some(bytes::Bytes::from_static(b"Test Author <test@example.com> 1234567890 -0000"));
+        assert_eq!(signature.to_git_bytes(), b"Test Author <test@example.com> 1234567890 -0000");
+
+        // and are ignored once a field was changed
+        signature.timestamp += 1;
+        assert_eq!(signature.to_git_bytes(), b"Test Author <test@example.com> 1234567891 +0000");
+        signature.timestamp -= 1;
+        signature.name = "Other Author".to_string();
+        assert_eq!(signature.to_git_bytes(), b"Other Author <test@example.com> 1234567890 +0000");
+    }
+    
+    #[test]
+    fn test_git_hash_derivation_requires_children() {
+        let blob = GitObject::Blob(Blob::new(bytes::Bytes::from("hello world\n")));
+        let tree = GitObject::Tree(Tree::new(vec![TreeEntry {
+            name: "hello.txt".to_string(),
+            mode: FileMode::Normal,
+            hash: blob.canonical_hash(),
+            entry_type: ObjectType::Blob,
+            raw_name: None,
+        }]));
+        
+        let mut deriver = CompatHashDeriver::new();
+        let result = deriver.derive_git_hash(&tree, GitHashType::Sha1);
+        assert!(matches!(result, Err(GitNextError::HashDerivation(_))));
+    }
+    
     #[test]
     fn test_canonical_serialization() {
         let blob = Blob::new(bytes::Bytes::from("test content"));
@@ -539,6 +1163,17 @@ pub mod tests {
         assert_eq!(hash1, hash2);
     }
 
+    /// Record stand-in Git hashes for every object `obj` references, so that
+    /// arbitrary objects can be exported without their children being present
+    pub fn seed_reference_hashes(deriver: &mut CompatHashDeriver, obj: &GitObject) {
+        for id in obj.references() {
+            for hash_type in [GitHashType::Sha1, GitHashType::Sha256] {
+                let stand_in = GitHash::from_git_bytes(id.as_bytes(), hash_type);
+                deriver.map_mut().insert(id, stand_in);
+            }
+        }
+    }
+
     // Property test generators
     prop_compose! {
         pub fn arb_signature()(
@@ -547,7 +1182,7 @@ pub mod tests {
             timestamp in any::<i64>(),
             timezone_offset in -1440i16..1440i16
         ) -> Signature {
-            Signature { name, email, timestamp, timezone_offset }
+            Signature { name, email, timestamp, timezone_offset, raw: None }
         }
     }
 
@@ -569,6 +1204,7 @@ pub mod tests {
                 mode,
                 hash: ObjectId::from_blake3_bytes(hash_bytes),
                 entry_type,
+                raw_name: None,
             }
         }
     }
@@ -579,12 +1215,22 @@ pub mod tests {
         }
     }
 
+    prop_compose! {
+        pub fn arb_extra_header()(
+            name in prop::sample::select(&["encoding", "mergetag", "gpgsig", "x-custom"]),
+            lines in prop::collection::vec("[\\x20-\\x7E]{0,40}", 1..4)
+        ) -> ExtraHeader {
+            ExtraHeader::new(name, lines.join("\n"))
+        }
+    }
+
     prop_compose! {
         pub fn arb_commit()(
             tree_hash in prop::array::uniform32(any::<u8>()),
             parents in prop::collection::vec(prop::array::uniform32(any::<u8>()), 0..5),
             author in arb_signature(),
             committer in arb_signature(),
+            extra_headers in prop::collection::vec(arb_extra_header(), 0..3),
             message in "[\\x20-\\x7E\\n]{1,500}"
         ) -> Commit {
             Commit {
@@ -592,7 +1238,9 @@ pub mod tests {
                 parents: parents.into_iter().map(ObjectId::from_blake3_bytes).collect(),
                 author,
                 committer,
+                extra_headers,
                 message,
+                raw_message: None,
             }
         }
     }
@@ -602,15 +1250,20 @@ pub mod tests {
             target_hash in prop::array::uniform32(any::<u8>()),
             target_type in prop::sample::select(&[ObjectType::Blob, ObjectType::Tree, ObjectType::Commit, ObjectType::Tag]),
             name in "[a-zA-Z0-9._-]{1,50}",
-            tagger in arb_signature(),
-            message in "[\\x20-\\x7E\\n]{1,500}"
+            tagger in prop::option::of(arb_signature()),
+            extra_headers in prop::collection::vec(arb_extra_header(), 0..2),
+            message in "[\\x20-\\x7E\\n]{1,500}",
+            signature in prop::option::of("-----BEGIN PGP SIGNATURE-----\n[a-zA-Z0-9+/=\n]{1,100}-----END PGP SIGNATURE-----\n")
         ) -> Tag {
             Tag {
                 target: ObjectId::from_blake3_bytes(target_hash),
                 target_type,
                 name,
                 tagger,
+                extra_headers,
                 message,
+                signature: signature.map(bytes::Bytes::from),
+                raw_message: None,
             }
         }
     }
@@ -657,6 +1310,7 @@ pub mod tests {
 
             // Git hash derivation should be deterministic
             let mut deriver = CompatHashDeriver::new();
+            seed_reference_hashes(&mut deriver, &obj);
             let git_hash1 = deriver.derive_git_hash(&obj, GitHashType::Sha1).unwrap();
             let git_hash2 = deriver.derive_git_hash(&obj, GitHashType::Sha1).unwrap();
             prop_assert_eq!(git_hash1, git_hash2);
@@ -677,26 +1331,27 @@ pub mod tests {
         #[test]
         fn prop_git_export_fidelity(obj in arb_git_object()) {
             let mut deriver = CompatHashDeriver::new();
+            seed_reference_hashes(&mut deriver, &obj);
             
             // Export to Git format should be deterministic
-            let git_bytes1 = deriver.serialize_to_git_format(&obj).unwrap();
-            let git_bytes2 = deriver.serialize_to_git_format(&obj).unwrap();
+            let git_bytes1 = deriver.serialize_to_git_format(&obj, GitHashType::Sha1).unwrap();
+            let git_bytes2 = deriver.serialize_to_git_format(&obj, GitHashType::Sha1).unwrap();
             prop_assert_eq!(git_bytes1, git_bytes2);
             
             // Git hash should be consistent with Git format bytes for SHA-1
-            let git_bytes_for_sha1 = deriver.serialize_to_git_format(&obj).unwrap();
+            let git_bytes_for_sha1 = deriver.serialize_to_git_format(&obj, GitHashType::Sha1).unwrap();
             let expected_sha1 = GitHash::from_git_bytes(&git_bytes_for_sha1, GitHashType::Sha1);
             let derived_sha1 = deriver.derive_git_hash(&obj, GitHashType::Sha1).unwrap();
             prop_assert_eq!(expected_sha1, derived_sha1);
             
             // Git hash should be consistent with Git format bytes for SHA-256
-            let git_bytes_for_sha256 = deriver.serialize_to_git_format(&obj).unwrap();
+            let git_bytes_for_sha256 = deriver.serialize_to_git_format(&obj, GitHashType::Sha256).unwrap();
             let expected_sha256 = GitHash::from_git_bytes(&git_bytes_for_sha256, GitHashType::Sha256);
             let derived_sha256 = deriver.derive_git_hash(&obj, GitHashType::Sha256).unwrap();
             prop_assert_eq!(expected_sha256, derived_sha256);
             
             // Git format should be valid (basic structure check)
-            let git_bytes_for_validation = deriver.serialize_to_git_format(&obj).unwrap();
+            let git_bytes_for_validation = deriver.serialize_to_git_format(&obj, GitHashType::Sha1).unwrap();
             match &obj {
                 GitObject::Blob(_) => {
                     prop_assert!(git_bytes_for_validation.starts_with(b"blob "));
@@ -715,7 +1370,7 @@ pub mod tests {
                     prop_assert!(content.contains("author "));
                     prop_assert!(content.contains("committer "));
                 }
-                GitObject::Tag(_) => {
+                GitObject::Tag(tag) => {
                     prop_assert!(git_bytes_for_validation.starts_with(b"tag "));
                     prop_assert!(git_bytes_for_validation.contains(&0u8)); // null separator
                     // Should contain required tag fields
@@ -723,7 +1378,7 @@ pub mod tests {
                     prop_assert!(content.contains("object "));
                     prop_assert!(content.contains("type "));
                     prop_assert!(content.contains("tag "));
-                    prop_assert!(content.contains("tagger "));
+                    prop_assert_eq!(content.contains("tagger "), tag.tagger.is_some());
                 }
             }
         }
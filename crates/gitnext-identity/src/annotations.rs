@@ -0,0 +1,271 @@
+//! Annotations: what people say about an artifact
+//!
+//! Annotations are JSON values under keys such as `owner`, `review` or
+//! `coverage`, set and removed through `ArtifactTracker`. A key may have a JSON
+//! Schema registered for it, and then every value stored under that key must be
+//! valid against it. Each change is kept with who made it and when, so past
+//! values can be read back. Like the rest of the tracker, annotations live in
+//! the artifacts blob, never in trees or commits.
+
+use crate::{IdentityError, Result};
+use chrono::{DateTime, Utc};
+use jsonschema::Validator;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+/// One change to an annotation of an artifact
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnnotationChange {
+    pub key: String,
+    /// Changes to one key of one artifact count from 1
+    pub version: u32,
+    /// The value set, or `None` when the annotation was removed
+    pub value: Option<Value>,
+    /// Who made the change; `None` for annotations that came with a tracked
+    /// artifact
+    pub author: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Compiled schemas of annotation keys
+#[derive(Default)]
+pub(crate) struct Schemas {
+    validators: HashMap<String, Validator>,
+}
+
+impl Schemas {
+    /// Compile the stored schemas
+    pub fn compile(schemas: &BTreeMap<String, Value>) -> Result<Self> {
+        let mut validators = HashMap::new();
+        for (key, schema) in schemas {
+            validators.insert(key.clone(), validator(key, schema)?);
+        }
+        Ok(Self { validators })
+    }
+
+    pub fn insert(&mut self, key: &str, validator: Validator) {
+        self.validators.insert(key.to_string(), validator);
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.validators.remove(key);
+    }
+
+    /// Check `value` against the schema of `key`, if it has one
+    pub fn check(&self, key: &str, value: &Value) -> Result<()> {
+        match self.validators.get(key) {
+            Some(validator) => check(validator, key, value),
+            None => Ok(()),
+        }
+    }
+}
+
+/// `schema` compiled for annotations under `key`
+pub(crate) fn validator(key: &str, schema: &Value) -> Result<Validator> {
+    jsonschema::validator_for(schema)
+        .map_err(|e| IdentityError::InvalidSchema { key: key.to_string(), reason: e.to_string() })
+}
+
+/// Check `value`, stored under `key`, with `validator`
+pub(crate) fn check(validator: &Validator, key: &str, value: &Value) -> Result<()> {
+    let errors: Vec<String> = validator.iter_errors(value).map(|e| e.to_string()).collect();
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(IdentityError::InvalidAnnotation { key: key.to_string(), reason: errors.join("; ") })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::tracker::tests::store_commit;
+    use crate::{
+        Artifact, ArtifactId, ArtifactLocation, ArtifactQuery, ArtifactTracker, ArtifactType, ExtractorRegistry, IdentityError,
+        ARTIFACTS_REF,
+    };
+    use gitnext_core::ObjectId;
+    use gitnext_storage::{MemoryStorage, Storage};
+    use proptest::prelude::*;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn function(signature: &str) -> Artifact {
+        Artifact::new(
+            ArtifactType::Function { signature: signature.to_string(), language: "rust".to_string() },
+            ArtifactLocation::new(ObjectId::from_blake3_bytes([1; 32]), "src/lib.rs"),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_annotations() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let mut tracker = ArtifactTracker::open(storage.clone()).await.unwrap();
+        let parse = function("fn parse()");
+        let id = parse.stable_id;
+        tracker.track_artifact(parse).await.unwrap();
+
+        tracker.set_annotation(&id, "owner", json!("parsing"), "alice").await.unwrap();
+        tracker.set_annotation(&id, "owner", json!("core"), "bob").await.unwrap();
+        // Setting the same value again is not a change
+        tracker.set_annotation(&id, "owner", json!("core"), "carol").await.unwrap();
+        tracker.set_annotation(&id, "coverage", json!(0.8), "ci").await.unwrap();
+        assert_eq!(tracker.remove_annotation(&id, "owner", "alice").await.unwrap(), Some(json!("core")));
+        assert_eq!(tracker.remove_annotation(&id, "owner", "alice").await.unwrap(), None);
+
+        let annotations = &tracker.get_artifact(&id).unwrap().metadata.annotations;
+        assert_eq!(annotations, &HashMap::from([("coverage".to_string(), json!(0.8))]));
+        let history = tracker.get_annotation_history(&id, Some("owner")).await.unwrap();
+        let changes: Vec<_> = history.iter().map(|c| (c.version, c.value.clone(), c.author.as_deref())).collect();
+        assert_eq!(changes, vec![
+            (1, Some(json!("parsing")), Some("alice")),
+            (2, Some(json!("core")), Some("bob")),
+            (3, None, Some("alice")),
+        ]);
+        assert!(history.windows(2).all(|w| w[0].timestamp <= w[1].timestamp));
+        assert_eq!(tracker.get_annotation_history(&id, None).await.unwrap().len(), 4);
+
+        // Values must follow the schema of their key, once there is one
+        let review = json!({"type": "object", "properties": {"status": {"enum": ["pending", "approved"]}}, "required": ["status"]});
+        tracker.register_annotation_schema("review", review.clone()).await.unwrap();
+        tracker.set_annotation(&id, "review", json!({"status": "approved"}), "bob").await.unwrap();
+        let invalid = tracker.set_annotation(&id, "review", json!({"status": "maybe"}), "bob").await;
+        assert!(matches!(invalid, Err(IdentityError::InvalidAnnotation { key, .. }) if key == "review"));
+        let mut tracked = tracker.get_artifact(&id).unwrap().clone();
+        tracked.metadata.annotations.insert("review".to_string(), json!({}));
+        assert!(tracker.track_artifact(tracked).await.is_err());
+        assert_eq!(tracker.get_artifact(&id).unwrap().metadata.annotations["review"], json!({"status": "approved"}));
+        let bad_schema = tracker.register_annotation_schema("owner", json!({"type": 12})).await;
+        assert!(matches!(bad_schema, Err(IdentityError::InvalidSchema { .. })));
+        // A schema the stored values break is refused
+        let number = tracker.register_annotation_schema("coverage", json!({"type": "string"})).await;
+        assert!(matches!(number, Err(IdentityError::InvalidAnnotation { key, .. }) if key == "coverage"));
+        assert!(tracker.annotation_schema("coverage").is_none());
+
+        let unknown = ArtifactId::new();
+        assert!(matches!(
+            tracker.set_annotation(&unknown, "owner", json!("x"), "alice").await,
+            Err(IdentityError::UnknownArtifact(_))
+        ));
+
+        // Schemas and history survive reopening; only the artifacts ref was written
+        let mut reopened = ArtifactTracker::open(storage.clone()).await.unwrap();
+        assert_eq!(reopened.annotation_schema("review"), Some(&review));
+        assert_eq!(reopened.get_annotation_history(&id, None).await.unwrap(), tracker.get_annotation_history(&id, None).await.unwrap());
+        assert!(reopened.set_annotation(&id, "review", json!("approved"), "bob").await.is_err());
+        reopened.remove_annotation_schema("review").await.unwrap();
+        reopened.set_annotation(&id, "review", json!("approved"), "bob").await.unwrap();
+        let refs: Vec<String> = storage.list_refs().await.unwrap().into_iter().map(|r| r.name).collect();
+        assert_eq!(refs, vec![ARTIFACTS_REF]);
+    }
+
+    #[tokio::test]
+    async fn test_annotations_leave_commits_alone() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let s = storage.as_ref();
+        let c1 = store_commit(s, &[], &[("src/lib.rs", "fn parse() -> u32 {\n    1\n}\n")], 1).await;
+        let mut tracker = ArtifactTracker::open(storage.clone()).await.unwrap();
+        let extractors = ExtractorRegistry::new();
+        tracker.index_commits(&extractors).await.unwrap();
+        let query = ArtifactQuery { name: Some("fn parse".to_string()), ..Default::default() };
+        let id = tracker.query_artifacts(query).await.unwrap()[0].stable_id;
+
+        tracker.set_annotation(&id, "owner", json!("alice"), "alice").await.unwrap();
+        assert_eq!(storage.load_object(&c1).await.unwrap().unwrap().canonical_hash(), c1);
+
+        // Indexing later commits keeps what was said about the artifact
+        let c2 = store_commit(s, &[c1], &[("src/parse.rs", "fn parse() -> u32 {\n    2\n}\n")], 2).await;
+        tracker.index_commits(&extractors).await.unwrap();
+        let artifact = tracker.get_artifact(&id).unwrap();
+        assert_eq!((artifact.location.commit, artifact.location.file_path.as_str()), (c2, "src/parse.rs"));
+        assert_eq!(artifact.metadata.annotations["owner"], "alice");
+    }
+
+    /// An annotation operation: set a key to a value, or remove it
+    fn operation() -> impl Strategy<Value = (usize, usize, Option<i64>)> {
+        (0usize..2, 0usize..3, prop::option::of(0i64..4))
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(30))]
+
+        /// Property 34: Artifact Metadata Preservation
+        /// For any artifacts with annotations, the annotations stay as they
+        /// were last set across indexing new commits, re-tracking the
+        /// artifacts elsewhere, refused changes and reopening the tracker, and
+        /// the history of each key replays to its current value.
+        /// **Validates: Requirements 11.6**
+        #[test]
+        fn prop_artifact_metadata_preservation(
+            steps in prop::collection::vec((operation(), 0u8..3), 1..16),
+        ) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+                let s = storage.as_ref();
+                let mut tracker = ArtifactTracker::open(storage.clone()).await.unwrap();
+                tracker.register_annotation_schema("coverage", json!({"type": "integer", "maximum": 2})).await.unwrap();
+                let artifacts = [function("fn f()"), function("fn g()")];
+                tracker.track_artifacts(artifacts.to_vec()).await.unwrap();
+                let keys = ["owner", "coverage", "review"];
+                let extractors = ExtractorRegistry::new();
+                let mut expected = vec![HashMap::<String, Value>::new(); artifacts.len()];
+                let mut parent = Vec::new();
+
+                for (time, ((artifact, key, value), other)) in steps.into_iter().enumerate() {
+                    let id = artifacts[artifact].stable_id;
+                    match value {
+                        Some(value) => {
+                            let result = tracker.set_annotation(&id, keys[key], json!(value), "alice").await;
+                            // Coverage above 2 is refused and changes nothing
+                            prop_assert_eq!(result.is_ok(), keys[key] != "coverage" || value <= 2);
+                            if result.is_ok() {
+                                expected[artifact].insert(keys[key].to_string(), json!(value));
+                            }
+                        }
+                        None => {
+                            let removed = tracker.remove_annotation(&id, keys[key], "bob").await.unwrap();
+                            prop_assert_eq!(removed, expected[artifact].remove(keys[key]));
+                        }
+                    }
+                    match other {
+                        0 => {
+                            let source = format!("fn h() -> i64 {{\n    {}\n}}\n", time);
+                            let commit = store_commit(s, &parent, &[("src/lib.rs", &source)], time as i64).await;
+                            parent = vec![commit];
+                            tracker.index_commits(&extractors).await.unwrap();
+                        }
+                        1 => {
+                            let mut moved = artifacts[artifact].clone();
+                            moved.location.file_path = format!("src/moved_{}.rs", time);
+                            tracker.track_artifact(moved).await.unwrap();
+                        }
+                        _ => tracker = ArtifactTracker::open(storage.clone()).await.unwrap(),
+                    }
+                    for (artifact, expected) in artifacts.iter().zip(&expected) {
+                        prop_assert_eq!(&tracker.get_artifact(&artifact.stable_id).unwrap().metadata.annotations, expected);
+                    }
+                }
+
+                let reopened = ArtifactTracker::open(storage.clone()).await.unwrap();
+                for (artifact, expected) in artifacts.iter().zip(&expected) {
+                    let history = reopened.get_annotation_history(&artifact.stable_id, None).await.unwrap();
+                    let mut replayed = HashMap::new();
+                    for change in &history {
+                        match &change.value {
+                            Some(value) => replayed.insert(change.key.clone(), value.clone()),
+                            None => replayed.remove(&change.key),
+                        };
+                    }
+                    prop_assert_eq!(&replayed, expected);
+                    for key in keys {
+                        let versions: Vec<u32> = history.iter().filter(|c| c.key == key).map(|c| c.version).collect();
+                        prop_assert!(versions.iter().enumerate().all(|(i, &v)| v as usize == i + 1));
+                    }
+                }
+                Ok(())
+            })?;
+        }
+    }
+}
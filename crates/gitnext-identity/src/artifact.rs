@@ -0,0 +1,198 @@
+//! Artifacts and where they are
+//!
+//! An `Artifact` is the current state of a tracked piece of code: what it is,
+//! where it is and what has been said about it. Each place it has been is kept
+//! as an `ArtifactVersion`, and `ArtifactReference`s record where it is used.
+
+use chrono::{DateTime, Utc};
+use gitnext_core::ObjectId;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use uuid::Uuid;
+
+/// Stable artifact identifier, kept across moves and renames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ArtifactId(pub Uuid);
+
+impl ArtifactId {
+    /// A new random identifier
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+impl Default for ArtifactId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for ArtifactId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// What an artifact is
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ArtifactType {
+    Function { signature: String, language: String },
+    Type { name: String, language: String },
+    Module { path: String, language: String },
+    Class { name: String, language: String },
+    Custom { type_name: String, data: serde_json::Value },
+}
+
+/// `ArtifactType` without its details, for queries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ArtifactKind {
+    Function,
+    Type,
+    Module,
+    Class,
+    Custom,
+}
+
+impl ArtifactType {
+    pub fn kind(&self) -> ArtifactKind {
+        match self {
+            ArtifactType::Function { .. } => ArtifactKind::Function,
+            ArtifactType::Type { .. } => ArtifactKind::Type,
+            ArtifactType::Module { .. } => ArtifactKind::Module,
+            ArtifactType::Class { .. } => ArtifactKind::Class,
+            ArtifactType::Custom { .. } => ArtifactKind::Custom,
+        }
+    }
+
+    /// The signature, name, path or custom type name
+    pub fn name(&self) -> &str {
+        match self {
+            ArtifactType::Function { signature, .. } => signature,
+            ArtifactType::Type { name, .. } | ArtifactType::Class { name, .. } => name,
+            ArtifactType::Module { path, .. } => path,
+            ArtifactType::Custom { type_name, .. } => type_name,
+        }
+    }
+
+    /// Source language, for all but custom artifacts
+    pub fn language(&self) -> Option<&str> {
+        match self {
+            ArtifactType::Function { language, .. }
+            | ArtifactType::Type { language, .. }
+            | ArtifactType::Module { language, .. }
+            | ArtifactType::Class { language, .. } => Some(language),
+            ArtifactType::Custom { .. } => None,
+        }
+    }
+}
+
+/// Where an artifact is as of a commit; line ranges count from 1 and include
+/// both ends, byte ranges exclude the end
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactLocation {
+    pub commit: ObjectId,
+    pub file_path: String,
+    pub line_range: Option<(u32, u32)>,
+    pub byte_range: Option<(u64, u64)>,
+}
+
+impl ArtifactLocation {
+    /// The whole of `file_path` in `commit`
+    pub fn new(commit: ObjectId, file_path: impl Into<String>) -> Self {
+        Self { commit, file_path: file_path.into(), line_range: None, byte_range: None }
+    }
+}
+
+/// What has been said about an artifact; never part of a Git object (ADR-004)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArtifactMetadata {
+    pub annotations: HashMap<String, serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+    pub last_modified: DateTime<Utc>,
+    pub tags: Vec<String>,
+}
+
+impl ArtifactMetadata {
+    /// Empty metadata created now
+    pub fn new() -> Self {
+        let now = Utc::now();
+        Self { annotations: HashMap::new(), created_at: now, last_modified: now, tags: Vec::new() }
+    }
+}
+
+impl Default for ArtifactMetadata {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A tracked artifact as it is now
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Artifact {
+    pub stable_id: ArtifactId,
+    pub artifact_type: ArtifactType,
+    pub location: ArtifactLocation,
+    pub metadata: ArtifactMetadata,
+}
+
+impl Artifact {
+    /// A new artifact with a fresh stable id
+    pub fn new(artifact_type: ArtifactType, location: ArtifactLocation) -> Self {
+        Self { stable_id: ArtifactId::new(), artifact_type, location, metadata: ArtifactMetadata::new() }
+    }
+}
+
+/// One state of an artifact; versions count from 1, each following the one
+/// before it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ArtifactVersion {
+    pub version: u32,
+    pub artifact_type: ArtifactType,
+    pub location: ArtifactLocation,
+    pub recorded_at: DateTime<Utc>,
+}
+
+/// A place that uses an artifact: a call site, an import, or another artifact
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ArtifactReference {
+    /// The artifact used
+    pub target: ArtifactId,
+    /// The artifact using it, if the reference is inside one
+    pub source: Option<ArtifactId>,
+    pub location: ArtifactLocation,
+}
+
+/// Which artifacts `ArtifactTracker::query_artifacts` returns; unset fields
+/// match everything
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArtifactQuery {
+    pub kind: Option<ArtifactKind>,
+    /// Text the name or signature contains
+    pub name: Option<String>,
+    pub language: Option<String>,
+    /// File or directory the artifact is in
+    pub path: Option<String>,
+    /// Commit the artifact was last seen at
+    pub commit: Option<ObjectId>,
+    pub tag: Option<String>,
+}
+
+impl ArtifactQuery {
+    pub fn matches(&self, artifact: &Artifact) -> bool {
+        let artifact_type = &artifact.artifact_type;
+        self.kind.is_none_or(|kind| artifact_type.kind() == kind)
+            && self.name.as_ref().is_none_or(|name| artifact_type.name().contains(name.as_str()))
+            && self.language.as_ref().is_none_or(|language| artifact_type.language() == Some(language.as_str()))
+            && self.path.as_ref().is_none_or(|path| within(&artifact.location.file_path, path))
+            && self.commit.is_none_or(|commit| artifact.location.commit == commit)
+            && self.tag.as_ref().is_none_or(|tag| artifact.metadata.tags.contains(tag))
+    }
+}
+
+/// Whether `file` is `path` or below it
+pub(crate) fn within(file: &str, path: &str) -> bool {
+    let path = path.trim_matches('/');
+    path.is_empty() || file == path || file.strip_prefix(path).is_some_and(|rest| rest.starts_with('/'))
+}
@@ -0,0 +1,286 @@
+//! Finding artifacts in source files
+//!
+//! An `ArtifactExtractor` turns the contents of a file into the artifacts
+//! defined there, each with a `Fingerprint`. Extractors are registered per
+//! language in an `ExtractorRegistry`; the built-in ones use tree-sitter
+//! grammars (see `languages`).
+//!
+//! When a commit changes files, the artifacts found in the new versions are
+//! matched against those of the old versions, in order of confidence: the same
+//! qualified name in the same file, then the same name and signature in any
+//! changed file, then the most similar body. A match keeps the old stable id, so
+//! a function moved to another file, or renamed with its body intact, is still
+//! the same artifact.
+
+use crate::languages::TreeSitterExtractor;
+use crate::{ArtifactType, Result};
+use serde::{Deserialize, Serialize};
+
+/// Default `ArtifactExtractor::similarity_threshold`
+pub const DEFAULT_SIMILARITY_THRESHOLD: f64 = 0.75;
+
+/// MinHash slots in a body fingerprint
+const MINHASH_SLOTS: u64 = 16;
+/// Tokens per shingle hashed into a body fingerprint
+const SHINGLE_TOKENS: usize = 3;
+/// Bodies shorter than this are too common to match on similarity alone
+const MIN_SIMILAR_TOKENS: u32 = 8;
+
+/// What identifies an artifact apart from its location
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    /// Name qualified by what the artifact is inside, such as `Parser::parse`
+    pub name: String,
+    /// Hash of the signature's tokens, so whitespace does not matter
+    pub signature: u64,
+    /// MinHash of the body's token shingles
+    pub body: Vec<u64>,
+    /// Tokens in the body
+    pub tokens: u32,
+    /// Hash of the body's exact bytes, telling any edit apart
+    #[serde(default)]
+    pub content: u64,
+}
+
+impl Fingerprint {
+    pub fn new(name: impl Into<String>, signature: &str, body: &[u8]) -> Self {
+        let signature = hash(&tokenize(signature.as_bytes()).join(&b' ')).0;
+        let tokens = tokenize(body);
+        let mut minhash = Vec::new();
+        if !tokens.is_empty() {
+            minhash = vec![u64::MAX; MINHASH_SLOTS as usize];
+            for shingle in tokens.windows(SHINGLE_TOKENS.min(tokens.len())) {
+                let (h1, h2) = hash(&shingle.join(&b' '));
+                for (i, slot) in minhash.iter_mut().enumerate() {
+                    *slot = (*slot).min(h1.wrapping_add((i as u64).wrapping_mul(h2)));
+                }
+            }
+        }
+        Self { name: name.into(), signature, body: minhash, tokens: tokens.len() as u32, content: hash(body).0 }
+    }
+
+    /// Estimated share of body shingles the two have in common, from 0 to 1;
+    /// 0 when either body is too short to tell
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        if self.tokens < MIN_SIMILAR_TOKENS || other.tokens < MIN_SIMILAR_TOKENS || self.body.len() != other.body.len() {
+            return 0.0;
+        }
+        let same = self.body.iter().zip(&other.body).filter(|(a, b)| a == b).count();
+        same as f64 / self.body.len() as f64
+    }
+}
+
+/// Text with runs of whitespace collapsed to single spaces
+pub(crate) fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Identifiers and numbers, and single punctuation characters; bytes outside
+/// ASCII count as letters
+fn tokenize(source: &[u8]) -> Vec<&[u8]> {
+    let word = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || !b.is_ascii();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < source.len() {
+        let start = i;
+        i += 1;
+        if word(source[start]) {
+            while i < source.len() && word(source[i]) {
+                i += 1;
+            }
+        } else if source[start].is_ascii_whitespace() {
+            continue;
+        }
+        tokens.push(&source[start..i]);
+    }
+    tokens
+}
+
+/// Two 64-bit hashes of `data` for double hashing
+fn hash(data: &[u8]) -> (u64, u64) {
+    let hash = blake3::hash(data);
+    let bytes = hash.as_bytes();
+    let h1 = u64::from_le_bytes(bytes[..8].try_into().expect("8 bytes"));
+    let h2 = u64::from_le_bytes(bytes[8..16].try_into().expect("8 bytes"));
+    (h1, h2)
+}
+
+/// An artifact found in a file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExtractedArtifact {
+    pub artifact_type: ArtifactType,
+    /// Lines from 1, both ends included
+    pub line_range: (u32, u32),
+    /// Bytes from 0, end excluded
+    pub byte_range: (u64, u64),
+    pub fingerprint: Fingerprint,
+}
+
+/// Finds the artifacts defined in files of one language
+pub trait ArtifactExtractor: Send + Sync {
+    /// Language recorded in the artifacts' types
+    fn language(&self) -> &str;
+
+    /// Whether files at `path` are in this language
+    fn handles(&self, path: &str) -> bool;
+
+    /// Artifacts defined in `source`, outermost first
+    fn extract(&self, source: &[u8]) -> Result<Vec<ExtractedArtifact>>;
+
+    /// Least body similarity for an artifact to continue one that is gone
+    fn similarity_threshold(&self) -> f64 {
+        DEFAULT_SIMILARITY_THRESHOLD
+    }
+}
+
+/// Extractors by language; the first one handling a path is used
+pub struct ExtractorRegistry {
+    extractors: Vec<Box<dyn ArtifactExtractor>>,
+}
+
+impl ExtractorRegistry {
+    /// The built-in Rust, TypeScript and Python extractors
+    pub fn new() -> Self {
+        let mut registry = Self::empty();
+        registry.register(Box::new(TreeSitterExtractor::rust()));
+        registry.register(Box::new(TreeSitterExtractor::typescript()));
+        registry.register(Box::new(TreeSitterExtractor::tsx()));
+        registry.register(Box::new(TreeSitterExtractor::python()));
+        registry
+    }
+
+    /// No extractors at all
+    pub fn empty() -> Self {
+        Self { extractors: Vec::new() }
+    }
+
+    /// Add `extractor`, after those already registered
+    pub fn register(&mut self, extractor: Box<dyn ArtifactExtractor>) {
+        self.extractors.push(extractor);
+    }
+
+    /// The extractor for files at `path`, if any
+    pub fn for_path(&self, path: &str) -> Option<&dyn ArtifactExtractor> {
+        self.extractors.iter().find(|e| e.handles(path)).map(|e| e.as_ref())
+    }
+}
+
+impl Default for ExtractorRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// An artifact up for matching: its path, what was extracted, and for new
+/// artifacts the similarity threshold of their extractor
+pub(crate) struct Candidate<'a> {
+    pub path: &'a str,
+    pub artifact: &'a ExtractedArtifact,
+    pub threshold: f64,
+}
+
+impl Candidate<'_> {
+    fn continues(&self, old: &Candidate) -> bool {
+        let (new, old) = (&self.artifact.artifact_type, &old.artifact.artifact_type);
+        new.kind() == old.kind() && new.language() == old.language()
+    }
+
+    /// Same name, and either in the same file or with the same signature
+    fn same(&self, old: &Candidate, same_file: bool) -> bool {
+        let (new_print, old_print) = (&self.artifact.fingerprint, &old.artifact.fingerprint);
+        new_print.name == old_print.name
+            && if same_file { self.path == old.path } else { new_print.signature == old_print.signature }
+    }
+}
+
+/// For each new artifact, the old one it continues, if any
+pub(crate) fn match_artifacts(old: &[Candidate], new: &[Candidate]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; new.len()];
+    let mut taken = vec![false; old.len()];
+    // Same file and name, then same name and signature anywhere
+    for same_file in [true, false] {
+        for (j, candidate) in new.iter().enumerate() {
+            if matches[j].is_some() {
+                continue;
+            }
+            let found = old.iter().enumerate()
+                .position(|(i, old)| !taken[i] && candidate.continues(old) && candidate.same(old, same_file));
+            if let Some(i) = found {
+                taken[i] = true;
+                matches[j] = Some(i);
+            }
+        }
+    }
+
+    // Most similar pairs first
+    let mut pairs = Vec::new();
+    for (j, candidate) in new.iter().enumerate().filter(|(j, _)| matches[*j].is_none()) {
+        for (i, old) in old.iter().enumerate().filter(|(i, _)| !taken[*i]) {
+            let similarity = candidate.artifact.fingerprint.similarity(&old.artifact.fingerprint);
+            if candidate.continues(old) && similarity >= candidate.threshold && similarity > 0.0 {
+                pairs.push((similarity, j, i));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.0.total_cmp(&a.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+    for (_, j, i) in pairs {
+        if matches[j].is_none() && !taken[i] {
+            taken[i] = true;
+            matches[j] = Some(i);
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(name: &str, signature: &str, body: &str) -> ExtractedArtifact {
+        ExtractedArtifact {
+            artifact_type: ArtifactType::Function { signature: signature.to_string(), language: "rust".to_string() },
+            line_range: (1, 1),
+            byte_range: (0, 0),
+            fingerprint: Fingerprint::new(name, signature, body.as_bytes()),
+        }
+    }
+
+    #[test]
+    fn test_fingerprint_similarity() {
+        let body = "{ let total = items.iter().map(|item| item.price * item.count).sum(); total + shipping }";
+        let a = Fingerprint::new("total", "fn total(items: &[Item])", body.as_bytes());
+        let b = Fingerprint::new("sum", "fn   sum(items: &[Item])", body.replace("  ", " ").as_bytes());
+        assert_eq!(a.similarity(&b), 1.0);
+        assert_eq!(a.signature, Fingerprint::new("x", "fn total(\n    items: &[Item]\n)", b"").signature);
+
+        let edited = Fingerprint::new("total", "", body.replace("shipping", "shipping + tax").as_bytes());
+        assert!(a.similarity(&edited) >= 0.5, "{}", a.similarity(&edited));
+        let other = Fingerprint::new("other", "", b"{ println!(\"{}\", name); std::process::exit(1) }");
+        assert!(a.similarity(&other) < 0.25);
+        // Short bodies never match on similarity
+        let short = Fingerprint::new("one", "", b"{ 1 }");
+        assert_eq!(short.similarity(&short), 0.0);
+    }
+
+    #[test]
+    fn test_match_artifacts() {
+        let body = "{ let total = items.iter().map(|item| item.price * item.count).sum(); total + shipping }";
+        let old = [
+            function("parse", "fn parse()", "{ a }"),
+            function("total", "fn total(items: &[Item])", body),
+            function("render", "fn render(&self)", "{ b }"),
+            function("gone", "fn gone()", "{ c }"),
+        ];
+        let new = [
+            function("parse", "fn parse(input: &str)", "{ d }"),
+            function("render", "fn render(&self)", "{ b }"),
+            function("sum", "fn sum(items: &[Item])", body),
+            function("fresh", "fn fresh()", "{ e }"),
+        ];
+        let old: Vec<Candidate> = old.iter().map(|artifact| Candidate { path: "a.rs", artifact, threshold: 0.75 }).collect();
+        let mut new: Vec<Candidate> = new.iter().map(|artifact| Candidate { path: "b.rs", artifact, threshold: 0.75 }).collect();
+        new[0].path = "a.rs";
+        // Same file and name; moved with its signature; renamed with its body
+        assert_eq!(match_artifacts(&old, &new), vec![Some(0), Some(2), Some(1), None]);
+    }
+}
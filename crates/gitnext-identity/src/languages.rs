@@ -0,0 +1,298 @@
+//! Tree-sitter extractors for Rust, TypeScript and Python
+//!
+//! Each language is a grammar plus the syntax nodes that define artifacts.
+//! Artifacts nested in others are found too, with names qualified by what they
+//! are inside: `Parser::parse` for a method in a Rust `impl Parser` block,
+//! `Parser.parse` in TypeScript and Python. The signature is the text before
+//! the body, and the body is what the fingerprint hashes.
+
+use crate::extract::{normalize, ArtifactExtractor, ExtractedArtifact, Fingerprint};
+use crate::{ArtifactType, IdentityError, Result};
+use tree_sitter::{Language, Node, Parser};
+
+/// What a syntax node defines
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rule {
+    Function,
+    Type,
+    Class,
+    Module,
+    /// Nothing itself, but names what is inside it, like a Rust `impl` block
+    Scope,
+}
+
+const RUST_RULES: &[(&str, Rule)] = &[
+    ("function_item", Rule::Function),
+    ("function_signature_item", Rule::Function),
+    ("struct_item", Rule::Type),
+    ("enum_item", Rule::Type),
+    ("union_item", Rule::Type),
+    ("trait_item", Rule::Type),
+    ("type_item", Rule::Type),
+    ("mod_item", Rule::Module),
+    ("impl_item", Rule::Scope),
+];
+
+const TYPESCRIPT_RULES: &[(&str, Rule)] = &[
+    ("function_declaration", Rule::Function),
+    ("generator_function_declaration", Rule::Function),
+    ("function_signature", Rule::Function),
+    ("method_definition", Rule::Function),
+    ("method_signature", Rule::Function),
+    ("abstract_method_signature", Rule::Function),
+    ("class_declaration", Rule::Class),
+    ("abstract_class_declaration", Rule::Class),
+    ("interface_declaration", Rule::Type),
+    ("type_alias_declaration", Rule::Type),
+    ("enum_declaration", Rule::Type),
+    ("internal_module", Rule::Module),
+    ("module", Rule::Module),
+];
+
+const PYTHON_RULES: &[(&str, Rule)] = &[("function_definition", Rule::Function), ("class_definition", Rule::Class)];
+
+/// Extractor driven by a tree-sitter grammar
+pub struct TreeSitterExtractor {
+    language: &'static str,
+    extensions: &'static [&'static str],
+    grammar: Language,
+    rules: &'static [(&'static str, Rule)],
+    /// Joins the parts of qualified names
+    separator: &'static str,
+}
+
+impl TreeSitterExtractor {
+    /// Rust, for `.rs` files
+    pub fn rust() -> Self {
+        Self {
+            language: "rust",
+            extensions: &["rs"],
+            grammar: tree_sitter_rust::LANGUAGE.into(),
+            rules: RUST_RULES,
+            separator: "::",
+        }
+    }
+
+    /// TypeScript, for `.ts`, `.mts` and `.cts` files
+    pub fn typescript() -> Self {
+        Self {
+            language: "typescript",
+            extensions: &["ts", "mts", "cts"],
+            grammar: tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
+            rules: TYPESCRIPT_RULES,
+            separator: ".",
+        }
+    }
+
+    /// TypeScript with JSX, for `.tsx` files
+    pub fn tsx() -> Self {
+        Self { extensions: &["tsx"], grammar: tree_sitter_typescript::LANGUAGE_TSX.into(), ..Self::typescript() }
+    }
+
+    /// Python, for `.py` and `.pyi` files
+    pub fn python() -> Self {
+        Self {
+            language: "python",
+            extensions: &["py", "pyi"],
+            grammar: tree_sitter_python::LANGUAGE.into(),
+            rules: PYTHON_RULES,
+            separator: ".",
+        }
+    }
+
+    fn visit(&self, node: Node, source: &[u8], scope: &mut Vec<String>, artifacts: &mut Vec<ExtractedArtifact>) {
+        let mut cursor = node.walk();
+        for child in node.named_children(&mut cursor) {
+            let rule = self.rules.iter().find(|(kind, _)| *kind == child.kind()).map(|&(_, rule)| rule);
+            let name = rule.and_then(|rule| Some((rule, self.name(child, rule, source)?)));
+            let Some((rule, name)) = name else {
+                self.visit(child, source, scope, artifacts);
+                continue;
+            };
+            scope.push(name);
+            if rule != Rule::Scope {
+                artifacts.push(self.artifact(child, rule, &scope.join(self.separator), source));
+            }
+            self.visit(child, source, scope, artifacts);
+            scope.pop();
+        }
+    }
+
+    fn name(&self, node: Node, rule: Rule, source: &[u8]) -> Option<String> {
+        let field = |name| node.child_by_field_name(name).map(|n| normalize(&text(n, source)));
+        match rule {
+            Rule::Scope => {
+                // `impl<T> Stack<T>` names its functions `Stack::...`
+                let mut target = node.child_by_field_name("type")?;
+                if target.kind() == "generic_type" {
+                    target = target.child_by_field_name("type")?;
+                }
+                let target = normalize(&text(target, source));
+                Some(match field("trait") {
+                    Some(name) => format!("<{} as {}>", target, name),
+                    None => target,
+                })
+            }
+            _ => field("name"),
+        }
+    }
+
+    fn artifact(&self, node: Node, rule: Rule, name: &str, source: &[u8]) -> ExtractedArtifact {
+        let body = node.child_by_field_name("body");
+        let header = match body {
+            Some(body) => &source[node.start_byte()..body.start_byte()],
+            None => &source[node.byte_range()],
+        };
+        let signature = normalize(&String::from_utf8_lossy(header));
+        let language = self.language.to_string();
+        let artifact_type = match rule {
+            Rule::Function => ArtifactType::Function { signature: signature.clone(), language },
+            Rule::Type => ArtifactType::Type { name: name.to_string(), language },
+            Rule::Class => ArtifactType::Class { name: name.to_string(), language },
+            Rule::Module | Rule::Scope => ArtifactType::Module { path: name.to_string(), language },
+        };
+        let body = &source[body.unwrap_or(node).byte_range()];
+        ExtractedArtifact {
+            artifact_type,
+            line_range: (node.start_position().row as u32 + 1, node.end_position().row as u32 + 1),
+            byte_range: (node.start_byte() as u64, node.end_byte() as u64),
+            fingerprint: Fingerprint::new(name, &signature, body),
+        }
+    }
+}
+
+impl ArtifactExtractor for TreeSitterExtractor {
+    fn language(&self) -> &str {
+        self.language
+    }
+
+    fn handles(&self, path: &str) -> bool {
+        let file = path.rsplit('/').next().unwrap_or(path);
+        file.rsplit_once('.').is_some_and(|(_, extension)| self.extensions.contains(&extension))
+    }
+
+    fn extract(&self, source: &[u8]) -> Result<Vec<ExtractedArtifact>> {
+        let mut parser = Parser::new();
+        parser.set_language(&self.grammar).map_err(|e| IdentityError::Extraction(e.to_string()))?;
+        let tree = parser.parse(source, None)
+            .ok_or_else(|| IdentityError::Extraction(format!("could not parse {} source", self.language)))?;
+        let mut artifacts = Vec::new();
+        self.visit(tree.root_node(), source, &mut Vec::new(), &mut artifacts);
+        Ok(artifacts)
+    }
+}
+
+fn text(node: Node, source: &[u8]) -> String {
+    String::from_utf8_lossy(&source[node.byte_range()]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ArtifactKind;
+
+    /// (kind, qualified name, lines) of each artifact
+    fn summary(extractor: &TreeSitterExtractor, source: &str) -> Vec<(ArtifactKind, String, (u32, u32))> {
+        extractor.extract(source.as_bytes()).unwrap().into_iter()
+            .map(|a| (a.artifact_type.kind(), a.fingerprint.name, a.line_range))
+            .collect()
+    }
+
+    #[test]
+    fn test_rust() {
+        let source = "\
+/// A parser
+pub struct Parser<'a> {
+    input: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    pub fn new(input: &'a str) -> Self {
+        Self { input }
+    }
+}
+
+impl std::fmt::Display for Parser<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { Ok(()) }
+}
+
+mod util {
+    pub trait Visit { fn visit(&self); }
+    type Map = std::collections::HashMap<String, u32>;
+}
+";
+        let extractor = TreeSitterExtractor::rust();
+        assert_eq!(summary(&extractor, source), vec![
+            (ArtifactKind::Type, "Parser".to_string(), (2, 4)),
+            (ArtifactKind::Function, "Parser::new".to_string(), (7, 9)),
+            (ArtifactKind::Function, "<Parser as std::fmt::Display>::fmt".to_string(), (13, 13)),
+            (ArtifactKind::Module, "util".to_string(), (16, 19)),
+            (ArtifactKind::Type, "util::Visit".to_string(), (17, 17)),
+            (ArtifactKind::Function, "util::Visit::visit".to_string(), (17, 17)),
+            (ArtifactKind::Type, "util::Map".to_string(), (18, 18)),
+        ]);
+        let artifacts = extractor.extract(source.as_bytes()).unwrap();
+        assert_eq!(artifacts[1].artifact_type, ArtifactType::Function {
+            signature: "pub fn new(input: &'a str) -> Self".to_string(),
+            language: "rust".to_string(),
+        });
+        let (start, end) = artifacts[1].byte_range;
+        assert!(source[start as usize..end as usize].starts_with("pub fn new"));
+        assert!(extractor.handles("src/lib.rs") && !extractor.handles("src/lib.rs.orig") && !extractor.handles("rs"));
+    }
+
+    #[test]
+    fn test_typescript_and_python() {
+        let typescript = "\
+export interface Shape { area(): number; }
+export class Circle implements Shape {
+  constructor(private r: number) {}
+  area(): number { return Math.PI * this.r ** 2; }
+}
+namespace Geometry { export function unit(): Circle { return new Circle(1); } }
+type Id = string;
+";
+        assert_eq!(summary(&TreeSitterExtractor::typescript(), typescript), vec![
+            (ArtifactKind::Type, "Shape".to_string(), (1, 1)),
+            (ArtifactKind::Function, "Shape.area".to_string(), (1, 1)),
+            (ArtifactKind::Class, "Circle".to_string(), (2, 5)),
+            (ArtifactKind::Function, "Circle.constructor".to_string(), (3, 3)),
+            (ArtifactKind::Function, "Circle.area".to_string(), (4, 4)),
+            (ArtifactKind::Module, "Geometry".to_string(), (6, 6)),
+            (ArtifactKind::Function, "Geometry.unit".to_string(), (6, 6)),
+            (ArtifactKind::Type, "Id".to_string(), (7, 7)),
+        ]);
+        let tsx = "export function App(): JSX.Element { return <div>hi</div>; }\n";
+        assert_eq!(summary(&TreeSitterExtractor::tsx(), tsx), vec![(ArtifactKind::Function, "App".to_string(), (1, 1))]);
+
+        let python = "\
+import os
+
+class Repo:
+    @property
+    def path(self):
+        return os.getcwd()
+
+    def walk(self, root):
+        def visit(entry):
+            return entry
+        return [visit(e) for e in os.listdir(root)]
+
+def main():
+    Repo().walk('.')
+";
+        let extractor = TreeSitterExtractor::python();
+        assert_eq!(summary(&extractor, python), vec![
+            (ArtifactKind::Class, "Repo".to_string(), (3, 11)),
+            (ArtifactKind::Function, "Repo.path".to_string(), (5, 6)),
+            (ArtifactKind::Function, "Repo.walk".to_string(), (8, 11)),
+            (ArtifactKind::Function, "Repo.walk.visit".to_string(), (9, 10)),
+            (ArtifactKind::Function, "main".to_string(), (13, 14)),
+        ]);
+        let artifacts = extractor.extract(python.as_bytes()).unwrap();
+        assert_eq!(artifacts[2].artifact_type.name(), "def walk(self, root):");
+        // Syntax errors leave the rest of the file readable
+        let broken = "def ok():\n    return 1\n\ndef broken(:\n";
+        assert_eq!(summary(&extractor, broken)[0].1, "ok");
+    }
+}
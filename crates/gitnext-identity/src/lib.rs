@@ -0,0 +1,66 @@
+//! GitNext Identity - artifact tracking (ADR-004, Requirements 11)
+//!
+//! Artifacts are pieces of a repository worth following on their own:
+//! functions, types, modules and the like. Each keeps a stable id across moves
+//! and renames, with a history of where it has been. Like other GitNext
+//! metadata, artifacts live in blobs named by refs under `refs/gitnext/`, never
+//! inside trees or commits, so Git object hashes are unaffected.
+//!
+//! - `annotations`: annotations on artifacts, their schemas and history
+//! - `artifact`: artifacts, their locations, versions and references
+//! - `extract`: extractors finding artifacts in files, and how they are matched
+//!   across commits
+//! - `languages`: tree-sitter extractors for Rust, TypeScript and Python
+//! - `provenance`: the commits that changed an artifact
+//! - `tracker`: `ArtifactTracker`, which stores and queries them
+
+use gitnext_core::ObjectId;
+use gitnext_storage::StorageError;
+use thiserror::Error;
+
+pub mod annotations;
+pub mod artifact;
+pub mod extract;
+pub mod languages;
+pub mod provenance;
+pub mod tracker;
+
+pub use annotations::AnnotationChange;
+pub use artifact::{
+    Artifact, ArtifactId, ArtifactKind, ArtifactLocation, ArtifactMetadata, ArtifactQuery, ArtifactReference,
+    ArtifactType, ArtifactVersion,
+};
+pub use extract::{ArtifactExtractor, ExtractedArtifact, ExtractorRegistry, Fingerprint, DEFAULT_SIMILARITY_THRESHOLD};
+pub use languages::TreeSitterExtractor;
+pub use provenance::{ArtifactChange, ArtifactChangeKind};
+pub use tracker::{ArtifactTracker, ARTIFACTS_REF};
+
+/// Identity error types
+#[derive(Debug, Error)]
+pub enum IdentityError {
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error("Serialization error: {0}")]
+    Serialization(String),
+
+    #[error("Unknown artifact: {0}")]
+    UnknownArtifact(ArtifactId),
+
+    #[error("Commit not indexed: {0}")]
+    NotIndexed(ObjectId),
+
+    #[error("Extraction error: {0}")]
+    Extraction(String),
+
+    #[error("Invalid schema for annotation {key}: {reason}")]
+    InvalidSchema { key: String, reason: String },
+
+    #[error("Invalid annotation {key}: {reason}")]
+    InvalidAnnotation { key: String, reason: String },
+
+    #[error("Tree error: {0}")]
+    Tree(#[from] gitnext_merge::MergeError),
+}
+
+pub type Result<T> = std::result::Result<T, IdentityError>;
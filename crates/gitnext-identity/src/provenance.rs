@@ -0,0 +1,340 @@
+//! Provenance: which commits changed an artifact, and how
+//!
+//! Changes are recorded while commits are indexed (see `tracker`), so queries
+//! look them up by artifact or by commit instead of walking history. A commit
+//! changes an artifact when the artifact differs from its version in every
+//! parent: added, deleted, or with a new body, signature, name or file. Only
+//! where the change is and which blobs it is between are kept; authors and
+//! hunks are read back from those commits and blobs when asked for.
+
+use crate::{ArtifactId, ArtifactLocation, ExtractedArtifact, IdentityError, Result};
+use gitnext_core::{GitObject, ObjectId, Signature};
+use gitnext_merge::split_lines;
+use gitnext_query::{diff_text, Hunk};
+use gitnext_storage::{Storage, StorageError};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+
+/// Lines of context around the changes in `ArtifactChange::hunks`
+const CONTEXT_LINES: usize = 3;
+
+/// What a commit did to an artifact
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArtifactChangeKind {
+    Added,
+    /// Still there, but edited, renamed or moved
+    Modified,
+    Deleted,
+}
+
+/// A commit changing an artifact
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtifactChange {
+    pub artifact: ArtifactId,
+    pub commit: ObjectId,
+    pub author: Signature,
+    pub kind: ArtifactChangeKind,
+    /// Its signature or body changed, rather than only its name or file;
+    /// always true for additions and deletions
+    pub body_changed: bool,
+    /// Where it was in the parent, unless added
+    pub old: Option<ArtifactLocation>,
+    /// Where it is in the commit, unless deleted
+    pub new: Option<ArtifactLocation>,
+    /// The file it was in, when it moved to another
+    pub moved_from: Option<String>,
+    /// Its qualified name before, when it was renamed
+    pub renamed_from: Option<String>,
+    /// Changes to its lines, numbered as in the files
+    pub hunks: Vec<Hunk>,
+}
+
+/// One side of a recorded change
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Side {
+    pub commit: ObjectId,
+    pub path: String,
+    pub blob: ObjectId,
+    /// Qualified name
+    pub name: String,
+    pub line_range: (u32, u32),
+    pub byte_range: (u64, u64),
+}
+
+impl Side {
+    pub fn of(commit: ObjectId, path: &str, blob: ObjectId, artifact: &ExtractedArtifact) -> Self {
+        Self {
+            commit,
+            path: path.to_string(),
+            blob,
+            name: artifact.fingerprint.name.clone(),
+            line_range: artifact.line_range,
+            byte_range: artifact.byte_range,
+        }
+    }
+
+    pub fn location(&self) -> ArtifactLocation {
+        ArtifactLocation {
+            line_range: Some(self.line_range),
+            byte_range: Some(self.byte_range),
+            ..ArtifactLocation::new(self.commit, self.path.clone())
+        }
+    }
+}
+
+/// A change as the index keeps it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct ChangeRecord {
+    pub artifact: ArtifactId,
+    pub commit: ObjectId,
+    pub old: Option<Side>,
+    pub new: Option<Side>,
+    pub body_changed: bool,
+}
+
+/// Authors and blob contents loaded while describing changes
+#[derive(Default)]
+pub(crate) struct Loaded {
+    authors: HashMap<ObjectId, Signature>,
+    blobs: HashMap<ObjectId, Vec<u8>>,
+}
+
+impl Loaded {
+    async fn author(&mut self, storage: &dyn Storage, commit: ObjectId) -> Result<Signature> {
+        if let Some(author) = self.authors.get(&commit) {
+            return Ok(author.clone());
+        }
+        let author = match storage.load_object(&commit).await? {
+            Some(GitObject::Commit(c)) => c.author,
+            _ => return Err(StorageError::ObjectNotFound { id: commit }.into()),
+        };
+        self.authors.insert(commit, author.clone());
+        Ok(author)
+    }
+
+    /// Whole lines `line_range` of the side's blob
+    async fn text(&mut self, storage: &dyn Storage, side: Option<&Side>) -> Result<Vec<u8>> {
+        let Some(side) = side else {
+            return Ok(Vec::new());
+        };
+        if let Entry::Vacant(slot) = self.blobs.entry(side.blob) {
+            let content = match storage.load_object(&side.blob).await? {
+                Some(GitObject::Blob(blob)) => blob.content.unwrap_or_default().to_vec(),
+                _ => return Err(StorageError::ObjectNotFound { id: side.blob }.into()),
+            };
+            slot.insert(content);
+        }
+        let lines = split_lines(&self.blobs[&side.blob]);
+        let (start, end) = side.line_range;
+        let (start, end) = ((start as usize).saturating_sub(1).min(lines.len()), (end as usize).min(lines.len()));
+        Ok(lines[start..end.max(start)].concat())
+    }
+
+    /// The change `record` describes
+    pub(crate) async fn change(&mut self, storage: &dyn Storage, record: &ChangeRecord) -> Result<ArtifactChange> {
+        let kind = match (&record.old, &record.new) {
+            (None, Some(_)) => ArtifactChangeKind::Added,
+            (Some(_), Some(_)) => ArtifactChangeKind::Modified,
+            (Some(_), None) => ArtifactChangeKind::Deleted,
+            (None, None) => return Err(IdentityError::Serialization("change without either side".to_string())),
+        };
+        let (old, new) = (record.old.as_ref(), record.new.as_ref());
+        let (moved_from, renamed_from) = match (old, new) {
+            (Some(old), Some(new)) => (
+                (old.path != new.path).then(|| old.path.clone()),
+                (old.name != new.name).then(|| old.name.clone()),
+            ),
+            _ => (None, None),
+        };
+        let mut hunks = diff_text(&self.text(storage, old).await?, &self.text(storage, new).await?, CONTEXT_LINES);
+        let offset = |side: Option<&Side>| side.map_or(0, |s| (s.line_range.0 as usize).saturating_sub(1));
+        for hunk in &mut hunks {
+            hunk.old_start += offset(old);
+            hunk.new_start += offset(new);
+        }
+        Ok(ArtifactChange {
+            artifact: record.artifact,
+            commit: record.commit,
+            author: self.author(storage, record.commit).await?,
+            kind,
+            body_changed: record.body_changed,
+            old: old.map(Side::location),
+            new: new.map(Side::location),
+            moved_from,
+            renamed_from,
+            hunks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::tests::store_commit;
+    use crate::{ArtifactQuery, ArtifactTracker, ExtractorRegistry};
+    use gitnext_query::DiffLine;
+    use gitnext_storage::MemoryStorage;
+    use proptest::prelude::*;
+    use std::sync::Arc;
+
+    /// Id of the one tracked artifact whose signature or name contains `name`
+    async fn id_of(tracker: &ArtifactTracker, name: &str) -> ArtifactId {
+        let query = ArtifactQuery { name: Some(name.to_string()), ..Default::default() };
+        let found = tracker.query_artifacts(query).await.unwrap();
+        assert_eq!(found.len(), 1, "one artifact named {}", name);
+        found[0].stable_id
+    }
+
+    #[tokio::test]
+    async fn test_provenance() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let s = storage.as_ref();
+        let total = "def total(items):\n    subtotal = sum(item.price * item.count for item in items)\n    return subtotal + SHIPPING\n";
+        let helper = "\ndef helper():\n    return 1\n";
+        let c1 = store_commit(s, &[], &[("a.py", &format!("{}{}", total, helper))], 1).await;
+        let taxed = total.replace("SHIPPING", "SHIPPING + TAX");
+        let c2 = store_commit(s, &[c1], &[("a.py", &format!("{}{}", taxed, helper))], 2).await;
+        let c3 = store_commit(s, &[c2], &[("a.py", &helper[1..]), ("b.py", &format!("import os\n\n{}", taxed))], 3).await;
+        let b4 = format!("import os\n\n{}", taxed.replace("def total", "def grand_total"));
+        let c4 = store_commit(s, &[c3], &[("b.py", &b4)], 4).await;
+        // Back to the version from c3
+        let c5 = store_commit(s, &[c4], &[("b.py", &format!("import os\n\n{}", taxed))], 5).await;
+
+        let mut tracker = ArtifactTracker::open(storage.clone()).await.unwrap();
+        tracker.index_commits(&ExtractorRegistry::new()).await.unwrap();
+        let (total_id, helper_id) = (id_of(&tracker, "total").await, id_of(&tracker, "helper").await);
+
+        let changes = tracker.get_artifact_provenance(&total_id).await.unwrap();
+        let summary: Vec<_> = changes.iter()
+            .map(|c| (c.commit, c.kind, c.body_changed, c.moved_from.as_deref(), c.renamed_from.as_deref()))
+            .collect();
+        assert_eq!(summary, vec![
+            (c1, ArtifactChangeKind::Added, true, None, None),
+            (c2, ArtifactChangeKind::Modified, true, None, None),
+            (c3, ArtifactChangeKind::Modified, false, Some("a.py"), None),
+            (c4, ArtifactChangeKind::Modified, true, None, Some("total")),
+            (c5, ArtifactChangeKind::Modified, true, None, Some("grand_total")),
+        ]);
+        assert_eq!(changes[1].author.timestamp, 2);
+        assert_eq!(changes[0].hunks.len(), 1);
+        assert_eq!((changes[0].hunks[0].old_lines, changes[0].hunks[0].new_lines), (0, 3));
+        let hunk = &changes[1].hunks[0];
+        assert_eq!((hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines), (1, 3, 1, 3));
+        assert_eq!(hunk.lines[2], DiffLine::Removed(b"    return subtotal + SHIPPING\n".to_vec()));
+        // Moved without changes; then renamed, numbered as in b.py
+        assert!(changes[2].hunks.is_empty());
+        assert_eq!(changes[2].new.as_ref().unwrap().line_range, Some((3, 5)));
+        assert_eq!((changes[3].hunks[0].old_start, changes[3].hunks[0].new_start), (3, 3));
+
+        let helper_changes = tracker.get_artifact_provenance(&helper_id).await.unwrap();
+        let kinds: Vec<_> = helper_changes.iter().map(|c| (c.commit, c.kind)).collect();
+        assert_eq!(kinds, vec![(c1, ArtifactChangeKind::Added), (c4, ArtifactChangeKind::Deleted)]);
+        assert_eq!(helper_changes[1].old.as_ref().unwrap().file_path, "a.py");
+
+        // By commit: c3 only moved `total`, leaving `helper` where it was
+        let by_commit = |changes: Vec<ArtifactChange>| changes.into_iter().map(|c| (c.artifact, c.kind)).collect::<Vec<_>>();
+        assert_eq!(by_commit(tracker.find_commit_artifacts(&c3).await.unwrap()), vec![(total_id, ArtifactChangeKind::Modified)]);
+        assert_eq!(
+            by_commit(tracker.find_commit_artifacts(&c4).await.unwrap()),
+            vec![(helper_id, ArtifactChangeKind::Deleted), (total_id, ArtifactChangeKind::Modified)],
+        );
+        let unknown = ObjectId::from_blake3_bytes([7; 32]);
+        assert!(matches!(tracker.find_commit_artifacts(&unknown).await, Err(IdentityError::NotIndexed(id)) if id == unknown));
+        assert!(tracker.get_artifact_provenance(&ArtifactId::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_merge_provenance() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let s = storage.as_ref();
+        let f = |n: u32| format!("def f(x):\n    return x + {}\n", n);
+        let g = |n: u32| format!("def g(x):\n    return x * {}\n", n);
+        let base = store_commit(s, &[], &[("f.py", &f(0)), ("g.py", &g(0))], 1).await;
+        let topic = store_commit(s, &[base], &[("f.py", &f(1)), ("g.py", &g(0))], 2).await;
+        let main = store_commit(s, &[base], &[("f.py", &f(0)), ("g.py", &g(2))], 3).await;
+        let merge = store_commit(s, &[main, topic], &[("f.py", &f(1)), ("g.py", &g(2))], 4).await;
+        let fixed = store_commit(s, &[main, topic], &[("f.py", &f(3)), ("g.py", &g(2))], 5).await;
+
+        let mut tracker = ArtifactTracker::open(storage.clone()).await.unwrap();
+        tracker.index_commits(&ExtractorRegistry::new()).await.unwrap();
+        let f_id = id_of(&tracker, "def f").await;
+        let commits: Vec<ObjectId> = tracker.get_artifact_provenance(&f_id).await.unwrap().iter().map(|c| c.commit).collect();
+        // The clean merge took `f` from the topic unchanged; the other merge edited it
+        assert_eq!(commits, vec![base, topic, fixed]);
+        assert!(tracker.find_commit_artifacts(&merge).await.unwrap().is_empty());
+    }
+
+    /// Python source for functions `f{k}` at `versions[k]` that are in `file`
+    fn source(functions: &[(u32, bool)], file: bool) -> String {
+        functions.iter().enumerate()
+            .filter(|(_, f)| f.1 == file)
+            .map(|(k, (version, _))| format!("def f{k}(x):\n    y = x * {k} + {version}\n    return y - {k}\n\n"))
+            .collect()
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(30))]
+
+        /// Property 31: Provenance Query Accuracy
+        /// For any history of edits and moves between files, each function's
+        /// provenance lists exactly the commits that added, edited or moved it,
+        /// telling edits from moves, and each commit's artifacts are exactly
+        /// the function it changed.
+        /// **Validates: Requirements 11.3**
+        #[test]
+        fn prop_provenance_query_accuracy(steps in prop::collection::vec((0usize..3, any::<bool>()), 1..8)) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+                let s = storage.as_ref();
+                // (version, in b.py) of each function
+                let mut functions = vec![(0u32, false); 3];
+                let files = |functions: &[(u32, bool)]| {
+                    [("a.py", source(functions, false)), ("b.py", source(functions, true))]
+                        .into_iter().filter(|(_, content)| !content.is_empty()).collect::<Vec<_>>()
+                };
+                let store = |parents: Vec<ObjectId>, files: Vec<(&'static str, String)>, time| async move {
+                    let files: Vec<(&str, &str)> = files.iter().map(|(p, c)| (*p, c.as_str())).collect();
+                    store_commit(s, &parents, &files, time).await
+                };
+                let mut commits = vec![store(vec![], files(&functions), 0).await];
+                let mut expected: Vec<Vec<(ObjectId, bool)>> = vec![vec![(commits[0], true)]; 3];
+                for (i, &(k, moved)) in steps.iter().enumerate() {
+                    if moved {
+                        functions[k].1 = !functions[k].1;
+                    } else {
+                        functions[k].0 += 1;
+                    }
+                    let commit = store(vec![commits[i]], files(&functions), i as i64 + 1).await;
+                    commits.push(commit);
+                    expected[k].push((commit, !moved));
+                }
+
+                let mut tracker = ArtifactTracker::open(storage.clone()).await.unwrap();
+                tracker.index_commits(&ExtractorRegistry::new()).await.unwrap();
+                let mut ids = Vec::new();
+                for (k, expected) in expected.iter().enumerate() {
+                    let id = id_of(&tracker, &format!("f{}(", k)).await;
+                    ids.push(id);
+                    let changes = tracker.get_artifact_provenance(&id).await.unwrap();
+                    let found: Vec<(ObjectId, bool)> = changes.iter().map(|c| (c.commit, c.body_changed)).collect();
+                    prop_assert_eq!(&found, expected);
+                    prop_assert_eq!(changes[0].kind, ArtifactChangeKind::Added);
+                    for change in &changes[1..] {
+                        prop_assert_eq!(change.kind, ArtifactChangeKind::Modified);
+                        prop_assert_eq!(change.moved_from.is_some(), !change.body_changed);
+                    }
+                }
+                let first = tracker.find_commit_artifacts(&commits[0]).await.unwrap();
+                prop_assert_eq!(first.len(), 3);
+                for (i, &(k, _)) in steps.iter().enumerate() {
+                    let touched: Vec<ArtifactId> = tracker.find_commit_artifacts(&commits[i + 1]).await.unwrap()
+                        .into_iter().map(|c| c.artifact).collect();
+                    prop_assert_eq!(touched, vec![ids[k]]);
+                }
+                Ok(())
+            })?;
+        }
+    }
+}
@@ -0,0 +1,1126 @@
+//! Artifact tracker
+//!
+//! `ArtifactTracker` keeps every tracked artifact with its version history and
+//! the references to it. Each list it keeps (records, references, indexed
+//! commits, file versions and changes) is saved in chunks, with the annotation
+//! schemas in a blob beside them, so a change stores only the chunks it touched.
+//! They are JSON rather than bincode, since annotations and custom artifacts
+//! hold arbitrary `serde_json::Value`s; lookups by id, path and reference target
+//! are rebuilt in memory when it is opened.
+//!
+//! `ARTIFACTS_REF` names a manifest of all of them, which saving moves by
+//! compare-and-swap. Every change is made to the artifacts as last saved:
+//! a tracker that finds someone else saved since it loaded them loads them
+//! again before changing anything, and one that loses the swap does the change
+//! again on what the winner saved, so trackers sharing a storage never drop
+//! each other's changes.
+//!
+//! Tracking an artifact whose id is already known records a new version when
+//! its type, path or range changed, so an id survives any number of moves and
+//! renames; when only the commit differs, the artifact is just seen at a later
+//! commit. Nothing here touches trees or commits.
+//!
+//! `index_commits` finds artifacts in commits of the commit-graph index that
+//! have not been seen yet, parents first. Only files changed from a parent are
+//! parsed: each version of a file (path and blob) is extracted once, and its
+//! artifacts with their stable ids are kept, so a later commit with the same
+//! version reuses them, and a commit changing the file matches the new
+//! artifacts against them (see `extract`).
+//!
+//! Annotations are merged into what is recorded rather than replacing it, so
+//! indexing and re-tracking never lose them; `set_annotation` and
+//! `remove_annotation` change them one key at a time (see `annotations`).
+
+use crate::annotations::{check, validator, Schemas};
+use crate::artifact::within;
+use crate::extract::{match_artifacts, Candidate};
+use crate::provenance::{ChangeRecord, Loaded, Side};
+use crate::{
+    AnnotationChange, Artifact, ArtifactChange, ArtifactId, ArtifactLocation, ArtifactMetadata, ArtifactQuery, ArtifactReference, ArtifactVersion,
+    ExtractedArtifact, ExtractorRegistry, IdentityError, Result,
+};
+use chrono::{DateTime, Utc};
+use gitnext_core::{FileMode, GitObject, ObjectId};
+use gitnext_merge::TreeChange;
+use gitnext_query::CommitGraphIndex;
+use gitnext_storage::metadata::{load_blob_content_at, load_chunk_items, load_versioned, store_blob_content, store_chunks, swap_blob, Codec};
+use gitnext_storage::{points_at, Storage, StorageError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ops::Bound;
+use std::sync::Arc;
+
+/// Internal reference naming the manifest of what holds tracked artifacts
+pub const ARTIFACTS_REF: &str = "refs/gitnext/artifacts";
+
+/// JSON, for the chunks and blobs `ARTIFACTS_REF` names
+struct Json;
+
+impl Codec for Json {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> std::result::Result<Vec<u8>, StorageError> {
+        serde_json::to_vec(value).map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(content: &[u8]) -> std::result::Result<T, StorageError> {
+        serde_json::from_slice(content).map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+}
+
+/// An artifact as it is now, with every version of it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Record {
+    artifact: Artifact,
+    history: Vec<ArtifactVersion>,
+    /// Every change to its annotations, oldest first
+    #[serde(default)]
+    annotations: Vec<AnnotationChange>,
+}
+
+impl Record {
+    /// Set or remove the annotation `key`; false when it already was so
+    fn annotate(&mut self, key: &str, value: Option<Value>, author: Option<&str>, now: DateTime<Utc>) -> bool {
+        let annotations = &mut self.artifact.metadata.annotations;
+        if annotations.get(key) == value.as_ref() {
+            return false;
+        }
+        match &value {
+            Some(value) => annotations.insert(key.to_string(), value.clone()),
+            None => annotations.remove(key),
+        };
+        let version = self.annotations.iter().filter(|change| change.key == key).count() as u32 + 1;
+        self.annotations.push(AnnotationChange {
+            key: key.to_string(),
+            version,
+            value,
+            author: author.map(str::to_string),
+            timestamp: now,
+        });
+        true
+    }
+}
+
+/// Artifacts found in one version of a file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct FileArtifacts {
+    path: String,
+    blob: ObjectId,
+    artifacts: Vec<(ArtifactId, ExtractedArtifact)>,
+}
+
+/// What `ARTIFACTS_REF` names
+#[derive(Debug, Clone, Default)]
+struct ArtifactIndex {
+    records: Vec<Record>,
+    references: Vec<ArtifactReference>,
+    /// Commits `index_commits` has handled, in order
+    commits: Vec<ObjectId>,
+    files: Vec<FileArtifacts>,
+    changes: Vec<ChangeRecord>,
+    /// JSON Schema of each annotation key that has one
+    schemas: BTreeMap<String, Value>,
+}
+
+/// The blobs an `ArtifactIndex` is saved in
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Manifest {
+    records: Vec<ObjectId>,
+    references: Vec<ObjectId>,
+    commits: Vec<ObjectId>,
+    files: Vec<ObjectId>,
+    changes: Vec<ObjectId>,
+    schemas: Option<ObjectId>,
+}
+
+/// What of the `ArtifactIndex` has been saved, and where: the records and
+/// schemas are changed in place, the other lists only grow
+#[derive(Debug, Clone)]
+struct Saved {
+    /// Positions of records changed or added since
+    changed: BTreeSet<usize>,
+    references: usize,
+    commits: usize,
+    files: usize,
+    changes: usize,
+    schemas: bool,
+    /// The manifest `ARTIFACTS_REF` pointed at then, if any
+    id: Option<ObjectId>,
+    manifest: Manifest,
+}
+
+impl Saved {
+    fn all(index: &ArtifactIndex, id: Option<ObjectId>, manifest: Manifest) -> Self {
+        Self {
+            changed: BTreeSet::new(),
+            references: index.references.len(),
+            commits: index.commits.len(),
+            files: index.files.len(),
+            changes: index.changes.len(),
+            schemas: true,
+            id,
+            manifest,
+        }
+    }
+
+    /// Whether `index` has changes not saved yet
+    fn behind(&self, index: &ArtifactIndex) -> bool {
+        !self.changed.is_empty()
+            || self.references < index.references.len()
+            || self.commits < index.commits.len()
+            || self.files < index.files.len()
+            || self.changes < index.changes.len()
+            || !self.schemas
+    }
+}
+
+/// Tracks artifacts by stable id
+pub struct ArtifactTracker {
+    storage: Arc<dyn Storage>,
+    index: ArtifactIndex,
+    /// Stable id -> position in `index.records`
+    positions: HashMap<ArtifactId, usize>,
+    /// Current file path -> positions of the artifacts in it
+    by_path: BTreeMap<String, BTreeSet<usize>>,
+    /// Target -> positions in `index.references`
+    references_to: HashMap<ArtifactId, Vec<usize>>,
+    indexed: HashSet<ObjectId>,
+    /// (path, blob) -> position in `index.files`
+    file_positions: HashMap<(String, ObjectId), usize>,
+    /// Artifact -> positions in `index.changes`
+    changes_by_artifact: HashMap<ArtifactId, Vec<usize>>,
+    /// Commit -> positions in `index.changes`
+    changes_by_commit: HashMap<ObjectId, Vec<usize>>,
+    schemas: Schemas,
+    saved: Saved,
+}
+
+impl ArtifactTracker {
+    /// Open the artifacts stored in `storage`, if any
+    pub async fn open(storage: Arc<dyn Storage>) -> Result<Self> {
+        let (index, saved) = load(storage.as_ref()).await?;
+        Self::with_index(storage, index, saved)
+    }
+
+    fn with_index(storage: Arc<dyn Storage>, index: ArtifactIndex, saved: Saved) -> Result<Self> {
+        let schemas = Schemas::compile(&index.schemas)?;
+        let mut tracker = Self {
+            storage,
+            index,
+            positions: HashMap::new(),
+            by_path: BTreeMap::new(),
+            references_to: HashMap::new(),
+            indexed: HashSet::new(),
+            file_positions: HashMap::new(),
+            changes_by_artifact: HashMap::new(),
+            changes_by_commit: HashMap::new(),
+            schemas,
+            saved,
+        };
+        for position in 0..tracker.index.records.len() {
+            let artifact = &tracker.index.records[position].artifact;
+            tracker.positions.insert(artifact.stable_id, position);
+            tracker.by_path.entry(artifact.location.file_path.clone()).or_default().insert(position);
+        }
+        for (position, reference) in tracker.index.references.iter().enumerate() {
+            tracker.references_to.entry(reference.target).or_default().push(position);
+        }
+        tracker.indexed.extend(tracker.index.commits.iter().copied());
+        for (position, file) in tracker.index.files.iter().enumerate() {
+            tracker.file_positions.insert((file.path.clone(), file.blob), position);
+        }
+        for (position, change) in tracker.index.changes.iter().enumerate() {
+            tracker.changes_by_artifact.entry(change.artifact).or_default().push(position);
+            tracker.changes_by_commit.entry(change.commit).or_default().push(position);
+        }
+        Ok(tracker)
+    }
+
+    /// Load the artifacts again if someone else saved them since this tracker
+    /// loaded or saved them
+    async fn refresh(&mut self) -> Result<()> {
+        let current = self.storage.list_refs().await?.into_iter()
+            .find(|r| r.name == ARTIFACTS_REF)
+            .map(|r| r.target);
+        if !points_at(current.as_ref(), self.saved.id.as_ref()) {
+            let (index, saved) = load(self.storage.as_ref()).await?;
+            *self = Self::with_index(self.storage.clone(), index, saved)?;
+        }
+        Ok(())
+    }
+
+    /// Make `change` to the artifacts as last saved and save it, making it again
+    /// if someone else saved first
+    async fn update<T>(&mut self, mut change: impl FnMut(&mut Self) -> Result<T>) -> Result<T> {
+        loop {
+            self.refresh().await?;
+            let result = change(self)?;
+            if self.save().await? {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Number of tracked artifacts
+    pub fn len(&self) -> usize {
+        self.index.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.records.is_empty()
+    }
+
+    /// The artifact with `id`, as it is now
+    pub fn get_artifact(&self, id: &ArtifactId) -> Option<&Artifact> {
+        self.positions.get(id).map(|&position| &self.index.records[position].artifact)
+    }
+
+    /// Track `artifact`: a new id starts a history, a known one gets a new
+    /// version if its type, path or range changed. Annotations and tags are added
+    /// to those already recorded; annotations must be valid against the schemas
+    /// of their keys.
+    pub async fn track_artifact(&mut self, artifact: Artifact) -> Result<()> {
+        self.track_artifacts(vec![artifact]).await
+    }
+
+    /// Track several artifacts, saving once
+    pub async fn track_artifacts(&mut self, artifacts: impl IntoIterator<Item = Artifact>) -> Result<()> {
+        let artifacts: Vec<Artifact> = artifacts.into_iter().collect();
+        self.update(|tracker| {
+            for (key, value) in artifacts.iter().flat_map(|artifact| &artifact.metadata.annotations) {
+                tracker.schemas.check(key, value)?;
+            }
+            for artifact in &artifacts {
+                tracker.track(artifact.clone());
+            }
+            Ok(())
+        }).await
+    }
+
+    /// Record the artifact in memory
+    fn track(&mut self, mut artifact: Artifact) {
+        let now = Utc::now();
+        let mut annotations: Vec<(String, Value)> = std::mem::take(&mut artifact.metadata.annotations).into_iter().collect();
+        annotations.sort_by(|a, b| a.0.cmp(&b.0));
+        let Some(&position) = self.positions.get(&artifact.stable_id) else {
+            let position = self.index.records.len();
+            self.positions.insert(artifact.stable_id, position);
+            self.by_path.entry(artifact.location.file_path.clone()).or_default().insert(position);
+            let version = ArtifactVersion {
+                version: 1,
+                artifact_type: artifact.artifact_type.clone(),
+                location: artifact.location.clone(),
+                recorded_at: now,
+            };
+            let mut record = Record { artifact, history: vec![version], annotations: Vec::new() };
+            for (key, value) in annotations {
+                record.annotate(&key, Some(value), None, now);
+            }
+            self.index.records.push(record);
+            self.saved.changed.insert(position);
+            return;
+        };
+
+        let record = &mut self.index.records[position];
+        let mut annotated = false;
+        for (key, value) in annotations {
+            annotated |= record.annotate(&key, Some(value), None, now);
+        }
+        let current = &mut record.artifact;
+        let (old, new) = (&current.location, &artifact.location);
+        let moved = old.file_path != new.file_path || old.line_range != new.line_range || old.byte_range != new.byte_range
+            || current.artifact_type != artifact.artifact_type;
+        let mut changed = annotated || moved || old.commit != new.commit;
+        if current.location.file_path != artifact.location.file_path {
+            if let Some(positions) = self.by_path.get_mut(&current.location.file_path) {
+                positions.remove(&position);
+                if positions.is_empty() {
+                    self.by_path.remove(&current.location.file_path);
+                }
+            }
+            self.by_path.entry(artifact.location.file_path.clone()).or_default().insert(position);
+        }
+        for tag in artifact.metadata.tags {
+            if !current.metadata.tags.contains(&tag) {
+                current.metadata.tags.push(tag);
+                changed = true;
+            }
+        }
+        if moved {
+            record.history.push(ArtifactVersion {
+                version: record.history.len() as u32 + 1,
+                artifact_type: artifact.artifact_type.clone(),
+                location: artifact.location.clone(),
+                recorded_at: now,
+            });
+            current.artifact_type = artifact.artifact_type;
+        }
+        current.location = artifact.location;
+        if changed {
+            current.metadata.last_modified = now;
+            self.saved.changed.insert(position);
+        }
+    }
+
+    /// Tracked artifacts matching `query`, in the order they were first tracked
+    pub async fn query_artifacts(&self, query: ArtifactQuery) -> Result<Vec<Artifact>> {
+        let positions: Vec<usize> = match &query.path {
+            Some(path) => {
+                let path = path.trim_matches('/');
+                let from = (Bound::Included(path), Bound::Unbounded);
+                let candidates: BTreeSet<usize> = self.by_path.range::<str, _>(from)
+                    .take_while(|(file, _)| file.starts_with(path))
+                    .filter(|(file, _)| within(file, path))
+                    .flat_map(|(_, positions)| positions.iter().copied())
+                    .collect();
+                candidates.into_iter().collect()
+            }
+            None => (0..self.index.records.len()).collect(),
+        };
+        Ok(positions.into_iter()
+            .map(|position| &self.index.records[position].artifact)
+            .filter(|artifact| query.matches(artifact))
+            .cloned()
+            .collect())
+    }
+
+    /// Every version of the artifact, oldest first
+    pub async fn get_artifact_history(&self, id: &ArtifactId) -> Result<Vec<ArtifactVersion>> {
+        match self.positions.get(id) {
+            Some(&position) => Ok(self.index.records[position].history.clone()),
+            None => Err(IdentityError::UnknownArtifact(*id)),
+        }
+    }
+
+    /// Check values of the annotation `key` against `schema` from now on,
+    /// replacing any schema it had; refused when the schema does not compile or
+    /// a value already stored under `key` is not valid against it
+    pub async fn register_annotation_schema(&mut self, key: &str, schema: Value) -> Result<()> {
+        self.update(|tracker| {
+            let validator = validator(key, &schema)?;
+            for record in &tracker.index.records {
+                if let Some(value) = record.artifact.metadata.annotations.get(key) {
+                    check(&validator, key, value)?;
+                }
+            }
+            tracker.schemas.insert(key, validator);
+            tracker.index.schemas.insert(key.to_string(), schema.clone());
+            tracker.saved.schemas = false;
+            Ok(())
+        }).await
+    }
+
+    /// Stop checking values of the annotation `key`
+    pub async fn remove_annotation_schema(&mut self, key: &str) -> Result<()> {
+        self.update(|tracker| {
+            tracker.schemas.remove(key);
+            if tracker.index.schemas.remove(key).is_some() {
+                tracker.saved.schemas = false;
+            }
+            Ok(())
+        }).await
+    }
+
+    /// The schema registered for the annotation `key`, if any
+    pub fn annotation_schema(&self, key: &str) -> Option<&Value> {
+        self.index.schemas.get(key)
+    }
+
+    /// Set the annotation `key` of the artifact to `value`, on behalf of
+    /// `author`; the value must be valid against the key's schema, if it has one
+    pub async fn set_annotation(&mut self, id: &ArtifactId, key: &str, value: Value, author: &str) -> Result<()> {
+        self.annotate(id, key, Some(value), author).await.map(|_| ())
+    }
+
+    /// Remove the annotation `key` of the artifact, on behalf of `author`;
+    /// returns the value it had
+    pub async fn remove_annotation(&mut self, id: &ArtifactId, key: &str, author: &str) -> Result<Option<Value>> {
+        self.annotate(id, key, None, author).await
+    }
+
+    /// Set or remove an annotation, returning the value it had
+    async fn annotate(&mut self, id: &ArtifactId, key: &str, value: Option<Value>, author: &str) -> Result<Option<Value>> {
+        self.update(|tracker| {
+            if let Some(value) = &value {
+                tracker.schemas.check(key, value)?;
+            }
+            let &position = tracker.positions.get(id).ok_or(IdentityError::UnknownArtifact(*id))?;
+            let record = &mut tracker.index.records[position];
+            let old = record.artifact.metadata.annotations.get(key).cloned();
+            let now = Utc::now();
+            if record.annotate(key, value.clone(), Some(author), now) {
+                record.artifact.metadata.last_modified = now;
+                tracker.saved.changed.insert(position);
+            }
+            Ok(old)
+        }).await
+    }
+
+    /// Every change to the artifact's annotations, oldest first; only those to
+    /// `key` if given
+    pub async fn get_annotation_history(&self, id: &ArtifactId, key: Option<&str>) -> Result<Vec<AnnotationChange>> {
+        let &position = self.positions.get(id).ok_or(IdentityError::UnknownArtifact(*id))?;
+        Ok(self.index.records[position].annotations.iter()
+            .filter(|change| key.is_none_or(|key| change.key == key))
+            .cloned()
+            .collect())
+    }
+
+    /// Record that the artifact `target` is used at `location`, from within
+    /// `source` if given
+    pub async fn record_reference(
+        &mut self,
+        target: ArtifactId,
+        source: Option<ArtifactId>,
+        location: ArtifactLocation,
+    ) -> Result<()> {
+        let reference = ArtifactReference { target, source, location };
+        self.update(|tracker| {
+            for id in std::iter::once(target).chain(source) {
+                if !tracker.positions.contains_key(&id) {
+                    return Err(IdentityError::UnknownArtifact(id));
+                }
+            }
+            let known = tracker.references_to.get(&target)
+                .is_some_and(|positions| positions.iter().any(|&p| tracker.index.references[p] == reference));
+            if !known {
+                tracker.references_to.entry(target).or_default().push(tracker.index.references.len());
+                tracker.index.references.push(reference.clone());
+            }
+            Ok(())
+        }).await
+    }
+
+    /// Places that use the artifact, in the order they were recorded
+    pub async fn find_artifact_references(&self, id: &ArtifactId) -> Result<Vec<ArtifactReference>> {
+        if !self.positions.contains_key(id) {
+            return Err(IdentityError::UnknownArtifact(*id));
+        }
+        Ok(self.references_to.get(id)
+            .map(|positions| positions.iter().map(|&p| self.index.references[p].clone()).collect())
+            .unwrap_or_default())
+    }
+
+    /// Find the artifacts in indexed commits not handled yet, with `extractors`;
+    /// returns how many commits were handled
+    pub async fn index_commits(&mut self, extractors: &ExtractorRegistry) -> Result<usize> {
+        let Some(graph) = CommitGraphIndex::load(self.storage.as_ref()).await? else {
+            return Ok(0);
+        };
+        let mut trees = HashMap::new();
+        loop {
+            self.refresh().await?;
+            let mut count = 0;
+            for node in graph.nodes() {
+                if self.indexed.contains(&node.id) {
+                    continue;
+                }
+                let parents: Vec<ObjectId> = node.parents.iter().map(|&p| graph.nodes()[p as usize].id).collect();
+                self.index_commit(extractors, node.id, &parents, &mut trees).await?;
+                self.indexed.insert(node.id);
+                self.index.commits.push(node.id);
+                count += 1;
+            }
+            if self.save().await? {
+                return Ok(count);
+            }
+        }
+    }
+
+    async fn index_commit(
+        &mut self,
+        extractors: &ExtractorRegistry,
+        commit: ObjectId,
+        parents: &[ObjectId],
+        trees: &mut HashMap<ObjectId, ObjectId>,
+    ) -> Result<()> {
+        let storage = self.storage.clone();
+        let tree = tree_of(storage.as_ref(), commit, trees).await?;
+        let mut bases = Vec::new();
+        for parent in parents {
+            bases.push(Some(tree_of(storage.as_ref(), *parent, trees).await?));
+        }
+        if bases.is_empty() {
+            bases.push(None);
+        }
+
+        // Files changed from the first parent; the versions of them in any
+        // parent, as (path, blob, parent, whether it is the first); and those
+        // the same as in a later parent, whose changes were recorded there
+        let mut changed = BTreeMap::new();
+        let mut previous = Vec::new();
+        let mut inherited = HashSet::new();
+        for (n, base) in bases.iter().enumerate() {
+            let changes = gitnext_merge::diff_trees(storage.as_ref(), base.as_ref(), Some(&tree), None).await?;
+            if n > 0 {
+                let paths: HashSet<&str> = changes.iter().map(TreeChange::path).collect();
+                inherited.extend(changed.keys().filter(|path: &&String| !paths.contains(path.as_str())).cloned());
+            }
+            for change in changes {
+                let (path, old, new) = match change {
+                    TreeChange::Added { path, new } => (path, None, Some(new)),
+                    TreeChange::Modified { path, old, new } => (path, Some(old), Some(new)),
+                    TreeChange::Deleted { path, old } => (path, Some(old), None),
+                    _ => continue,
+                };
+                match new {
+                    Some(new) if n == 0 => {
+                        changed.insert(path.clone(), new);
+                    }
+                    _ if n > 0 && !changed.contains_key(&path) => continue,
+                    _ => {}
+                }
+                if let Some(old) = old {
+                    previous.push((path, old.id, parents[n], n == 0));
+                }
+            }
+        }
+
+        // Versions already extracted are reused; the rest are extracted now
+        let mut reused = Vec::new();
+        let mut extracted = Vec::new();
+        for (path, version) in changed {
+            if matches!(version.mode, FileMode::Symlink | FileMode::Tree | FileMode::Gitlink) {
+                continue;
+            }
+            let Some(extractor) = extractors.for_path(&path) else {
+                continue;
+            };
+            if self.file_positions.contains_key(&(path.clone(), version.id)) {
+                reused.push((path, version.id));
+                continue;
+            }
+            let content = match storage.load_object(&version.id).await? {
+                Some(GitObject::Blob(blob)) => blob.content.unwrap_or_default(),
+                _ => return Err(StorageError::ObjectNotFound { id: version.id }.into()),
+            };
+            let artifacts = extractor.extract(&content)?;
+            extracted.push((path, version.id, extractor.similarity_threshold(), artifacts));
+        }
+
+        let (files, found, changes) = {
+            // Every artifact of the versions in the parents
+            let mut known = Vec::new();
+            for (path, blob, parent, first) in &previous {
+                if let Some(&position) = self.file_positions.get(&(path.clone(), *blob)) {
+                    for (id, artifact) in &self.index.files[position].artifacts {
+                        known.push((*id, Side::of(*parent, path, *blob, artifact), artifact, *first));
+                    }
+                }
+            }
+            // Artifacts now in the commit: (id, where, what, the known version it continues)
+            let mut current: Vec<(ArtifactId, Side, ExtractedArtifact, Option<usize>)> = Vec::new();
+            for (path, blob) in &reused {
+                for (id, artifact) in &self.index.files[self.file_positions[&(path.clone(), *blob)]].artifacts {
+                    current.push((*id, Side::of(commit, path, *blob, artifact), artifact.clone(), None));
+                }
+            }
+
+            // Match new artifacts against known ones not reused, each id once
+            let mut seen: HashSet<ArtifactId> = current.iter().map(|c| c.0).collect();
+            let pool: Vec<usize> = (0..known.len()).filter(|&i| seen.insert(known[i].0)).collect();
+            let old: Vec<Candidate> = pool.iter()
+                .map(|&i| Candidate { path: &known[i].1.path, artifact: known[i].2, threshold: 0.0 })
+                .collect();
+            let new: Vec<Candidate> = extracted.iter()
+                .flat_map(|(path, _, threshold, artifacts)| {
+                    artifacts.iter().map(|artifact| Candidate { path, artifact, threshold: *threshold })
+                })
+                .collect();
+            let mut matches = match_artifacts(&old, &new).into_iter().map(|m| m.map(|i| pool[i]));
+
+            let mut files = Vec::new();
+            for (path, blob, _, artifacts) in extracted {
+                let mut ids = Vec::new();
+                for artifact in artifacts {
+                    let matched = matches.next().expect("a match per artifact");
+                    let id = matched.map_or_else(ArtifactId::new, |i| known[i].0);
+                    current.push((id, Side::of(commit, &path, blob, &artifact), artifact.clone(), matched));
+                    ids.push((id, artifact));
+                }
+                files.push(FileArtifacts { path, blob, artifacts: ids });
+            }
+
+            // Changes: artifacts differing from every parent's version, and those gone
+            let mut changes = Vec::new();
+            for (id, side, artifact, matched) in &current {
+                if inherited.contains(&side.path) {
+                    continue;
+                }
+                let mut versions = known.iter().enumerate().filter(|(_, k)| k.0 == *id);
+                let unchanged = versions.clone().any(|(_, (_, old, old_artifact, _))| {
+                    old.path == side.path && old.name == side.name && same_body(old_artifact, artifact)
+                });
+                if unchanged {
+                    continue;
+                }
+                let old = matched.or_else(|| versions.next().map(|(i, _)| i)).map(|i| &known[i]);
+                changes.push(ChangeRecord {
+                    artifact: *id,
+                    commit,
+                    old: old.map(|k| k.1.clone()),
+                    new: Some(side.clone()),
+                    body_changed: old.is_none_or(|k| !same_body(k.2, artifact)),
+                });
+            }
+            let present: HashSet<ArtifactId> = current.iter().map(|c| c.0).collect();
+            for &i in &pool {
+                let (id, side, _, first) = &known[i];
+                if *first && !present.contains(id) {
+                    changes.push(ChangeRecord { artifact: *id, commit, old: Some(side.clone()), new: None, body_changed: true });
+                }
+            }
+            let found: Vec<(ArtifactId, Side, ExtractedArtifact)> =
+                current.into_iter().map(|(id, side, artifact, _)| (id, side, artifact)).collect();
+            (files, found, changes)
+        };
+
+        for file in files {
+            self.file_positions.insert((file.path.clone(), file.blob), self.index.files.len());
+            self.index.files.push(file);
+        }
+        for change in changes {
+            let position = self.index.changes.len();
+            self.changes_by_artifact.entry(change.artifact).or_default().push(position);
+            self.changes_by_commit.entry(change.commit).or_default().push(position);
+            self.index.changes.push(change);
+        }
+        for (id, side, artifact) in found {
+            self.track(Artifact {
+                stable_id: id,
+                artifact_type: artifact.artifact_type,
+                location: side.location(),
+                metadata: ArtifactMetadata::new(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Commits that changed the artifact, oldest first
+    pub async fn get_artifact_provenance(&self, id: &ArtifactId) -> Result<Vec<ArtifactChange>> {
+        if !self.positions.contains_key(id) {
+            return Err(IdentityError::UnknownArtifact(*id));
+        }
+        self.describe(self.changes_by_artifact.get(id)).await
+    }
+
+    /// Artifacts the indexed commit `commit` changed, in path order
+    pub async fn find_commit_artifacts(&self, commit: &ObjectId) -> Result<Vec<ArtifactChange>> {
+        if !self.indexed.contains(commit) {
+            return Err(IdentityError::NotIndexed(*commit));
+        }
+        let mut changes = self.describe(self.changes_by_commit.get(commit)).await?;
+        let path = |c: &ArtifactChange| c.new.as_ref().or(c.old.as_ref()).map(|l| (l.file_path.clone(), l.line_range));
+        changes.sort_by_key(path);
+        Ok(changes)
+    }
+
+    async fn describe(&self, positions: Option<&Vec<usize>>) -> Result<Vec<ArtifactChange>> {
+        let mut loaded = Loaded::default();
+        let mut changes = Vec::new();
+        for &position in positions.into_iter().flatten() {
+            changes.push(loaded.change(self.storage.as_ref(), &self.index.changes[position]).await?);
+        }
+        Ok(changes)
+    }
+
+    /// Save what changed since the last save under a new manifest; false,
+    /// saving nothing, when someone else saved since
+    async fn save(&mut self) -> Result<bool> {
+        if !self.saved.behind(&self.index) {
+            return Ok(true);
+        }
+        let storage = self.storage.as_ref();
+        let index = &self.index;
+        let saved = &self.saved;
+        let mut manifest = saved.manifest.clone();
+        store_chunks::<Json, _>(storage, &index.records, saved.changed.iter().copied(), &mut manifest.records).await?;
+        store_chunks::<Json, _>(storage, &index.references, saved.references..index.references.len(), &mut manifest.references).await?;
+        store_chunks::<Json, _>(storage, &index.commits, saved.commits..index.commits.len(), &mut manifest.commits).await?;
+        store_chunks::<Json, _>(storage, &index.files, saved.files..index.files.len(), &mut manifest.files).await?;
+        store_chunks::<Json, _>(storage, &index.changes, saved.changes..index.changes.len(), &mut manifest.changes).await?;
+        if !saved.schemas {
+            manifest.schemas = Some(store_blob_content(storage, Json::encode(&index.schemas)?).await?);
+        }
+        let Some(id) = swap_blob(storage, ARTIFACTS_REF, saved.id, &manifest).await? else {
+            return Ok(false);
+        };
+        self.saved = Saved::all(&self.index, Some(id), manifest);
+        Ok(true)
+    }
+}
+
+/// Whether the two have the same signature and body
+fn same_body(a: &ExtractedArtifact, b: &ExtractedArtifact) -> bool {
+    a.fingerprint.signature == b.fingerprint.signature && a.fingerprint.content == b.fingerprint.content
+}
+
+/// Tree of `commit`, remembered in `trees`
+async fn tree_of(storage: &dyn Storage, commit: ObjectId, trees: &mut HashMap<ObjectId, ObjectId>) -> Result<ObjectId> {
+    if let Some(tree) = trees.get(&commit) {
+        return Ok(*tree);
+    }
+    match storage.load_object(&commit).await? {
+        Some(GitObject::Commit(c)) => {
+            trees.insert(commit, c.tree);
+            Ok(c.tree)
+        }
+        _ => Err(StorageError::ObjectNotFound { id: commit }.into()),
+    }
+}
+
+/// The index `ARTIFACTS_REF` names, empty if there is none
+async fn load(storage: &dyn Storage) -> Result<(ArtifactIndex, Saved)> {
+    let Some((manifest, id)) = load_versioned::<Manifest>(storage, ARTIFACTS_REF).await? else {
+        let index = ArtifactIndex::default();
+        let saved = Saved::all(&index, None, Manifest::default());
+        return Ok((index, saved));
+    };
+    let schemas = match &manifest.schemas {
+        Some(schemas) => Json::decode(&load_blob_content_at(storage, schemas).await?)?,
+        None => BTreeMap::new(),
+    };
+    let index = ArtifactIndex {
+        records: load_chunk_items::<Json, _>(storage, &manifest.records).await?,
+        references: load_chunk_items::<Json, _>(storage, &manifest.references).await?,
+        commits: load_chunk_items::<Json, _>(storage, &manifest.commits).await?,
+        files: load_chunk_items::<Json, _>(storage, &manifest.files).await?,
+        changes: load_chunk_items::<Json, _>(storage, &manifest.changes).await?,
+        schemas,
+    };
+    let saved = Saved::all(&index, Some(id), manifest);
+    Ok((index, saved))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{ArtifactKind, ArtifactType};
+    use gitnext_core::{Blob, Commit, ObjectType, Signature, Tree, TreeEntry};
+    use gitnext_storage::MemoryStorage;
+    use proptest::prelude::*;
+
+    /// Store a commit of `files` (path, content) with `parents`; its time is
+    /// `time`, which keeps otherwise equal commits apart
+    pub(crate) async fn store_commit(
+        storage: &dyn Storage,
+        parents: &[ObjectId],
+        files: &[(&str, &str)],
+        time: i64,
+    ) -> ObjectId {
+        let files: BTreeMap<String, String> = files.iter().map(|(p, c)| (p.to_string(), c.to_string())).collect();
+        let tree = store_tree(storage, &files).await;
+        let signature = Signature {
+            name: "Alice".to_string(),
+            email: "alice@example.com".to_string(),
+            timestamp: time,
+            timezone_offset: 0,
+            raw: None,
+        };
+        let commit = GitObject::Commit(Commit {
+            tree,
+            parents: parents.to_vec(),
+            author: signature.clone(),
+            committer: signature,
+            extra_headers: Vec::new(),
+            message: format!("Commit at {}\n", time),
+            raw_message: None,
+        });
+        let id = commit.canonical_hash();
+        storage.store_object(&id, &commit).await.unwrap();
+        gitnext_query::update_indexes(storage, [id]).await.unwrap();
+        id
+    }
+
+    async fn store_tree(storage: &dyn Storage, files: &BTreeMap<String, String>) -> ObjectId {
+        let mut entries = Vec::new();
+        let mut dirs: BTreeMap<&str, BTreeMap<String, String>> = BTreeMap::new();
+        for (path, content) in files {
+            if let Some((dir, rest)) = path.split_once('/') {
+                dirs.entry(dir).or_default().insert(rest.to_string(), content.clone());
+                continue;
+            }
+            let blob = GitObject::Blob(Blob::new(bytes::Bytes::from(content.clone())));
+            let hash = blob.canonical_hash();
+            storage.store_object(&hash, &blob).await.unwrap();
+            entries.push(TreeEntry { name: path.clone(), mode: FileMode::Normal, hash, entry_type: ObjectType::Blob, raw_name: None });
+        }
+        for (name, files) in dirs {
+            let hash = Box::pin(store_tree(storage, &files)).await;
+            entries.push(TreeEntry { name: name.to_string(), mode: FileMode::Tree, hash, entry_type: ObjectType::Tree, raw_name: None });
+        }
+        let tree = GitObject::Tree(Tree::new(entries));
+        let id = tree.canonical_hash();
+        storage.store_object(&id, &tree).await.unwrap();
+        id
+    }
+
+    fn commit(n: u8) -> ObjectId {
+        ObjectId::from_blake3_bytes([n; 32])
+    }
+
+    fn function(signature: &str) -> ArtifactType {
+        ArtifactType::Function { signature: signature.to_string(), language: "rust".to_string() }
+    }
+
+    fn at(n: u8, path: &str, lines: (u32, u32)) -> ArtifactLocation {
+        ArtifactLocation { line_range: Some(lines), ..ArtifactLocation::new(commit(n), path) }
+    }
+
+    #[tokio::test]
+    async fn test_track_and_query() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let mut tracker = ArtifactTracker::open(storage.clone()).await.unwrap();
+        assert!(tracker.is_empty());
+
+        let mut parse = Artifact::new(function("fn parse(input: &str)"), at(1, "src/parser.rs", (10, 20)));
+        parse.metadata.tags.push("core".to_string());
+        let config = Artifact::new(
+            ArtifactType::Type { name: "Config".to_string(), language: "rust".to_string() },
+            at(1, "src/config.rs", (1, 8)),
+        );
+        let docs = Artifact::new(
+            ArtifactType::Custom { type_name: "diagram".to_string(), data: serde_json::json!({"format": "svg"}) },
+            ArtifactLocation::new(commit(1), "srcs/diagram.svg"),
+        );
+        tracker.track_artifacts(vec![parse.clone(), config.clone(), docs.clone()]).await.unwrap();
+        assert_eq!(tracker.len(), 3);
+
+        // Moved to another file and renamed, then annotated without moving
+        let mut moved = parse.clone();
+        moved.artifact_type = function("fn parse_input(input: &str)");
+        moved.location = at(2, "src/parse/mod.rs", (3, 13));
+        moved.metadata.tags = vec!["core".to_string(), "hot".to_string()];
+        tracker.track_artifact(moved.clone()).await.unwrap();
+        let mut annotated = moved.clone();
+        annotated.metadata.annotations.insert("owner".to_string(), serde_json::json!("parsing"));
+        tracker.track_artifact(annotated).await.unwrap();
+
+        let current = tracker.get_artifact(&parse.stable_id).unwrap();
+        assert_eq!(current.location, moved.location);
+        assert_eq!(current.metadata.tags, vec!["core", "hot"]);
+        assert_eq!(current.metadata.annotations["owner"], "parsing");
+        assert_eq!(current.metadata.created_at, parse.metadata.created_at);
+        let history = tracker.get_artifact_history(&parse.stable_id).await.unwrap();
+        assert_eq!(history.iter().map(|v| v.version).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(history[0].location, parse.location);
+        assert_eq!(history[1].artifact_type, moved.artifact_type);
+
+        let ids = |artifacts: Vec<Artifact>| artifacts.into_iter().map(|a| a.stable_id).collect::<Vec<_>>();
+        let query = |q: ArtifactQuery| tracker.query_artifacts(q);
+        assert_eq!(ids(query(ArtifactQuery::default()).await.unwrap()).len(), 3);
+        let in_src = ArtifactQuery { path: Some("src/".to_string()), ..Default::default() };
+        assert_eq!(ids(query(in_src).await.unwrap()), vec![parse.stable_id, config.stable_id]);
+        let old_path = ArtifactQuery { path: Some("src/parser.rs".to_string()), ..Default::default() };
+        assert!(query(old_path).await.unwrap().is_empty());
+        let functions = ArtifactQuery { kind: Some(ArtifactKind::Function), name: Some("parse_".to_string()), ..Default::default() };
+        assert_eq!(ids(query(functions).await.unwrap()), vec![parse.stable_id]);
+        let tagged = ArtifactQuery { tag: Some("hot".to_string()), commit: Some(commit(2)), ..Default::default() };
+        assert_eq!(ids(query(tagged).await.unwrap()), vec![parse.stable_id]);
+        let rust = ArtifactQuery { language: Some("rust".to_string()), ..Default::default() };
+        assert_eq!(query(rust).await.unwrap().len(), 2);
+
+        tracker.record_reference(config.stable_id, Some(parse.stable_id), at(2, "src/parse/mod.rs", (5, 5))).await.unwrap();
+        tracker.record_reference(config.stable_id, None, at(2, "src/main.rs", (1, 1))).await.unwrap();
+        tracker.record_reference(config.stable_id, None, at(2, "src/main.rs", (1, 1))).await.unwrap();
+        let references = tracker.find_artifact_references(&config.stable_id).await.unwrap();
+        assert_eq!(references.len(), 2);
+        assert_eq!(references[0].source, Some(parse.stable_id));
+        assert!(tracker.find_artifact_references(&parse.stable_id).await.unwrap().is_empty());
+
+        let unknown = ArtifactId::new();
+        assert!(matches!(tracker.get_artifact_history(&unknown).await, Err(IdentityError::UnknownArtifact(id)) if id == unknown));
+        assert!(tracker.record_reference(unknown, None, at(2, "x", (1, 1))).await.is_err());
+
+        // Everything survives reopening, and only the lists that were used were written
+        let reopened = ArtifactTracker::open(storage.clone()).await.unwrap();
+        assert_eq!(reopened.get_artifact(&parse.stable_id), tracker.get_artifact(&parse.stable_id));
+        assert_eq!(reopened.get_artifact(&docs.stable_id).unwrap().artifact_type, docs.artifact_type);
+        assert_eq!(reopened.get_artifact_history(&parse.stable_id).await.unwrap(), history);
+        assert_eq!(reopened.find_artifact_references(&config.stable_id).await.unwrap(), references);
+        let refs: Vec<String> = storage.list_refs().await.unwrap().into_iter().map(|r| r.name).collect();
+        assert_eq!(refs, vec![ARTIFACTS_REF]);
+        let manifest = &reopened.saved.manifest;
+        assert_eq!((manifest.records.len(), manifest.references.len()), (1, 1));
+        assert!(manifest.commits.is_empty() && manifest.files.is_empty() && manifest.changes.is_empty());
+        assert_eq!(manifest.schemas, None);
+    }
+
+    #[tokio::test]
+    async fn test_save_writes_only_changed_chunks() {
+        use gitnext_storage::metadata::CHUNK_LEN;
+
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let mut tracker = ArtifactTracker::open(storage.clone()).await.unwrap();
+        let artifacts: Vec<Artifact> = (0..CHUNK_LEN + 1)
+            .map(|n| Artifact::new(function(&format!("fn f{}()", n)), at(1, "src/lib.rs", (n as u32, n as u32))))
+            .collect();
+        tracker.track_artifacts(artifacts.clone()).await.unwrap();
+        let before = tracker.saved.manifest.records.clone();
+        assert_eq!(before.len(), 2);
+
+        tracker.set_annotation(&artifacts[CHUNK_LEN].stable_id, "owner", serde_json::json!("core"), "alice").await.unwrap();
+        let after = tracker.saved.manifest.records.clone();
+        assert_eq!(after[0], before[0]);
+        assert_ne!(after[1], before[1]);
+        let reopened = ArtifactTracker::open(storage.clone()).await.unwrap();
+        assert_eq!(reopened.len(), CHUNK_LEN + 1);
+        assert_eq!(reopened.get_artifact(&artifacts[CHUNK_LEN].stable_id).unwrap().metadata.annotations["owner"], "core");
+    }
+
+    #[tokio::test]
+    async fn test_trackers_sharing_storage() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let parse = Artifact::new(function("fn parse()"), at(1, "src/lib.rs", (1, 3)));
+        let mut one = ArtifactTracker::open(storage.clone()).await.unwrap();
+        one.track_artifact(parse.clone()).await.unwrap();
+
+        // Both open the same artifacts, then change them in turn
+        let mut other = ArtifactTracker::open(storage.clone()).await.unwrap();
+        one.set_annotation(&parse.stable_id, "owner", serde_json::json!("core"), "alice").await.unwrap();
+        other.set_annotation(&parse.stable_id, "review", serde_json::json!("done"), "bob").await.unwrap();
+        let config = Artifact::new(function("fn config()"), at(1, "src/config.rs", (1, 3)));
+        one.track_artifact(config.clone()).await.unwrap();
+
+        let reopened = ArtifactTracker::open(storage.clone()).await.unwrap();
+        let annotations = &reopened.get_artifact(&parse.stable_id).unwrap().metadata.annotations;
+        assert_eq!((&annotations["owner"], &annotations["review"]), (&serde_json::json!("core"), &serde_json::json!("done")));
+        let history = reopened.get_annotation_history(&parse.stable_id, None).await.unwrap();
+        assert_eq!(history.iter().map(|c| c.key.as_str()).collect::<Vec<_>>(), vec!["owner", "review"]);
+        assert_eq!(reopened.len(), 2);
+        assert!(one.get_artifact(&parse.stable_id).unwrap().metadata.annotations.contains_key("review"));
+
+        // A tracker that loses the race makes its change again on the winner's
+        let mut stale = ArtifactTracker::open(storage.clone()).await.unwrap();
+        other.set_annotation(&config.stable_id, "owner", serde_json::json!("config"), "bob").await.unwrap();
+        stale.index.records[0].artifact.metadata.tags.push("unsaved".to_string());
+        stale.saved.changed.insert(0);
+        assert!(!stale.save().await.unwrap());
+        stale.remove_annotation(&parse.stable_id, "review", "carol").await.unwrap();
+        let reopened = ArtifactTracker::open(storage.clone()).await.unwrap();
+        assert_eq!(reopened.get_artifact(&config.stable_id).unwrap().metadata.annotations["owner"], "config");
+        assert!(!reopened.get_artifact(&parse.stable_id).unwrap().metadata.annotations.contains_key("review"));
+    }
+
+    /// Id of the artifact whose qualified name is `name`
+    fn id_of(tracker: &ArtifactTracker, name: &str) -> ArtifactId {
+        let found: Vec<ArtifactId> = tracker.index.files.iter()
+            .flat_map(|file| &file.artifacts)
+            .filter(|(_, artifact)| artifact.fingerprint.name == name)
+            .map(|(id, _)| *id)
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        assert_eq!(found.len(), 1, "one artifact named {}", name);
+        found[0]
+    }
+
+    #[tokio::test]
+    async fn test_index_commits() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let s = storage.as_ref();
+        let total = "fn total(items: &[Item]) -> u64 {\n    items.iter().map(|item| item.price * item.count).sum::<u64>() + SHIPPING\n}\n";
+        let helper = "fn helper() -> u32 {\n    1\n}\n";
+        let app = "class App:\n    def run(self):\n        return 0\n";
+        let lib = format!("{}\n{}", helper, total);
+        let c1 = store_commit(s, &[], &[("src/lib.rs", &lib), ("app.py", app), ("README", "hi")], 1).await;
+
+        let mut tracker = ArtifactTracker::open(storage.clone()).await.unwrap();
+        let extractors = ExtractorRegistry::new();
+        assert_eq!(tracker.index_commits(&extractors).await.unwrap(), 1);
+        assert_eq!(tracker.len(), 4);
+        let (total_id, helper_id, run_id) = (id_of(&tracker, "total"), id_of(&tracker, "helper"), id_of(&tracker, "App.run"));
+        let location = &tracker.get_artifact(&total_id).unwrap().location;
+        assert_eq!((location.file_path.as_str(), location.line_range, location.commit), ("src/lib.rs", Some((5, 7)), c1));
+
+        // `total` moves to another file as `sum`, and `helper` changes its signature
+        let sum = total.replace("fn total", "fn sum");
+        let c2_files = [("src/lib.rs", "fn helper(n: u32) -> u32 {\n    n\n}\n"), ("src/math.rs", &sum), ("app.py", app)];
+        let c2 = store_commit(s, &[c1], &c2_files, 2).await;
+        assert_eq!(tracker.index_commits(&extractors).await.unwrap(), 1);
+        assert_eq!(tracker.len(), 4);
+        assert_eq!(id_of(&tracker, "sum"), total_id);
+        assert_eq!(id_of(&tracker, "helper"), helper_id);
+        let history = tracker.get_artifact_history(&total_id).await.unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!((history[0].location.commit, history[1].location.commit), (c1, c2));
+        assert_eq!(history[1].location.file_path, "src/math.rs");
+        assert_eq!(history[1].artifact_type.name(), "fn sum(items: &[Item]) -> u64");
+        // app.py did not change, so it was not looked at again
+        assert_eq!(tracker.get_artifact(&run_id).unwrap().location.commit, c1);
+
+        // A branch adds a method; merging it in reuses what was found there
+        let app2 = format!("{}    def stop(self):\n        return 1\n", app);
+        let topic = store_commit(s, &[c2], &[c2_files[0], c2_files[1], ("app.py", &app2)], 3).await;
+        let main = store_commit(s, &[c2], &[c2_files[0], c2_files[1], ("app.py", app), ("src/new.rs", "struct New;\n")], 4).await;
+        let merge_files = [c2_files[0], c2_files[1], ("app.py", &app2), ("src/new.rs", "struct New;\n")];
+        let merge = store_commit(s, &[main, topic], &merge_files, 5).await;
+        let mut reopened = ArtifactTracker::open(storage.clone()).await.unwrap();
+        assert_eq!(reopened.index_commits(&extractors).await.unwrap(), 3);
+        assert_eq!(reopened.len(), 6);
+        let stop = reopened.get_artifact(&id_of(&reopened, "App.stop")).unwrap();
+        assert_eq!(stop.location.commit, merge);
+        assert_eq!(reopened.get_artifact_history(&stop.stable_id).await.unwrap()[0].location.commit, topic);
+        assert_eq!(id_of(&reopened, "App.run"), run_id);
+        assert_eq!(reopened.index_commits(&extractors).await.unwrap(), 0);
+
+        let none = ArtifactTracker::open(Arc::new(MemoryStorage::new())).await.unwrap().index_commits(&extractors).await;
+        assert_eq!(none.unwrap(), 0);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(50))]
+
+        /// Property 29: Object ID Stability
+        /// For any sequence of moves, renames and re-tracking of an artifact,
+        /// its stable id keeps finding it at its latest location, with one
+        /// version per change of type, path or range, after the tracker is
+        /// reopened as well.
+        /// **Validates: Requirements 11.1**
+        #[test]
+        fn prop_object_id_stability(
+            moves in prop::collection::vec((0u8..4, 0usize..3, 0usize..3, 1u32..50), 1..12),
+        ) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+                let mut tracker = ArtifactTracker::open(storage.clone()).await.unwrap();
+                let paths = ["src/a.rs", "src/b/c.rs", "lib.rs"];
+                let names = ["fn f()", "fn g()", "fn h(x: u32)"];
+                let other = Artifact::new(function("fn other()"), at(0, "other.rs", (1, 1)));
+                tracker.track_artifact(other.clone()).await.unwrap();
+
+                let mut artifact = Artifact::new(function(names[0]), at(0, paths[0], (1, 1)));
+                let id = artifact.stable_id;
+                let mut expected = Vec::new();
+                for (commit, path, name, line) in moves {
+                    artifact.artifact_type = function(names[name]);
+                    artifact.location = at(commit, paths[path], (line, line + 5));
+                    // Seeing it again at another commit is not a new version
+                    let state = (artifact.artifact_type.clone(), artifact.location.file_path.clone(), artifact.location.line_range);
+                    if expected.last() != Some(&state) {
+                        expected.push(state);
+                    }
+                    tracker.track_artifact(artifact.clone()).await.unwrap();
+                    prop_assert_eq!(&tracker.get_artifact(&id).unwrap().location, &artifact.location);
+                }
+
+                let reopened = ArtifactTracker::open(storage.clone()).await.unwrap();
+                for tracker in [&tracker, &reopened] {
+                    prop_assert_eq!(tracker.len(), 2);
+                    let history = tracker.get_artifact_history(&id).await.unwrap();
+                    let versions: Vec<_> = history.iter()
+                        .map(|v| (v.artifact_type.clone(), v.location.file_path.clone(), v.location.line_range))
+                        .collect();
+                    prop_assert_eq!(&versions, &expected);
+                    prop_assert!(history.iter().enumerate().all(|(i, v)| v.version as usize == i + 1));
+                    prop_assert_eq!(&tracker.get_artifact(&other.stable_id).unwrap().location, &other.location);
+                    let query = ArtifactQuery { path: Some(artifact.location.file_path.clone()), ..Default::default() };
+                    let found: Vec<ArtifactId> = tracker.query_artifacts(query).await.unwrap().into_iter().map(|a| a.stable_id).collect();
+                    prop_assert_eq!(found, vec![id]);
+                }
+                Ok(())
+            })?;
+        }
+    }
+}
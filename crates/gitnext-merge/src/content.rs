@@ -0,0 +1,333 @@
+//! Three-way merge of file contents (diff3)
+//!
+//! Both sides are diffed against the base. Changes from one side only are taken
+//! as they are; changes from both sides that overlap or touch form a conflict
+//! unless they are identical. Like `git merge-file`, conflicts are narrowed to the
+//! lines the two sides disagree on, and binary contents are never merged line by
+//! line.
+
+use crate::diff::{diff_lines, split_lines, DiffHunk};
+use crate::rename::RenameOptions;
+use std::ops::Range;
+
+/// Length of Git's conflict markers
+pub const MARKER_SIZE: usize = 7;
+
+/// How much of a conflict the markers show
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictStyle {
+    /// Ours and theirs
+    #[default]
+    Merge,
+    /// Ours, the base and theirs (`merge.conflictStyle=diff3`)
+    Diff3,
+}
+
+/// Which side conflicting changes resolve to, instead of producing markers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergeFavor {
+    #[default]
+    None,
+    Ours,
+    Theirs,
+    /// Both sides, ours first
+    Union,
+}
+
+/// Labels and conflict handling for content merges
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    pub ours_label: String,
+    pub base_label: String,
+    pub theirs_label: String,
+    pub style: ConflictStyle,
+    pub favor: MergeFavor,
+    /// Rename detection for tree merges; `None` merges paths as they are
+    pub renames: Option<RenameOptions>,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        Self {
+            ours_label: "ours".to_string(),
+            base_label: "base".to_string(),
+            theirs_label: "theirs".to_string(),
+            style: ConflictStyle::default(),
+            favor: MergeFavor::default(),
+            renames: Some(RenameOptions::default()),
+        }
+    }
+}
+
+/// Result of merging one file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentMerge {
+    /// Merged content, with conflict markers where the sides disagree. Conflicting
+    /// binary content leaves ours unchanged.
+    pub content: Vec<u8>,
+    /// Number of conflict regions
+    pub conflicts: usize,
+}
+
+impl ContentMerge {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts == 0
+    }
+}
+
+/// Whether `content` looks binary, by Git's rule of a NUL in the first 8000 bytes
+pub fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(8000).any(|&b| b == 0)
+}
+
+/// One side's change, in base coordinates and its own
+struct Change {
+    ours: bool,
+    base: Range<usize>,
+    side: Range<usize>,
+}
+
+/// Merge `ours` and `theirs`, two versions of `base`
+pub fn merge_content(base: &[u8], ours: &[u8], theirs: &[u8], options: &MergeOptions) -> ContentMerge {
+    let clean = |content: &[u8]| ContentMerge { content: content.to_vec(), conflicts: 0 };
+    if ours == theirs || theirs == base {
+        return clean(ours);
+    }
+    if ours == base {
+        return clean(theirs);
+    }
+    if is_binary(base) || is_binary(ours) || is_binary(theirs) {
+        return match options.favor {
+            MergeFavor::Theirs => clean(theirs),
+            MergeFavor::Ours => clean(ours),
+            _ => ContentMerge { content: ours.to_vec(), conflicts: 1 },
+        };
+    }
+
+    let base_lines = split_lines(base);
+    let ours_lines = split_lines(ours);
+    let theirs_lines = split_lines(theirs);
+
+    let mut changes: Vec<Change> = Vec::new();
+    let hunk_change = |ours: bool| move |hunk: DiffHunk| Change { ours, base: hunk.old, side: hunk.new };
+    changes.extend(diff_lines(&base_lines, &ours_lines).into_iter().map(hunk_change(true)));
+    changes.extend(diff_lines(&base_lines, &theirs_lines).into_iter().map(hunk_change(false)));
+    changes.sort_by_key(|c| (c.base.start, c.base.end));
+
+    let mut output = Vec::new();
+    let mut conflicts = 0;
+    let mut position = 0;
+    // Offsets from base line numbers to each side's, before the current chunk
+    let (mut ours_offset, mut theirs_offset) = (0isize, 0isize);
+    let mut i = 0;
+    while i < changes.len() {
+        // A chunk is a run of changes that overlap or touch
+        let mut end = changes[i].base.end;
+        let mut j = i + 1;
+        while j < changes.len() && changes[j].base.start <= end {
+            end = end.max(changes[j].base.end);
+            j += 1;
+        }
+        let chunk = &changes[i..j];
+        let start = chunk[0].base.start;
+
+        for line in &base_lines[position..start] {
+            output.extend_from_slice(line);
+        }
+        let ours_range = side_range(chunk, true, start..end, ours_offset);
+        let theirs_range = side_range(chunk, false, start..end, theirs_offset);
+        ours_offset = ours_range.end as isize - end as isize;
+        theirs_offset = theirs_range.end as isize - end as isize;
+
+        let ours_chunk = &ours_lines[ours_range];
+        let theirs_chunk = &theirs_lines[theirs_range];
+        let base_chunk = &base_lines[start..end];
+        if !chunk.iter().any(|c| !c.ours) {
+            extend(&mut output, ours_chunk);
+        } else if !chunk.iter().any(|c| c.ours) || ours_chunk == theirs_chunk {
+            extend(&mut output, theirs_chunk);
+        } else {
+            match options.favor {
+                MergeFavor::Ours => extend(&mut output, ours_chunk),
+                MergeFavor::Theirs => extend(&mut output, theirs_chunk),
+                MergeFavor::Union => {
+                    extend_terminated(&mut output, ours_chunk);
+                    extend(&mut output, theirs_chunk);
+                }
+                MergeFavor::None => {
+                    write_conflict(&mut output, base_chunk, ours_chunk, theirs_chunk, options);
+                    conflicts += 1;
+                }
+            }
+        }
+        position = end;
+        i = j;
+    }
+    for line in &base_lines[position..] {
+        output.extend_from_slice(line);
+    }
+    ContentMerge { content: output, conflicts }
+}
+
+/// Lines of one side covering the base lines `chunk_base`; `offset` maps base
+/// line numbers before the chunk to the side's
+fn side_range(chunk: &[Change], ours: bool, chunk_base: Range<usize>, offset: isize) -> Range<usize> {
+    let mut side_changes = chunk.iter().filter(|c| c.ours == ours);
+    match side_changes.next() {
+        None => {
+            let start = (chunk_base.start as isize + offset) as usize;
+            start..start + chunk_base.len()
+        }
+        Some(first) => {
+            let last = side_changes.next_back().unwrap_or(first);
+            let start = first.side.start - (first.base.start - chunk_base.start);
+            let end = last.side.end + (chunk_base.end - last.base.end);
+            start..end
+        }
+    }
+}
+
+fn extend(output: &mut Vec<u8>, lines: &[&[u8]]) {
+    for line in lines {
+        output.extend_from_slice(line);
+    }
+}
+
+/// Append `lines`, ending them with a newline so that more can follow
+fn extend_terminated(output: &mut Vec<u8>, lines: &[&[u8]]) {
+    extend(output, lines);
+    if !output.is_empty() && !output.ends_with(b"\n") {
+        output.push(b'\n');
+    }
+}
+
+fn marker(output: &mut Vec<u8>, c: u8, label: &str) {
+    output.extend(std::iter::repeat_n(c, MARKER_SIZE));
+    if !label.is_empty() {
+        output.push(b' ');
+        output.extend_from_slice(label.as_bytes());
+    }
+    output.push(b'\n');
+}
+
+fn write_conflict(output: &mut Vec<u8>, base: &[&[u8]], ours: &[&[u8]], theirs: &[&[u8]], options: &MergeOptions) {
+    // Lines both sides agree on stay outside the markers, except in diff3 style,
+    // where the base would no longer line up
+    let (prefix, suffix) = if options.style == ConflictStyle::Merge {
+        let prefix = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+        let rest = ours.len().min(theirs.len()) - prefix;
+        let suffix = ours.iter().rev().zip(theirs.iter().rev()).take(rest).take_while(|(a, b)| a == b).count();
+        (prefix, suffix)
+    } else {
+        (0, 0)
+    };
+    extend(output, &ours[..prefix]);
+    if !output.is_empty() && !output.ends_with(b"\n") {
+        output.push(b'\n');
+    }
+
+    marker(output, b'<', &options.ours_label);
+    extend_terminated(output, &ours[prefix..ours.len() - suffix]);
+    if options.style == ConflictStyle::Diff3 {
+        marker(output, b'|', &options.base_label);
+        extend_terminated(output, base);
+    }
+    marker(output, b'=', "");
+    extend_terminated(output, &theirs[prefix..theirs.len() - suffix]);
+    marker(output, b'>', &options.theirs_label);
+
+    extend(output, &ours[ours.len() - suffix..]);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn merge(base: &str, ours: &str, theirs: &str) -> (String, usize) {
+        let result = merge_content(base.as_bytes(), ours.as_bytes(), theirs.as_bytes(), &MergeOptions::default());
+        (String::from_utf8(result.content).unwrap(), result.conflicts)
+    }
+
+    #[test]
+    fn test_clean_merges() {
+        let base = "a\nb\nc\nd\ne\nf\n";
+        assert_eq!(merge(base, "A\nb\nc\nd\ne\nf\n", "a\nb\nc\nd\ne\nF\n"), ("A\nb\nc\nd\ne\nF\n".to_string(), 0));
+        // Deletion on one side, insertion on the other
+        assert_eq!(merge(base, "a\nc\nd\ne\nf\n", "a\nb\nc\nd\ne\nf\ng\n"), ("a\nc\nd\ne\nf\ng\n".to_string(), 0));
+        // The same change on both sides
+        assert_eq!(merge(base, "a\nx\nc\nd\ne\nf\n", "a\nx\nc\nd\ne\nF\n"), ("a\nx\nc\nd\ne\nF\n".to_string(), 0));
+    }
+
+    #[test]
+    fn test_conflict_markers() {
+        let (merged, conflicts) = merge("a\nb\nc\n", "a\nours\nc\n", "a\ntheirs\nc\n");
+        assert_eq!(conflicts, 1);
+        assert_eq!(merged, "a\n<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\nc\n");
+
+        let options = MergeOptions {
+            ours_label: "HEAD".to_string(),
+            base_label: "merged common ancestors".to_string(),
+            theirs_label: "feature".to_string(),
+            style: ConflictStyle::Diff3,
+            ..MergeOptions::default()
+        };
+        let result = merge_content(b"a\nb\nc\n", b"a\nours\nc\n", b"a\ntheirs\nc\n", &options);
+        assert_eq!(
+            String::from_utf8(result.content).unwrap(),
+            "a\n<<<<<<< HEAD\nours\n||||||| merged common ancestors\nb\n=======\ntheirs\n>>>>>>> feature\nc\n"
+        );
+    }
+
+    #[test]
+    fn test_conflicts_are_narrowed_and_terminated() {
+        // Both sides append different lines after the same new line
+        let (merged, conflicts) = merge("a\n", "a\nsame\nmine\n", "a\nsame\nyours\n");
+        assert_eq!(conflicts, 1);
+        assert_eq!(merged, "a\nsame\n<<<<<<< ours\nmine\n=======\nyours\n>>>>>>> theirs\n");
+
+        // A last line without a newline still gets its marker on a line of its own
+        let (merged, _) = merge("a\nb", "a\nx", "a\ny");
+        assert_eq!(merged, "a\n<<<<<<< ours\nx\n=======\ny\n>>>>>>> theirs\n");
+    }
+
+    #[test]
+    fn test_favor_and_binary() {
+        let favor = |favor| MergeOptions { favor, ..MergeOptions::default() };
+        let result = merge_content(b"a\nb\nc\n", b"a\nx\nc\n", b"a\ny\nc\n", &favor(MergeFavor::Theirs));
+        assert_eq!((result.content.as_slice(), result.conflicts), (&b"a\ny\nc\n"[..], 0));
+        let result = merge_content(b"a\nb\nc\n", b"a\nx\nc\n", b"a\ny\nc\n", &favor(MergeFavor::Union));
+        assert_eq!(result.content, b"a\nx\ny\nc\n");
+
+        let result = merge_content(b"\0base", b"\0ours", b"\0theirs", &MergeOptions::default());
+        assert_eq!((result.content.as_slice(), result.conflicts), (&b"\0ours"[..], 1));
+    }
+
+    fn lines() -> impl Strategy<Value = Vec<String>> {
+        prop::collection::vec("[a-d]", 0..12)
+    }
+
+    proptest! {
+        /// Property 4: Merge Operation Correctness
+        /// Changes made on one side only are merged cleanly, and changes to
+        /// separate parts of a file are both kept.
+        /// **Validates: Requirements 1.5**
+        #[test]
+        fn prop_three_way_merge(base in lines(), ours in lines(), head in lines(), tail in lines()) {
+            let join = |lines: &[String]| lines.iter().map(|l| format!("{}\n", l)).collect::<String>();
+            let base_text = join(&base);
+            let ours_text = join(&ours);
+            prop_assert_eq!(merge(&base_text, &ours_text, &base_text), (ours_text.clone(), 0));
+            prop_assert_eq!(merge(&base_text, &base_text, &ours_text), (ours_text.clone(), 0));
+
+            // One side edits the start, the other the end, separated by a stable line
+            let wrap = |start: &[String], end: &[String]| {
+                format!("{}SEPARATOR\n{}", join(start), join(end))
+            };
+            let original = wrap(&base, &base);
+            let (merged, conflicts) = merge(&original, &wrap(&head, &base), &wrap(&base, &tail));
+            prop_assert_eq!(conflicts, 0);
+            prop_assert_eq!(merged, wrap(&head, &tail));
+        }
+    }
+}
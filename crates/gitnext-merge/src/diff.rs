@@ -0,0 +1,257 @@
+//! Myers line diff
+//!
+//! The linear-space variant of Myers' O(ND) algorithm: common prefixes and
+//! suffixes are stripped, then searching from both ends of each remaining region
+//! finds a point on an optimal path that splits it in two. Lines are interned
+//! first, so the algorithm compares integers.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// A changed region: `old` lines replaced by `new` lines. One side is empty for a
+/// pure insertion or deletion.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiffHunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Split `content` into lines, each keeping its `\n`; a final line may lack one
+pub fn split_lines(content: &[u8]) -> Vec<&[u8]> {
+    content.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Changed regions between two line sequences, in order
+pub fn diff_lines<'a>(old: &[&'a [u8]], new: &[&'a [u8]]) -> Vec<DiffHunk> {
+    let mut ids: HashMap<&'a [u8], u32> = HashMap::new();
+    let mut intern = |line: &'a [u8]| {
+        let next = ids.len() as u32;
+        *ids.entry(line).or_insert(next)
+    };
+    let old: Vec<u32> = old.iter().map(|line| intern(line)).collect();
+    let new: Vec<u32> = new.iter().map(|line| intern(line)).collect();
+    diff(&old, &new)
+}
+
+/// Changed regions between two sequences, in order
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<DiffHunk> {
+    let mut matches = Vec::new();
+    find_matches(old, 0..old.len(), new, 0..new.len(), &mut matches);
+
+    // Hunks are the gaps between runs of matching elements
+    let mut hunks = Vec::new();
+    let (mut x, mut y) = (0, 0);
+    for (old_start, new_start, len) in matches.into_iter().chain(std::iter::once((old.len(), new.len(), 0))) {
+        if old_start > x || new_start > y {
+            hunks.push(DiffHunk { old: x..old_start, new: y..new_start });
+        }
+        x = old_start + len;
+        y = new_start + len;
+    }
+    hunks
+}
+
+/// Append the runs `(old_start, new_start, len)` of matching elements in the given
+/// regions, in order
+fn find_matches<T: PartialEq>(
+    a: &[T],
+    mut a_range: Range<usize>,
+    b: &[T],
+    mut b_range: Range<usize>,
+    matches: &mut Vec<(usize, usize, usize)>,
+) {
+    let prefix = a[a_range.clone()].iter().zip(&b[b_range.clone()]).take_while(|(x, y)| x == y).count();
+    if prefix > 0 {
+        push_match(matches, a_range.start, b_range.start, prefix);
+        a_range.start += prefix;
+        b_range.start += prefix;
+    }
+    let suffix = a[a_range.clone()].iter().rev().zip(b[b_range.clone()].iter().rev()).take_while(|(x, y)| x == y).count();
+    a_range.end -= suffix;
+    b_range.end -= suffix;
+
+    if !a_range.is_empty() && !b_range.is_empty() {
+        if let Some((x, y)) = bisect(&a[a_range.clone()], &b[b_range.clone()]) {
+            let (x, y) = (x + a_range.start, y + b_range.start);
+            find_matches(a, a_range.start..x, b, b_range.start..y, matches);
+            find_matches(a, x..a_range.end, b, y..b_range.end, matches);
+        }
+    }
+
+    if suffix > 0 {
+        push_match(matches, a_range.end, b_range.end, suffix);
+    }
+}
+
+/// Record a run, merging it into the previous one when they are contiguous
+fn push_match(matches: &mut Vec<(usize, usize, usize)>, a: usize, b: usize, len: usize) {
+    if let Some(last) = matches.last_mut() {
+        if last.0 + last.2 == a && last.1 + last.2 == b {
+            last.2 += len;
+            return;
+        }
+    }
+    matches.push((a, b, len));
+}
+
+/// A point `(x, y)` on an optimal path from `(0, 0)` to `(a.len(), b.len())`, found
+/// where the forward and backward searches meet; `None` when nothing matches
+fn bisect<T: PartialEq>(a: &[T], b: &[T]) -> Option<(usize, usize)> {
+    let n = a.len() as isize;
+    let m = b.len() as isize;
+    let max_d = (n + m + 1) / 2;
+    let offset = max_d;
+    let length = 2 * max_d + 2;
+    // Furthest x reached on each diagonal k = x - y; backwards from the end, x
+    // counts from the end too
+    let mut forward = vec![-1isize; length as usize];
+    let mut backward = vec![-1isize; length as usize];
+    forward[(offset + 1) as usize] = 0;
+    backward[(offset + 1) as usize] = 0;
+    let delta = n - m;
+    // With an odd delta the paths meet on a forward step, otherwise on a backward one
+    let front = delta % 2 != 0;
+    // Diagonals that left the grid are not searched again
+    let (mut k1_start, mut k1_end, mut k2_start, mut k2_end) = (0, 0, 0, 0);
+
+    for d in 0..max_d {
+        let mut k1 = -d + k1_start;
+        while k1 <= d - k1_end {
+            let i = (offset + k1) as usize;
+            let mut x1 = if k1 == -d || (k1 != d && forward[i - 1] < forward[i + 1]) {
+                forward[i + 1]
+            } else {
+                forward[i - 1] + 1
+            };
+            let mut y1 = x1 - k1;
+            while x1 < n && y1 < m && a[x1 as usize] == b[y1 as usize] {
+                x1 += 1;
+                y1 += 1;
+            }
+            forward[i] = x1;
+            if x1 > n {
+                k1_end += 2;
+            } else if y1 > m {
+                k1_start += 2;
+            } else if front {
+                let j = offset + delta - k1;
+                if j >= 0 && j < length && backward[j as usize] != -1 && x1 >= n - backward[j as usize] {
+                    return Some((x1 as usize, y1 as usize));
+                }
+            }
+            k1 += 2;
+        }
+
+        let mut k2 = -d + k2_start;
+        while k2 <= d - k2_end {
+            let i = (offset + k2) as usize;
+            let mut x2 = if k2 == -d || (k2 != d && backward[i - 1] < backward[i + 1]) {
+                backward[i + 1]
+            } else {
+                backward[i - 1] + 1
+            };
+            let mut y2 = x2 - k2;
+            while x2 < n && y2 < m && a[(n - x2 - 1) as usize] == b[(m - y2 - 1) as usize] {
+                x2 += 1;
+                y2 += 1;
+            }
+            backward[i] = x2;
+            if x2 > n {
+                k2_end += 2;
+            } else if y2 > m {
+                k2_start += 2;
+            } else if !front {
+                let j = offset + delta - k2;
+                if j >= 0 && j < length && forward[j as usize] != -1 {
+                    let x1 = forward[j as usize];
+                    let y1 = offset + x1 - j;
+                    if x1 >= n - x2 {
+                        return Some((x1 as usize, y1 as usize));
+                    }
+                }
+            }
+            k2 += 2;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Rebuild `new` from `old` and the hunks
+    fn apply<T: Clone>(old: &[T], new: &[T], hunks: &[DiffHunk]) -> Vec<T> {
+        let mut result = Vec::new();
+        let mut position = 0;
+        for hunk in hunks {
+            result.extend_from_slice(&old[position..hunk.old.start]);
+            result.extend_from_slice(&new[hunk.new.clone()]);
+            position = hunk.old.end;
+        }
+        result.extend_from_slice(&old[position..]);
+        result
+    }
+
+    /// Length of the longest common subsequence, by dynamic programming
+    fn lcs_len<T: PartialEq>(a: &[T], b: &[T]) -> usize {
+        let mut row = vec![0; b.len() + 1];
+        for x in a {
+            let mut diagonal = 0;
+            for (j, y) in b.iter().enumerate() {
+                let above = row[j + 1];
+                row[j + 1] = if x == y { diagonal + 1 } else { above.max(row[j]) };
+                diagonal = above;
+            }
+        }
+        row[b.len()]
+    }
+
+    /// Number of elements the hunks delete or insert
+    fn edit_count(hunks: &[DiffHunk]) -> usize {
+        hunks.iter().map(|h| h.old.len() + h.new.len()).sum()
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let old = split_lines(b"a\nb\nc\nd\ne\n");
+        let new = split_lines(b"a\nc\nd\nx\ne");
+        let hunks = diff_lines(&old, &new);
+        assert_eq!(hunks, vec![
+            DiffHunk { old: 1..2, new: 1..1 },
+            DiffHunk { old: 4..5, new: 3..5 },
+        ]);
+        assert!(diff_lines(&old, &old).is_empty());
+        assert_eq!(diff_lines(&[], &new), vec![DiffHunk { old: 0..0, new: 0..5 }]);
+    }
+
+    #[test]
+    fn test_diff_is_minimal() {
+        // The classic example from Myers' paper has an edit distance of 5
+        let hunks = diff(b"ABCABBA", b"CBABAC");
+        assert_eq!(edit_count(&hunks), 5);
+        assert_eq!(apply(b"ABCABBA", b"CBABAC", &hunks), b"CBABAC");
+    }
+
+    proptest! {
+        /// Property 18: Diff Operation Correctness
+        /// Applying the hunks to the old sequence yields the new one with a minimal
+        /// number of edits, and no hunk is empty or adjacent to another.
+        /// **Validates: Requirements 5.4**
+        #[test]
+        fn prop_diff_reconstructs_new(
+            old in prop::collection::vec(0u8..4, 0..60),
+            new in prop::collection::vec(0u8..4, 0..60),
+        ) {
+            let hunks = diff(&old, &new);
+            prop_assert_eq!(apply(&old, &new, &hunks), new.clone());
+            for pair in hunks.windows(2) {
+                prop_assert!(pair[0].old.end < pair[1].old.start);
+            }
+            prop_assert!(hunks.iter().all(|h| !h.old.is_empty() || !h.new.is_empty()));
+            // Minimal: only what the longest common subsequence leaves out changes
+            prop_assert_eq!(edit_count(&hunks), old.len() + new.len() - 2 * lcs_len(&old, &new));
+        }
+    }
+}
@@ -0,0 +1,44 @@
+//! GitNext Merge - three-way merges of contents, trees and commits
+//!
+//! Layers, bottom up:
+//!
+//! - `diff`: Myers line diff
+//! - `content`: diff3 merge of one file, with Git-style conflict markers
+//! - `rename`: tree diffs with rename and copy detection
+//! - `tree`: recursive three-way merge of trees, including adds, deletes, mode
+//!   changes, file/directory conflicts and renames
+//! - `recursive`: merge bases and merging commits, with criss-cross histories
+//!   merged through a virtual base
+//!
+//! (Requirements 1.5)
+
+use gitnext_core::ObjectId;
+use gitnext_storage::StorageError;
+use thiserror::Error;
+
+/// Merge error types
+#[derive(Debug, Error)]
+pub enum MergeError {
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error("Object {0} is not a tree")]
+    NotATree(ObjectId),
+
+    #[error("Object {0} is not a commit")]
+    NotACommit(ObjectId),
+}
+
+pub type Result<T> = std::result::Result<T, MergeError>;
+
+pub mod content;
+pub mod diff;
+pub mod recursive;
+pub mod rename;
+pub mod tree;
+
+pub use content::{merge_content, ConflictStyle, ContentMerge, MergeFavor, MergeOptions};
+pub use diff::{diff_lines, split_lines, DiffHunk};
+pub use recursive::{merge_bases, merge_commits};
+pub use rename::{diff_trees, RenameOptions, TreeChange};
+pub use tree::{merge_trees, Conflict, ConflictKind, TreeMerge, Version};
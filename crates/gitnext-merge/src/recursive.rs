@@ -0,0 +1,220 @@
+//! Merging commits: merge bases and the recursive strategy
+//!
+//! Merge bases are found as Git does, by painting ancestors of both commits in
+//! commit date order until only commits reachable from a found base remain. When
+//! criss-cross history leaves several bases, they are first merged with each other
+//! into a virtual base tree, conflict markers and all, which then serves as the
+//! base of the real merge.
+
+use crate::content::{ConflictStyle, MergeFavor, MergeOptions};
+use crate::tree::{merge_trees, TreeMerge};
+use crate::{MergeError, Result};
+use gitnext_core::{GitObject, ObjectId};
+use gitnext_storage::{Storage, StorageError};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+
+const OURS: u8 = 1;
+const THEIRS: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// Parents and commit time of a commit
+async fn load_commit(storage: &dyn Storage, id: &ObjectId) -> Result<(Vec<ObjectId>, i64, ObjectId)> {
+    match storage.load_object(id).await? {
+        Some(GitObject::Commit(commit)) => Ok((commit.parents, commit.committer.timestamp, commit.tree)),
+        Some(_) => Err(MergeError::NotACommit(*id)),
+        None => Err(StorageError::ObjectNotFound { id: *id }.into()),
+    }
+}
+
+async fn commit_tree(storage: &dyn Storage, id: &ObjectId) -> Result<ObjectId> {
+    Ok(load_commit(storage, id).await?.2)
+}
+
+/// The best common ancestors of `a` and `b`: those not reachable from another
+/// common ancestor. Empty for unrelated histories.
+pub async fn merge_bases(storage: &dyn Storage, a: &ObjectId, b: &ObjectId) -> Result<Vec<ObjectId>> {
+    if a == b {
+        return Ok(vec![*a]);
+    }
+
+    let mut flags: HashMap<ObjectId, u8> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    let mut results = Vec::new();
+    for (id, flag) in [(*a, OURS), (*b, THEIRS)] {
+        flags.insert(id, flag);
+        queue.push((load_commit(storage, &id).await?.1, id));
+    }
+
+    while queue.iter().any(|(_, id)| flags[id] & STALE == 0) {
+        let Some((_, id)) = queue.pop() else { break };
+        let mut flag = flags[&id];
+        if flag & (OURS | THEIRS) == OURS | THEIRS {
+            if flag & RESULT == 0 {
+                flags.insert(id, flag | RESULT);
+                results.push(id);
+            }
+            flag |= STALE;
+        }
+        let propagated = flag & (OURS | THEIRS | STALE);
+        for parent in load_commit(storage, &id).await?.0 {
+            let current = flags.get(&parent).copied().unwrap_or(0);
+            if current & propagated == propagated {
+                continue;
+            }
+            flags.insert(parent, current | propagated);
+            queue.push((load_commit(storage, &parent).await?.1, parent));
+        }
+    }
+
+    let mut bases: Vec<ObjectId> = results.into_iter().filter(|id| flags[id] & STALE == 0).collect();
+    if bases.len() > 1 {
+        // Clock skew can leave a base that is an ancestor of another
+        let mut redundant = HashSet::new();
+        for base in &bases {
+            let ancestors = ancestors(storage, base).await?;
+            redundant.extend(bases.iter().filter(|other| *other != base && ancestors.contains(*other)).copied());
+        }
+        bases.retain(|base| !redundant.contains(base));
+    }
+    Ok(bases)
+}
+
+/// Every commit reachable from `id`, including itself
+async fn ancestors(storage: &dyn Storage, id: &ObjectId) -> Result<HashSet<ObjectId>> {
+    let mut seen = HashSet::from([*id]);
+    let mut stack = vec![*id];
+    while let Some(id) = stack.pop() {
+        for parent in load_commit(storage, &id).await?.0 {
+            if seen.insert(parent) {
+                stack.push(parent);
+            }
+        }
+    }
+    Ok(seen)
+}
+
+/// Merge the commits `ours` and `theirs` with the recursive strategy
+pub async fn merge_commits(
+    storage: &dyn Storage,
+    ours: &ObjectId,
+    theirs: &ObjectId,
+    options: &MergeOptions,
+) -> Result<TreeMerge> {
+    let bases = merge_bases(storage, ours, theirs).await?;
+    let base = virtual_base(storage, bases).await?;
+    let ours_tree = commit_tree(storage, ours).await?;
+    let theirs_tree = commit_tree(storage, theirs).await?;
+    merge_trees(storage, base.as_ref(), &ours_tree, &theirs_tree, options).await
+}
+
+/// The tree to merge against: that of the only base, or the bases merged together
+fn virtual_base(
+    storage: &dyn Storage,
+    bases: Vec<ObjectId>,
+) -> Pin<Box<dyn Future<Output = Result<Option<ObjectId>>> + Send + '_>> {
+    Box::pin(async move {
+        let Some((first, rest)) = bases.split_first() else {
+            return Ok(None);
+        };
+        let mut tree = commit_tree(storage, first).await?;
+        let options = MergeOptions {
+            ours_label: "Temporary merge branch 1".to_string(),
+            theirs_label: "Temporary merge branch 2".to_string(),
+            style: ConflictStyle::Merge,
+            favor: MergeFavor::None,
+            ..MergeOptions::default()
+        };
+        let mut merged = vec![*first];
+        for next in rest {
+            let mut inner_bases = Vec::new();
+            for commit in &merged {
+                for base in merge_bases(storage, commit, next).await? {
+                    if !inner_bases.contains(&base) {
+                        inner_bases.push(base);
+                    }
+                }
+            }
+            let inner_base = virtual_base(storage, inner_bases).await?;
+            let next_tree = commit_tree(storage, next).await?;
+            tree = merge_trees(storage, inner_base.as_ref(), &tree, &next_tree, &options).await?.tree;
+            merged.push(*next);
+        }
+        Ok(Some(tree))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::tests::{build_tree, read_tree};
+    use gitnext_core::{Commit, FileMode, Signature};
+    use gitnext_storage::MemoryStorage;
+
+    async fn commit(storage: &dyn Storage, time: i64, parents: &[ObjectId], content: &str) -> ObjectId {
+        let tree = build_tree(storage, &[("file.txt", FileMode::Normal, content)]).await;
+        let signature = Signature {
+            name: "Test".to_string(),
+            email: "test@example.com".to_string(),
+            timestamp: time,
+            timezone_offset: 0,
+            raw: None,
+        };
+        let commit = GitObject::Commit(Commit {
+            tree,
+            parents: parents.to_vec(),
+            author: signature.clone(),
+            committer: signature,
+            extra_headers: Vec::new(),
+            message: format!("commit {}\n", time),
+            raw_message: None,
+        });
+        let id = commit.canonical_hash();
+        storage.store_object(&id, &commit).await.unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn test_merge_bases() {
+        let storage = MemoryStorage::new();
+        let root = commit(&storage, 1, &[], "1\n2\n3\n4\n5\n").await;
+        let a = commit(&storage, 2, &[root], "one\n2\n3\n4\n5\n").await;
+        let b = commit(&storage, 3, &[root], "1\n2\n3\n4\nfive\n").await;
+        let a2 = commit(&storage, 4, &[a], "one\ntwo\n3\n4\n5\n").await;
+        assert_eq!(merge_bases(&storage, &a2, &b).await.unwrap(), vec![root]);
+        assert_eq!(merge_bases(&storage, &a2, &a).await.unwrap(), vec![a]);
+        assert_eq!(merge_bases(&storage, &b, &b).await.unwrap(), vec![b]);
+
+        let unrelated = commit(&storage, 5, &[], "other\n").await;
+        assert!(merge_bases(&storage, &a2, &unrelated).await.unwrap().is_empty());
+
+        let result = merge_commits(&storage, &a2, &b, &MergeOptions::default()).await.unwrap();
+        assert!(result.is_clean());
+        assert_eq!(read_tree(&storage, &result.tree).await["file.txt"].1, "one\ntwo\n3\n4\nfive\n");
+    }
+
+    #[tokio::test]
+    async fn test_criss_cross_merge() {
+        let storage = MemoryStorage::new();
+        let root = commit(&storage, 1, &[], "1\n2\n3\n4\n5\n6\n7\n").await;
+        let a = commit(&storage, 2, &[root], "one\n2\n3\n4\n5\n6\n7\n").await;
+        let b = commit(&storage, 3, &[root], "1\n2\n3\n4\n5\n6\nseven\n").await;
+        // Both sides merge each other, so the next merge has two best bases
+        let c = commit(&storage, 4, &[a, b], "one\n2\n3\n4\n5\n6\nseven\n").await;
+        let d = commit(&storage, 5, &[b, a], "one\n2\n3\n4\n5\n6\nseven\n").await;
+        let c2 = commit(&storage, 6, &[c], "one\ntwo\n3\n4\n5\n6\nseven\n").await;
+        let d2 = commit(&storage, 7, &[d], "one\n2\n3\n4\n5\nsix\nseven\n").await;
+
+        let mut bases = merge_bases(&storage, &c2, &d2).await.unwrap();
+        bases.sort();
+        let mut expected = vec![a, b];
+        expected.sort();
+        assert_eq!(bases, expected);
+
+        let result = merge_commits(&storage, &c2, &d2, &MergeOptions::default()).await.unwrap();
+        assert!(result.is_clean(), "{:?}", result.conflicts);
+        assert_eq!(read_tree(&storage, &result.tree).await["file.txt"].1, "one\ntwo\n3\n4\n5\nsix\nseven\n");
+    }
+}
@@ -0,0 +1,577 @@
+//! Tree diffs with rename and copy detection
+//!
+//! `diff_trees` compares two trees path by path, skipping subtrees with equal ids.
+//! With rename detection, deleted files are then paired with added files: first
+//! those with the same blob id, then, unless there are more candidate pairs than
+//! the rename limit allows, those with similar enough content. Similarity is
+//! estimated as in Git: each blob is cut into chunks ending at a newline or after
+//! 64 bytes, and the score is the share of the larger file's bytes whose chunks
+//! the other file has too.
+//!
+//! Merges use the same detection to follow a file renamed on one side, so that
+//! the other side's edits to it land at its new path.
+
+use crate::tree::{load_entries, store, Conflict, ConflictKind, Entry, Version};
+use crate::Result;
+use gitnext_core::{FileMode, GitObject, ObjectId, ObjectType, Tree, TreeEntry};
+use gitnext_storage::{Storage, StorageError};
+use std::cmp::Reverse;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+
+/// Longest chunk a fingerprint hashes; longer lines are cut
+const CHUNK_SIZE: usize = 64;
+
+/// How renames and copies are detected
+#[derive(Debug, Clone)]
+pub struct RenameOptions {
+    /// Minimum similarity, in percent, for two files to pair up (Git's `-M50%`)
+    pub threshold: u8,
+    /// Only exact renames are looked for when sources times destinations exceed
+    /// the square of this (Git's `diff.renameLimit`)
+    pub limit: usize,
+    /// Also find copies, with every file of the old tree as a possible source
+    /// (`--find-copies-harder`). Merges ignore this.
+    pub copies: bool,
+}
+
+impl Default for RenameOptions {
+    fn default() -> Self {
+        Self { threshold: 50, limit: 1000, copies: false }
+    }
+}
+
+/// A file that differs between two trees; `score` is the similarity of a rename
+/// or copy in percent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TreeChange {
+    Added { path: String, new: Version },
+    Deleted { path: String, old: Version },
+    Modified { path: String, old: Version, new: Version },
+    Renamed { from: String, to: String, old: Version, new: Version, score: u8 },
+    Copied { from: String, to: String, old: Version, new: Version, score: u8 },
+}
+
+impl TreeChange {
+    /// The path in the new tree, or the deleted path
+    pub fn path(&self) -> &str {
+        match self {
+            TreeChange::Added { path, .. } | TreeChange::Deleted { path, .. } | TreeChange::Modified { path, .. } => path,
+            TreeChange::Renamed { to, .. } | TreeChange::Copied { to, .. } => to,
+        }
+    }
+}
+
+/// Differences from the tree `old` to the tree `new`, in path order; `None` stands
+/// for an empty tree. Renames and copies are detected when `renames` is given.
+pub async fn diff_trees(
+    storage: &dyn Storage,
+    old: Option<&ObjectId>,
+    new: Option<&ObjectId>,
+    renames: Option<&RenameOptions>,
+) -> Result<Vec<TreeChange>> {
+    let mut changes = Vec::new();
+    collect(storage, String::new(), old.copied(), new.copied(), &mut changes).await?;
+    let mut changes = match renames {
+        Some(options) => detect_renames(storage, changes, old, options).await?,
+        None => changes.into_iter().map(FileChange::into_change).collect(),
+    };
+    changes.sort_by(|a, b| a.path().cmp(b.path()));
+    Ok(changes)
+}
+
+/// A file added, deleted or modified, before rename detection
+struct FileChange {
+    path: String,
+    old: Option<Entry>,
+    new: Option<Entry>,
+}
+
+impl FileChange {
+    fn into_change(self) -> TreeChange {
+        let path = self.path;
+        match (self.old, self.new) {
+            (Some(old), Some(new)) => TreeChange::Modified { path, old: old.version(), new: new.version() },
+            (Some(old), None) => TreeChange::Deleted { path, old: old.version() },
+            (None, Some(new)) => TreeChange::Added { path, new: new.version() },
+            (None, None) => unreachable!("a change has at least one side"),
+        }
+    }
+}
+
+fn join(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) }
+}
+
+/// Append the changed files between two directories
+fn collect<'a>(
+    storage: &'a dyn Storage,
+    prefix: String,
+    old: Option<ObjectId>,
+    new: Option<ObjectId>,
+    changes: &'a mut Vec<FileChange>,
+) -> Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>> {
+    Box::pin(async move {
+        let old = load_entries(storage, old).await?;
+        let new = load_entries(storage, new).await?;
+        let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        for name in names {
+            let (o, n) = (old.get(name).copied(), new.get(name).copied());
+            if o == n {
+                continue;
+            }
+            let path = join(&prefix, name);
+            let tree_part = |e: Option<Entry>| e.filter(Entry::is_tree).map(|e| e.id);
+            let file_part = |e: Option<Entry>| e.filter(|e| !e.is_tree());
+            let (old_tree, new_tree) = (tree_part(o), tree_part(n));
+            if old_tree != new_tree {
+                collect(storage, path.clone(), old_tree, new_tree, changes).await?;
+            }
+            let (old_file, new_file) = (file_part(o), file_part(n));
+            if old_file != new_file {
+                changes.push(FileChange { path, old: old_file, new: new_file });
+            }
+        }
+        Ok(())
+    })
+}
+
+/// A file a rename or copy may come from
+struct Source {
+    path: String,
+    entry: Entry,
+    deleted: bool,
+    uses: usize,
+}
+
+/// Whether a file takes part in rename detection: blobs do, submodules do not
+fn renameable(entry: &Entry) -> bool {
+    entry.kind == ObjectType::Blob
+}
+
+/// Whether one file may be a rename of another; symlinks pair only with symlinks
+fn compatible(a: &Entry, b: &Entry) -> bool {
+    (a.mode == FileMode::Symlink) == (b.mode == FileMode::Symlink)
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+async fn detect_renames(
+    storage: &dyn Storage,
+    changes: Vec<FileChange>,
+    old_tree: Option<&ObjectId>,
+    options: &RenameOptions,
+) -> Result<Vec<TreeChange>> {
+    let mut sources: Vec<Source> = changes.iter()
+        .filter_map(|c| match (c.old, c.new) {
+            (Some(entry), None) => Some(Source { path: c.path.clone(), entry, deleted: true, uses: 0 }),
+            _ => None,
+        })
+        .collect();
+    if options.copies {
+        let deleted: BTreeSet<String> = sources.iter().map(|s| s.path.clone()).collect();
+        let mut files = Vec::new();
+        collect(storage, String::new(), old_tree.copied(), None, &mut files).await?;
+        sources.extend(files.into_iter()
+            .filter(|f| !deleted.contains(&f.path))
+            .filter_map(|f| f.old.map(|entry| Source { path: f.path, entry, deleted: false, uses: 0 })));
+    }
+    sources.retain(|s| renameable(&s.entry));
+    let destinations: Vec<usize> = (0..changes.len())
+        .filter(|&i| changes[i].old.is_none() && changes[i].new.is_some_and(|e| renameable(&e)))
+        .collect();
+    // Without copy detection a deleted file is renamed once; with it, every
+    // further destination is a copy
+    let available = |source: &Source| options.copies || source.uses == 0;
+    // Destination index to source index, score and whether it is a rename
+    let mut pairs: HashMap<usize, (usize, u8, bool)> = HashMap::new();
+    let pair = |pairs: &mut HashMap<usize, (usize, u8, bool)>, sources: &mut [Source], d: usize, s: usize, score: u8| {
+        let rename = sources[s].deleted && sources[s].uses == 0;
+        sources[s].uses += 1;
+        pairs.insert(d, (s, score, rename));
+    };
+
+    // Exact renames, preferring deleted files and then equal file names
+    let mut by_id: HashMap<ObjectId, Vec<usize>> = HashMap::new();
+    for (i, source) in sources.iter().enumerate() {
+        by_id.entry(source.entry.id).or_default().push(i);
+    }
+    let mut inexact = Vec::new();
+    for &d in &destinations {
+        let (path, entry) = (&changes[d].path, changes[d].new.unwrap());
+        let best = by_id.get(&entry.id).into_iter().flatten().copied()
+            .filter(|&s| available(&sources[s]) && compatible(&sources[s].entry, &entry))
+            .min_by_key(|&s| (!sources[s].deleted, basename(&sources[s].path) != basename(path), s));
+        match best {
+            Some(s) => pair(&mut pairs, &mut sources, d, s, 100),
+            None => inexact.push(d),
+        }
+    }
+
+    // Similar content, best scores first
+    let candidates: Vec<usize> = (0..sources.len()).filter(|&s| available(&sources[s])).collect();
+    if !inexact.is_empty()
+        && !candidates.is_empty()
+        && inexact.len().saturating_mul(candidates.len()) <= options.limit.saturating_mul(options.limit)
+    {
+        let mut fingerprints: HashMap<ObjectId, Fingerprint> = HashMap::new();
+        for id in inexact.iter().map(|&d| changes[d].new.unwrap().id).chain(candidates.iter().map(|&s| sources[s].entry.id)) {
+            if let std::collections::hash_map::Entry::Vacant(slot) = fingerprints.entry(id) {
+                slot.insert(Fingerprint::new(&load_blob(storage, &id).await?));
+            }
+        }
+        let mut scored = Vec::new();
+        for &d in &inexact {
+            let entry = changes[d].new.unwrap();
+            for &s in &candidates {
+                if !compatible(&sources[s].entry, &entry) {
+                    continue;
+                }
+                let score = fingerprints[&sources[s].entry.id].similarity(&fingerprints[&entry.id], options.threshold);
+                if let Some(score) = score {
+                    scored.push((score, s, d));
+                }
+            }
+        }
+        scored.sort_by_key(|&(score, s, d)| {
+            (Reverse(score), !sources[s].deleted, basename(&sources[s].path) != basename(&changes[d].path), s, d)
+        });
+        for (score, s, d) in scored {
+            if !pairs.contains_key(&d) && available(&sources[s]) {
+                pair(&mut pairs, &mut sources, d, s, score);
+            }
+        }
+    }
+
+    let renamed: BTreeSet<&str> = sources.iter().filter(|s| s.deleted && s.uses > 0).map(|s| s.path.as_str()).collect();
+    let mut result = Vec::with_capacity(changes.len());
+    for (i, change) in changes.iter().enumerate() {
+        if let Some(&(s, score, rename)) = pairs.get(&i) {
+            let source = &sources[s];
+            let (from, to) = (source.path.clone(), change.path.clone());
+            let (old, new) = (source.entry.version(), change.new.unwrap().version());
+            result.push(if rename {
+                TreeChange::Renamed { from, to, old, new, score }
+            } else {
+                TreeChange::Copied { from, to, old, new, score }
+            });
+        } else if !(change.new.is_none() && renamed.contains(change.path.as_str())) {
+            result.push(FileChange { path: change.path.clone(), old: change.old, new: change.new }.into_change());
+        }
+    }
+    Ok(result)
+}
+
+async fn load_blob(storage: &dyn Storage, id: &ObjectId) -> Result<bytes::Bytes> {
+    match storage.load_object(id).await? {
+        Some(GitObject::Blob(blob)) => Ok(blob.content.unwrap_or_default()),
+        Some(_) => Err(StorageError::CorruptionDetected { id: *id, details: "Expected a blob".to_string() }.into()),
+        None => Err(StorageError::ObjectNotFound { id: *id }.into()),
+    }
+}
+
+/// Bytes of content per chunk hash
+struct Fingerprint {
+    size: usize,
+    chunks: HashMap<u64, usize>,
+}
+
+impl Fingerprint {
+    fn new(content: &[u8]) -> Self {
+        let mut chunks = HashMap::new();
+        let mut start = 0;
+        for (i, &byte) in content.iter().enumerate() {
+            if byte == b'\n' || i + 1 - start == CHUNK_SIZE || i + 1 == content.len() {
+                let mut hasher = DefaultHasher::new();
+                content[start..=i].hash(&mut hasher);
+                *chunks.entry(hasher.finish()).or_insert(0) += i + 1 - start;
+                start = i + 1;
+            }
+        }
+        Self { size: content.len(), chunks }
+    }
+
+    /// Similarity in percent, or `None` when below `threshold`
+    fn similarity(&self, other: &Fingerprint, threshold: u8) -> Option<u8> {
+        let larger = self.size.max(other.size);
+        if larger == 0 {
+            return Some(100);
+        }
+        // Files of very different sizes cannot reach the threshold
+        if self.size.min(other.size) * 100 < threshold as usize * larger {
+            return None;
+        }
+        let (small, large) = if self.chunks.len() <= other.chunks.len() { (self, other) } else { (other, self) };
+        let common: usize = small.chunks.iter()
+            .map(|(hash, &bytes)| large.chunks.get(hash).map_or(0, |&other| bytes.min(other)))
+            .sum();
+        let score = (common * 100 / larger) as u8;
+        (score >= threshold).then_some(score)
+    }
+}
+
+/// Trees to merge in place of `base`, `ours` and `theirs`: a file renamed on one
+/// side is moved to its new path in the base and on the other side. Renames that
+/// cannot be followed are returned as conflicts: the same file renamed to two
+/// paths, or renamed on one side and deleted on the other.
+pub(crate) async fn follow_renames(
+    storage: &dyn Storage,
+    base: &ObjectId,
+    ours: &ObjectId,
+    theirs: &ObjectId,
+    options: &RenameOptions,
+) -> Result<(ObjectId, ObjectId, ObjectId, Vec<Conflict>)> {
+    let options = RenameOptions { copies: false, ..options.clone() };
+    let renames = |changes: Vec<TreeChange>| -> BTreeMap<String, String> {
+        changes.into_iter()
+            .filter_map(|c| match c {
+                TreeChange::Renamed { from, to, .. } => Some((from, to)),
+                _ => None,
+            })
+            .collect()
+    };
+    let ours_renames = renames(diff_trees(storage, Some(base), Some(ours), Some(&options)).await?);
+    let theirs_renames = renames(diff_trees(storage, Some(base), Some(theirs), Some(&options)).await?);
+    if ours_renames.is_empty() && theirs_renames.is_empty() {
+        return Ok((*base, *ours, *theirs, Vec::new()));
+    }
+
+    let mut base_files = flatten(storage, base).await?;
+    let mut ours_files = flatten(storage, ours).await?;
+    let mut theirs_files = flatten(storage, theirs).await?;
+    let (mut base_moved, mut ours_moved, mut theirs_moved) = (false, false, false);
+    let version = |files: &BTreeMap<String, Entry>, path: &str| files.get(path).map(Entry::version);
+    let mut conflicts = Vec::new();
+
+    for (from, to) in &ours_renames {
+        let base_version = version(&base_files, from);
+        match theirs_renames.get(from) {
+            Some(other) if other == to => base_moved |= move_file(&mut base_files, from, to),
+            Some(other) => {
+                conflicts.push(Conflict {
+                    path: to.clone(),
+                    kind: ConflictKind::RenameRename,
+                    base: base_version,
+                    ours: version(&ours_files, to),
+                    theirs: None,
+                });
+                conflicts.push(Conflict {
+                    path: other.clone(),
+                    kind: ConflictKind::RenameRename,
+                    base: base_version,
+                    ours: None,
+                    theirs: version(&theirs_files, other),
+                });
+            }
+            None if !theirs_files.contains_key(from) => conflicts.push(Conflict {
+                path: to.clone(),
+                kind: ConflictKind::RenameDelete,
+                base: base_version,
+                ours: version(&ours_files, to),
+                theirs: None,
+            }),
+            None if is_free(&theirs_files, to) => {
+                base_moved |= move_file(&mut base_files, from, to);
+                theirs_moved |= move_file(&mut theirs_files, from, to);
+            }
+            None => {}
+        }
+    }
+    for (from, to) in theirs_renames.iter().filter(|(from, _)| !ours_renames.contains_key(*from)) {
+        if !ours_files.contains_key(from) {
+            conflicts.push(Conflict {
+                path: to.clone(),
+                kind: ConflictKind::RenameDelete,
+                base: version(&base_files, from),
+                ours: None,
+                theirs: version(&theirs_files, to),
+            });
+        } else if is_free(&ours_files, to) {
+            base_moved |= move_file(&mut base_files, from, to);
+            ours_moved |= move_file(&mut ours_files, from, to);
+        }
+    }
+
+    // Only trees whose files moved are rebuilt
+    let base = if base_moved { build(storage, &base_files).await? } else { *base };
+    let ours = if ours_moved { build(storage, &ours_files).await? } else { *ours };
+    let theirs = if theirs_moved { build(storage, &theirs_files).await? } else { *theirs };
+    Ok((base, ours, theirs, conflicts))
+}
+
+/// Move the file at `from` to `to`, returning whether there was one
+fn move_file(files: &mut BTreeMap<String, Entry>, from: &str, to: &str) -> bool {
+    match files.remove(from) {
+        Some(entry) => {
+            files.insert(to.to_string(), entry);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Whether `path` is neither a file nor a directory in `files`
+fn is_free(files: &BTreeMap<String, Entry>, path: &str) -> bool {
+    let dir = format!("{}/", path);
+    !files.contains_key(path) && files.range(dir.clone()..).next().is_none_or(|(p, _)| !p.starts_with(&dir))
+}
+
+/// Every non-directory entry of a tree by path
+async fn flatten(storage: &dyn Storage, tree: &ObjectId) -> Result<BTreeMap<String, Entry>> {
+    let mut changes = Vec::new();
+    collect(storage, String::new(), None, Some(*tree), &mut changes).await?;
+    Ok(changes.into_iter().filter_map(|c| c.new.map(|entry| (c.path, entry))).collect())
+}
+
+/// Store the tree holding `files`
+async fn build(storage: &dyn Storage, files: &BTreeMap<String, Entry>) -> Result<ObjectId> {
+    let files: Vec<(&str, Entry)> = files.iter().map(|(path, entry)| (path.as_str(), *entry)).collect();
+    build_level(storage, files).await
+}
+
+fn build_level<'a>(
+    storage: &'a dyn Storage,
+    files: Vec<(&'a str, Entry)>,
+) -> Pin<Box<dyn Future<Output = Result<ObjectId>> + Send + 'a>> {
+    Box::pin(async move {
+        let mut entries = Vec::new();
+        let mut dirs: BTreeMap<&str, Vec<(&str, Entry)>> = BTreeMap::new();
+        for (path, entry) in files {
+            match path.split_once('/') {
+                Some((dir, rest)) => dirs.entry(dir).or_default().push((rest, entry)),
+                None => entries.push(TreeEntry { name: path.to_string(), mode: entry.mode, hash: entry.id, entry_type: entry.kind, raw_name: None }),
+            }
+        }
+        for (name, files) in dirs {
+            let hash = build_level(storage, files).await?;
+            entries.push(TreeEntry { name: name.to_string(), mode: FileMode::Tree, hash, entry_type: ObjectType::Tree, raw_name: None });
+        }
+        store(storage, GitObject::Tree(Tree::new(entries))).await
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::tests::build_tree;
+    use gitnext_storage::MemoryStorage;
+
+    const N: FileMode = FileMode::Normal;
+
+    /// Lines `0..n` with `name` in each, so that different names share nothing
+    fn lines(name: &str, n: usize) -> String {
+        (0..n).map(|i| format!("{} line {}\n", name, i)).collect()
+    }
+
+    /// Changes as `(kind, from, to, score)`
+    async fn diff(
+        old: &[(&str, FileMode, &str)],
+        new: &[(&str, FileMode, &str)],
+        options: Option<&RenameOptions>,
+    ) -> Vec<(char, String, String, u8)> {
+        let storage = MemoryStorage::new();
+        let old = build_tree(&storage, old).await;
+        let new = build_tree(&storage, new).await;
+        let changes = diff_trees(&storage, Some(&old), Some(&new), options).await.unwrap();
+        changes.into_iter()
+            .map(|c| match c {
+                TreeChange::Added { path, .. } => ('A', String::new(), path, 0),
+                TreeChange::Deleted { path, .. } => ('D', path, String::new(), 0),
+                TreeChange::Modified { path, .. } => ('M', path.clone(), path, 0),
+                TreeChange::Renamed { from, to, score, .. } => ('R', from, to, score),
+                TreeChange::Copied { from, to, score, .. } => ('C', from, to, score),
+            })
+            .collect()
+    }
+
+    fn change(kind: char, from: &str, to: &str, score: u8) -> (char, String, String, u8) {
+        (kind, from.to_string(), to.to_string(), score)
+    }
+
+    #[tokio::test]
+    async fn test_diff_trees() {
+        let old = [("a.txt", N, "a\n"), ("dir/b.txt", N, "b\n"), ("dir/same.txt", N, "same\n"), ("path", N, "file\n")];
+        let new = [("a.txt", N, "a2\n"), ("dir/same.txt", N, "same\n"), ("dir/c.txt", N, "c\n"), ("path/inner", N, "inner\n")];
+        assert_eq!(diff(&old, &new, None).await, vec![
+            change('M', "a.txt", "a.txt", 0),
+            change('D', "dir/b.txt", "", 0),
+            change('A', "", "dir/c.txt", 0),
+            change('D', "path", "", 0),
+            change('A', "", "path/inner", 0),
+        ]);
+
+        let storage = MemoryStorage::new();
+        let tree = build_tree(&storage, &new).await;
+        assert!(diff_trees(&storage, Some(&tree), Some(&tree), None).await.unwrap().is_empty());
+        assert_eq!(diff_trees(&storage, None, Some(&tree), None).await.unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_renames() {
+        let a = lines("a", 10);
+        let b = lines("b", 10);
+        let b_edited = b.replace("b line 9\n", "changed\n");
+        let c = lines("c", 10);
+        let old = [("a.txt", N, a.as_str()), ("src/b.txt", N, b.as_str()), ("c.txt", N, c.as_str())];
+        let new = [("moved/a.txt", N, a.as_str()), ("lib/b.txt", N, b_edited.as_str()), ("d.txt", N, "unrelated\n")];
+        let options = RenameOptions::default();
+        assert_eq!(diff(&old, &new, Some(&options)).await, vec![
+            change('D', "c.txt", "", 0),
+            change('A', "", "d.txt", 0),
+            change('R', "src/b.txt", "lib/b.txt", 90),
+            change('R', "a.txt", "moved/a.txt", 100),
+        ]);
+
+        // Above the threshold only exact renames remain
+        let strict = RenameOptions { threshold: 95, ..RenameOptions::default() };
+        let changes = diff(&old, &new, Some(&strict)).await;
+        assert!(changes.contains(&change('R', "a.txt", "moved/a.txt", 100)));
+        assert!(changes.contains(&change('D', "src/b.txt", "", 0)));
+
+        // So do they past the rename limit
+        let limited = RenameOptions { limit: 1, ..RenameOptions::default() };
+        let changes = diff(&old, &new, Some(&limited)).await;
+        assert!(changes.contains(&change('R', "a.txt", "moved/a.txt", 100)));
+        assert!(changes.contains(&change('A', "", "lib/b.txt", 0)));
+    }
+
+    #[tokio::test]
+    async fn test_copies() {
+        let a = lines("a", 10);
+        let a_edited = format!("{}extra\n", a);
+        let old = [("a.txt", N, a.as_str()), ("gone.txt", N, "gone\n")];
+        let new = [("a.txt", N, a.as_str()), ("copy.txt", N, a_edited.as_str()), ("one.txt", N, "gone\n"), ("two.txt", N, "gone\n")];
+
+        // A deleted file is renamed once; further identical files are copies of it
+        let copies = RenameOptions { copies: true, ..RenameOptions::default() };
+        assert_eq!(diff(&old, &new, Some(&copies)).await, vec![
+            change('C', "a.txt", "copy.txt", 93),
+            change('R', "gone.txt", "one.txt", 100),
+            change('C', "gone.txt", "two.txt", 100),
+        ]);
+        assert_eq!(diff(&old, &new, Some(&RenameOptions::default())).await, vec![
+            change('A', "", "copy.txt", 0),
+            change('R', "gone.txt", "one.txt", 100),
+            change('A', "", "two.txt", 0),
+        ]);
+    }
+
+    #[test]
+    fn test_fingerprint_similarity() {
+        let a = Fingerprint::new(b"one\ntwo\nthree\nfour\n");
+        let b = Fingerprint::new(b"one\ntwo\nthree\nfive\n");
+        assert_eq!(a.similarity(&b, 0), Some(73));
+        assert_eq!(a.similarity(&b, 80), None);
+        assert_eq!(a.similarity(&Fingerprint::new(b""), 50), None);
+        // Long lines are cut, so a change late in one costs only its chunk
+        let long = Fingerprint::new(&[b'x'; 640]);
+        let mut edited = [b'x'; 640];
+        edited[639] = b'y';
+        assert_eq!(long.similarity(&Fingerprint::new(&edited), 50), Some(90));
+    }
+}
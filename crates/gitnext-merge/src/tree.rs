@@ -0,0 +1,483 @@
+//! Three-way tree merge
+//!
+//! Entries are compared per name. A side that left an entry as it was in the base
+//! takes the other side's version; identical changes are taken once. Otherwise
+//! subtrees are merged recursively and files are merged by content, with
+//! conflicts recorded for:
+//!
+//! - diverging content or additions of different content (`Content`, `AddAdd`)
+//! - a file modified on one side and deleted on the other (`ModifyDelete`)
+//! - diverging mode changes (`Mode`)
+//! - a file on one side where the other has a directory (`FileDirectory`); the
+//!   directory keeps the name and the file moves to `name~<label>`, as in Git
+//! - a file renamed differently on each side, or renamed on one side and deleted
+//!   on the other (`RenameRename`, `RenameDelete`)
+//!
+//! With rename detection on, a file renamed on one side is first moved to its new
+//! path in the base and on the other side, so that edits from both meet there.
+//!
+//! The merged tree holds every path's result: conflict markers for content
+//! conflicts, the modified version for modify/delete and ours for conflicts that
+//! cannot be merged (binary files, symlinks, submodules).
+
+use crate::content::{merge_content, MergeOptions};
+use crate::rename::follow_renames;
+use crate::{MergeError, Result};
+use gitnext_core::{Blob, FileMode, GitObject, ObjectId, ObjectType, Tree, TreeEntry};
+use gitnext_storage::{Storage, StorageError};
+use std::collections::{BTreeMap, BTreeSet};
+use std::future::Future;
+use std::pin::Pin;
+
+/// What went wrong at a conflicted path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    Content,
+    AddAdd,
+    ModifyDelete,
+    Mode,
+    FileDirectory,
+    /// A file renamed to different paths on each side; one conflict per path
+    RenameRename,
+    /// A file renamed on one side and deleted on the other
+    RenameDelete,
+}
+
+/// One side's version of a conflicted file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Version {
+    pub mode: FileMode,
+    pub id: ObjectId,
+}
+
+/// A path the merge could not resolve, with the versions it was merged from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Path of the file in the merged tree
+    pub path: String,
+    pub kind: ConflictKind,
+    pub base: Option<Version>,
+    pub ours: Option<Version>,
+    pub theirs: Option<Version>,
+}
+
+/// Result of a tree merge
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeMerge {
+    pub tree: ObjectId,
+    /// Conflicts, in path order
+    pub conflicts: Vec<Conflict>,
+}
+
+impl TreeMerge {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+/// A tree entry, keyed by name where it is used
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Entry {
+    pub(crate) mode: FileMode,
+    pub(crate) id: ObjectId,
+    pub(crate) kind: ObjectType,
+}
+
+impl Entry {
+    pub(crate) fn is_tree(&self) -> bool {
+        self.kind == ObjectType::Tree
+    }
+
+    pub(crate) fn version(&self) -> Version {
+        Version { mode: self.mode, id: self.id }
+    }
+}
+
+/// Merge the trees `ours` and `theirs` of which `base` is the common ancestor;
+/// `None` merges them as two additions
+pub async fn merge_trees(
+    storage: &dyn Storage,
+    base: Option<&ObjectId>,
+    ours: &ObjectId,
+    theirs: &ObjectId,
+    options: &MergeOptions,
+) -> Result<TreeMerge> {
+    let (base, ours, theirs, conflicts) = match (base, &options.renames) {
+        (Some(base), Some(renames)) => {
+            let (base, ours, theirs, conflicts) = follow_renames(storage, base, ours, theirs, renames).await?;
+            (Some(base), ours, theirs, conflicts)
+        }
+        _ => (base.copied(), *ours, *theirs, Vec::new()),
+    };
+    let mut merger = TreeMerger { storage, options, conflicts };
+    let tree = merger.merge_level(String::new(), base, Some(ours), Some(theirs)).await?;
+    let tree = match tree {
+        Some(tree) => tree,
+        None => store(storage, GitObject::Tree(Tree::new(Vec::new()))).await?,
+    };
+    let mut conflicts = merger.conflicts;
+    conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(TreeMerge { tree, conflicts })
+}
+
+pub(crate) async fn store(storage: &dyn Storage, object: GitObject) -> Result<ObjectId> {
+    let id = object.canonical_hash();
+    storage.store_object(&id, &object).await?;
+    Ok(id)
+}
+
+/// The entries of the tree `tree` by name; none for `None`
+pub(crate) async fn load_entries(storage: &dyn Storage, tree: Option<ObjectId>) -> Result<BTreeMap<String, Entry>> {
+    let Some(id) = tree else {
+        return Ok(BTreeMap::new());
+    };
+    match storage.load_object(&id).await? {
+        Some(GitObject::Tree(tree)) => Ok(tree.entries.into_iter()
+            .map(|e| (e.name, Entry { mode: e.mode, id: e.hash, kind: e.entry_type }))
+            .collect()),
+        Some(_) => Err(MergeError::NotATree(id)),
+        None => Err(StorageError::ObjectNotFound { id }.into()),
+    }
+}
+
+/// The one of three values that a three-way merge resolves to without looking
+/// inside, if any
+fn trivial<T: PartialEq + Copy>(base: T, ours: T, theirs: T) -> Option<T> {
+    if ours == theirs || theirs == base {
+        Some(ours)
+    } else if ours == base {
+        Some(theirs)
+    } else {
+        None
+    }
+}
+
+struct TreeMerger<'a> {
+    storage: &'a dyn Storage,
+    options: &'a MergeOptions,
+    conflicts: Vec<Conflict>,
+}
+
+impl<'a> TreeMerger<'a> {
+    async fn load_blob(&self, entry: Option<Entry>) -> Result<Vec<u8>> {
+        let Some(entry) = entry else {
+            return Ok(Vec::new());
+        };
+        match self.storage.load_object(&entry.id).await? {
+            Some(GitObject::Blob(blob)) => Ok(blob.content.map(|c| c.to_vec()).unwrap_or_default()),
+            Some(_) => Err(StorageError::CorruptionDetected { id: entry.id, details: "Expected a blob".to_string() }.into()),
+            None => Err(StorageError::ObjectNotFound { id: entry.id }.into()),
+        }
+    }
+
+    /// Merge one directory level, returning the stored tree or `None` when empty
+    fn merge_level(
+        &mut self,
+        prefix: String,
+        base: Option<ObjectId>,
+        ours: Option<ObjectId>,
+        theirs: Option<ObjectId>,
+    ) -> Pin<Box<dyn Future<Output = Result<Option<ObjectId>>> + Send + '_>> {
+        Box::pin(async move {
+            let base = load_entries(self.storage, base).await?;
+            let ours = load_entries(self.storage, ours).await?;
+            let theirs = load_entries(self.storage, theirs).await?;
+            let names: BTreeSet<&String> = base.keys().chain(ours.keys()).chain(theirs.keys()).collect();
+
+            let mut merged: BTreeMap<String, Entry> = BTreeMap::new();
+            let mut displaced = Vec::new();
+            for name in names {
+                let path = if prefix.is_empty() { name.clone() } else { format!("{}/{}", prefix, name) };
+                let (b, o, t) = (base.get(name).copied(), ours.get(name).copied(), theirs.get(name).copied());
+                if let Some(entry) = trivial(b, o, t) {
+                    if let Some(entry) = entry {
+                        merged.insert(name.clone(), entry);
+                    }
+                    continue;
+                }
+
+                // Files and directories of the same name are merged separately
+                let tree_part = |e: Option<Entry>| e.filter(Entry::is_tree).map(|e| e.id);
+                let blob_part = |e: Option<Entry>| e.filter(|e| !e.is_tree());
+                let (bt, ot, tt) = (tree_part(b), tree_part(o), tree_part(t));
+                let tree = match trivial(bt, ot, tt) {
+                    Some(tree) => tree,
+                    None => self.merge_level(path.clone(), bt, ot, tt).await?,
+                };
+                let (bb, ob, tb) = (blob_part(b), blob_part(o), blob_part(t));
+                let (file, kind) = self.merge_file(bb, ob, tb).await?;
+
+                if let Some(tree) = tree {
+                    merged.insert(name.clone(), Entry { mode: FileMode::Tree, id: tree, kind: ObjectType::Tree });
+                }
+                let conflict = |path: String, kind| Conflict {
+                    path,
+                    kind,
+                    base: bb.map(|e| e.version()),
+                    ours: ob.map(|e| e.version()),
+                    theirs: tb.map(|e| e.version()),
+                };
+                match (file, tree) {
+                    (Some(file), Some(_)) => {
+                        let label = if ob.is_some() { &self.options.ours_label } else { &self.options.theirs_label };
+                        displaced.push((name.clone(), label.replace('/', "_"), file, self.conflicts.len()));
+                        self.conflicts.push(conflict(path, ConflictKind::FileDirectory));
+                    }
+                    (Some(file), None) => {
+                        merged.insert(name.clone(), file);
+                        if let Some(kind) = kind {
+                            self.conflicts.push(conflict(path, kind));
+                        }
+                    }
+                    (None, _) => {
+                        if let Some(kind) = kind {
+                            self.conflicts.push(conflict(path, kind));
+                        }
+                    }
+                }
+            }
+
+            // Files displaced by directories take a free name beside them
+            for (name, label, file, conflict) in displaced {
+                let mut candidate = format!("{}~{}", name, label);
+                let mut n = 0;
+                while merged.contains_key(&candidate) {
+                    n += 1;
+                    candidate = format!("{}~{}_{}", name, label, n);
+                }
+                self.conflicts[conflict].path = if prefix.is_empty() { candidate.clone() } else { format!("{}/{}", prefix, candidate) };
+                merged.insert(candidate, file);
+            }
+
+            if merged.is_empty() {
+                return Ok(None);
+            }
+            let entries = merged.into_iter()
+                .map(|(name, e)| TreeEntry { name, mode: e.mode, hash: e.id, entry_type: e.kind, raw_name: None })
+                .collect();
+            Ok(Some(store(self.storage, GitObject::Tree(Tree::new(entries))).await?))
+        })
+    }
+
+    /// Merge the non-directory entries of one path, returning the result and the
+    /// kind of conflict, if any
+    async fn merge_file(
+        &mut self,
+        base: Option<Entry>,
+        ours: Option<Entry>,
+        theirs: Option<Entry>,
+    ) -> Result<(Option<Entry>, Option<ConflictKind>)> {
+        if let Some(entry) = trivial(base, ours, theirs) {
+            return Ok((entry, None));
+        }
+        let (o, t) = match (ours, theirs) {
+            (Some(o), Some(t)) => (o, t),
+            // Changed on one side, deleted on the other: keep the change
+            (o, t) => return Ok((o.or(t), Some(ConflictKind::ModifyDelete))),
+        };
+        let added = base.is_none();
+
+        let (mode, mode_conflict) = match trivial(base.map(|b| b.mode), Some(o.mode), Some(t.mode)) {
+            Some(mode) => (mode.unwrap_or(o.mode), false),
+            None => (o.mode, true),
+        };
+        if o.id == t.id {
+            let kind = mode_conflict.then_some(ConflictKind::Mode);
+            return Ok((Some(Entry { mode, ..o }), kind));
+        }
+        let conflict_kind = if added { ConflictKind::AddAdd } else { ConflictKind::Content };
+
+        // Only regular files merge line by line
+        let mergeable = |e: &Entry| e.kind == ObjectType::Blob && e.mode != FileMode::Symlink;
+        let base_blob = base.filter(mergeable);
+        if !mergeable(&o) || !mergeable(&t) || base.is_some_and(|b| !mergeable(&b)) {
+            return Ok((Some(o), Some(conflict_kind)));
+        }
+
+        let base_content = self.load_blob(base_blob).await?;
+        let ours_content = self.load_blob(Some(o)).await?;
+        let theirs_content = self.load_blob(Some(t)).await?;
+        let result = merge_content(&base_content, &ours_content, &theirs_content, self.options);
+        let clean = result.is_clean();
+        let id = store(self.storage, GitObject::Blob(Blob::new(bytes::Bytes::from(result.content)))).await?;
+        let kind = if !clean {
+            Some(conflict_kind)
+        } else if mode_conflict {
+            Some(ConflictKind::Mode)
+        } else {
+            None
+        };
+        Ok((Some(Entry { mode, id, kind: ObjectType::Blob }), kind))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use gitnext_storage::MemoryStorage;
+
+    /// Store a tree of `(path, mode, content)` files
+    pub(crate) async fn build_tree(storage: &dyn Storage, files: &[(&str, FileMode, &str)]) -> ObjectId {
+        let mut root: BTreeMap<String, Vec<(&str, FileMode, &str)>> = BTreeMap::new();
+        let mut entries = Vec::new();
+        for &(path, mode, content) in files {
+            match path.split_once('/') {
+                Some((dir, rest)) => root.entry(dir.to_string()).or_default().push((rest, mode, content)),
+                None => {
+                    let blob = GitObject::Blob(Blob::new(bytes::Bytes::from(content.to_string())));
+                    let hash = store(storage, blob).await.unwrap();
+                    entries.push(TreeEntry { name: path.to_string(), mode, hash, entry_type: ObjectType::Blob, raw_name: None });
+                }
+            }
+        }
+        for (name, files) in root {
+            let hash = Box::pin(build_tree(storage, &files)).await;
+            entries.push(TreeEntry { name, mode: FileMode::Tree, hash, entry_type: ObjectType::Tree, raw_name: None });
+        }
+        store(storage, GitObject::Tree(Tree::new(entries))).await.unwrap()
+    }
+
+    /// Every file of a tree with its mode and content
+    pub(crate) async fn read_tree(storage: &dyn Storage, tree: &ObjectId) -> BTreeMap<String, (FileMode, String)> {
+        let mut files = BTreeMap::new();
+        let mut stack = vec![(String::new(), *tree)];
+        while let Some((prefix, id)) = stack.pop() {
+            let Some(GitObject::Tree(tree)) = storage.load_object(&id).await.unwrap() else { panic!("not a tree") };
+            for entry in tree.entries {
+                let path = format!("{}{}", prefix, entry.name);
+                if entry.entry_type == ObjectType::Tree {
+                    stack.push((format!("{}/", path), entry.hash));
+                } else {
+                    let Some(GitObject::Blob(blob)) = storage.load_object(&entry.hash).await.unwrap() else { panic!("not a blob") };
+                    let content = String::from_utf8(blob.content.unwrap_or_default().to_vec()).unwrap();
+                    files.insert(path, (entry.mode, content));
+                }
+            }
+        }
+        files
+    }
+
+    const N: FileMode = FileMode::Normal;
+    const X: FileMode = FileMode::Executable;
+
+    async fn merge(
+        base: &[(&str, FileMode, &str)],
+        ours: &[(&str, FileMode, &str)],
+        theirs: &[(&str, FileMode, &str)],
+    ) -> (BTreeMap<String, (FileMode, String)>, Vec<(String, ConflictKind)>) {
+        let storage = MemoryStorage::new();
+        let base = build_tree(&storage, base).await;
+        let ours = build_tree(&storage, ours).await;
+        let theirs = build_tree(&storage, theirs).await;
+        let result = merge_trees(&storage, Some(&base), &ours, &theirs, &MergeOptions::default()).await.unwrap();
+        let conflicts = result.conflicts.iter().map(|c| (c.path.clone(), c.kind)).collect();
+        (read_tree(&storage, &result.tree).await, conflicts)
+    }
+
+    fn files(files: &[(&str, FileMode, &str)]) -> BTreeMap<String, (FileMode, String)> {
+        files.iter().map(|&(path, mode, content)| (path.to_string(), (mode, content.to_string()))).collect()
+    }
+
+    #[tokio::test]
+    async fn test_clean_tree_merge() {
+        let base = [("a.txt", N, "1\n2\n3\n4\n5\n"), ("dir/old.txt", N, "old\n"), ("run.sh", N, "run\n")];
+        let ours = [("a.txt", N, "one\n2\n3\n4\n5\n"), ("dir/old.txt", N, "old\n"), ("dir/new.txt", N, "new\n"), ("run.sh", X, "run\n")];
+        let theirs = [("a.txt", N, "1\n2\n3\n4\nfive\n"), ("run.sh", N, "run again\n"), ("added.txt", N, "added\n")];
+        let (merged, conflicts) = merge(&base, &ours, &theirs).await;
+        assert!(conflicts.is_empty(), "{:?}", conflicts);
+        assert_eq!(merged, files(&[
+            ("a.txt", N, "one\n2\n3\n4\nfive\n"),
+            ("added.txt", N, "added\n"),
+            ("dir/new.txt", N, "new\n"),
+            ("run.sh", X, "run again\n"),
+        ]));
+    }
+
+    #[tokio::test]
+    async fn test_tree_conflicts() {
+        let base = [("content.txt", N, "base\n"), ("deleted.txt", N, "base\n"), ("mode.sh", N, "x\n"), ("path", N, "file\n")];
+        let ours = [
+            ("content.txt", N, "ours\n"),
+            ("added.txt", N, "ours\n"),
+            ("mode.sh", X, "x\n"),
+            ("path", N, "file edited\n"),
+        ];
+        let theirs = [
+            ("content.txt", N, "theirs\n"),
+            ("added.txt", N, "theirs\n"),
+            ("deleted.txt", N, "edited\n"),
+            ("mode.sh", FileMode::Symlink, "x\n"),
+            ("path/inside.txt", N, "now a directory\n"),
+        ];
+        let (merged, conflicts) = merge(&base, &ours, &theirs).await;
+        assert_eq!(conflicts, vec![
+            ("added.txt".to_string(), ConflictKind::AddAdd),
+            ("content.txt".to_string(), ConflictKind::Content),
+            ("deleted.txt".to_string(), ConflictKind::ModifyDelete),
+            ("mode.sh".to_string(), ConflictKind::Mode),
+            ("path~ours".to_string(), ConflictKind::FileDirectory),
+        ]);
+        assert_eq!(merged["content.txt"].1, "<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n");
+        assert_eq!(merged["added.txt"].1, "<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n");
+        assert_eq!(merged["deleted.txt"], (N, "edited\n".to_string()));
+        assert_eq!(merged["mode.sh"].0, X);
+        assert_eq!(merged["path~ours"].1, "file edited\n");
+        assert_eq!(merged["path/inside.txt"].1, "now a directory\n");
+    }
+
+    #[tokio::test]
+    async fn test_directory_deleted_on_one_side() {
+        let base = [("dir/a.txt", N, "a\n"), ("dir/b.txt", N, "b\n")];
+        let ours: [(&str, FileMode, &str); 0] = [];
+        let (merged, conflicts) = merge(&base, &ours, &[("dir/a.txt", N, "a\n"), ("dir/b.txt", N, "b\n"), ("c", N, "c\n")]).await;
+        assert!(conflicts.is_empty());
+        assert_eq!(merged, files(&[("c", N, "c\n")]));
+
+        let (merged, conflicts) = merge(&base, &ours, &[("dir/a.txt", N, "a\n"), ("dir/b.txt", N, "b edited\n")]).await;
+        assert_eq!(conflicts, vec![("dir/b.txt".to_string(), ConflictKind::ModifyDelete)]);
+        assert_eq!(merged, files(&[("dir/b.txt", N, "b edited\n")]));
+    }
+
+    #[tokio::test]
+    async fn test_merge_follows_renames() {
+        let content = "1\n2\n3\n4\n5\n6\n7\n8\n";
+        let edited = "1\n2\n3\n4\n5\n6\n7\neight\n";
+        let renamed = "one\n2\n3\n4\n5\n6\n7\n8\n";
+        let base = [("src/file.txt", N, content), ("other.txt", N, "other\n")];
+
+        // Renamed (and edited) on one side, edited on the other
+        let (merged, conflicts) = merge(&base, &[("lib/file.txt", N, renamed), ("other.txt", N, "other\n")], &[("src/file.txt", N, edited), ("other.txt", N, "other\n")]).await;
+        assert!(conflicts.is_empty(), "{:?}", conflicts);
+        assert_eq!(merged, files(&[("lib/file.txt", N, "one\n2\n3\n4\n5\n6\n7\neight\n"), ("other.txt", N, "other\n")]));
+        let (merged, conflicts) = merge(&base, &[("src/file.txt", N, edited), ("other.txt", N, "other\n")], &[("lib/file.txt", N, renamed), ("other.txt", N, "other\n")]).await;
+        assert!(conflicts.is_empty(), "{:?}", conflicts);
+        assert_eq!(merged["lib/file.txt"].1, "one\n2\n3\n4\n5\n6\n7\neight\n");
+
+        // Renamed the same way on both sides
+        let (merged, conflicts) = merge(&base, &[("moved.txt", N, renamed)], &[("moved.txt", N, content), ("other.txt", N, "other\n")]).await;
+        assert!(conflicts.is_empty(), "{:?}", conflicts);
+        assert_eq!(merged, files(&[("moved.txt", N, renamed)]));
+
+        // Renamed on one side, deleted on the other
+        let (merged, conflicts) = merge(&base, &[("moved.txt", N, content), ("other.txt", N, "other\n")], &[("other.txt", N, "other\n")]).await;
+        assert_eq!(conflicts, vec![("moved.txt".to_string(), ConflictKind::RenameDelete)]);
+        assert_eq!(merged["moved.txt"].1, content);
+
+        // Renamed differently on each side
+        let (merged, conflicts) = merge(&base, &[("a.txt", N, content), ("other.txt", N, "other\n")], &[("b.txt", N, content), ("other.txt", N, "other\n")]).await;
+        assert_eq!(conflicts, vec![
+            ("a.txt".to_string(), ConflictKind::RenameRename),
+            ("b.txt".to_string(), ConflictKind::RenameRename),
+        ]);
+        assert!(merged.contains_key("a.txt") && merged.contains_key("b.txt") && !merged.contains_key("src/file.txt"));
+
+        // Without rename detection the edit and the rename collide
+        let storage = MemoryStorage::new();
+        let base = build_tree(&storage, &base).await;
+        let ours = build_tree(&storage, &[("lib/file.txt", N, content), ("other.txt", N, "other\n")]).await;
+        let theirs = build_tree(&storage, &[("src/file.txt", N, edited), ("other.txt", N, "other\n")]).await;
+        let options = MergeOptions { renames: None, ..MergeOptions::default() };
+        let result = merge_trees(&storage, Some(&base), &ours, &theirs, &options).await.unwrap();
+        assert_eq!(result.conflicts[0].kind, ConflictKind::ModifyDelete);
+    }
+}
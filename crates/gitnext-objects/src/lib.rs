@@ -3,7 +3,7 @@
 //! This module implements ADR-002 (Canonical Object Model) with immutable structs
 //! and comprehensive validation and builder utilities.
 
-use gitnext_core::{ObjectId, ObjectType, GitObject, Blob, Tree, Commit, Tag, Signature, TreeEntry, FileMode};
+use gitnext_core::{ObjectId, ObjectType, GitObject, Blob, Tree, Commit, Tag, Signature, TreeEntry, FileMode, ExtraHeader};
 use thiserror::Error;
 
 /// Object-specific error types
@@ -154,7 +154,9 @@ impl ObjectOps for Tag {
             return Err(ObjectError::MissingField("message".to_string()));
         }
         
-        self.tagger.validate()?;
+        if let Some(tagger) = &self.tagger {
+            tagger.validate()?;
+        }
         
         Ok(())
     }
@@ -163,7 +165,7 @@ impl ObjectOps for Tag {
         // Approximate size calculation
         32 + // target hash
         self.name.len() as u64 +
-        self.tagger.size() +
+        self.tagger.as_ref().map_or(0, |tagger| tagger.size()) +
         self.message.len() as u64
     }
     
@@ -218,6 +220,7 @@ impl ObjectOps for TreeEntry {
         match (self.mode, self.entry_type) {
             (FileMode::Tree, ObjectType::Tree) => Ok(()),
             (FileMode::Normal | FileMode::Executable | FileMode::Symlink, ObjectType::Blob) => Ok(()),
+            (FileMode::Gitlink, ObjectType::Commit) => Ok(()),
             _ => Err(ObjectError::InvalidTreeEntry(
                 format!("Mode {:?} doesn't match type {:?}", self.mode, self.entry_type)
             )),
@@ -249,7 +252,7 @@ impl BlobBuilder {
     }
     
     pub fn build(self) -> Blob {
-        let content = self.content.unwrap_or_else(|| bytes::Bytes::new());
+        let content = self.content.unwrap_or_default();
         Blob::new(content)
     }
 }
@@ -275,6 +278,7 @@ impl TreeBuilder {
             mode,
             hash,
             entry_type,
+            raw_name: None,
         });
         self
     }
@@ -295,6 +299,7 @@ pub struct CommitBuilder {
     parents: Vec<ObjectId>,
     author: Option<Signature>,
     committer: Option<Signature>,
+    extra_headers: Vec<ExtraHeader>,
     message: String,
 }
 
@@ -305,6 +310,7 @@ impl CommitBuilder {
             parents: Vec::new(),
             author: None,
             committer: None,
+            extra_headers: Vec::new(),
             message: String::new(),
         }
     }
@@ -329,6 +335,12 @@ impl CommitBuilder {
         self
     }
     
+    /// Append a header written after `committer` (e.g. `encoding`, `gpgsig`)
+    pub fn extra_header(mut self, name: impl Into<String>, value: impl Into<bytes::Bytes>) -> Self {
+        self.extra_headers.push(ExtraHeader::new(name, value));
+        self
+    }
+    
     pub fn message(mut self, message: impl Into<String>) -> Self {
         self.message = message.into();
         self
@@ -344,7 +356,9 @@ impl CommitBuilder {
             parents: self.parents,
             author,
             committer,
+            extra_headers: self.extra_headers,
             message: self.message,
+            raw_message: None,
         })
     }
 }
@@ -401,6 +415,7 @@ mod tests {
             email: "test@example.com".to_string(),
             timestamp: 1234567890,
             timezone_offset: 0,
+            raw: None,
         };
         
         let commit = CommitBuilder::new()
@@ -427,6 +442,7 @@ mod tests {
             email: "test@example.com".to_string(),
             timestamp: 1234567890,
             timezone_offset: 0,
+            raw: None,
         };
         assert!(valid_sig.validate().is_ok());
         
@@ -435,6 +451,7 @@ mod tests {
             email: "invalid-email".to_string(),
             timestamp: 1234567890,
             timezone_offset: 0,
+            raw: None,
         };
         assert!(invalid_sig.validate().is_err());
     }
@@ -448,6 +465,7 @@ mod tests {
             mode: FileMode::Normal,
             hash,
             entry_type: ObjectType::Blob,
+            raw_name: None,
         };
         assert!(valid_entry.validate().is_ok());
         
@@ -456,6 +474,7 @@ mod tests {
             mode: FileMode::Normal,
             hash,
             entry_type: ObjectType::Blob,
+            raw_name: None,
         };
         assert!(invalid_entry.validate().is_err());
     }
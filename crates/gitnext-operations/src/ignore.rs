@@ -0,0 +1,257 @@
+//! `.gitignore` matching
+//!
+//! Follows gitignore(5): blank lines and `#` comments are skipped, `!` re-includes,
+//! a trailing `/` matches only directories, and a pattern containing any other `/`
+//! is anchored to the directory of its `.gitignore`. Patterns in deeper files take
+//! precedence, and within a file the last matching pattern wins. Wildcards follow
+//! Git's wildmatch: `*` and `?` stop at `/`, `**` spans directories.
+
+use crate::index::is_within;
+
+#[derive(Debug, Clone)]
+struct IgnorePattern {
+    /// Directory of the `.gitignore` the pattern came from, `""` at the top
+    base: String,
+    pattern: Vec<u8>,
+    negated: bool,
+    dir_only: bool,
+    /// Matched against the path relative to `base` rather than the file name
+    anchored: bool,
+}
+
+/// Patterns from every `.gitignore` loaded so far
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the patterns of the `.gitignore` in directory `base`. Files must be added
+    /// parents first, so that deeper patterns take precedence.
+    pub fn add_patterns(&mut self, base: &str, contents: &str) {
+        for line in contents.lines() {
+            let mut line = line.trim_end_matches('\r');
+            // Trailing spaces are dropped unless escaped
+            while line.ends_with(' ') && !line.ends_with("\\ ") {
+                line = &line[..line.len() - 1];
+            }
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = match line.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, line.strip_prefix('\\').filter(|rest| rest.starts_with(['!', '#'])).unwrap_or(line)),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(rest) => (true, rest),
+                None => (false, line),
+            };
+            if line.is_empty() {
+                continue;
+            }
+            let anchored = line.contains('/');
+            self.patterns.push(IgnorePattern {
+                base: base.to_string(),
+                pattern: line.trim_start_matches('/').as_bytes().to_vec(),
+                negated,
+                dir_only,
+                anchored,
+            });
+        }
+    }
+
+    /// Whether `path` (relative to the top of the tree) is ignored by its own name.
+    /// Callers walking a tree do not descend into ignored directories.
+    pub fn is_ignored(&self, path: &str, is_dir: bool) -> bool {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        for pattern in self.patterns.iter().rev() {
+            if pattern.dir_only && !is_dir {
+                continue;
+            }
+            if path == pattern.base || !is_within(path, &pattern.base) {
+                continue;
+            }
+            let matched = if pattern.anchored {
+                let relative = if pattern.base.is_empty() { path } else { &path[pattern.base.len() + 1..] };
+                wildmatch(&pattern.pattern, relative.as_bytes())
+            } else {
+                wildmatch(&pattern.pattern, name.as_bytes())
+            };
+            if matched {
+                return !pattern.negated;
+            }
+        }
+        false
+    }
+}
+
+/// Whether `text` matches the glob `pattern`, with `/` only matched literally or by `**`
+pub fn wildmatch(pattern: &[u8], text: &[u8]) -> bool {
+    match_from(pattern, 0, text, 0)
+}
+
+fn match_from(p: &[u8], mut pi: usize, t: &[u8], mut ti: usize) -> bool {
+    while pi < p.len() {
+        match p[pi] {
+            b'*' => {
+                let double = p.get(pi + 1) == Some(&b'*');
+                let segment_start = pi == 0 || p[pi - 1] == b'/';
+                let segment_end = p.get(pi + 2).is_none_or(|&c| c == b'/');
+                if double && segment_start && segment_end {
+                    // `**` as a whole segment: any number of directories
+                    let rest = pi + 2;
+                    if rest == p.len() {
+                        return true;
+                    }
+                    // `**/` also matches nothing at all
+                    if match_from(p, rest + 1, t, ti) {
+                        return true;
+                    }
+                    return (ti..t.len()).any(|i| t[i] == b'/' && match_from(p, rest + 1, t, i + 1));
+                }
+                // A plain `*` (or `**` within a segment) stays within one directory
+                let mut rest = pi + 1;
+                while p.get(rest) == Some(&b'*') {
+                    rest += 1;
+                }
+                for i in ti..=t.len() {
+                    if match_from(p, rest, t, i) {
+                        return true;
+                    }
+                    if i < t.len() && t[i] == b'/' {
+                        break;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if ti == t.len() || t[ti] == b'/' {
+                    return false;
+                }
+            }
+            b'[' => match match_class(p, pi, t.get(ti).copied()) {
+                Some((matched, next)) => {
+                    if !matched {
+                        return false;
+                    }
+                    pi = next;
+                    ti += 1;
+                    continue;
+                }
+                // An unterminated class is a literal `[`
+                None => {
+                    if t.get(ti) != Some(&b'[') {
+                        return false;
+                    }
+                }
+            },
+            b'\\' if pi + 1 < p.len() => {
+                pi += 1;
+                if t.get(ti) != Some(&p[pi]) {
+                    return false;
+                }
+            }
+            c => {
+                if t.get(ti) != Some(&c) {
+                    return false;
+                }
+            }
+        }
+        pi += 1;
+        ti += 1;
+    }
+    ti == t.len()
+}
+
+/// Match `c` against the bracket expression at `p[start]`, returning whether it
+/// matched and the index after the expression; `None` when it is unterminated
+fn match_class(p: &[u8], start: usize, c: Option<u8>) -> Option<(bool, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(p.get(i), Some(b'!' | b'^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        let mut low = *p.get(i)?;
+        if low == b']' && !first {
+            break;
+        }
+        first = false;
+        if low == b'\\' {
+            i += 1;
+            low = *p.get(i)?;
+        }
+        let mut high = low;
+        if p.get(i + 1) == Some(&b'-') && p.get(i + 2).is_some_and(|&h| h != b']') {
+            i += 2;
+            high = p[i];
+            if high == b'\\' {
+                i += 1;
+                high = *p.get(i)?;
+            }
+        }
+        if let Some(c) = c {
+            matched |= low <= c && c <= high;
+        }
+        i += 1;
+    }
+    let matched = match c {
+        Some(b'/') | None => false,
+        Some(_) => matched != negated,
+    };
+    Some((matched, i + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wildmatch() {
+        for (pattern, text) in [
+            ("*.o", "main.o"),
+            ("a?c", "abc"),
+            ("[a-c]x", "bx"),
+            ("[!a-c]x", "dx"),
+            ("**/build", "build"),
+            ("**/build", "a/b/build"),
+            ("docs/**", "docs/a/b.md"),
+            ("a/**/b", "a/b"),
+            ("a/**/b", "a/x/y/b"),
+            ("\\*literal", "*literal"),
+        ] {
+            assert!(wildmatch(pattern.as_bytes(), text.as_bytes()), "{} ~ {}", pattern, text);
+        }
+        for (pattern, text) in [("*.o", "dir/main.o"), ("a?c", "a/c"), ("[a-c]x", "dx"), ("a/**/b", "a/xb"), ("docs/**", "docs")] {
+            assert!(!wildmatch(pattern.as_bytes(), text.as_bytes()), "{} !~ {}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn test_ignore_rules() {
+        let mut rules = IgnoreRules::new();
+        rules.add_patterns("", "# build output\n*.log\n!keep.log\n/target\nbuild/\ndocs/*.tmp\n");
+        rules.add_patterns("sub", "!*.log\nlocal\n");
+
+        assert!(rules.is_ignored("debug.log", false));
+        assert!(rules.is_ignored("deep/dir/debug.log", false));
+        assert!(!rules.is_ignored("keep.log", false));
+        assert!(rules.is_ignored("target", true));
+        assert!(!rules.is_ignored("src/target", true));
+        assert!(rules.is_ignored("src/build", true));
+        assert!(!rules.is_ignored("build", false));
+        assert!(rules.is_ignored("docs/a.tmp", false));
+        assert!(!rules.is_ignored("docs/sub/a.tmp", false));
+
+        // Deeper files override
+        assert!(!rules.is_ignored("sub/debug.log", false));
+        assert!(rules.is_ignored("sub/local", false));
+        assert!(!rules.is_ignored("local", false));
+    }
+}
@@ -0,0 +1,372 @@
+//! Working-tree index (staging area)
+//!
+//! As in Git, the index holds one entry per path and merge stage: stage 0 for a
+//! resolved path, stages 1-3 (base, ours, theirs) while a merge conflict is pending.
+//! Each entry keeps the file's stat data so that unchanged files need not be
+//! re-hashed. The index is kept in a blob named by `refs/gitnext/index`.
+
+use gitnext_core::{FileMode, GitObject, ObjectId, ObjectType, Tree, TreeEntry};
+use gitnext_storage::metadata::{load_blob, save_blob};
+use gitnext_storage::{Storage, StorageError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::Metadata;
+use thiserror::Error;
+
+/// Internal reference naming the serialized index
+pub const INDEX_REF: &str = "refs/gitnext/index";
+
+/// Errors of index and working-tree operations
+#[derive(Debug, Error)]
+pub enum IndexError {
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Repository has no working tree")]
+    NoWorkTree,
+
+    #[error("Invalid path '{0}'")]
+    InvalidPath(String),
+
+    #[error("Pathspec '{0}' did not match any files")]
+    PathNotFound(String),
+
+    #[error("'{0}' has local modifications")]
+    LocalModifications(String),
+
+    #[error("'{0}' is unmerged")]
+    Unmerged(String),
+
+    #[error("Checkout would overwrite local changes to: {}", .0.join(", "))]
+    WouldClobber(Vec<String>),
+}
+
+/// File system metadata cached per entry, compared to detect changed files cheaply
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StatData {
+    pub ctime: i64,
+    pub ctime_nsec: u32,
+    pub mtime: i64,
+    pub mtime_nsec: u32,
+    pub dev: u64,
+    pub ino: u64,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+}
+
+impl StatData {
+    /// Stat data of a file, from `symlink_metadata`
+    #[cfg(unix)]
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        use std::os::unix::fs::MetadataExt;
+        Self {
+            ctime: metadata.ctime(),
+            ctime_nsec: metadata.ctime_nsec() as u32,
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec() as u32,
+            dev: metadata.dev(),
+            ino: metadata.ino(),
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size(),
+        }
+    }
+
+    /// Stat data of a file, from `symlink_metadata`
+    #[cfg(not(unix))]
+    pub fn from_metadata(metadata: &Metadata) -> Self {
+        let mtime = metadata.modified().ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        Self {
+            mtime: mtime.as_secs() as i64,
+            mtime_nsec: mtime.subsec_nanos(),
+            size: metadata.len(),
+            ..Self::default()
+        }
+    }
+}
+
+/// One path at one merge stage
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    /// Path relative to the top of the working tree, `/`-separated
+    pub path: String,
+    pub mode: FileMode,
+    pub id: ObjectId,
+    /// 0 when resolved; 1 (base), 2 (ours) or 3 (theirs) during a conflict
+    pub stage: u8,
+    pub stat: StatData,
+}
+
+impl IndexEntry {
+    /// A resolved entry with no cached stat data
+    pub fn new(path: impl Into<String>, mode: FileMode, id: ObjectId) -> Self {
+        Self {
+            path: path.into(),
+            mode,
+            id,
+            stage: 0,
+            stat: StatData::default(),
+        }
+    }
+}
+
+/// Entries sorted by path, then stage
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexState {
+    entries: Vec<IndexEntry>,
+}
+
+/// Whether `path` is `dir` or lies beneath it; every path lies beneath ""
+pub(crate) fn is_within(path: &str, dir: &str) -> bool {
+    dir.is_empty() || path == dir || (path.starts_with(dir) && path.as_bytes().get(dir.len()) == Some(&b'/'))
+}
+
+impl IndexState {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn position(&self, path: &str, stage: u8) -> Result<usize, usize> {
+        self.entries.binary_search_by(|e| (e.path.as_str(), e.stage).cmp(&(path, stage)))
+    }
+
+    /// The resolved (stage 0) entry for `path`
+    pub fn get(&self, path: &str) -> Option<&IndexEntry> {
+        self.position(path, 0).ok().map(|i| &self.entries[i])
+    }
+
+    /// Every stage of `path`
+    pub fn stages<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a IndexEntry> + 'a {
+        let start = self.position(path, 0).unwrap_or_else(|i| i);
+        self.entries[start..].iter().take_while(move |e| e.path == path)
+    }
+
+    /// Whether any stage of `path` is present
+    pub fn contains(&self, path: &str) -> bool {
+        self.stages(path).next().is_some()
+    }
+
+    /// Add or replace an entry. A resolved entry replaces the conflict stages of its
+    /// path, and a file replaces a directory of the same name (and vice versa).
+    pub fn insert(&mut self, entry: IndexEntry) {
+        if entry.stage == 0 {
+            self.entries.retain(|e| {
+                e.path != entry.path && !is_within(&e.path, &entry.path) && !is_within(&entry.path, &e.path)
+            });
+        }
+        match self.position(&entry.path, entry.stage) {
+            Ok(i) => self.entries[i] = entry,
+            Err(i) => self.entries.insert(i, entry),
+        }
+    }
+
+    /// Remove every stage of `path`, returning whether anything was removed
+    pub fn remove(&mut self, path: &str) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|e| e.path != path);
+        self.entries.len() != before
+    }
+
+    /// Paths of entries at `dir` or beneath it, each once
+    pub fn paths_within(&self, dir: &str) -> Vec<String> {
+        let mut paths: Vec<String> = self.entries.iter()
+            .filter(|e| is_within(&e.path, dir))
+            .map(|e| e.path.clone())
+            .collect();
+        paths.dedup();
+        paths
+    }
+
+    /// Paths with unresolved conflict stages
+    pub fn conflicted_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.entries.iter()
+            .filter(|e| e.stage != 0)
+            .map(|e| e.path.clone())
+            .collect();
+        paths.dedup();
+        paths
+    }
+
+    pub fn has_conflicts(&self) -> bool {
+        self.entries.iter().any(|e| e.stage != 0)
+    }
+
+    /// An index matching the tree `tree_id`, without stat data (`read-tree`)
+    pub async fn from_tree(storage: &dyn Storage, tree_id: &ObjectId) -> Result<Self, StorageError> {
+        let files = flatten_tree(storage, tree_id).await?;
+        Ok(files.into_iter().map(|(path, (mode, id))| IndexEntry::new(path, mode, id)).collect())
+    }
+
+    /// Store the trees of the resolved entries, returning the root tree (`write-tree`)
+    pub async fn write_tree(&self, storage: &dyn Storage) -> Result<ObjectId, IndexError> {
+        if let Some(path) = self.conflicted_paths().into_iter().next() {
+            return Err(IndexError::Unmerged(path));
+        }
+
+        let mut root = DirNode::default();
+        for entry in &self.entries {
+            let mut node = &mut root;
+            let mut components = entry.path.split('/').peekable();
+            while let Some(name) = components.next() {
+                if components.peek().is_none() {
+                    node.files.insert(name.to_string(), (entry.mode, entry.id));
+                } else {
+                    node = node.dirs.entry(name.to_string()).or_default();
+                }
+            }
+        }
+
+        let mut trees = Vec::new();
+        let root_id = root.build(&mut trees);
+        for (id, tree) in trees {
+            storage.store_object(&id, &GitObject::Tree(tree)).await?;
+        }
+        Ok(root_id)
+    }
+
+    /// Load the persisted index, if one was saved
+    pub async fn load(storage: &dyn Storage) -> Result<Option<Self>, StorageError> {
+        load_blob(storage, INDEX_REF).await
+    }
+
+    /// Persist the index
+    pub async fn save(&self, storage: &dyn Storage) -> Result<(), StorageError> {
+        save_blob(storage, INDEX_REF, self).await
+    }
+}
+
+/// Collects entries without checking for file/directory clashes; a later entry
+/// replaces an earlier one of the same path and stage
+impl FromIterator<IndexEntry> for IndexState {
+    fn from_iter<I: IntoIterator<Item = IndexEntry>>(iter: I) -> Self {
+        let mut entries: Vec<IndexEntry> = iter.into_iter().collect();
+        entries.reverse();
+        entries.sort_by(|a, b| (a.path.as_str(), a.stage).cmp(&(b.path.as_str(), b.stage)));
+        entries.dedup_by(|a, b| a.path == b.path && a.stage == b.stage);
+        Self { entries }
+    }
+}
+
+/// A directory being assembled by `write_tree`
+#[derive(Default)]
+struct DirNode {
+    files: BTreeMap<String, (FileMode, ObjectId)>,
+    dirs: BTreeMap<String, DirNode>,
+}
+
+impl DirNode {
+    /// Tree id of this directory, pushing it and its subtrees onto `trees`
+    fn build(self, trees: &mut Vec<(ObjectId, Tree)>) -> ObjectId {
+        let mut entries: Vec<TreeEntry> = self.files.into_iter()
+            .map(|(name, (mode, hash))| TreeEntry { name, mode, hash, entry_type: ObjectType::Blob, raw_name: None })
+            .collect();
+        for (name, dir) in self.dirs {
+            let hash = dir.build(trees);
+            entries.push(TreeEntry { name, mode: FileMode::Tree, hash, entry_type: ObjectType::Tree, raw_name: None });
+        }
+        let tree = Tree::new(entries);
+        let id = GitObject::Tree(tree.clone()).canonical_hash();
+        trees.push((id, tree));
+        id
+    }
+}
+
+/// Every file of a tree by path, with its mode and blob id
+pub async fn flatten_tree(
+    storage: &dyn Storage,
+    tree_id: &ObjectId,
+) -> Result<BTreeMap<String, (FileMode, ObjectId)>, StorageError> {
+    let mut files = BTreeMap::new();
+    let mut stack = vec![(String::new(), *tree_id)];
+    while let Some((prefix, id)) = stack.pop() {
+        let tree = match storage.load_object(&id).await? {
+            Some(GitObject::Tree(tree)) => tree,
+            Some(_) => {
+                return Err(StorageError::CorruptionDetected { id, details: "Expected a tree".to_string() });
+            }
+            None => return Err(StorageError::ObjectNotFound { id }),
+        };
+        for entry in tree.entries {
+            let path = if prefix.is_empty() { entry.name } else { format!("{}/{}", prefix, entry.name) };
+            match entry.entry_type {
+                ObjectType::Tree => stack.push((path, entry.hash)),
+                // Submodule commits are not files of this repository
+                ObjectType::Commit => {}
+                _ => {
+                    files.insert(path, (entry.mode, entry.hash));
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gitnext_core::Blob;
+    use gitnext_storage::memory::MemoryStorage;
+
+    fn blob_id(content: &str) -> ObjectId {
+        GitObject::Blob(Blob::new(bytes::Bytes::from(content.to_string()))).canonical_hash()
+    }
+
+    #[test]
+    fn test_insert_keeps_order_and_replaces_conflicting_paths() {
+        let mut index = IndexState::new();
+        index.insert(IndexEntry::new("b.txt", FileMode::Normal, blob_id("b")));
+        index.insert(IndexEntry::new("a/x.txt", FileMode::Normal, blob_id("x")));
+        index.insert(IndexEntry { stage: 2, ..IndexEntry::new("c.txt", FileMode::Normal, blob_id("ours")) });
+        index.insert(IndexEntry { stage: 3, ..IndexEntry::new("c.txt", FileMode::Normal, blob_id("theirs")) });
+        let paths: Vec<_> = index.entries().iter().map(|e| (e.path.as_str(), e.stage)).collect();
+        assert_eq!(paths, vec![("a/x.txt", 0), ("b.txt", 0), ("c.txt", 2), ("c.txt", 3)]);
+        assert_eq!(index.conflicted_paths(), vec!["c.txt"]);
+
+        // Resolving drops the stages; a file replaces the directory it shadows
+        index.insert(IndexEntry::new("c.txt", FileMode::Normal, blob_id("merged")));
+        index.insert(IndexEntry::new("a", FileMode::Executable, blob_id("a")));
+        let paths: Vec<_> = index.entries().iter().map(|e| (e.path.as_str(), e.stage)).collect();
+        assert_eq!(paths, vec![("a", 0), ("b.txt", 0), ("c.txt", 0)]);
+        assert!(!index.has_conflicts());
+    }
+
+    #[tokio::test]
+    async fn test_write_tree_round_trip() {
+        let storage = MemoryStorage::new();
+        let mut index = IndexState::new();
+        for (path, content) in [("README", "readme"), ("src/lib.rs", "lib"), ("src/bin/main.rs", "main")] {
+            let object = GitObject::Blob(Blob::new(bytes::Bytes::from(content.to_string())));
+            storage.store_object(&object.canonical_hash(), &object).await.unwrap();
+            index.insert(IndexEntry::new(path, FileMode::Normal, object.canonical_hash()));
+        }
+
+        let tree = index.write_tree(&storage).await.unwrap();
+        let files = flatten_tree(&storage, &tree).await.unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), vec!["README", "src/bin/main.rs", "src/lib.rs"]);
+        assert_eq!(IndexState::from_tree(&storage, &tree).await.unwrap(), index);
+
+        index.save(&storage).await.unwrap();
+        assert_eq!(IndexState::load(&storage).await.unwrap(), Some(index.clone()));
+
+        index.insert(IndexEntry { stage: 1, ..IndexEntry::new("README", FileMode::Normal, blob_id("base")) });
+        assert!(matches!(index.write_tree(&storage).await, Err(IndexError::Unmerged(path)) if path == "README"));
+    }
+}
@@ -1 +1,7 @@
+pub mod ignore;
+pub mod index;
+pub mod merge;
+pub mod remote;
 pub mod repository;
+pub mod staging;
+pub mod worktree;
@@ -0,0 +1,574 @@
+//! Merging branches: `merge`, `merge --continue` and `merge --abort`
+//!
+//! Diverged histories are merged by `gitnext-merge` and the result is checked out
+//! into the working tree, if there is one. A clean merge is committed with both
+//! heads as parents. A merge with conflicts leaves their paths at index stages 1-3
+//! (base, ours, theirs) and the merge itself recorded under `refs/gitnext/merge-head`,
+//! Git's `MERGE_HEAD`, until it is continued or aborted. Finished merges and
+//! fast-forwards are logged as `Operation::Merge`.
+
+use crate::index::{IndexEntry, IndexError, IndexState};
+use crate::repository::{
+    CommandIntent, LogEntry, MergeResult, MergeStrategy, Operation, Repository, RepositoryState, UserMetadata,
+};
+use crate::worktree::checkout_tree;
+use chrono::Utc;
+use gitnext_core::{Commit, FileMode, GitObject, ObjectId, Signature};
+use gitnext_merge::{merge_bases, merge_commits, merge_trees, MergeOptions, TreeMerge};
+use gitnext_storage::metadata::{load_blob, save_blob};
+use gitnext_storage::{Storage, StorageError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use thiserror::Error;
+use uuid::Uuid;
+
+/// Internal reference naming the state of a merge stopped by conflicts
+pub const MERGE_HEAD_REF: &str = "refs/gitnext/merge-head";
+
+/// Errors of `merge` and its `continue` and `abort` steps
+#[derive(Debug, Error)]
+pub enum MergeError {
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error(transparent)]
+    Index(#[from] IndexError),
+
+    #[error("Merge error: {0}")]
+    Merge(#[from] gitnext_merge::MergeError),
+
+    #[error("A merge is already in progress")]
+    InProgress,
+
+    #[error("There is no merge in progress")]
+    NotInProgress,
+
+    #[error("Refusing to merge unrelated histories")]
+    UnrelatedHistories,
+
+    #[error("Merge has unresolved conflicts in: {}", .0.join(", "))]
+    Unresolved(Vec<String>),
+}
+
+/// A merge stopped by conflicts, kept in a blob named by [`MERGE_HEAD_REF`]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MergeState {
+    /// Branch or ref being merged, as given to `merge`
+    pub branch: String,
+    /// HEAD when the merge started
+    pub orig_head: ObjectId,
+    /// The commit being merged
+    pub merge_head: ObjectId,
+    pub strategy: MergeStrategy,
+    /// Message for the merge commit
+    pub message: String,
+}
+
+impl MergeState {
+    /// Load the state of the merge in progress, if any
+    pub async fn load(storage: &dyn Storage) -> Result<Option<Self>, StorageError> {
+        load_blob(storage, MERGE_HEAD_REF).await
+    }
+
+    async fn save(&self, storage: &dyn Storage) -> Result<(), StorageError> {
+        save_blob(storage, MERGE_HEAD_REF, self).await
+    }
+
+    async fn clear(storage: &dyn Storage) -> Result<(), StorageError> {
+        storage.delete_ref(MERGE_HEAD_REF).await
+    }
+}
+
+impl Repository {
+    /// Merge `branch` (a branch name, a remote-tracking branch such as
+    /// `origin/main`, or a full ref name) into HEAD.
+    ///
+    /// HEAD is fast-forwarded when it is an ancestor of `branch`. Otherwise the
+    /// heads are merged with `strategy` and, when that is clean, committed with
+    /// `signature`. On conflicts the merge is left in progress for
+    /// [`merge_continue`](Self::merge_continue) or [`merge_abort`](Self::merge_abort).
+    /// The index must match HEAD, and local changes the merge would overwrite are
+    /// refused.
+    pub async fn merge(
+        &self,
+        branch: &str,
+        strategy: MergeStrategy,
+        signature: Signature,
+    ) -> Result<MergeResult, MergeError> {
+        let storage = self.storage.as_ref();
+        if MergeState::load(storage).await?.is_some() {
+            return Err(MergeError::InProgress);
+        }
+        let (ref_name, theirs) = self.resolve_merge_ref(branch).await?;
+        let ours = self.head().await?;
+
+        let bases = merge_bases(storage, &ours, &theirs).await?;
+        if bases.contains(&theirs) {
+            return Ok(MergeResult::UpToDate);
+        }
+        if bases.is_empty() {
+            return Err(MergeError::UnrelatedHistories);
+        }
+
+        let before_refs = self.get_all_refs().await?;
+        let before_index = self.index().await?;
+        let ours_tree = self.commit_tree(&ours).await?;
+        require_clean_index(&before_index, &IndexState::from_tree(storage, &ours_tree).await?)?;
+
+        if bases.contains(&ours) {
+            let tree = self.commit_tree(&theirs).await?;
+            let index = self.check_out_merge(&tree, &before_index).await?;
+            self.finish_merge(branch, strategy, ours, theirs, before_refs, before_index, index).await?;
+            return Ok(MergeResult::FastForward { commit: theirs });
+        }
+
+        let options = MergeOptions {
+            ours_label: "HEAD".to_string(),
+            theirs_label: branch.to_string(),
+            ..MergeOptions::default()
+        };
+        let theirs_tree = self.commit_tree(&theirs).await?;
+        let merged = match strategy {
+            MergeStrategy::Recursive => merge_commits(storage, &ours, &theirs, &options).await?,
+            MergeStrategy::ThreeWay => {
+                let base = self.commit_tree(&bases[0]).await?;
+                merge_trees(storage, Some(&base), &ours_tree, &theirs_tree, &options).await?
+            }
+            MergeStrategy::Ours => TreeMerge { tree: ours_tree, conflicts: Vec::new() },
+            MergeStrategy::Theirs => TreeMerge { tree: theirs_tree, conflicts: Vec::new() },
+        };
+        let mut index = self.check_out_merge(&merged.tree, &before_index).await?;
+        let message = merge_message(branch, &ref_name);
+
+        if merged.is_clean() {
+            let commit = self.write_merge_commit(&merged.tree, ours, theirs, signature, message).await?;
+            self.finish_merge(branch, strategy, ours, commit, before_refs, before_index, index).await?;
+            return Ok(MergeResult::Success { commit });
+        }
+
+        for conflict in &merged.conflicts {
+            index.remove(&conflict.path);
+            for (stage, version) in [(1, &conflict.base), (2, &conflict.ours), (3, &conflict.theirs)] {
+                if let Some(version) = version {
+                    let mut entry = IndexEntry::new(conflict.path.clone(), version.mode, version.id);
+                    entry.stage = stage;
+                    index.insert(entry);
+                }
+            }
+        }
+        index.save(storage).await?;
+        let state = MergeState {
+            branch: branch.to_string(),
+            orig_head: ours,
+            merge_head: theirs,
+            strategy,
+            message,
+        };
+        state.save(storage).await?;
+        Ok(MergeResult::Conflicts { conflicted_files: index.conflicted_paths() })
+    }
+
+    /// The merge in progress, if any
+    pub async fn merge_state(&self) -> Result<Option<MergeState>, StorageError> {
+        MergeState::load(self.storage.as_ref()).await
+    }
+
+    /// Commit the merge in progress from the index once every conflict is resolved
+    /// (`merge --continue`)
+    pub async fn merge_continue(&self, signature: Signature) -> Result<ObjectId, MergeError> {
+        let storage = self.storage.as_ref();
+        let state = MergeState::load(storage).await?.ok_or(MergeError::NotInProgress)?;
+        let index = self.index().await?;
+        let conflicted = index.conflicted_paths();
+        if !conflicted.is_empty() {
+            return Err(MergeError::Unresolved(conflicted));
+        }
+
+        let before_refs = self.get_all_refs().await?;
+        let head = self.head().await?;
+        let before_index = IndexState::from_tree(storage, &self.commit_tree(&state.orig_head).await?).await?;
+        let tree = index.write_tree(storage).await?;
+        let commit = self.write_merge_commit(&tree, head, state.merge_head, signature, state.message).await?;
+        MergeState::clear(storage).await?;
+        self.finish_merge(&state.branch, state.strategy, head, commit, before_refs, before_index, index).await?;
+        Ok(commit)
+    }
+
+    /// Give up the merge in progress, returning the index and the files the merge
+    /// touched to HEAD (`merge --abort`). Local changes to other files are kept.
+    pub async fn merge_abort(&self) -> Result<(), MergeError> {
+        let storage = self.storage.as_ref();
+        if MergeState::load(storage).await?.is_none() {
+            return Err(MergeError::NotInProgress);
+        }
+        let tree = self.commit_tree(&self.head().await?).await?;
+        let index = match self.work_tree() {
+            Some(root) => checkout_tree(storage, &tree, root, &self.index().await?, true).await?,
+            None => IndexState::from_tree(storage, &tree).await?,
+        };
+        index.save(storage).await?;
+        MergeState::clear(storage).await?;
+        Ok(())
+    }
+
+    /// Full ref name and target of the branch to merge
+    async fn resolve_merge_ref(&self, branch: &str) -> Result<(String, ObjectId), StorageError> {
+        let refs = self.get_all_refs().await?;
+        let candidates = if branch.starts_with("refs/") {
+            vec![branch.to_string()]
+        } else {
+            vec![format!("refs/heads/{}", branch), format!("refs/remotes/{}", branch)]
+        };
+        candidates.into_iter()
+            .find_map(|name| refs.get(&name).map(|id| (name, *id)))
+            .ok_or_else(|| StorageError::RefNotFound { name: branch.to_string() })
+    }
+
+    async fn commit_tree(&self, id: &ObjectId) -> Result<ObjectId, StorageError> {
+        match self.storage.load_object(id).await? {
+            Some(GitObject::Commit(commit)) => Ok(commit.tree),
+            Some(_) => Err(StorageError::CorruptionDetected { id: *id, details: "Not a commit".to_string() }),
+            None => Err(StorageError::ObjectNotFound { id: *id }),
+        }
+    }
+
+    /// The index for the merge result `tree`, checked out into the working tree if
+    /// there is one
+    async fn check_out_merge(&self, tree: &ObjectId, current: &IndexState) -> Result<IndexState, MergeError> {
+        let storage = self.storage.as_ref();
+        Ok(match self.work_tree() {
+            Some(root) => checkout_tree(storage, tree, root, current, false).await?,
+            None => IndexState::from_tree(storage, tree).await?,
+        })
+    }
+
+    async fn write_merge_commit(
+        &self,
+        tree: &ObjectId,
+        ours: ObjectId,
+        theirs: ObjectId,
+        signature: Signature,
+        message: String,
+    ) -> Result<ObjectId, StorageError> {
+        let commit = GitObject::Commit(Commit {
+            tree: *tree,
+            parents: vec![ours, theirs],
+            author: signature.clone(),
+            committer: signature,
+            extra_headers: Vec::new(),
+            message,
+            raw_message: None,
+        });
+        let id = commit.canonical_hash();
+        self.storage.store_object(&id, &commit).await?;
+        self.index_commits([id]).await?;
+        Ok(id)
+    }
+
+    /// Move HEAD and the current branch to `after_head`, save the index and log the
+    /// merge
+    #[allow(clippy::too_many_arguments)]
+    async fn finish_merge(
+        &self,
+        branch: &str,
+        strategy: MergeStrategy,
+        before_head: ObjectId,
+        after_head: ObjectId,
+        before_refs: HashMap<String, ObjectId>,
+        before_index: IndexState,
+        after_index: IndexState,
+    ) -> Result<(), StorageError> {
+        self.storage.update_ref("HEAD", &after_head).await?;
+        if let Some(current) = self.get_current_branch().await? {
+            self.storage.update_ref(&format!("refs/heads/{}", current), &after_head).await?;
+        }
+        after_index.save(self.storage.as_ref()).await?;
+
+        let log_entry = LogEntry {
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            operation: Operation::Merge {
+                branch: branch.to_string(),
+                before_head,
+                after_head,
+                strategy,
+            },
+            before_state: RepositoryState {
+                head: Some(before_head),
+                refs: before_refs,
+                index_state: Some(before_index),
+            },
+            after_state: RepositoryState {
+                head: Some(after_head),
+                refs: self.get_all_refs().await?,
+                index_state: Some(after_index),
+            },
+            command_intent: CommandIntent {
+                command: "merge".to_string(),
+                args: vec![branch.to_string()],
+                working_directory: self.work_tree()
+                    .map(|root| root.display().to_string())
+                    .unwrap_or_else(|| ".".to_string()),
+            },
+            user_metadata: UserMetadata {
+                user_name: None,
+                user_email: None,
+                session_id: None,
+            },
+        };
+        self.operation_log.record(log_entry).await
+    }
+}
+
+/// Refuse an index with changes staged on top of `head` or with unmerged paths
+fn require_clean_index(index: &IndexState, head: &IndexState) -> Result<(), IndexError> {
+    if let Some(path) = index.conflicted_paths().into_iter().next() {
+        return Err(IndexError::Unmerged(path));
+    }
+    let files = |state: &IndexState| -> BTreeMap<String, (FileMode, ObjectId)> {
+        state.entries().iter().map(|e| (e.path.clone(), (e.mode, e.id))).collect()
+    };
+    let (staged, head) = (files(index), files(head));
+    match staged.keys().chain(head.keys()).filter(|path| staged.get(*path) != head.get(*path)).min() {
+        Some(path) => Err(IndexError::LocalModifications(path.clone())),
+        None => Ok(()),
+    }
+}
+
+/// Git's default merge message for `branch`, which resolved to `ref_name`
+fn merge_message(branch: &str, ref_name: &str) -> String {
+    if let Some(name) = ref_name.strip_prefix("refs/heads/") {
+        format!("Merge branch '{}'", name)
+    } else if let Some(name) = ref_name.strip_prefix("refs/remotes/") {
+        format!("Merge remote-tracking branch '{}'", name)
+    } else {
+        format!("Merge commit '{}'", branch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gitnext_storage::memory::MemoryStorage;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    fn signature() -> Signature {
+        Signature {
+            name: "Test Author".to_string(),
+            email: "test@example.com".to_string(),
+            timestamp: Utc::now().timestamp(),
+            timezone_offset: 0,
+            raw: None,
+        }
+    }
+
+    fn write(dir: &Path, path: &str, content: &str) {
+        let file = dir.join(path);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(file, content).unwrap();
+    }
+
+    fn read(dir: &Path, path: &str) -> String {
+        std::fs::read_to_string(dir.join(path)).unwrap()
+    }
+
+    /// A repository on `main` with `files` committed in its working tree
+    async fn repository(dir: &Path, files: &[(&str, &str)]) -> Repository {
+        let repo = Repository::init(Arc::new(MemoryStorage::new())).await.unwrap().with_work_tree(dir);
+        repo.switch_branch("main").await.unwrap();
+        for (path, content) in files {
+            write(dir, path, content);
+        }
+        commit_work_tree(&repo, "base").await;
+        repo
+    }
+
+    async fn commit_work_tree(repo: &Repository, message: &str) -> ObjectId {
+        repo.add(&["."]).await.unwrap();
+        let tree = repo.index().await.unwrap().write_tree(repo.storage.as_ref()).await.unwrap();
+        let head = repo.head().await.unwrap();
+        repo.commit(&tree, vec![head], signature(), signature(), message.to_string()).await.unwrap()
+    }
+
+    /// Create `branch` at a child of `parent` holding exactly `files`
+    async fn branch(repo: &Repository, name: &str, parent: ObjectId, files: &[(&str, &str)]) -> ObjectId {
+        let dir = tempfile::tempdir().unwrap();
+        for (path, content) in files {
+            write(dir.path(), path, content);
+        }
+        let tree = repo.write_tree_from_dir(dir.path()).await.unwrap();
+        let object = GitObject::Commit(Commit {
+            tree,
+            parents: vec![parent],
+            author: signature(),
+            committer: signature(),
+            extra_headers: Vec::new(),
+            message: name.to_string(),
+            raw_message: None,
+        });
+        let id = object.canonical_hash();
+        repo.storage.store_object(&id, &object).await.unwrap();
+        repo.create_branch(name, &id).await.unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn test_fast_forward_and_up_to_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repository(dir.path(), &[("a.txt", "a\n")]).await;
+        let base = repo.head().await.unwrap();
+        let feature = branch(&repo, "feature", base, &[("a.txt", "a\n"), ("b.txt", "b\n")]).await;
+
+        let result = repo.merge("feature", MergeStrategy::Recursive, signature()).await.unwrap();
+        assert_eq!(result, MergeResult::FastForward { commit: feature });
+        assert_eq!(repo.head().await.unwrap(), feature);
+        assert_eq!(repo.get_all_refs().await.unwrap()["refs/heads/main"], feature);
+        assert_eq!(read(dir.path(), "b.txt"), "b\n");
+        assert!(repo.status().await.unwrap().is_clean());
+
+        let result = repo.merge("feature", MergeStrategy::Recursive, signature()).await.unwrap();
+        assert_eq!(result, MergeResult::UpToDate);
+
+        // Undo moves HEAD and the index back
+        assert!(matches!(repo.undo().await.unwrap(), Some(Operation::Merge { .. })));
+        assert_eq!(repo.head().await.unwrap(), base);
+        assert!(!repo.index().await.unwrap().contains("b.txt"));
+    }
+
+    #[tokio::test]
+    async fn test_clean_merge_creates_merge_commit() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repository(dir.path(), &[("a.txt", "1\n2\n3\n4\n5\n")]).await;
+        let base = repo.head().await.unwrap();
+        let feature = branch(&repo, "feature", base, &[("a.txt", "1\n2\n3\n4\nfive\n"), ("new.txt", "new\n")]).await;
+        write(dir.path(), "a.txt", "one\n2\n3\n4\n5\n");
+        let ours = commit_work_tree(&repo, "ours").await;
+
+        let result = repo.merge("feature", MergeStrategy::Recursive, signature()).await.unwrap();
+        let MergeResult::Success { commit } = result else { panic!("{:?}", result) };
+        let Some(GitObject::Commit(merge)) = repo.storage.load_object(&commit).await.unwrap() else { panic!() };
+        assert_eq!(merge.parents, vec![ours, feature]);
+        assert_eq!(merge.message, "Merge branch 'feature'");
+        assert_eq!(read(dir.path(), "a.txt"), "one\n2\n3\n4\nfive\n");
+        assert_eq!(read(dir.path(), "new.txt"), "new\n");
+        assert!(repo.status().await.unwrap().is_clean());
+        assert_eq!(repo.get_all_refs().await.unwrap()["refs/heads/main"], commit);
+
+        assert!(repo.undo().await.unwrap().is_some());
+        assert_eq!(repo.head().await.unwrap(), ours);
+        assert!(matches!(repo.redo().await.unwrap(), Some(Operation::Merge { .. })));
+        assert_eq!(repo.head().await.unwrap(), commit);
+    }
+
+    #[tokio::test]
+    async fn test_merge_strategies() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repository(dir.path(), &[("a.txt", "base\n")]).await;
+        let base = repo.head().await.unwrap();
+        branch(&repo, "feature", base, &[("a.txt", "theirs\n")]).await;
+        write(dir.path(), "a.txt", "ours\n");
+        let ours = commit_work_tree(&repo, "ours").await;
+
+        let result = repo.merge("feature", MergeStrategy::Ours, signature()).await.unwrap();
+        assert!(matches!(result, MergeResult::Success { .. }));
+        assert_eq!(read(dir.path(), "a.txt"), "ours\n");
+        repo.undo().await.unwrap();
+        assert_eq!(repo.head().await.unwrap(), ours);
+
+        // The working tree is not rewound by undo, so check out HEAD again first
+        repo.checkout_tree(&repo.commit_tree(&ours).await.unwrap(), true).await.unwrap();
+        let result = repo.merge("feature", MergeStrategy::Theirs, signature()).await.unwrap();
+        assert!(matches!(result, MergeResult::Success { .. }));
+        assert_eq!(read(dir.path(), "a.txt"), "theirs\n");
+    }
+
+    #[tokio::test]
+    async fn test_conflicts_and_continue() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repository(dir.path(), &[("a.txt", "base\n"), ("b.txt", "b\n")]).await;
+        let base = repo.head().await.unwrap();
+        let feature = branch(&repo, "feature", base, &[("a.txt", "theirs\n"), ("b.txt", "b2\n")]).await;
+        write(dir.path(), "a.txt", "ours\n");
+        let ours = commit_work_tree(&repo, "ours").await;
+
+        let result = repo.merge("feature", MergeStrategy::Recursive, signature()).await.unwrap();
+        assert_eq!(result, MergeResult::Conflicts { conflicted_files: vec!["a.txt".to_string()] });
+        assert_eq!(read(dir.path(), "a.txt"), "<<<<<<< HEAD\nours\n=======\ntheirs\n>>>>>>> feature\n");
+        assert_eq!(read(dir.path(), "b.txt"), "b2\n");
+        assert_eq!(repo.head().await.unwrap(), ours);
+        let index = repo.index().await.unwrap();
+        assert_eq!(index.stages("a.txt").map(|e| e.stage).collect::<Vec<_>>(), vec![1, 2, 3]);
+        let state = repo.merge_state().await.unwrap().unwrap();
+        assert_eq!((state.orig_head, state.merge_head), (ours, feature));
+        assert_eq!(repo.status().await.unwrap().conflicted, vec!["a.txt".to_string()]);
+
+        assert!(matches!(repo.merge("feature", MergeStrategy::Recursive, signature()).await, Err(MergeError::InProgress)));
+        assert!(matches!(repo.merge_continue(signature()).await, Err(MergeError::Unresolved(paths)) if paths == ["a.txt"]));
+
+        write(dir.path(), "a.txt", "resolved\n");
+        repo.add(&["a.txt"]).await.unwrap();
+        let commit = repo.merge_continue(signature()).await.unwrap();
+        let Some(GitObject::Commit(merge)) = repo.storage.load_object(&commit).await.unwrap() else { panic!() };
+        assert_eq!(merge.parents, vec![ours, feature]);
+        assert_eq!(repo.head().await.unwrap(), commit);
+        assert!(repo.merge_state().await.unwrap().is_none());
+        assert!(repo.status().await.unwrap().is_clean());
+        assert!(matches!(repo.merge_continue(signature()).await, Err(MergeError::NotInProgress)));
+    }
+
+    #[tokio::test]
+    async fn test_abort_restores_head() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repository(dir.path(), &[("a.txt", "base\n"), ("local.txt", "local\n")]).await;
+        let base = repo.head().await.unwrap();
+        branch(&repo, "feature", base, &[("a.txt", "theirs\n"), ("local.txt", "local\n"), ("c.txt", "c\n")]).await;
+        write(dir.path(), "a.txt", "ours\n");
+        let ours = commit_work_tree(&repo, "ours").await;
+        // An unstaged change the merge does not touch survives it
+        write(dir.path(), "local.txt", "edited\n");
+
+        let result = repo.merge("feature", MergeStrategy::Recursive, signature()).await.unwrap();
+        assert!(matches!(result, MergeResult::Conflicts { .. }));
+        repo.merge_abort().await.unwrap();
+        assert_eq!(repo.head().await.unwrap(), ours);
+        assert!(repo.merge_state().await.unwrap().is_none());
+        assert_eq!(read(dir.path(), "a.txt"), "ours\n");
+        assert_eq!(read(dir.path(), "local.txt"), "edited\n");
+        assert!(!dir.path().join("c.txt").exists());
+        assert!(!repo.index().await.unwrap().has_conflicts());
+        assert!(matches!(repo.merge_abort().await, Err(MergeError::NotInProgress)));
+    }
+
+    #[tokio::test]
+    async fn test_merge_refuses_staged_changes_and_unrelated_histories() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repository(dir.path(), &[("a.txt", "a\n")]).await;
+        let base = repo.head().await.unwrap();
+        branch(&repo, "feature", base, &[("a.txt", "a\n"), ("b.txt", "b\n")]).await;
+
+        write(dir.path(), "a.txt", "staged\n");
+        repo.add(&["a.txt"]).await.unwrap();
+        let result = repo.merge("feature", MergeStrategy::Recursive, signature()).await;
+        assert!(matches!(result, Err(MergeError::Index(IndexError::LocalModifications(path))) if path == "a.txt"));
+        repo.undo().await.unwrap();
+
+        let storage = repo.storage.as_ref();
+        let tree = IndexState::new().write_tree(storage).await.unwrap();
+        let orphan = GitObject::Commit(Commit {
+            tree,
+            parents: Vec::new(),
+            author: signature(),
+            committer: signature(),
+            extra_headers: Vec::new(),
+            message: "orphan".to_string(),
+            raw_message: None,
+        });
+        let orphan_id = orphan.canonical_hash();
+        storage.store_object(&orphan_id, &orphan).await.unwrap();
+        repo.create_branch("orphan", &orphan_id).await.unwrap();
+        let result = repo.merge("orphan", MergeStrategy::Recursive, signature()).await;
+        assert!(matches!(result, Err(MergeError::UnrelatedHistories)));
+        assert!(matches!(repo.merge("missing", MergeStrategy::Recursive, signature()).await, Err(MergeError::Storage(_))));
+    }
+}
@@ -0,0 +1,443 @@
+//! Remote repositories: clone, fetch and push (Requirements 3.1, 3.2)
+//!
+//! Remote URLs are kept in blobs named by `refs/gitnext/remotes/<name>`. Fetched branches land in `refs/remotes/<name>/*`;
+//! tags are created under `refs/tags/*` when missing locally. Objects cross the
+//! boundary as Git packs, with Git hashes recorded in the compat `GitHashIndex`.
+
+use crate::repository::{CommandIntent, LogEntry, Operation, Repository, RepositoryState, UserMetadata};
+use gitnext_compat::{valid_ref_name, GitHashIndex, CURRENT_BRANCH_REF};
+use gitnext_core::{GitHash, GitHashType, GitObject, ObjectId};
+use gitnext_protocol::{
+    build_pack, connect, import_pack, resolve_thin_pack, ProtocolError, PushReport, RefUpdate, RemoteRef,
+};
+use gitnext_storage::metadata::{load_blob_content, save_blob_content};
+use gitnext_storage::{Storage, StorageError};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Prefix of the internal refs naming remote URL blobs
+pub const REMOTE_URL_REF_PREFIX: &str = "refs/gitnext/remotes/";
+
+/// Remote created by `clone_from`
+pub const DEFAULT_REMOTE: &str = "origin";
+
+/// Most local commits offered as `have`s during a fetch
+const MAX_HAVES: usize = 256;
+
+/// Summary of a fetch
+#[derive(Debug, Clone, Default)]
+pub struct FetchReport {
+    /// Objects stored that were not present before
+    pub objects_imported: usize,
+    /// Local refs created or moved, with their new targets
+    pub updated_refs: HashMap<String, ObjectId>,
+}
+
+/// One parsed `[+]<src>[:<dst>]` push refspec
+struct PushSpec {
+    force: bool,
+    /// Local ref to push; `None` deletes `dst`
+    src: Option<(String, ObjectId)>,
+    dst: String,
+}
+
+async fn store_text_ref(storage: &dyn Storage, name: &str, text: &str) -> Result<(), StorageError> {
+    save_blob_content(storage, name, text.as_bytes().to_vec()).await
+}
+
+/// Local ref a fetched remote ref is stored under, if it is fetched at all;
+/// refs whose names Git would refuse are skipped
+fn tracking_ref(remote: &str, name: &str) -> Option<String> {
+    if !valid_ref_name(name) {
+        return None;
+    }
+    let local = if let Some(branch) = name.strip_prefix("refs/heads/") {
+        format!("refs/remotes/{}/{}", remote, branch)
+    } else if name.starts_with("refs/tags/") {
+        name.to_string()
+    } else {
+        return None;
+    };
+    valid_ref_name(&local).then_some(local)
+}
+
+/// Import the objects of `wants` not already known, returning how many were stored
+async fn fetch_objects(
+    storage: &dyn Storage,
+    index: &mut GitHashIndex,
+    transport: &mut dyn gitnext_protocol::Transport,
+    wants: Vec<GitHash>,
+    haves: &[GitHash],
+) -> Result<usize, ProtocolError> {
+    if wants.is_empty() {
+        return Ok(0);
+    }
+    let mut pack = transport.fetch_packfile(&wants, haves).await?;
+    resolve_thin_pack(&mut pack, storage, index).await?;
+    let imported = import_pack(storage, index, &pack, true).await?;
+    index.save().await?;
+    Ok(imported)
+}
+
+/// Whether `ancestor` is reachable from `descendant` through commit parents
+async fn is_ancestor(storage: &dyn Storage, ancestor: &ObjectId, descendant: &ObjectId) -> Result<bool, StorageError> {
+    let mut stack = vec![*descendant];
+    let mut seen = HashSet::new();
+    while let Some(id) = stack.pop() {
+        if id == *ancestor {
+            return Ok(true);
+        }
+        if !seen.insert(id) {
+            continue;
+        }
+        if let Some(GitObject::Commit(commit)) = storage.load_object(&id).await? {
+            stack.extend(commit.parents);
+        }
+    }
+    Ok(false)
+}
+
+impl Repository {
+    /// Clone the repository at `url` into empty `storage` (Requirements 3.2)
+    ///
+    /// Branches become `refs/remotes/origin/*`, the remote's default branch is
+    /// checked out locally and `origin` is recorded as a remote.
+    pub async fn clone_from(url: &str, storage: Arc<dyn Storage>) -> Result<Repository, ProtocolError> {
+        if !storage.list_refs().await?.is_empty() {
+            return Err(StorageError::Backend("Cannot clone into a non-empty repository".to_string()).into());
+        }
+
+        let mut transport = connect(url).await?;
+        let refs = transport.list_refs().await?;
+
+        let mut index = GitHashIndex::open(storage.clone()).await?;
+        let wants: Vec<GitHash> = refs.iter()
+            .filter(|r| tracking_ref(DEFAULT_REMOTE, &r.name).is_some())
+            .map(|r| r.hash)
+            .collect();
+        let objects_imported = fetch_objects(storage.as_ref(), &mut index, transport.as_mut(), wants, &[]).await?;
+
+        let repo = Repository::open(storage.clone()).await?;
+        repo.add_remote(DEFAULT_REMOTE, url).await?;
+        let mut report = repo.update_fetched_refs(DEFAULT_REMOTE, &refs, &index).await?;
+        report.objects_imported = objects_imported;
+
+        // Check out the branch the remote's HEAD names, or the first one
+        let default_branch = refs.iter()
+            .find(|r| r.name == "HEAD")
+            .and_then(|head| head.symref_target.clone())
+            .filter(|name| valid_ref_name(name))
+            .or_else(|| refs.iter().find(|r| r.name.starts_with("refs/heads/") && valid_ref_name(&r.name)).map(|r| r.name.clone()))
+            .and_then(|name| name.strip_prefix("refs/heads/").map(str::to_string));
+        let branch = default_branch.clone().unwrap_or_else(|| "main".to_string());
+        store_text_ref(storage.as_ref(), CURRENT_BRANCH_REF, &branch).await?;
+        let checkout = default_branch
+            .and_then(|branch| report.updated_refs.get(&format!("refs/remotes/{}/{}", DEFAULT_REMOTE, branch)).copied());
+        if let Some(commit) = checkout {
+            let branch_ref = format!("refs/heads/{}", branch);
+            storage.update_ref(&branch_ref, &commit).await?;
+            storage.update_ref("HEAD", &commit).await?;
+            report.updated_refs.insert(branch_ref, commit);
+            report.updated_refs.insert("HEAD".to_string(), commit);
+        }
+
+        let operation = Operation::Fetch {
+            remote: DEFAULT_REMOTE.to_string(),
+            url: url.to_string(),
+            updated_refs: report.updated_refs,
+        };
+        repo.record_remote_operation(operation, HashMap::new(), "clone", vec![url.to_string()]).await?;
+        Ok(repo)
+    }
+
+    /// Record `url` as remote `name`, replacing any previous URL
+    pub async fn add_remote(&self, name: &str, url: &str) -> Result<(), StorageError> {
+        store_text_ref(self.storage.as_ref(), &format!("{}{}", REMOTE_URL_REF_PREFIX, name), url).await
+    }
+
+    /// URL of remote `name`, if configured
+    pub async fn remote_url(&self, name: &str) -> Result<Option<String>, StorageError> {
+        let content = load_blob_content(self.storage.as_ref(), &format!("{}{}", REMOTE_URL_REF_PREFIX, name)).await?;
+        Ok(content.map(|content| String::from_utf8_lossy(&content).into_owned()))
+    }
+
+    async fn require_remote_url(&self, name: &str) -> Result<String, StorageError> {
+        self.remote_url(name).await?
+            .ok_or_else(|| StorageError::RefNotFound { name: format!("{}{}", REMOTE_URL_REF_PREFIX, name) })
+    }
+
+    /// Fetch new objects from `remote` and update its remote-tracking refs
+    /// (Requirements 3.2)
+    pub async fn fetch(&self, remote: &str) -> Result<FetchReport, ProtocolError> {
+        let url = self.require_remote_url(remote).await?;
+        let before_refs = self.get_all_refs().await?;
+
+        let mut transport = connect(&url).await?;
+        let hash_type = transport.hash_type().await?;
+        let refs = transport.list_refs().await?;
+
+        let mut index = GitHashIndex::open(self.storage.clone()).await?;
+        let mut wants = Vec::new();
+        for remote_ref in refs.iter().filter(|r| tracking_ref(remote, &r.name).is_some()) {
+            let known = match index.object_id(&remote_ref.hash) {
+                Some(id) => self.storage.load_object(&id).await?.is_some(),
+                None => false,
+            };
+            if !known && !wants.contains(&remote_ref.hash) {
+                wants.push(remote_ref.hash);
+            }
+        }
+
+        let haves = if wants.is_empty() { Vec::new() } else { self.local_haves(&mut index, hash_type).await? };
+        let objects_imported = fetch_objects(self.storage.as_ref(), &mut index, transport.as_mut(), wants, &haves).await?;
+
+        let mut report = self.update_fetched_refs(remote, &refs, &index).await?;
+        report.objects_imported = objects_imported;
+        if !report.updated_refs.is_empty() {
+            let operation = Operation::Fetch {
+                remote: remote.to_string(),
+                url,
+                updated_refs: report.updated_refs.clone(),
+            };
+            self.record_remote_operation(operation, before_refs, "fetch", vec![remote.to_string()]).await?;
+        }
+        Ok(report)
+    }
+
+    /// Point remote-tracking refs and new tags at fetched objects
+    async fn update_fetched_refs(
+        &self,
+        remote: &str,
+        refs: &[RemoteRef],
+        index: &GitHashIndex,
+    ) -> Result<FetchReport, ProtocolError> {
+        let current = self.get_all_refs().await?;
+        let mut report = FetchReport::default();
+        for remote_ref in refs {
+            let Some(local) = tracking_ref(remote, &remote_ref.name) else {
+                continue;
+            };
+            let id = index.object_id(&remote_ref.hash)
+                .ok_or(ProtocolError::MissingBase(remote_ref.hash))?;
+            // Tags are never moved once they exist locally
+            let keep = match current.get(&local) {
+                Some(existing) => *existing == id || local.starts_with("refs/tags/"),
+                None => false,
+            };
+            if !keep {
+                self.storage.update_ref(&local, &id).await?;
+                report.updated_refs.insert(local, id);
+            }
+        }
+        self.index_commits(report.updated_refs.values().copied()).await?;
+        Ok(report)
+    }
+
+    /// Git hashes of recent local commits, newest first, for fetch negotiation
+    async fn local_haves(&self, index: &mut GitHashIndex, hash_type: GitHashType) -> Result<Vec<GitHash>, ProtocolError> {
+        let mut queue = BinaryHeap::new();
+        let mut seen = HashSet::new();
+        let tips = self.get_all_refs().await?.into_iter()
+            .filter(|(name, _)| name == "HEAD" || name.starts_with("refs/heads/") || name.starts_with("refs/remotes/"))
+            .map(|(_, id)| id);
+        for id in tips {
+            if seen.insert(id) {
+                if let Some(GitObject::Commit(commit)) = self.storage.load_object(&id).await? {
+                    queue.push((commit.committer.timestamp, id, commit.parents));
+                }
+            }
+        }
+
+        let mut haves = Vec::new();
+        while let Some((_, id, parents)) = queue.pop() {
+            if haves.len() == MAX_HAVES {
+                break;
+            }
+            haves.push(index.derive(&id, hash_type).await?);
+            for parent in parents {
+                if seen.insert(parent) {
+                    if let Some(GitObject::Commit(commit)) = self.storage.load_object(&parent).await? {
+                        queue.push((commit.committer.timestamp, parent, commit.parents));
+                    }
+                }
+            }
+        }
+        Ok(haves)
+    }
+
+    /// Resolve a push refspec against local refs
+    async fn parse_push_spec(&self, spec: &str, local_refs: &HashMap<String, ObjectId>) -> Result<PushSpec, ProtocolError> {
+        let (force, spec) = match spec.strip_prefix('+') {
+            Some(rest) => (true, rest),
+            None => (false, spec),
+        };
+        let (src, dst) = spec.split_once(':').unwrap_or((spec, spec));
+        let invalid = || StorageError::Backend(format!("Invalid refspec '{}'", spec));
+
+        let src = if src.is_empty() {
+            None
+        } else {
+            let candidates = [src.to_string(), format!("refs/heads/{}", src), format!("refs/tags/{}", src)];
+            let name = candidates.into_iter()
+                .find(|name| name.starts_with("refs/") && local_refs.contains_key(name))
+                .ok_or_else(|| StorageError::RefNotFound { name: src.to_string() })?;
+            let id = local_refs[&name];
+            Some((name, id))
+        };
+
+        if dst.is_empty() {
+            return Err(invalid().into());
+        }
+        let dst = if dst.starts_with("refs/") {
+            dst.to_string()
+        } else {
+            match &src {
+                Some((name, _)) if name.starts_with("refs/tags/") => format!("refs/tags/{}", dst),
+                _ => format!("refs/heads/{}", dst),
+            }
+        };
+        Ok(PushSpec { force, src, dst })
+    }
+
+    /// Push local refs to `remote` with refspecs of the form `[+]<src>[:<dst>]`
+    /// (Requirements 3.1)
+    ///
+    /// An empty `<src>` deletes `<dst>`. Updates that are not fast-forwards are
+    /// refused before anything is sent unless forced with `+`.
+    pub async fn push(&self, remote: &str, refspecs: &[&str]) -> Result<PushReport, ProtocolError> {
+        let url = self.require_remote_url(remote).await?;
+        let before_refs = self.get_all_refs().await?;
+        let mut specs = Vec::new();
+        for refspec in refspecs {
+            specs.push(self.parse_push_spec(refspec, &before_refs).await?);
+        }
+
+        let mut transport = connect(&url).await?;
+        let hash_type = transport.hash_type().await?;
+        let advertised: HashMap<String, GitHash> = transport.list_push_refs().await?
+            .into_iter()
+            .map(|r| (r.name, r.hash))
+            .collect();
+
+        let mut index = GitHashIndex::open(self.storage.clone()).await?;
+        let mut updates = Vec::new();
+        let mut tips = Vec::new();
+        for spec in &specs {
+            let old = advertised.get(&spec.dst).copied();
+            let new = match &spec.src {
+                Some((_, id)) => Some(index.derive(id, hash_type).await?),
+                None => None,
+            };
+            if old == new {
+                continue;
+            }
+            if let (Some(old), Some((_, id)), false) = (old, &spec.src, spec.force) {
+                let fast_forward = match index.object_id(&old) {
+                    Some(old_id) => is_ancestor(self.storage.as_ref(), &old_id, id).await?,
+                    None => false,
+                };
+                if !fast_forward {
+                    return Err(ProtocolError::Rejected {
+                        name: spec.dst.clone(),
+                        reason: "non-fast-forward".to_string(),
+                    });
+                }
+            }
+            if let Some((_, id)) = &spec.src {
+                tips.push(*id);
+            }
+            updates.push(RefUpdate { name: spec.dst.clone(), old, new });
+        }
+        if updates.is_empty() {
+            return Ok(PushReport::default());
+        }
+
+        let pack = if tips.is_empty() {
+            None
+        } else {
+            let remote_has: Vec<ObjectId> = advertised.values().filter_map(|hash| index.object_id(hash)).collect();
+            let written = build_pack(self.storage.as_ref(), &mut index, &tips, &remote_has, hash_type, true).await?;
+            Some(written.pack)
+        };
+        index.save().await?;
+        let report = transport.send_packfile(&updates, pack).await?.into_result()?;
+
+        // Mirror accepted branch updates in the remote-tracking refs
+        let mut updated_refs = HashMap::new();
+        for spec in &specs {
+            let Some(branch) = spec.dst.strip_prefix("refs/heads/") else {
+                continue;
+            };
+            let tracking = format!("refs/remotes/{}/{}", remote, branch);
+            match &spec.src {
+                Some((_, id)) => self.storage.update_ref(&tracking, id).await?,
+                None if before_refs.contains_key(&tracking) => self.storage.delete_ref(&tracking).await?,
+                None => continue,
+            }
+            updated_refs.insert(tracking, spec.src.as_ref().map(|(_, id)| *id));
+        }
+
+        let operation = Operation::Push {
+            remote: remote.to_string(),
+            url,
+            updated_refs,
+        };
+        let mut args = vec![remote.to_string()];
+        args.extend(refspecs.iter().map(|s| s.to_string()));
+        self.record_remote_operation(operation, before_refs, "push", args).await?;
+        Ok(report)
+    }
+
+    async fn record_remote_operation(
+        &self,
+        operation: Operation,
+        before_refs: HashMap<String, ObjectId>,
+        command: &str,
+        args: Vec<String>,
+    ) -> Result<(), StorageError> {
+        let before_head = before_refs.get("HEAD").copied();
+        let log_entry = LogEntry {
+            id: Uuid::new_v4(),
+            timestamp: chrono::Utc::now(),
+            operation,
+            before_state: RepositoryState {
+                head: before_head,
+                refs: before_refs,
+                index_state: None,
+            },
+            after_state: RepositoryState {
+                head: self.head().await.ok(),
+                refs: self.get_all_refs().await?,
+                index_state: None,
+            },
+            command_intent: CommandIntent {
+                command: command.to_string(),
+                args,
+                working_directory: ".".to_string(),
+            },
+            user_metadata: UserMetadata {
+                user_name: None,
+                user_email: None,
+                session_id: None,
+            },
+        };
+        self.operation_log.record(log_entry).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracking_ref() {
+        assert_eq!(tracking_ref("origin", "refs/heads/main").as_deref(), Some("refs/remotes/origin/main"));
+        assert_eq!(tracking_ref("origin", "refs/tags/v1").as_deref(), Some("refs/tags/v1"));
+        assert_eq!(tracking_ref("origin", "refs/pull/1/head"), None);
+        for name in ["refs/tags/../../../../x", "refs/heads/a/../../gitnext/index", "refs/heads/a b", "refs/gitnext/index"] {
+            assert_eq!(tracking_ref("origin", name), None, "{}", name);
+        }
+        assert_eq!(tracking_ref("../x", "refs/heads/main"), None);
+    }
+}
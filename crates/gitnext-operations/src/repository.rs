@@ -1,20 +1,35 @@
 use gitnext_core::{GitObject, ObjectId, Tree, Commit, Signature, Blob};
 use gitnext_storage::{Storage, StorageError, ReferenceTarget};
+use gitnext_query::IndexUpdater;
+use gitnext_identity::{ArtifactTracker, ExtractorRegistry, IdentityError};
 use std::sync::Arc;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use chrono::{DateTime, Utc};
 use uuid::Uuid;
 
 /// Repository struct with storage backend (Requirements 1.1)
 /// Implements basic repository operations with operation logging
 pub struct Repository {
-    storage: Arc<dyn Storage>,
-    operation_log: std::sync::Mutex<OperationLog>,
+    pub(crate) storage: Arc<dyn Storage>,
+    pub(crate) operation_log: OperationLog,
+    /// Working directory for `add`, `remove` and `status`, if any
+    pub(crate) work_tree: Option<PathBuf>,
+    /// Extractors `index_artifacts` finds artifacts with (ADR-004)
+    pub(crate) extractors: ExtractorRegistry,
+    /// Query indexes, kept loaded so that each commit only adds to them
+    pub(crate) indexes: IndexUpdater,
 }
 
 /// Operation logging system for undo/redo functionality (ADR-003)
 pub struct OperationLog {
     storage: Arc<dyn Storage>,
+    /// Position and chain, locked only between await points
+    cursor: std::sync::Mutex<LogCursor>,
+}
+
+#[derive(Debug, Default)]
+struct LogCursor {
     /// Current position in the operation log for undo/redo
     current_position: usize,
     /// Chain of operation log entries
@@ -64,6 +79,24 @@ pub enum Operation {
         after_head: ObjectId,
         strategy: MergeStrategy,
     },
+    /// Clone or fetch; `updated_refs` are the local refs written
+    Fetch {
+        remote: String,
+        url: String,
+        updated_refs: HashMap<String, ObjectId>,
+    },
+    /// Push; `updated_refs` are the remote-tracking refs moved (`None` when deleted).
+    /// Undo only rewinds those local refs, not the remote.
+    Push {
+        remote: String,
+        url: String,
+        updated_refs: HashMap<String, Option<ObjectId>>,
+    },
+    /// `add` or `rm`; the index itself is kept in the before and after states
+    UpdateIndex {
+        command: String,
+        paths: Vec<String>,
+    },
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -87,23 +120,28 @@ pub struct UserMetadata {
     pub session_id: Option<String>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct IndexState {
-    // Placeholder for working directory index state
-    pub entries: HashMap<String, ObjectId>,
-}
+pub use crate::index::IndexState;
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// How `Repository::merge` combines diverged histories
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum MergeStrategy {
+    /// Three-way merge against one merge base (Git's `resolve`)
     ThreeWay,
+    /// Keep our tree and only record the merge (`-s ours`)
     Ours,
+    /// Take their tree as it is
     Theirs,
+    /// Three-way merge; several merge bases are first merged into a virtual one
     Recursive,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum MergeResult {
     Success { commit: ObjectId },
+    /// HEAD was an ancestor of the merged commit and now points at it
+    FastForward { commit: ObjectId },
+    /// The merged commit was already reachable from HEAD
+    UpToDate,
     Conflicts { conflicted_files: Vec<String> },
 }
 
@@ -124,6 +162,7 @@ impl Repository {
             email: "gitnext@system".to_string(),
             timestamp: Utc::now().timestamp(),
             timezone_offset: 0,
+            raw: None,
         };
         
         let initial_commit = Commit {
@@ -131,7 +170,9 @@ impl Repository {
             parents: vec![],
             author: author.clone(),
             committer: author,
+            extra_headers: Vec::new(),
             message: "Initial commit".to_string(),
+            raw_message: None,
         };
         
         let commit_object = GitObject::Commit(initial_commit);
@@ -147,12 +188,16 @@ impl Repository {
         storage.update_ref("HEAD", &commit_id).await?;
         
         // Create repository with operation log
-        let operation_log = std::sync::Mutex::new(OperationLog::new(storage.clone()));
+        let operation_log = OperationLog::new(storage.clone());
         
         let repo = Repository {
             storage,
             operation_log,
+            work_tree: None,
+            extractors: ExtractorRegistry::new(),
+            indexes: IndexUpdater::default(),
         };
+        repo.index_commits([commit_id]).await?;
         
         // Record the initialization operation
         let init_state = RepositoryState {
@@ -196,23 +241,42 @@ impl Repository {
             },
         };
         
-        repo.operation_log.lock().unwrap().record(log_entry).await?;
+        repo.operation_log.record(log_entry).await?;
         
         Ok(repo)
     }
     
     /// Open an existing repository
     pub async fn open(storage: Arc<dyn Storage>) -> Result<Self, StorageError> {
-        let mut operation_log = OperationLog::new(storage.clone());
+        let operation_log = OperationLog::new(storage.clone());
         
         // Load existing operation log chain
         operation_log.load_chain().await?;
         
         Ok(Repository {
             storage,
-            operation_log: std::sync::Mutex::new(operation_log),
+            operation_log,
+            work_tree: None,
+            extractors: ExtractorRegistry::new(),
+            indexes: IndexUpdater::default(),
         })
     }
+
+    /// Attach a working directory, enabling `add`, `remove` and `status`
+    pub fn with_work_tree(mut self, path: impl Into<PathBuf>) -> Self {
+        self.work_tree = Some(path.into());
+        self
+    }
+
+    /// Find artifacts with `extractors` instead of the built-in ones
+    pub fn with_extractors(mut self, extractors: ExtractorRegistry) -> Self {
+        self.extractors = extractors;
+        self
+    }
+
+    pub fn work_tree(&self) -> Option<&std::path::Path> {
+        self.work_tree.as_deref()
+    }
     
     /// Get the current HEAD commit
     pub async fn head(&self) -> Result<ObjectId, StorageError> {
@@ -287,7 +351,7 @@ impl Repository {
             },
         };
         
-        self.operation_log.lock().unwrap().record(log_entry).await?;
+        self.operation_log.record(log_entry).await?;
         
         Ok(())
     }
@@ -355,7 +419,7 @@ impl Repository {
             },
         };
         
-        self.operation_log.lock().unwrap().record(log_entry).await?;
+        self.operation_log.record(log_entry).await?;
         
         Ok(())
     }
@@ -427,13 +491,13 @@ impl Repository {
             },
         };
         
-        self.operation_log.lock().unwrap().record(log_entry).await?;
+        self.operation_log.record(log_entry).await?;
         
         Ok(())
     }
     
     /// Helper method to get all references as a HashMap
-    async fn get_all_refs(&self) -> Result<HashMap<String, ObjectId>, StorageError> {
+    pub(crate) async fn get_all_refs(&self) -> Result<HashMap<String, ObjectId>, StorageError> {
         let refs = self.storage.list_refs().await?;
         let mut ref_map = HashMap::new();
         
@@ -445,45 +509,59 @@ impl Repository {
         
         Ok(ref_map)
     }
+
+    /// Add new commits, and any ancestors not indexed yet, to the query indexes
+    pub(crate) async fn index_commits(&self, commits: impl IntoIterator<Item = ObjectId>) -> Result<(), StorageError> {
+        self.indexes.update(self.storage.as_ref(), commits).await?;
+        Ok(())
+    }
+
+    /// Find the artifacts in indexed commits not handled yet, returning how many
+    /// commits were handled (ADR-004). Parsing files is kept out of `commit`,
+    /// `merge` and `fetch`, so it is run when artifacts are wanted.
+    pub async fn index_artifacts(&self) -> Result<usize, IdentityError> {
+        let mut tracker = ArtifactTracker::open(self.storage.clone()).await?;
+        tracker.index_commits(&self.extractors).await
+    }
     
     /// Undo the last operation (Requirements 4.2, 4.3, 4.5)
     pub async fn undo(&self) -> Result<Option<Operation>, StorageError> {
-        self.operation_log.lock().unwrap().undo(self).await
+        self.operation_log.undo(self).await
     }
     
     /// Redo a previously undone operation (Requirements 4.2, 4.3, 4.5)
     pub async fn redo(&self) -> Result<Option<Operation>, StorageError> {
-        self.operation_log.lock().unwrap().redo(self).await
+        self.operation_log.redo(self).await
     }
     
     /// Check if there are operations that can be undone
     pub fn can_undo(&self) -> bool {
-        self.operation_log.lock().unwrap().can_undo()
+        self.operation_log.can_undo()
     }
     
     /// Check if there are operations that can be redone
     pub fn can_redo(&self) -> bool {
-        self.operation_log.lock().unwrap().can_redo()
+        self.operation_log.can_redo()
     }
     
     /// Get a preview of the operation that would be undone
     pub async fn peek_undo(&self) -> Result<Option<Operation>, StorageError> {
-        self.operation_log.lock().unwrap().peek_undo().await
+        self.operation_log.peek_undo().await
     }
     
     /// Get a preview of the operation that would be redone
     pub async fn peek_redo(&self) -> Result<Option<Operation>, StorageError> {
-        self.operation_log.lock().unwrap().peek_redo().await
+        self.operation_log.peek_redo().await
     }
     
     /// Get the current position in the operation log
     pub fn operation_log_position(&self) -> usize {
-        self.operation_log.lock().unwrap().current_position()
+        self.operation_log.current_position()
     }
     
     /// Get the total number of operations in the log
     pub fn operation_log_size(&self) -> usize {
-        self.operation_log.lock().unwrap().total_operations()
+        self.operation_log.total_operations()
     }
     
     /// Create a new commit with the given tree and message (Requirements 1.3, 4.1)
@@ -508,7 +586,9 @@ impl Repository {
             parents: parents.clone(),
             author,
             committer,
+            extra_headers: Vec::new(),
             message,
+            raw_message: None,
         };
         
         let commit_object = GitObject::Commit(commit);
@@ -516,6 +596,7 @@ impl Repository {
         
         // Store the commit object
         self.storage.store_object(&commit_id, &commit_object).await?;
+        self.index_commits([commit_id]).await?;
         
         // Update HEAD to point to the new commit
         self.storage.update_ref("HEAD", &commit_id).await?;
@@ -562,7 +643,7 @@ impl Repository {
             },
         };
         
-        self.operation_log.lock().unwrap().record(log_entry).await?;
+        self.operation_log.record(log_entry).await?;
         
         Ok(commit_id)
     }
@@ -611,55 +692,69 @@ impl OperationLog {
     pub fn new(storage: Arc<dyn Storage>) -> Self {
         Self {
             storage,
-            current_position: 0,
-            log_chain: Vec::new(),
+            cursor: std::sync::Mutex::new(LogCursor::default()),
         }
     }
     
+    fn cursor(&self) -> std::sync::MutexGuard<'_, LogCursor> {
+        self.cursor.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+    
     /// Get the current position in the operation log
     pub fn current_position(&self) -> usize {
-        self.current_position
+        self.cursor().current_position
     }
     
     /// Get the total number of operations in the log
     pub fn total_operations(&self) -> usize {
-        self.log_chain.len()
+        self.cursor().log_chain.len()
     }
     
     /// Check if there are operations that can be undone
     pub fn can_undo(&self) -> bool {
-        self.current_position > 0
+        self.cursor().current_position > 0
     }
     
     /// Check if there are operations that can be redone
     pub fn can_redo(&self) -> bool {
-        self.current_position < self.log_chain.len()
+        let cursor = self.cursor();
+        cursor.current_position < cursor.log_chain.len()
+    }
+    
+    /// Entry that would be undone next, if any
+    fn undo_candidate(&self) -> Option<Uuid> {
+        let cursor = self.cursor();
+        cursor.current_position.checked_sub(1).map(|index| cursor.log_chain[index])
+    }
+    
+    /// Entry that would be redone next, if any
+    fn redo_candidate(&self) -> Option<Uuid> {
+        let cursor = self.cursor();
+        cursor.log_chain.get(cursor.current_position).copied()
     }
     
     /// Get a preview of the operation that would be undone
     pub async fn peek_undo(&self) -> Result<Option<Operation>, StorageError> {
-        if !self.can_undo() {
+        let Some(entry_id) = self.undo_candidate() else {
             return Ok(None);
-        }
+        };
         
-        let entry_id = self.log_chain[self.current_position - 1];
         let entry = self.load_log_entry(entry_id).await?;
         Ok(entry.map(|e| e.operation))
     }
     
     /// Get a preview of the operation that would be redone
     pub async fn peek_redo(&self) -> Result<Option<Operation>, StorageError> {
-        if !self.can_redo() {
+        let Some(entry_id) = self.redo_candidate() else {
             return Ok(None);
-        }
+        };
         
-        let entry_id = self.log_chain[self.current_position];
         let entry = self.load_log_entry(entry_id).await?;
         Ok(entry.map(|e| e.operation))
     }
     
     /// Record an operation in the log (Requirements 4.1, 4.4)
-    pub async fn record(&mut self, entry: LogEntry) -> Result<(), StorageError> {
+    pub async fn record(&self, entry: LogEntry) -> Result<(), StorageError> {
         // Serialize the log entry
         let serialized = bincode::serialize(&entry)
             .map_err(|e| StorageError::Serialization(e.to_string()))?;
@@ -678,13 +773,17 @@ impl OperationLog {
         
         // Add to log chain and update position
         // If we're not at the end of the chain, truncate future operations
-        if self.current_position < self.log_chain.len() {
-            self.log_chain.truncate(self.current_position);
+        {
+            let mut cursor = self.cursor();
+            let position = cursor.current_position;
+            if position < cursor.log_chain.len() {
+                cursor.log_chain.truncate(position);
+            }
+            
+            cursor.log_chain.push(entry.id);
+            cursor.current_position = cursor.log_chain.len();
         }
         
-        self.log_chain.push(entry.id);
-        self.current_position = self.log_chain.len();
-        
         // Update the log chain reference
         self.update_log_chain_ref().await?;
         
@@ -693,22 +792,19 @@ impl OperationLog {
     
     /// Get the current operation log entry
     pub async fn current_entry(&self) -> Result<Option<LogEntry>, StorageError> {
-        if self.current_position == 0 || self.current_position > self.log_chain.len() {
+        let Some(entry_id) = self.undo_candidate() else {
             return Ok(None);
-        }
+        };
         
-        let entry_id = self.log_chain[self.current_position - 1];
         self.load_log_entry(entry_id).await
     }
     
     /// Undo the last operation (Requirements 4.2, 4.3, 4.5)
-    pub async fn undo(&mut self, repo: &Repository) -> Result<Option<Operation>, StorageError> {
-        if self.current_position == 0 {
-            return Ok(None); // Nothing to undo
-        }
-        
+    pub async fn undo(&self, repo: &Repository) -> Result<Option<Operation>, StorageError> {
         // Get the current operation
-        let entry_id = self.log_chain[self.current_position - 1];
+        let Some(entry_id) = self.undo_candidate() else {
+            return Ok(None); // Nothing to undo
+        };
         let entry = self.load_log_entry(entry_id).await?
             .ok_or_else(|| StorageError::Backend("Log entry not found".to_string()))?;
         
@@ -717,7 +813,7 @@ impl OperationLog {
         self.restore_repository_state(repo, &entry.before_state).await?;
         
         // Move position back
-        self.current_position -= 1;
+        self.cursor().current_position -= 1;
         self.update_log_chain_ref().await?;
         
         Ok(Some(entry.operation))
@@ -742,26 +838,29 @@ impl OperationLog {
         }
         
         // Delete any references that exist currently but not in the target state
-        for (ref_name, _) in &current_refs {
+        for ref_name in current_refs.keys() {
             // Skip log references and internal tracking references
-            if !ref_name.starts_with("refs/logs/") && !ref_name.starts_with("refs/gitnext/") {
-                if !state.refs.contains_key(ref_name) {
-                    repo.storage.delete_ref(ref_name).await?;
-                }
+            if !ref_name.starts_with("refs/logs/")
+                && !ref_name.starts_with("refs/gitnext/")
+                && !state.refs.contains_key(ref_name)
+            {
+                repo.storage.delete_ref(ref_name).await?;
             }
         }
+
+        if let Some(index) = &state.index_state {
+            index.save(repo.storage.as_ref()).await?;
+        }
         
         Ok(())
     }
     
     /// Redo a previously undone operation (Requirements 4.2, 4.3, 4.5)
-    pub async fn redo(&mut self, repo: &Repository) -> Result<Option<Operation>, StorageError> {
-        if self.current_position >= self.log_chain.len() {
-            return Ok(None); // Nothing to redo
-        }
-        
+    pub async fn redo(&self, repo: &Repository) -> Result<Option<Operation>, StorageError> {
         // Get the next operation to redo
-        let entry_id = self.log_chain[self.current_position];
+        let Some(entry_id) = self.redo_candidate() else {
+            return Ok(None); // Nothing to redo
+        };
         let entry = self.load_log_entry(entry_id).await?
             .ok_or_else(|| StorageError::Backend("Log entry not found".to_string()))?;
         
@@ -807,10 +906,28 @@ impl OperationLog {
                     repo.storage.update_ref(&branch_ref, after_head).await?;
                 }
             }
+            Operation::Fetch { updated_refs, .. } => {
+                for (ref_name, target) in updated_refs {
+                    repo.storage.update_ref(ref_name, target).await?;
+                }
+            }
+            Operation::Push { updated_refs, .. } => {
+                for (ref_name, target) in updated_refs {
+                    match target {
+                        Some(target) => repo.storage.update_ref(ref_name, target).await?,
+                        None => repo.storage.delete_ref(ref_name).await?,
+                    }
+                }
+            }
+            Operation::UpdateIndex { .. } => {}
+        }
+
+        if let Some(index) = &entry.after_state.index_state {
+            index.save(repo.storage.as_ref()).await?;
         }
         
         // Move position forward
-        self.current_position += 1;
+        self.cursor().current_position += 1;
         self.update_log_chain_ref().await?;
         
         Ok(Some(entry.operation))
@@ -845,8 +962,11 @@ impl OperationLog {
     /// Update the log chain reference for persistence
     async fn update_log_chain_ref(&self) -> Result<(), StorageError> {
         // Serialize the log chain state
-        let chain_data = bincode::serialize(&(self.current_position, &self.log_chain))
-            .map_err(|e| StorageError::Serialization(e.to_string()))?;
+        let chain_data = {
+            let cursor = self.cursor();
+            bincode::serialize(&(cursor.current_position, &cursor.log_chain))
+                .map_err(|e| StorageError::Serialization(e.to_string()))?
+        };
         
         let chain_blob = Blob::new(bytes::Bytes::from(chain_data));
         let chain_object = GitObject::Blob(chain_blob);
@@ -859,7 +979,7 @@ impl OperationLog {
     }
     
     /// Load the log chain from storage
-    pub async fn load_chain(&mut self) -> Result<(), StorageError> {
+    pub async fn load_chain(&self) -> Result<(), StorageError> {
         let refs = self.storage.list_refs().await?;
         
         // Find the chain reference
@@ -876,8 +996,9 @@ impl OperationLog {
                     let (position, chain): (usize, Vec<Uuid>) = bincode::deserialize(content)
                         .map_err(|e| StorageError::Serialization(e.to_string()))?;
                     
-                    self.current_position = position;
-                    self.log_chain = chain;
+                    let mut cursor = self.cursor();
+                    cursor.current_position = position;
+                    cursor.log_chain = chain;
                 }
             }
         }
@@ -893,20 +1014,19 @@ impl OperationLog {
     }
     
     /// Compact the operation log to manage storage growth
-    pub async fn compact(&mut self, keep_entries: usize) -> Result<(), StorageError> {
-        if self.log_chain.len() <= keep_entries {
-            return Ok(()); // Nothing to compact
-        }
-        
-        // Keep only the most recent entries
-        let remove_count = self.log_chain.len() - keep_entries;
-        self.log_chain.drain(0..remove_count);
-        
-        // Adjust current position
-        if self.current_position > remove_count {
-            self.current_position -= remove_count;
-        } else {
-            self.current_position = 0;
+    pub async fn compact(&self, keep_entries: usize) -> Result<(), StorageError> {
+        {
+            let mut cursor = self.cursor();
+            if cursor.log_chain.len() <= keep_entries {
+                return Ok(()); // Nothing to compact
+            }
+            
+            // Keep only the most recent entries
+            let remove_count = cursor.log_chain.len() - keep_entries;
+            cursor.log_chain.drain(0..remove_count);
+            
+            // Adjust current position
+            cursor.current_position = cursor.current_position.saturating_sub(remove_count);
         }
         
         // Update the chain reference
@@ -1064,6 +1184,7 @@ mod tests {
             email: "test@example.com".to_string(),
             timestamp: Utc::now().timestamp(),
             timezone_offset: 0,
+            raw: None,
         };
         
         let committer = author.clone();
@@ -1100,6 +1221,72 @@ mod tests {
         assert_eq!(head_after_redo, commit_id);
     }
 
+    #[tokio::test]
+    async fn test_commits_are_indexed() {
+        let storage = Arc::new(MemoryStorage::new());
+        let repo = Repository::init(storage.clone()).await.unwrap();
+        let initial_head = repo.head().await.unwrap();
+        let tree_id = GitObject::Tree(Tree::new(vec![])).canonical_hash();
+        let author = Signature {
+            name: "Test Author".to_string(),
+            email: "test@example.com".to_string(),
+            timestamp: Utc::now().timestamp(),
+            timezone_offset: 0,
+            raw: None,
+        };
+        let commit_id = repo.commit(&tree_id, vec![initial_head], author.clone(), author, "Second".to_string())
+            .await.unwrap();
+
+        let graph = gitnext_query::CommitGraphIndex::load(storage.as_ref()).await.unwrap().unwrap();
+        assert_eq!(graph.len(), 2);
+        assert_eq!(graph.node(&commit_id).unwrap().level, 2);
+        assert!(graph.is_ancestor(&initial_head, &commit_id).unwrap());
+        let commits = gitnext_query::CommitIndex::load(storage.as_ref()).await.unwrap().unwrap();
+        assert_eq!(commits.entries()[1].message, "Second");
+
+        // Undo leaves the index alone; it only ever gains commits
+        repo.undo().await.unwrap();
+        assert_eq!(gitnext_query::CommitGraphIndex::load(storage.as_ref()).await.unwrap().unwrap(), graph);
+    }
+
+    #[tokio::test]
+    async fn test_index_artifacts_of_commits() {
+        let storage = Arc::new(MemoryStorage::new());
+        let repo = Repository::init(storage.clone()).await.unwrap();
+        let blob = GitObject::Blob(Blob::new(bytes::Bytes::from("fn main() {\n    run();\n}\n")));
+        let blob_id = blob.canonical_hash();
+        storage.store_object(&blob_id, &blob).await.unwrap();
+        let tree = GitObject::Tree(Tree::new(vec![gitnext_core::TreeEntry {
+            name: "main.rs".to_string(),
+            mode: gitnext_core::FileMode::Normal,
+            hash: blob_id,
+            entry_type: gitnext_core::ObjectType::Blob,
+            raw_name: None,
+        }]));
+        let tree_id = tree.canonical_hash();
+        storage.store_object(&tree_id, &tree).await.unwrap();
+        let author = Signature {
+            name: "Test Author".to_string(),
+            email: "test@example.com".to_string(),
+            timestamp: Utc::now().timestamp(),
+            timezone_offset: 0,
+            raw: None,
+        };
+        let head = repo.head().await.unwrap();
+        let commit_id = repo.commit(&tree_id, vec![head], author.clone(), author, "Main".to_string()).await.unwrap();
+
+        // Committing leaves the files unparsed
+        assert!(ArtifactTracker::open(storage.clone()).await.unwrap().is_empty());
+        assert_eq!(repo.index_artifacts().await.unwrap(), 2);
+        assert_eq!(repo.index_artifacts().await.unwrap(), 0);
+
+        let tracker = ArtifactTracker::open(storage.clone()).await.unwrap();
+        let artifacts = tracker.query_artifacts(Default::default()).await.unwrap();
+        assert_eq!(artifacts.len(), 1);
+        assert_eq!(artifacts[0].artifact_type.name(), "fn main()");
+        assert_eq!(artifacts[0].location.commit, commit_id);
+    }
+
     #[tokio::test]
     async fn test_undo_redo_branch_switch() {
         let storage = Arc::new(MemoryStorage::new());
@@ -1251,6 +1438,7 @@ mod tests {
             email: "test@example.com".to_string(),
             timestamp: Utc::now().timestamp(),
             timezone_offset: 0,
+            raw: None,
         };
         
         let committer = author.clone();
@@ -1311,7 +1499,8 @@ mod property_tests {
         /// **Validates: Requirements 4.1**
         #[test]
         fn prop_operation_logging_completeness(
-            branch_names in prop::collection::vec(arb_branch_name(), 1..5)
+            // Distinct new names, so that each creation adds a ref
+            branch_names in prop::collection::btree_set(arb_branch_name().prop_filter("existing branch", |name| name != "main"), 1..5)
         ) {
             tokio_test::block_on(async {
                 let storage = Arc::new(MemoryStorage::new());
@@ -1503,6 +1692,7 @@ mod property_tests {
                     email: author_email.clone(),
                     timestamp: chrono::Utc::now().timestamp(),
                     timezone_offset: 0,
+                    raw: None,
                 };
                 
                 // Create a series of commits
@@ -1616,6 +1806,7 @@ mod property_tests {
                     email: "test@example.com".to_string(),
                     timestamp: chrono::Utc::now().timestamp(),
                     timezone_offset: 0,
+                    raw: None,
                 };
                 
                 let mut commit_targets = vec![initial_head];
@@ -1760,6 +1951,7 @@ mod property_tests {
                     email: "test@example.com".to_string(),
                     timestamp: chrono::Utc::now().timestamp(),
                     timezone_offset: 0,
+                    raw: None,
                 };
                 
                 let mut operations_performed = Vec::new();
@@ -1869,7 +2061,7 @@ mod property_tests {
                     }
                     
                     // Verify no extra user-visible references were created
-                    for (ref_name, _) in &actual_refs {
+                    for ref_name in actual_refs.keys() {
                         if !ref_name.starts_with("refs/logs/") && !ref_name.starts_with("refs/gitnext/") {
                             prop_assert!(expected_state.1.contains_key(ref_name), 
                                 "No unexpected reference {} should exist after undoing operation {} ({})", 
@@ -1986,6 +2178,7 @@ mod property_tests {
                     email: "test@example.com".to_string(),
                     timestamp: chrono::Utc::now().timestamp(),
                     timezone_offset: 0,
+                    raw: None,
                 };
                 
                 let mut operations_performed = Vec::new();
@@ -2126,7 +2319,7 @@ mod property_tests {
                     
                     // Verify no unexpected non-log references were created
                     // Allow internal tracking references like refs/gitnext/current-branch
-                    for (ref_name, _) in &actual_refs {
+                    for ref_name in actual_refs.keys() {
                         if !ref_name.starts_with("refs/logs/") && !ref_name.starts_with("refs/gitnext/") {
                             prop_assert!(expected_state.1.contains_key(ref_name), 
                                 "No unexpected reference {} should exist after redoing operation {} ({})", 
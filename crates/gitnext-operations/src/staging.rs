@@ -0,0 +1,469 @@
+//! Staging: `add`, `rm` and `status` against a working directory
+//!
+//! The working directory is attached with `Repository::with_work_tree`. Paths given
+//! to `add` and `remove` are relative to its top; `"."` names the whole tree. Index
+//! changes are recorded in the operation log with the index before and after, so
+//! `undo` and `redo` cover staging as well.
+
+use crate::index::{flatten_tree, IndexEntry, IndexError, IndexState};
+use crate::repository::{CommandIntent, LogEntry, Operation, Repository, RepositoryState, UserMetadata};
+use crate::worktree::{remove_work_file, symlink_metadata, tracks_within, walk, work_file_entry, work_path};
+use chrono::Utc;
+use gitnext_core::{FileMode, GitObject, ObjectId};
+use gitnext_storage::StorageError;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::Metadata;
+use std::path::Path;
+use uuid::Uuid;
+
+/// How a path differs between two of HEAD, the index and the working tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+    /// Changed between a regular file and a symlink
+    TypeChanged,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatusEntry {
+    pub path: String,
+    pub kind: ChangeKind,
+}
+
+/// Result of `Repository::status`; every list is sorted by path
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Status {
+    /// HEAD tree against the index
+    pub staged: Vec<StatusEntry>,
+    /// Index against the working tree
+    pub unstaged: Vec<StatusEntry>,
+    /// Files in the working tree but not in the index
+    pub untracked: Vec<String>,
+    /// Paths with unresolved merge stages
+    pub conflicted: Vec<String>,
+}
+
+impl Status {
+    /// Whether there is nothing to commit and nothing modified or untracked
+    pub fn is_clean(&self) -> bool {
+        self.staged.is_empty() && self.unstaged.is_empty() && self.untracked.is_empty() && self.conflicted.is_empty()
+    }
+}
+
+/// Normalize a pathspec to an index path prefix; `""` stands for the whole tree
+fn normalize_pathspec(spec: &str) -> Result<String, IndexError> {
+    if spec.starts_with('/') || spec.contains('\\') || spec.contains('\0') {
+        return Err(IndexError::InvalidPath(spec.to_string()));
+    }
+    let mut components = Vec::new();
+    for component in spec.split('/') {
+        match component {
+            "" | "." => {}
+            ".." => return Err(IndexError::InvalidPath(spec.to_string())),
+            component => components.push(component),
+        }
+    }
+    if components.first() == Some(&".git") {
+        return Err(IndexError::InvalidPath(spec.to_string()));
+    }
+    Ok(components.join("/"))
+}
+
+fn compare(mode_a: FileMode, id_a: &ObjectId, mode_b: FileMode, id_b: &ObjectId) -> Option<ChangeKind> {
+    if (mode_a == FileMode::Symlink) != (mode_b == FileMode::Symlink) {
+        Some(ChangeKind::TypeChanged)
+    } else if mode_a != mode_b || id_a != id_b {
+        Some(ChangeKind::Modified)
+    } else {
+        None
+    }
+}
+
+impl Repository {
+    fn require_work_tree(&self) -> Result<&Path, IndexError> {
+        self.work_tree().ok_or(IndexError::NoWorkTree)
+    }
+
+    /// Tree of the HEAD commit, if HEAD is set
+    async fn head_tree(&self) -> Result<Option<ObjectId>, StorageError> {
+        let head = match self.head().await {
+            Ok(head) => head,
+            Err(StorageError::RefNotFound { .. }) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        match self.storage.load_object(&head).await? {
+            Some(GitObject::Commit(commit)) => Ok(Some(commit.tree)),
+            Some(_) => Err(StorageError::CorruptionDetected { id: head, details: "HEAD is not a commit".to_string() }),
+            None => Err(StorageError::ObjectNotFound { id: head }),
+        }
+    }
+
+    /// The index; until one is saved, it matches the HEAD tree
+    pub async fn index(&self) -> Result<IndexState, StorageError> {
+        if let Some(index) = IndexState::load(self.storage.as_ref()).await? {
+            return Ok(index);
+        }
+        match self.head_tree().await? {
+            Some(tree) => IndexState::from_tree(self.storage.as_ref(), &tree).await,
+            None => Ok(IndexState::new()),
+        }
+    }
+
+    /// Index entry for the working file at `path`, or `None` when it is a directory.
+    /// Blobs that had to be read are stored when `store` is set.
+    async fn work_tree_entry(
+        &self,
+        root: &Path,
+        path: &str,
+        metadata: &Metadata,
+        cached: Option<&IndexEntry>,
+        store: bool,
+    ) -> Result<Option<IndexEntry>, IndexError> {
+        if metadata.is_dir() {
+            return Ok(None);
+        }
+        let (entry, blob) = work_file_entry(root, path, metadata, cached)?;
+        if let (true, Some(blob)) = (store, blob) {
+            self.storage.store_object(&entry.id, &blob).await?;
+        }
+        Ok(Some(entry))
+    }
+
+    /// Stage the files at `paths`, including removals of tracked files that are gone
+    /// (`git add -A <paths>`)
+    pub async fn add(&self, paths: &[&str]) -> Result<(), IndexError> {
+        let root = self.require_work_tree()?.to_path_buf();
+        let before = self.index().await?;
+        let mut index = before.clone();
+
+        for spec in paths {
+            let prefix = normalize_pathspec(spec)?;
+            let files = walk(&root, &prefix, &|path| tracks_within(&index, path))?;
+            let tracked = index.paths_within(&prefix);
+            if files.is_empty() && tracked.is_empty() && symlink_metadata(&work_path(&root, &prefix))?.is_none() {
+                return Err(IndexError::PathNotFound(spec.to_string()));
+            }
+
+            for path in tracked.iter().filter(|path| !files.contains_key(*path)) {
+                index.remove(path);
+            }
+            for (path, metadata) in &files {
+                let cached = index.get(path).cloned();
+                if let Some(entry) = self.work_tree_entry(&root, path, metadata, cached.as_ref(), true).await? {
+                    index.insert(entry);
+                }
+            }
+        }
+
+        self.record_index_update("add", paths, before, index).await
+    }
+
+    /// Unstage the files at `paths` and, unless `cached`, delete them from the
+    /// working tree (`git rm -r`). Files with changes that would be lost are refused.
+    pub async fn remove(&self, paths: &[&str], cached: bool) -> Result<(), IndexError> {
+        let root = self.require_work_tree()?.to_path_buf();
+        let before = self.index().await?;
+        let head_files = match self.head_tree().await? {
+            Some(tree) => flatten_tree(self.storage.as_ref(), &tree).await?,
+            None => BTreeMap::new(),
+        };
+        let mut index = before.clone();
+
+        let mut removed = BTreeSet::new();
+        for spec in paths {
+            let prefix = normalize_pathspec(spec)?;
+            let tracked = index.paths_within(&prefix);
+            if tracked.is_empty() {
+                return Err(IndexError::PathNotFound(spec.to_string()));
+            }
+            removed.extend(tracked);
+        }
+
+        for path in &removed {
+            let Some(entry) = index.get(path) else {
+                // Unmerged paths are removed whatever their stages hold
+                continue;
+            };
+            let staged = head_files.get(path) != Some(&(entry.mode, entry.id));
+            let modified = match symlink_metadata(&work_path(&root, path))? {
+                Some(metadata) => self.work_tree_entry(&root, path, &metadata, Some(entry), false).await?
+                    .is_some_and(|file| file.mode != entry.mode || file.id != entry.id),
+                None => false,
+            };
+            let refused = if cached { staged && modified } else { staged || modified };
+            if refused {
+                return Err(IndexError::LocalModifications(path.clone()));
+            }
+        }
+
+        for path in &removed {
+            index.remove(path);
+            if !cached {
+                remove_work_file(&root, path)?;
+            }
+        }
+
+        self.record_index_update("rm", paths, before, index).await
+    }
+
+    /// Compare the HEAD tree, the index and the working tree
+    pub async fn status(&self) -> Result<Status, IndexError> {
+        let root = self.require_work_tree()?.to_path_buf();
+        let index = self.index().await?;
+        let head_files = match self.head_tree().await? {
+            Some(tree) => flatten_tree(self.storage.as_ref(), &tree).await?,
+            None => BTreeMap::new(),
+        };
+        let conflicted = index.conflicted_paths();
+        let mut status = Status { conflicted, ..Status::default() };
+
+        // HEAD against the index
+        let staged: BTreeMap<&str, &IndexEntry> = index.entries().iter()
+            .filter(|e| e.stage == 0)
+            .map(|e| (e.path.as_str(), e))
+            .collect();
+        let mut staged_paths: BTreeSet<&str> = staged.keys().copied().collect();
+        staged_paths.extend(head_files.keys().map(String::as_str));
+        for path in staged_paths {
+            if status.conflicted.iter().any(|c| c == path) {
+                continue;
+            }
+            let kind = match (head_files.get(path), staged.get(path)) {
+                (None, Some(_)) => Some(ChangeKind::Added),
+                (Some(_), None) => Some(ChangeKind::Deleted),
+                (Some((mode, id)), Some(entry)) => compare(*mode, id, entry.mode, &entry.id),
+                (None, None) => None,
+            };
+            if let Some(kind) = kind {
+                status.staged.push(StatusEntry { path: path.to_string(), kind });
+            }
+        }
+
+        // The index against the working tree
+        let files = walk(&root, "", &|path| tracks_within(&index, path))?;
+        for (path, entry) in &staged {
+            let file = match files.get(*path) {
+                Some(metadata) => self.work_tree_entry(&root, path, metadata, Some(entry), false).await?,
+                None => None,
+            };
+            let kind = match file {
+                None => Some(ChangeKind::Deleted),
+                Some(file) => compare(entry.mode, &entry.id, file.mode, &file.id),
+            };
+            if let Some(kind) = kind {
+                status.unstaged.push(StatusEntry { path: path.to_string(), kind });
+            }
+        }
+        status.untracked = files.into_keys()
+            .filter(|path| !index.contains(path))
+            .collect();
+        Ok(status)
+    }
+
+    /// Save a changed index and log the change
+    async fn record_index_update(
+        &self,
+        command: &str,
+        paths: &[&str],
+        before: IndexState,
+        after: IndexState,
+    ) -> Result<(), IndexError> {
+        if before == after {
+            return Ok(());
+        }
+        after.save(self.storage.as_ref()).await?;
+
+        let head = self.head().await.ok();
+        let refs = self.get_all_refs().await?;
+        let args: Vec<String> = paths.iter().map(|path| path.to_string()).collect();
+        let log_entry = LogEntry {
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            operation: Operation::UpdateIndex {
+                command: command.to_string(),
+                paths: args.clone(),
+            },
+            before_state: RepositoryState {
+                head,
+                refs: refs.clone(),
+                index_state: Some(before),
+            },
+            after_state: RepositoryState {
+                head,
+                refs,
+                index_state: Some(after),
+            },
+            command_intent: CommandIntent {
+                command: command.to_string(),
+                args,
+                working_directory: self.require_work_tree()?.display().to_string(),
+            },
+            user_metadata: UserMetadata {
+                user_name: None,
+                user_email: None,
+                session_id: None,
+            },
+        };
+        self.operation_log.record(log_entry).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gitnext_storage::memory::MemoryStorage;
+    use std::sync::Arc;
+
+    fn entry(path: &str, kind: ChangeKind) -> StatusEntry {
+        StatusEntry { path: path.to_string(), kind }
+    }
+
+    async fn repository(dir: &Path) -> Repository {
+        Repository::init(Arc::new(MemoryStorage::new())).await.unwrap().with_work_tree(dir)
+    }
+
+    #[test]
+    fn test_normalize_pathspec() {
+        assert_eq!(normalize_pathspec(".").unwrap(), "");
+        assert_eq!(normalize_pathspec("./src//lib.rs").unwrap(), "src/lib.rs");
+        assert_eq!(normalize_pathspec("src/").unwrap(), "src");
+        for spec in ["/etc/passwd", "../outside", "a/../../b", ".git/config"] {
+            assert!(matches!(normalize_pathspec(spec), Err(IndexError::InvalidPath(_))), "{}", spec);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_add_and_status() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repository(dir.path()).await;
+        std::fs::create_dir_all(dir.path().join("src")).unwrap();
+        std::fs::write(dir.path().join("README"), "readme\n").unwrap();
+        std::fs::write(dir.path().join("src/lib.rs"), "lib\n").unwrap();
+
+        let status = repo.status().await.unwrap();
+        assert_eq!(status.untracked, vec!["README", "src/lib.rs"]);
+        assert!(status.staged.is_empty());
+
+        repo.add(&["."]).await.unwrap();
+        let status = repo.status().await.unwrap();
+        assert_eq!(status.staged, vec![entry("README", ChangeKind::Added), entry("src/lib.rs", ChangeKind::Added)]);
+        assert!(status.unstaged.is_empty() && status.untracked.is_empty());
+
+        // Commit the index, then change the working tree
+        let tree = repo.index().await.unwrap().write_tree(repo.storage.as_ref()).await.unwrap();
+        let author = gitnext_core::Signature {
+            name: "Test".to_string(),
+            email: "test@example.com".to_string(),
+            timestamp: 0,
+            timezone_offset: 0,
+            raw: None,
+        };
+        let head = repo.head().await.unwrap();
+        repo.commit(&tree, vec![head], author.clone(), author, "Add files".to_string()).await.unwrap();
+        assert!(repo.status().await.unwrap().is_clean());
+
+        std::fs::write(dir.path().join("README"), "changed\n").unwrap();
+        std::fs::remove_file(dir.path().join("src/lib.rs")).unwrap();
+        std::fs::write(dir.path().join("new.txt"), "new\n").unwrap();
+        let status = repo.status().await.unwrap();
+        assert!(status.staged.is_empty());
+        assert_eq!(status.unstaged, vec![entry("README", ChangeKind::Modified), entry("src/lib.rs", ChangeKind::Deleted)]);
+        assert_eq!(status.untracked, vec!["new.txt"]);
+
+        repo.add(&["src", "README"]).await.unwrap();
+        let status = repo.status().await.unwrap();
+        assert_eq!(status.staged, vec![entry("README", ChangeKind::Modified), entry("src/lib.rs", ChangeKind::Deleted)]);
+        assert!(status.unstaged.is_empty());
+
+        assert!(matches!(repo.add(&["missing"]).await, Err(IndexError::PathNotFound(_))));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_add_modes() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repository(dir.path()).await;
+        std::fs::write(dir.path().join("run.sh"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(dir.path().join("run.sh"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink("run.sh", dir.path().join("link")).unwrap();
+
+        repo.add(&["."]).await.unwrap();
+        let index = repo.index().await.unwrap();
+        assert_eq!(index.get("run.sh").unwrap().mode, FileMode::Executable);
+        let link = index.get("link").unwrap();
+        assert_eq!(link.mode, FileMode::Symlink);
+        assert_eq!(link.id, GitObject::Blob(gitnext_core::Blob::new(bytes::Bytes::from_static(b"run.sh"))).canonical_hash());
+
+        // A symlink replaced by a regular file changes type
+        std::fs::remove_file(dir.path().join("link")).unwrap();
+        std::fs::write(dir.path().join("link"), "run.sh").unwrap();
+        let status = repo.status().await.unwrap();
+        assert_eq!(status.unstaged, vec![entry("link", ChangeKind::TypeChanged)]);
+    }
+
+    #[tokio::test]
+    async fn test_remove() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repository(dir.path()).await;
+        std::fs::create_dir_all(dir.path().join("docs")).unwrap();
+        std::fs::write(dir.path().join("docs/guide.md"), "guide\n").unwrap();
+        std::fs::write(dir.path().join("keep.txt"), "keep\n").unwrap();
+        repo.add(&["."]).await.unwrap();
+
+        // Staged but uncommitted content would be lost
+        assert!(matches!(repo.remove(&["docs"], false).await, Err(IndexError::LocalModifications(_))));
+
+        repo.remove(&["docs"], true).await.unwrap();
+        assert!(dir.path().join("docs/guide.md").exists());
+        assert_eq!(repo.status().await.unwrap().untracked, vec!["docs/guide.md"]);
+
+        repo.add(&["docs"]).await.unwrap();
+        let tree = repo.index().await.unwrap().write_tree(repo.storage.as_ref()).await.unwrap();
+        let author = gitnext_core::Signature {
+            name: "Test".to_string(),
+            email: "test@example.com".to_string(),
+            timestamp: 0,
+            timezone_offset: 0,
+            raw: None,
+        };
+        let head = repo.head().await.unwrap();
+        repo.commit(&tree, vec![head], author.clone(), author, "Add docs".to_string()).await.unwrap();
+
+        repo.remove(&["docs"], false).await.unwrap();
+        assert!(!dir.path().join("docs").exists());
+        assert!(dir.path().join("keep.txt").exists());
+        let status = repo.status().await.unwrap();
+        assert_eq!(status.staged, vec![entry("docs/guide.md", ChangeKind::Deleted)]);
+        assert!(matches!(repo.remove(&["docs"], false).await, Err(IndexError::PathNotFound(_))));
+    }
+
+    #[tokio::test]
+    async fn test_undo_and_redo_staging() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = repository(dir.path()).await;
+        std::fs::write(dir.path().join("a.txt"), "a\n").unwrap();
+
+        repo.add(&["a.txt"]).await.unwrap();
+        assert_eq!(repo.status().await.unwrap().staged, vec![entry("a.txt", ChangeKind::Added)]);
+
+        let undone = repo.undo().await.unwrap();
+        assert!(matches!(undone, Some(Operation::UpdateIndex { ref command, .. }) if command == "add"));
+        let status = repo.status().await.unwrap();
+        assert!(status.staged.is_empty());
+        assert_eq!(status.untracked, vec!["a.txt"]);
+
+        repo.redo().await.unwrap();
+        assert_eq!(repo.status().await.unwrap().staged, vec![entry("a.txt", ChangeKind::Added)]);
+    }
+
+    #[tokio::test]
+    async fn test_requires_work_tree() {
+        let repo = Repository::init(Arc::new(MemoryStorage::new())).await.unwrap();
+        assert!(matches!(repo.status().await, Err(IndexError::NoWorkTree)));
+        assert!(matches!(repo.add(&["."]).await, Err(IndexError::NoWorkTree)));
+    }
+}
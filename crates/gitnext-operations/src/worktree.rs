@@ -0,0 +1,520 @@
+//! Moving trees between storage and the file system
+//!
+//! `write_tree_from_dir` stores a directory as a tree, skipping `.git` and paths
+//! ignored by `.gitignore` files. `checkout_tree` writes a tree out to a directory,
+//! using the index of what is checked out there to find stale files and local
+//! modifications.
+
+use crate::ignore::IgnoreRules;
+use crate::index::{flatten_tree, is_within, IndexEntry, IndexError, IndexState, StatData};
+use crate::repository::Repository;
+use gitnext_core::{Blob, FileMode, GitObject, ObjectId, TreeEntry};
+use gitnext_storage::{Storage, StorageError};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::Metadata;
+use std::path::{Path, PathBuf};
+
+pub(crate) fn work_path(root: &Path, path: &str) -> PathBuf {
+    if path.is_empty() {
+        root.to_path_buf()
+    } else {
+        root.join(path)
+    }
+}
+
+/// Refuse paths with components that would leave the work tree or reach into
+/// `.git`, whatever tree they came from
+fn check_path(path: &str) -> Result<(), IndexError> {
+    if path.split('/').all(TreeEntry::is_valid_name) {
+        Ok(())
+    } else {
+        Err(IndexError::InvalidPath(path.to_string()))
+    }
+}
+
+/// Metadata of `path` itself (not a symlink's target), or `None` when missing
+pub(crate) fn symlink_metadata(path: &Path) -> Result<Option<Metadata>, IndexError> {
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) => Ok(Some(metadata)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Index mode of a working file
+pub(crate) fn file_mode(metadata: &Metadata) -> FileMode {
+    if metadata.file_type().is_symlink() {
+        return FileMode::Symlink;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 != 0 {
+            return FileMode::Executable;
+        }
+    }
+    FileMode::Normal
+}
+
+/// Contents of a working file as a blob: the target of a symlink, else its bytes
+fn read_blob(path: &Path, metadata: &Metadata) -> Result<GitObject, IndexError> {
+    let content = if metadata.file_type().is_symlink() {
+        let target = std::fs::read_link(path)?;
+        target.to_str()
+            .ok_or_else(|| IndexError::InvalidPath(path.display().to_string()))?
+            .as_bytes()
+            .to_vec()
+    } else {
+        std::fs::read(path)?
+    };
+    Ok(GitObject::Blob(Blob::new(bytes::Bytes::from(content))))
+}
+
+/// Index entry for the working file at `path`. The file is read only when its stat
+/// data differs from `cached`; the blob is returned when it was read.
+pub(crate) fn work_file_entry(
+    root: &Path,
+    path: &str,
+    metadata: &Metadata,
+    cached: Option<&IndexEntry>,
+) -> Result<(IndexEntry, Option<GitObject>), IndexError> {
+    let mode = file_mode(metadata);
+    let stat = StatData::from_metadata(metadata);
+    if let Some(cached) = cached {
+        if cached.stat == stat && cached.mode == mode && cached.stat != StatData::default() {
+            return Ok((cached.clone(), None));
+        }
+    }
+    let blob = read_blob(&work_path(root, path), metadata)?;
+    let entry = IndexEntry { stat, ..IndexEntry::new(path, mode, blob.canonical_hash()) };
+    Ok((entry, Some(blob)))
+}
+
+/// Files at or beneath `prefix`, skipping `.git` and ignored paths. Ignored paths
+/// for which `tracked` holds are still walked, as ignore rules do not apply to
+/// files already in the index.
+pub(crate) fn walk(
+    root: &Path,
+    prefix: &str,
+    tracked: &dyn Fn(&str) -> bool,
+) -> Result<BTreeMap<String, Metadata>, IndexError> {
+    let mut rules = IgnoreRules::new();
+    // Ignore files of the directories above the prefix apply too
+    let mut dir = String::new();
+    for component in prefix.split('/').filter(|c| !c.is_empty()) {
+        load_gitignore(root, &dir, &mut rules)?;
+        dir = if dir.is_empty() { component.to_string() } else { format!("{}/{}", dir, component) };
+    }
+
+    let mut files = BTreeMap::new();
+    if let Some(metadata) = symlink_metadata(&work_path(root, prefix))? {
+        visit(root, prefix, metadata, &mut rules, tracked, &mut files)?;
+    }
+    Ok(files)
+}
+
+fn load_gitignore(root: &Path, dir: &str, rules: &mut IgnoreRules) -> Result<(), IndexError> {
+    let path = work_path(root, dir).join(".gitignore");
+    match std::fs::read(&path) {
+        Ok(contents) => rules.add_patterns(dir, &String::from_utf8_lossy(&contents)),
+        Err(e) if matches!(e.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::NotADirectory) => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+fn visit(
+    root: &Path,
+    path: &str,
+    metadata: Metadata,
+    rules: &mut IgnoreRules,
+    tracked: &dyn Fn(&str) -> bool,
+    files: &mut BTreeMap<String, Metadata>,
+) -> Result<(), IndexError> {
+    let is_dir = metadata.is_dir();
+    if !path.is_empty() && rules.is_ignored(path, is_dir) && !tracked(path) {
+        return Ok(());
+    }
+    if !is_dir {
+        files.insert(path.to_string(), metadata);
+        return Ok(());
+    }
+
+    load_gitignore(root, path, rules)?;
+    for entry in std::fs::read_dir(work_path(root, path))? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_str().ok_or_else(|| IndexError::InvalidPath(entry.path().display().to_string()))?;
+        if name == ".git" {
+            continue;
+        }
+        let child = if path.is_empty() { name.to_string() } else { format!("{}/{}", path, name) };
+        visit(root, &child, entry.metadata()?, rules, tracked, files)?;
+    }
+    Ok(())
+}
+
+/// Remove the directories above `file` that are left empty, up to `root`
+fn prune_empty_parents(root: &Path, file: &Path) {
+    let mut dir = file.parent();
+    while let Some(parent) = dir.filter(|dir| *dir != root && dir.starts_with(root)) {
+        if std::fs::remove_dir(parent).is_err() {
+            break;
+        }
+        dir = parent.parent();
+    }
+}
+
+pub(crate) fn remove_work_file(root: &Path, path: &str) -> Result<(), IndexError> {
+    let file = work_path(root, path);
+    match symlink_metadata(&file)? {
+        Some(metadata) if !metadata.is_dir() => std::fs::remove_file(&file)?,
+        _ => return Ok(()),
+    }
+    prune_empty_parents(root, &file);
+    Ok(())
+}
+
+/// Store the files of `dir` as blobs and trees, returning the root tree. `.git` and
+/// ignored paths are skipped; executable files and symlinks keep their modes.
+pub async fn write_tree_from_dir(storage: &dyn Storage, dir: &Path) -> Result<ObjectId, IndexError> {
+    let files = walk(dir, "", &|_| false)?;
+    let mut entries = Vec::with_capacity(files.len());
+    for (path, metadata) in &files {
+        let (entry, blob) = work_file_entry(dir, path, metadata, None)?;
+        if let Some(blob) = blob {
+            storage.store_object(&entry.id, &blob).await?;
+        }
+        entries.push(entry);
+    }
+    entries.into_iter().collect::<IndexState>().write_tree(storage).await
+}
+
+/// Write `content` to `path` as a file of `mode`, replacing whatever is there
+fn write_file(root: &Path, path: &str, mode: FileMode, content: &[u8]) -> Result<(), IndexError> {
+    check_path(path)?;
+    // Files in the way of the parent directories go first
+    let mut dir = String::new();
+    let components: Vec<&str> = path.split('/').collect();
+    for component in &components[..components.len() - 1] {
+        dir = if dir.is_empty() { component.to_string() } else { format!("{}/{}", dir, component) };
+        if symlink_metadata(&work_path(root, &dir))?.is_some_and(|metadata| !metadata.is_dir()) {
+            std::fs::remove_file(work_path(root, &dir))?;
+        }
+    }
+    let file = work_path(root, path);
+    if let Some(parent) = file.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    match symlink_metadata(&file)? {
+        Some(metadata) if metadata.is_dir() => std::fs::remove_dir_all(&file)?,
+        Some(_) => std::fs::remove_file(&file)?,
+        None => {}
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if mode == FileMode::Symlink {
+            let target = std::str::from_utf8(content).map_err(|_| IndexError::InvalidPath(path.to_string()))?;
+            std::os::unix::fs::symlink(target, &file)?;
+            return Ok(());
+        }
+        std::fs::write(&file, content)?;
+        if mode == FileMode::Executable {
+            // Executable by whoever may read it, as Git does under the umask
+            let mut permissions = std::fs::metadata(&file)?.permissions();
+            let readable = permissions.mode() & 0o444;
+            permissions.set_mode(permissions.mode() | (readable >> 2));
+            std::fs::set_permissions(&file, permissions)?;
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        // Without symlink support the link target is written as a file, as Git does
+        let _ = mode;
+        std::fs::write(&file, content)?;
+    }
+    Ok(())
+}
+
+/// Write the tree `tree_id` out to `dir`, where `current` describes what is checked
+/// out now, and return the index of the result.
+///
+/// Files tracked by `current` but not in the tree are removed. Unless `force` is
+/// set, the checkout is refused when it would overwrite or remove a modified
+/// tracked file or an untracked file; local changes to files the checkout leaves
+/// alone are kept.
+pub async fn checkout_tree(
+    storage: &dyn Storage,
+    tree_id: &ObjectId,
+    dir: &Path,
+    current: &IndexState,
+    force: bool,
+) -> Result<IndexState, IndexError> {
+    if !force {
+        if let Some(path) = current.conflicted_paths().into_iter().next() {
+            return Err(IndexError::Unmerged(path));
+        }
+    }
+    let target = flatten_tree(storage, tree_id).await?;
+    for path in target.keys() {
+        check_path(path)?;
+    }
+
+    // Tracked files: modified, verified unchanged, or missing
+    let mut conflicts = BTreeSet::new();
+    let mut kept = BTreeMap::new();
+    for entry in current.entries().iter().filter(|e| e.stage == 0) {
+        let untouched = target.get(&entry.path) == Some(&(entry.mode, entry.id));
+        let Some(metadata) = symlink_metadata(&work_path(dir, &entry.path))? else {
+            // A deleted file stays deleted if the checkout leaves it alone
+            if untouched {
+                kept.insert(entry.path.clone(), entry.clone());
+            }
+            continue;
+        };
+        let file = if metadata.is_dir() {
+            None
+        } else {
+            Some(work_file_entry(dir, &entry.path, &metadata, Some(entry))?.0)
+        };
+        let modified = file.as_ref().is_none_or(|file| file.mode != entry.mode || file.id != entry.id);
+        if modified && !untouched {
+            conflicts.insert(entry.path.clone());
+        } else if untouched {
+            kept.insert(entry.path.clone(), file.filter(|_| !modified).unwrap_or_else(|| entry.clone()));
+        }
+    }
+
+    // Untracked files where the tree puts something
+    for (path, (mode, id)) in &target {
+        if current.contains(path) {
+            continue;
+        }
+        let mut dir_path = String::new();
+        for component in path.split('/').take(path.matches('/').count()) {
+            dir_path = if dir_path.is_empty() { component.to_string() } else { format!("{}/{}", dir_path, component) };
+            let obstacle = symlink_metadata(&work_path(dir, &dir_path))?.is_some_and(|m| !m.is_dir());
+            if obstacle && !current.contains(&dir_path) {
+                conflicts.insert(dir_path.clone());
+            }
+        }
+        match symlink_metadata(&work_path(dir, path))? {
+            Some(metadata) if metadata.is_dir() => {
+                let untracked = walk(dir, path, &|p| current.contains(p))?;
+                conflicts.extend(untracked.into_keys().filter(|p| !current.contains(p)));
+            }
+            Some(metadata) => {
+                let (file, _) = work_file_entry(dir, path, &metadata, None)?;
+                if (file.mode, file.id) == (*mode, *id) {
+                    kept.insert(path.clone(), file);
+                } else {
+                    conflicts.insert(path.clone());
+                }
+            }
+            None => {}
+        }
+    }
+    if !force && !conflicts.is_empty() {
+        return Err(IndexError::WouldClobber(conflicts.into_iter().collect()));
+    }
+
+    // Stale files go first, so that directories can take their place
+    for path in current.entries().iter().map(|e| &e.path).collect::<BTreeSet<_>>() {
+        if !target.contains_key(path) {
+            remove_work_file(dir, path)?;
+        }
+    }
+
+    let mut entries = Vec::with_capacity(target.len());
+    for (path, (mode, id)) in target {
+        if let Some(entry) = kept.remove(&path) {
+            entries.push(entry);
+            continue;
+        }
+        let content = match storage.load_object(&id).await? {
+            Some(GitObject::Blob(blob)) => blob.content.unwrap_or_default(),
+            Some(_) => {
+                return Err(StorageError::CorruptionDetected { id, details: format!("'{}' is not a blob", path) }.into());
+            }
+            None => return Err(StorageError::ObjectNotFound { id }.into()),
+        };
+        write_file(dir, &path, mode, &content)?;
+        let metadata = std::fs::symlink_metadata(work_path(dir, &path))?;
+        entries.push(IndexEntry { stat: StatData::from_metadata(&metadata), ..IndexEntry::new(path, mode, id) });
+    }
+    Ok(entries.into_iter().collect())
+}
+
+impl Repository {
+    /// Store the files of `path` as a tree; see [`write_tree_from_dir`]
+    pub async fn write_tree_from_dir(&self, path: impl AsRef<Path>) -> Result<ObjectId, IndexError> {
+        write_tree_from_dir(self.storage.as_ref(), path.as_ref()).await
+    }
+
+    /// Check out `tree_id` into the working tree and make it the index; see
+    /// [`checkout_tree`]
+    pub async fn checkout_tree(&self, tree_id: &ObjectId, force: bool) -> Result<(), IndexError> {
+        let root = self.work_tree().ok_or(IndexError::NoWorkTree)?;
+        let current = self.index().await?;
+        let index = checkout_tree(self.storage.as_ref(), tree_id, root, &current, force).await?;
+        index.save(self.storage.as_ref()).await?;
+        Ok(())
+    }
+}
+
+/// Whether any entry of `index` lies at or beneath `path`
+pub(crate) fn tracks_within(index: &IndexState, path: &str) -> bool {
+    index.entries().iter().any(|e| is_within(&e.path, path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gitnext_storage::memory::MemoryStorage;
+
+    fn write(dir: &Path, path: &str, content: &str) {
+        let file = dir.join(path);
+        std::fs::create_dir_all(file.parent().unwrap()).unwrap();
+        std::fs::write(file, content).unwrap();
+    }
+
+    async fn store(storage: &MemoryStorage, object: GitObject) -> ObjectId {
+        let id = object.canonical_hash();
+        storage.store_object(&id, &object).await.unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn test_checkout_refuses_unsafe_paths() {
+        use gitnext_core::{ObjectType, Tree};
+        let storage = MemoryStorage::new();
+        let blob = store(&storage, GitObject::Blob(Blob::new(bytes::Bytes::from_static(b"pwned\n")))).await;
+        let file = |name: &str| TreeEntry { name: name.to_string(), mode: FileMode::Normal, hash: blob, entry_type: ObjectType::Blob, raw_name: None };
+        let inner = store(&storage, GitObject::Tree(Tree::new(vec![file("escaped")]))).await;
+        let dir = |name: &str| TreeEntry { name: name.to_string(), mode: FileMode::Tree, hash: inner, entry_type: ObjectType::Tree, raw_name: None };
+
+        let root = tempfile::tempdir().unwrap();
+        let work = root.path().join("work");
+        std::fs::create_dir(&work).unwrap();
+        let absolute = root.path().join("absolute").display().to_string();
+        for entries in [
+            vec![dir("..")],
+            vec![dir(".git")],
+            vec![dir(".Git")],
+            vec![file("../escaped")],
+            vec![file(&absolute)],
+            vec![file("ok.txt"), dir("sub"), dir(".")],
+        ] {
+            let tree = store(&storage, GitObject::Tree(Tree::new(entries))).await;
+            let result = checkout_tree(&storage, &tree, &work, &IndexState::new(), true).await;
+            assert!(matches!(result, Err(IndexError::InvalidPath(_))), "{:?}", result.err());
+        }
+        assert!(!root.path().join("escaped").exists());
+        assert!(!root.path().join("absolute").exists());
+        assert_eq!(std::fs::read_dir(&work).unwrap().count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_write_tree_from_dir_respects_gitignore() {
+        let dir = tempfile::tempdir().unwrap();
+        write(dir.path(), ".gitignore", "*.log\n/target/\n");
+        write(dir.path(), "src/lib.rs", "lib\n");
+        write(dir.path(), "src/debug.log", "noise\n");
+        write(dir.path(), "src/.gitignore", "!keep.log\n");
+        write(dir.path(), "src/keep.log", "kept\n");
+        write(dir.path(), "target/out", "binary\n");
+        write(dir.path(), ".git/HEAD", "ref: refs/heads/main\n");
+
+        let storage = MemoryStorage::new();
+        let tree = write_tree_from_dir(&storage, dir.path()).await.unwrap();
+        let files = flatten_tree(&storage, &tree).await.unwrap();
+        assert_eq!(files.keys().collect::<Vec<_>>(), vec![".gitignore", "src/.gitignore", "src/keep.log", "src/lib.rs"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_modes_round_trip() {
+        use std::os::unix::fs::PermissionsExt;
+        let source = tempfile::tempdir().unwrap();
+        write(source.path(), "bin/run", "#!/bin/sh\n");
+        std::fs::set_permissions(source.path().join("bin/run"), std::fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink("bin/run", source.path().join("run")).unwrap();
+
+        let storage = MemoryStorage::new();
+        let tree = write_tree_from_dir(&storage, source.path()).await.unwrap();
+        let files = flatten_tree(&storage, &tree).await.unwrap();
+        assert_eq!(files["bin/run"].0, FileMode::Executable);
+        assert_eq!(files["run"].0, FileMode::Symlink);
+
+        let target = tempfile::tempdir().unwrap();
+        checkout_tree(&storage, &tree, target.path(), &IndexState::new(), false).await.unwrap();
+        assert!(std::fs::metadata(target.path().join("bin/run")).unwrap().permissions().mode() & 0o111 != 0);
+        assert_eq!(std::fs::read_link(target.path().join("run")).unwrap(), Path::new("bin/run"));
+        assert_eq!(write_tree_from_dir(&storage, target.path()).await.unwrap(), tree);
+    }
+
+    #[tokio::test]
+    async fn test_checkout_replaces_files_and_protects_changes() {
+        let storage = MemoryStorage::new();
+        let source = tempfile::tempdir().unwrap();
+        write(source.path(), "a.txt", "a1\n");
+        write(source.path(), "old/stale.txt", "stale\n");
+        let first = write_tree_from_dir(&storage, source.path()).await.unwrap();
+
+        std::fs::remove_dir_all(source.path().join("old")).unwrap();
+        write(source.path(), "a.txt", "a2\n");
+        write(source.path(), "new/file.txt", "new\n");
+        let second = write_tree_from_dir(&storage, source.path()).await.unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let index = checkout_tree(&storage, &first, dir.path(), &IndexState::new(), false).await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.path().join("a.txt")).unwrap(), "a1\n");
+
+        // Modified tracked files and untracked files in the way are protected
+        write(dir.path(), "a.txt", "local\n");
+        write(dir.path(), "new/file.txt", "untracked\n");
+        let result = checkout_tree(&storage, &second, dir.path(), &index, false).await;
+        match result {
+            Err(IndexError::WouldClobber(paths)) => assert_eq!(paths, vec!["a.txt", "new/file.txt"]),
+            other => panic!("expected a refusal, got {:?}", other),
+        }
+        assert_eq!(std::fs::read_to_string(dir.path().join("a.txt")).unwrap(), "local\n");
+
+        // Forcing overwrites them and removes stale files with their directories
+        let index = checkout_tree(&storage, &second, dir.path(), &index, true).await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.path().join("a.txt")).unwrap(), "a2\n");
+        assert_eq!(std::fs::read_to_string(dir.path().join("new/file.txt")).unwrap(), "new\n");
+        assert!(!dir.path().join("old").exists());
+        assert_eq!(index.write_tree(&storage).await.unwrap(), second);
+
+        // Local changes to files the checkout does not touch are carried over
+        write(dir.path(), "new/file.txt", "edited\n");
+        checkout_tree(&storage, &first, dir.path(), &index, false).await.unwrap_err();
+        write(dir.path(), "new/file.txt", "new\n");
+        write(dir.path(), "untracked.txt", "mine\n");
+        let index = checkout_tree(&storage, &first, dir.path(), &index, false).await.unwrap();
+        assert!(!dir.path().join("new").exists());
+        assert_eq!(std::fs::read_to_string(dir.path().join("untracked.txt")).unwrap(), "mine\n");
+        write(dir.path(), "old/stale.txt", "edited\n");
+        let error = checkout_tree(&storage, &second, dir.path(), &index, false).await.unwrap_err();
+        assert!(matches!(error, IndexError::WouldClobber(paths) if paths == vec!["old/stale.txt"]));
+    }
+
+    #[tokio::test]
+    async fn test_repository_checkout_updates_index() {
+        let storage = std::sync::Arc::new(MemoryStorage::new());
+        let source = tempfile::tempdir().unwrap();
+        write(source.path(), "README", "readme\n");
+        let dir = tempfile::tempdir().unwrap();
+        let repo = Repository::init(storage).await.unwrap().with_work_tree(dir.path());
+        let tree = repo.write_tree_from_dir(source.path()).await.unwrap();
+
+        repo.checkout_tree(&tree, false).await.unwrap();
+        assert_eq!(std::fs::read_to_string(dir.path().join("README")).unwrap(), "readme\n");
+        let status = repo.status().await.unwrap();
+        assert_eq!(status.staged.len(), 1);
+        assert!(status.unstaged.is_empty() && status.untracked.is_empty());
+    }
+}
@@ -0,0 +1,166 @@
+//! Clone, fetch and push against `git http-backend`
+
+#[path = "../../gitnext-protocol/tests/common/mod.rs"]
+mod common;
+
+use common::http::{served_repo, HttpBackend};
+use common::GitFixture;
+use gitnext_compat::GitHashIndex;
+use gitnext_core::{Blob, FileMode, GitHashType, GitObject, ObjectId, ObjectType, Signature, Tree, TreeEntry};
+use gitnext_operations::repository::{Operation, Repository};
+use gitnext_protocol::ProtocolError;
+use gitnext_storage::{MemoryStorage, ReferenceTarget, Storage};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+struct Remote {
+    fixture: GitFixture,
+    backend: HttpBackend,
+    path: PathBuf,
+}
+
+impl Remote {
+    async fn start() -> Option<Self> {
+        let fixture = GitFixture::init(&[])?;
+        fixture.build_delta_history(6);
+        fixture.git(&["branch", "side", "main~2"]);
+
+        let root = fixture.scratch("served");
+        let path = served_repo(&fixture, &root, "remote.git", &[]);
+        fixture.git(&["push", "-q", path.to_str().unwrap(), "--all"]);
+        fixture.git(&["push", "-q", path.to_str().unwrap(), "--tags"]);
+        let backend = HttpBackend::start(&fixture, &root).await;
+        Some(Self { fixture, backend, path })
+    }
+
+    fn url(&self) -> String {
+        self.backend.url("remote.git")
+    }
+
+    fn rev_parse(&self, rev: &str) -> String {
+        self.fixture.git_in(&self.path, &["rev-parse", rev])
+    }
+}
+
+async fn ref_target(storage: &dyn Storage, name: &str) -> Option<ObjectId> {
+    storage.list_refs().await.unwrap().into_iter()
+        .find(|r| r.name == name)
+        .and_then(|r| match r.target {
+            ReferenceTarget::Direct(id) => Some(id),
+            ReferenceTarget::Symbolic(_) => None,
+        })
+}
+
+async fn git_hash(storage: &Arc<dyn Storage>, name: &str) -> String {
+    let id = ref_target(storage.as_ref(), name).await.unwrap_or_else(|| panic!("{} missing", name));
+    let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+    index.derive(&id, GitHashType::Sha1).await.unwrap().to_string()
+}
+
+/// Commit a new file on top of the current branch
+async fn commit_file(repo: &Repository, storage: &dyn Storage, name: &str, content: &str) -> ObjectId {
+    let head = repo.head().await.unwrap();
+    let Some(GitObject::Commit(parent)) = storage.load_object(&head).await.unwrap() else { panic!() };
+    let Some(GitObject::Tree(tree)) = storage.load_object(&parent.tree).await.unwrap() else { panic!() };
+
+    let blob = GitObject::Blob(Blob::new(bytes::Bytes::from(content.as_bytes().to_vec())));
+    let blob_id = blob.canonical_hash();
+    storage.store_object(&blob_id, &blob).await.unwrap();
+    let mut entries = tree.entries.clone();
+    entries.push(TreeEntry { name: name.to_string(), mode: FileMode::Normal, hash: blob_id, entry_type: ObjectType::Blob, raw_name: None });
+    let tree = GitObject::Tree(Tree::new(entries));
+    let tree_id = tree.canonical_hash();
+    storage.store_object(&tree_id, &tree).await.unwrap();
+
+    let signature = Signature {
+        name: "Local Author".to_string(),
+        email: "local@example.com".to_string(),
+        timestamp: 1234567999,
+        timezone_offset: 60,
+        raw: None,
+    };
+    repo.commit(&tree_id, vec![head], signature.clone(), signature, format!("Add {}\n", name)).await.unwrap()
+}
+
+#[tokio::test]
+async fn test_clone_from_http() {
+    let Some(remote) = Remote::start().await else { return };
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let repo = Repository::clone_from(&remote.url(), storage.clone()).await.unwrap();
+
+    assert_eq!(repo.remote_url("origin").await.unwrap(), Some(remote.url()));
+    assert_eq!(repo.get_current_branch().await.unwrap().as_deref(), Some("main"));
+    assert_eq!(git_hash(&storage, "HEAD").await, remote.rev_parse("main"));
+    assert_eq!(git_hash(&storage, "refs/heads/main").await, remote.rev_parse("main"));
+    assert_eq!(git_hash(&storage, "refs/remotes/origin/main").await, remote.rev_parse("main"));
+    assert_eq!(git_hash(&storage, "refs/remotes/origin/side").await, remote.rev_parse("side"));
+    assert_eq!(git_hash(&storage, "refs/tags/v1").await, remote.rev_parse("v1"));
+    assert!(matches!(repo.peek_undo().await.unwrap(), Some(Operation::Fetch { .. })));
+
+    // Cloning needs empty storage
+    assert!(Repository::clone_from(&remote.url(), storage.clone()).await.is_err());
+}
+
+#[tokio::test]
+async fn test_fetch_updates_tracking_refs() {
+    let Some(remote) = Remote::start().await else { return };
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let repo = Repository::clone_from(&remote.url(), storage.clone()).await.unwrap();
+    let cloned_main = ref_target(storage.as_ref(), "refs/remotes/origin/main").await.unwrap();
+
+    // Nothing new yet
+    let report = repo.fetch("origin").await.unwrap();
+    assert_eq!(report.objects_imported, 0);
+    assert!(report.updated_refs.is_empty());
+
+    remote.fixture.write("src/new.rs", "pub fn added() {}\n");
+    remote.fixture.commit("Add new.rs");
+    remote.fixture.git(&["push", "-q", remote.path.to_str().unwrap(), "main"]);
+
+    let report = repo.fetch("origin").await.unwrap();
+    // Commit, root tree, src tree and the new blob
+    assert_eq!(report.objects_imported, 4);
+    assert_eq!(report.updated_refs.len(), 1);
+    assert_eq!(git_hash(&storage, "refs/remotes/origin/main").await, remote.rev_parse("main"));
+    // The local branch is left alone
+    assert_eq!(ref_target(storage.as_ref(), "refs/heads/main").await, Some(cloned_main));
+
+    // Undo rewinds the tracking ref
+    repo.undo().await.unwrap();
+    assert_eq!(ref_target(storage.as_ref(), "refs/remotes/origin/main").await, Some(cloned_main));
+
+    assert!(repo.fetch("upstream").await.is_err());
+}
+
+#[tokio::test]
+async fn test_push_to_http() {
+    let Some(remote) = Remote::start().await else { return };
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let repo = Repository::clone_from(&remote.url(), storage.clone()).await.unwrap();
+
+    let local = commit_file(&repo, storage.as_ref(), "local.txt", "made in gitnext\n").await;
+    repo.push("origin", &["main", "main:refs/heads/copy"]).await.unwrap();
+
+    let pushed = git_hash(&storage, "refs/heads/main").await;
+    assert_eq!(remote.rev_parse("main"), pushed);
+    assert_eq!(remote.rev_parse("copy"), pushed);
+    assert_eq!(remote.fixture.git_in(&remote.path, &["cat-file", "blob", "main:local.txt"]), "made in gitnext");
+    remote.fixture.git_in(&remote.path, &["fsck", "--strict", "--no-dangling"]);
+    assert_eq!(ref_target(storage.as_ref(), "refs/remotes/origin/main").await, Some(local));
+
+    // Someone else pushes; our next push is no longer a fast-forward
+    remote.fixture.write("other.txt", "elsewhere\n");
+    let other = remote.fixture.commit("Elsewhere");
+    remote.fixture.git(&["push", "-q", "-f", remote.path.to_str().unwrap(), "HEAD:refs/heads/main"]);
+    commit_file(&repo, storage.as_ref(), "second.txt", "again\n").await;
+    let result = repo.push("origin", &["main"]).await;
+    assert!(matches!(result, Err(ProtocolError::Rejected { name, .. }) if name == "refs/heads/main"));
+    assert_eq!(remote.rev_parse("main"), other);
+
+    // Forcing overwrites it, and an empty source deletes
+    repo.push("origin", &["+main", ":side"]).await.unwrap();
+    assert_eq!(remote.rev_parse("main"), git_hash(&storage, "refs/heads/main").await);
+    assert!(remote.fixture.git_in(&remote.path, &["branch", "--list", "side"]).is_empty());
+    assert!(ref_target(storage.as_ref(), "refs/remotes/origin/side").await.is_none());
+    remote.fixture.git_in(&remote.path, &["fsck", "--strict", "--no-dangling"]);
+}
@@ -0,0 +1,78 @@
+//! `git://` transport: raw pkt-lines over TCP to `git daemon`
+//!
+//! The client opens each connection with a request naming the service and the
+//! repository path, `git-upload-pack /repo.git\0host=example.com\0`, followed by
+//! `\0version=2\0` to ask for protocol v2. Everything after that is the service's own
+//! protocol. The daemon has no authentication; pushing requires it to run with
+//! `--enable=receive-pack`.
+
+use crate::pkt_line;
+use crate::stream::{PktStream, ServiceConnector};
+use crate::url::GIT_DAEMON_PORT;
+use crate::{ProtocolError, Result};
+use async_trait::async_trait;
+use tokio::net::TcpStream;
+
+/// Connects to the services of one repository on a `git daemon`
+pub struct DaemonConnector {
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl DaemonConnector {
+    pub fn new(host: &str, port: u16, path: &str) -> Self {
+        Self {
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        }
+    }
+
+    /// The initial request line
+    fn request(&self, service: &str, protocol_v2: bool) -> Vec<u8> {
+        let host = if self.port == GIT_DAEMON_PORT {
+            self.host.clone()
+        } else if self.host.contains(':') {
+            format!("[{}]:{}", self.host, self.port)
+        } else {
+            format!("{}:{}", self.host, self.port)
+        };
+
+        let mut payload = format!("{} {}\0host={}\0", service, self.path, host).into_bytes();
+        if protocol_v2 {
+            payload.extend_from_slice(b"\0version=2\0");
+        }
+        let mut request = Vec::new();
+        pkt_line::write_data(&mut request, &payload);
+        request
+    }
+}
+
+#[async_trait]
+impl ServiceConnector for DaemonConnector {
+    async fn open(&self, service: &str, protocol_v2: bool) -> Result<PktStream> {
+        let socket = TcpStream::connect((self.host.as_str(), self.port)).await
+            .map_err(|e| ProtocolError::Transport(format!("Cannot connect to {}:{}: {}", self.host, self.port, e)))?;
+        socket.set_nodelay(true)?;
+        let (reader, writer) = socket.into_split();
+
+        let mut stream = PktStream::new(Box::new(reader), Box::new(writer));
+        stream.send(&self.request(service, protocol_v2)).await?;
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_daemon_request() {
+        let connector = DaemonConnector::new("example.com", GIT_DAEMON_PORT, "/repo.git");
+        assert_eq!(connector.request("git-upload-pack", true), b"003agit-upload-pack /repo.git\0host=example.com\0\0version=2\0");
+
+        let connector = DaemonConnector::new("::1", 1234, "/repo.git");
+        assert_eq!(connector.request("git-receive-pack", false), b"002fgit-receive-pack /repo.git\0host=[::1]:1234\0");
+    }
+}
@@ -0,0 +1,95 @@
+//! Smart HTTP channel
+//!
+//! Advertisements come from `GET $url/info/refs?service=...`; commands are `POST`ed
+//! to `$url/git-upload-pack` and `$url/git-receive-pack`. Every request stands alone,
+//! which protocol v2 is designed for.
+
+use crate::smart::Channel;
+use crate::{ProtocolError, Result};
+use async_trait::async_trait;
+
+const UPLOAD_PACK: &str = "git-upload-pack";
+const RECEIVE_PACK: &str = "git-receive-pack";
+
+/// `Channel` over Git's smart HTTP protocol
+pub struct HttpChannel {
+    client: reqwest::Client,
+    /// Repository URL without a trailing slash
+    base_url: String,
+}
+
+fn transport_error(error: reqwest::Error) -> ProtocolError {
+    ProtocolError::Transport(error.to_string())
+}
+
+impl HttpChannel {
+    pub fn new(url: &str) -> Result<Self> {
+        let client = reqwest::Client::builder()
+            .user_agent(crate::smart::AGENT)
+            .build()
+            .map_err(transport_error)?;
+        Ok(Self {
+            client,
+            base_url: url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    async fn advertisement(&self, service: &str, protocol_v2: bool) -> Result<Vec<u8>> {
+        let url = format!("{}/info/refs?service={}", self.base_url, service);
+        let mut request = self.client.get(&url);
+        if protocol_v2 {
+            request = request.header("Git-Protocol", "version=2");
+        }
+        let response = Self::check(request.send().await.map_err(transport_error)?, &url)?;
+
+        // Dumb HTTP servers hand out info/refs as a plain file
+        let expected = format!("application/x-{}-advertisement", service);
+        let content_type = response.headers().get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default();
+        if content_type != expected {
+            return Err(ProtocolError::Unsupported(format!("{} is not a smart HTTP server", self.base_url)));
+        }
+        Ok(response.bytes().await.map_err(transport_error)?.to_vec())
+    }
+
+    async fn post(&self, service: &str, body: Vec<u8>, protocol_v2: bool) -> Result<Vec<u8>> {
+        let url = format!("{}/{}", self.base_url, service);
+        let mut request = self.client.post(&url)
+            .header(reqwest::header::CONTENT_TYPE, format!("application/x-{}-request", service))
+            .header(reqwest::header::ACCEPT, format!("application/x-{}-result", service))
+            .body(body);
+        if protocol_v2 {
+            request = request.header("Git-Protocol", "version=2");
+        }
+        let response = Self::check(request.send().await.map_err(transport_error)?, &url)?;
+        Ok(response.bytes().await.map_err(transport_error)?.to_vec())
+    }
+
+    fn check(response: reqwest::Response, url: &str) -> Result<reqwest::Response> {
+        let status = response.status();
+        if !status.is_success() {
+            return Err(ProtocolError::Transport(format!("{} returned {}", url, status)));
+        }
+        Ok(response)
+    }
+}
+
+#[async_trait]
+impl Channel for HttpChannel {
+    async fn upload_pack_advertisement(&mut self) -> Result<Vec<u8>> {
+        self.advertisement(UPLOAD_PACK, true).await
+    }
+
+    async fn upload_pack(&mut self, request: Vec<u8>) -> Result<Vec<u8>> {
+        self.post(UPLOAD_PACK, request, true).await
+    }
+
+    async fn receive_pack_advertisement(&mut self) -> Result<Vec<u8>> {
+        self.advertisement(RECEIVE_PACK, false).await
+    }
+
+    async fn receive_pack(&mut self, request: Vec<u8>) -> Result<Vec<u8>> {
+        self.post(RECEIVE_PACK, request, false).await
+    }
+}
@@ -0,0 +1,85 @@
+//! GitNext Protocol - Git wire protocol support
+//!
+//! Packfiles are the unit of transfer in every Git protocol. This crate reads packs
+//! received from Git peers (resolving deltas and thin-pack bases) and writes
+//! delta-compressed packs from canonical objects, translating between Git hashes and
+//! `ObjectId`s through the compat layer's `GitHashIndex` (ADR-001, ADR-006).
+//!
+//! Remotes are reached through the `Transport` trait, over smart HTTP, `git://` or an
+//! ssh command. Fetches speak protocol v2 (`ls-refs`, `fetch`); pushes speak
+//! `git-receive-pack` with `report-status`.
+
+use gitnext_compat::CompatError;
+use gitnext_core::{GitHash, GitNextError};
+use gitnext_storage::StorageError;
+use thiserror::Error;
+
+/// Protocol error types
+#[derive(Debug, Error)]
+pub enum ProtocolError {
+    #[error("Compatibility error: {0}")]
+    Compat(#[from] CompatError),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error("Object error: {0}")]
+    Object(#[from] GitNextError),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid pack: {0}")]
+    InvalidPack(String),
+
+    #[error("Pack checksum mismatch: expected {expected}, found {actual}")]
+    ChecksumMismatch { expected: GitHash, actual: GitHash },
+
+    #[error("Delta base not found: {0}")]
+    MissingBase(GitHash),
+
+    #[error("Invalid protocol response: {0}")]
+    InvalidResponse(String),
+
+    #[error("Transport error: {0}")]
+    Transport(String),
+
+    #[error("Remote error: {0}")]
+    Remote(String),
+
+    #[error("Unsupported remote URL: {0}")]
+    UnsupportedUrl(String),
+
+    #[error("Unsupported by remote: {0}")]
+    Unsupported(String),
+
+    #[error("Push of {name} rejected: {reason}")]
+    Rejected { name: String, reason: String },
+}
+
+pub type Result<T> = std::result::Result<T, ProtocolError>;
+
+pub mod daemon;
+pub mod http;
+pub mod pack_reader;
+pub mod pack_writer;
+pub mod pkt_line;
+pub mod smart;
+pub mod ssh;
+pub mod stream;
+pub mod transfer;
+pub mod transport;
+pub mod url;
+
+pub use daemon::DaemonConnector;
+pub use http::HttpChannel;
+pub use pack_reader::{PackObject, PackReader, ParsedPack};
+pub use pack_writer::{PackWriter, PackWriterOptions};
+pub use smart::{Channel, SmartTransport};
+pub use ssh::SshConnector;
+pub use stream::{PktStream, ServiceConnector, StreamChannel};
+pub use transfer::{build_pack, build_pack_with_options, import_pack, load_git_object, resolve_thin_pack};
+pub use transport::{
+    connect, connect_with_options, PushReport, RefStatus, RefUpdate, RemoteRef, Transport, TransportOptions,
+};
+pub use url::RemoteUrl;
@@ -0,0 +1,410 @@
+//! Streaming pack v2 reader
+//!
+//! Packs are read front to back from any `Read` source, so they can be consumed
+//! straight off a network connection. Each entry is inflated as it arrives and its
+//! offset and CRC-32 recorded for the `.idx`; deltas are resolved once the whole pack
+//! has been read and its trailing checksum verified. REF_DELTA entries whose base is
+//! not in the pack (thin packs) stay pending until the base is supplied.
+
+use crate::{ProtocolError, Result};
+use gitnext_compat::git_format::object_type_name;
+use gitnext_compat::pack::{PackIndex, MAX_PREALLOCATION};
+use gitnext_compat::{delta::apply_delta, GitObjectSource};
+use gitnext_core::{GitHash, GitHashType, GitHasher, ObjectType};
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+
+const PACK_MAGIC: &[u8; 4] = b"PACK";
+
+/// Read buffer size for the underlying stream
+const BUFFER_SIZE: usize = 64 * 1024;
+
+fn invalid(message: impl Into<String>) -> ProtocolError {
+    ProtocolError::InvalidPack(message.into())
+}
+
+/// Git id of an object with the given type and content
+fn object_hash(object_type: ObjectType, content: &[u8], hash_type: GitHashType) -> GitHash {
+    let mut hasher = GitHasher::new(hash_type);
+    hasher.update(format!("{} {}\0", object_type_name(object_type), content.len()).as_bytes());
+    hasher.update(content);
+    hasher.finalize()
+}
+
+/// A fully resolved object from a pack
+#[derive(Debug, Clone)]
+pub struct PackObject {
+    pub hash: GitHash,
+    pub object_type: ObjectType,
+    /// Object content without the Git header
+    pub data: Vec<u8>,
+    /// Offset of the entry within the pack
+    pub offset: u64,
+    /// CRC-32 of the raw (compressed) entry, as stored in `.idx` files
+    pub crc32: u32,
+}
+
+/// Buffered reader that tracks the pack offset and checksums everything consumed
+struct PackStream<R> {
+    inner: R,
+    buffer: Vec<u8>,
+    start: usize,
+    end: usize,
+    offset: u64,
+    hasher: GitHasher,
+    crc: crc32fast::Hasher,
+}
+
+impl<R: Read> PackStream<R> {
+    fn new(inner: R, hash_type: GitHashType) -> Self {
+        Self {
+            inner,
+            buffer: vec![0; BUFFER_SIZE],
+            start: 0,
+            end: 0,
+            offset: 0,
+            hasher: GitHasher::new(hash_type),
+            crc: crc32fast::Hasher::new(),
+        }
+    }
+
+    /// Buffered bytes not yet consumed, reading more if none are left
+    fn fill(&mut self) -> Result<&[u8]> {
+        if self.start == self.end {
+            self.start = 0;
+            self.end = loop {
+                match self.inner.read(&mut self.buffer) {
+                    Ok(n) => break n,
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e.into()),
+                }
+            };
+        }
+        Ok(&self.buffer[self.start..self.end])
+    }
+
+    fn consume(&mut self, n: usize) {
+        let consumed = &self.buffer[self.start..self.start + n];
+        self.hasher.update(consumed);
+        self.crc.update(consumed);
+        self.start += n;
+        self.offset += n as u64;
+    }
+
+    fn read_bytes(&mut self, mut n: usize) -> Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(n);
+        while n > 0 {
+            let available = self.fill()?;
+            if available.is_empty() {
+                return Err(invalid("Unexpected end of pack"));
+            }
+            let take = available.len().min(n);
+            out.extend_from_slice(&available[..take]);
+            self.consume(take);
+            n -= take;
+        }
+        Ok(out)
+    }
+
+    fn read_byte(&mut self) -> Result<u8> {
+        Ok(self.read_bytes(1)?[0])
+    }
+
+    /// Inflate one zlib stream, consuming exactly its compressed bytes
+    fn inflate(&mut self, size: usize) -> Result<Vec<u8>> {
+        let mut decompress = flate2::Decompress::new(true);
+        let mut out = Vec::with_capacity(size.min(MAX_PREALLOCATION) + 1);
+
+        loop {
+            let input = self.fill()?;
+            if input.is_empty() {
+                return Err(invalid("Unexpected end of pack inside compressed data"));
+            }
+            out.reserve(1);
+            let before = decompress.total_in();
+            let status = decompress
+                .decompress_vec(input, &mut out, flate2::FlushDecompress::None)
+                .map_err(|e| invalid(format!("Corrupt compressed data: {}", e)))?;
+            let used = (decompress.total_in() - before) as usize;
+            self.consume(used);
+
+            if out.len() > size {
+                break;
+            }
+            if status == flate2::Status::StreamEnd {
+                break;
+            }
+        }
+
+        if out.len() != size {
+            return Err(invalid(format!("Inflated size mismatch: expected {}, found {}", size, out.len())));
+        }
+        Ok(out)
+    }
+}
+
+/// Raw entry as read from the stream, before delta resolution
+enum RawEntry {
+    Base(ObjectType, Vec<u8>),
+    OfsDelta { base_offset: u64, delta: Vec<u8> },
+    RefDelta { base: GitHash, delta: Vec<u8> },
+}
+
+/// Reads packs of a given object format
+pub struct PackReader {
+    hash_type: GitHashType,
+}
+
+impl PackReader {
+    pub fn new(hash_type: GitHashType) -> Self {
+        Self { hash_type }
+    }
+
+    /// Read a complete pack from `reader`, verifying its trailing checksum and
+    /// resolving every delta whose base is inside the pack
+    pub fn read(&self, reader: impl Read) -> Result<ParsedPack> {
+        let mut stream = PackStream::new(reader, self.hash_type);
+
+        let header = stream.read_bytes(12)?;
+        if &header[..4] != PACK_MAGIC {
+            return Err(invalid("Missing PACK signature"));
+        }
+        let version = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        if version != 2 && version != 3 {
+            return Err(invalid(format!("Unsupported pack version {}", version)));
+        }
+        let count = u32::from_be_bytes([header[8], header[9], header[10], header[11]]) as usize;
+
+        // (offset, crc32, entry)
+        let mut entries = Vec::with_capacity(count.min(1 << 16));
+        for _ in 0..count {
+            stream.crc = crc32fast::Hasher::new();
+            let offset = stream.offset;
+            let entry = self.read_entry(&mut stream, offset)?;
+            let crc = std::mem::take(&mut stream.crc).finalize();
+            entries.push((offset, crc, entry));
+        }
+
+        let actual = std::mem::replace(&mut stream.hasher, GitHasher::new(self.hash_type)).finalize();
+        let trailer = stream.read_bytes(self.hash_type.digest_len())?;
+        let expected = GitHash::from_slice(&trailer)?;
+        if expected != actual {
+            return Err(ProtocolError::ChecksumMismatch { expected, actual });
+        }
+
+        let mut pack = ParsedPack {
+            hash_type: self.hash_type,
+            checksum: actual,
+            objects: Vec::with_capacity(entries.len()),
+            by_hash: HashMap::new(),
+            unresolved: Vec::with_capacity(entries.len()),
+            ofs_children: HashMap::new(),
+            ref_children: HashMap::new(),
+        };
+        pack.resolve(entries)?;
+        Ok(pack)
+    }
+
+    fn read_entry<R: Read>(&self, stream: &mut PackStream<R>, offset: u64) -> Result<RawEntry> {
+        // Type and size header: 3 type bits, then little-endian base-128 size
+        let mut byte = stream.read_byte()?;
+        let type_code = (byte >> 4) & 0x7;
+        let mut size = (byte & 0x0f) as usize;
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            byte = stream.read_byte()?;
+            if shift >= usize::BITS {
+                return Err(invalid("Pack entry size overflows"));
+            }
+            size |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+        }
+
+        match type_code {
+            1..=4 => {
+                let object_type = match type_code {
+                    1 => ObjectType::Commit,
+                    2 => ObjectType::Tree,
+                    3 => ObjectType::Blob,
+                    _ => ObjectType::Tag,
+                };
+                Ok(RawEntry::Base(object_type, stream.inflate(size)?))
+            }
+            6 => {
+                // Offset encoding adds one per continuation byte so encodings are unique
+                let mut byte = stream.read_byte()?;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    byte = stream.read_byte()?;
+                    if distance >= 1 << 56 {
+                        return Err(invalid("Delta offset overflows"));
+                    }
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+                }
+                let base_offset = offset.checked_sub(distance)
+                    .filter(|_| distance > 0)
+                    .ok_or_else(|| invalid("Delta base offset out of range"))?;
+                Ok(RawEntry::OfsDelta { base_offset, delta: stream.inflate(size)? })
+            }
+            7 => {
+                let base = GitHash::from_slice(&stream.read_bytes(self.hash_type.digest_len())?)?;
+                Ok(RawEntry::RefDelta { base, delta: stream.inflate(size)? })
+            }
+            other => Err(invalid(format!("Unknown pack entry type {}", other))),
+        }
+    }
+}
+
+/// Delta entry not yet resolved: (offset, crc32, delta)
+type Unresolved = (u64, u32, Vec<u8>);
+
+/// Object whose content is known and whose dependent deltas can now be resolved
+struct Ready {
+    /// Entry index, offset and CRC-32; `None` for thin-pack bases from outside the pack
+    entry: Option<(usize, u64, u32)>,
+    object_type: ObjectType,
+    data: Vec<u8>,
+}
+
+/// A pack that has been read and (as far as possible) resolved
+pub struct ParsedPack {
+    hash_type: GitHashType,
+    checksum: GitHash,
+    objects: Vec<PackObject>,
+    by_hash: HashMap<GitHash, usize>,
+    /// Delta entries by entry index, taken once resolved
+    unresolved: Vec<Option<Unresolved>>,
+    ofs_children: HashMap<usize, Vec<usize>>,
+    /// REF_DELTA entries by base; what remains after reading waits on outside bases
+    ref_children: HashMap<GitHash, Vec<usize>>,
+}
+
+impl ParsedPack {
+    /// Resolve deltas breadth-first from each base, as `git index-pack` does
+    fn resolve(&mut self, entries: Vec<(u64, u32, RawEntry)>) -> Result<()> {
+        let index_of: HashMap<u64, usize> = entries.iter()
+            .enumerate()
+            .map(|(i, (offset, _, _))| (*offset, i))
+            .collect();
+
+        let mut ready = VecDeque::new();
+        for (i, (offset, crc32, entry)) in entries.into_iter().enumerate() {
+            match entry {
+                RawEntry::Base(object_type, data) => {
+                    self.unresolved.push(None);
+                    ready.push_back(Ready { entry: Some((i, offset, crc32)), object_type, data });
+                }
+                RawEntry::OfsDelta { base_offset, delta } => {
+                    let base = *index_of.get(&base_offset)
+                        .ok_or_else(|| invalid(format!("No entry at delta base offset {}", base_offset)))?;
+                    self.ofs_children.entry(base).or_default().push(i);
+                    self.unresolved.push(Some((offset, crc32, delta)));
+                }
+                RawEntry::RefDelta { base, delta } => {
+                    self.ref_children.entry(base).or_default().push(i);
+                    self.unresolved.push(Some((offset, crc32, delta)));
+                }
+            }
+        }
+
+        self.drain(ready)
+    }
+
+    fn drain(&mut self, mut ready: VecDeque<Ready>) -> Result<()> {
+        while let Some(Ready { entry, object_type, data }) = ready.pop_front() {
+            let hash = object_hash(object_type, &data, self.hash_type);
+
+            let ofs_children = entry.and_then(|(i, _, _)| self.ofs_children.remove(&i));
+            let ref_children = self.ref_children.remove(&hash);
+            for child in ofs_children.into_iter().flatten().chain(ref_children.into_iter().flatten()) {
+                let (offset, crc32, delta) = self.unresolved[child].take()
+                    .ok_or_else(|| invalid("Delta entry resolved twice"))?;
+                let child_data = apply_delta(&data, &delta)?;
+                ready.push_back(Ready { entry: Some((child, offset, crc32)), object_type, data: child_data });
+            }
+
+            if let Some((_, offset, crc32)) = entry {
+                self.push(PackObject { hash, object_type, data, offset, crc32 });
+            }
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, object: PackObject) {
+        self.by_hash.entry(object.hash).or_insert(self.objects.len());
+        self.objects.push(object);
+    }
+
+    pub fn hash_type(&self) -> GitHashType {
+        self.hash_type
+    }
+
+    /// The pack's trailing checksum, which also names it on disk
+    pub fn checksum(&self) -> &GitHash {
+        &self.checksum
+    }
+
+    /// Resolved objects in pack order
+    pub fn objects(&self) -> &[PackObject] {
+        &self.objects
+    }
+
+    pub fn get(&self, hash: &GitHash) -> Option<&PackObject> {
+        self.by_hash.get(hash).map(|&i| &self.objects[i])
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Whether every delta has been resolved
+    pub fn is_complete(&self) -> bool {
+        self.unresolved.iter().all(Option::is_none)
+    }
+
+    /// Bases outside the pack that REF_DELTA entries still wait for (thin packs)
+    pub fn missing_bases(&self) -> Vec<GitHash> {
+        self.ref_children.keys().copied().collect()
+    }
+
+    /// Supply a base object from outside the pack, resolving every delta waiting on it.
+    /// The base itself is not added to the pack's objects.
+    pub fn add_base(&mut self, object_type: ObjectType, data: Vec<u8>) -> Result<()> {
+        self.drain(VecDeque::from([Ready { entry: None, object_type, data }]))
+    }
+
+    /// Require every delta to be resolved
+    pub fn ensure_complete(&self) -> Result<()> {
+        if let Some(base) = self.ref_children.keys().next() {
+            return Err(ProtocolError::MissingBase(*base));
+        }
+        if !self.is_complete() {
+            return Err(invalid("Delta chain does not lead to a base object"));
+        }
+        Ok(())
+    }
+
+    /// Version 2 `.idx` for the resolved objects
+    pub fn index(&self) -> Vec<u8> {
+        let entries = self.objects.iter()
+            .map(|object| (object.hash, object.crc32, object.offset))
+            .collect();
+        PackIndex::encode(entries, &self.checksum)
+    }
+}
+
+impl GitObjectSource for ParsedPack {
+    fn hash_type(&self) -> GitHashType {
+        self.hash_type
+    }
+
+    fn read_object(&self, hash: &GitHash) -> gitnext_compat::Result<(ObjectType, Vec<u8>)> {
+        self.get(hash)
+            .map(|object| (object.object_type, object.data.clone()))
+            .ok_or(gitnext_compat::CompatError::MissingObject(*hash))
+    }
+}
@@ -0,0 +1,307 @@
+//! Delta-compressing pack v2 writer
+//!
+//! Objects are ordered by type, name hint and size (largest first), so that likely
+//! delta pairs end up next to each other, then each object is delta-encoded against
+//! the best of the previous `window` objects of the same type. Deltas against objects
+//! in the pack use OFS_DELTA; deltas against thin-pack bases, which the receiver
+//! already has and which are not written, use REF_DELTA.
+
+use crate::{ProtocolError, Result};
+use gitnext_compat::delta::DeltaIndex;
+use gitnext_compat::pack::{encode_entry_header, pack_type_code, PackIndex, WrittenPack};
+use gitnext_core::{CompatHashDeriver, GitHash, GitHashType, GitObject, ObjectType};
+use std::collections::{HashSet, VecDeque};
+use std::io::Write;
+
+const PACK_MAGIC: &[u8; 4] = b"PACK";
+
+/// Objects smaller than this are always stored whole
+const MIN_DELTA_SIZE: usize = 32;
+
+/// Options controlling pack generation
+#[derive(Debug, Clone)]
+pub struct PackWriterOptions {
+    /// Number of preceding objects tried as delta bases; 0 disables deltas
+    pub window: usize,
+    /// Longest delta chain allowed
+    pub max_depth: usize,
+    /// Use OFS_DELTA for bases in the pack (the `ofs-delta` capability); REF_DELTA otherwise
+    pub ofs_delta: bool,
+    pub compression: flate2::Compression,
+}
+
+impl Default for PackWriterOptions {
+    fn default() -> Self {
+        Self {
+            window: 10,
+            max_depth: 50,
+            ofs_delta: true,
+            compression: flate2::Compression::default(),
+        }
+    }
+}
+
+struct Entry {
+    hash: GitHash,
+    object_type: ObjectType,
+    data: Vec<u8>,
+    name_hash: u32,
+    /// Thin-pack base: usable as a delta base but not written
+    external: bool,
+}
+
+/// Chosen representation of a written object
+enum Representation {
+    Whole,
+    /// (entry index of the base, delta)
+    Delta(usize, Vec<u8>),
+}
+
+/// Collects objects and writes them as a pack
+pub struct PackWriter {
+    hash_type: GitHashType,
+    options: PackWriterOptions,
+    entries: Vec<Entry>,
+    seen: HashSet<GitHash>,
+}
+
+/// Git's path hash: sorts objects with the same file name (and similar endings) together
+fn name_hash(name: &str) -> u32 {
+    let mut hash: u32 = 0;
+    for c in name.bytes().filter(|c| !c.is_ascii_whitespace()) {
+        hash = (hash >> 2).wrapping_add((c as u32) << 24);
+    }
+    hash
+}
+
+impl PackWriter {
+    pub fn new(hash_type: GitHashType) -> Self {
+        Self::with_options(hash_type, PackWriterOptions::default())
+    }
+
+    pub fn with_options(hash_type: GitHashType, options: PackWriterOptions) -> Self {
+        Self {
+            hash_type,
+            options,
+            entries: Vec::new(),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Add an object by Git hash, type and content; `name` is the path it was reached
+    /// by, if known, and only guides delta base selection
+    pub fn add(&mut self, hash: GitHash, object_type: ObjectType, data: Vec<u8>, name: Option<&str>) {
+        if self.seen.insert(hash) {
+            self.entries.push(Entry {
+                hash,
+                object_type,
+                data,
+                name_hash: name.map(name_hash).unwrap_or(0),
+                external: false,
+            });
+        }
+    }
+
+    /// Add a canonical object, serializing it to Git format through `deriver`, whose
+    /// map must already hold the Git hashes of the object's children
+    pub fn add_object(&mut self, object: &GitObject, deriver: &CompatHashDeriver, name: Option<&str>) -> Result<GitHash> {
+        let git_bytes = deriver.serialize_to_git_format(object, self.hash_type)?;
+        let hash = GitHash::from_git_bytes(&git_bytes, self.hash_type);
+        let header_len = git_bytes.iter().position(|&b| b == 0).expect("Git objects have a header") + 1;
+        self.add(hash, object.object_type(), git_bytes[header_len..].to_vec(), name);
+        Ok(hash)
+    }
+
+    /// Offer an object the receiver already has as a delta base, making the pack thin
+    pub fn add_thin_base(&mut self, hash: GitHash, object_type: ObjectType, data: Vec<u8>, name: Option<&str>) {
+        if self.seen.insert(hash) {
+            self.entries.push(Entry {
+                hash,
+                object_type,
+                data,
+                name_hash: name.map(name_hash).unwrap_or(0),
+                external: true,
+            });
+        }
+    }
+
+    /// Number of objects that will be written (thin bases excluded)
+    pub fn len(&self) -> usize {
+        self.entries.iter().filter(|e| !e.external).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Select delta bases and write the pack and its index
+    pub fn write(mut self) -> Result<WrittenPack> {
+        // Same type together, then by name hint, then largest first so smaller
+        // objects become deltas of larger ones; thin bases lead on ties
+        self.entries.sort_by(|a, b| {
+            pack_type_code(a.object_type).cmp(&pack_type_code(b.object_type))
+                .then(a.name_hash.cmp(&b.name_hash))
+                .then(b.data.len().cmp(&a.data.len()))
+                .then(b.external.cmp(&a.external))
+        });
+
+        let representations = self.select_deltas();
+        self.encode(representations)
+    }
+
+    fn select_deltas(&self) -> Vec<Representation> {
+        let mut representations = Vec::with_capacity(self.entries.len());
+        let mut depths = vec![0usize; self.entries.len()];
+        let mut window: VecDeque<(usize, DeltaIndex<'_>)> = VecDeque::new();
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            if !window.front().is_some_and(|(base, _)| self.entries[*base].object_type == entry.object_type) {
+                window.clear();
+            }
+
+            let mut best: Option<(usize, Vec<u8>)> = None;
+            if !entry.external && entry.data.len() >= MIN_DELTA_SIZE {
+                for (base, index) in window.iter().rev() {
+                    if depths[*base] >= self.options.max_depth {
+                        continue;
+                    }
+                    // A delta must at least halve the object to be worth its chain
+                    let limit = best.as_ref()
+                        .map_or(entry.data.len() / 2, |(_, delta)| delta.len().saturating_sub(1));
+                    if let Some(delta) = index.encode(&entry.data, limit) {
+                        best = Some((*base, delta));
+                    }
+                }
+            }
+
+            representations.push(match best {
+                Some((base, delta)) => {
+                    depths[i] = depths[base] + 1;
+                    Representation::Delta(base, delta)
+                }
+                None => Representation::Whole,
+            });
+
+            if self.options.window > 0 && entry.data.len() >= MIN_DELTA_SIZE {
+                if window.len() == self.options.window {
+                    window.pop_front();
+                }
+                window.push_back((i, DeltaIndex::new(&entry.data)));
+            }
+        }
+
+        representations
+    }
+
+    fn encode(&self, representations: Vec<Representation>) -> Result<WrittenPack> {
+        let count = self.len();
+        let count = u32::try_from(count)
+            .map_err(|_| ProtocolError::InvalidPack("Too many objects for one pack".to_string()))?;
+
+        let mut pack = Vec::new();
+        pack.extend_from_slice(PACK_MAGIC);
+        pack.extend_from_slice(&2u32.to_be_bytes());
+        pack.extend_from_slice(&count.to_be_bytes());
+
+        let mut offsets = vec![None; self.entries.len()];
+        let mut index_entries = Vec::with_capacity(count as usize);
+        for (i, (entry, representation)) in self.entries.iter().zip(representations).enumerate() {
+            if entry.external {
+                continue;
+            }
+            let offset = pack.len() as u64;
+
+            let payload: &[u8] = match &representation {
+                Representation::Whole => {
+                    pack.extend_from_slice(&encode_entry_header(pack_type_code(entry.object_type), entry.data.len()));
+                    &entry.data
+                }
+                Representation::Delta(base, delta) => {
+                    match offsets[*base] {
+                        Some(base_offset) if self.options.ofs_delta => {
+                            pack.extend_from_slice(&encode_entry_header(6, delta.len()));
+                            pack.extend_from_slice(&encode_offset(offset - base_offset));
+                        }
+                        _ => {
+                            pack.extend_from_slice(&encode_entry_header(7, delta.len()));
+                            pack.extend_from_slice(self.entries[*base].hash.as_bytes());
+                        }
+                    }
+                    delta
+                }
+            };
+
+            let mut encoder = flate2::write::ZlibEncoder::new(&mut pack, self.options.compression);
+            encoder.write_all(payload)?;
+            encoder.finish()?;
+
+            let crc = crc32fast::hash(&pack[offset as usize..]);
+            index_entries.push((entry.hash, crc, offset));
+            offsets[i] = Some(offset);
+        }
+
+        let checksum = GitHash::from_git_bytes(&pack, self.hash_type);
+        pack.extend_from_slice(checksum.as_bytes());
+        let index = PackIndex::encode(index_entries, &checksum);
+
+        Ok(WrittenPack { pack, index, checksum })
+    }
+}
+
+/// OFS_DELTA distance: big-endian base-128 where each continuation adds one
+fn encode_offset(mut distance: u64) -> Vec<u8> {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance > 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_offset() {
+        assert_eq!(encode_offset(1), vec![0x01]);
+        assert_eq!(encode_offset(127), vec![0x7f]);
+        // 128 = ((0 + 1) << 7) | 0
+        assert_eq!(encode_offset(128), vec![0x80, 0x00]);
+        assert_eq!(encode_offset(16511), vec![0xff, 0x7f]);
+        assert_eq!(encode_offset(16512), vec![0x80, 0x80, 0x00]);
+    }
+
+    fn blob(content: &[u8]) -> (GitHash, Vec<u8>) {
+        let header = format!("blob {}\0", content.len());
+        let hash = GitHash::from_git_bytes(&[header.as_bytes(), content].concat(), GitHashType::Sha1);
+        (hash, content.to_vec())
+    }
+
+    #[test]
+    fn test_similar_objects_are_deltified() {
+        let original: Vec<u8> = (0..2000).flat_map(|i| format!("line {}\n", i).into_bytes()).collect();
+        let mut edited = original.clone();
+        edited.extend_from_slice(b"one more line\n");
+
+        let write = |window| {
+            let mut writer = PackWriter::with_options(GitHashType::Sha1, PackWriterOptions { window, ..Default::default() });
+            for content in [&original, &edited] {
+                let (hash, data) = blob(content);
+                writer.add(hash, ObjectType::Blob, data, Some("file.txt"));
+            }
+            writer.write().unwrap()
+        };
+
+        let deltified = write(10);
+        let whole = write(0);
+        assert!(deltified.pack.len() < whole.pack.len() * 2 / 3);
+
+        let read = crate::PackReader::new(GitHashType::Sha1).read(deltified.pack.as_slice()).unwrap();
+        assert_eq!(read.get(&blob(&edited).0).unwrap().data, edited);
+        assert_eq!(read.index(), deltified.index);
+    }
+}
@@ -0,0 +1,149 @@
+//! pkt-line framing
+//!
+//! Every Git protocol message is a sequence of pkt-lines: a four hex digit length
+//! (including itself) followed by the payload. Lengths 0000, 0001 and 0002 are the
+//! flush, delimiter and response-end markers.
+
+use crate::{ProtocolError, Result};
+
+/// Largest pkt-line payload
+pub const MAX_PAYLOAD: usize = 65516;
+
+/// A decoded pkt-line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PktLine<'a> {
+    Data(&'a [u8]),
+    Flush,
+    Delim,
+    ResponseEnd,
+}
+
+impl<'a> PktLine<'a> {
+    /// Payload as text without its trailing newline, for line-oriented messages
+    pub fn text(&self) -> Option<&'a str> {
+        match self {
+            PktLine::Data(data) => {
+                let data = data.strip_suffix(b"\n").unwrap_or(data);
+                std::str::from_utf8(data).ok()
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Append one data pkt-line
+pub fn write_data(out: &mut Vec<u8>, payload: &[u8]) {
+    debug_assert!(payload.len() <= MAX_PAYLOAD);
+    out.extend_from_slice(format!("{:04x}", payload.len() + 4).as_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Append a text pkt-line, terminated by a newline
+pub fn write_line(out: &mut Vec<u8>, line: &str) {
+    write_data(out, format!("{}\n", line).as_bytes());
+}
+
+pub fn write_flush(out: &mut Vec<u8>) {
+    out.extend_from_slice(b"0000");
+}
+
+pub fn write_delim(out: &mut Vec<u8>) {
+    out.extend_from_slice(b"0001");
+}
+
+/// Cursor over a buffer of pkt-lines
+pub struct PktReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PktReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    /// Bytes after the last pkt-line read (a pack that follows the framing, say)
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.pos..]
+    }
+
+    /// Read the next pkt-line; `None` at the end of the buffer
+    pub fn next_line(&mut self) -> Result<Option<PktLine<'a>>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let length = self.data.get(self.pos..self.pos + 4)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+            .ok_or_else(|| ProtocolError::InvalidResponse("Malformed pkt-line length".to_string()))?;
+
+        let line = match length {
+            0 => PktLine::Flush,
+            1 => PktLine::Delim,
+            2 => PktLine::ResponseEnd,
+            3 => return Err(ProtocolError::InvalidResponse("Invalid pkt-line length 3".to_string())),
+            _ => {
+                let payload = self.data.get(self.pos + 4..self.pos + length)
+                    .ok_or_else(|| ProtocolError::InvalidResponse("Truncated pkt-line".to_string()))?;
+                self.pos += length;
+                return Ok(Some(PktLine::Data(payload)));
+            }
+        };
+        self.pos += 4;
+        Ok(Some(line))
+    }
+
+    /// Read the next pkt-line, treating the end of the buffer as an error
+    pub fn expect_line(&mut self) -> Result<PktLine<'a>> {
+        self.next_line()?
+            .ok_or_else(|| ProtocolError::InvalidResponse("Unexpected end of response".to_string()))
+    }
+}
+
+/// Length of the pkt-line stream at the start of `data` up to and including its first
+/// flush, if the buffer holds that much
+pub fn flush_terminated_len(data: &[u8]) -> Option<usize> {
+    let mut reader = PktReader::new(data);
+    loop {
+        match reader.next_line() {
+            Ok(Some(PktLine::Flush)) => return Some(reader.pos),
+            Ok(Some(_)) => continue,
+            _ => return None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pkt_line_round_trip() {
+        let mut out = Vec::new();
+        write_line(&mut out, "command=ls-refs");
+        write_delim(&mut out);
+        write_data(&mut out, b"\x01binary");
+        write_flush(&mut out);
+        assert_eq!(&out[..24], b"0014command=ls-refs\n0001");
+
+        let mut reader = PktReader::new(&out);
+        assert_eq!(reader.expect_line().unwrap().text(), Some("command=ls-refs"));
+        assert_eq!(reader.expect_line().unwrap(), PktLine::Delim);
+        assert_eq!(reader.expect_line().unwrap(), PktLine::Data(b"\x01binary"));
+        assert_eq!(reader.expect_line().unwrap(), PktLine::Flush);
+        assert!(reader.next_line().unwrap().is_none());
+        assert_eq!(flush_terminated_len(&out), Some(out.len()));
+        assert_eq!(flush_terminated_len(&out[..out.len() - 1]), None);
+    }
+
+    #[test]
+    fn test_pkt_line_rejects_bad_framing() {
+        assert!(PktReader::new(b"zzzz").next_line().is_err());
+        assert!(PktReader::new(b"0003").next_line().is_err());
+        assert!(PktReader::new(b"0010short").next_line().is_err());
+    }
+}
@@ -0,0 +1,515 @@
+//! Git's smart protocol over a request/response channel
+//!
+//! Fetches use protocol v2: a capability advertisement, then `ls-refs` and `fetch`
+//! commands. `fetch` negotiates in rounds of `have` lines, resending the haves the
+//! server acknowledged (stateless servers forget them), until the server is ready or
+//! the client runs out and sends `done`. Pushes use `git-receive-pack`, which only
+//! speaks protocol v0: a ref advertisement, then update commands, the pack and a
+//! `report-status` response.
+
+use crate::pack_reader::{PackReader, ParsedPack};
+use crate::pkt_line::{self, PktLine, PktReader};
+use crate::transport::{PushReport, RefStatus, RefUpdate, RemoteRef, Transport};
+use crate::{ProtocolError, Result};
+use async_trait::async_trait;
+use gitnext_core::{GitHash, GitHashType};
+use std::collections::HashSet;
+
+/// Agent string sent to servers
+pub const AGENT: &str = concat!("gitnext/", env!("CARGO_PKG_VERSION"));
+
+/// Haves sent per negotiation round
+const HAVES_PER_ROUND: usize = 32;
+
+/// Moves protocol messages to and from a remote's `git-upload-pack` and
+/// `git-receive-pack`. Responses are returned whole.
+#[async_trait]
+pub trait Channel: Send {
+    /// Protocol v2 capability advertisement of `git-upload-pack`
+    async fn upload_pack_advertisement(&mut self) -> Result<Vec<u8>>;
+
+    /// Run one protocol v2 command
+    async fn upload_pack(&mut self, request: Vec<u8>) -> Result<Vec<u8>>;
+
+    /// Ref advertisement of `git-receive-pack`
+    async fn receive_pack_advertisement(&mut self) -> Result<Vec<u8>>;
+
+    /// Send update commands and a pack, returning the status report
+    async fn receive_pack(&mut self, request: Vec<u8>) -> Result<Vec<u8>>;
+}
+
+/// `Transport` speaking the smart protocol over any `Channel`
+pub struct SmartTransport<C> {
+    channel: C,
+    /// Capability lines of `git-upload-pack`, once advertised
+    upload_capabilities: Option<Vec<String>>,
+    /// Capabilities of `git-receive-pack`, once advertised
+    receive_capabilities: Option<Vec<String>>,
+    hash_type: Option<GitHashType>,
+}
+
+fn invalid(message: impl Into<String>) -> ProtocolError {
+    ProtocolError::InvalidResponse(message.into())
+}
+
+fn parse_hash(hex: &str, hash_type: GitHashType) -> Result<GitHash> {
+    let hash = GitHash::from_hex(hex).map_err(|_| invalid(format!("Invalid object id '{}'", hex)))?;
+    if hash.hash_type() != hash_type {
+        return Err(invalid(format!("Object id '{}' is not {:?}", hex, hash_type)));
+    }
+    Ok(hash)
+}
+
+/// Name of an object format in capabilities
+pub fn object_format_name(hash_type: GitHashType) -> &'static str {
+    match hash_type {
+        GitHashType::Sha1 => "sha1",
+        GitHashType::Sha256 => "sha256",
+    }
+}
+
+/// Object format named by a capability value
+pub fn parse_object_format(name: &str) -> Result<GitHashType> {
+    match name {
+        "sha1" => Ok(GitHashType::Sha1),
+        "sha256" => Ok(GitHashType::Sha256),
+        other => Err(ProtocolError::Unsupported(format!("object-format={}", other))),
+    }
+}
+
+/// The all-zero object id standing for a missing ref
+pub fn zero_hash(hash_type: GitHashType) -> String {
+    "0".repeat(hash_type.digest_len() * 2)
+}
+
+/// Fail on an `ERR` pkt-line
+fn check_error(line: &PktLine<'_>) -> Result<()> {
+    match line.text().and_then(|text| text.strip_prefix("ERR ")) {
+        Some(message) => Err(ProtocolError::Remote(message.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// Skip the `# service=...` preamble smart HTTP puts before advertisements
+fn skip_service_line(reader: &mut PktReader<'_>) -> Result<()> {
+    let mut peek = PktReader::new(reader.remaining());
+    if let Some(line) = peek.next_line()? {
+        if line.text().is_some_and(|text| text.starts_with("# service=")) {
+            reader.expect_line()?;
+            if reader.expect_line()? != PktLine::Flush {
+                return Err(invalid("Expected flush after service line"));
+            }
+        }
+    }
+    Ok(())
+}
+
+impl<C: Channel> SmartTransport<C> {
+    pub fn new(channel: C) -> Self {
+        Self {
+            channel,
+            upload_capabilities: None,
+            receive_capabilities: None,
+            hash_type: None,
+        }
+    }
+
+    /// Read the v2 capability advertisement, once
+    async fn upload_capabilities(&mut self) -> Result<&[String]> {
+        if self.upload_capabilities.is_none() {
+            let response = self.channel.upload_pack_advertisement().await?;
+            let mut reader = PktReader::new(&response);
+            skip_service_line(&mut reader)?;
+
+            let mut capabilities = Vec::new();
+            loop {
+                let line = reader.expect_line()?;
+                check_error(&line)?;
+                match line {
+                    PktLine::Flush => break,
+                    PktLine::Data(_) => {
+                        let text = line.text().ok_or_else(|| invalid("Non-text capability"))?;
+                        capabilities.push(text.to_string());
+                    }
+                    _ => return Err(invalid("Unexpected marker in capability advertisement")),
+                }
+            }
+            if capabilities.first().map(String::as_str) != Some("version 2") {
+                return Err(ProtocolError::Unsupported("server does not speak protocol v2".to_string()));
+            }
+
+            let format = capabilities.iter()
+                .find_map(|c| c.strip_prefix("object-format="))
+                .unwrap_or("sha1");
+            self.hash_type = Some(parse_object_format(format)?);
+            self.upload_capabilities = Some(capabilities);
+        }
+        Ok(self.upload_capabilities.as_deref().unwrap_or_default())
+    }
+
+    /// Start a v2 command request: command, capabilities and the argument delimiter
+    async fn command_request(&mut self, command: &str) -> Result<Vec<u8>> {
+        let capabilities = self.upload_capabilities().await?;
+        let supported = capabilities.iter().any(|c| c == command || c.starts_with(&format!("{}=", command)));
+        if !supported {
+            return Err(ProtocolError::Unsupported(format!("command {}", command)));
+        }
+        let advertises_format = capabilities.iter().any(|c| c.starts_with("object-format="));
+        let hash_type = self.hash_type.unwrap_or(GitHashType::Sha1);
+
+        let mut request = Vec::new();
+        pkt_line::write_line(&mut request, &format!("command={}", command));
+        pkt_line::write_line(&mut request, &format!("agent={}", AGENT));
+        if advertises_format {
+            pkt_line::write_line(&mut request, &format!("object-format={}", object_format_name(hash_type)));
+        }
+        pkt_line::write_delim(&mut request);
+        Ok(request)
+    }
+
+    /// One negotiation round; returns the pack once the server sends one
+    async fn fetch_round(
+        &mut self,
+        wants: &[GitHash],
+        haves: &[GitHash],
+        done: bool,
+        common: &mut Vec<GitHash>,
+    ) -> Result<Option<ParsedPack>> {
+        let mut request = self.command_request("fetch").await?;
+        let hash_type = self.hash_type.unwrap_or(GitHashType::Sha1);
+        for argument in ["thin-pack", "ofs-delta", "no-progress"] {
+            pkt_line::write_line(&mut request, argument);
+        }
+        for want in wants {
+            pkt_line::write_line(&mut request, &format!("want {}", want));
+        }
+        for have in haves {
+            pkt_line::write_line(&mut request, &format!("have {}", have));
+        }
+        if done {
+            pkt_line::write_line(&mut request, "done");
+        }
+        pkt_line::write_flush(&mut request);
+
+        let response = self.channel.upload_pack(request).await?;
+        let mut reader = PktReader::new(&response);
+        loop {
+            let header = reader.expect_line()?;
+            check_error(&header)?;
+            match header.text() {
+                Some("acknowledgments") => {
+                    let mut ready = false;
+                    loop {
+                        let line = reader.expect_line()?;
+                        match line {
+                            PktLine::Flush if !ready => return Ok(None),
+                            PktLine::Delim => break,
+                            PktLine::Data(_) => match line.text() {
+                                Some("NAK") => {}
+                                Some("ready") => ready = true,
+                                Some(text) if text.starts_with("ACK ") => {
+                                    let hash = parse_hash(&text[4..], hash_type)?;
+                                    if !common.contains(&hash) {
+                                        common.push(hash);
+                                    }
+                                }
+                                _ => return Err(invalid("Unexpected acknowledgment line")),
+                            },
+                            _ => return Err(invalid("Unexpected end of acknowledgments")),
+                        }
+                    }
+                }
+                Some("shallow-info") | Some("wanted-refs") | Some("packfile-uris") => {
+                    // Sections this client never asks for; skip to the next one
+                    while reader.expect_line()? != PktLine::Delim {}
+                }
+                Some("packfile") => return read_sideband_pack(&mut reader, hash_type).map(Some),
+                _ => return Err(invalid("Unexpected fetch response section")),
+            }
+        }
+    }
+
+    /// Read the receive-pack ref advertisement, once per push
+    async fn receive_advertisement(&mut self) -> Result<Vec<RemoteRef>> {
+        let response = self.channel.receive_pack_advertisement().await?;
+        let mut reader = PktReader::new(&response);
+        skip_service_line(&mut reader)?;
+
+        let mut lines = Vec::new();
+        let mut capabilities = Vec::new();
+        loop {
+            let line = reader.expect_line()?;
+            check_error(&line)?;
+            match line {
+                PktLine::Flush => break,
+                PktLine::Data(data) => {
+                    let data = data.strip_suffix(b"\n").unwrap_or(data);
+                    let (ref_line, caps) = match data.iter().position(|&b| b == 0) {
+                        Some(nul) => (&data[..nul], Some(&data[nul + 1..])),
+                        None => (data, None),
+                    };
+                    if let Some(caps) = caps {
+                        capabilities = String::from_utf8_lossy(caps)
+                            .split(' ')
+                            .filter(|c| !c.is_empty())
+                            .map(str::to_string)
+                            .collect();
+                    }
+                    let text = std::str::from_utf8(ref_line).map_err(|_| invalid("Non-UTF-8 ref advertisement"))?;
+                    lines.push(text.to_string());
+                }
+                _ => return Err(invalid("Unexpected marker in ref advertisement")),
+            }
+        }
+
+        let format = capabilities.iter()
+            .find_map(|c| c.strip_prefix("object-format="))
+            .unwrap_or("sha1");
+        let hash_type = parse_object_format(format)?;
+        self.hash_type = Some(hash_type);
+        self.receive_capabilities = Some(capabilities);
+
+        let mut refs: Vec<RemoteRef> = Vec::new();
+        for line in lines {
+            let (hex, name) = line.split_once(' ').ok_or_else(|| invalid("Malformed ref advertisement"))?;
+            // An empty repository advertises only its capabilities
+            if name == "capabilities^{}" {
+                continue;
+            }
+            let hash = parse_hash(hex, hash_type)?;
+            if let Some(tag) = name.strip_suffix("^{}") {
+                if let Some(last) = refs.last_mut().filter(|r| r.name == tag) {
+                    last.peeled = Some(hash);
+                }
+                continue;
+            }
+            refs.push(RemoteRef { name: name.to_string(), hash, symref_target: None, peeled: None });
+        }
+        Ok(refs)
+    }
+}
+
+/// Reassemble a side-band-64k pack stream (band 1 data, 2 progress, 3 error)
+fn read_sideband_pack(reader: &mut PktReader<'_>, hash_type: GitHashType) -> Result<ParsedPack> {
+    let mut pack = Vec::new();
+    loop {
+        match reader.expect_line()? {
+            PktLine::Flush => break,
+            PktLine::Data(data) => match data.split_first() {
+                Some((1, payload)) => pack.extend_from_slice(payload),
+                Some((2, _)) => {}
+                Some((3, message)) => {
+                    return Err(ProtocolError::Remote(String::from_utf8_lossy(message).trim_end().to_string()));
+                }
+                _ => return Err(invalid("Invalid side-band channel")),
+            },
+            _ => return Err(invalid("Unexpected marker in packfile section")),
+        }
+    }
+    PackReader::new(hash_type).read(pack.as_slice())
+}
+
+/// Parse a `report-status` response
+fn parse_report(response: &[u8]) -> Result<PushReport> {
+    let mut reader = PktReader::new(response);
+    let first = reader.expect_line()?;
+    check_error(&first)?;
+    match first.text() {
+        Some("unpack ok") => {}
+        Some(text) => {
+            let reason = text.strip_prefix("unpack ").unwrap_or(text);
+            return Err(ProtocolError::Remote(format!("unpack failed: {}", reason)));
+        }
+        None => return Err(invalid("Missing unpack status")),
+    }
+
+    let mut report = PushReport::default();
+    loop {
+        let line = reader.expect_line()?;
+        if line == PktLine::Flush {
+            break;
+        }
+        let text = line.text().ok_or_else(|| invalid("Malformed status line"))?;
+        if let Some(name) = text.strip_prefix("ok ") {
+            report.refs.push((name.to_string(), RefStatus::Ok));
+        } else if let Some(rest) = text.strip_prefix("ng ") {
+            let (name, reason) = rest.split_once(' ').unwrap_or((rest, "rejected"));
+            report.refs.push((name.to_string(), RefStatus::Rejected(reason.to_string())));
+        } else {
+            return Err(invalid(format!("Unexpected status line '{}'", text)));
+        }
+    }
+    Ok(report)
+}
+
+#[async_trait]
+impl<C: Channel> Transport for SmartTransport<C> {
+    async fn hash_type(&mut self) -> Result<GitHashType> {
+        if let Some(hash_type) = self.hash_type {
+            return Ok(hash_type);
+        }
+        self.upload_capabilities().await?;
+        Ok(self.hash_type.unwrap_or(GitHashType::Sha1))
+    }
+
+    async fn list_refs(&mut self) -> Result<Vec<RemoteRef>> {
+        let mut request = self.command_request("ls-refs").await?;
+        let hash_type = self.hash_type.unwrap_or(GitHashType::Sha1);
+        for argument in ["peel", "symrefs", "ref-prefix HEAD", "ref-prefix refs/heads/", "ref-prefix refs/tags/"] {
+            pkt_line::write_line(&mut request, argument);
+        }
+        pkt_line::write_flush(&mut request);
+
+        let response = self.channel.upload_pack(request).await?;
+        let mut reader = PktReader::new(&response);
+        let mut refs = Vec::new();
+        loop {
+            let line = reader.expect_line()?;
+            check_error(&line)?;
+            if line == PktLine::Flush {
+                break;
+            }
+            let text = line.text().ok_or_else(|| invalid("Malformed ls-refs line"))?;
+            let mut fields = text.split(' ');
+            let (Some(hex), Some(name)) = (fields.next(), fields.next()) else {
+                return Err(invalid(format!("Malformed ls-refs line '{}'", text)));
+            };
+            let mut remote_ref = RemoteRef {
+                name: name.to_string(),
+                hash: parse_hash(hex, hash_type)?,
+                symref_target: None,
+                peeled: None,
+            };
+            for attribute in fields {
+                if let Some(target) = attribute.strip_prefix("symref-target:") {
+                    remote_ref.symref_target = Some(target.to_string());
+                } else if let Some(peeled) = attribute.strip_prefix("peeled:") {
+                    remote_ref.peeled = Some(parse_hash(peeled, hash_type)?);
+                }
+            }
+            refs.push(remote_ref);
+        }
+        Ok(refs)
+    }
+
+    async fn fetch_packfile(&mut self, wants: &[GitHash], haves: &[GitHash]) -> Result<ParsedPack> {
+        if wants.is_empty() {
+            return Err(ProtocolError::InvalidPack("Nothing to fetch".to_string()));
+        }
+        let wants: Vec<GitHash> = {
+            let mut seen = HashSet::new();
+            wants.iter().copied().filter(|w| seen.insert(*w)).collect()
+        };
+
+        // Every round resends the haves acknowledged so far; the last one ends with `done`
+        let batches: Vec<&[GitHash]> = haves.chunks(HAVES_PER_ROUND).collect();
+        let rounds = batches.len().max(1);
+        let mut common: Vec<GitHash> = Vec::new();
+        for round in 0..rounds {
+            let mut round_haves = common.clone();
+            if let Some(batch) = batches.get(round) {
+                round_haves.extend(batch.iter().filter(|h| !common.contains(h)).copied());
+            }
+            let done = round + 1 == rounds;
+            if let Some(pack) = self.fetch_round(&wants, &round_haves, done, &mut common).await? {
+                return Ok(pack);
+            }
+        }
+        Err(invalid("Server sent no packfile"))
+    }
+
+    async fn list_push_refs(&mut self) -> Result<Vec<RemoteRef>> {
+        self.receive_advertisement().await
+    }
+
+    async fn send_packfile(&mut self, updates: &[RefUpdate], pack: Option<Vec<u8>>) -> Result<PushReport> {
+        if updates.is_empty() {
+            return Ok(PushReport::default());
+        }
+        if self.receive_capabilities.is_none() {
+            self.receive_advertisement().await?;
+        }
+        let capabilities = self.receive_capabilities.clone().unwrap_or_default();
+        let hash_type = self.hash_type.unwrap_or(GitHashType::Sha1);
+        let supports = |name: &str| capabilities.iter().any(|c| c == name);
+
+        if !supports("report-status") {
+            return Err(ProtocolError::Unsupported("report-status".to_string()));
+        }
+        if pack.is_some() && !supports("ofs-delta") {
+            return Err(ProtocolError::Unsupported("ofs-delta".to_string()));
+        }
+        let mut requested = vec!["report-status".to_string()];
+        if supports("ofs-delta") {
+            requested.push("ofs-delta".to_string());
+        }
+        if updates.iter().any(|u| u.new.is_none()) {
+            if !supports("delete-refs") {
+                return Err(ProtocolError::Unsupported("delete-refs".to_string()));
+            }
+            requested.push("delete-refs".to_string());
+        }
+        if capabilities.iter().any(|c| c.starts_with("object-format=")) {
+            requested.push(format!("object-format={}", object_format_name(hash_type)));
+        }
+        requested.push(format!("agent={}", AGENT));
+
+        let zero = zero_hash(hash_type);
+        let format = |hash: &Option<GitHash>| hash.map_or_else(|| zero.clone(), |h| h.to_string());
+        let mut request = Vec::new();
+        for (i, update) in updates.iter().enumerate() {
+            let mut command = format!("{} {} {}", format(&update.old), format(&update.new), update.name);
+            if i == 0 {
+                command = format!("{}\0{}", command, requested.join(" "));
+            }
+            pkt_line::write_line(&mut request, &command);
+        }
+        pkt_line::write_flush(&mut request);
+        if updates.iter().any(|u| u.new.is_some()) {
+            let pack = pack.ok_or_else(|| ProtocolError::InvalidPack("Push without a pack".to_string()))?;
+            request.extend_from_slice(&pack);
+        }
+
+        // The advertisement is only good for one push
+        self.receive_capabilities = None;
+        let response = self.channel.receive_pack(request).await?;
+        parse_report(&response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_report() {
+        let mut response = Vec::new();
+        pkt_line::write_line(&mut response, "unpack ok");
+        pkt_line::write_line(&mut response, "ok refs/heads/main");
+        pkt_line::write_line(&mut response, "ng refs/heads/old non-fast-forward");
+        pkt_line::write_flush(&mut response);
+
+        let report = parse_report(&response).unwrap();
+        assert_eq!(report.refs, vec![
+            ("refs/heads/main".to_string(), RefStatus::Ok),
+            ("refs/heads/old".to_string(), RefStatus::Rejected("non-fast-forward".to_string())),
+        ]);
+        assert!(matches!(report.into_result(), Err(ProtocolError::Rejected { name, .. }) if name == "refs/heads/old"));
+
+        let mut failed = Vec::new();
+        pkt_line::write_line(&mut failed, "unpack index-pack abnormal exit");
+        pkt_line::write_flush(&mut failed);
+        assert!(matches!(parse_report(&failed), Err(ProtocolError::Remote(_))));
+    }
+
+    #[test]
+    fn test_sideband_errors_surface() {
+        let mut response = Vec::new();
+        pkt_line::write_data(&mut response, b"\x02counting objects\n");
+        pkt_line::write_data(&mut response, b"\x03upload-pack: not our ref\n");
+        pkt_line::write_flush(&mut response);
+
+        let result = read_sideband_pack(&mut PktReader::new(&response), GitHashType::Sha1);
+        assert!(matches!(result, Err(ProtocolError::Remote(message)) if message == "upload-pack: not our ref"));
+    }
+}
@@ -0,0 +1,156 @@
+//! ssh transport: pkt-lines over the stdio of an external command
+//!
+//! Like Git, the command comes from `GIT_SSH_COMMAND` (a shell snippet), `GIT_SSH`
+//! (a program) or defaults to `ssh`, and is run as
+//! `<command> [-p <port>] [-o SendEnv=GIT_PROTOCOL] [--] [<user>@]<host> "<service> '<path>'"`.
+//! The `-o` option and the `--` ending options are only passed to OpenSSH,
+//! recognized by its program name; `GIT_PROTOCOL=version=2` is always set in the
+//! command's environment. Users and hosts starting with `-` are refused, so no
+//! ssh command can take them for options.
+
+use crate::stream::{PktStream, ServiceConnector};
+use crate::{ProtocolError, Result};
+use async_trait::async_trait;
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+use tokio::process::Command;
+
+/// Most error output kept from the command
+const MAX_DIAGNOSTICS: u64 = 64 * 1024;
+
+/// The ssh command configured in the environment, as a shell snippet
+pub fn default_ssh_command() -> String {
+    if let Ok(command) = std::env::var("GIT_SSH_COMMAND") {
+        if !command.trim().is_empty() {
+            return command;
+        }
+    }
+    match std::env::var("GIT_SSH") {
+        Ok(program) if !program.is_empty() => shell_quote(&program),
+        _ => "ssh".to_string(),
+    }
+}
+
+/// Quote `value` as a single shell word
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Connects to a repository's services through an ssh command
+pub struct SshConnector {
+    command: String,
+    user: Option<String>,
+    host: String,
+    port: Option<u16>,
+    path: String,
+}
+
+impl SshConnector {
+    pub fn new(command: &str, user: Option<&str>, host: &str, port: Option<u16>, path: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            user: user.map(str::to_string),
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        }
+    }
+
+    /// Whether the command runs OpenSSH, which understands `-o SendEnv`
+    fn is_openssh(&self) -> bool {
+        let program = self.command.split_whitespace().next().unwrap_or_default();
+        let name = program.trim_matches(|c| c == '\'' || c == '"').rsplit('/').next().unwrap_or_default();
+        name == "ssh"
+    }
+
+    /// Arguments appended to the command
+    fn arguments(&self, service: &str, protocol_v2: bool) -> Vec<String> {
+        let mut arguments = Vec::new();
+        if let Some(port) = self.port {
+            arguments.extend(["-p".to_string(), port.to_string()]);
+        }
+        if protocol_v2 && self.is_openssh() {
+            arguments.extend(["-o".to_string(), "SendEnv=GIT_PROTOCOL".to_string()]);
+        }
+        if self.is_openssh() {
+            arguments.push("--".to_string());
+        }
+        arguments.push(match &self.user {
+            Some(user) => format!("{}@{}", user, self.host),
+            None => self.host.clone(),
+        });
+        arguments.push(format!("{} {}", service, shell_quote(&self.path)));
+        arguments
+    }
+}
+
+#[async_trait]
+impl ServiceConnector for SshConnector {
+    async fn open(&self, service: &str, protocol_v2: bool) -> Result<PktStream> {
+        if self.host.starts_with('-') || self.user.as_ref().is_some_and(|user| user.starts_with('-')) {
+            return Err(ProtocolError::UnsupportedUrl(format!("ssh destination '{}' looks like an option", self.host)));
+        }
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(format!("{} \"$@\"", self.command))
+            .arg(&self.command)
+            .args(self.arguments(service, protocol_v2))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if protocol_v2 {
+            command.env("GIT_PROTOCOL", "version=2");
+        }
+
+        let mut child = command.spawn()
+            .map_err(|e| ProtocolError::Transport(format!("Cannot run ssh command '{}': {}", self.command, e)))?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let stderr = child.stderr.take().expect("stderr is piped");
+
+        // Drain error output so the command never blocks on it, keeping the start
+        let diagnostics = tokio::spawn(async move {
+            let mut stderr = stderr;
+            let mut output = Vec::new();
+            let _ = (&mut stderr).take(MAX_DIAGNOSTICS).read_to_end(&mut output).await;
+            let _ = tokio::io::copy(&mut stderr, &mut tokio::io::sink()).await;
+            output
+        });
+        Ok(PktStream::new(Box::new(stdout), Box::new(stdin))
+            .with_diagnostics(diagnostics)
+            .with_guard(Box::new(child)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_arguments() {
+        let connector = SshConnector::new("ssh -i key", Some("git"), "example.com", Some(2222), "org/it's.git");
+        assert_eq!(connector.arguments("git-upload-pack", true), vec![
+            "-p", "2222", "-o", "SendEnv=GIT_PROTOCOL", "--", "git@example.com", "git-upload-pack 'org/it'\\''s.git'",
+        ]);
+
+        let connector = SshConnector::new("'/opt/my plink'", None, "example.com", None, "/srv/repo");
+        assert_eq!(connector.arguments("git-receive-pack", true), vec!["example.com", "git-receive-pack '/srv/repo'"]);
+    }
+
+    #[tokio::test]
+    async fn test_refuse_option_like_destination() {
+        // The command would create `pwned` if the host reached it as an option
+        let dir = std::env::temp_dir().join(format!("gitnext-ssh-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let marker = dir.join("pwned");
+        let host = format!("-oProxyCommand=touch {}", marker.display());
+        for (user, host) in [(None, host.as_str()), (Some("-oProxyCommand=sh"), "example.com")] {
+            let connector = SshConnector::new("ssh", user, host, None, "repo");
+            assert!(matches!(connector.open("git-upload-pack", false).await, Err(ProtocolError::UnsupportedUrl(_))));
+        }
+        assert!(!marker.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
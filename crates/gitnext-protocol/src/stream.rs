@@ -0,0 +1,159 @@
+//! pkt-line channels over byte streams
+//!
+//! `git daemon` and ssh connect the client to a running `git-upload-pack` or
+//! `git-receive-pack` over a full-duplex stream. Each service gets its own
+//! connection; a protocol v2 upload-pack connection carries every command after the
+//! capability advertisement, while receive-pack connections serve a single push.
+//! Responses are read up to their terminating flush.
+
+use crate::pkt_line::MAX_PAYLOAD;
+use crate::smart::Channel;
+use crate::{ProtocolError, Result};
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::task::JoinHandle;
+
+pub const UPLOAD_PACK: &str = "git-upload-pack";
+pub const RECEIVE_PACK: &str = "git-receive-pack";
+
+/// One connection to a service
+pub struct PktStream {
+    reader: BufReader<Box<dyn AsyncRead + Unpin + Send>>,
+    writer: Box<dyn AsyncWrite + Unpin + Send>,
+    /// Collects the other end's error output, when it has one, for failure messages
+    diagnostics: Option<JoinHandle<Vec<u8>>>,
+    /// Whatever must live as long as the connection (a child process, say)
+    _guard: Option<Box<dyn Send>>,
+}
+
+impl PktStream {
+    pub fn new(reader: Box<dyn AsyncRead + Unpin + Send>, writer: Box<dyn AsyncWrite + Unpin + Send>) -> Self {
+        Self {
+            reader: BufReader::new(reader),
+            writer,
+            diagnostics: None,
+            _guard: None,
+        }
+    }
+
+    /// Report the output `diagnostics` collects when the connection fails
+    pub fn with_diagnostics(mut self, diagnostics: JoinHandle<Vec<u8>>) -> Self {
+        self.diagnostics = Some(diagnostics);
+        self
+    }
+
+    /// Keep `guard` alive until the stream is dropped
+    pub fn with_guard(mut self, guard: Box<dyn Send>) -> Self {
+        self._guard = Some(guard);
+        self
+    }
+
+    pub async fn send(&mut self, data: &[u8]) -> Result<()> {
+        self.writer.write_all(data).await?;
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    async fn closed(&mut self) -> ProtocolError {
+        let mut detail = None;
+        if let Some(diagnostics) = self.diagnostics.take() {
+            // The other end has hung up, so its error output ends shortly
+            if let Ok(Ok(output)) = tokio::time::timeout(Duration::from_secs(1), diagnostics).await {
+                detail = Some(String::from_utf8_lossy(&output).trim().to_string()).filter(|d| !d.is_empty());
+            }
+        }
+        match detail {
+            Some(detail) => ProtocolError::Transport(format!("Connection closed: {}", detail)),
+            None => ProtocolError::Transport("Connection closed".to_string()),
+        }
+    }
+
+    /// Read pkt-lines up to and including the next flush. An `ERR` line also ends
+    /// the response, since the server hangs up after sending one.
+    pub async fn read_until_flush(&mut self) -> Result<Vec<u8>> {
+        let mut response = Vec::new();
+        loop {
+            let mut length = [0u8; 4];
+            if self.reader.read_exact(&mut length).await.is_err() {
+                return Err(self.closed().await);
+            }
+            response.extend_from_slice(&length);
+
+            let length = std::str::from_utf8(&length).ok()
+                .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| ProtocolError::InvalidResponse("Malformed pkt-line length".to_string()))?;
+            match length {
+                0 => return Ok(response),
+                1 | 2 => continue,
+                3 => return Err(ProtocolError::InvalidResponse("Invalid pkt-line length 3".to_string())),
+                _ if length - 4 > MAX_PAYLOAD => {
+                    return Err(ProtocolError::InvalidResponse("Oversized pkt-line".to_string()));
+                }
+                _ => {
+                    let start = response.len();
+                    response.resize(start + length - 4, 0);
+                    if self.reader.read_exact(&mut response[start..]).await.is_err() {
+                        return Err(self.closed().await);
+                    }
+                    if response[start..].starts_with(b"ERR ") {
+                        return Ok(response);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Opens connections to a remote's services
+#[async_trait]
+pub trait ServiceConnector: Send + Sync {
+    /// Start `service`, asking for protocol v2 when `protocol_v2` is set
+    async fn open(&self, service: &str, protocol_v2: bool) -> Result<PktStream>;
+}
+
+/// `Channel` over connections made by a `ServiceConnector`
+pub struct StreamChannel<C> {
+    connector: C,
+    upload: Option<PktStream>,
+    receive: Option<PktStream>,
+}
+
+impl<C: ServiceConnector> StreamChannel<C> {
+    pub fn new(connector: C) -> Self {
+        Self { connector, upload: None, receive: None }
+    }
+}
+
+fn not_connected(service: &str) -> ProtocolError {
+    ProtocolError::Transport(format!("{} request before its advertisement", service))
+}
+
+#[async_trait]
+impl<C: ServiceConnector> Channel for StreamChannel<C> {
+    async fn upload_pack_advertisement(&mut self) -> Result<Vec<u8>> {
+        let mut stream = self.connector.open(UPLOAD_PACK, true).await?;
+        let advertisement = stream.read_until_flush().await?;
+        self.upload = Some(stream);
+        Ok(advertisement)
+    }
+
+    async fn upload_pack(&mut self, request: Vec<u8>) -> Result<Vec<u8>> {
+        let stream = self.upload.as_mut().ok_or_else(|| not_connected(UPLOAD_PACK))?;
+        stream.send(&request).await?;
+        stream.read_until_flush().await
+    }
+
+    async fn receive_pack_advertisement(&mut self) -> Result<Vec<u8>> {
+        let mut stream = self.connector.open(RECEIVE_PACK, false).await?;
+        let advertisement = stream.read_until_flush().await?;
+        self.receive = Some(stream);
+        Ok(advertisement)
+    }
+
+    async fn receive_pack(&mut self, request: Vec<u8>) -> Result<Vec<u8>> {
+        let mut stream = self.receive.take().ok_or_else(|| not_connected(RECEIVE_PACK))?;
+        stream.send(&request).await?;
+        stream.read_until_flush().await
+    }
+}
@@ -0,0 +1,292 @@
+//! Moving packs in and out of a storage backend
+//!
+//! Received packs are converted to canonical objects with the compat layer's
+//! bottom-up import, recording every Git hash in the `GitHashIndex`. Thin-pack bases
+//! are served from storage by re-serializing canonical objects to Git format, and
+//! outgoing packs are built the same way.
+
+use crate::pack_reader::ParsedPack;
+use crate::pack_writer::{PackWriter, PackWriterOptions};
+use crate::Result;
+use gitnext_compat::pack::WrittenPack;
+use gitnext_compat::{import_reachable, GitHashIndex};
+use gitnext_core::{GitHash, GitHashType, GitObject, ObjectId, ObjectType};
+use gitnext_storage::{Storage, StorageError};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Load a stored object by Git hash as (type, Git content without header)
+pub async fn load_git_object(
+    storage: &dyn Storage,
+    index: &GitHashIndex,
+    hash: &GitHash,
+    hash_type: GitHashType,
+) -> Result<Option<(ObjectType, Vec<u8>)>> {
+    let Some(id) = index.object_id(hash) else {
+        return Ok(None);
+    };
+    let Some(object) = storage.load_object(&id).await? else {
+        return Ok(None);
+    };
+
+    let git_bytes = index.deriver().serialize_to_git_format(&object, hash_type)?;
+    let header_len = git_bytes.iter().position(|&b| b == 0).expect("Git objects have a header") + 1;
+    Ok(Some((object.object_type(), git_bytes[header_len..].to_vec())))
+}
+
+/// Resolve a thin pack's outside delta bases from storage
+pub async fn resolve_thin_pack(pack: &mut ParsedPack, storage: &dyn Storage, index: &GitHashIndex) -> Result<()> {
+    let hash_type = pack.hash_type();
+    loop {
+        let mut progressed = false;
+        for base in pack.missing_bases() {
+            if let Some((object_type, data)) = load_git_object(storage, index, &base, hash_type).await? {
+                pack.add_base(object_type, data)?;
+                progressed = true;
+            }
+        }
+        if !progressed {
+            break;
+        }
+    }
+    pack.ensure_complete()
+}
+
+/// Store every object of a fully resolved pack, returning how many were new
+///
+/// Objects the pack refers to but does not contain must already be in `index`.
+pub async fn import_pack(
+    storage: &dyn Storage,
+    index: &mut GitHashIndex,
+    pack: &ParsedPack,
+    verify_round_trip: bool,
+) -> Result<usize> {
+    pack.ensure_complete()?;
+
+    let mut imported = 0;
+    for object in pack.objects() {
+        imported += import_reachable(storage, pack, index, &object.hash, verify_round_trip).await?;
+    }
+    Ok(imported)
+}
+
+const OURS: u8 = 1;
+const THEIRS: u8 = 2;
+
+/// What packing `tips` but not `exclude` starts from
+#[derive(Default)]
+struct Walk {
+    /// Commits reachable from `tips` and not from `exclude`, newest first
+    commits: Vec<(ObjectId, GitObject)>,
+    /// Tags and the trees and blobs they name, reached from `tips` other than
+    /// through commits
+    others: Vec<ObjectId>,
+    /// Objects not to pack: excluded tags, trees and blobs, and everything in
+    /// the trees of the excluded commits at the edge of `commits`
+    excluded: HashSet<ObjectId>,
+    /// Trees and blobs of those edge trees with the names they have there, as
+    /// candidate thin-pack bases
+    bases: Vec<(ObjectId, String)>,
+}
+
+impl Walk {
+    /// Walk commits from `tips` and `exclude` together, newest first, until only
+    /// excluded ones are left, as `git rev-list` does; then mark the trees of
+    /// the excluded commits that are parents of included ones. Trees are not
+    /// walked past one already marked, and blobs are not loaded at all.
+    async fn new(storage: &dyn Storage, tips: &[ObjectId], exclude: &[ObjectId]) -> Result<Self> {
+        let mut walk = Self::default();
+        let mut flags: HashMap<ObjectId, u8> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        let mut loaded: HashMap<ObjectId, GitObject> = HashMap::new();
+        let starts = tips.iter().map(|id| (*id, OURS)).chain(exclude.iter().map(|id| (*id, THEIRS)));
+        for (start, flag) in starts {
+            // Peel tags down to what they name
+            let mut id = start;
+            loop {
+                let Some(object) = storage.load_object(&id).await? else {
+                    break;
+                };
+                match object {
+                    GitObject::Commit(ref commit) => {
+                        let timestamp = commit.committer.timestamp;
+                        let old = flags.get(&id).copied().unwrap_or(0);
+                        flags.insert(id, old | flag);
+                        if old == 0 {
+                            queue.push((timestamp, id));
+                        }
+                        loaded.insert(id, object);
+                        break;
+                    }
+                    GitObject::Tag(ref tag) => {
+                        let target = tag.target;
+                        walk.start(id, flag);
+                        id = target;
+                    }
+                    GitObject::Tree(_) if flag == THEIRS => {
+                        walk.mark_tree(storage, id).await?;
+                        break;
+                    }
+                    _ => {
+                        walk.start(id, flag);
+                        break;
+                    }
+                }
+            }
+        }
+
+        let mut edges = Vec::new();
+        while queue.iter().any(|(_, id)| flags[id] & THEIRS == 0) {
+            let (_, id) = queue.pop().expect("the queue is not empty");
+            let flag = flags[&id];
+            let object = match loaded.remove(&id) {
+                Some(object) => object,
+                None => storage.load_object(&id).await?.ok_or(StorageError::ObjectNotFound { id })?,
+            };
+            let GitObject::Commit(commit) = &object else {
+                return Err(StorageError::CorruptionDetected { id, details: "Parent is not a commit".to_string() }.into());
+            };
+            let flag = if flag & THEIRS != 0 { THEIRS } else { OURS };
+            for parent in &commit.parents {
+                let old = flags.get(parent).copied().unwrap_or(0);
+                if old | flag == old {
+                    continue;
+                }
+                // Parents missing from storage are past a shallow boundary
+                let parent_object = match loaded.remove(parent) {
+                    Some(object) => object,
+                    None => match storage.load_object(parent).await? {
+                        Some(object) => object,
+                        None => continue,
+                    },
+                };
+                let GitObject::Commit(parent_commit) = &parent_object else {
+                    return Err(StorageError::CorruptionDetected { id: *parent, details: "Parent is not a commit".to_string() }.into());
+                };
+                flags.insert(*parent, old | flag);
+                queue.push((parent_commit.committer.timestamp, *parent));
+                loaded.insert(*parent, parent_object);
+            }
+            if flag == OURS {
+                edges.extend(commit.parents.iter().copied());
+                walk.commits.push((id, object));
+            }
+        }
+        // Commits marked excluded after they were walked are left out after all
+        walk.commits.retain(|(id, _)| flags[id] & THEIRS == 0);
+
+        for edge in edges {
+            if flags.get(&edge).is_some_and(|flag| flag & THEIRS != 0) {
+                if let Some(GitObject::Commit(commit)) = storage.load_object(&edge).await? {
+                    walk.mark_tree(storage, commit.tree).await?;
+                }
+            }
+        }
+        Ok(walk)
+    }
+
+    /// Pack or exclude an object that is not a commit, as `flag` says
+    fn start(&mut self, id: ObjectId, flag: u8) {
+        if flag == OURS {
+            self.others.push(id);
+        } else {
+            self.excluded.insert(id);
+        }
+    }
+
+    /// Exclude the tree `root` and everything in it that is not excluded yet
+    async fn mark_tree(&mut self, storage: &dyn Storage, root: ObjectId) -> Result<()> {
+        if !self.excluded.insert(root) {
+            return Ok(());
+        }
+        let mut stack = vec![root];
+        while let Some(id) = stack.pop() {
+            let Some(GitObject::Tree(tree)) = storage.load_object(&id).await? else {
+                continue;
+            };
+            for entry in tree.entries {
+                if entry.entry_type == ObjectType::Commit || !self.excluded.insert(entry.hash) {
+                    continue;
+                }
+                if entry.entry_type == ObjectType::Tree {
+                    stack.push(entry.hash);
+                }
+                self.bases.push((entry.hash, entry.name));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Write a pack of every object reachable from `tips` but not from `exclude`
+///
+/// As with `git pack-objects`, only commits are walked to find where `exclude`
+/// begins; the objects left out are those of the trees of the excluded commits
+/// at that edge, so an object the edge does not have is sent even if an older
+/// excluded commit had it. With `thin`, the objects of those trees whose names
+/// match a packed object are offered as delta bases without being written, as
+/// `git pack-objects --thin` does; the receiver must have them. `exclude` ids
+/// missing from storage are ignored. Git hashes are derived (and recorded in
+/// `index`) as needed.
+pub async fn build_pack(
+    storage: &dyn Storage,
+    index: &mut GitHashIndex,
+    tips: &[ObjectId],
+    exclude: &[ObjectId],
+    hash_type: GitHashType,
+    thin: bool,
+) -> Result<WrittenPack> {
+    build_pack_with_options(storage, index, tips, exclude, hash_type, thin, PackWriterOptions::default()).await
+}
+
+/// `build_pack` with control over delta encoding
+pub async fn build_pack_with_options(
+    storage: &dyn Storage,
+    index: &mut GitHashIndex,
+    tips: &[ObjectId],
+    exclude: &[ObjectId],
+    hash_type: GitHashType,
+    thin: bool,
+    options: PackWriterOptions,
+) -> Result<WrittenPack> {
+    let Walk { commits, others, mut excluded, bases } = Walk::new(storage, tips, exclude).await?;
+    for tip in tips {
+        index.derive(tip, hash_type).await?;
+    }
+
+    let mut writer = PackWriter::with_options(hash_type, options);
+    let mut stack: Vec<(ObjectId, Option<String>)> = others.into_iter().rev().map(|id| (id, None)).collect();
+    for (_, object) in commits.iter().rev() {
+        if let GitObject::Commit(commit) = object {
+            stack.push((commit.tree, None));
+        }
+    }
+    for (_, object) in &commits {
+        writer.add_object(object, index.deriver(), None)?;
+    }
+    let mut names = HashSet::new();
+    while let Some((id, name)) = stack.pop() {
+        if !excluded.insert(id) {
+            continue;
+        }
+        let object = storage.load_object(&id).await?.ok_or(StorageError::ObjectNotFound { id })?;
+        writer.add_object(&object, index.deriver(), name.as_deref())?;
+        if let GitObject::Tree(tree) = &object {
+            stack.extend(tree.entries.iter()
+                .filter(|entry| entry.entry_type != ObjectType::Commit)
+                .map(|entry| (entry.hash, Some(entry.name.clone()))));
+        }
+        names.extend(name);
+    }
+
+    if thin && !writer.is_empty() {
+        for (id, name) in bases.into_iter().filter(|(_, name)| names.contains(name)) {
+            let hash = index.derive(&id, hash_type).await?;
+            let object = storage.load_object(&id).await?.ok_or(StorageError::ObjectNotFound { id })?;
+            let git_bytes = index.deriver().serialize_to_git_format(&object, hash_type)?;
+            let header_len = git_bytes.iter().position(|&b| b == 0).expect("Git objects have a header") + 1;
+            writer.add_thin_base(hash, object.object_type(), git_bytes[header_len..].to_vec(), Some(&name));
+        }
+    }
+
+    writer.write()
+}
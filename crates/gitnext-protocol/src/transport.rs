@@ -0,0 +1,106 @@
+//! Remote transports
+//!
+//! A `Transport` lists a remote's refs, fetches packs for a set of wanted commits and
+//! sends packs with ref updates. All Git hashes are in the remote's object format.
+
+use crate::daemon::DaemonConnector;
+use crate::http::HttpChannel;
+use crate::pack_reader::ParsedPack;
+use crate::smart::SmartTransport;
+use crate::ssh::{default_ssh_command, SshConnector};
+use crate::stream::StreamChannel;
+use crate::url::RemoteUrl;
+use crate::{ProtocolError, Result};
+use async_trait::async_trait;
+use gitnext_core::{GitHash, GitHashType};
+
+/// A ref advertised by a remote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteRef {
+    pub name: String,
+    pub hash: GitHash,
+    /// Target of a symbolic ref, such as the branch `HEAD` points at
+    pub symref_target: Option<String>,
+    /// Object an annotated tag ultimately points at
+    pub peeled: Option<GitHash>,
+}
+
+/// One ref change requested by a push; `None` stands for a missing ref
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RefUpdate {
+    pub name: String,
+    pub old: Option<GitHash>,
+    pub new: Option<GitHash>,
+}
+
+/// Outcome of one ref update, as reported by the remote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RefStatus {
+    Ok,
+    Rejected(String),
+}
+
+/// Per-ref results of a push
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PushReport {
+    pub refs: Vec<(String, RefStatus)>,
+}
+
+impl PushReport {
+    /// The first rejected ref as an error
+    pub fn into_result(self) -> Result<Self> {
+        match self.refs.iter().find_map(|(name, status)| match status {
+            RefStatus::Rejected(reason) => Some((name, reason)),
+            RefStatus::Ok => None,
+        }) {
+            Some((name, reason)) => Err(ProtocolError::Rejected { name: name.clone(), reason: reason.clone() }),
+            None => Ok(self),
+        }
+    }
+}
+
+/// Connection to a remote repository
+#[async_trait]
+pub trait Transport: Send {
+    /// Object format of the remote repository
+    async fn hash_type(&mut self) -> Result<GitHashType>;
+
+    /// Branches, tags and `HEAD` available for fetching
+    async fn list_refs(&mut self) -> Result<Vec<RemoteRef>>;
+
+    /// Fetch a pack holding `wants` and their history, omitting what `haves` already
+    /// cover; the pack may be thin against objects reachable from `haves`
+    async fn fetch_packfile(&mut self, wants: &[GitHash], haves: &[GitHash]) -> Result<ParsedPack>;
+
+    /// Current values of the refs a push may update
+    async fn list_push_refs(&mut self) -> Result<Vec<RemoteRef>>;
+
+    /// Send a pack (omitted when every update is a deletion) and apply `updates`
+    async fn send_packfile(&mut self, updates: &[RefUpdate], pack: Option<Vec<u8>>) -> Result<PushReport>;
+}
+
+/// Options for opening transports
+#[derive(Debug, Clone, Default)]
+pub struct TransportOptions {
+    /// Shell snippet run for ssh URLs; `GIT_SSH_COMMAND`, `GIT_SSH` or `ssh` if unset
+    pub ssh_command: Option<String>,
+}
+
+/// Open a transport for `url`, chosen by its scheme
+pub async fn connect(url: &str) -> Result<Box<dyn Transport>> {
+    connect_with_options(url, &TransportOptions::default()).await
+}
+
+pub async fn connect_with_options(url: &str, options: &TransportOptions) -> Result<Box<dyn Transport>> {
+    Ok(match RemoteUrl::parse(url)? {
+        RemoteUrl::Http(url) => Box::new(SmartTransport::new(HttpChannel::new(&url)?)),
+        RemoteUrl::Git { host, port, path } => {
+            Box::new(SmartTransport::new(StreamChannel::new(DaemonConnector::new(&host, port, &path))))
+        }
+        RemoteUrl::Ssh { user, host, port, path } => {
+            let command = options.ssh_command.clone().unwrap_or_else(default_ssh_command);
+            let connector = SshConnector::new(&command, user.as_deref(), &host, port, &path);
+            Box::new(SmartTransport::new(StreamChannel::new(connector)))
+        }
+    })
+}
@@ -0,0 +1,162 @@
+//! Remote URL parsing
+//!
+//! Accepts the URL forms Git does for network remotes: `http(s)://`, `git://`,
+//! `ssh://` (also `git+ssh://` and `ssh+git://`) and scp-like `[user@]host:path`.
+
+use crate::{ProtocolError, Result};
+
+/// Default port of `git daemon`
+pub const GIT_DAEMON_PORT: u16 = 9418;
+
+/// A parsed remote location
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteUrl {
+    /// Smart HTTP; the URL is used as is
+    Http(String),
+    /// `git daemon` over TCP
+    Git { host: String, port: u16, path: String },
+    /// Git over a secure shell command
+    Ssh { user: Option<String>, host: String, port: Option<u16>, path: String },
+}
+
+fn unsupported(url: &str) -> ProtocolError {
+    ProtocolError::UnsupportedUrl(url.to_string())
+}
+
+/// Refuse users, hosts and paths that an ssh command would take for an option,
+/// as Git does (CVE-2017-1000117)
+fn check_not_option(part: &str, url: &str) -> Result<()> {
+    if part.starts_with('-') {
+        return Err(unsupported(url));
+    }
+    Ok(())
+}
+
+/// Split `[user@]host[:port]`, accepting bracketed IPv6 hosts
+fn split_authority(authority: &str, url: &str) -> Result<(Option<String>, String, Option<u16>)> {
+    let (user, host_port) = match authority.rsplit_once('@') {
+        Some((user, rest)) => (Some(user.to_string()), rest),
+        None => (None, authority),
+    };
+    let (host, port) = if let Some(rest) = host_port.strip_prefix('[') {
+        let (host, after) = rest.split_once(']').ok_or_else(|| unsupported(url))?;
+        (host, after.strip_prefix(':'))
+    } else {
+        match host_port.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (host_port, None),
+        }
+    };
+    let port = match port {
+        Some(port) => Some(port.parse().map_err(|_| unsupported(url))?),
+        None => None,
+    };
+    if host.is_empty() {
+        return Err(unsupported(url));
+    }
+    check_not_option(host, url)?;
+    if let Some(user) = &user {
+        check_not_option(user, url)?;
+    }
+    Ok((user, host.to_string(), port))
+}
+
+impl RemoteUrl {
+    pub fn parse(url: &str) -> Result<Self> {
+        if url.starts_with("http://") || url.starts_with("https://") {
+            return Ok(RemoteUrl::Http(url.to_string()));
+        }
+
+        if let Some((scheme, rest)) = url.split_once("://") {
+            let (authority, path) = match rest.find('/') {
+                Some(slash) => (&rest[..slash], &rest[slash..]),
+                None => return Err(unsupported(url)),
+            };
+            let (user, host, port) = split_authority(authority, url)?;
+            return match scheme {
+                "git" if user.is_none() => Ok(RemoteUrl::Git {
+                    host,
+                    port: port.unwrap_or(GIT_DAEMON_PORT),
+                    path: path.to_string(),
+                }),
+                "ssh" | "git+ssh" | "ssh+git" => {
+                    // ssh://host/~user/repo names a path relative to a home directory
+                    let path = if path.starts_with("/~") { &path[1..] } else { path };
+                    check_not_option(path, url)?;
+                    Ok(RemoteUrl::Ssh { user, host, port, path: path.to_string() })
+                }
+                _ => Err(unsupported(url)),
+            };
+        }
+
+        // scp-like syntax: a colon before any slash, and not a Windows drive letter
+        match url.find(':') {
+            Some(colon) if colon > 1 && !url[..colon].contains('/') => {
+                let (user, host) = match url[..colon].rsplit_once('@') {
+                    Some((user, host)) => (Some(user.to_string()), host),
+                    None => (None, &url[..colon]),
+                };
+                let host = host.trim_start_matches('[').trim_end_matches(']');
+                let path = &url[colon + 1..];
+                if host.is_empty() || path.is_empty() {
+                    return Err(unsupported(url));
+                }
+                for part in user.iter().map(String::as_str).chain([host, path]) {
+                    check_not_option(part, url)?;
+                }
+                Ok(RemoteUrl::Ssh { user, host: host.to_string(), port: None, path: path.to_string() })
+            }
+            _ => Err(unsupported(url)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ssh(user: Option<&str>, host: &str, port: Option<u16>, path: &str) -> RemoteUrl {
+        RemoteUrl::Ssh { user: user.map(str::to_string), host: host.to_string(), port, path: path.to_string() }
+    }
+
+    #[test]
+    fn test_parse_remote_urls() {
+        assert_eq!(RemoteUrl::parse("https://example.com/repo.git").unwrap(), RemoteUrl::Http("https://example.com/repo.git".to_string()));
+        assert_eq!(
+            RemoteUrl::parse("git://example.com/srv/repo.git").unwrap(),
+            RemoteUrl::Git { host: "example.com".to_string(), port: 9418, path: "/srv/repo.git".to_string() }
+        );
+        assert_eq!(
+            RemoteUrl::parse("git://[::1]:1234/repo").unwrap(),
+            RemoteUrl::Git { host: "::1".to_string(), port: 1234, path: "/repo".to_string() }
+        );
+        assert_eq!(RemoteUrl::parse("ssh://git@example.com:2222/org/repo.git").unwrap(), ssh(Some("git"), "example.com", Some(2222), "/org/repo.git"));
+        assert_eq!(RemoteUrl::parse("git+ssh://example.com/~alice/repo").unwrap(), ssh(None, "example.com", None, "~alice/repo"));
+        assert_eq!(RemoteUrl::parse("git@example.com:org/repo.git").unwrap(), ssh(Some("git"), "example.com", None, "org/repo.git"));
+        assert_eq!(RemoteUrl::parse("mirror:/srv/repo").unwrap(), ssh(None, "mirror", None, "/srv/repo"));
+    }
+
+    #[test]
+    fn test_reject_unsupported_urls() {
+        for url in ["ftp://example.com/repo", "file:///srv/repo", "/srv/repo", "C:/repo", "repo", "git://host", "git://user@host/repo", "ssh://host:port/repo"] {
+            assert!(matches!(RemoteUrl::parse(url), Err(ProtocolError::UnsupportedUrl(_))), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_reject_option_like_parts() {
+        let urls = [
+            "ssh://-oProxyCommand=touch%20pwned/repo",
+            "ssh://-oProxyCommand=sh@host/repo",
+            "git://-host/repo",
+            "-oProxyCommand=sh:repo",
+            "-user@host:repo",
+            "host:-repo",
+        ];
+        for url in urls {
+            assert!(matches!(RemoteUrl::parse(url), Err(ProtocolError::UnsupportedUrl(_))), "{}", url);
+        }
+        // Dashes elsewhere are fine
+        assert_eq!(RemoteUrl::parse("ssh://my-host/repo-x").unwrap(), ssh(None, "my-host", None, "/repo-x"));
+    }
+}
@@ -0,0 +1,50 @@
+//! `git daemon` serving a directory of repositories on a local port
+
+use super::GitFixture;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+
+/// A running `git daemon`, stopped when dropped
+pub struct GitDaemon {
+    child: Child,
+    port: u16,
+}
+
+impl Drop for GitDaemon {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl GitDaemon {
+    /// Export every repository under `root`, accepting pushes
+    pub fn start(fixture: &GitFixture, root: &Path) -> Self {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let child = Command::new("git")
+            .args(["daemon", "--reuseaddr", "--export-all", "--enable=receive-pack", "--listen=127.0.0.1"])
+            .arg(format!("--port={}", port))
+            .arg(format!("--base-path={}", root.display()))
+            .arg(root)
+            .envs(fixture.environment())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to run git daemon");
+        let daemon = Self { child, port };
+
+        for _ in 0..100 {
+            if std::net::TcpStream::connect(("127.0.0.1", port)).is_ok() {
+                return daemon;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        panic!("git daemon did not start");
+    }
+
+    /// URL of the repository at `name` under the served root
+    pub fn url(&self, name: &str) -> String {
+        format!("git://127.0.0.1:{}/{}", self.port, name)
+    }
+}
@@ -0,0 +1,159 @@
+//! Minimal HTTP/1.1 front end running `git http-backend` as a CGI program
+
+use super::GitFixture;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Serves the bare repositories under a directory until dropped
+pub struct HttpBackend {
+    address: std::net::SocketAddr,
+    task: tokio::task::JoinHandle<()>,
+}
+
+impl Drop for HttpBackend {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    query: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpBackend {
+    /// Serve `root`, whose repositories may be pushed to
+    pub async fn start(fixture: &GitFixture, root: &Path) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let context = Arc::new((fixture.environment(), root.to_path_buf()));
+
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let context = context.clone();
+                tokio::spawn(async move {
+                    let (environment, root) = context.as_ref();
+                    let _ = Self::handle(stream, environment, root).await;
+                });
+            }
+        });
+        Self { address, task }
+    }
+
+    /// URL of the repository at `name` under the served root
+    pub fn url(&self, name: &str) -> String {
+        format!("http://{}/{}", self.address, name)
+    }
+
+    async fn read_request(stream: &mut BufReader<TcpStream>) -> std::io::Result<Request> {
+        let mut line = String::new();
+        stream.read_line(&mut line).await?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or_default().to_string();
+        let target = parts.next().unwrap_or_default().to_string();
+        let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+
+        let mut headers = Vec::new();
+        loop {
+            let mut line = String::new();
+            stream.read_line(&mut line).await?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+            }
+        }
+
+        let length = headers.iter()
+            .find(|(name, _)| name == "content-length")
+            .and_then(|(_, value)| value.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).await?;
+
+        Ok(Request { method, path: path.to_string(), query: query.to_string(), headers, body })
+    }
+
+    async fn handle(stream: TcpStream, environment: &[(String, String)], root: &Path) -> std::io::Result<()> {
+        let mut stream = BufReader::new(stream);
+        let request = Self::read_request(&mut stream).await?;
+
+        let header = |name: &str| {
+            request.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.clone()).unwrap_or_default()
+        };
+        let mut command = std::process::Command::new("git");
+        command
+            .arg("http-backend")
+            .envs(environment.iter().map(|(k, v)| (k, v)))
+            .env("GIT_PROJECT_ROOT", root)
+            .env("GIT_HTTP_EXPORT_ALL", "1")
+            .env("REQUEST_METHOD", &request.method)
+            .env("PATH_INFO", &request.path)
+            .env("QUERY_STRING", &request.query)
+            .env("CONTENT_TYPE", header("content-type"))
+            .env("CONTENT_LENGTH", request.body.len().to_string())
+            .env("GIT_PROTOCOL", header("git-protocol"))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null());
+
+        let body = request.body;
+        let output = tokio::task::spawn_blocking(move || {
+            let mut child = command.spawn()?;
+            let mut stdin = child.stdin.take().unwrap();
+            let writer = std::thread::spawn(move || stdin.write_all(&body));
+            let output = child.wait_with_output()?;
+            let _ = writer.join();
+            Ok::<_, std::io::Error>(output.stdout)
+        })
+        .await
+        .unwrap()?;
+
+        // CGI output: headers (with an optional Status), a blank line, then the body
+        let split = output.windows(4).position(|w| w == b"\r\n\r\n").map(|i| (i, i + 4))
+            .or_else(|| output.windows(2).position(|w| w == b"\n\n").map(|i| (i, i + 2)))
+            .unwrap_or((output.len(), output.len()));
+        let cgi_headers = String::from_utf8_lossy(&output[..split.0]).into_owned();
+        let body = &output[split.1..];
+
+        let mut status = "200 OK".to_string();
+        let mut response = Vec::new();
+        for line in cgi_headers.lines().filter(|l| !l.is_empty()) {
+            match line.strip_prefix("Status:") {
+                Some(value) => status = value.trim().to_string(),
+                None => {
+                    response.extend_from_slice(line.as_bytes());
+                    response.extend_from_slice(b"\r\n");
+                }
+            }
+        }
+        let mut head = format!("HTTP/1.1 {}\r\n", status).into_bytes();
+        head.extend_from_slice(&response);
+        head.extend_from_slice(format!("Content-Length: {}\r\nConnection: close\r\n\r\n", body.len()).as_bytes());
+
+        let stream = stream.get_mut();
+        stream.write_all(&head).await?;
+        stream.write_all(body).await?;
+        stream.shutdown().await
+    }
+}
+
+/// A bare repository under `root` that accepts pushes over HTTP
+pub fn served_repo(fixture: &GitFixture, root: &Path, name: &str, init_args: &[&str]) -> PathBuf {
+    let path = root.join(name);
+    let mut args = vec!["init", "-q", "--bare", "-b", "main"];
+    args.extend_from_slice(init_args);
+    args.push(path.to_str().unwrap());
+    fixture.git_in(fixture.path(), &args);
+    fixture.git_in(&path, &["config", "http.receivepack", "true"]);
+    path
+}
@@ -0,0 +1,153 @@
+//! Fixture repositories built with the `git` command line tool
+
+#![allow(dead_code)]
+
+pub mod daemon;
+pub mod http;
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use tempfile::TempDir;
+
+/// A scratch Git repository with a fixed identity and clock
+pub struct GitFixture {
+    dir: TempDir,
+    path: PathBuf,
+}
+
+impl GitFixture {
+    /// Initialize a repository, or return `None` when `git` is not installed
+    pub fn init(extra_args: &[&str]) -> Option<Self> {
+        if Command::new("git").arg("--version").output().is_err() {
+            eprintln!("git not found, skipping");
+            return None;
+        }
+
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("repo");
+        let fixture = Self { dir, path };
+
+        let mut args = vec!["init", "-q", "-b", "main"];
+        args.extend_from_slice(extra_args);
+        args.push(fixture.path.to_str().unwrap());
+        fixture.git_in(fixture.dir.path(), &args);
+        Some(fixture)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// A fresh directory inside the fixture's temporary directory
+    pub fn scratch(&self, name: &str) -> PathBuf {
+        self.dir.path().join(name)
+    }
+
+    /// Environment every git invocation runs with
+    pub fn environment(&self) -> Vec<(String, String)> {
+        [
+            ("HOME", self.dir.path().to_str().unwrap()),
+            ("GIT_CONFIG_NOSYSTEM", "1"),
+            ("GIT_CONFIG_GLOBAL", "/dev/null"),
+            ("GIT_AUTHOR_NAME", "Test Author"),
+            ("GIT_AUTHOR_EMAIL", "author@example.com"),
+            ("GIT_AUTHOR_DATE", "1234567890 +0000"),
+            ("GIT_COMMITTER_NAME", "Test Committer"),
+            ("GIT_COMMITTER_EMAIL", "committer@example.com"),
+            ("GIT_COMMITTER_DATE", "1234567890 +0000"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+    }
+
+    fn command(&self, cwd: &Path, args: &[&str]) -> Command {
+        let mut command = Command::new("git");
+        command.args(args).current_dir(cwd).envs(self.environment());
+        command
+    }
+
+    pub fn git(&self, args: &[&str]) -> String {
+        self.git_in(&self.path, args)
+    }
+
+    pub fn git_in(&self, cwd: &Path, args: &[&str]) -> String {
+        let stdout = self.git_stdin(cwd, args, b"");
+        String::from_utf8(stdout).unwrap().trim().to_string()
+    }
+
+    /// Run git without checking that it succeeds
+    pub fn git_output(&self, cwd: &Path, args: &[&str]) -> std::process::Output {
+        self.command(cwd, args).output().expect("failed to run git")
+    }
+
+    /// Run git feeding `input` on stdin, returning raw stdout
+    pub fn git_stdin(&self, cwd: &Path, args: &[&str], input: &[u8]) -> Vec<u8> {
+        let mut child = self.command(cwd, args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("failed to run git");
+        let mut stdin = child.stdin.take().unwrap();
+        let input = input.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&input));
+        let output = child.wait_with_output().unwrap();
+        writer.join().unwrap().unwrap();
+        assert!(
+            output.status.success(),
+            "git {:?} failed: {}",
+            args,
+            String::from_utf8_lossy(&output.stderr)
+        );
+        output.stdout
+    }
+
+    pub fn write(&self, path: &str, contents: &str) {
+        let full = self.path.join(path);
+        std::fs::create_dir_all(full.parent().unwrap()).unwrap();
+        std::fs::write(full, contents).unwrap();
+    }
+
+    /// Stage everything and commit
+    pub fn commit(&self, message: &str) -> String {
+        self.git(&["add", "-A"]);
+        self.git(&["commit", "-q", "-m", message]);
+        self.git(&["rev-parse", "HEAD"])
+    }
+
+    /// A history of repeatedly edited files, so that packs contain delta chains
+    pub fn build_delta_history(&self, commits: usize) {
+        let mut source: String = (0..400)
+            .map(|i| format!("fn function_{}() -> u32 {{ {} }}\n", i, i * 7))
+            .collect();
+        for n in 0..commits {
+            source.push_str(&format!("// revision {}\n", n));
+            source = source.replacen(&format!("function_{}()", n * 3), &format!("renamed_{}()", n), 1);
+            self.write("src/lib.rs", &source);
+            self.write("docs/notes.txt", &source.to_uppercase());
+            self.write(&format!("small/{}.txt", n), &format!("small {}\n", n));
+            self.commit(&format!("Revision {}", n));
+        }
+        self.git(&["tag", "-a", "v1", "-m", "Tagged"]);
+    }
+
+    /// `git pack-objects` output for the objects reachable from `revs` (one per line,
+    /// `^rev` excludes; `--all` selects every ref)
+    pub fn pack_objects(&self, revs: &str, extra_args: &[&str]) -> Vec<u8> {
+        let mut args = vec!["pack-objects", "--stdout", "--revs", "-q"];
+        args.extend_from_slice(extra_args);
+        let (options, revs): (Vec<&str>, Vec<&str>) = revs.lines().partition(|rev| rev.starts_with("--"));
+        args.extend(options);
+        self.git_stdin(&self.path, &args, format!("{}\n", revs.join("\n")).as_bytes())
+    }
+
+    /// Hashes of every object reachable from `rev` (`--all` for every ref)
+    pub fn objects(&self, rev: &str) -> Vec<String> {
+        self.git(&["rev-list", "--objects", rev])
+            .lines()
+            .map(|line| line.split(' ').next().unwrap().to_string())
+            .collect()
+    }
+}
@@ -0,0 +1,300 @@
+//! Packfile reading and writing against the `git` command line tool
+
+mod common;
+
+use common::GitFixture;
+use gitnext_compat::pack::encode_entry_header;
+use gitnext_compat::GitHashIndex;
+use gitnext_core::{GitHash, GitHashType};
+use gitnext_protocol::{
+    build_pack, import_pack, resolve_thin_pack, PackReader, PackWriter, PackWriterOptions, ParsedPack, ProtocolError,
+};
+use gitnext_storage::{MemoryStorage, Storage};
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+/// Reader handing out a few bytes per call, like a slow network connection
+struct Trickle<'a> {
+    data: &'a [u8],
+}
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(self.data.len()).min(7);
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
+
+fn hash_set(pack: &ParsedPack) -> HashSet<String> {
+    pack.objects().iter().map(|o| o.hash.to_string()).collect()
+}
+
+/// Write a pack into a scratch directory and have `git index-pack` build its index
+fn git_index_pack(fixture: &GitFixture, name: &str, pack: &[u8]) -> Vec<u8> {
+    let pack_path = fixture.scratch(&format!("{}.pack", name));
+    std::fs::write(&pack_path, pack).unwrap();
+    fixture.git(&["index-pack", "--strict", pack_path.to_str().unwrap()]);
+    std::fs::read(pack_path.with_extension("idx")).unwrap()
+}
+
+/// Number of deltified entries reported by `git verify-pack -v`
+fn verified_delta_count(fixture: &GitFixture, pack: &[u8], index: &[u8]) -> usize {
+    let pack_path = fixture.scratch("verify.pack");
+    std::fs::write(&pack_path, pack).unwrap();
+    std::fs::write(pack_path.with_extension("idx"), index).unwrap();
+    let output = fixture.git(&["verify-pack", "-v", pack_path.to_str().unwrap()]);
+    // Deltified entries list their depth and base after the sizes
+    output.lines().filter(|line| line.split_whitespace().count() == 7).count()
+}
+
+fn bare_repo(fixture: &GitFixture, name: &str, hash_type: GitHashType) -> std::path::PathBuf {
+    let path = fixture.scratch(name);
+    let format = match hash_type {
+        GitHashType::Sha1 => "sha1",
+        GitHashType::Sha256 => "sha256",
+    };
+    fixture.git_in(
+        fixture.path(),
+        &["init", "-q", "--bare", &format!("--object-format={}", format), path.to_str().unwrap()],
+    );
+    path
+}
+
+/// Feed a pack into `repo` and run a strict fsck over what arrived
+fn git_accepts(fixture: &GitFixture, repo: &Path, pack: &[u8], fix_thin: bool) {
+    let mut args = vec!["index-pack", "--strict", "--stdin"];
+    if fix_thin {
+        args.push("--fix-thin");
+    }
+    fixture.git_stdin(repo, &args, pack);
+}
+
+#[test]
+fn test_read_git_pack_with_ofs_deltas() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_delta_history(12);
+
+    let data = fixture.pack_objects("--all", &["--delta-base-offset"]);
+    let pack = PackReader::new(GitHashType::Sha1).read(Trickle { data: &data }).unwrap();
+
+    assert!(pack.is_complete());
+    let expected: HashSet<String> = fixture.objects("--all").into_iter().collect();
+    assert_eq!(hash_set(&pack), expected);
+
+    // Index offsets and CRCs match what git computes for the same pack
+    assert_eq!(pack.index(), git_index_pack(&fixture, "ofs", &data));
+    assert!(verified_delta_count(&fixture, &data, &pack.index()) > 0);
+}
+
+#[test]
+fn test_read_git_pack_with_ref_deltas() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_delta_history(12);
+
+    // Without --delta-base-offset git names delta bases by hash
+    let data = fixture.pack_objects("--all", &[]);
+    let pack = PackReader::new(GitHashType::Sha1).read(data.as_slice()).unwrap();
+
+    let expected: HashSet<String> = fixture.objects("--all").into_iter().collect();
+    assert_eq!(hash_set(&pack), expected);
+    assert_eq!(pack.index(), git_index_pack(&fixture, "ref", &data));
+
+    let head = GitHash::from_hex(&fixture.git(&["rev-parse", "HEAD:src/lib.rs"])).unwrap();
+    let content = fixture.git(&["cat-file", "blob", "HEAD:src/lib.rs"]);
+    assert_eq!(String::from_utf8_lossy(&pack.get(&head).unwrap().data).trim_end(), content);
+}
+
+#[test]
+fn test_read_rejects_corrupt_pack() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_delta_history(2);
+    let mut data = fixture.pack_objects("--all", &["--delta-base-offset"]);
+
+    let last = data.len() - 1;
+    data[last] ^= 0xff;
+    let result = PackReader::new(GitHashType::Sha1).read(data.as_slice());
+    assert!(matches!(result, Err(ProtocolError::ChecksumMismatch { .. })));
+
+    data.truncate(data.len() / 2);
+    assert!(PackReader::new(GitHashType::Sha1).read(data.as_slice()).is_err());
+}
+
+#[test]
+fn test_read_rejects_forged_entry_size() {
+    // One blob whose header claims far more bytes than its zlib stream holds
+    for size in [5usize, 1 << 40, usize::MAX >> 4] {
+        let mut data = b"PACK".to_vec();
+        data.extend_from_slice(&2u32.to_be_bytes());
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&encode_entry_header(3, size));
+        let mut encoder = flate2::write::ZlibEncoder::new(&mut data, flate2::Compression::default());
+        encoder.write_all(b"one\n").unwrap();
+        encoder.finish().unwrap();
+        let checksum = GitHash::from_git_bytes(&data, GitHashType::Sha1);
+        data.extend_from_slice(checksum.as_bytes());
+
+        let result = PackReader::new(GitHashType::Sha1).read(data.as_slice());
+        assert!(matches!(result, Err(ProtocolError::InvalidPack(_))), "{:?}", result.err());
+    }
+}
+
+#[test]
+fn test_written_pack_is_accepted_by_git() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_delta_history(12);
+
+    let source = PackReader::new(GitHashType::Sha1)
+        .read(fixture.pack_objects("--all", &[]).as_slice())
+        .unwrap();
+
+    for ofs_delta in [true, false] {
+        let options = PackWriterOptions { ofs_delta, ..Default::default() };
+        let mut writer = PackWriter::with_options(GitHashType::Sha1, options);
+        for object in source.objects() {
+            writer.add(object.hash, object.object_type, object.data.clone(), None);
+        }
+        let written = writer.write().unwrap();
+
+        // Deltas actually shrink the pack, and git agrees with our index
+        let undeltified: usize = source.objects().iter().map(|o| o.data.len()).sum();
+        assert!(written.pack.len() < undeltified / 2);
+        assert!(verified_delta_count(&fixture, &written.pack, &written.index) > 0);
+        assert_eq!(written.index, git_index_pack(&fixture, "written", &written.pack));
+
+        let repo = bare_repo(&fixture, &format!("target-{}", ofs_delta), GitHashType::Sha1);
+        git_accepts(&fixture, &repo, &written.pack, false);
+
+        // And our own reader reproduces every object
+        let reread = PackReader::new(GitHashType::Sha1).read(written.pack.as_slice()).unwrap();
+        assert_eq!(hash_set(&reread), hash_set(&source));
+        assert_eq!(reread.index(), written.index);
+    }
+}
+
+#[tokio::test]
+async fn test_thin_pack_from_git_resolves_against_storage() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_delta_history(12);
+
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+
+    // The receiver already has main~4
+    let base = PackReader::new(GitHashType::Sha1)
+        .read(fixture.pack_objects("main~4", &[]).as_slice())
+        .unwrap();
+    import_pack(storage.as_ref(), &mut index, &base, true).await.unwrap();
+
+    let data = fixture.pack_objects("main\n^main~4", &["--thin", "--delta-base-offset"]);
+    let mut thin = PackReader::new(GitHashType::Sha1).read(data.as_slice()).unwrap();
+    assert!(!thin.missing_bases().is_empty());
+    assert!(matches!(import_pack(storage.as_ref(), &mut index, &thin, true).await, Err(ProtocolError::MissingBase(_))));
+
+    resolve_thin_pack(&mut thin, storage.as_ref(), &index).await.unwrap();
+    let imported = import_pack(storage.as_ref(), &mut index, &thin, true).await.unwrap();
+    assert_eq!(imported, thin.len());
+
+    for hash in fixture.objects("main") {
+        let hash = GitHash::from_hex(&hash).unwrap();
+        let id = index.object_id(&hash).unwrap_or_else(|| panic!("{} missing", hash));
+        assert!(storage.load_object(&id).await.unwrap().is_some());
+    }
+}
+
+#[tokio::test]
+async fn test_write_thin_pack_from_canonical_objects() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_delta_history(12);
+
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+    let full = PackReader::new(GitHashType::Sha1)
+        .read(fixture.pack_objects("--all", &[]).as_slice())
+        .unwrap();
+    import_pack(storage.as_ref(), &mut index, &full, true).await.unwrap();
+
+    // The receiver has main~4; send the rest as a thin pack built from canonical objects
+    let have: HashSet<String> = fixture.objects("main~4").into_iter().collect();
+    let mut writer = PackWriter::new(GitHashType::Sha1);
+    for object in full.objects() {
+        let id = index.object_id(&object.hash).unwrap();
+        let canonical = storage.load_object(&id).await.unwrap().unwrap();
+        if have.contains(&object.hash.to_string()) {
+            writer.add_thin_base(object.hash, object.object_type, object.data.clone(), None);
+        } else {
+            let hash = writer.add_object(&canonical, index.deriver(), None).unwrap();
+            assert_eq!(hash, object.hash);
+        }
+    }
+    assert_eq!(writer.len(), full.len() - have.len());
+    let written = writer.write().unwrap();
+
+    let repo = bare_repo(&fixture, "receiver", GitHashType::Sha1);
+    fixture.git(&["push", "-q", repo.to_str().unwrap(), "main~4:refs/heads/main"]);
+    git_accepts(&fixture, &repo, &written.pack, true);
+
+    let head = fixture.git(&["rev-parse", "main"]);
+    fixture.git_in(&repo, &["update-ref", "refs/heads/main", &head]);
+    fixture.git_in(&repo, &["fsck", "--strict", "--no-dangling"]);
+}
+
+#[tokio::test]
+async fn test_build_pack_leaves_out_what_the_receiver_has() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_delta_history(12);
+
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+    let full = PackReader::new(GitHashType::Sha1)
+        .read(fixture.pack_objects("--all", &[]).as_slice())
+        .unwrap();
+    import_pack(storage.as_ref(), &mut index, &full, true).await.unwrap();
+    let id = |rev: &str| index.object_id(&GitHash::from_hex(&fixture.git(&["rev-parse", rev])).unwrap()).unwrap();
+    let (tip, base, tag) = (id("main"), id("main~4"), id("v1"));
+
+    // Exactly what `git rev-list --objects main ^main~4` lists
+    let written = build_pack(storage.as_ref(), &mut index, &[tip], &[base], GitHashType::Sha1, false).await.unwrap();
+    let pack = PackReader::new(GitHashType::Sha1).read(written.pack.as_slice()).unwrap();
+    let expected: HashSet<String> = fixture.git(&["rev-list", "--objects", "main", "^main~4"])
+        .lines()
+        .map(|line| line.split(' ').next().unwrap().to_string())
+        .collect();
+    assert_eq!(hash_set(&pack), expected);
+
+    // A tag of an excluded commit is sent alone
+    let written = build_pack(storage.as_ref(), &mut index, &[tag], &[tip], GitHashType::Sha1, false).await.unwrap();
+    let pack = PackReader::new(GitHashType::Sha1).read(written.pack.as_slice()).unwrap();
+    assert_eq!(hash_set(&pack), HashSet::from([fixture.git(&["rev-parse", "v1"])]));
+
+    // A thin pack may use the receiver's objects as bases; git completes it
+    let thin = build_pack(storage.as_ref(), &mut index, &[tip], &[base], GitHashType::Sha1, true).await.unwrap();
+    let repo = bare_repo(&fixture, "receiver", GitHashType::Sha1);
+    fixture.git(&["push", "-q", repo.to_str().unwrap(), "main~4:refs/heads/main"]);
+    git_accepts(&fixture, &repo, &thin.pack, true);
+}
+
+#[test]
+fn test_sha256_packs() {
+    let Some(fixture) = GitFixture::init(&["--object-format=sha256"]) else { return };
+    fixture.build_delta_history(6);
+
+    let data = fixture.pack_objects("--all", &["--delta-base-offset"]);
+    let pack = PackReader::new(GitHashType::Sha256).read(data.as_slice()).unwrap();
+    assert_eq!(pack.checksum().hash_type(), GitHashType::Sha256);
+    let expected: HashSet<String> = fixture.objects("--all").into_iter().collect();
+    assert_eq!(hash_set(&pack), expected);
+    assert_eq!(pack.index(), git_index_pack(&fixture, "sha256", &data));
+
+    let mut writer = PackWriter::new(GitHashType::Sha256);
+    for object in pack.objects() {
+        writer.add(object.hash, object.object_type, object.data.clone(), None);
+    }
+    let written = writer.write().unwrap();
+    let repo = bare_repo(&fixture, "sha256-target", GitHashType::Sha256);
+    git_accepts(&fixture, &repo, &written.pack, false);
+    assert_eq!(written.index, git_index_pack(&fixture, "sha256-written", &written.pack));
+}
@@ -0,0 +1,134 @@
+//! `git://` transport against `git daemon`, and the ssh transport through a script
+//! posing as ssh that runs the requested service locally
+
+mod common;
+
+use common::daemon::GitDaemon;
+use common::http::served_repo;
+use common::GitFixture;
+use gitnext_compat::GitHashIndex;
+use gitnext_core::{GitHash, GitHashType};
+use gitnext_protocol::{
+    build_pack, connect, connect_with_options, import_pack, resolve_thin_pack, ProtocolError, RefStatus, RefUpdate, TransportOptions,
+};
+use gitnext_storage::{MemoryStorage, Storage};
+use std::collections::HashSet;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+fn hash(hex: &str) -> GitHash {
+    GitHash::from_hex(hex).unwrap()
+}
+
+/// A fixture with history, pushed to a bare repository under the `served` directory
+fn served_history() -> Option<(GitFixture, PathBuf)> {
+    let fixture = GitFixture::init(&[])?;
+    fixture.build_delta_history(6);
+    fixture.git(&["branch", "side", "main~2"]);
+
+    let root = fixture.scratch("served");
+    let remote = served_repo(&fixture, &root, "remote.git", &[]);
+    fixture.git(&["push", "-q", remote.to_str().unwrap(), "--all"]);
+    fixture.git(&["push", "-q", remote.to_str().unwrap(), "--tags"]);
+    Some((fixture, remote))
+}
+
+/// Write a script that behaves like ssh: it logs its arguments, drops the options
+/// and the host, and runs the remote command locally in the fixture's environment
+fn fake_ssh(fixture: &GitFixture) -> PathBuf {
+    let path = fixture.scratch("fake-ssh");
+    let mut script = String::from("#!/bin/sh\n");
+    for (key, value) in fixture.environment() {
+        script.push_str(&format!("export {}='{}'\n", key, value));
+    }
+    script.push_str(&format!("echo \"$@\" >> '{}'\n", fixture.scratch("ssh.log").display()));
+    script.push_str("for command; do :; done\nexec sh -c \"$command\"\n");
+    std::fs::write(&path, script).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+/// Fetch main, then push it back to a new branch and delete side
+async fn fetch_and_push(fixture: &GitFixture, remote: &Path, url: &str, options: &TransportOptions) {
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+    let mut transport = connect_with_options(url, options).await.unwrap();
+    assert_eq!(transport.hash_type().await.unwrap(), GitHashType::Sha1);
+
+    let refs = transport.list_refs().await.unwrap();
+    let main = hash(&fixture.git(&["rev-parse", "main"]));
+    let side = hash(&fixture.git(&["rev-parse", "side"]));
+    assert_eq!(refs.iter().find(|r| r.name == "HEAD").unwrap().symref_target.as_deref(), Some("refs/heads/main"));
+    assert_eq!(refs.iter().find(|r| r.name == "refs/heads/main").unwrap().hash, main);
+
+    // Two commands over the same upload-pack connection
+    let pack = transport.fetch_packfile(&[side], &[]).await.unwrap();
+    import_pack(storage.as_ref(), &mut index, &pack, true).await.unwrap();
+    let mut pack = transport.fetch_packfile(&[main], &[side]).await.unwrap();
+    resolve_thin_pack(&mut pack, storage.as_ref(), &index).await.unwrap();
+    import_pack(storage.as_ref(), &mut index, &pack, true).await.unwrap();
+    let expected: HashSet<String> = fixture.objects("main").into_iter().collect();
+    assert!(expected.iter().all(|object| index.object_id(&hash(object)).is_some()));
+
+    let advertised = transport.list_push_refs().await.unwrap();
+    assert_eq!(advertised.iter().find(|r| r.name == "refs/heads/side").unwrap().hash, side);
+    let tip = index.object_id(&main).unwrap();
+    let written = build_pack(storage.as_ref(), &mut index, &[tip], &[tip], GitHashType::Sha1, true).await.unwrap();
+    let updates = vec![
+        RefUpdate { name: "refs/heads/pushed".to_string(), old: None, new: Some(main) },
+        RefUpdate { name: "refs/heads/side".to_string(), old: Some(side), new: None },
+    ];
+    let report = transport.send_packfile(&updates, Some(written.pack)).await.unwrap();
+    assert!(report.refs.iter().all(|(_, status)| *status == RefStatus::Ok), "{:?}", report);
+    assert_eq!(fixture.git_in(remote, &["rev-parse", "pushed"]), main.to_string());
+    assert!(fixture.git_in(remote, &["branch", "--list", "side"]).is_empty());
+
+    // Each push opens its own receive-pack connection
+    let advertised = transport.list_push_refs().await.unwrap();
+    assert!(advertised.iter().any(|r| r.name == "refs/heads/pushed"));
+    let updates = vec![RefUpdate { name: "refs/heads/pushed".to_string(), old: Some(main), new: None }];
+    transport.send_packfile(&updates, None).await.unwrap().into_result().unwrap();
+    assert!(fixture.git_in(remote, &["branch", "--list", "pushed"]).is_empty());
+}
+
+#[tokio::test]
+async fn test_fetch_and_push_over_git_daemon() {
+    let Some((fixture, remote)) = served_history() else { return };
+    let daemon = GitDaemon::start(&fixture, remote.parent().unwrap());
+    fetch_and_push(&fixture, &remote, &daemon.url("remote.git"), &TransportOptions::default()).await;
+
+    // The daemon hangs up with an error line for unknown repositories
+    let mut transport = connect(&daemon.url("missing.git")).await.unwrap();
+    assert!(matches!(transport.list_refs().await, Err(ProtocolError::Remote(_))));
+}
+
+#[tokio::test]
+async fn test_fetch_and_push_over_ssh_command() {
+    let Some((fixture, remote)) = served_history() else { return };
+    let options = TransportOptions { ssh_command: Some(fake_ssh(&fixture).display().to_string()) };
+    let url = format!("ssh://git@example.com:2222{}", remote.display());
+    fetch_and_push(&fixture, &remote, &url, &options).await;
+
+    let log = std::fs::read_to_string(fixture.scratch("ssh.log")).unwrap();
+    let first = log.lines().next().unwrap();
+    assert_eq!(first, format!("-p 2222 git@example.com git-upload-pack '{}'", remote.display()));
+    assert!(log.lines().any(|line| line.contains("git-receive-pack")));
+
+    // scp-like URLs take the same route
+    let url = format!("example.com:{}", remote.display());
+    let mut transport = connect_with_options(&url, &options).await.unwrap();
+    assert!(!transport.list_refs().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_ssh_command_failure_reports_stderr() {
+    let options = TransportOptions {
+        ssh_command: Some("echo 'Permission denied (publickey).' >&2; exit 255;".to_string()),
+    };
+    let mut transport = connect_with_options("git@example.com:repo.git", &options).await.unwrap();
+    match transport.list_refs().await {
+        Err(ProtocolError::Transport(message)) => assert!(message.contains("Permission denied"), "{}", message),
+        other => panic!("unexpected result {:?}", other.map(|_| ())),
+    }
+}
@@ -0,0 +1,162 @@
+//! Smart HTTP transport against `git http-backend`
+
+mod common;
+
+use common::http::{served_repo, HttpBackend};
+use common::GitFixture;
+use gitnext_compat::GitHashIndex;
+use gitnext_core::{GitHash, GitHashType};
+use gitnext_protocol::{
+    build_pack, connect, import_pack, resolve_thin_pack, ProtocolError, RefStatus, RefUpdate,
+};
+use gitnext_storage::{MemoryStorage, Storage};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+fn hash(hex: &str) -> GitHash {
+    GitHash::from_hex(hex).unwrap()
+}
+
+/// A fixture with history, pushed to a served bare repository
+async fn served_history(init_args: &[&str]) -> Option<(GitFixture, HttpBackend, String)> {
+    let fixture = GitFixture::init(init_args)?;
+    fixture.build_delta_history(8);
+    fixture.git(&["branch", "side", "main~3"]);
+
+    let root = fixture.scratch("served");
+    let remote = served_repo(&fixture, &root, "remote.git", init_args);
+    fixture.git(&["push", "-q", remote.to_str().unwrap(), "--all"]);
+    fixture.git(&["push", "-q", remote.to_str().unwrap(), "--tags"]);
+
+    let backend = HttpBackend::start(&fixture, &root).await;
+    let url = backend.url("remote.git");
+    Some((fixture, backend, url))
+}
+
+#[tokio::test]
+async fn test_list_refs_over_http() {
+    let Some((fixture, _backend, url)) = served_history(&[]).await else { return };
+    let mut transport = connect(&url).await.unwrap();
+    assert_eq!(transport.hash_type().await.unwrap(), GitHashType::Sha1);
+
+    let refs = transport.list_refs().await.unwrap();
+    let find = |name: &str| refs.iter().find(|r| r.name == name).unwrap_or_else(|| panic!("{} missing", name));
+
+    assert_eq!(find("HEAD").symref_target.as_deref(), Some("refs/heads/main"));
+    assert_eq!(find("refs/heads/main").hash, hash(&fixture.git(&["rev-parse", "main"])));
+    assert_eq!(find("refs/heads/side").hash, hash(&fixture.git(&["rev-parse", "side"])));
+    let tag = find("refs/tags/v1");
+    assert_eq!(tag.hash, hash(&fixture.git(&["rev-parse", "v1"])));
+    assert_eq!(tag.peeled, Some(hash(&fixture.git(&["rev-parse", "v1^{commit}"]))));
+}
+
+#[tokio::test]
+async fn test_fetch_full_then_incremental() {
+    let Some((fixture, backend, url)) = served_history(&[]).await else { return };
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+
+    // Clone-style fetch of side, with nothing to offer
+    let side = hash(&fixture.git(&["rev-parse", "side"]));
+    let mut transport = connect(&url).await.unwrap();
+    let pack = transport.fetch_packfile(&[side], &[]).await.unwrap();
+    let expected: HashSet<String> = fixture.objects("side").into_iter().collect();
+    let received: HashSet<String> = pack.objects().iter().map(|o| o.hash.to_string()).collect();
+    assert_eq!(received, expected);
+    import_pack(storage.as_ref(), &mut index, &pack, true).await.unwrap();
+
+    // Offering side (and some unknown commits) gets only what main adds, as a thin pack
+    let main = hash(&fixture.git(&["rev-parse", "main"]));
+    let mut haves = vec![side];
+    haves.extend((0..40).map(|i| GitHash::from_git_bytes(format!("unknown {}", i).as_bytes(), GitHashType::Sha1)));
+    let mut pack = transport.fetch_packfile(&[main, main], &haves).await.unwrap();
+    assert!(!pack.missing_bases().is_empty());
+    resolve_thin_pack(&mut pack, storage.as_ref(), &index).await.unwrap();
+
+    let received: HashSet<String> = pack.objects().iter().map(|o| o.hash.to_string()).collect();
+    let all: HashSet<String> = fixture.objects("main").into_iter().collect();
+    assert!(received.is_subset(&all));
+    assert!(all.difference(&expected).all(|object| received.contains(object)));
+    assert!(received.len() < all.len() - expected.len() / 2);
+    import_pack(storage.as_ref(), &mut index, &pack, true).await.unwrap();
+    for object in fixture.objects("main") {
+        assert!(index.object_id(&hash(&object)).is_some(), "{} missing", object);
+    }
+
+    // Unknown wants are refused by the server
+    let bogus = GitHash::from_git_bytes(b"bogus", GitHashType::Sha1);
+    assert!(matches!(transport.fetch_packfile(&[bogus], &[]).await, Err(ProtocolError::Remote(_))));
+    drop(backend);
+}
+
+#[tokio::test]
+async fn test_push_over_http() {
+    let Some((fixture, backend, url)) = served_history(&[]).await else { return };
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let mut index = GitHashIndex::open(storage.clone()).await.unwrap();
+
+    // Take the full history locally
+    let mut transport = connect(&url).await.unwrap();
+    let main = hash(&fixture.git(&["rev-parse", "main"]));
+    let pack = transport.fetch_packfile(&[main], &[]).await.unwrap();
+    import_pack(storage.as_ref(), &mut index, &pack, true).await.unwrap();
+
+    // Make the remote lag behind, then push main and a new branch back
+    let remote = fixture.scratch("served").join("remote.git");
+    let behind = hash(&fixture.git(&["rev-parse", "main~4"]));
+    fixture.git_in(&remote, &["update-ref", "refs/heads/main", &behind.to_string()]);
+    fixture.git_in(&remote, &["reflog", "expire", "--expire=now", "--all"]);
+    fixture.git_in(&remote, &["gc", "-q", "--prune=now"]);
+
+    let advertised = transport.list_push_refs().await.unwrap();
+    assert_eq!(advertised.iter().find(|r| r.name == "refs/heads/main").unwrap().hash, behind);
+
+    let tip = index.object_id(&main).unwrap();
+    let base = index.object_id(&behind).unwrap();
+    let written = build_pack(storage.as_ref(), &mut index, &[tip], &[base], GitHashType::Sha1, true).await.unwrap();
+    let updates = vec![
+        RefUpdate { name: "refs/heads/main".to_string(), old: Some(behind), new: Some(main) },
+        RefUpdate { name: "refs/heads/pushed".to_string(), old: None, new: Some(main) },
+        RefUpdate { name: "refs/heads/side".to_string(), old: Some(hash(&fixture.git(&["rev-parse", "side"]))), new: None },
+    ];
+    let report = transport.send_packfile(&updates, Some(written.pack)).await.unwrap();
+    assert!(report.refs.iter().all(|(_, status)| *status == RefStatus::Ok), "{:?}", report);
+
+    assert_eq!(fixture.git_in(&remote, &["rev-parse", "main"]), main.to_string());
+    assert_eq!(fixture.git_in(&remote, &["rev-parse", "pushed"]), main.to_string());
+    assert!(fixture.git_in(&remote, &["branch", "--list", "side"]).is_empty());
+    fixture.git_in(&remote, &["fsck", "--strict", "--no-dangling"]);
+
+    // A stale old value is refused per ref
+    let empty = build_pack(storage.as_ref(), &mut index, &[], &[], GitHashType::Sha1, false).await.unwrap();
+    let updates = vec![RefUpdate { name: "refs/heads/main".to_string(), old: Some(behind), new: Some(behind) }];
+    let report = transport.send_packfile(&updates, Some(empty.pack)).await.unwrap();
+    assert!(matches!(&report.refs[0].1, RefStatus::Rejected(_)));
+    assert!(matches!(report.into_result(), Err(ProtocolError::Rejected { .. })));
+    drop(backend);
+}
+
+#[tokio::test]
+async fn test_sha256_fetch_over_http() {
+    let Some((fixture, _backend, url)) = served_history(&["--object-format=sha256"]).await else { return };
+    let mut transport = connect(&url).await.unwrap();
+    assert_eq!(transport.hash_type().await.unwrap(), GitHashType::Sha256);
+
+    let refs = transport.list_refs().await.unwrap();
+    let main = refs.iter().find(|r| r.name == "refs/heads/main").unwrap().hash;
+    assert_eq!(main.hash_type(), GitHashType::Sha256);
+    let pack = transport.fetch_packfile(&[main], &[]).await.unwrap();
+    assert_eq!(pack.len(), fixture.objects("main").len());
+}
+
+#[tokio::test]
+async fn test_connect_errors() {
+    assert!(matches!(connect("ftp://example.com/repo.git").await, Err(ProtocolError::UnsupportedUrl(_))));
+
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    let root = fixture.scratch("served");
+    std::fs::create_dir_all(&root).unwrap();
+    let backend = HttpBackend::start(&fixture, &root).await;
+    let mut transport = connect(&backend.url("missing.git")).await.unwrap();
+    assert!(matches!(transport.list_refs().await, Err(ProtocolError::Transport(_))));
+}
@@ -0,0 +1,344 @@
+//! Line-level blame
+//!
+//! As in Git, the lines of the file are passed from each commit to its parents:
+//! those a parent has too move on to it, and those no parent has were changed by
+//! the commit. Commits are visited newest first in generation order, so lines
+//! reaching one commit by several paths are handled together. The commit graph
+//! supplies parents, and the commit index trees and changed-path filters, so a
+//! commit whose filter rules the path out hands every line to its first parent
+//! without a tree being loaded. When the path is missing from a parent, rename
+//! detection finds where the file came from.
+
+use crate::filter::{entry_at, CommitIndex};
+use crate::graph::CommitGraphIndex;
+use crate::{QueryError, Result};
+use gitnext_core::{FileMode, GitObject, ObjectId, Signature};
+use gitnext_merge::diff::diff;
+use gitnext_merge::{split_lines, DiffHunk, RenameOptions, TreeChange};
+use gitnext_storage::{Storage, StorageError};
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
+
+/// How blame walks history
+#[derive(Debug, Clone)]
+pub struct BlameOptions {
+    /// Follow only first parents, as `--first-parent`
+    pub first_parent: bool,
+    /// Lines differing only in whitespace count as unchanged, as `-w`
+    pub ignore_whitespace: bool,
+    /// Commits never blamed for a line they changed; the line goes to the line
+    /// at the same place in the parent's version when there is one
+    /// (`--ignore-rev`)
+    pub ignore_revisions: Vec<ObjectId>,
+    /// Rename detection for files missing from a parent; `None` stops there
+    pub renames: Option<RenameOptions>,
+}
+
+impl Default for BlameOptions {
+    fn default() -> Self {
+        Self {
+            first_parent: false,
+            ignore_whitespace: false,
+            ignore_revisions: Vec::new(),
+            renames: Some(RenameOptions::default()),
+        }
+    }
+}
+
+/// Consecutive lines last changed by one commit
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlameRange {
+    /// First line in the blamed file, from 1
+    pub start: usize,
+    pub lines: usize,
+    pub commit: ObjectId,
+    pub author: Signature,
+    /// Path of the file in `commit`
+    pub path: String,
+    /// First line in `commit`'s version of the file, from 1
+    pub original_start: usize,
+}
+
+/// Who last changed each line of a file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Blame {
+    pub ranges: Vec<BlameRange>,
+}
+
+impl Blame {
+    /// The range holding `line`, counting from 1
+    pub fn line(&self, line: usize) -> Option<&BlameRange> {
+        self.ranges.iter().find(|r| r.start <= line && line < r.start + r.lines)
+    }
+}
+
+/// Lines of one commit's version of the file still to blame: (line in this
+/// version, line in the blamed file), from 0
+type Lines = Vec<(usize, usize)>;
+
+/// Blame `path` as of the indexed commit at `position`
+pub(crate) async fn blame(
+    storage: &dyn Storage,
+    graph: &CommitGraphIndex,
+    index: &CommitIndex,
+    position: usize,
+    path: &str,
+    options: &BlameOptions,
+) -> Result<Blame> {
+    let path = path.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>().join("/");
+    let mut blobs = Blobs::default();
+    let Some(blob) = file_at(storage, index, position, &path).await? else {
+        return Err(QueryError::PathNotFound(path));
+    };
+    let count = split_lines(blobs.load(storage, blob).await?).len();
+
+    // (commit, path) -> lines waiting there, and the queue of such suspects
+    let mut pending: HashMap<(usize, String), (ObjectId, Lines)> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+    pending.insert((position, path.clone()), (blob, (0..count).map(|l| (l, l)).collect()));
+    queue.push((graph.order_key(position), path));
+    // (line in the blamed file, commit, path, line in its version)
+    let mut blamed: Vec<(usize, usize, String, usize)> = Vec::with_capacity(count);
+
+    while let Some(((_, _, suspect), path)) = queue.pop() {
+        let Some((blob, mut lines)) = pending.remove(&(suspect, path.clone())) else {
+            continue;
+        };
+        lines.sort_unstable();
+        let ignored = options.ignore_revisions.contains(&graph.nodes()[suspect].id);
+        let parents = &graph.nodes()[suspect].parents;
+        let parents = if options.first_parent { &parents[..parents.len().min(1)] } else { &parents[..] };
+
+        for (n, &parent) in parents.iter().enumerate() {
+            if lines.is_empty() {
+                break;
+            }
+            let parent = parent as usize;
+            // Unchanged from the first parent: everything moves on untouched
+            let (parent_path, parent_blob) = if n == 0 && !index.entries()[suspect].may_change(&path) {
+                (path.clone(), blob)
+            } else {
+                match source(storage, index, suspect, parent, &path, options).await? {
+                    Some(found) => found,
+                    None => continue,
+                }
+            };
+
+            let mapping = if parent_blob == blob {
+                None
+            } else {
+                let old = normalized(blobs.load(storage, parent_blob).await?, options.ignore_whitespace);
+                let new = normalized(blobs.load(storage, blob).await?, options.ignore_whitespace);
+                Some(diff(&old, &new))
+            };
+            let mut passed = Vec::new();
+            lines.retain(|&(line, final_line)| {
+                let target = match &mapping {
+                    None => Some(line),
+                    Some(regions) => parent_line(regions, line, ignored && n == 0),
+                };
+                if let Some(target) = target {
+                    passed.push((target, final_line));
+                }
+                target.is_none()
+            });
+            if !passed.is_empty() {
+                let key = (parent, parent_path.clone());
+                if !pending.contains_key(&key) {
+                    queue.push((graph.order_key(parent), parent_path));
+                }
+                pending.entry(key).or_insert_with(|| (parent_blob, Vec::new())).1.extend(passed);
+            }
+        }
+        blamed.extend(lines.into_iter().map(|(line, final_line)| (final_line, suspect, path.clone(), line)));
+    }
+
+    blamed.sort_unstable_by_key(|b| b.0);
+    let mut authors: HashMap<usize, Signature> = HashMap::new();
+    let mut ranges: Vec<BlameRange> = Vec::new();
+    for (final_line, commit, path, line) in blamed {
+        if let Some(last) = ranges.last_mut() {
+            let same = last.commit == graph.nodes()[commit].id && last.path == path;
+            if same && last.start + last.lines == final_line + 1 && last.original_start + last.lines == line + 1 {
+                last.lines += 1;
+                continue;
+            }
+        }
+        let id = graph.nodes()[commit].id;
+        let author = match authors.get(&commit) {
+            Some(author) => author.clone(),
+            None => {
+                let author = match storage.load_object(&id).await? {
+                    Some(GitObject::Commit(commit)) => commit.author,
+                    _ => return Err(QueryError::NotACommit(id)),
+                };
+                authors.insert(commit, author.clone());
+                author
+            }
+        };
+        ranges.push(BlameRange { start: final_line + 1, lines: 1, commit: id, author, path, original_start: line + 1 });
+    }
+    Ok(Blame { ranges })
+}
+
+/// The blob at `path` in the commit at `position`, if it is a file there
+async fn file_at(storage: &dyn Storage, index: &CommitIndex, position: usize, path: &str) -> Result<Option<ObjectId>> {
+    let components: Vec<&str> = path.split('/').collect();
+    let tree = index.entries()[position].tree;
+    Ok(match entry_at(storage, &mut HashMap::new(), tree, &components).await? {
+        Some((FileMode::Tree, _)) | None => None,
+        Some((_, id)) => Some(id),
+    })
+}
+
+/// Path and blob in `parent` that the file at `path` in `suspect` came from:
+/// the same path, or the source of a rename or copy
+async fn source(
+    storage: &dyn Storage,
+    index: &CommitIndex,
+    suspect: usize,
+    parent: usize,
+    path: &str,
+    options: &BlameOptions,
+) -> Result<Option<(String, ObjectId)>> {
+    if let Some(blob) = file_at(storage, index, parent, path).await? {
+        return Ok(Some((path.to_string(), blob)));
+    }
+    let Some(renames) = &options.renames else {
+        return Ok(None);
+    };
+    let (old, new) = (index.entries()[parent].tree, index.entries()[suspect].tree);
+    let changes = gitnext_merge::diff_trees(storage, Some(&old), Some(&new), Some(renames)).await?;
+    Ok(changes.into_iter().find_map(|change| match change {
+        TreeChange::Renamed { from, to, old, .. } | TreeChange::Copied { from, to, old, .. } if to == path => {
+            Some((from, old.id))
+        }
+        _ => None,
+    }))
+}
+
+/// Where `line` of the new version is in the old one: unchanged lines map
+/// across, and with `guess` so do changed ones, to the same offset in the
+/// changed region when it is long enough
+fn parent_line(regions: &[DiffHunk], line: usize, guess: bool) -> Option<usize> {
+    let mut shift = 0isize;
+    for region in regions {
+        if line < region.new.start {
+            break;
+        }
+        if line < region.new.end {
+            let offset = line - region.new.start;
+            return (guess && offset < region.old.len()).then(|| region.old.start + offset);
+        }
+        shift = region.old.end as isize - region.new.end as isize;
+    }
+    Some((line as isize + shift) as usize)
+}
+
+/// Lines to compare, without whitespace when it is ignored
+fn normalized(content: &[u8], ignore_whitespace: bool) -> Vec<Vec<u8>> {
+    split_lines(content).into_iter()
+        .map(|line| match ignore_whitespace {
+            true => line.iter().copied().filter(|b| !b.is_ascii_whitespace()).collect(),
+            false => line.to_vec(),
+        })
+        .collect()
+}
+
+/// Blob contents loaded so far
+#[derive(Default)]
+struct Blobs(HashMap<ObjectId, Vec<u8>>);
+
+impl Blobs {
+    async fn load(&mut self, storage: &dyn Storage, id: ObjectId) -> Result<&[u8]> {
+        if let Entry::Vacant(slot) = self.0.entry(id) {
+            let content = match storage.load_object(&id).await? {
+                Some(GitObject::Blob(blob)) => blob.content.unwrap_or_default().to_vec(),
+                _ => return Err(StorageError::ObjectNotFound { id }.into()),
+            };
+            slot.insert(content);
+        }
+        Ok(&self.0[&id])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::tests::{commit, files};
+    use crate::QueryEngine;
+    use gitnext_storage::MemoryStorage;
+    use std::sync::Arc;
+
+    /// (commit, path, original line) for each line, from ranges that must cover
+    /// the file in order
+    fn lines(blame: &Blame) -> Vec<(ObjectId, &str, usize)> {
+        let mut lines = Vec::new();
+        for range in &blame.ranges {
+            assert_eq!(range.start, lines.len() + 1);
+            for i in 0..range.lines {
+                lines.push((range.commit, range.path.as_str(), range.original_start + i));
+            }
+        }
+        lines
+    }
+
+    #[tokio::test]
+    async fn test_blame() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let s = storage.as_ref();
+        let c1 = commit(s, &[], &files(&[("src/a.rs", "one\ntwo\nthree\n")]), "Alice", 1, "add\n").await;
+        let c2 = commit(s, &[c1], &files(&[("src/a.rs", "one\nTWO\nthree\nfour\n")]), "Bob", 2, "edit\n").await;
+        let c3_files = files(&[("lib/b.rs", "one\nTWO\nthree\nfour\n"), ("x", "x")]);
+        let c3 = commit(s, &[c2], &c3_files, "Carol", 3, "move\n").await;
+        let c4_files = files(&[("lib/b.rs", "one\n  TWO\nthree\nfour\n"), ("x", "x")]);
+        let c4 = commit(s, &[c3], &c4_files, "Dave", 4, "indent\n").await;
+        let topic = commit(s, &[c2], &files(&[("src/a.rs", "one\nTWO\nthree\nfour\nfive\n")]), "Eve", 5, "five\n").await;
+        let merge_files = files(&[("lib/b.rs", "one\n  TWO\nthree\nfour\nfive\n"), ("x", "x")]);
+        let merge = commit(s, &[c4, topic], &merge_files, "Dave", 6, "merge\n").await;
+        s.update_ref("refs/heads/main", &merge).await.unwrap();
+        let engine = QueryEngine::open(storage.clone()).await.unwrap();
+
+        let renames = BlameOptions::default();
+        let blame = engine.blame(&c4, "lib/b.rs", &renames).await.unwrap();
+        assert_eq!(lines(&blame), vec![(c1, "src/a.rs", 1), (c4, "lib/b.rs", 2), (c1, "src/a.rs", 3), (c2, "src/a.rs", 4)]);
+        assert_eq!(blame.line(4).unwrap().author.name, "Bob");
+        assert_eq!(blame.ranges.len(), 4);
+
+        let whitespace = BlameOptions { ignore_whitespace: true, ..renames.clone() };
+        let blame = engine.blame(&c4, "/lib/b.rs", &whitespace).await.unwrap();
+        assert_eq!(blame.line(2).map(|r| (r.commit, r.original_start)), Some((c2, 2)));
+        let ignoring = BlameOptions { ignore_revisions: vec![c4], ..renames.clone() };
+        let blame = engine.blame(&c4, "lib/b.rs", &ignoring).await.unwrap();
+        assert_eq!(blame.line(2).map(|r| (r.commit, r.original_start)), Some((c2, 2)));
+
+        // Without rename detection the history stops at the move
+        let stop = BlameOptions { renames: None, ..Default::default() };
+        let blame = engine.blame(&c4, "lib/b.rs", &stop).await.unwrap();
+        let commits: Vec<ObjectId> = lines(&blame).into_iter().map(|(c, _, _)| c).collect();
+        assert_eq!(commits, vec![c3, c4, c3, c3]);
+
+        // The topic branch added the last line, to the file before it moved
+        let blame = engine.blame(&merge, "lib/b.rs", &renames).await.unwrap();
+        assert_eq!(lines(&blame)[4], (topic, "src/a.rs", 5));
+        assert_eq!(lines(&blame)[1], (c4, "lib/b.rs", 2));
+        let first_parent = BlameOptions { first_parent: true, ..renames.clone() };
+        let blame = engine.blame(&merge, "lib/b.rs", &first_parent).await.unwrap();
+        assert_eq!(lines(&blame)[4], (merge, "lib/b.rs", 5));
+        assert_eq!(lines(&blame)[0], (c1, "src/a.rs", 1));
+
+        assert!(matches!(engine.blame(&c4, "lib", &renames).await, Err(QueryError::PathNotFound(p)) if p == "lib"));
+        assert!(matches!(engine.blame(&c1, "lib/b.rs", &renames).await, Err(QueryError::PathNotFound(_))));
+    }
+
+    #[test]
+    fn test_parent_line() {
+        // old: a b c d, new: a X c Y Z d
+        let regions = vec![DiffHunk { old: 1..2, new: 1..2 }, DiffHunk { old: 3..3, new: 3..5 }];
+        let mapped: Vec<Option<usize>> = (0..6).map(|l| parent_line(&regions, l, false)).collect();
+        assert_eq!(mapped, vec![Some(0), None, Some(2), None, None, Some(3)]);
+        let guessed: Vec<Option<usize>> = (0..6).map(|l| parent_line(&regions, l, true)).collect();
+        assert_eq!(guessed, vec![Some(0), Some(1), Some(2), None, None, Some(3)]);
+    }
+}
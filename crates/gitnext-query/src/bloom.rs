@@ -0,0 +1,191 @@
+//! Changed-path Bloom filters
+//!
+//! Like Git's commit-graph changed-path filters: each commit gets a Bloom filter
+//! of the paths that differ from its first parent (every path, for a root
+//! commit), with the directories leading to each of them. A path the filter
+//! rules out certainly has the same entry as in the first parent, so history
+//! limited to that path can skip the commit without loading any trees. Commits
+//! changing more than `MAX_CHANGED_PATHS` paths get no filter, as Git does.
+//!
+//! Filters use 10 bits per path and 7 probes derived from the path's BLAKE3
+//! hash, for about a 1% false-positive rate.
+
+use gitnext_core::{GitObject, ObjectId, ObjectType};
+use gitnext_storage::{Storage, StorageError};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Most changed paths a filter is built for
+pub const MAX_CHANGED_PATHS: usize = 512;
+
+const BITS_PER_PATH: usize = 10;
+const PROBES: u64 = 7;
+
+/// Bloom filter over a commit's changed paths
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChangedPathFilter {
+    bits: Vec<u8>,
+}
+
+impl ChangedPathFilter {
+    /// Filter holding `paths`
+    pub fn new<'a>(paths: impl ExactSizeIterator<Item = &'a str>) -> Self {
+        let mut filter = Self { bits: vec![0; (paths.len() * BITS_PER_PATH).div_ceil(8)] };
+        for path in paths {
+            for bit in filter.probes(path) {
+                filter.bits[bit / 8] |= 1 << (bit % 8);
+            }
+        }
+        filter
+    }
+
+    /// False when `path` certainly did not change
+    pub fn may_contain(&self, path: &str) -> bool {
+        !self.bits.is_empty() && self.probes(path).all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    fn probes(&self, path: &str) -> impl Iterator<Item = usize> {
+        let hash = blake3::hash(path.as_bytes());
+        let bytes = hash.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[..8].try_into().expect("8 bytes"));
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().expect("8 bytes"));
+        let size = (self.bits.len() * 8).max(1) as u64;
+        (0..PROBES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % size) as usize)
+    }
+}
+
+/// Filter for a commit with tree `tree` whose first parent has `parent`; `None`
+/// when too many paths changed or a tree is missing from storage
+pub async fn changed_path_filter(
+    storage: &dyn Storage,
+    parent: Option<&ObjectId>,
+    tree: &ObjectId,
+) -> Result<Option<ChangedPathFilter>, StorageError> {
+    let mut paths = Vec::new();
+    if !changed_paths(storage, parent.copied(), Some(*tree), &mut paths).await? {
+        return Ok(None);
+    }
+    Ok(Some(ChangedPathFilter::new(paths.iter().map(String::as_str))))
+}
+
+/// Append to `paths` the paths whose entries differ between the two trees,
+/// directories included; false once there are too many, or if a tree is missing
+async fn changed_paths(
+    storage: &dyn Storage,
+    old: Option<ObjectId>,
+    new: Option<ObjectId>,
+    paths: &mut Vec<String>,
+) -> Result<bool, StorageError> {
+    // (tree on the old side, tree on the new side, path) still to compare
+    let mut pending = vec![(old, new, String::new())];
+    while let Some((old, new, prefix)) = pending.pop() {
+        let (Some(old), Some(new)) = (entries(storage, old).await?, entries(storage, new).await?) else {
+            return Ok(false);
+        };
+        let mut names: BTreeMap<&str, (Option<&Entry>, Option<&Entry>)> = BTreeMap::new();
+        for (name, entry) in &old {
+            names.entry(name).or_default().0 = Some(entry);
+        }
+        for (name, entry) in &new {
+            names.entry(name).or_default().1 = Some(entry);
+        }
+        for (name, (old, new)) in names {
+            if old == new {
+                continue;
+            }
+            let path = if prefix.is_empty() { name.to_string() } else { format!("{}/{}", prefix, name) };
+            let subtree = |entry: Option<&Entry>| entry.filter(|e| e.is_tree).map(|e| e.id);
+            if subtree(old).is_some() || subtree(new).is_some() {
+                pending.push((subtree(old), subtree(new), path.clone()));
+            }
+            paths.push(path);
+            if paths.len() > MAX_CHANGED_PATHS {
+                return Ok(false);
+            }
+        }
+    }
+    Ok(true)
+}
+
+#[derive(PartialEq, Eq)]
+struct Entry {
+    id: ObjectId,
+    mode: gitnext_core::FileMode,
+    is_tree: bool,
+}
+
+/// Entries of `tree` by name; `None` when it is not in storage
+async fn entries(
+    storage: &dyn Storage,
+    tree: Option<ObjectId>,
+) -> Result<Option<BTreeMap<String, Entry>>, StorageError> {
+    let Some(id) = tree else {
+        return Ok(Some(BTreeMap::new()));
+    };
+    Ok(match storage.load_object(&id).await? {
+        Some(GitObject::Tree(tree)) => Some(tree.entries.into_iter()
+            .map(|e| (e.name, Entry { id: e.hash, mode: e.mode, is_tree: e.entry_type == ObjectType::Tree }))
+            .collect()),
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::tests::build_tree;
+    use gitnext_storage::MemoryStorage;
+
+    async fn store(storage: &dyn Storage, files: &[(&str, &str)]) -> ObjectId {
+        let files = files.iter().map(|(path, content)| (path.to_string(), content.to_string())).collect();
+        let mut objects = Vec::new();
+        let tree = build_tree(&files, &mut objects);
+        for (id, object) in objects {
+            storage.store_object(&id, &object).await.unwrap();
+        }
+        tree
+    }
+
+    #[test]
+    fn test_filter_has_no_false_negatives() {
+        let paths: Vec<String> = (0..200).map(|i| format!("src/module{}/file{}.rs", i % 7, i)).collect();
+        let filter = ChangedPathFilter::new(paths.iter().map(String::as_str));
+        assert!(paths.iter().all(|path| filter.may_contain(path)));
+        let false_positives = (0..10_000).filter(|i| filter.may_contain(&format!("other/{}", i))).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+
+        let empty = ChangedPathFilter::new(std::iter::empty());
+        assert!(!empty.may_contain("anything"));
+    }
+
+    #[tokio::test]
+    async fn test_changed_paths() {
+        let storage = MemoryStorage::new();
+        let parent = store(&storage, &[("README", "hi"), ("src/a.rs", "a"), ("src/deep/b.rs", "b"), ("docs/x.md", "x")]).await;
+        let tree = store(&storage, &[("README", "hi"), ("src/a.rs", "a"), ("src/deep/b.rs", "b2"), ("new/y.md", "y")]).await;
+
+        let mut paths = Vec::new();
+        assert!(changed_paths(&storage, Some(parent), Some(tree), &mut paths).await.unwrap());
+        paths.sort();
+        assert_eq!(paths, vec!["docs", "docs/x.md", "new", "new/y.md", "src", "src/deep", "src/deep/b.rs"]);
+
+        let filter = changed_path_filter(&storage, Some(&parent), &tree).await.unwrap().unwrap();
+        assert!(paths.iter().all(|path| filter.may_contain(path)));
+        let root = changed_path_filter(&storage, None, &parent).await.unwrap().unwrap();
+        assert!(root.may_contain("README") && root.may_contain("src/deep"));
+        let unchanged = changed_path_filter(&storage, Some(&tree), &tree).await.unwrap().unwrap();
+        assert!(!unchanged.may_contain("README"));
+    }
+
+    #[tokio::test]
+    async fn test_too_many_changed_paths() {
+        let storage = MemoryStorage::new();
+        let names: Vec<String> = (0..MAX_CHANGED_PATHS).map(|i| format!("dir/{}", i)).collect();
+        let files: Vec<(&str, &str)> = names.iter().map(|name| (name.as_str(), "x")).collect();
+        let tree = store(&storage, &files).await;
+        // The files and their directory
+        assert!(changed_path_filter(&storage, None, &tree).await.unwrap().is_none());
+        let fewer = store(&storage, &files[1..]).await;
+        assert!(changed_path_filter(&storage, None, &fewer).await.unwrap().is_some());
+    }
+}
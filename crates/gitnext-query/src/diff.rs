@@ -0,0 +1,669 @@
+//! Tree and commit diffs (Requirements 5.4)
+//!
+//! Trees are compared with `gitnext_merge::diff_trees`, which never descends into
+//! subtrees whose ids match, and changed text files with its Myers line diff.
+//! The result is plain data that renders as a unified patch, `--stat` or
+//! `--numstat`, and serializes for the WASM bindings and the server.
+
+use crate::{QueryError, Result};
+use gitnext_core::{FileMode, GitHash, GitHashType, GitHasher, GitObject, ObjectId};
+use gitnext_merge::content::is_binary;
+use gitnext_merge::{diff_lines, split_lines, DiffHunk, RenameOptions, TreeChange, Version};
+use gitnext_storage::Storage;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+
+/// How diffs are computed
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    /// Unchanged lines shown around each change (Git's `-U`)
+    pub context: usize,
+    /// Rename and copy detection; `None` reports renames as a delete and an add
+    pub renames: Option<RenameOptions>,
+    /// Hash type of the Git ids that patches name file versions by
+    pub hash_type: GitHashType,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self { context: 3, renames: Some(RenameOptions::default()), hash_type: GitHashType::Sha1 }
+    }
+}
+
+/// What happened to a file; scores are similarities in percent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeKind {
+    Added,
+    Deleted,
+    Modified,
+    Renamed { score: u8 },
+    Copied { score: u8 },
+}
+
+/// One line of a hunk, with its line ending if it had one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiffLine {
+    Context(Vec<u8>),
+    Added(Vec<u8>),
+    Removed(Vec<u8>),
+}
+
+/// A run of changes with the context around it; starts count from 1, or are
+/// the line before the hunk when its side is empty, as in patch headers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+/// One changed file; the old side is missing for additions and the new side for
+/// deletions
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileDiff {
+    pub kind: ChangeKind,
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+    pub old_mode: Option<FileMode>,
+    pub new_mode: Option<FileMode>,
+    pub old_id: Option<ObjectId>,
+    pub new_id: Option<ObjectId>,
+    /// Git ids of the two versions, for the `index` line of patches
+    pub old_git_id: Option<GitHash>,
+    pub new_git_id: Option<GitHash>,
+    /// Either side holds binary content; binary files have no hunks
+    pub binary: bool,
+    pub hunks: Vec<Hunk>,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+impl FileDiff {
+    /// The path in the new tree, or the deleted path
+    pub fn path(&self) -> &str {
+        self.new_path.as_deref().or(self.old_path.as_deref()).unwrap_or_default()
+    }
+
+    /// The path as `--stat` shows it: `old => new` for renames and copies
+    pub fn display_path(&self) -> String {
+        match (&self.old_path, &self.new_path) {
+            (Some(old), Some(new)) if old != new => format!("{} => {}", old, new),
+            _ => self.path().to_string(),
+        }
+    }
+
+    /// The mode changed, with the file type or just its executable bit
+    pub fn mode_changed(&self) -> bool {
+        matches!((self.old_mode, self.new_mode), (Some(old), Some(new)) if old != new)
+    }
+}
+
+/// Totals of a diff, as on the last line of `--stat`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DiffSummary {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+/// The changed files between two trees, in path order
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TreeDiff {
+    pub files: Vec<FileDiff>,
+}
+
+impl TreeDiff {
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    pub fn summary(&self) -> DiffSummary {
+        DiffSummary {
+            files_changed: self.files.len(),
+            insertions: self.files.iter().map(|f| f.insertions).sum(),
+            deletions: self.files.iter().map(|f| f.deletions).sum(),
+        }
+    }
+
+    /// The diff as a Git-style unified patch
+    pub fn patch(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for file in &self.files {
+            write_file_patch(&mut out, file);
+        }
+        out
+    }
+
+    /// `--numstat`: insertions, deletions and path per line, `-` for binary counts
+    pub fn numstat(&self) -> String {
+        let mut out = String::new();
+        for file in &self.files {
+            if file.binary {
+                let _ = writeln!(out, "-\t-\t{}", file.display_path());
+            } else {
+                let _ = writeln!(out, "{}\t{}\t{}", file.insertions, file.deletions, file.display_path());
+            }
+        }
+        out
+    }
+
+    /// `--stat`: a line per file with a bar of `+` and `-` scaled to fit `width`
+    /// columns, then the summary
+    pub fn stat(&self, width: usize) -> String {
+        let names: Vec<String> = self.files.iter().map(FileDiff::display_path).collect();
+        let name_width = names.iter().map(|n| n.chars().count()).max().unwrap_or(0);
+        let max_change = self.files.iter().map(|f| f.insertions + f.deletions).max().unwrap_or(0);
+        let count_width = max_change.to_string().len().max(if self.files.iter().any(|f| f.binary) { 3 } else { 0 });
+        // " name | count bar"
+        let bar_width = width.saturating_sub(name_width + count_width + 4).max(1);
+        let scale = |n: usize| match n {
+            0 => 0,
+            _ if max_change <= bar_width => n,
+            _ => 1 + n * (bar_width - 1) / max_change,
+        };
+
+        let mut out = String::new();
+        for (file, name) in self.files.iter().zip(&names) {
+            if file.binary {
+                let _ = writeln!(out, " {:<name_width$} | {:>count_width$}", name, "Bin");
+                continue;
+            }
+            let total = file.insertions + file.deletions;
+            let (plus, minus) = (scale(file.insertions), scale(file.deletions));
+            let line = format!(" {:<name_width$} | {:>count_width$} {}{}", name, total, "+".repeat(plus), "-".repeat(minus));
+            let _ = writeln!(out, "{}", line.trim_end());
+        }
+
+        let summary = self.summary();
+        let plural = |n: usize, word: &str| format!("{} {}{}", n, word, if n == 1 { "" } else { "s" });
+        let _ = write!(out, " {} changed", plural(summary.files_changed, "file"));
+        if summary.insertions > 0 {
+            let _ = write!(out, ", {}(+)", plural(summary.insertions, "insertion"));
+        }
+        if summary.deletions > 0 {
+            let _ = write!(out, ", {}(-)", plural(summary.deletions, "deletion"));
+        }
+        out.push('\n');
+        out
+    }
+}
+
+/// Differences from the tree `old` to the tree `new`; `None` stands for an empty
+/// tree
+pub async fn diff_trees(
+    storage: &dyn Storage,
+    old: Option<&ObjectId>,
+    new: Option<&ObjectId>,
+    options: &DiffOptions,
+) -> Result<TreeDiff> {
+    let changes = gitnext_merge::diff_trees(storage, old, new, options.renames.as_ref()).await?;
+    let mut files = Vec::with_capacity(changes.len());
+    for change in changes {
+        let (kind, old, new) = match change {
+            TreeChange::Added { path, new } => (ChangeKind::Added, None, Some((path, new))),
+            TreeChange::Deleted { path, old } => (ChangeKind::Deleted, Some((path, old)), None),
+            TreeChange::Modified { path, old, new } => (ChangeKind::Modified, Some((path.clone(), old)), Some((path, new))),
+            TreeChange::Renamed { from, to, old, new, score } => {
+                (ChangeKind::Renamed { score }, Some((from, old)), Some((to, new)))
+            }
+            TreeChange::Copied { from, to, old, new, score } => {
+                (ChangeKind::Copied { score }, Some((from, old)), Some((to, new)))
+            }
+        };
+        files.push(diff_file(storage, kind, old, new, options).await?);
+    }
+    Ok(TreeDiff { files })
+}
+
+/// Differences from the commit `old` to the commit `new`
+pub async fn diff_commits(
+    storage: &dyn Storage,
+    old: &ObjectId,
+    new: &ObjectId,
+    options: &DiffOptions,
+) -> Result<TreeDiff> {
+    let (old, new) = (commit_tree(storage, old).await?, commit_tree(storage, new).await?);
+    diff_trees(storage, Some(&old), Some(&new), options).await
+}
+
+async fn commit_tree(storage: &dyn Storage, id: &ObjectId) -> Result<ObjectId> {
+    match storage.load_object(id).await? {
+        Some(GitObject::Commit(commit)) => Ok(commit.tree),
+        _ => Err(QueryError::NotACommit(*id)),
+    }
+}
+
+async fn diff_file(
+    storage: &dyn Storage,
+    kind: ChangeKind,
+    old: Option<(String, Version)>,
+    new: Option<(String, Version)>,
+    options: &DiffOptions,
+) -> Result<FileDiff> {
+    let old_content = content(storage, old.as_ref().map(|(_, v)| v)).await?;
+    let new_content = content(storage, new.as_ref().map(|(_, v)| v)).await?;
+    let git_id = |version: Option<&(String, Version)>, content: &[u8]| {
+        version.map(|(_, version)| git_id(version, content, options.hash_type))
+    };
+    let binary = is_binary(&old_content) || is_binary(&new_content);
+    let mut file = FileDiff {
+        kind,
+        old_mode: old.as_ref().map(|(_, v)| v.mode),
+        new_mode: new.as_ref().map(|(_, v)| v.mode),
+        old_id: old.as_ref().map(|(_, v)| v.id),
+        new_id: new.as_ref().map(|(_, v)| v.id),
+        old_git_id: git_id(old.as_ref(), &old_content),
+        new_git_id: git_id(new.as_ref(), &new_content),
+        old_path: old.map(|(path, _)| path),
+        new_path: new.map(|(path, _)| path),
+        binary,
+        hunks: Vec::new(),
+        insertions: 0,
+        deletions: 0,
+    };
+    if !binary && file.old_id != file.new_id {
+        let (old_lines, new_lines) = (split_lines(&old_content), split_lines(&new_content));
+        let regions = diff_lines(&old_lines, &new_lines);
+        file.insertions = regions.iter().map(|r| r.new.len()).sum();
+        file.deletions = regions.iter().map(|r| r.old.len()).sum();
+        file.hunks = hunks(&old_lines, &new_lines, &regions, options.context);
+    }
+    Ok(file)
+}
+
+async fn content(storage: &dyn Storage, version: Option<&Version>) -> Result<Vec<u8>> {
+    let Some(version) = version else {
+        return Ok(Vec::new());
+    };
+    // Submodules show as the commit they point at, as in Git
+    if version.mode == FileMode::Gitlink {
+        return Ok(format!("Subproject commit {}\n", version.id.gitlink_commit()).into_bytes());
+    }
+    match storage.load_object(&version.id).await? {
+        Some(GitObject::Blob(blob)) => Ok(blob.content.unwrap_or_default().to_vec()),
+        _ => Err(gitnext_storage::StorageError::ObjectNotFound { id: version.id }.into()),
+    }
+}
+
+/// The Git id of a file version: the hash of its blob, or the commit a submodule
+/// points at
+fn git_id(version: &Version, content: &[u8], hash_type: GitHashType) -> GitHash {
+    if version.mode == FileMode::Gitlink {
+        return version.id.gitlink_commit();
+    }
+    let mut hasher = GitHasher::new(hash_type);
+    hasher.update(format!("blob {}\0", content.len()).as_bytes());
+    hasher.update(content);
+    hasher.finalize()
+}
+
+/// Hunks turning the text `old` into `new`, with `context` lines around changes;
+/// lines count from 1 at the start of each text
+pub fn diff_text(old: &[u8], new: &[u8], context: usize) -> Vec<Hunk> {
+    let (old_lines, new_lines) = (split_lines(old), split_lines(new));
+    hunks(&old_lines, &new_lines, &diff_lines(&old_lines, &new_lines), context)
+}
+
+/// Group changed regions into hunks, merging those whose contexts would touch
+fn hunks(old: &[&[u8]], new: &[&[u8]], regions: &[DiffHunk], context: usize) -> Vec<Hunk> {
+    let mut groups: Vec<&[DiffHunk]> = Vec::new();
+    let mut start = 0;
+    for i in 1..=regions.len() {
+        if i == regions.len() || regions[i].old.start - regions[i - 1].old.end > 2 * context {
+            groups.push(&regions[start..i]);
+            start = i;
+        }
+    }
+
+    groups.into_iter().map(|group| {
+        let (first, last) = (&group[0], &group[group.len() - 1]);
+        // Lines between regions are the same on both sides
+        let before = context.min(first.old.start);
+        let after = context.min(old.len() - last.old.end);
+        let (old_start, new_start) = (first.old.start - before, first.new.start - before);
+        let mut lines = Vec::new();
+        let mut position = old_start;
+        for region in group {
+            lines.extend(old[position..region.old.start].iter().map(|l| DiffLine::Context(l.to_vec())));
+            lines.extend(old[region.old.clone()].iter().map(|l| DiffLine::Removed(l.to_vec())));
+            lines.extend(new[region.new.clone()].iter().map(|l| DiffLine::Added(l.to_vec())));
+            position = region.old.end;
+        }
+        lines.extend(old[position..last.old.end + after].iter().map(|l| DiffLine::Context(l.to_vec())));
+
+        let old_lines = last.old.end + after - old_start;
+        let new_lines = last.new.end + after - new_start;
+        Hunk {
+            old_start: if old_lines == 0 { old_start } else { old_start + 1 },
+            old_lines,
+            new_start: if new_lines == 0 { new_start } else { new_start + 1 },
+            new_lines,
+            lines,
+        }
+    }).collect()
+}
+
+fn mode(mode: FileMode) -> String {
+    format!("{:06o}", mode as u32)
+}
+
+fn short_id(id: Option<GitHash>) -> String {
+    match id {
+        Some(id) => id.to_string()[..7].to_string(),
+        None => "0000000".to_string(),
+    }
+}
+
+fn write_file_patch(out: &mut Vec<u8>, file: &FileDiff) {
+    let old_path = file.old_path.as_deref().unwrap_or(file.path());
+    let new_path = file.new_path.as_deref().unwrap_or(file.path());
+    let mut header = format!("diff --git a/{} b/{}\n", old_path, new_path);
+    match (file.kind, file.old_mode, file.new_mode) {
+        (ChangeKind::Added, _, Some(new)) => {
+            let _ = writeln!(header, "new file mode {}", mode(new));
+        }
+        (ChangeKind::Deleted, Some(old), _) => {
+            let _ = writeln!(header, "deleted file mode {}", mode(old));
+        }
+        (ChangeKind::Renamed { score }, _, _) => {
+            let _ = write!(header, "similarity index {}%\nrename from {}\nrename to {}\n", score, old_path, new_path);
+        }
+        (ChangeKind::Copied { score }, _, _) => {
+            let _ = write!(header, "similarity index {}%\ncopy from {}\ncopy to {}\n", score, old_path, new_path);
+        }
+        _ => {}
+    }
+    if file.mode_changed() {
+        let (old, new) = (file.old_mode.expect("mode changed"), file.new_mode.expect("mode changed"));
+        let _ = write!(header, "old mode {}\nnew mode {}\n", mode(old), mode(new));
+    }
+    if file.old_id != file.new_id {
+        let _ = write!(header, "index {}..{}", short_id(file.old_git_id), short_id(file.new_git_id));
+        match (file.old_mode, file.new_mode) {
+            (Some(old), Some(new)) if old == new => {
+                let _ = writeln!(header, " {}", mode(old));
+            }
+            _ => header.push('\n'),
+        }
+        let old_name = if file.old_id.is_some() { format!("a/{}", old_path) } else { "/dev/null".to_string() };
+        let new_name = if file.new_id.is_some() { format!("b/{}", new_path) } else { "/dev/null".to_string() };
+        if file.binary {
+            let _ = writeln!(header, "Binary files {} and {} differ", old_name, new_name);
+        } else if !file.hunks.is_empty() {
+            let _ = write!(header, "--- {}\n+++ {}\n", old_name, new_name);
+        }
+    }
+    out.extend_from_slice(header.as_bytes());
+
+    for hunk in &file.hunks {
+        let range = |start: usize, lines: usize| match lines {
+            1 => start.to_string(),
+            _ => format!("{},{}", start, lines),
+        };
+        let header = format!("@@ -{} +{} @@\n", range(hunk.old_start, hunk.old_lines), range(hunk.new_start, hunk.new_lines));
+        out.extend_from_slice(header.as_bytes());
+        for line in &hunk.lines {
+            let (marker, text) = match line {
+                DiffLine::Context(text) => (b' ', text),
+                DiffLine::Added(text) => (b'+', text),
+                DiffLine::Removed(text) => (b'-', text),
+            };
+            out.push(marker);
+            out.extend_from_slice(text);
+            if !text.ends_with(b"\n") {
+                out.extend_from_slice(b"\n\\ No newline at end of file\n");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::tests::build_tree;
+    use gitnext_core::{Blob, ObjectType, Tree, TreeEntry};
+    use gitnext_storage::MemoryStorage;
+    use proptest::prelude::*;
+    use std::collections::BTreeMap;
+
+    async fn store_files(storage: &dyn Storage, files: &BTreeMap<String, String>) -> ObjectId {
+        let mut objects = Vec::new();
+        let tree = build_tree(files, &mut objects);
+        for (id, object) in objects {
+            storage.store_object(&id, &object).await.unwrap();
+        }
+        tree
+    }
+
+    async fn store_blob(storage: &dyn Storage, content: &[u8]) -> ObjectId {
+        let blob = GitObject::Blob(Blob::new(bytes::Bytes::copy_from_slice(content)));
+        let id = blob.canonical_hash();
+        storage.store_object(&id, &blob).await.unwrap();
+        id
+    }
+
+    async fn store_tree(storage: &dyn Storage, entries: &[(&str, FileMode, ObjectId)]) -> ObjectId {
+        let tree = GitObject::Tree(Tree::new(entries.iter()
+            .map(|(name, mode, hash)| TreeEntry { name: name.to_string(), mode: *mode, hash: *hash, entry_type: ObjectType::Blob, raw_name: None })
+            .collect()));
+        let id = tree.canonical_hash();
+        storage.store_object(&id, &tree).await.unwrap();
+        id
+    }
+
+    fn numbered(lines: std::ops::Range<usize>) -> String {
+        lines.map(|i| format!("line {}\n", i)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_unified_patch() {
+        let storage = MemoryStorage::new();
+        let old_text = numbered(1..21);
+        let new_text = old_text.replace("line 2\n", "line two\n").replace("line 18\n", "").replace("line 20\n", "line 20");
+        let old = store_tree(&storage, &[
+            ("gone.txt", FileMode::Normal, store_blob(&storage, b"bye\n").await),
+            ("run.sh", FileMode::Normal, store_blob(&storage, b"echo\n").await),
+            ("text.txt", FileMode::Normal, store_blob(&storage, old_text.as_bytes()).await),
+        ]).await;
+        let new = store_tree(&storage, &[
+            ("image.png", FileMode::Normal, store_blob(&storage, b"\x89PNG\0\0").await),
+            ("new.txt", FileMode::Normal, store_blob(&storage, b"hello\n").await),
+            ("run.sh", FileMode::Executable, store_blob(&storage, b"echo\n").await),
+            ("text.txt", FileMode::Normal, store_blob(&storage, new_text.as_bytes()).await),
+        ]).await;
+
+        let options = DiffOptions { context: 1, renames: None, ..Default::default() };
+        let diff = diff_trees(&storage, Some(&old), Some(&new), &options).await.unwrap();
+        let paths: Vec<&str> = diff.files.iter().map(FileDiff::path).collect();
+        assert_eq!(paths, vec!["gone.txt", "image.png", "new.txt", "run.sh", "text.txt"]);
+        let kinds: Vec<ChangeKind> = diff.files.iter().map(|f| f.kind).collect();
+        use ChangeKind::*;
+        assert_eq!(kinds, vec![Deleted, Added, Added, Modified, Modified]);
+        assert!(diff.files[1].binary && diff.files[1].hunks.is_empty());
+        assert!(diff.files[3].mode_changed() && diff.files[3].hunks.is_empty());
+
+        let text = &diff.files[4];
+        assert_eq!((text.insertions, text.deletions), (2, 3));
+        assert_eq!(text.hunks.len(), 2);
+        // Versions are named by the ids `git hash-object` gives them
+        let expected = "diff --git a/gone.txt b/gone.txt\n\
+             deleted file mode 100644\n\
+             index b023018..0000000\n\
+             --- a/gone.txt\n\
+             +++ /dev/null\n\
+             @@ -1 +0,0 @@\n\
+             -bye\n\
+             diff --git a/image.png b/image.png\n\
+             new file mode 100644\n\
+             index 0000000..41e3e49\n\
+             Binary files /dev/null and b/image.png differ\n\
+             diff --git a/new.txt b/new.txt\n\
+             new file mode 100644\n\
+             index 0000000..ce01362\n\
+             --- /dev/null\n\
+             +++ b/new.txt\n\
+             @@ -0,0 +1 @@\n\
+             +hello\n\
+             diff --git a/run.sh b/run.sh\n\
+             old mode 100644\n\
+             new mode 100755\n\
+             diff --git a/text.txt b/text.txt\n\
+             index c4352f8..e52d22b 100644\n\
+             --- a/text.txt\n\
+             +++ b/text.txt\n\
+             @@ -1,3 +1,3 @@\n \
+             line 1\n\
+             -line 2\n\
+             +line two\n \
+             line 3\n\
+             @@ -17,4 +17,3 @@\n \
+             line 17\n\
+             -line 18\n \
+             line 19\n\
+             -line 20\n\
+             +line 20\n\
+             \\ No newline at end of file\n";
+        assert_eq!(String::from_utf8(diff.patch()).unwrap(), expected);
+
+        assert_eq!(diff.numstat(), "0\t1\tgone.txt\n-\t-\timage.png\n1\t0\tnew.txt\n0\t0\trun.sh\n2\t3\ttext.txt\n");
+        assert_eq!(
+            diff.stat(80),
+            " gone.txt  |   1 -\n image.png | Bin\n new.txt   |   1 +\n run.sh    |   0\n text.txt  |   5 ++---\n \
+             5 files changed, 3 insertions(+), 4 deletions(-)\n"
+        );
+        assert_eq!(diff.summary(), DiffSummary { files_changed: 5, insertions: 3, deletions: 4 });
+    }
+
+    #[tokio::test]
+    async fn test_renames_and_commits() {
+        let storage = MemoryStorage::new();
+        let body = numbered(1..30);
+        let old_files = BTreeMap::from([("src/old.rs".to_string(), body.clone()), ("same".to_string(), "x\n".to_string())]);
+        let new_files = BTreeMap::from([("src/new.rs".to_string(), body + "line 30\n"), ("same".to_string(), "x\n".to_string())]);
+        let (old, new) = (store_files(&storage, &old_files).await, store_files(&storage, &new_files).await);
+
+        let diff = diff_trees(&storage, Some(&old), Some(&new), &DiffOptions::default()).await.unwrap();
+        assert_eq!(diff.files.len(), 1);
+        let file = &diff.files[0];
+        assert_eq!(file.kind, ChangeKind::Renamed { score: 96 });
+        assert_eq!(file.display_path(), "src/old.rs => src/new.rs");
+        assert_eq!((file.insertions, file.deletions), (1, 0));
+        let patch = String::from_utf8(diff.patch()).unwrap();
+        assert!(patch.starts_with(
+            "diff --git a/src/old.rs b/src/new.rs\nsimilarity index 96%\nrename from src/old.rs\nrename to src/new.rs\nindex "
+        ));
+        assert!(patch.contains("--- a/src/old.rs\n+++ b/src/new.rs\n@@ -27,3 +27,4 @@\n"));
+
+        let unpaired = diff_trees(&storage, Some(&old), Some(&new), &DiffOptions { renames: None, ..Default::default() })
+            .await.unwrap();
+        assert_eq!(unpaired.files.iter().map(|f| f.kind).collect::<Vec<_>>(), vec![ChangeKind::Added, ChangeKind::Deleted]);
+        assert!(diff_trees(&storage, Some(&old), Some(&old), &DiffOptions::default()).await.unwrap().is_empty());
+
+        let signature = gitnext_core::Signature {
+            name: "Test".to_string(),
+            email: "test@example.com".to_string(),
+            timestamp: 0,
+            timezone_offset: 0,
+            raw: None,
+        };
+        let mut ids = Vec::new();
+        for tree in [old, new] {
+            let commit = GitObject::Commit(gitnext_core::Commit {
+                tree,
+                parents: Vec::new(),
+                author: signature.clone(),
+                committer: signature.clone(),
+                extra_headers: Vec::new(),
+                message: "commit\n".to_string(),
+                raw_message: None,
+            });
+            ids.push(commit.canonical_hash());
+            storage.store_object(&commit.canonical_hash(), &commit).await.unwrap();
+        }
+        assert_eq!(diff_commits(&storage, &ids[0], &ids[1], &DiffOptions::default()).await.unwrap(), diff);
+        assert!(matches!(
+            diff_commits(&storage, &old, &ids[1], &DiffOptions::default()).await,
+            Err(QueryError::NotACommit(id)) if id == old
+        ));
+    }
+
+    /// Rebuild the new content of a file from the old one and its hunks
+    fn apply(old: &[u8], hunks: &[Hunk]) -> Vec<u8> {
+        let old_lines = split_lines(old);
+        let mut out = Vec::new();
+        let mut position = 0;
+        for hunk in hunks {
+            let start = if hunk.old_lines == 0 { hunk.old_start } else { hunk.old_start - 1 };
+            for line in &old_lines[position..start] {
+                out.extend_from_slice(line);
+            }
+            position = start;
+            for line in &hunk.lines {
+                match line {
+                    DiffLine::Context(text) => {
+                        assert_eq!(old_lines[position], &text[..]);
+                        out.extend_from_slice(text);
+                        position += 1;
+                    }
+                    DiffLine::Removed(text) => {
+                        assert_eq!(old_lines[position], &text[..]);
+                        position += 1;
+                    }
+                    DiffLine::Added(text) => out.extend_from_slice(text),
+                }
+            }
+        }
+        for line in &old_lines[position..] {
+            out.extend_from_slice(line);
+        }
+        out
+    }
+
+    fn file_sets() -> impl Strategy<Value = BTreeMap<String, String>> {
+        let content = prop::collection::vec(prop::sample::select(vec!["a\n", "b\n", "c\n", "d\n", "e"]), 0..12)
+            .prop_map(|lines| lines.concat());
+        let path = prop::sample::select(vec!["a", "b", "dir/c", "dir/d", "dir/sub/e", "f/g"]);
+        prop::collection::btree_map(path.prop_map(str::to_string), content, 0..6)
+    }
+
+    proptest! {
+        /// Property 18: Diff Operation Correctness
+        /// For any two trees, the diff between them lists exactly the files whose
+        /// contents differ, and applying each file's hunks to its old content gives
+        /// its new content.
+        /// **Validates: Requirements 5.4**
+        #[test]
+        fn prop_diff_reproduces_new_tree(old in file_sets(), new in file_sets(), context in 0usize..4) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                let storage = MemoryStorage::new();
+                let (old_tree, new_tree) = (store_files(&storage, &old).await, store_files(&storage, &new).await);
+                let options = DiffOptions { context, renames: None, ..Default::default() };
+                let diff = diff_trees(&storage, Some(&old_tree), Some(&new_tree), &options).await.unwrap();
+
+                let changed: Vec<&String> = old.keys().chain(new.keys())
+                    .collect::<std::collections::BTreeSet<_>>()
+                    .into_iter()
+                    .filter(|path| old.get(*path) != new.get(*path))
+                    .collect();
+                prop_assert_eq!(diff.files.iter().map(FileDiff::path).collect::<Vec<_>>(), changed);
+
+                for file in &diff.files {
+                    let before = old.get(file.path()).map(String::as_str).unwrap_or_default();
+                    let after = new.get(file.path()).map(String::as_str).unwrap_or_default();
+                    prop_assert_eq!(apply(before.as_bytes(), &file.hunks), after.as_bytes().to_vec());
+                    let added = file.hunks.iter().flat_map(|h| &h.lines).filter(|l| matches!(l, DiffLine::Added(_))).count();
+                    prop_assert_eq!(added, file.insertions);
+                    // Hunks never touch: their contexts would have joined them
+                    let start = |h: &Hunk| if h.old_lines == 0 { h.old_start } else { h.old_start - 1 };
+                    for pair in file.hunks.windows(2) {
+                        prop_assert!(start(&pair[1]) > start(&pair[0]) + pair[0].old_lines);
+                    }
+                }
+                Ok(())
+            })?;
+        }
+    }
+}
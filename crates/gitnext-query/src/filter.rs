@@ -0,0 +1,693 @@
+//! Commit filters (Requirements 5.3)
+//!
+//! `CommitIndex` holds what filters look at for every commit in the commit graph,
+//! at the same positions, with author and commit-date maps so those predicates
+//! are lookups rather than walks. It follows the graph: since the graph only
+//! grows at the end, the commits past the index's length are the ones to add.
+//! The entries are kept in chunks named by `refs/gitnext/commit-index` and the
+//! maps are rebuilt from them on load, so saving writes only the chunks that
+//! grew.
+//!
+//! Path predicates compare the entry at the path in a commit's tree with the one
+//! in its parents' trees, so they load trees but never commits, and skip commits
+//! whose changed-path filter rules the path out.
+
+use crate::bloom::{changed_path_filter, ChangedPathFilter};
+use crate::graph::CommitGraphIndex;
+use crate::{QueryError, Result};
+use gitnext_core::{FileMode, GitObject, ObjectId, ObjectType};
+use gitnext_storage::metadata::{load_list, save_list, Bincode, Persisted};
+use gitnext_storage::{Storage, StorageError};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+
+/// Prefix of the internal references naming the chunks of the commit index
+pub const COMMIT_INDEX_REF: &str = "refs/gitnext/commit-index";
+
+/// What filters need to know about one commit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitEntry {
+    pub id: ObjectId,
+    pub tree: ObjectId,
+    /// Author as `Name <email>`
+    pub author: String,
+    /// Committer time, as in the graph
+    pub timestamp: i64,
+    pub message: String,
+    /// Paths changed from the first parent; `None` when there were too many
+    pub changed_paths: Option<ChangedPathFilter>,
+}
+
+impl CommitEntry {
+    /// False when the commit certainly has the same entry at `path` as its
+    /// first parent
+    pub fn may_change(&self, path: &str) -> bool {
+        let path = path.split('/').filter(|c| !c.is_empty()).collect::<Vec<_>>().join("/");
+        path.is_empty() || self.changed_paths.as_ref().is_none_or(|filter| filter.may_contain(&path))
+    }
+}
+
+/// Filter data for the commits of a `CommitGraphIndex`, by graph position
+#[derive(Debug, Clone, Default)]
+pub struct CommitIndex {
+    entries: Vec<CommitEntry>,
+    /// Positions by lowercased author
+    by_author: BTreeMap<String, Vec<u32>>,
+    /// Positions by committer time
+    by_date: BTreeMap<i64, Vec<u32>>,
+    /// Number of entries already persisted
+    saved: usize,
+    persisted: Persisted,
+}
+
+/// Indexes are equal when they hold the same entries; the maps follow from them
+impl PartialEq for CommitIndex {
+    fn eq(&self, other: &Self) -> bool {
+        self.entries == other.entries
+    }
+}
+
+impl Eq for CommitIndex {}
+
+impl CommitIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries in graph order
+    pub fn entries(&self) -> &[CommitEntry] {
+        &self.entries
+    }
+
+    /// Load the persisted index, if one was saved
+    pub async fn load(storage: &dyn Storage) -> std::result::Result<Option<Self>, StorageError> {
+        let (entries, persisted): (Vec<CommitEntry>, _) = load_list::<Bincode, _>(storage, COMMIT_INDEX_REF).await?;
+        if entries.is_empty() {
+            return Ok(None);
+        }
+        let mut index = Self::new();
+        for entry in entries {
+            index.push(entry);
+        }
+        index.saved = index.entries.len();
+        index.persisted = persisted;
+        Ok(Some(index))
+    }
+
+    /// Persist the entries added since the index was loaded or last saved.
+    /// Returns false, saving nothing, when someone else saved the index in the
+    /// meantime; the index should then be loaded again and synced.
+    pub async fn save(&mut self, storage: &dyn Storage) -> std::result::Result<bool, StorageError> {
+        if self.saved == self.entries.len() {
+            return Ok(true);
+        }
+        if !save_list::<Bincode, _>(storage, COMMIT_INDEX_REF, &self.entries, self.saved..self.entries.len(), &mut self.persisted).await? {
+            return Ok(false);
+        }
+        self.saved = self.entries.len();
+        Ok(true)
+    }
+
+    fn push(&mut self, entry: CommitEntry) {
+        let position = self.entries.len() as u32;
+        self.by_author.entry(entry.author.to_lowercase()).or_default().push(position);
+        self.by_date.entry(entry.timestamp).or_default().push(position);
+        self.entries.push(entry);
+    }
+
+    /// Take in the entries others saved since the index was loaded or last saved.
+    /// Entries not saved yet are dropped; `sync` derives them again.
+    pub async fn refresh(&mut self, storage: &dyn Storage) -> std::result::Result<(), StorageError> {
+        if !self.persisted.is_current(storage, COMMIT_INDEX_REF).await? {
+            *self = Self::load(storage).await?.unwrap_or_default();
+        }
+        Ok(())
+    }
+
+    /// Add the commits `graph` has and this index lacks, returning how many were
+    /// added. An index that does not match the start of the graph is rebuilt.
+    pub async fn sync(
+        &mut self,
+        storage: &dyn Storage,
+        graph: &CommitGraphIndex,
+    ) -> std::result::Result<usize, StorageError> {
+        let nodes = graph.nodes();
+        let matches = self.entries.len() <= nodes.len()
+            && self.entries.last().is_none_or(|entry| entry.id == nodes[self.entries.len() - 1].id);
+        if !matches {
+            let persisted = std::mem::take(&mut self.persisted);
+            *self = Self { persisted, ..Self::default() };
+        }
+        let before = self.entries.len();
+        for node in &nodes[before..] {
+            let commit = match storage.load_object(&node.id).await? {
+                Some(GitObject::Commit(commit)) => commit,
+                _ => return Err(StorageError::ObjectNotFound { id: node.id }),
+            };
+            let parent_tree = node.parents.first().map(|&p| self.entries[p as usize].tree);
+            let changed_paths = changed_path_filter(storage, parent_tree.as_ref(), &commit.tree).await?;
+            self.push(CommitEntry {
+                id: node.id,
+                tree: commit.tree,
+                author: format!("{} <{}>", commit.author.name, commit.author.email),
+                timestamp: node.timestamp,
+                message: commit.message,
+                changed_paths,
+            });
+        }
+        Ok(self.entries.len() - before)
+    }
+}
+
+/// A condition on commits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Predicate {
+    /// Author name or email contains the text, ignoring case
+    Author(String),
+    /// Committed within the bounds, inclusive, in seconds since the epoch
+    Date { since: Option<i64>, until: Option<i64> },
+    /// Message matches the regular expression
+    Message(String),
+    /// Changes the file or directory at the path compared with every parent; a
+    /// root commit changes every path it has
+    Path(String),
+    And(Vec<Predicate>),
+    Or(Vec<Predicate>),
+    Not(Box<Predicate>),
+}
+
+/// Order of filtered commits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommitOrder {
+    /// Children before parents, newer first otherwise
+    #[default]
+    Topological,
+    /// Newest commit time first, whatever the topology
+    Date,
+}
+
+/// Which commits `QueryEngine::filter_commits` returns
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CommitFilter {
+    /// `b`, `a..b` (reachable from `b` but not `a`), with HEAD for a missing side;
+    /// every ref when `None`
+    pub range: Option<String>,
+    /// Condition commits must meet; all of them when `None`
+    pub predicate: Option<Predicate>,
+    pub order: CommitOrder,
+    /// Most commits to return
+    pub limit: Option<usize>,
+}
+
+type Positions<'a> = Pin<Box<dyn Future<Output = Result<Vec<usize>>> + Send + 'a>>;
+
+/// Evaluates predicates against the indexes
+pub(crate) struct Evaluator<'a> {
+    pub storage: &'a dyn Storage,
+    pub graph: &'a CommitGraphIndex,
+    pub index: &'a CommitIndex,
+}
+
+impl<'a> Evaluator<'a> {
+    /// The `candidates` that meet `predicate`, in their original order
+    pub fn eval(&'a self, predicate: &'a Predicate, candidates: Vec<usize>) -> Positions<'a> {
+        Box::pin(async move {
+            Ok(match predicate {
+                Predicate::Author(text) => {
+                    let text = text.to_lowercase();
+                    let matching: HashSet<usize> = self.index.by_author.iter()
+                        .filter(|(author, _)| author.contains(&text))
+                        .flat_map(|(_, positions)| positions.iter().map(|&p| p as usize))
+                        .collect();
+                    candidates.into_iter().filter(|p| matching.contains(p)).collect()
+                }
+                Predicate::Date { since, until } => {
+                    let (since, until) = (since.unwrap_or(i64::MIN), until.unwrap_or(i64::MAX));
+                    if since > until {
+                        return Ok(Vec::new());
+                    }
+                    let matching: HashSet<usize> = self.index.by_date.range(since..=until)
+                        .flat_map(|(_, positions)| positions.iter().map(|&p| p as usize))
+                        .collect();
+                    candidates.into_iter().filter(|p| matching.contains(p)).collect()
+                }
+                Predicate::Message(pattern) => {
+                    let regex = Regex::new(pattern).map_err(|e| QueryError::InvalidPattern(e.to_string()))?;
+                    candidates.into_iter().filter(|&p| regex.is_match(&self.index.entries[p].message)).collect()
+                }
+                Predicate::Path(path) => self.touching(path, candidates).await?,
+                Predicate::And(predicates) => {
+                    let mut candidates = candidates;
+                    for predicate in predicates {
+                        candidates = self.eval(predicate, candidates).await?;
+                    }
+                    candidates
+                }
+                Predicate::Or(predicates) => {
+                    let mut matching = HashSet::new();
+                    for predicate in predicates {
+                        let rest: Vec<usize> = candidates.iter().copied().filter(|p| !matching.contains(p)).collect();
+                        matching.extend(self.eval(predicate, rest).await?);
+                    }
+                    candidates.into_iter().filter(|p| matching.contains(p)).collect()
+                }
+                Predicate::Not(predicate) => {
+                    let matching: HashSet<usize> = self.eval(predicate, candidates.clone()).await?.into_iter().collect();
+                    candidates.into_iter().filter(|p| !matching.contains(p)).collect()
+                }
+            })
+        })
+    }
+
+    async fn touching(&self, path: &str, candidates: Vec<usize>) -> Result<Vec<usize>> {
+        let components: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+        // Entries at the path by tree, as parents are usually looked at again
+        let mut cache = HashMap::new();
+        let mut found = Vec::new();
+        for position in candidates {
+            if !self.index.entries[position].may_change(path) {
+                continue;
+            }
+            let tree = self.index.entries[position].tree;
+            let entry = entry_at(self.storage, &mut cache, tree, &components).await?;
+            let parents = &self.graph.nodes()[position].parents;
+            let mut touched = !parents.is_empty() || entry.is_some();
+            for &parent in parents {
+                let parent_tree = self.index.entries[parent as usize].tree;
+                if entry_at(self.storage, &mut cache, parent_tree, &components).await? == entry {
+                    touched = false;
+                    break;
+                }
+            }
+            if touched {
+                found.push(position);
+            }
+        }
+        Ok(found)
+    }
+}
+
+/// Mode and id of the entry at `components` below `tree`, if there is one
+pub(crate) async fn entry_at(
+    storage: &dyn Storage,
+    cache: &mut HashMap<ObjectId, Option<(FileMode, ObjectId)>>,
+    tree: ObjectId,
+    components: &[&str],
+) -> Result<Option<(FileMode, ObjectId)>> {
+    if let Some(entry) = cache.get(&tree) {
+        return Ok(*entry);
+    }
+    let mut entry = Some((FileMode::Tree, tree));
+    for component in components {
+        let Some((FileMode::Tree, id)) = entry else {
+            entry = None;
+            break;
+        };
+        entry = match storage.load_object(&id).await? {
+            Some(GitObject::Tree(tree)) => tree.entries.iter()
+                .find(|e| e.name == *component)
+                .map(|e| (if e.entry_type == ObjectType::Tree { FileMode::Tree } else { e.mode }, e.hash)),
+            _ => return Err(StorageError::ObjectNotFound { id }.into()),
+        };
+    }
+    cache.insert(tree, entry);
+    Ok(entry)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::QueryEngine;
+    use gitnext_core::{Blob, Commit, Signature, Tree, TreeEntry};
+    use gitnext_storage::MemoryStorage;
+    use proptest::prelude::*;
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+
+    /// Tree objects for `files`, children first; returns the root tree's id
+    pub(crate) fn build_tree(files: &BTreeMap<String, String>, objects: &mut Vec<(ObjectId, GitObject)>) -> ObjectId {
+        let mut blobs = Vec::new();
+        let mut dirs: BTreeMap<&str, BTreeMap<String, String>> = BTreeMap::new();
+        for (path, content) in files {
+            match path.split_once('/') {
+                Some((dir, rest)) => {
+                    dirs.entry(dir).or_default().insert(rest.to_string(), content.clone());
+                }
+                None => blobs.push((path, content)),
+            }
+        }
+        let mut entries = Vec::new();
+        for (name, content) in blobs {
+            let blob = GitObject::Blob(Blob::new(bytes::Bytes::from(content.clone())));
+            let hash = blob.canonical_hash();
+            objects.push((hash, blob));
+            entries.push(TreeEntry { name: name.clone(), mode: FileMode::Normal, hash, entry_type: ObjectType::Blob, raw_name: None });
+        }
+        for (name, files) in dirs {
+            let hash = build_tree(&files, objects);
+            entries.push(TreeEntry { name: name.to_string(), mode: FileMode::Tree, hash, entry_type: ObjectType::Tree, raw_name: None });
+        }
+        let tree = GitObject::Tree(Tree::new(entries));
+        let id = tree.canonical_hash();
+        objects.push((id, tree));
+        id
+    }
+
+    pub(crate) async fn commit(
+        storage: &dyn Storage,
+        parents: &[ObjectId],
+        files: &BTreeMap<String, String>,
+        author: &str,
+        time: i64,
+        message: &str,
+    ) -> ObjectId {
+        let mut objects = Vec::new();
+        let tree = build_tree(files, &mut objects);
+        let signature = Signature {
+            name: author.to_string(),
+            email: format!("{}@example.com", author.to_lowercase()),
+            timestamp: time,
+            timezone_offset: 0,
+            raw: None,
+        };
+        let commit = GitObject::Commit(Commit {
+            tree,
+            parents: parents.to_vec(),
+            author: signature.clone(),
+            committer: signature,
+            extra_headers: Vec::new(),
+            message: message.to_string(),
+            raw_message: None,
+        });
+        let id = commit.canonical_hash();
+        objects.push((id, commit));
+        for (id, object) in objects {
+            storage.store_object(&id, &object).await.unwrap();
+        }
+        id
+    }
+
+    pub(crate) fn files(entries: &[(&str, &str)]) -> BTreeMap<String, String> {
+        entries.iter().map(|(path, content)| (path.to_string(), content.to_string())).collect()
+    }
+
+    fn filter(range: Option<&str>, predicate: Predicate) -> CommitFilter {
+        CommitFilter { range: range.map(str::to_string), predicate: Some(predicate), ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn test_filter_commits() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let s = storage.as_ref();
+        let root_files = files(&[("a.txt", "a"), ("src/lib.rs", "lib")]);
+        let root = commit(s, &[], &root_files, "Alice", 100, "Initial import\n").await;
+        let fix_files = files(&[("a.txt", "a"), ("src/lib.rs", "lib fixed")]);
+        let fix = commit(s, &[root], &fix_files, "Bob", 200, "Fix parser bug\n").await;
+        // Made on a machine whose clock ran behind
+        let docs_files = files(&[("a.txt", "a"), ("docs/guide.md", "guide"), ("src/lib.rs", "lib fixed")]);
+        let docs = commit(s, &[fix], &docs_files, "Alice", 50, "Add docs\n").await;
+        let topic_files = files(&[("a.txt", "b"), ("src/lib.rs", "lib")]);
+        let topic = commit(s, &[root], &topic_files, "Carol", 150, "Fix typo\n").await;
+        let merge_files = files(&[("a.txt", "b"), ("docs/guide.md", "guide"), ("src/lib.rs", "lib fixed")]);
+        let merge = commit(s, &[docs, topic], &merge_files, "Alice", 400, "Merge branch 'topic'\n").await;
+        s.update_ref("refs/heads/main", &merge).await.unwrap();
+        s.update_ref("refs/heads/topic", &topic).await.unwrap();
+        s.update_ref("HEAD", &merge).await.unwrap();
+
+        let engine = QueryEngine::open(storage.clone()).await.unwrap();
+        let all = engine.filter_commits(CommitFilter::default()).await.unwrap();
+        assert_eq!(all, vec![merge, docs, fix, topic, root]);
+        let by_date = CommitFilter { order: CommitOrder::Date, ..Default::default() };
+        assert_eq!(engine.filter_commits(by_date).await.unwrap(), vec![merge, fix, topic, root, docs]);
+
+        let cases = [
+            (filter(None, Predicate::Author("ALICE".to_string())), vec![merge, docs, root]),
+            (filter(None, Predicate::Message("^Fix".to_string())), vec![fix, topic]),
+            (filter(None, Predicate::Date { since: Some(150), until: Some(300) }), vec![fix, topic]),
+            (filter(None, Predicate::Path("src".to_string())), vec![fix, root]),
+            // The merge took a.txt from topic, so it does not count as changing it
+            (filter(None, Predicate::Path("a.txt".to_string())), vec![topic, root]),
+            (filter(None, Predicate::Path("docs/guide.md/".to_string())), vec![docs]),
+            (filter(None, Predicate::Path("missing/file".to_string())), vec![]),
+            (
+                filter(None, Predicate::Or(vec![
+                    Predicate::Author("bob@".to_string()),
+                    Predicate::Path("docs".to_string()),
+                ])),
+                vec![docs, fix],
+            ),
+            (
+                filter(None, Predicate::And(vec![
+                    Predicate::Not(Box::new(Predicate::Author("alice".to_string()))),
+                    Predicate::Message("(?i)fix".to_string()),
+                ])),
+                vec![fix, topic],
+            ),
+            (filter(Some("topic..main"), Predicate::And(Vec::new())), vec![merge, docs, fix]),
+            (filter(Some("main..topic"), Predicate::Or(Vec::new())), vec![]),
+            (filter(Some("topic"), Predicate::Path("a.txt".to_string())), vec![topic, root]),
+            (filter(Some("refs/heads/topic.."), Predicate::Author("alice".to_string())), vec![merge, docs]),
+            (filter(Some(&fix.to_string()), Predicate::Author("".to_string())), vec![fix, root]),
+        ];
+        for (filter, expected) in cases {
+            assert_eq!(engine.filter_commits(filter.clone()).await.unwrap(), expected, "{:?}", filter);
+        }
+
+        let limited = CommitFilter { limit: Some(2), ..Default::default() };
+        assert_eq!(engine.filter_commits(limited).await.unwrap(), vec![merge, docs]);
+        let invalid = filter(None, Predicate::Message("(".to_string()));
+        assert!(matches!(engine.filter_commits(invalid).await, Err(QueryError::InvalidPattern(_))));
+        let unknown = filter(Some("nope..main"), Predicate::And(Vec::new()));
+        assert!(matches!(engine.filter_commits(unknown).await, Err(QueryError::InvalidRevision(r)) if r == "nope"));
+    }
+
+    #[tokio::test]
+    async fn test_index_follows_graph() {
+        let storage = MemoryStorage::new();
+        let first = commit(&storage, &[], &files(&[("a", "1")]), "Alice", 1, "one\n").await;
+        let second = commit(&storage, &[first], &files(&[("a", "2")]), "Bob", 2, "two\n").await;
+        assert_eq!(crate::update_indexes(&storage, [first]).await.unwrap(), 1);
+        assert_eq!(crate::update_indexes(&storage, [second]).await.unwrap(), 1);
+        let index = CommitIndex::load(&storage).await.unwrap().unwrap();
+        assert_eq!(index.entries().iter().map(|e| e.id).collect::<Vec<_>>(), vec![first, second]);
+        assert_eq!(index.entries()[1].author, "Bob <bob@example.com>");
+        // The maps are rebuilt on load
+        assert_eq!(index.by_author.keys().collect::<Vec<_>>(), vec!["alice <alice@example.com>", "bob <bob@example.com>"]);
+        assert_eq!(index.by_date.keys().collect::<Vec<_>>(), vec![&1, &2]);
+
+        // An index saved for another graph is rebuilt from it
+        let other = commit(&storage, &[], &files(&[("b", "1")]), "Carol", 3, "other\n").await;
+        let mut graph = CommitGraphIndex::new();
+        graph.add_commits(&storage, [other]).await.unwrap();
+        let mut stale = index.clone();
+        assert_eq!(stale.sync(&storage, &graph).await.unwrap(), 1);
+        assert_eq!(stale.entries()[0].id, other);
+        assert_eq!(stale.by_author.keys().collect::<Vec<_>>(), vec!["carol <carol@example.com>"]);
+    }
+
+    #[tokio::test]
+    async fn test_save_after_someone_else() {
+        let storage = MemoryStorage::new();
+        let first = commit(&storage, &[], &files(&[("a", "1")]), "Alice", 1, "one\n").await;
+        let second = commit(&storage, &[first], &files(&[("a", "2")]), "Bob", 2, "two\n").await;
+        crate::update_indexes(&storage, [first]).await.unwrap();
+
+        let mut graph = CommitGraphIndex::load(&storage).await.unwrap().unwrap();
+        graph.add_commits(&storage, [second]).await.unwrap();
+        let mut one = CommitIndex::load(&storage).await.unwrap().unwrap();
+        let mut other = one.clone();
+        one.sync(&storage, &graph).await.unwrap();
+        other.sync(&storage, &graph).await.unwrap();
+        assert!(one.save(&storage).await.unwrap());
+        // The second writer is refused rather than overwriting the first's chunks
+        assert!(!other.save(&storage).await.unwrap());
+        assert_eq!(CommitIndex::load(&storage).await.unwrap().unwrap(), one);
+
+        // Both indexing more commits at once keep everything indexed
+        let third = commit(&storage, &[second], &files(&[("a", "3")]), "Carol", 3, "three\n").await;
+        let fourth = commit(&storage, &[second], &files(&[("b", "4")]), "Dave", 4, "four\n").await;
+        let (a, b) = tokio::join!(crate::update_indexes(&storage, [third]), crate::update_indexes(&storage, [fourth]));
+        a.unwrap();
+        b.unwrap();
+        let graph = CommitGraphIndex::load(&storage).await.unwrap().unwrap();
+        let index = CommitIndex::load(&storage).await.unwrap().unwrap();
+        assert_eq!(graph.len(), 4);
+        assert_eq!(index.entries().iter().map(|e| e.id).collect::<Vec<_>>(), graph.nodes().iter().map(|n| n.id).collect::<Vec<_>>());
+    }
+
+    const PATHS: [&str; 5] = ["a", "b/c", "b/d", "e/f/g", "e/h"];
+    const AUTHORS: [&str; 3] = ["Alice", "Bob", "Carol"];
+    const MESSAGES: [&str; 4] = ["fix parser bug", "Fix docs", "add feature", "merge"];
+
+    /// Message of generated commit `i`, numbered so that no two commits are the same
+    fn message(i: usize, m: usize) -> String {
+        format!("{}\n\nCommit {}\n", MESSAGES[m], i)
+    }
+
+    /// One generated commit: parents, author, time, message and file edits
+    /// (`None` deletes)
+    type Spec = (Vec<usize>, usize, i64, usize, Vec<(usize, Option<u8>)>);
+
+    fn histories() -> impl Strategy<Value = Vec<Spec>> {
+        (1usize..12).prop_flat_map(|n| {
+            (0..n)
+                .map(|i| {
+                    let parents = if i == 0 {
+                        Just(Vec::new()).boxed()
+                    } else {
+                        prop::collection::btree_set(0..i, 0..=2).prop_map(|s| s.into_iter().collect()).boxed()
+                    };
+                    let edits = prop::collection::vec((0..PATHS.len(), prop::option::of(0u8..3)), 0..3);
+                    (parents, 0..AUTHORS.len(), 0i64..20, 0..MESSAGES.len(), edits)
+                })
+                .collect::<Vec<_>>()
+        })
+    }
+
+    fn predicates() -> impl Strategy<Value = Predicate> {
+        let leaf = prop_oneof![
+            prop::sample::select(vec!["alice", "BOB", "example.com", "@example.com>", "zed"])
+                .prop_map(|s| Predicate::Author(s.to_string())),
+            (prop::option::of(0i64..20), prop::option::of(0i64..20))
+                .prop_map(|(since, until)| Predicate::Date { since, until }),
+            prop::sample::select(vec!["^fix", "(?i)fix", "parser|docs", "(?m)bug$", "Commit 1$"])
+                .prop_map(|s| Predicate::Message(s.to_string())),
+            prop::sample::select(vec!["a", "b", "b/c", "e", "e/f", "e/f/g", "/e/h", "b/c/x", "z"])
+                .prop_map(|s| Predicate::Path(s.to_string())),
+        ];
+        leaf.prop_recursive(3, 12, 3, |inner| prop_oneof![
+            prop::collection::vec(inner.clone(), 0..3).prop_map(Predicate::And),
+            prop::collection::vec(inner.clone(), 0..3).prop_map(Predicate::Or),
+            inner.prop_map(|p| Predicate::Not(Box::new(p))),
+        ])
+    }
+
+    /// Files at or below `path`
+    fn below<'a>(files: &'a BTreeMap<String, String>, path: &str) -> BTreeMap<&'a String, &'a String> {
+        let path = path.trim_matches('/');
+        files.iter().filter(|(p, _)| *p == path || p.starts_with(&format!("{}/", path))).collect()
+    }
+
+    /// Whether commit `i` meets `predicate`, worked out from the generated history
+    fn expected(predicate: &Predicate, i: usize, specs: &[Spec], trees: &[BTreeMap<String, String>]) -> bool {
+        let (parents, author, time, m, _) = &specs[i];
+        match predicate {
+            Predicate::Author(text) => {
+                let author = format!("{} <{}@example.com>", AUTHORS[*author], AUTHORS[*author].to_lowercase());
+                author.to_lowercase().contains(&text.to_lowercase())
+            }
+            Predicate::Date { since, until } => since.is_none_or(|s| *time >= s) && until.is_none_or(|u| *time <= u),
+            Predicate::Message(pattern) => Regex::new(pattern).unwrap().is_match(&message(i, *m)),
+            Predicate::Path(path) => {
+                let here = below(&trees[i], path);
+                if parents.is_empty() {
+                    !here.is_empty()
+                } else {
+                    parents.iter().all(|&p| below(&trees[p], path) != here)
+                }
+            }
+            Predicate::And(predicates) => predicates.iter().all(|p| expected(p, i, specs, trees)),
+            Predicate::Or(predicates) => predicates.iter().any(|p| expected(p, i, specs, trees)),
+            Predicate::Not(predicate) => !expected(predicate, i, specs, trees),
+        }
+    }
+
+    fn reachable(specs: &[Spec], start: usize) -> BTreeSet<usize> {
+        let mut seen = BTreeSet::from([start]);
+        let mut stack = vec![start];
+        while let Some(i) = stack.pop() {
+            for &p in &specs[i].0 {
+                if seen.insert(p) {
+                    stack.push(p);
+                }
+            }
+        }
+        seen
+    }
+
+    proptest! {
+        /// Property 17: Commit Filtering Accuracy
+        /// For any commit filter criteria (author, date, message, file changes), the
+        /// filtered results contain exactly the commits that match the criteria, in
+        /// the order asked for.
+        /// **Validates: Requirements 5.3**
+        #[test]
+        fn prop_filter_commits_matches_criteria(
+            specs in histories(),
+            predicate in predicates(),
+            range in (any::<prop::sample::Index>(), prop::option::of(any::<prop::sample::Index>())),
+            by_date in any::<bool>(),
+        ) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+                let mut ids = Vec::new();
+                let mut trees: Vec<BTreeMap<String, String>> = Vec::new();
+                for (i, (parents, author, time, m, edits)) in specs.iter().enumerate() {
+                    let mut tree = parents.first().map(|&p| trees[p].clone()).unwrap_or_default();
+                    for (path, content) in edits {
+                        match content {
+                            Some(content) => { tree.insert(PATHS[*path].to_string(), format!("{}-{}", i, content)); }
+                            None => { tree.remove(PATHS[*path]); }
+                        }
+                    }
+                    let parent_ids: Vec<ObjectId> = parents.iter().map(|&p| ids[p]).collect();
+                    let id = commit(storage.as_ref(), &parent_ids, &tree, AUTHORS[*author], *time, &message(i, *m)).await;
+                    storage.update_ref(&format!("refs/heads/c{}", i), &id).await.unwrap();
+                    ids.push(id);
+                    trees.push(tree);
+                }
+                let engine = QueryEngine::open(storage.clone()).await.unwrap();
+
+                let to = range.0.index(specs.len());
+                let from = range.1.map(|r| r.index(specs.len()));
+                let mut in_range = reachable(&specs, to);
+                if let Some(from) = from {
+                    in_range = in_range.difference(&reachable(&specs, from)).copied().collect();
+                }
+                let matching: BTreeSet<ObjectId> = in_range.iter()
+                    .filter(|&&i| expected(&predicate, i, &specs, &trees))
+                    .map(|&i| ids[i])
+                    .collect();
+
+                let filter = CommitFilter {
+                    range: Some(match from {
+                        Some(from) => format!("c{}..c{}", from, to),
+                        None => format!("c{}", to),
+                    }),
+                    predicate: Some(predicate.clone()),
+                    order: if by_date { CommitOrder::Date } else { CommitOrder::Topological },
+                    limit: None,
+                };
+                let found = engine.filter_commits(filter).await.unwrap();
+                prop_assert_eq!(found.len(), matching.len());
+                prop_assert_eq!(found.iter().copied().collect::<BTreeSet<_>>(), matching);
+
+                let index: HashMap<ObjectId, usize> = ids.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+                for (n, id) in found.iter().enumerate() {
+                    let i = index[id];
+                    for later in &found[n + 1..] {
+                        let j = index[later];
+                        if by_date {
+                            prop_assert!(specs[i].2 >= specs[j].2);
+                        } else {
+                            prop_assert!(!reachable(&specs, j).contains(&i), "ancestor listed before descendant");
+                        }
+                    }
+                }
+                Ok(())
+            })?;
+        }
+    }
+}
@@ -0,0 +1,605 @@
+//! Commit-graph index
+//!
+//! Each indexed commit is a node holding the positions of its parents, its commit
+//! time and two generation numbers, as in Git's commit-graph file: the topological
+//! level (one more than the highest parent's) and the corrected commit date (the
+//! commit time, raised to be later than every parent's). Both are smaller for an
+//! ancestor than for any of its descendants, so walks can stop as soon as they
+//! pass below a commit, and a queue ordered by corrected date visits children
+//! before parents whatever the clocks said.
+//!
+//! Commits are only ever appended, parents first, so the index is brought up to
+//! date by walking from new tips until known commits are reached. The nodes are
+//! kept in chunks named by `refs/gitnext/commit-graph`, so saving after new
+//! commits writes only the last chunks. An index that finds the persisted graph
+//! was extended by someone else since it loaded it takes that graph and adds
+//! its own commits to it again, positions being only meaningful within one
+//! graph.
+
+use crate::{QueryError, Result};
+use gitnext_core::{GitObject, ObjectId};
+use gitnext_storage::metadata::{load_list, save_list, Bincode, Persisted};
+use gitnext_storage::{Storage, StorageError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// Internal reference naming the manifest of the commit graph's chunks
+pub const COMMIT_GRAPH_REF: &str = "refs/gitnext/commit-graph";
+
+const OURS: u8 = 1;
+const THEIRS: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// One indexed commit
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommitNode {
+    pub id: ObjectId,
+    /// Positions of the parents in the index, in commit order
+    pub parents: Vec<u32>,
+    /// Committer time, in seconds since the epoch
+    pub timestamp: i64,
+    /// Topological level; 1 for root commits
+    pub level: u32,
+    /// Corrected commit date
+    pub corrected_date: i64,
+}
+
+/// Indexed commits, parents before children
+#[derive(Debug, Clone, Default)]
+pub struct CommitGraphIndex {
+    nodes: Vec<CommitNode>,
+    positions: HashMap<ObjectId, u32>,
+    /// Number of nodes already persisted
+    saved: usize,
+    persisted: Persisted,
+}
+
+/// Indexes are equal when they hold the same commits in the same order
+impl PartialEq for CommitGraphIndex {
+    fn eq(&self, other: &Self) -> bool {
+        self.nodes == other.nodes
+    }
+}
+
+impl Eq for CommitGraphIndex {}
+
+impl CommitGraphIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    pub fn contains(&self, id: &ObjectId) -> bool {
+        self.positions.contains_key(id)
+    }
+
+    pub fn node(&self, id: &ObjectId) -> Option<&CommitNode> {
+        self.positions.get(id).map(|&i| &self.nodes[i as usize])
+    }
+
+    /// Indexed commits, parents before children
+    pub fn nodes(&self) -> &[CommitNode] {
+        &self.nodes
+    }
+
+    pub(crate) fn position(&self, id: &ObjectId) -> Result<usize> {
+        self.positions.get(id).map(|&i| i as usize).ok_or(QueryError::NotIndexed(*id))
+    }
+
+    /// Load the persisted index, if one was saved
+    pub async fn load(storage: &dyn Storage) -> std::result::Result<Option<Self>, StorageError> {
+        let (nodes, persisted): (Vec<CommitNode>, _) = load_list::<Bincode, _>(storage, COMMIT_GRAPH_REF).await?;
+        if nodes.is_empty() {
+            return Ok(None);
+        }
+        let positions = nodes.iter().enumerate().map(|(i, node)| (node.id, i as u32)).collect();
+        let saved = nodes.len();
+        Ok(Some(Self { nodes, positions, saved, persisted }))
+    }
+
+    /// Persist the nodes added since the index was loaded or last saved. If
+    /// others saved commits in the meantime, the index becomes theirs with its
+    /// own commits added, so positions may change.
+    pub async fn save(&mut self, storage: &dyn Storage) -> std::result::Result<(), StorageError> {
+        loop {
+            if self.saved == self.nodes.len() {
+                return Ok(());
+            }
+            if save_list::<Bincode, _>(storage, COMMIT_GRAPH_REF, &self.nodes, self.saved..self.nodes.len(), &mut self.persisted).await? {
+                self.saved = self.nodes.len();
+                return Ok(());
+            }
+            self.reload(storage).await?;
+        }
+    }
+
+    /// Take in the commits others saved since the index was loaded or last saved
+    pub async fn refresh(&mut self, storage: &dyn Storage) -> std::result::Result<(), StorageError> {
+        if !self.persisted.is_current(storage, COMMIT_GRAPH_REF).await? {
+            self.reload(storage).await?;
+        }
+        Ok(())
+    }
+
+    /// Load the persisted graph and add the commits not saved yet to it
+    async fn reload(&mut self, storage: &dyn Storage) -> std::result::Result<(), StorageError> {
+        let added: Vec<ObjectId> = self.nodes[self.saved..].iter().map(|node| node.id).collect();
+        *self = Self::load(storage).await?.unwrap_or_default();
+        self.add_commits(storage, added).await?;
+        Ok(())
+    }
+
+    /// Add the commits reachable from `tips` that are not indexed yet, returning
+    /// how many were added. Tags are peeled; tips naming other objects are
+    /// skipped, as are parents missing from storage (past a shallow boundary).
+    pub async fn add_commits(
+        &mut self,
+        storage: &dyn Storage,
+        tips: impl IntoIterator<Item = ObjectId>,
+    ) -> std::result::Result<usize, StorageError> {
+        let before = self.nodes.len();
+        for tip in tips {
+            let Some(tip) = peel(storage, tip).await? else {
+                continue;
+            };
+            // Depth first, appending a commit once its parents are in
+            let mut pending: HashMap<ObjectId, (Vec<ObjectId>, i64)> = HashMap::new();
+            let mut stack = vec![(tip, false)];
+            while let Some((id, expanded)) = stack.pop() {
+                if self.positions.contains_key(&id) {
+                    continue;
+                }
+                if expanded {
+                    if let Some((parents, timestamp)) = pending.remove(&id) {
+                        self.push(id, &parents, timestamp);
+                    }
+                    continue;
+                }
+                if pending.contains_key(&id) {
+                    continue;
+                }
+                match storage.load_object(&id).await? {
+                    Some(GitObject::Commit(commit)) => {
+                        stack.push((id, true));
+                        stack.extend(commit.parents.iter().rev()
+                            .filter(|parent| !self.positions.contains_key(*parent))
+                            .map(|parent| (*parent, false)));
+                        pending.insert(id, (commit.parents, commit.committer.timestamp));
+                    }
+                    Some(_) => {
+                        return Err(StorageError::CorruptionDetected { id, details: "Parent is not a commit".to_string() });
+                    }
+                    None => {}
+                }
+            }
+        }
+        Ok(self.nodes.len() - before)
+    }
+
+    fn push(&mut self, id: ObjectId, parents: &[ObjectId], timestamp: i64) {
+        let parents: Vec<u32> = parents.iter().filter_map(|parent| self.positions.get(parent).copied()).collect();
+        let level = parents.iter().map(|&p| self.nodes[p as usize].level).max().unwrap_or(0) + 1;
+        let corrected_date = parents.iter()
+            .map(|&p| self.nodes[p as usize].corrected_date + 1)
+            .fold(timestamp, i64::max);
+        self.positions.insert(id, self.nodes.len() as u32);
+        self.nodes.push(CommitNode { id, parents, timestamp, level, corrected_date });
+    }
+
+    /// Whether `ancestor` is reachable from `descendant`; a commit is its own
+    /// ancestor
+    pub fn is_ancestor(&self, ancestor: &ObjectId, descendant: &ObjectId) -> Result<bool> {
+        let ancestor = self.position(ancestor)?;
+        let descendant = self.position(descendant)?;
+        Ok(self.reaches(descendant, ancestor))
+    }
+
+    fn reaches(&self, from: usize, target: usize) -> bool {
+        let goal = &self.nodes[target];
+        let mut seen = HashSet::from([from]);
+        let mut stack = vec![from];
+        while let Some(i) = stack.pop() {
+            if i == target {
+                return true;
+            }
+            let node = &self.nodes[i];
+            // Nothing below the target's generation can lead to it
+            if node.level <= goal.level || node.corrected_date <= goal.corrected_date {
+                continue;
+            }
+            for &parent in &node.parents {
+                if seen.insert(parent as usize) {
+                    stack.push(parent as usize);
+                }
+            }
+        }
+        false
+    }
+
+    /// `id` and every commit reachable from it, children before parents
+    pub fn ancestors(&self, id: &ObjectId) -> Result<Vec<ObjectId>> {
+        let start = self.position(id)?;
+        Ok(self.reachable(&[start], &[]).into_iter().map(|i| self.nodes[i].id).collect())
+    }
+
+    /// Positions of the commits reachable from `include` but not from `exclude`,
+    /// children before parents
+    pub(crate) fn reachable(&self, include: &[usize], exclude: &[usize]) -> Vec<usize> {
+        let walk = |starts: &[usize], skip: &HashSet<usize>| {
+            let mut seen: HashSet<usize> = starts.iter().copied().filter(|i| !skip.contains(i)).collect();
+            let mut stack: Vec<usize> = seen.iter().copied().collect();
+            while let Some(i) = stack.pop() {
+                for &parent in &self.nodes[i].parents {
+                    let parent = parent as usize;
+                    if !skip.contains(&parent) && seen.insert(parent) {
+                        stack.push(parent);
+                    }
+                }
+            }
+            seen
+        };
+        let excluded = walk(exclude, &HashSet::new());
+        let mut found: Vec<usize> = walk(include, &excluded).into_iter().collect();
+        found.sort_by_key(|&i| std::cmp::Reverse(self.order_key(i)));
+        found
+    }
+
+    /// Key ordering descendants after their ancestors, and otherwise by date
+    pub(crate) fn order_key(&self, i: usize) -> (i64, u32, usize) {
+        (self.nodes[i].corrected_date, self.nodes[i].level, i)
+    }
+
+    /// The best common ancestors of `a` and `b`: those not reachable from another
+    /// common ancestor, newest first. Criss-cross histories have several; unrelated
+    /// ones have none.
+    pub fn merge_bases(&self, a: &ObjectId, b: &ObjectId) -> Result<Vec<ObjectId>> {
+        let (a, b) = (self.position(a)?, self.position(b)?);
+        if a == b {
+            return Ok(vec![self.nodes[a].id]);
+        }
+
+        let mut flags: HashMap<usize, u8> = HashMap::from([(a, OURS), (b, THEIRS)]);
+        let mut queue = BinaryHeap::from([(self.order_key(a), a), (self.order_key(b), b)]);
+        let mut results = Vec::new();
+        while queue.iter().any(|(_, i)| flags[i] & STALE == 0) {
+            let Some((_, i)) = queue.pop() else { break };
+            let mut flag = flags[&i];
+            if flag & (OURS | THEIRS) == OURS | THEIRS {
+                if flag & RESULT == 0 {
+                    flags.insert(i, flag | RESULT);
+                    results.push(i);
+                }
+                flag |= STALE;
+            }
+            let propagated = flag & (OURS | THEIRS | STALE);
+            for &parent in &self.nodes[i].parents {
+                let parent = parent as usize;
+                let current = flags.get(&parent).copied().unwrap_or(0);
+                if current & propagated != propagated {
+                    flags.insert(parent, current | propagated);
+                    queue.push((self.order_key(parent), parent));
+                }
+            }
+        }
+
+        let candidates: Vec<usize> = results.into_iter().filter(|i| flags[i] & STALE == 0).collect();
+        let mut bases: Vec<usize> = candidates.iter()
+            .copied()
+            .filter(|&i| !candidates.iter().any(|&other| other != i && self.reaches(other, i)))
+            .collect();
+        bases.sort_by_key(|&i| std::cmp::Reverse(self.order_key(i)));
+        Ok(bases.into_iter().map(|i| self.nodes[i].id).collect())
+    }
+
+    /// Commits reachable from `a` but not `b`, and from `b` but not `a`
+    pub fn ahead_behind(&self, a: &ObjectId, b: &ObjectId) -> Result<(usize, usize)> {
+        let (a, b) = (self.position(a)?, self.position(b)?);
+        let mut flags: HashMap<usize, u8> = HashMap::new();
+        *flags.entry(a).or_default() |= OURS;
+        *flags.entry(b).or_default() |= THEIRS;
+        let mut queue = BinaryHeap::from([(self.order_key(a), a), (self.order_key(b), b)]);
+        let mut done = HashSet::new();
+        let (mut ahead, mut behind) = (0, 0);
+
+        // Every descendant of a commit is dequeued before it, so its flags are
+        // final by then; once only commits reachable from both remain, so are
+        // all of their ancestors
+        while queue.iter().any(|(_, i)| flags[i] != OURS | THEIRS) {
+            let Some((_, i)) = queue.pop() else { break };
+            if !done.insert(i) {
+                continue;
+            }
+            let flag = flags[&i];
+            match flag {
+                OURS => ahead += 1,
+                THEIRS => behind += 1,
+                _ => {}
+            }
+            for &parent in &self.nodes[i].parents {
+                let parent = parent as usize;
+                let current = flags.entry(parent).or_default();
+                if *current | flag != *current {
+                    *current |= flag;
+                    queue.push((self.order_key(parent), parent));
+                }
+            }
+        }
+        Ok((ahead, behind))
+    }
+}
+
+/// The commit `id` names, following tags; `None` for other objects
+pub(crate) async fn peel(storage: &dyn Storage, mut id: ObjectId) -> std::result::Result<Option<ObjectId>, StorageError> {
+    loop {
+        match storage.load_object(&id).await? {
+            Some(GitObject::Commit(_)) => return Ok(Some(id)),
+            Some(GitObject::Tag(tag)) => id = tag.target,
+            _ => return Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gitnext_core::{Blob, Commit, Signature, Tag};
+    use gitnext_storage::MemoryStorage;
+    use proptest::prelude::*;
+    use std::collections::BTreeSet;
+
+    /// Store a commit with `parents` made at `time`; `n` keeps ids apart
+    async fn commit(storage: &dyn Storage, n: usize, parents: &[ObjectId], time: i64) -> ObjectId {
+        let signature = Signature {
+            name: "Test".to_string(),
+            email: "test@example.com".to_string(),
+            timestamp: time,
+            timezone_offset: 0,
+            raw: None,
+        };
+        let object = GitObject::Commit(Commit {
+            tree: GitObject::Blob(Blob::new(bytes::Bytes::new())).canonical_hash(),
+            parents: parents.to_vec(),
+            author: signature.clone(),
+            committer: signature,
+            extra_headers: Vec::new(),
+            message: format!("commit {}\n", n),
+            raw_message: None,
+        });
+        let id = object.canonical_hash();
+        storage.store_object(&id, &object).await.unwrap();
+        id
+    }
+
+    /// Store the commits of a history given as parent lists, returning their ids
+    async fn history(storage: &dyn Storage, parents: &[Vec<usize>], times: &[i64]) -> Vec<ObjectId> {
+        let mut ids = Vec::new();
+        for (n, (parents, time)) in parents.iter().zip(times).enumerate() {
+            let parent_ids: Vec<ObjectId> = parents.iter().map(|&p| ids[p]).collect();
+            ids.push(commit(storage, n, &parent_ids, *time).await);
+        }
+        ids
+    }
+
+    /// Ancestors of `i` by index, by plain search
+    fn reachable(parents: &[Vec<usize>], i: usize) -> BTreeSet<usize> {
+        let mut seen = BTreeSet::from([i]);
+        let mut stack = vec![i];
+        while let Some(i) = stack.pop() {
+            for &p in &parents[i] {
+                if seen.insert(p) {
+                    stack.push(p);
+                }
+            }
+        }
+        seen
+    }
+
+    #[tokio::test]
+    async fn test_generations_and_queries() {
+        let storage = MemoryStorage::new();
+        // 0 - 1 - 2 - 4
+        //      \     /
+        //       3 ---     with 3's clock running far behind
+        let parents = vec![vec![], vec![0], vec![1], vec![1], vec![2, 3]];
+        let ids = history(&storage, &parents, &[100, 200, 300, 50, 400]).await;
+        let mut index = CommitGraphIndex::new();
+        assert_eq!(index.add_commits(&storage, [ids[4]]).await.unwrap(), 5);
+
+        let node = |i: usize| index.node(&ids[i]).unwrap();
+        assert_eq!((node(0).level, node(2).level, node(3).level, node(4).level), (1, 3, 3, 4));
+        assert_eq!(node(3).corrected_date, 201);
+        assert_eq!(node(4).parents, vec![index.positions[&ids[2]], index.positions[&ids[3]]]);
+
+        assert!(index.is_ancestor(&ids[3], &ids[4]).unwrap());
+        assert!(index.is_ancestor(&ids[4], &ids[4]).unwrap());
+        assert!(!index.is_ancestor(&ids[3], &ids[2]).unwrap());
+        assert_eq!(index.merge_bases(&ids[2], &ids[3]).unwrap(), vec![ids[1]]);
+        assert_eq!(index.ahead_behind(&ids[4], &ids[2]).unwrap(), (2, 0));
+        assert_eq!(index.ahead_behind(&ids[2], &ids[3]).unwrap(), (1, 1));
+        let ancestors = index.ancestors(&ids[4]).unwrap();
+        assert_eq!(ancestors[0], ids[4]);
+        assert_eq!(ancestors.len(), 5);
+        assert_eq!(ancestors[4], ids[0]);
+
+        let unknown = commit(&storage, 9, &[], 0).await;
+        assert!(matches!(index.is_ancestor(&unknown, &ids[4]), Err(QueryError::NotIndexed(id)) if id == unknown));
+    }
+
+    #[tokio::test]
+    async fn test_criss_cross_merge_bases() {
+        let storage = MemoryStorage::new();
+        let parents = vec![vec![], vec![0], vec![0], vec![1, 2], vec![2, 1], vec![3], vec![4]];
+        let ids = history(&storage, &parents, &[1, 2, 3, 4, 5, 6, 7]).await;
+        let mut index = CommitGraphIndex::new();
+        index.add_commits(&storage, [ids[5], ids[6]]).await.unwrap();
+        assert_eq!(index.merge_bases(&ids[5], &ids[6]).unwrap(), vec![ids[2], ids[1]]);
+        let unrelated = commit(&storage, 7, &[], 8).await;
+        index.add_commits(&storage, [unrelated]).await.unwrap();
+        assert!(index.merge_bases(&ids[5], &unrelated).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_persistence_tags_and_missing_parents() {
+        let storage = MemoryStorage::new();
+        let missing = ObjectId::from_blake3_bytes([7; 32]);
+        let root = commit(&storage, 0, &[missing], 10).await;
+        let tip = commit(&storage, 1, &[root], 20).await;
+        let tag = GitObject::Tag(Tag {
+            target: tip,
+            target_type: gitnext_core::ObjectType::Commit,
+            name: "v1".to_string(),
+            tagger: Some(Signature {
+                name: "Test".to_string(),
+                email: "test@example.com".to_string(),
+                timestamp: 30,
+                timezone_offset: 0,
+                raw: None,
+            }),
+            extra_headers: Vec::new(),
+            message: "v1\n".to_string(),
+            signature: None,
+            raw_message: None,
+        });
+        let tag_id = tag.canonical_hash();
+        storage.store_object(&tag_id, &tag).await.unwrap();
+
+        assert!(CommitGraphIndex::load(&storage).await.unwrap().is_none());
+        assert_eq!(crate::update_indexes(&storage, [tag_id]).await.unwrap(), 2);
+        assert_eq!(crate::update_indexes(&storage, [tip]).await.unwrap(), 0);
+        let index = CommitGraphIndex::load(&storage).await.unwrap().unwrap();
+        assert_eq!(index.len(), 2);
+        // The missing parent is left out, like a shallow boundary
+        assert!(index.node(&root).unwrap().parents.is_empty());
+        assert!(index.is_ancestor(&root, &tip).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_save_writes_only_changed_chunks() {
+        use gitnext_storage::metadata::CHUNK_LEN;
+
+        let storage = MemoryStorage::new();
+        let mut ids = Vec::new();
+        for n in 0..CHUNK_LEN + 1 {
+            let parents: Vec<ObjectId> = ids.last().copied().into_iter().collect();
+            ids.push(commit(&storage, n, &parents, n as i64).await);
+        }
+        let mut index = CommitGraphIndex::new();
+        index.add_commits(&storage, [ids[CHUNK_LEN]]).await.unwrap();
+        index.save(&storage).await.unwrap();
+        let before = index.persisted.chunks().to_vec();
+        assert_eq!(before.len(), 2);
+
+        let tip = commit(&storage, CHUNK_LEN + 1, &[ids[CHUNK_LEN]], 0).await;
+        index.add_commits(&storage, [tip]).await.unwrap();
+        index.save(&storage).await.unwrap();
+        let after = index.persisted.chunks().to_vec();
+        assert_eq!(after[0], before[0]);
+        assert_ne!(after[1], before[1]);
+
+        let loaded = CommitGraphIndex::load(&storage).await.unwrap().unwrap();
+        assert_eq!(loaded, index);
+        assert!(loaded.is_ancestor(&ids[0], &tip).unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_save_merges_commits_saved_by_others() {
+        let storage = MemoryStorage::new();
+        let root = commit(&storage, 0, &[], 0).await;
+        let mut index = CommitGraphIndex::new();
+        index.add_commits(&storage, [root]).await.unwrap();
+        index.save(&storage).await.unwrap();
+
+        // Two indexes loaded from the same graph each add a branch
+        let mut one = CommitGraphIndex::load(&storage).await.unwrap().unwrap();
+        let mut other = one.clone();
+        let left = commit(&storage, 1, &[root], 1).await;
+        let right = commit(&storage, 2, &[root], 2).await;
+        one.add_commits(&storage, [left]).await.unwrap();
+        other.add_commits(&storage, [right]).await.unwrap();
+        one.save(&storage).await.unwrap();
+        other.save(&storage).await.unwrap();
+
+        let loaded = CommitGraphIndex::load(&storage).await.unwrap().unwrap();
+        assert_eq!(loaded, other);
+        assert_eq!(loaded.len(), 3);
+        assert!(loaded.is_ancestor(&root, &left).unwrap());
+        assert!(loaded.is_ancestor(&root, &right).unwrap());
+    }
+
+    fn histories() -> impl Strategy<Value = (Vec<Vec<usize>>, Vec<i64>, usize)> {
+        (1usize..25).prop_flat_map(|n| {
+            let parents = (0..n)
+                .map(|i| if i == 0 {
+                    Just(Vec::new()).boxed()
+                } else {
+                    prop::collection::btree_set(0..i, 0..=2).prop_map(|set| set.into_iter().collect()).boxed()
+                })
+                .collect::<Vec<_>>();
+            (parents, prop::collection::vec(0i64..50, n), 0..=n)
+        })
+    }
+
+    proptest! {
+        /// Property 19: Incremental Index Updates
+        /// Adding commits to an index keeps its existing entries and yields the same
+        /// index as building it at once, and its answers match plain graph searches.
+        /// **Validates: Requirements 5.5**
+        #[test]
+        fn prop_incremental_index_matches_full_build((parents, times, split) in histories()) {
+            let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+            runtime.block_on(async {
+                let storage = MemoryStorage::new();
+                let ids = history(&storage, &parents, &times).await;
+
+                let mut incremental = CommitGraphIndex::new();
+                incremental.add_commits(&storage, ids[..split].iter().copied()).await.unwrap();
+                let first = incremental.nodes.clone();
+                incremental.add_commits(&storage, ids.iter().copied()).await.unwrap();
+                prop_assert_eq!(&incremental.nodes[..first.len()], &first[..]);
+
+                let mut full = CommitGraphIndex::new();
+                full.add_commits(&storage, ids.iter().rev().copied()).await.unwrap();
+                prop_assert_eq!(incremental.len(), ids.len());
+                for (i, id) in ids.iter().enumerate() {
+                    let (a, b) = (incremental.node(id).unwrap(), full.node(id).unwrap());
+                    prop_assert_eq!((a.level, a.corrected_date), (b.level, b.corrected_date));
+                    prop_assert!(a.parents.iter().all(|&p| incremental.nodes[p as usize].level < a.level));
+                    prop_assert_eq!(a.parents.len(), parents[i].len());
+                }
+
+                let ancestors: Vec<BTreeSet<usize>> = (0..ids.len()).map(|i| reachable(&parents, i)).collect();
+                for a in 0..ids.len() {
+                    for b in 0..ids.len() {
+                        prop_assert_eq!(incremental.is_ancestor(&ids[a], &ids[b]).unwrap(), ancestors[b].contains(&a));
+                        let ahead = ancestors[a].difference(&ancestors[b]).count();
+                        let behind = ancestors[b].difference(&ancestors[a]).count();
+                        prop_assert_eq!(incremental.ahead_behind(&ids[a], &ids[b]).unwrap(), (ahead, behind));
+
+                        let common: BTreeSet<usize> = ancestors[a].intersection(&ancestors[b]).copied().collect();
+                        let best: BTreeSet<ObjectId> = common.iter()
+                            .filter(|&&c| !common.iter().any(|&other| other != c && ancestors[other].contains(&c)))
+                            .map(|&c| ids[c])
+                            .collect();
+                        let bases: BTreeSet<ObjectId> = incremental.merge_bases(&ids[a], &ids[b]).unwrap().into_iter().collect();
+                        prop_assert_eq!(bases, best);
+                    }
+                    let order = incremental.ancestors(&ids[a]).unwrap();
+                    prop_assert_eq!(order.len(), ancestors[a].len());
+                    let position: HashMap<ObjectId, usize> = order.iter().enumerate().map(|(i, id)| (*id, i)).collect();
+                    for &c in &ancestors[a] {
+                        prop_assert!(parents[c].iter().all(|p| position[&ids[*p]] > position[&ids[c]]));
+                    }
+                }
+                Ok(())
+            })?;
+        }
+    }
+}
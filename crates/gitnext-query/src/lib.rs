@@ -0,0 +1,385 @@
+//! GitNext Query - indexed repository queries, diffs and blame (Requirements 5.1-5.4)
+//!
+//! `QueryEngine` answers ancestry questions from a persisted commit-graph index
+//! (`graph`) and filters commits with the secondary indexes that follow it
+//! (`filter`, with changed-path Bloom filters from `bloom`), keeping both up to
+//! date with the repository's refs. It also compares trees and commits (`diff`)
+//! and blames lines of files (`blame`) using the same indexes. Operations that
+//! add commits update the indexes too, so they rarely have to catch up.
+
+use gitnext_core::ObjectId;
+use gitnext_storage::{ReferenceTarget, Storage, StorageError};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::RwLock;
+
+pub mod blame;
+pub mod bloom;
+pub mod diff;
+pub mod filter;
+pub mod graph;
+
+pub use blame::{Blame, BlameOptions, BlameRange};
+pub use bloom::{ChangedPathFilter, MAX_CHANGED_PATHS};
+pub use diff::{diff_commits, diff_text, diff_trees, ChangeKind, DiffLine, DiffOptions, DiffSummary, FileDiff, Hunk, TreeDiff};
+pub use filter::{CommitEntry, CommitFilter, CommitIndex, CommitOrder, Predicate, COMMIT_INDEX_REF};
+pub use graph::{CommitGraphIndex, CommitNode, COMMIT_GRAPH_REF};
+
+/// Query error types
+#[derive(Debug, Error)]
+pub enum QueryError {
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error("Commit {0} is not indexed")]
+    NotIndexed(ObjectId),
+
+    #[error("Unknown revision: {0}")]
+    InvalidRevision(String),
+
+    #[error("Invalid pattern: {0}")]
+    InvalidPattern(String),
+
+    #[error("Object {0} is not a commit")]
+    NotACommit(ObjectId),
+
+    #[error("Path not found: {0}")]
+    PathNotFound(String),
+
+    #[error("Tree error: {0}")]
+    Tree(#[from] gitnext_merge::MergeError),
+}
+
+pub type Result<T> = std::result::Result<T, QueryError>;
+
+/// Ref targets the indexes start from: every ref except GitNext's own metadata
+async fn ref_tips(storage: &dyn Storage) -> Result<Vec<ObjectId>> {
+    let mut tips = Vec::new();
+    for reference in storage.list_refs().await? {
+        if reference.name.starts_with("refs/gitnext/") || reference.name.starts_with("refs/logs/") {
+            continue;
+        }
+        if let ReferenceTarget::Direct(id) = reference.target {
+            if !tips.contains(&id) {
+                tips.push(id);
+            }
+        }
+    }
+    Ok(tips)
+}
+
+/// The commit graph and the indexes that follow it
+#[derive(Default)]
+struct Indexes {
+    graph: CommitGraphIndex,
+    commits: CommitIndex,
+}
+
+impl Indexes {
+    /// Load the persisted indexes, catching the commit index up with the graph
+    async fn load(storage: &dyn Storage) -> std::result::Result<Self, StorageError> {
+        let graph = CommitGraphIndex::load(storage).await?.unwrap_or_default();
+        let commits = CommitIndex::load(storage).await?.unwrap_or_default();
+        let mut indexes = Self { graph, commits };
+        indexes.save(storage).await?;
+        Ok(indexes)
+    }
+
+    /// Take in what others saved since the indexes were loaded or last saved
+    async fn refresh(&mut self, storage: &dyn Storage) -> std::result::Result<(), StorageError> {
+        self.graph.refresh(storage).await?;
+        self.commits.refresh(storage).await
+    }
+
+    /// Index `tips` and their ancestors if needed, saving the indexes when they grew
+    async fn add(
+        &mut self,
+        storage: &dyn Storage,
+        tips: impl IntoIterator<Item = ObjectId>,
+    ) -> std::result::Result<usize, StorageError> {
+        let added = self.graph.add_commits(storage, tips).await?;
+        if added > 0 {
+            self.save(storage).await?;
+        }
+        Ok(added)
+    }
+
+    /// Save the graph, then catch the commit index up with it (saving may take
+    /// in commits others indexed) and save that, taking in the one someone else
+    /// saved first if need be
+    async fn save(&mut self, storage: &dyn Storage) -> std::result::Result<(), StorageError> {
+        self.graph.save(storage).await?;
+        loop {
+            self.commits.sync(storage, &self.graph).await?;
+            if self.commits.save(storage).await? {
+                return Ok(());
+            }
+            self.commits = CommitIndex::load(storage).await?.unwrap_or_default();
+            if self.commits.len() > self.graph.len() {
+                self.graph.refresh(storage).await?;
+            }
+        }
+    }
+}
+
+/// Bring the persisted indexes up to date with `tips`, creating them if needed;
+/// returns how many commits were added
+///
+/// This loads the whole indexes; callers adding commits repeatedly keep an
+/// `IndexUpdater` instead.
+pub async fn update_indexes(
+    storage: &dyn Storage,
+    tips: impl IntoIterator<Item = ObjectId>,
+) -> std::result::Result<usize, StorageError> {
+    IndexUpdater::default().update(storage, tips).await
+}
+
+/// Keeps the persisted indexes loaded between updates, so indexing a new commit
+/// costs a check that nobody else saved in the meantime rather than a load of
+/// the whole history
+#[derive(Default)]
+pub struct IndexUpdater {
+    indexes: tokio::sync::Mutex<Option<Indexes>>,
+}
+
+impl IndexUpdater {
+    /// Bring the persisted indexes up to date with `tips` as `update_indexes`
+    /// does, loading them on first use
+    pub async fn update(
+        &self,
+        storage: &dyn Storage,
+        tips: impl IntoIterator<Item = ObjectId>,
+    ) -> std::result::Result<usize, StorageError> {
+        let mut cached = self.indexes.lock().await;
+        let indexes = match cached.as_mut() {
+            Some(indexes) => {
+                indexes.refresh(storage).await?;
+                indexes
+            }
+            None => cached.insert(Indexes::load(storage).await?),
+        };
+        let result = indexes.add(storage, tips).await;
+        // A failed update may leave commits indexed in memory only
+        if result.is_err() {
+            *cached = None;
+        }
+        result
+    }
+}
+
+/// Indexed queries over one repository's storage
+pub struct QueryEngine {
+    storage: Arc<dyn Storage>,
+    indexes: RwLock<Indexes>,
+}
+
+impl QueryEngine {
+    /// Open the engine on `storage`, indexing any commits the persisted indexes
+    /// lack
+    pub async fn open(storage: Arc<dyn Storage>) -> Result<Self> {
+        let indexes = Indexes::load(storage.as_ref()).await?;
+        let engine = Self { storage, indexes: RwLock::new(indexes) };
+        engine.refresh().await?;
+        Ok(engine)
+    }
+
+    /// Index the commits reachable from refs that are not indexed yet, returning
+    /// how many were added
+    pub async fn refresh(&self) -> Result<usize> {
+        let tips = ref_tips(self.storage.as_ref()).await?;
+        self.index(&tips).await
+    }
+
+    /// Index `commits` and their ancestors if needed
+    async fn index(&self, commits: &[ObjectId]) -> Result<usize> {
+        {
+            let indexes = self.indexes.read().await;
+            if commits.iter().all(|id| indexes.graph.contains(id)) {
+                return Ok(0);
+            }
+        }
+        let mut indexes = self.indexes.write().await;
+        Ok(indexes.add(self.storage.as_ref(), commits.iter().copied()).await?)
+    }
+
+    /// Number of indexed commits
+    pub async fn commit_count(&self) -> usize {
+        self.indexes.read().await.graph.len()
+    }
+
+    /// `commit` and every commit reachable from it, children before parents
+    pub async fn ancestors(&self, commit: &ObjectId) -> Result<Vec<ObjectId>> {
+        self.index(&[*commit]).await?;
+        self.indexes.read().await.graph.ancestors(commit)
+    }
+
+    /// Whether `ancestor` is reachable from `descendant`
+    pub async fn is_ancestor(&self, ancestor: &ObjectId, descendant: &ObjectId) -> Result<bool> {
+        self.index(&[*ancestor, *descendant]).await?;
+        self.indexes.read().await.graph.is_ancestor(ancestor, descendant)
+    }
+
+    /// Best common ancestors of `a` and `b`, newest first: one for most
+    /// histories, several for criss-cross merges, none for unrelated ones
+    pub async fn merge_base(&self, a: &ObjectId, b: &ObjectId) -> Result<Vec<ObjectId>> {
+        self.index(&[*a, *b]).await?;
+        self.indexes.read().await.graph.merge_bases(a, b)
+    }
+
+    /// How many commits `a` has that `b` lacks, and the other way round
+    pub async fn ahead_behind(&self, a: &ObjectId, b: &ObjectId) -> Result<(usize, usize)> {
+        self.index(&[*a, *b]).await?;
+        self.indexes.read().await.graph.ahead_behind(a, b)
+    }
+
+    /// The commit a revision names: a ref, a branch, tag or remote-tracking
+    /// branch name, or a commit id
+    pub async fn resolve(&self, revision: &str) -> Result<ObjectId> {
+        let candidates = [
+            revision.to_string(),
+            format!("refs/{}", revision),
+            format!("refs/heads/{}", revision),
+            format!("refs/tags/{}", revision),
+            format!("refs/remotes/{}", revision),
+        ];
+        let refs = self.storage.list_refs().await?;
+        let target = candidates.iter()
+            .find_map(|name| refs.iter().find(|r| &r.name == name))
+            .and_then(|r| match r.target {
+                ReferenceTarget::Direct(id) => Some(id),
+                ReferenceTarget::Symbolic(_) => None,
+            })
+            .or_else(|| {
+                let bytes: [u8; 32] = hex::decode(revision).ok()?.try_into().ok()?;
+                Some(ObjectId::from_blake3_bytes(bytes))
+            });
+        let commit = match target {
+            Some(id) => graph::peel(self.storage.as_ref(), id).await?,
+            None => None,
+        };
+        let commit = commit.ok_or_else(|| QueryError::InvalidRevision(revision.to_string()))?;
+        self.index(&[commit]).await?;
+        Ok(commit)
+    }
+
+    /// Differences from the commit `old` to the commit `new`
+    pub async fn diff(&self, old: &ObjectId, new: &ObjectId, options: &DiffOptions) -> Result<TreeDiff> {
+        diff_commits(self.storage.as_ref(), old, new, options).await
+    }
+
+    /// Who last changed each line of the file at `path` in `commit`
+    pub async fn blame(&self, commit: &ObjectId, path: &str, options: &BlameOptions) -> Result<Blame> {
+        self.index(&[*commit]).await?;
+        let indexes = self.indexes.read().await;
+        let position = indexes.graph.position(commit)?;
+        blame::blame(self.storage.as_ref(), &indexes.graph, &indexes.commits, position, path, options).await
+    }
+
+    /// Commits in the filter's range that meet its predicate, in its order
+    pub async fn filter_commits(&self, filter: CommitFilter) -> Result<Vec<ObjectId>> {
+        let (include, exclude) = match filter.range.as_deref() {
+            None => {
+                self.refresh().await?;
+                (ref_tips(self.storage.as_ref()).await?, Vec::new())
+            }
+            Some(range) => match range.split_once("..") {
+                Some((from, to)) => {
+                    let side = |s: &str| if s.is_empty() { "HEAD".to_string() } else { s.to_string() };
+                    (vec![self.resolve(&side(to)).await?], vec![self.resolve(&side(from)).await?])
+                }
+                None => (vec![self.resolve(range).await?], Vec::new()),
+            },
+        };
+
+        let indexes = self.indexes.read().await;
+        let graph = &indexes.graph;
+        // Tips naming trees or blobs are not in the graph
+        let positions = |ids: &[ObjectId]| ids.iter().filter_map(|id| graph.position(id).ok()).collect::<Vec<_>>();
+        let mut commits = graph.reachable(&positions(&include), &positions(&exclude));
+        if let Some(predicate) = &filter.predicate {
+            let evaluator = filter::Evaluator { storage: self.storage.as_ref(), graph, index: &indexes.commits };
+            commits = evaluator.eval(predicate, commits).await?;
+        }
+        if filter.order == CommitOrder::Date {
+            commits.sort_by_key(|&p| std::cmp::Reverse(graph.nodes()[p].timestamp));
+        }
+        if let Some(limit) = filter.limit {
+            commits.truncate(limit);
+        }
+        Ok(commits.into_iter().map(|p| graph.nodes()[p].id).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gitnext_core::{Blob, Commit, GitObject, Signature};
+    use gitnext_storage::MemoryStorage;
+
+    async fn commit(storage: &dyn Storage, parents: &[ObjectId], message: &str) -> ObjectId {
+        let signature = Signature {
+            name: "Test".to_string(),
+            email: "test@example.com".to_string(),
+            timestamp: 1_700_000_000,
+            timezone_offset: 0,
+            raw: None,
+        };
+        let object = GitObject::Commit(Commit {
+            tree: GitObject::Blob(Blob::new(bytes::Bytes::new())).canonical_hash(),
+            parents: parents.to_vec(),
+            author: signature.clone(),
+            committer: signature,
+            extra_headers: Vec::new(),
+            message: message.to_string(),
+            raw_message: None,
+        });
+        let id = object.canonical_hash();
+        storage.store_object(&id, &object).await.unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn test_engine_indexes_refs_and_new_commits() {
+        let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+        let root = commit(storage.as_ref(), &[], "root\n").await;
+        let main = commit(storage.as_ref(), &[root], "main\n").await;
+        storage.update_ref("refs/heads/main", &main).await.unwrap();
+
+        let engine = QueryEngine::open(storage.clone()).await.unwrap();
+        assert_eq!(engine.commit_count().await, 2);
+        assert!(CommitGraphIndex::load(storage.as_ref()).await.unwrap().is_some());
+
+        // Commits no ref names yet are indexed when queried
+        let topic = commit(storage.as_ref(), &[root], "topic\n").await;
+        assert_eq!(engine.merge_base(&main, &topic).await.unwrap(), vec![root]);
+        assert_eq!(engine.ahead_behind(&main, &topic).await.unwrap(), (1, 1));
+        assert!(engine.is_ancestor(&root, &topic).await.unwrap());
+        assert_eq!(engine.ancestors(&topic).await.unwrap(), vec![topic, root]);
+        assert_eq!(engine.commit_count().await, 3);
+
+        // A reopened engine starts from the saved index
+        let reopened = QueryEngine::open(storage.clone()).await.unwrap();
+        assert_eq!(reopened.commit_count().await, 3);
+        assert_eq!(reopened.refresh().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_updaters_sharing_storage() {
+        let storage = MemoryStorage::new();
+        let (one, other) = (IndexUpdater::default(), IndexUpdater::default());
+        let root = commit(&storage, &[], "root\n").await;
+        let second = commit(&storage, &[root], "second\n").await;
+        let third = commit(&storage, &[second], "third\n").await;
+
+        assert_eq!(one.update(&storage, [root]).await.unwrap(), 1);
+        assert_eq!(other.update(&storage, [second]).await.unwrap(), 1);
+        // The loaded indexes take in what the other updater saved
+        assert_eq!(one.update(&storage, [third]).await.unwrap(), 1);
+        assert_eq!(one.update(&storage, [third]).await.unwrap(), 0);
+
+        let graph = CommitGraphIndex::load(&storage).await.unwrap().unwrap();
+        let index = CommitIndex::load(&storage).await.unwrap().unwrap();
+        assert_eq!(graph.nodes().iter().map(|n| n.id).collect::<Vec<_>>(), vec![root, second, third]);
+        assert_eq!(index.entries().iter().map(|e| e.id).collect::<Vec<_>>(), vec![root, second, third]);
+    }
+}
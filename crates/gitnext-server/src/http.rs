@@ -0,0 +1,200 @@
+//! Smart HTTP endpoints
+//!
+//! Repositories are served under their registered name, as `git http-backend` lays
+//! them out:
+//!
+//! - `GET /<name>/info/refs?service=git-upload-pack|git-receive-pack`
+//! - `POST /<name>/git-upload-pack`
+//! - `POST /<name>/git-receive-pack`
+//!
+//! The `Git-Protocol: version=2` header selects protocol v2 for upload-pack. Request
+//! bodies may be gzip-compressed. The dumb HTTP protocol is not served.
+
+use crate::repository::{ServeOptions, ServedRepository};
+use crate::upload_pack::ProtocolVersion;
+use crate::{Result, ServerError};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::{header, HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use gitnext_protocol::pkt_line;
+use gitnext_storage::Storage;
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::{Arc, RwLock};
+use tokio::net::TcpListener;
+
+/// Largest accepted request body, after decompression
+const MAX_REQUEST_SIZE: usize = 1 << 30;
+
+const UPLOAD_PACK: &str = "git-upload-pack";
+const RECEIVE_PACK: &str = "git-receive-pack";
+
+/// Serves registered repositories to Git clients over smart HTTP
+#[derive(Default)]
+pub struct GitServer {
+    repositories: RwLock<HashMap<String, Arc<ServedRepository>>>,
+}
+
+/// A response body of pkt-lines for `service`
+fn service_response(service: &str, kind: &str, body: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, format!("application/x-{}-{}", service, kind)),
+            (header::CACHE_CONTROL, "no-cache".to_string()),
+        ],
+        body,
+    )
+        .into_response()
+}
+
+fn error_response(error: ServerError) -> Response {
+    let status = match &error {
+        ServerError::RepositoryNotFound(_) => StatusCode::NOT_FOUND,
+        ServerError::BadRequest(_) => StatusCode::BAD_REQUEST,
+        ServerError::Forbidden(_) => StatusCode::FORBIDDEN,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    (status, error.to_string()).into_response()
+}
+
+/// The `service` query parameter of an `info/refs` request
+fn requested_service(uri: &Uri) -> Option<&str> {
+    uri.query()?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix("service="))
+}
+
+/// Request body with any `Content-Encoding: gzip` undone
+fn decode_body(headers: &HeaderMap, body: Bytes) -> Result<Vec<u8>> {
+    match headers.get(header::CONTENT_ENCODING).and_then(|v| v.to_str().ok()) {
+        Some("gzip" | "x-gzip") => {
+            let mut decoded = Vec::new();
+            flate2::read::GzDecoder::new(&body[..])
+                .take(MAX_REQUEST_SIZE as u64 + 1)
+                .read_to_end(&mut decoded)?;
+            if decoded.len() > MAX_REQUEST_SIZE {
+                return Err(ServerError::BadRequest("Request body too large".to_string()));
+            }
+            Ok(decoded)
+        }
+        Some("identity") | None => Ok(body.to_vec()),
+        Some(other) => Err(ServerError::BadRequest(format!("Unsupported content encoding '{}'", other))),
+    }
+}
+
+impl GitServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `storage` as `name` with default options
+    pub async fn add_repository(&self, name: &str, storage: Arc<dyn Storage>) -> Result<Arc<ServedRepository>> {
+        self.add_repository_with_options(name, storage, ServeOptions::default()).await
+    }
+
+    /// Serve `storage` as `name`, replacing any repository of that name
+    pub async fn add_repository_with_options(
+        &self,
+        name: &str,
+        storage: Arc<dyn Storage>,
+        options: ServeOptions,
+    ) -> Result<Arc<ServedRepository>> {
+        let repository = Arc::new(ServedRepository::open(storage, options).await?);
+        let name = name.trim_matches('/').to_string();
+        self.repositories.write().expect("repository map poisoned").insert(name, repository.clone());
+        Ok(repository)
+    }
+
+    pub fn remove_repository(&self, name: &str) -> Option<Arc<ServedRepository>> {
+        self.repositories.write().expect("repository map poisoned").remove(name.trim_matches('/'))
+    }
+
+    /// The repository served as `name`; `name.git` also matches a repository registered as `name`
+    pub fn repository(&self, name: &str) -> Option<Arc<ServedRepository>> {
+        let repositories = self.repositories.read().expect("repository map poisoned");
+        repositories.get(name)
+            .or_else(|| name.strip_suffix(".git").and_then(|bare| repositories.get(bare)))
+            .cloned()
+    }
+
+    /// Axum router answering Git's smart HTTP requests
+    pub fn router(self: Arc<Self>) -> Router {
+        Router::new()
+            .fallback(handle)
+            .layer(DefaultBodyLimit::max(MAX_REQUEST_SIZE))
+            .with_state(self)
+    }
+
+    /// Accept connections on `listener` until the task is dropped
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> std::io::Result<()> {
+        axum::serve(listener, self.router()).await
+    }
+
+    fn require_repository(&self, name: &str) -> Result<Arc<ServedRepository>> {
+        self.repository(name).ok_or_else(|| ServerError::RepositoryNotFound(name.to_string()))
+    }
+
+    async fn info_refs(&self, name: &str, service: Option<&str>, version: ProtocolVersion) -> Result<Response> {
+        let repository = self.require_repository(name)?;
+        let (service, advertisement) = match service {
+            Some(UPLOAD_PACK) => (UPLOAD_PACK, repository.upload_pack_advertisement(version).await?),
+            Some(RECEIVE_PACK) if repository.options().allow_push => {
+                (RECEIVE_PACK, repository.receive_pack_advertisement().await?)
+            }
+            Some(RECEIVE_PACK) => return Err(ServerError::Forbidden(format!("Pushing to {} is disabled", name))),
+            _ => return Err(ServerError::RepositoryNotFound(format!("{} (dumb HTTP is not served)", name))),
+        };
+
+        // Protocol v2 responses start directly with the capabilities
+        let mut body = Vec::new();
+        if version == ProtocolVersion::V0 || service == RECEIVE_PACK {
+            pkt_line::write_line(&mut body, &format!("# service={}", service));
+            pkt_line::write_flush(&mut body);
+        }
+        body.extend_from_slice(&advertisement);
+        Ok(service_response(service, "advertisement", body))
+    }
+
+    async fn service(&self, name: &str, service: &str, version: ProtocolVersion, request: Vec<u8>) -> Result<Response> {
+        let repository = self.require_repository(name)?;
+        let result = match service {
+            UPLOAD_PACK => repository.upload_pack(version, &request).await,
+            _ if repository.options().allow_push => repository.receive_pack(&request).await,
+            _ => return Err(ServerError::Forbidden(format!("Pushing to {} is disabled", name))),
+        };
+
+        // Refused requests are reported in-band, where clients show them to the user
+        let body = match result {
+            Ok(body) => body,
+            Err(ServerError::BadRequest(message)) => {
+                let mut body = Vec::new();
+                pkt_line::write_line(&mut body, &format!("ERR {}", message));
+                body
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(service_response(service, "result", body))
+    }
+}
+
+async fn handle(State(server): State<Arc<GitServer>>, method: Method, uri: Uri, headers: HeaderMap, body: Bytes) -> Response {
+    let path = uri.path().trim_start_matches('/');
+    let version = ProtocolVersion::from_git_protocol(headers.get("Git-Protocol").and_then(|v| v.to_str().ok()));
+
+    let result = if let (Some(name), &Method::GET) = (path.strip_suffix("/info/refs"), &method) {
+        server.info_refs(name, requested_service(&uri), version).await
+    } else if let (Some((name, service)), &Method::POST) = (path.rsplit_once('/'), &method) {
+        match service {
+            UPLOAD_PACK | RECEIVE_PACK => match decode_body(&headers, body) {
+                Ok(request) => server.service(name, service, version, request).await,
+                Err(e) => Err(e),
+            },
+            _ => Err(ServerError::RepositoryNotFound(path.to_string())),
+        }
+    } else {
+        Err(ServerError::RepositoryNotFound(path.to_string()))
+    };
+    result.unwrap_or_else(error_response)
+}
@@ -0,0 +1,49 @@
+//! GitNext Server - hosting repositories for standard Git clients
+//!
+//! Serves `Storage`-backed repositories over Git's smart HTTP protocol:
+//! `info/refs`, `git-upload-pack` (protocol v0 and v2) and `git-receive-pack`.
+//! Canonical objects are translated to Git objects on the fly through the compat
+//! layer's `GitHashIndex`, and pushed Git objects are imported back into canonical
+//! form (ADR-001, ADR-006; Requirements 8.1, 8.4, 8.5).
+
+use gitnext_compat::CompatError;
+use gitnext_protocol::ProtocolError;
+use gitnext_storage::StorageError;
+use thiserror::Error;
+
+/// Server error types
+#[derive(Debug, Error)]
+pub enum ServerError {
+    #[error("Compatibility error: {0}")]
+    Compat(#[from] CompatError),
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] StorageError),
+
+    #[error("Protocol error: {0}")]
+    Protocol(#[from] ProtocolError),
+
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Repository not found: {0}")]
+    RepositoryNotFound(String),
+
+    /// A client request the server refuses; reported to the client as an `ERR` line
+    #[error("{0}")]
+    BadRequest(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+}
+
+pub type Result<T> = std::result::Result<T, ServerError>;
+
+pub mod http;
+pub mod receive_pack;
+pub mod repository;
+pub mod upload_pack;
+
+pub use http::GitServer;
+pub use repository::{ServeOptions, ServedRepository};
+pub use upload_pack::ProtocolVersion;
@@ -0,0 +1,223 @@
+//! `git-receive-pack`: accepting pushes
+//!
+//! Pushes speak protocol v0 only. The client sends ref update commands and a pack;
+//! the pack is resolved against stored objects (it may be thin), imported into
+//! canonical form with its Git hashes recorded, and each update is applied when the
+//! ref still has the value the client saw. That check is a compare-and-swap in
+//! storage, so servers sharing a database cannot clobber each other's updates;
+//! nor do they lose each other's Git hash mappings, which are merged on saving.
+//! Results go back as a `report-status`.
+
+use crate::repository::{direct_target, ServedRepository};
+use crate::upload_pack::{bad_request, write_v0_refs};
+use crate::Result;
+use gitnext_compat::{valid_ref_name, GitHashIndex};
+use gitnext_core::{GitHash, GitObject, ObjectId};
+use gitnext_protocol::pkt_line::{self, PktLine, PktReader};
+use gitnext_protocol::smart::{object_format_name, AGENT};
+use gitnext_protocol::{import_pack, resolve_thin_pack, PackReader};
+use gitnext_storage::{ReferenceTarget, Storage, StorageError};
+use std::collections::HashSet;
+
+/// Capabilities of receive-pack, besides `object-format` and `agent`
+const CAPABILITIES: &[&str] = &["report-status", "delete-refs", "ofs-delta"];
+
+/// One requested ref update; `None` stands for a missing ref
+struct Command {
+    name: String,
+    old: Option<GitHash>,
+    new: Option<GitHash>,
+}
+
+impl ServedRepository {
+    /// Ref advertisement of `git-receive-pack`
+    pub async fn receive_pack_advertisement(&self) -> Result<Vec<u8>> {
+        let mut index = self.lock_index().await?;
+        let advertisement = self.advertisement(&mut index).await?;
+        let refs: Vec<_> = advertisement.refs.into_iter().filter(|r| r.name != "HEAD").collect();
+
+        let mut capabilities: Vec<String> = CAPABILITIES.iter().map(|c| c.to_string()).collect();
+        capabilities.push(format!("object-format={}", object_format_name(self.hash_type())));
+        capabilities.push(format!("agent={}", AGENT));
+
+        let mut out = Vec::new();
+        write_v0_refs(&mut out, &refs, &capabilities.join(" "), self.hash_type(), false);
+        pkt_line::write_flush(&mut out);
+        Ok(out)
+    }
+
+    /// Apply one push: update commands, then the pack
+    pub async fn receive_pack(&self, request: &[u8]) -> Result<Vec<u8>> {
+        let mut reader = PktReader::new(request);
+        let mut commands = Vec::new();
+        let mut capabilities = HashSet::new();
+        loop {
+            let line = reader.next_line().map_err(|e| bad_request(e.to_string()))?;
+            let line = match line {
+                None | Some(PktLine::Flush) => break,
+                Some(line) => line.text().ok_or_else(|| bad_request("Expected a command"))?,
+            };
+            let command = match line.split_once('\0') {
+                Some((command, requested)) => {
+                    capabilities.extend(requested.split(' ').map(str::to_string));
+                    command
+                }
+                None => line,
+            };
+            let mut words = command.splitn(3, ' ');
+            let (Some(old), Some(new), Some(name)) = (words.next(), words.next(), words.next()) else {
+                return Err(bad_request(format!("Malformed command '{}'", command)));
+            };
+            commands.push(Command {
+                name: name.to_string(),
+                old: self.parse_update_hash(old)?,
+                new: self.parse_update_hash(new)?,
+            });
+        }
+        // An empty request probes whether the server is reachable
+        if commands.is_empty() {
+            return Ok(Vec::new());
+        }
+        let format = format!("object-format={}", object_format_name(self.hash_type()));
+        if let Some(other) = capabilities.iter().find(|c| c.starts_with("object-format=") && **c != format) {
+            return Err(bad_request(format!("Mismatched object format '{}'", other)));
+        }
+
+        let mut index = self.lock_index().await?;
+        let pack = reader.remaining();
+        let unpack = if pack.is_empty() {
+            Ok(())
+        } else {
+            self.unpack(&mut index, pack).await
+        };
+
+        let mut report = Vec::new();
+        match &unpack {
+            Ok(()) => {
+                pkt_line::write_line(&mut report, "unpack ok");
+                let advertisement = self.advertisement(&mut index).await?;
+                let head_branch = format!("refs/heads/{}", advertisement.head_branch);
+                for command in &commands {
+                    let current = advertisement.refs.iter()
+                        .find(|r| r.name == command.name)
+                        .map(|r| r.hash);
+                    let line = match self.update(&index, command, current, &head_branch).await? {
+                        Ok(()) => format!("ok {}", command.name),
+                        Err(reason) => format!("ng {} {}", command.name, reason),
+                    };
+                    pkt_line::write_line(&mut report, &line);
+                }
+            }
+            Err(e) => {
+                pkt_line::write_line(&mut report, &format!("unpack {}", e.to_string().replace('\n', " ")));
+                for command in &commands {
+                    pkt_line::write_line(&mut report, &format!("ng {} unpacker error", command.name));
+                }
+            }
+        }
+        pkt_line::write_flush(&mut report);
+        index.save().await?;
+
+        if capabilities.contains("report-status") {
+            Ok(report)
+        } else {
+            unpack.map(|_| Vec::new())
+        }
+    }
+
+    fn parse_update_hash(&self, hex: &str) -> Result<Option<GitHash>> {
+        let hash = GitHash::from_hex(hex)
+            .ok()
+            .filter(|hash| hash.hash_type() == self.hash_type())
+            .ok_or_else(|| bad_request(format!("Invalid object id '{}'", hex)))?;
+        Ok(Some(hash).filter(|hash| hash.as_bytes().iter().any(|&b| b != 0)))
+    }
+
+    /// Resolve and import a received pack
+    async fn unpack(&self, index: &mut GitHashIndex, data: &[u8]) -> Result<()> {
+        let mut pack = PackReader::new(self.hash_type()).read(data)?;
+        resolve_thin_pack(&mut pack, self.storage().as_ref(), index).await?;
+        import_pack(self.storage().as_ref(), index, &pack, true).await?;
+        Ok(())
+    }
+
+    /// Apply one command, returning the reason when it is refused
+    async fn update(
+        &self,
+        index: &GitHashIndex,
+        command: &Command,
+        current: Option<GitHash>,
+        head_branch: &str,
+    ) -> Result<std::result::Result<(), String>> {
+        let storage = self.storage();
+        if !valid_ref_name(&command.name) {
+            return Ok(Err("funny refname".to_string()));
+        }
+        if current != command.old {
+            return Ok(Err("stale info".to_string()));
+        }
+        let expected = command.old.and_then(|old| index.object_id(&old));
+
+        let Some(new) = command.new else {
+            if command.name == head_branch {
+                return Ok(Err("deletion of the current branch prohibited".to_string()));
+            }
+            return swap(storage.compare_and_swap_ref(&command.name, expected.as_ref(), None).await);
+        };
+
+        let target: Option<ObjectId> = index.object_id(&new);
+        let object = match target {
+            Some(id) => storage.load_object(&id).await?.map(|object| (id, object)),
+            None => None,
+        };
+        let Some((id, object)) = object else {
+            return Ok(Err("missing necessary objects".to_string()));
+        };
+        if command.name.starts_with("refs/heads/") && !matches!(object, GitObject::Commit(_)) {
+            return Ok(Err("non-commit object on a branch".to_string()));
+        }
+
+        if let Err(reason) = swap(storage.compare_and_swap_ref(&command.name, expected.as_ref(), Some(&id)).await)? {
+            return Ok(Err(reason));
+        }
+        if command.name == head_branch {
+            follow_branch(storage.as_ref(), head_branch).await?;
+        }
+        Ok(Ok(()))
+    }
+}
+
+/// Move HEAD, which gitnext-operations keeps at the current branch's commit, to
+/// where `branch` is now. Each move is a compare-and-swap from the HEAD read
+/// with the branch, retried when another push got in between, so whichever
+/// push finishes last leaves HEAD at the branch's latest commit.
+async fn follow_branch(storage: &dyn Storage, branch: &str) -> Result<()> {
+    loop {
+        let refs = storage.list_refs().await?;
+        let head = refs.iter().find(|r| r.name == "HEAD");
+        if head.is_some_and(|r| matches!(r.target, ReferenceTarget::Symbolic(_))) {
+            return Ok(());
+        }
+        let head = direct_target(&refs, "HEAD");
+        let Some(tip) = direct_target(&refs, branch) else {
+            return Ok(());
+        };
+        if head == Some(tip) {
+            return Ok(());
+        }
+        match storage.compare_and_swap_ref("HEAD", head.as_ref(), Some(&tip)).await {
+            Err(StorageError::ConcurrentModification) => continue,
+            result => return Ok(result?),
+        }
+    }
+}
+
+/// Outcome of a ref compare-and-swap: refused as stale when another update got
+/// there first
+fn swap(result: gitnext_storage::Result<()>) -> Result<std::result::Result<(), String>> {
+    match result {
+        Ok(()) => Ok(Ok(())),
+        Err(StorageError::ConcurrentModification) => Ok(Err("stale info".to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
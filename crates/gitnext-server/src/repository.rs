@@ -0,0 +1,179 @@
+//! A storage backend as seen by Git clients
+//!
+//! Clients see the refs under `refs/` except GitNext's internal ones, plus `HEAD`
+//! naming the current branch recorded by gitnext-operations. Every advertised object
+//! is given a Git hash of the served object format, derived once and persisted in the
+//! `GitHashIndex` next to the objects.
+
+use crate::Result;
+use gitnext_compat::{is_internal_ref, GitHashIndex, CURRENT_BRANCH_REF};
+use gitnext_core::{GitHash, GitHashType, GitObject, ObjectId};
+use gitnext_storage::metadata::load_blob_content;
+use gitnext_storage::{Reference, ReferenceTarget, Storage};
+use std::sync::Arc;
+use tokio::sync::{Mutex, MutexGuard};
+
+/// Branch `HEAD` names when the storage does not record a current branch
+pub const DEFAULT_BRANCH: &str = "main";
+
+/// Options for serving one repository
+#[derive(Debug, Clone)]
+pub struct ServeOptions {
+    /// Object format presented to clients
+    pub hash_type: GitHashType,
+    /// Accept pushes through `git-receive-pack`. Off by default: the server does
+    /// not authenticate anyone, so only enable it where every client may push.
+    pub allow_push: bool,
+}
+
+impl Default for ServeOptions {
+    fn default() -> Self {
+        Self {
+            hash_type: GitHashType::Sha1,
+            allow_push: false,
+        }
+    }
+}
+
+/// A ref as advertised to clients
+#[derive(Debug, Clone)]
+pub(crate) struct AdvertisedRef {
+    pub name: String,
+    pub id: ObjectId,
+    pub hash: GitHash,
+    /// Object an annotated tag ultimately points at
+    pub peeled: Option<GitHash>,
+    pub symref_target: Option<String>,
+}
+
+/// Refs of a repository in the form clients see them
+pub(crate) struct Advertisement {
+    /// `HEAD` (when it resolves) followed by the other refs in name order
+    pub refs: Vec<AdvertisedRef>,
+    /// Branch `HEAD` names, whether or not it exists yet
+    pub head_branch: String,
+}
+
+impl Advertisement {
+    pub fn get(&self, name: &str) -> Option<&AdvertisedRef> {
+        self.refs.iter().find(|r| r.name == name)
+    }
+}
+
+/// A repository served to Git clients
+///
+/// Requests are serialized on the repository's `GitHashIndex`, which keeps ref
+/// updates and the persisted hash map consistent under concurrent clients
+/// (Requirement 8.2).
+pub struct ServedRepository {
+    storage: Arc<dyn Storage>,
+    options: ServeOptions,
+    index: Mutex<GitHashIndex>,
+}
+
+pub(crate) fn direct_target(refs: &[Reference], name: &str) -> Option<ObjectId> {
+    refs.iter().find(|r| r.name == name).and_then(|r| match &r.target {
+        ReferenceTarget::Direct(id) => Some(*id),
+        ReferenceTarget::Symbolic(_) => None,
+    })
+}
+
+impl ServedRepository {
+    pub async fn open(storage: Arc<dyn Storage>, options: ServeOptions) -> Result<Self> {
+        let index = GitHashIndex::open(storage.clone()).await?;
+        Ok(Self {
+            storage,
+            options,
+            index: Mutex::new(index),
+        })
+    }
+
+    pub fn storage(&self) -> &Arc<dyn Storage> {
+        &self.storage
+    }
+
+    pub fn options(&self) -> &ServeOptions {
+        &self.options
+    }
+
+    pub fn hash_type(&self) -> GitHashType {
+        self.options.hash_type
+    }
+
+    /// Take the repository's index, waiting for other requests to finish with it,
+    /// with the mappings saved by others sharing the storage since
+    pub(crate) async fn lock_index(&self) -> Result<MutexGuard<'_, GitHashIndex>> {
+        let mut index = self.index.lock().await;
+        index.refresh().await?;
+        Ok(index)
+    }
+
+    /// The branch recorded by gitnext-operations, or `main`
+    async fn head_branch(&self, refs: &[Reference]) -> Result<String> {
+        if direct_target(refs, CURRENT_BRANCH_REF).is_none() {
+            return Ok(DEFAULT_BRANCH.to_string());
+        }
+        let content = load_blob_content(self.storage.as_ref(), CURRENT_BRANCH_REF).await?;
+        Ok(content.map_or_else(|| DEFAULT_BRANCH.to_string(), |content| String::from_utf8_lossy(&content).into_owned()))
+    }
+
+    /// Git hash of the non-tag object at the end of a chain of tags starting at `id`
+    async fn peel(&self, index: &mut GitHashIndex, id: ObjectId) -> Result<Option<GitHash>> {
+        let mut target = id;
+        while let Some(GitObject::Tag(tag)) = self.storage.load_object(&target).await? {
+            target = tag.target;
+        }
+        if target == id {
+            return Ok(None);
+        }
+        Ok(Some(index.derive(&target, self.hash_type()).await?))
+    }
+
+    /// Current refs with their Git hashes, deriving (and saving) any not yet known
+    pub(crate) async fn advertisement(&self, index: &mut GitHashIndex) -> Result<Advertisement> {
+        let hash_type = self.hash_type();
+        let all_refs = self.storage.list_refs().await?;
+        let head_branch = self.head_branch(&all_refs).await?;
+
+        let mut named: Vec<(String, ObjectId)> = all_refs.iter()
+            .filter(|r| r.name.starts_with("refs/") && !is_internal_ref(&r.name))
+            .filter_map(|r| match &r.target {
+                ReferenceTarget::Direct(id) => Some((r.name.clone(), *id)),
+                ReferenceTarget::Symbolic(_) => None,
+            })
+            .collect();
+        named.sort();
+
+        let mut refs = Vec::with_capacity(named.len() + 1);
+        for (name, id) in named {
+            let hash = index.derive(&id, hash_type).await?;
+            let peeled = self.peel(index, id).await?;
+            refs.push(AdvertisedRef { name, id, hash, peeled, symref_target: None });
+        }
+
+        // HEAD follows the current branch; a detached HEAD is shown as is
+        let branch_ref = format!("refs/heads/{}", head_branch);
+        let head = match refs.iter().find(|r| r.name == branch_ref) {
+            Some(branch) => Some(AdvertisedRef {
+                name: "HEAD".to_string(),
+                symref_target: Some(branch_ref.clone()),
+                ..branch.clone()
+            }),
+            None if direct_target(&all_refs, CURRENT_BRANCH_REF).is_none() => match direct_target(&all_refs, "HEAD") {
+                Some(id) => Some(AdvertisedRef {
+                    name: "HEAD".to_string(),
+                    id,
+                    hash: index.derive(&id, hash_type).await?,
+                    peeled: None,
+                    symref_target: None,
+                }),
+                None => None,
+            },
+            None => None,
+        };
+        refs.splice(0..0, head);
+
+        index.save().await?;
+        Ok(Advertisement { refs, head_branch })
+    }
+}
@@ -0,0 +1,483 @@
+//! `git-upload-pack`: ref advertisement, negotiation and pack generation
+//!
+//! Both protocol versions are served statelessly, as smart HTTP requires: every
+//! request repeats the client's wants and the haves found common so far. Protocol v0
+//! negotiates with `multi_ack_detailed` and sends the pack after `done`; protocol v2
+//! answers `ls-refs` and `fetch` commands. Packs are built from canonical objects and
+//! exclude everything reachable from the common commits.
+
+use crate::repository::{AdvertisedRef, Advertisement, ServedRepository};
+use crate::{Result, ServerError};
+use gitnext_compat::GitHashIndex;
+use gitnext_core::{GitHash, GitHashType, GitObject, ObjectId};
+use gitnext_protocol::pkt_line::{self, PktLine, PktReader, MAX_PAYLOAD};
+use gitnext_protocol::smart::{object_format_name, zero_hash, AGENT};
+use gitnext_protocol::{build_pack_with_options, PackWriterOptions};
+use std::collections::HashSet;
+
+/// Capabilities of a protocol v0 upload-pack, besides `symref`, `object-format` and `agent`
+const V0_CAPABILITIES: &[&str] = &[
+    "multi_ack_detailed",
+    "side-band-64k",
+    "thin-pack",
+    "ofs-delta",
+    "no-progress",
+    "include-tag",
+];
+
+/// Largest sideband payload with `side-band` (as opposed to `side-band-64k`)
+const SMALL_SIDEBAND_PAYLOAD: usize = 999;
+
+/// Wire protocol version a client asked for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolVersion {
+    V0,
+    V2,
+}
+
+impl ProtocolVersion {
+    /// Version requested by a `GIT_PROTOCOL` value (the `Git-Protocol` header over HTTP)
+    pub fn from_git_protocol(value: Option<&str>) -> Self {
+        match value {
+            Some(value) if value.split(':').any(|part| part == "version=2") => ProtocolVersion::V2,
+            _ => ProtocolVersion::V0,
+        }
+    }
+}
+
+pub(crate) fn bad_request(message: impl Into<String>) -> ServerError {
+    ServerError::BadRequest(message.into())
+}
+
+/// Text pkt-lines of a request, with flushes and delimiters
+pub(crate) fn request_lines(request: &[u8]) -> Result<Vec<PktLine<'_>>> {
+    let mut reader = PktReader::new(request);
+    let mut lines = Vec::new();
+    while let Some(line) = reader.next_line().map_err(|e| bad_request(e.to_string()))? {
+        lines.push(line);
+    }
+    Ok(lines)
+}
+
+fn text<'a>(line: &PktLine<'a>) -> Result<&'a str> {
+    line.text().ok_or_else(|| bad_request("Expected a text pkt-line"))
+}
+
+fn parse_client_hash(hex: &str, repo: &ServedRepository) -> Result<GitHash> {
+    match GitHash::from_hex(hex) {
+        Ok(hash) if hash.hash_type() == repo.hash_type() => Ok(hash),
+        _ => Err(bad_request(format!("Invalid object id '{}'", hex))),
+    }
+}
+
+/// Append `data` to `out` as pkt-lines on sideband `band`
+pub(crate) fn write_sideband(out: &mut Vec<u8>, band: u8, data: &[u8], max_payload: usize) {
+    for chunk in data.chunks(max_payload - 1) {
+        let mut payload = Vec::with_capacity(chunk.len() + 1);
+        payload.push(band);
+        payload.extend_from_slice(chunk);
+        pkt_line::write_data(out, &payload);
+    }
+}
+
+/// Options of one pack request
+#[derive(Debug, Default)]
+struct FetchRequest {
+    wants: Vec<GitHash>,
+    haves: Vec<GitHash>,
+    done: bool,
+    thin_pack: bool,
+    ofs_delta: bool,
+    include_tag: bool,
+}
+
+/// Commits reachable from `roots` through parents, tags peeled
+async fn ancestry(repo: &ServedRepository, roots: &[ObjectId]) -> Result<HashSet<ObjectId>> {
+    let storage = repo.storage();
+    let mut seen = HashSet::new();
+    let mut stack = roots.to_vec();
+    while let Some(id) = stack.pop() {
+        if !seen.insert(id) {
+            continue;
+        }
+        match storage.load_object(&id).await? {
+            Some(GitObject::Commit(commit)) => stack.extend(commit.parents.into_iter().filter(|p| !seen.contains(p))),
+            Some(GitObject::Tag(tag)) => stack.push(tag.target),
+            _ => {}
+        }
+    }
+    Ok(seen)
+}
+
+impl ServedRepository {
+    /// Ref advertisement (v0) or capability advertisement (v2) of `git-upload-pack`
+    pub async fn upload_pack_advertisement(&self, version: ProtocolVersion) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match version {
+            ProtocolVersion::V2 => {
+                pkt_line::write_line(&mut out, "version 2");
+                pkt_line::write_line(&mut out, &format!("agent={}", AGENT));
+                pkt_line::write_line(&mut out, "ls-refs=unborn");
+                pkt_line::write_line(&mut out, "fetch");
+                pkt_line::write_line(&mut out, "server-option");
+                pkt_line::write_line(&mut out, &format!("object-format={}", object_format_name(self.hash_type())));
+            }
+            ProtocolVersion::V0 => {
+                let mut index = self.lock_index().await?;
+                let advertisement = self.advertisement(&mut index).await?;
+                let mut capabilities: Vec<String> = V0_CAPABILITIES.iter().map(|c| c.to_string()).collect();
+                if let Some(target) = advertisement.get("HEAD").and_then(|head| head.symref_target.as_ref()) {
+                    capabilities.push(format!("symref=HEAD:{}", target));
+                }
+                capabilities.push(format!("object-format={}", object_format_name(self.hash_type())));
+                capabilities.push(format!("agent={}", AGENT));
+                write_v0_refs(&mut out, &advertisement.refs, &capabilities.join(" "), self.hash_type(), true);
+            }
+        }
+        pkt_line::write_flush(&mut out);
+        Ok(out)
+    }
+
+    /// Answer one `git-upload-pack` request
+    pub async fn upload_pack(&self, version: ProtocolVersion, request: &[u8]) -> Result<Vec<u8>> {
+        let lines = request_lines(request)?;
+        match version {
+            ProtocolVersion::V0 => self.upload_pack_v0(&lines).await,
+            ProtocolVersion::V2 => self.upload_pack_v2(&lines).await,
+        }
+    }
+
+    async fn upload_pack_v0(&self, lines: &[PktLine<'_>]) -> Result<Vec<u8>> {
+        let mut request = FetchRequest::default();
+        let mut capabilities = HashSet::new();
+        let mut lines = lines.iter();
+
+        for line in lines.by_ref() {
+            if *line == PktLine::Flush {
+                break;
+            }
+            let line = text(line)?;
+            let Some(want) = line.strip_prefix("want ") else {
+                return Err(bad_request(format!("Unsupported request '{}'", line)));
+            };
+            let mut words = want.split(' ');
+            request.wants.push(parse_client_hash(words.next().unwrap_or_default(), self)?);
+            capabilities.extend(words);
+        }
+        // A lone flush: the client wants nothing
+        if request.wants.is_empty() {
+            return Ok(Vec::new());
+        }
+        request.thin_pack = capabilities.contains("thin-pack");
+        request.ofs_delta = capabilities.contains("ofs-delta");
+        request.include_tag = capabilities.contains("include-tag");
+        let multi_ack_detailed = capabilities.contains("multi_ack_detailed");
+        let multi_ack = multi_ack_detailed || capabilities.contains("multi_ack");
+
+        let mut out = Vec::new();
+        let mut index = self.lock_index().await?;
+        let advertisement = self.advertisement(&mut index).await?;
+        let wants = self.resolve_wants(&index, &advertisement, &request.wants).await?;
+
+        let mut common = Vec::new();
+        let mut last_common = None;
+        for line in lines {
+            if *line == PktLine::Flush {
+                break;
+            }
+            let line = text(line)?;
+            if line == "done" {
+                request.done = true;
+                break;
+            }
+            let Some(hex) = line.strip_prefix("have ") else {
+                return Err(bad_request(format!("Unsupported request '{}'", line)));
+            };
+            let hash = parse_client_hash(hex, self)?;
+            let Some(id) = self.known_object(&index, &hash).await? else {
+                continue;
+            };
+            common.push(id);
+            last_common = Some(hash);
+            if multi_ack_detailed {
+                pkt_line::write_line(&mut out, &format!("ACK {} common", hash));
+            } else if multi_ack {
+                pkt_line::write_line(&mut out, &format!("ACK {} continue", hash));
+            } else if common.len() == 1 {
+                pkt_line::write_line(&mut out, &format!("ACK {}", hash));
+            }
+        }
+
+        if !request.done {
+            if let Some(last) = last_common {
+                if multi_ack_detailed && self.ready(&wants, &common).await? {
+                    pkt_line::write_line(&mut out, &format!("ACK {} ready", last));
+                }
+            }
+            if multi_ack || last_common.is_none() {
+                pkt_line::write_line(&mut out, "NAK");
+            }
+            return Ok(out);
+        }
+
+        match last_common {
+            Some(last) if multi_ack => pkt_line::write_line(&mut out, &format!("ACK {}", last)),
+            Some(_) => {}
+            None => pkt_line::write_line(&mut out, "NAK"),
+        }
+        let pack = self.pack(&mut index, &advertisement, &wants, &common, &request).await?;
+        if capabilities.contains("side-band-64k") {
+            write_sideband(&mut out, 1, &pack, MAX_PAYLOAD);
+            pkt_line::write_flush(&mut out);
+        } else if capabilities.contains("side-band") {
+            write_sideband(&mut out, 1, &pack, SMALL_SIDEBAND_PAYLOAD);
+            pkt_line::write_flush(&mut out);
+        } else {
+            out.extend_from_slice(&pack);
+        }
+        Ok(out)
+    }
+
+    async fn upload_pack_v2(&self, lines: &[PktLine<'_>]) -> Result<Vec<u8>> {
+        let mut lines = lines.iter().peekable();
+        let command = match lines.next() {
+            None | Some(PktLine::Flush) => return Ok(Vec::new()),
+            Some(line) => text(line)?.strip_prefix("command=")
+                .ok_or_else(|| bad_request("Expected a command"))?
+                .to_string(),
+        };
+
+        // Capabilities up to the delimiter, then arguments up to the flush
+        for line in lines.by_ref() {
+            match line {
+                PktLine::Delim | PktLine::Flush => break,
+                line => {
+                    if let Some(format) = text(line)?.strip_prefix("object-format=") {
+                        if format != object_format_name(self.hash_type()) {
+                            return Err(bad_request(format!("Mismatched object format '{}'", format)));
+                        }
+                    }
+                }
+            }
+        }
+        let mut arguments = Vec::new();
+        for line in lines {
+            if *line == PktLine::Flush {
+                break;
+            }
+            arguments.push(text(line)?);
+        }
+
+        match command.as_str() {
+            "ls-refs" => self.ls_refs(&arguments).await,
+            "fetch" => self.fetch(&arguments).await,
+            other => Err(bad_request(format!("Unknown command '{}'", other))),
+        }
+    }
+
+    async fn ls_refs(&self, arguments: &[&str]) -> Result<Vec<u8>> {
+        let symrefs = arguments.contains(&"symrefs");
+        let peel = arguments.contains(&"peel");
+        let unborn = arguments.contains(&"unborn");
+        let prefixes: Vec<&str> = arguments.iter().filter_map(|a| a.strip_prefix("ref-prefix ")).collect();
+
+        let mut index = self.lock_index().await?;
+        let advertisement = self.advertisement(&mut index).await?;
+        drop(index);
+
+        let wanted = |name: &str| prefixes.is_empty() || prefixes.iter().any(|prefix| name.starts_with(prefix));
+        let mut out = Vec::new();
+        if unborn && wanted("HEAD") && advertisement.get("HEAD").is_none() {
+            let target = format!("refs/heads/{}", advertisement.head_branch);
+            pkt_line::write_line(&mut out, &format!("unborn HEAD symref-target:{}", target));
+        }
+        for r in advertisement.refs.iter().filter(|r| wanted(&r.name)) {
+            let mut line = format!("{} {}", r.hash, r.name);
+            if let (true, Some(target)) = (symrefs, &r.symref_target) {
+                line.push_str(&format!(" symref-target:{}", target));
+            }
+            if let (true, Some(peeled)) = (peel, &r.peeled) {
+                line.push_str(&format!(" peeled:{}", peeled));
+            }
+            pkt_line::write_line(&mut out, &line);
+        }
+        pkt_line::write_flush(&mut out);
+        Ok(out)
+    }
+
+    async fn fetch(&self, arguments: &[&str]) -> Result<Vec<u8>> {
+        let mut request = FetchRequest::default();
+        let mut wait_for_done = false;
+        for argument in arguments {
+            let (name, value) = argument.split_once(' ').unwrap_or((argument, ""));
+            match name {
+                "want" => request.wants.push(parse_client_hash(value, self)?),
+                "have" => request.haves.push(parse_client_hash(value, self)?),
+                "done" => request.done = true,
+                "thin-pack" => request.thin_pack = true,
+                "ofs-delta" => request.ofs_delta = true,
+                "include-tag" => request.include_tag = true,
+                "wait-for-done" => wait_for_done = true,
+                "no-progress" => {}
+                other => return Err(bad_request(format!("Unsupported fetch argument '{}'", other))),
+            }
+        }
+        if request.wants.is_empty() {
+            return Err(bad_request("fetch without wants"));
+        }
+
+        let mut index = self.lock_index().await?;
+        let advertisement = self.advertisement(&mut index).await?;
+        let wants = self.resolve_wants(&index, &advertisement, &request.wants).await?;
+
+        let mut out = Vec::new();
+        let mut common = Vec::new();
+        if !request.done {
+            pkt_line::write_line(&mut out, "acknowledgments");
+            for have in &request.haves {
+                if let Some(id) = self.known_object(&index, have).await? {
+                    common.push(id);
+                    pkt_line::write_line(&mut out, &format!("ACK {}", have));
+                }
+            }
+            if common.is_empty() {
+                pkt_line::write_line(&mut out, "NAK");
+            }
+            if wait_for_done || common.is_empty() || !self.ready(&wants, &common).await? {
+                pkt_line::write_flush(&mut out);
+                return Ok(out);
+            }
+            pkt_line::write_line(&mut out, "ready");
+            pkt_line::write_delim(&mut out);
+        } else {
+            for have in &request.haves {
+                common.extend(self.known_object(&index, have).await?);
+            }
+        }
+
+        let pack = self.pack(&mut index, &advertisement, &wants, &common, &request).await?;
+        pkt_line::write_line(&mut out, "packfile");
+        write_sideband(&mut out, 1, &pack, MAX_PAYLOAD);
+        pkt_line::write_flush(&mut out);
+        Ok(out)
+    }
+
+    /// The stored object a client-supplied hash names, if there is one
+    async fn known_object(&self, index: &GitHashIndex, hash: &GitHash) -> Result<Option<ObjectId>> {
+        match index.object_id(hash) {
+            Some(id) if self.storage().load_object(&id).await?.is_some() => Ok(Some(id)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Objects of `wants`, which must be advertised or reachable from an advertised ref
+    async fn resolve_wants(
+        &self,
+        index: &GitHashIndex,
+        advertisement: &Advertisement,
+        wants: &[GitHash],
+    ) -> Result<Vec<ObjectId>> {
+        let mut resolved = Vec::with_capacity(wants.len());
+        let mut reachable = None;
+        for want in wants {
+            let not_ours = || bad_request(format!("upload-pack: not our ref {}", want));
+            let id = self.known_object(index, want).await?.ok_or_else(not_ours)?;
+            if !advertisement.refs.iter().any(|r| r.id == id) {
+                if reachable.is_none() {
+                    let tips: Vec<ObjectId> = advertisement.refs.iter().map(|r| r.id).collect();
+                    reachable = Some(ancestry(self, &tips).await?);
+                }
+                if !reachable.as_ref().is_some_and(|reachable| reachable.contains(&id)) {
+                    return Err(not_ours());
+                }
+            }
+            if !resolved.contains(&id) {
+                resolved.push(id);
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// Whether the common commits cover every want, so negotiation can stop
+    async fn ready(&self, wants: &[ObjectId], common: &[ObjectId]) -> Result<bool> {
+        let common: HashSet<&ObjectId> = common.iter().collect();
+        for want in wants {
+            let history = ancestry(self, &[*want]).await?;
+            if !history.iter().any(|id| common.contains(id)) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Pack of the wants without what the common commits reach, plus annotated tags
+    /// of sent commits when `include-tag` was asked for
+    async fn pack(
+        &self,
+        index: &mut GitHashIndex,
+        advertisement: &Advertisement,
+        wants: &[ObjectId],
+        common: &[ObjectId],
+        request: &FetchRequest,
+    ) -> Result<Vec<u8>> {
+        let mut tips = wants.to_vec();
+        if request.include_tag {
+            let tags: Vec<_> = advertisement.refs.iter()
+                .filter(|r| r.name.starts_with("refs/tags/") && r.peeled.is_some() && !tips.contains(&r.id))
+                .collect();
+            if !tags.is_empty() {
+                let sent = ancestry(self, wants).await?;
+                let had = ancestry(self, common).await?;
+                for tag in tags {
+                    let Some(target) = tag.peeled.and_then(|peeled| index.object_id(&peeled)) else {
+                        continue;
+                    };
+                    if sent.contains(&target) && !had.contains(&target) {
+                        tips.push(tag.id);
+                    }
+                }
+            }
+        }
+
+        let options = PackWriterOptions { ofs_delta: request.ofs_delta, ..PackWriterOptions::default() };
+        let written = build_pack_with_options(
+            self.storage().as_ref(),
+            index,
+            &tips,
+            common,
+            self.hash_type(),
+            request.thin_pack,
+            options,
+        )
+        .await?;
+        index.save().await?;
+        Ok(written.pack)
+    }
+}
+
+/// Append a v0 ref advertisement of `refs`, `capabilities` on the first line and
+/// peeled tags after their tag when `peel` is set
+pub(crate) fn write_v0_refs(
+    out: &mut Vec<u8>,
+    refs: &[AdvertisedRef],
+    capabilities: &str,
+    hash_type: GitHashType,
+    peel: bool,
+) {
+    if refs.is_empty() {
+        let line = format!("{} capabilities^{{}}\0{}\n", zero_hash(hash_type), capabilities);
+        pkt_line::write_data(out, line.as_bytes());
+        return;
+    }
+    for (i, r) in refs.iter().enumerate() {
+        let line = if i == 0 {
+            format!("{} {}\0{}\n", r.hash, r.name, capabilities)
+        } else {
+            format!("{} {}\n", r.hash, r.name)
+        };
+        pkt_line::write_data(out, line.as_bytes());
+        if let (true, Some(peeled)) = (peel, &r.peeled) {
+            pkt_line::write_line(out, &format!("{} {}^{{}}", peeled, r.name));
+        }
+    }
+}
@@ -0,0 +1,220 @@
+//! Stock `git` clients against the smart HTTP server (Property 24: Protocol
+//! Translation Correctness)
+
+#[path = "../../gitnext-protocol/tests/common/mod.rs"]
+mod common;
+
+use common::GitFixture;
+use gitnext_compat::GitImporter;
+use gitnext_core::GitHashType;
+use gitnext_server::{GitServer, ServeOptions};
+use gitnext_storage::{MemoryStorage, ReferenceTarget, Storage};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// A running server, stopped when dropped
+struct Running {
+    base: String,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Running {
+    async fn start(server: Arc<GitServer>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        let task = tokio::spawn(async move {
+            server.serve(listener).await.unwrap();
+        });
+        Self { base, task }
+    }
+
+    fn url(&self, name: &str) -> String {
+        format!("{}/{}", self.base, name)
+    }
+}
+
+/// A fixture with history and a tag, imported into fresh storage
+async fn imported_history(init_args: &[&str]) -> Option<(GitFixture, Arc<dyn Storage>)> {
+    let fixture = GitFixture::init(init_args)?;
+    fixture.build_delta_history(6);
+    fixture.git(&["branch", "side", "main~2"]);
+
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    GitImporter::new(storage.clone()).import(fixture.path()).await.unwrap();
+    Some((fixture, storage))
+}
+
+/// Clone `url` into a new scratch directory, with extra `git` options before `clone`
+fn clone(fixture: &GitFixture, url: &str, name: &str, options: &[&str]) -> PathBuf {
+    let path = fixture.scratch(name);
+    let mut args = options.to_vec();
+    args.extend(["clone", "-q", url, path.to_str().unwrap()]);
+    fixture.git_in(fixture.path(), &args);
+    path
+}
+
+fn rev_parse(fixture: &GitFixture, cwd: &Path, rev: &str) -> String {
+    fixture.git_in(cwd, &["rev-parse", rev])
+}
+
+/// Direct target of a stored ref, as a Git-facing client would not see it
+async fn stored_ref(storage: &Arc<dyn Storage>, name: &str) -> Option<gitnext_core::ObjectId> {
+    storage.list_refs().await.unwrap().into_iter()
+        .find(|r| r.name == name)
+        .and_then(|r| match r.target {
+            ReferenceTarget::Direct(id) => Some(id),
+            ReferenceTarget::Symbolic(_) => None,
+        })
+}
+
+/// Options of a repository that accepts pushes
+fn pushable() -> ServeOptions {
+    ServeOptions { allow_push: true, ..ServeOptions::default() }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_clone_with_protocol_v2_and_v0() {
+    let Some((fixture, storage)) = imported_history(&[]).await else { return };
+    let server = Arc::new(GitServer::new());
+    server.add_repository("project.git", storage).await.unwrap();
+    let running = Running::start(server).await;
+
+    for (name, options) in [("v2", &["-c", "protocol.version=2"][..]), ("v0", &["-c", "protocol.version=0"][..])] {
+        let path = clone(&fixture, &running.url("project.git"), name, options);
+        for rev in ["main", "origin/side", "v1", "v1^{commit}"] {
+            let expected = rev_parse(&fixture, fixture.path(), rev.trim_start_matches("origin/"));
+            assert_eq!(rev_parse(&fixture, &path, rev), expected, "{} over {}", rev, name);
+        }
+        assert_eq!(fixture.git_in(&path, &["symbolic-ref", "HEAD"]), "refs/heads/main");
+        assert_eq!(fixture.git_in(&path, &["show", "HEAD:src/lib.rs"]), fixture.git(&["show", "HEAD:src/lib.rs"]));
+        fixture.git_in(&path, &["fsck", "--strict", "--no-dangling"]);
+    }
+
+    // Unknown repositories are not found
+    let missing = fixture.git_output(fixture.path(), &["ls-remote", &running.url("missing.git")]);
+    assert!(!missing.status.success());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_push_then_fetch_incrementally() {
+    let Some((fixture, storage)) = imported_history(&[]).await else { return };
+    let server = Arc::new(GitServer::new());
+    server.add_repository_with_options("project.git", storage.clone(), pushable()).await.unwrap();
+    let running = Running::start(server).await;
+    let url = running.url("project.git");
+
+    let v2 = clone(&fixture, &url, "v2", &[]);
+    let v0 = clone(&fixture, &url, "v0", &["-c", "protocol.version=0"]);
+
+    // New history, pushed from the original repository, on main and a new branch
+    fixture.write("src/lib.rs", &format!("{}// pushed\n", fixture.git(&["show", "HEAD:src/lib.rs"])));
+    fixture.write("new.txt", "new file\n");
+    let tip = fixture.commit("Pushed change");
+    fixture.git(&["tag", "-a", "v2", "-m", "Second tag"]);
+    fixture.git(&["push", "-q", &url, "main", "main:refs/heads/feature", "v2"]);
+
+    let id = stored_ref(&storage, "refs/heads/main").await.unwrap();
+    assert_eq!(stored_ref(&storage, "HEAD").await, Some(id));
+    assert_eq!(stored_ref(&storage, "refs/heads/feature").await, Some(id));
+
+    for (path, options) in [(&v2, &[][..]), (&v0, &["-c", "protocol.version=0"][..])] {
+        let mut args = options.to_vec();
+        args.extend(["fetch", "-q", "origin"]);
+        fixture.git_in(path, &args);
+        assert_eq!(rev_parse(&fixture, path, "origin/main"), tip);
+        assert_eq!(rev_parse(&fixture, path, "origin/feature"), tip);
+        // Followed automatically because it points into fetched history
+        assert_eq!(rev_parse(&fixture, path, "v2"), rev_parse(&fixture, fixture.path(), "v2"));
+        fixture.git_in(path, &["fsck", "--strict", "--no-dangling"]);
+    }
+
+    // Pushing from a clone: a fast-forward and a branch deletion
+    fixture.git_in(&v2, &["config", "user.name", "Clone User"]);
+    std::fs::write(v2.join("clone.txt"), "from the clone\n").unwrap();
+    fixture.git_in(&v2, &["merge", "-q", "--ff-only", "origin/main"]);
+    fixture.git_in(&v2, &["add", "-A"]);
+    fixture.git_in(&v2, &["commit", "-q", "-m", "From the clone"]);
+    fixture.git_in(&v2, &["push", "-q", "origin", "main", ":feature"]);
+    fixture.git_in(&v0, &["-c", "protocol.version=0", "fetch", "-q", "--prune", "origin"]);
+    assert_eq!(rev_parse(&fixture, &v0, "origin/main"), rev_parse(&fixture, &v2, "main"));
+    assert!(fixture.git_in(&v0, &["branch", "-r", "--list", "origin/feature"]).is_empty());
+    assert!(stored_ref(&storage, "refs/heads/feature").await.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_push_into_empty_repository() {
+    let Some(fixture) = GitFixture::init(&[]) else { return };
+    fixture.build_delta_history(4);
+    let storage: Arc<dyn Storage> = Arc::new(MemoryStorage::new());
+    let server = Arc::new(GitServer::new());
+    server.add_repository_with_options("empty.git", storage.clone(), pushable()).await.unwrap();
+    let running = Running::start(server).await;
+    let url = running.url("empty.git");
+
+    // Cloning an empty repository still learns its default branch
+    let empty = clone(&fixture, &url, "empty", &[]);
+    assert!(fixture.git_output(&empty, &["rev-parse", "HEAD"]).status.code() != Some(0));
+
+    fixture.git(&["push", "-q", &url, "--all"]);
+    fixture.git(&["push", "-q", &url, "--tags"]);
+    assert!(stored_ref(&storage, "HEAD").await.is_some());
+
+    let path = clone(&fixture, &url, "copy", &[]);
+    assert_eq!(rev_parse(&fixture, &path, "HEAD"), fixture.git(&["rev-parse", "main"]));
+    assert_eq!(rev_parse(&fixture, &path, "v1"), fixture.git(&["rev-parse", "v1"]));
+    fixture.git_in(&path, &["fsck", "--strict", "--no-dangling"]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_refused_pushes() {
+    let Some((fixture, storage)) = imported_history(&[]).await else { return };
+    let server = Arc::new(GitServer::new());
+    server.add_repository_with_options("project.git", storage.clone(), pushable()).await.unwrap();
+    server.add_repository("readonly.git", storage.clone()).await.unwrap();
+    let running = Running::start(server).await;
+    let main = stored_ref(&storage, "refs/heads/main").await;
+
+    // The current branch cannot be deleted
+    let output = fixture.git_output(fixture.path(), &["push", &running.url("project.git"), ":main"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("deletion of the current branch prohibited"), "{}", stderr);
+
+    // Stale pushes are refused by the client's own fast-forward check; forced ones
+    // go through the server's compare-and-swap
+    fixture.git(&["reset", "-q", "--hard", "main~1"]);
+    let output = fixture.git_output(fixture.path(), &["push", &running.url("project.git"), "main"]);
+    assert!(!output.status.success());
+    assert_eq!(stored_ref(&storage, "refs/heads/main").await, main);
+    fixture.git(&["push", "-q", "--force", &running.url("project.git"), "main"]);
+    assert_ne!(stored_ref(&storage, "refs/heads/main").await, main);
+
+    // Repositories are read-only unless pushes are allowed: they can be cloned
+    // but not pushed to
+    clone(&fixture, &running.url("readonly.git"), "readonly", &[]);
+    let output = fixture.git_output(fixture.path(), &["push", &running.url("readonly.git"), "main:refs/heads/other"]);
+    assert!(!output.status.success());
+    assert!(stored_ref(&storage, "refs/heads/other").await.is_none());
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_serve_sha256_repository() {
+    let Some((fixture, storage)) = imported_history(&["--object-format=sha256"]).await else { return };
+    let server = Arc::new(GitServer::new());
+    let options = ServeOptions { hash_type: GitHashType::Sha256, ..ServeOptions::default() };
+    server.add_repository_with_options("project.git", storage, options).await.unwrap();
+    let running = Running::start(server).await;
+
+    let path = clone(&fixture, &running.url("project.git"), "clone", &[]);
+    assert_eq!(fixture.git_in(&path, &["rev-parse", "--show-object-format"]), "sha256");
+    assert_eq!(rev_parse(&fixture, &path, "main"), fixture.git(&["rev-parse", "main"]));
+    fixture.git_in(&path, &["fsck", "--strict", "--no-dangling"]);
+}
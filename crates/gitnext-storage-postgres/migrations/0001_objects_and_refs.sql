@@ -0,0 +1,13 @@
+-- Canonical objects, by their BLAKE3 id
+CREATE TABLE objects (
+    id BYTEA PRIMARY KEY,
+    object_type SMALLINT NOT NULL,
+    data BYTEA NOT NULL
+);
+
+-- References; target_type is 0 for an object id, 1 for a symbolic ref name
+CREATE TABLE refs (
+    name TEXT PRIMARY KEY,
+    target_type SMALLINT NOT NULL,
+    target_value BYTEA NOT NULL
+);
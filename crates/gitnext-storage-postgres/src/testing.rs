@@ -0,0 +1,135 @@
+//! A throwaway Postgres for tests
+//!
+//! `storage()` opens a `PostgresStorage` in a fresh schema of a server shared
+//! by the whole test process, so tests never see each other's objects or refs.
+//! The server is the one at `GITNEXT_TEST_POSTGRES_URL` when that is set.
+//! Otherwise it is started from the `initdb` and `postgres` binaries on the
+//! PATH, or in `pg_config --bindir`, in a temporary directory; it stops and the
+//! directory is removed when the test process exits. Postgres refuses to run as
+//! root, so as root it runs as `GITNEXT_TEST_POSTGRES_USER` (`postgres` by
+//! default). When there is neither, `url` and `storage` return `None` and the
+//! tests using them are skipped, which is said once on standard error.
+
+use crate::{PostgresOptions, PostgresStorage};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// Runs `postgres` in the background until its standard input closes, which
+/// happens when the test process exits, then removes the directory
+const SUPERVISOR: &str = r#"
+"$0" -D "$1/data" -k "$1" -h 127.0.0.1 -p "$2" -c max_connections=300 -c fsync=off >"$1/log" 2>&1 &
+server=$!
+cat >/dev/null
+kill -INT "$server"
+wait "$server"
+rm -rf "$1"
+"#;
+
+struct Server {
+    url: String,
+    /// Its standard input stays open for as long as the process runs
+    _supervisor: Option<Child>,
+}
+
+static SERVER: OnceLock<Option<Server>> = OnceLock::new();
+static SCHEMAS: AtomicUsize = AtomicUsize::new(0);
+
+/// URL of the test server, starting it if needed; `None` when there is none
+pub fn url() -> Option<&'static str> {
+    SERVER.get_or_init(start).as_ref().map(|server| server.url.as_str())
+}
+
+/// A schema name no other test in any process uses
+pub fn unique_schema() -> String {
+    format!("test_{}_{}", std::process::id(), SCHEMAS.fetch_add(1, Ordering::SeqCst))
+}
+
+/// A storage of its own on the test server, if there is one
+pub async fn storage() -> Option<PostgresStorage> {
+    let options = PostgresOptions { max_connections: 4, schema: Some(unique_schema()), ..Default::default() };
+    Some(PostgresStorage::connect_with(url()?, options).await.expect("test Postgres storage"))
+}
+
+fn start() -> Option<Server> {
+    if let Ok(url) = std::env::var("GITNEXT_TEST_POSTGRES_URL") {
+        return Some(Server { url, _supervisor: None });
+    }
+    let Some(bin) = bindir() else {
+        eprintln!("Skipping Postgres tests: initdb and postgres are not on the PATH and GITNEXT_TEST_POSTGRES_URL is not set");
+        return None;
+    };
+    let user = running_as_root()
+        .then(|| std::env::var("GITNEXT_TEST_POSTGRES_USER").unwrap_or_else(|_| "postgres".to_string()));
+    let dir = std::env::temp_dir().join(format!("gitnext-postgres-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create Postgres directory");
+    if let Some(user) = &user {
+        run(Command::new("chown").arg(user).arg(&dir));
+    }
+
+    let data = dir.join("data");
+    run(as_user(&user, &bin.join("initdb"))
+        .args(["-A", "trust", "-U", "postgres", "-E", "UTF8", "-D"])
+        .arg(&data)
+        .stdout(Stdio::null()));
+    let port = TcpListener::bind("127.0.0.1:0").and_then(|l| l.local_addr()).expect("free port").port();
+    let supervisor = as_user(&user, Path::new("sh"))
+        .arg("-c")
+        .arg(SUPERVISOR)
+        .arg(bin.join("postgres"))
+        .arg(&dir)
+        .arg(port.to_string())
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("start postgres");
+
+    // The eighth line of postmaster.pid says when the server is ready
+    let ready = || {
+        let pid = std::fs::read_to_string(data.join("postmaster.pid")).unwrap_or_default();
+        pid.lines().nth(7).is_some_and(|status| status.trim() == "ready")
+    };
+    let started = Instant::now();
+    while !ready() {
+        if started.elapsed() > Duration::from_secs(60) {
+            let log = std::fs::read_to_string(dir.join("log")).unwrap_or_default();
+            panic!("Postgres did not start:\n{}", log);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    Some(Server { url: format!("postgres://postgres@127.0.0.1:{}/postgres", port), _supervisor: Some(supervisor) })
+}
+
+/// Directory of the Postgres server binaries
+fn bindir() -> Option<PathBuf> {
+    let on_path = std::env::var_os("PATH")
+        .and_then(|path| std::env::split_paths(&path).find(|dir| dir.join("postgres").is_file() && dir.join("initdb").is_file()));
+    on_path.or_else(|| {
+        let output = Command::new("pg_config").arg("--bindir").output().ok()?;
+        let dir = PathBuf::from(String::from_utf8(output.stdout).ok()?.trim());
+        dir.join("postgres").is_file().then_some(dir)
+    })
+}
+
+fn running_as_root() -> bool {
+    Command::new("id").arg("-u").output().is_ok_and(|output| output.stdout.trim_ascii() == b"0")
+}
+
+/// `program`, run as `user` if given
+fn as_user(user: &Option<String>, program: &Path) -> Command {
+    match user {
+        Some(user) => {
+            let mut command = Command::new("runuser");
+            command.args(["-u", user, "--"]).arg(program).current_dir("/");
+            command
+        }
+        None => Command::new(program),
+    }
+}
+
+fn run(command: &mut Command) {
+    let status = command.status().unwrap_or_else(|e| panic!("{:?}: {}", command, e));
+    assert!(status.success(), "{:?}: {}", command, status);
+}
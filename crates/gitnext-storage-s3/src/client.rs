@@ -0,0 +1,156 @@
+//! A minimal S3 client: GET and PUT of whole or ranged objects, with
+//! conditional writes, signed with AWS Signature Version 4
+//!
+//! Requests use path-style URLs (`endpoint/bucket/key`), which AWS, MinIO and
+//! most S3-compatible stores accept.
+
+use chrono::Utc;
+use gitnext_storage::StorageError;
+use hmac::{Hmac, Mac};
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, ETAG, IF_MATCH, IF_NONE_MATCH, RANGE};
+use reqwest::{Method, StatusCode, Url};
+use sha2::{Digest, Sha256};
+
+use crate::{Result, S3Config};
+
+/// Bytes of a key left as they are when signing; everything else is
+/// percent-encoded
+fn unreserved(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b'~' | b'/')
+}
+
+/// When a PUT should go through
+pub(crate) enum Condition<'a> {
+    Always,
+    /// Only if the object's ETag is still this
+    IfMatch(&'a str),
+    /// Only if there is no such object yet
+    IfAbsent,
+}
+
+/// An object read back
+pub(crate) struct Object {
+    pub body: Vec<u8>,
+    pub etag: Option<String>,
+}
+
+/// An object written
+pub(crate) struct Stored {
+    /// `None` when the store did not send one
+    pub etag: Option<String>,
+}
+
+pub(crate) struct Client {
+    http: reqwest::Client,
+    config: S3Config,
+}
+
+impl Client {
+    pub fn new(config: S3Config) -> Self {
+        Self { http: reqwest::Client::new(), config }
+    }
+
+    /// The object at `key`, or bytes `start..end` of it
+    pub async fn get(&self, key: &str, range: Option<(u64, u64)>) -> Result<Option<Object>> {
+        let mut headers = HeaderMap::new();
+        if let Some((start, end)) = range {
+            headers.insert(RANGE, header(&format!("bytes={}-{}", start, end - 1))?);
+        }
+        let response = self.send(Method::GET, key, headers, Vec::new()).await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let etag = response.headers().get(ETAG).and_then(|v| v.to_str().ok()).map(str::to_string);
+                let body = response.bytes().await.map_err(|e| http_error("read", key, e))?;
+                Ok(Some(Object { body: body.to_vec(), etag }))
+            }
+            _ => Err(failure("GET", key, response).await),
+        }
+    }
+
+    /// Store `body` at `key`; `None` when `condition` did not hold
+    pub async fn put(&self, key: &str, body: Vec<u8>, condition: Condition<'_>) -> Result<Option<Stored>> {
+        let mut headers = HeaderMap::new();
+        match condition {
+            Condition::Always => {}
+            Condition::IfMatch(etag) => {
+                headers.insert(IF_MATCH, header(etag)?);
+            }
+            Condition::IfAbsent => {
+                headers.insert(IF_NONE_MATCH, HeaderValue::from_static("*"));
+            }
+        }
+        let response = self.send(Method::PUT, key, headers, body).await?;
+        match response.status() {
+            // 409 is what some stores answer to a conditional write racing another
+            StatusCode::PRECONDITION_FAILED | StatusCode::CONFLICT => Ok(None),
+            status if status.is_success() => {
+                let etag = response.headers().get(ETAG).and_then(|v| v.to_str().ok()).map(str::to_string);
+                Ok(Some(Stored { etag }))
+            }
+            _ => Err(failure("PUT", key, response).await),
+        }
+    }
+
+    async fn send(&self, method: Method, key: &str, mut headers: HeaderMap, body: Vec<u8>) -> Result<reqwest::Response> {
+        let config = &self.config;
+        let path: String = format!("/{}/{}", config.bucket, key).bytes()
+            .map(|b| if unreserved(b) { (b as char).to_string() } else { format!("%{:02X}", b) })
+            .collect();
+        let url = Url::parse(&format!("{}{}", config.endpoint.trim_end_matches('/'), path))
+            .map_err(|e| StorageError::Backend(format!("Invalid S3 endpoint '{}': {}", config.endpoint, e)))?;
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (Some(host), None) => host.to_string(),
+            (None, _) => return Err(StorageError::Backend(format!("S3 endpoint '{}' has no host", config.endpoint))),
+        };
+
+        let now = Utc::now();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload = hex::encode(Sha256::digest(&body));
+        let canonical = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\nhost;x-amz-content-sha256;x-amz-date\n{}",
+            method, path, host, payload, timestamp, payload
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, config.region);
+        let to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", timestamp, scope, hex::encode(Sha256::digest(canonical.as_bytes())));
+        let mut key = hmac(format!("AWS4{}", config.secret_key).as_bytes(), date.as_bytes());
+        for part in [config.region.as_str(), "s3", "aws4_request"] {
+            key = hmac(&key, part.as_bytes());
+        }
+        let signature = hex::encode(hmac(&key, to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={}",
+            config.access_key, scope, signature
+        );
+        headers.insert("x-amz-date", header(&timestamp)?);
+        headers.insert("x-amz-content-sha256", header(&payload)?);
+        headers.insert(AUTHORIZATION, header(&authorization)?);
+
+        self.http.request(method, url).headers(headers).body(body).send().await.map_err(|e| {
+            StorageError::BackendUnavailable { backend: format!("S3 at {}: {}", config.endpoint, e) }
+        })
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn header(value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|e| StorageError::Backend(format!("Invalid header value '{}': {}", value, e)))
+}
+
+fn http_error(what: &str, key: &str, error: reqwest::Error) -> StorageError {
+    StorageError::Backend(format!("Failed to {} S3 object {}: {}", what, key, error))
+}
+
+/// An unexpected response, with the store's error message
+async fn failure(method: &str, key: &str, response: reqwest::Response) -> StorageError {
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    StorageError::Backend(format!("S3 {} {} failed with {}: {}", method, key, status, body.trim()))
+}
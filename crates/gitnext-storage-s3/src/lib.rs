@@ -0,0 +1,647 @@
+//! S3-compatible storage backend for cloud deployments (Requirement 2.6)
+//!
+//! Objects are content-addressed keys, `objects/ab/cdef...` under the
+//! configured prefix. Transactions storing many objects can write them instead
+//! as one bundle, a pack-like `bundles/<name>.pack` of the objects back to back
+//! with a `bundles/<name>.idx` saying where each one is; objects in bundles are
+//! read with ranged GETs.
+//!
+//! Refs live in one small manifest, `manifest.json`, which also lists the
+//! bundles. It is only ever replaced by a conditional PUT (`If-Match` on the
+//! ETag it was read with, or `If-None-Match: *` when there is none yet), so a
+//! writer that lost a race re-reads it and tries again instead of overwriting
+//! someone else's update. A transaction's refs and bundle land in the same
+//! manifest write, so they become visible together.
+//!
+//! Object stores differ in how soon they show what was written, and
+//! `Consistency` says which kind this one is. Either way, ref updates never
+//! lose a concurrent update; what changes is how fresh reads are. This client
+//! always sees its own writes.
+
+use async_trait::async_trait;
+use client::{Client, Condition};
+use gitnext_core::{GitObject, ObjectId};
+use gitnext_storage::{points_at, Reference, ReferenceTarget, Storage, StorageError, Transaction};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+mod client;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
+
+pub type Result<T> = std::result::Result<T, StorageError>;
+
+/// Key of the manifest under the prefix
+const MANIFEST: &str = "manifest.json";
+
+/// Where the store is and how to sign in
+#[derive(Debug, Clone)]
+pub struct S3Config {
+    /// Such as `https://s3.us-east-1.amazonaws.com` or `http://localhost:9000`
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    /// Put before every key, so several repositories can share a bucket
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
+}
+
+impl S3Config {
+    /// `bucket` at `endpoint`, in `us-east-1`, without credentials
+    pub fn new(endpoint: impl Into<String>, bucket: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            region: "us-east-1".to_string(),
+            bucket: bucket.into(),
+            prefix: String::new(),
+            access_key: String::new(),
+            secret_key: String::new(),
+        }
+    }
+
+    pub fn with_region(mut self, region: impl Into<String>) -> Self {
+        self.region = region.into();
+        self
+    }
+
+    /// Keys start with `prefix`; a `/` is added if it has none at the end
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        if !self.prefix.is_empty() && !self.prefix.ends_with('/') {
+            self.prefix.push('/');
+        }
+        self
+    }
+
+    pub fn with_credentials(mut self, access_key: impl Into<String>, secret_key: impl Into<String>) -> Self {
+        self.access_key = access_key.into();
+        self.secret_key = secret_key.into();
+        self
+    }
+}
+
+/// How soon the store shows what was written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Consistency {
+    /// Reads see every completed write, as with AWS S3 and MinIO: the manifest
+    /// is read on every ref lookup, and an object that is not found is missing.
+    Strong,
+    /// Reads may be stale for a while. Ref lookups may answer from the
+    /// manifest this client last saw if it is at most `max_staleness` old, and
+    /// an object that is not found is asked for `read_retries` more times,
+    /// `retry_delay` apart, before it counts as missing.
+    Eventual { max_staleness: Duration, read_retries: u32, retry_delay: Duration },
+}
+
+impl Consistency {
+    /// `Eventual`, trusting the manifest for 5 seconds and retrying reads of
+    /// objects 3 times, 200ms apart
+    pub fn eventual() -> Self {
+        Consistency::Eventual {
+            max_staleness: Duration::from_secs(5),
+            read_retries: 3,
+            retry_delay: Duration::from_millis(200),
+        }
+    }
+}
+
+/// How `S3Storage` uses the store
+#[derive(Debug, Clone)]
+pub struct S3Options {
+    pub consistency: Consistency,
+    /// Transactions storing at least this many objects write them as one
+    /// bundle; with `None`, every object gets a key of its own
+    pub bundle_threshold: Option<usize>,
+    /// Attempts at replacing the manifest before giving up with
+    /// `ConcurrentModification`
+    pub manifest_attempts: u32,
+}
+
+impl Default for S3Options {
+    fn default() -> Self {
+        Self { consistency: Consistency::Strong, bundle_threshold: Some(16), manifest_attempts: 10 }
+    }
+}
+
+/// What `manifest.json` holds
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct Manifest {
+    refs: BTreeMap<String, Target>,
+    /// Names of the bundles, oldest first
+    bundles: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Target {
+    /// Hex object id
+    Direct(String),
+    Symbolic(String),
+}
+
+impl Target {
+    fn resolve(&self) -> Result<ReferenceTarget> {
+        match self {
+            Target::Direct(hex) => Ok(ReferenceTarget::Direct(parse_id(hex)?)),
+            Target::Symbolic(name) => Ok(ReferenceTarget::Symbolic(name.clone())),
+        }
+    }
+}
+
+/// A manifest as read or written, with its ETag
+#[derive(Clone)]
+struct Seen {
+    manifest: Manifest,
+    etag: Option<String>,
+    at: Instant,
+}
+
+/// Where an object is in a bundle
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    offset: u64,
+    len: u64,
+}
+
+struct Inner {
+    client: Client,
+    prefix: String,
+    options: S3Options,
+    seen: Mutex<Option<Seen>>,
+    /// Objects of the bundles read so far
+    bundled: RwLock<HashMap<ObjectId, (Arc<str>, Slot)>>,
+    loaded_bundles: Mutex<HashSet<String>>,
+}
+
+/// Storage in an S3-compatible object store
+pub struct S3Storage {
+    inner: Arc<Inner>,
+}
+
+impl S3Storage {
+    /// Storage at `config` with the default options: strong consistency, and
+    /// bundles for transactions of 16 objects or more
+    pub fn new(config: S3Config) -> Self {
+        Self::with_options(config, S3Options::default())
+    }
+
+    pub fn with_options(config: S3Config, options: S3Options) -> Self {
+        let prefix = config.prefix.clone();
+        let inner = Inner {
+            client: Client::new(config),
+            prefix,
+            options,
+            seen: Mutex::new(None),
+            bundled: RwLock::new(HashMap::new()),
+            loaded_bundles: Mutex::new(HashSet::new()),
+        };
+        Self { inner: Arc::new(inner) }
+    }
+
+    pub fn consistency(&self) -> Consistency {
+        self.inner.options.consistency
+    }
+}
+
+impl Inner {
+    fn object_key(&self, id: &ObjectId) -> String {
+        let hex = id.to_string();
+        format!("{}objects/{}/{}", self.prefix, &hex[..2], &hex[2..])
+    }
+
+    fn bundle_key(&self, name: &str, extension: &str) -> String {
+        format!("{}bundles/{}.{}", self.prefix, name, extension)
+    }
+
+    /// The manifest, read again unless the one last seen is recent enough to
+    /// trust
+    async fn manifest(&self) -> Result<Seen> {
+        if let Consistency::Eventual { max_staleness, .. } = self.options.consistency {
+            let seen = self.seen.lock().unwrap().clone();
+            if let Some(seen) = seen.filter(|seen| seen.at.elapsed() <= max_staleness) {
+                return Ok(seen);
+            }
+        }
+        self.read_manifest().await
+    }
+
+    async fn read_manifest(&self) -> Result<Seen> {
+        let key = format!("{}{}", self.prefix, MANIFEST);
+        let seen = match self.client.get(&key, None).await? {
+            Some(object) => Seen {
+                manifest: serde_json::from_slice(&object.body)
+                    .map_err(|e| StorageError::Serialization(format!("Invalid S3 manifest: {}", e)))?,
+                // Without it the manifest could only be replaced unconditionally
+                etag: Some(object.etag.ok_or_else(|| StorageError::Backend(format!("S3 manifest {} has no ETag", key)))?),
+                at: Instant::now(),
+            },
+            None => Seen { manifest: Manifest::default(), etag: None, at: Instant::now() },
+        };
+        *self.seen.lock().unwrap() = Some(seen.clone());
+        Ok(seen)
+    }
+
+    /// Apply `change` to the current manifest and write it back if nothing
+    /// else replaced it meanwhile, starting over when something did. `change`
+    /// returns false when there is nothing to write.
+    async fn update_manifest(&self, mut change: impl FnMut(&mut Manifest) -> Result<bool>) -> Result<()> {
+        let key = format!("{}{}", self.prefix, MANIFEST);
+        for _ in 0..self.options.manifest_attempts {
+            // Always fresh, so `change` never judges a stale manifest
+            let Seen { mut manifest, etag, .. } = self.read_manifest().await?;
+            if !change(&mut manifest)? {
+                return Ok(());
+            }
+            let body = serde_json::to_vec(&manifest)
+                .map_err(|e| StorageError::Serialization(format!("Failed to serialize S3 manifest: {}", e)))?;
+            let condition = match &etag {
+                Some(etag) => Condition::IfMatch(etag),
+                None => Condition::IfAbsent,
+            };
+            if let Some(stored) = self.client.put(&key, body, condition).await? {
+                // Without an ETag it is read again when next needed
+                *self.seen.lock().unwrap() = stored.etag.map(|etag| Seen { manifest, etag: Some(etag), at: Instant::now() });
+                return Ok(());
+            }
+        }
+        Err(StorageError::ConcurrentModification)
+    }
+
+    /// Read the indexes of bundles not read yet
+    async fn load_bundles(&self, manifest: &Manifest) -> Result<()> {
+        for name in &manifest.bundles {
+            if self.loaded_bundles.lock().unwrap().contains(name) {
+                continue;
+            }
+            let key = self.bundle_key(name, "idx");
+            let index = self.client.get(&key, None).await?
+                .ok_or_else(|| StorageError::Backend(format!("Bundle index {} is missing", key)))?;
+            let slots: Vec<(ObjectId, u64, u64)> = bincode::deserialize(&index.body)
+                .map_err(|e| StorageError::Serialization(format!("Invalid bundle index {}: {}", key, e)))?;
+            let bundle: Arc<str> = Arc::from(name.as_str());
+            let mut bundled = self.bundled.write().unwrap();
+            for (id, offset, len) in slots {
+                bundled.insert(id, (bundle.clone(), Slot { offset, len }));
+            }
+            self.loaded_bundles.lock().unwrap().insert(name.clone());
+        }
+        Ok(())
+    }
+
+    /// The object, looking in known bundles, then its own key, then bundles
+    /// added since
+    async fn find_object(&self, id: &ObjectId) -> Result<Option<GitObject>> {
+        let slot = self.bundled.read().unwrap().get(id).cloned();
+        if let Some((bundle, slot)) = slot {
+            return self.read_bundled(id, &bundle, slot).await.map(Some);
+        }
+        if let Some(object) = self.client.get(&self.object_key(id), None).await? {
+            return deserialize(id, &object.body).map(Some);
+        }
+        let seen = self.manifest().await?;
+        self.load_bundles(&seen.manifest).await?;
+        let slot = self.bundled.read().unwrap().get(id).cloned();
+        match slot {
+            Some((bundle, slot)) => self.read_bundled(id, &bundle, slot).await.map(Some),
+            None => Ok(None),
+        }
+    }
+
+    async fn read_bundled(&self, id: &ObjectId, bundle: &str, slot: Slot) -> Result<GitObject> {
+        let key = self.bundle_key(bundle, "pack");
+        let object = self.client.get(&key, Some((slot.offset, slot.offset + slot.len))).await?
+            .ok_or_else(|| StorageError::Backend(format!("Bundle {} is missing", key)))?;
+        deserialize(id, &object.body)
+    }
+
+    async fn put_object(&self, id: &ObjectId, object: &GitObject) -> Result<()> {
+        // Ids are content hashes, so an object already there is this one
+        self.client.put(&self.object_key(id), serialize(object)?, Condition::IfAbsent).await?;
+        Ok(())
+    }
+
+    /// Write `objects` as one bundle, returning its name
+    async fn put_bundle(&self, objects: &[(ObjectId, GitObject)]) -> Result<String> {
+        let mut pack = Vec::new();
+        let mut slots = Vec::new();
+        for (id, object) in objects {
+            let data = serialize(object)?;
+            slots.push((*id, pack.len() as u64, data.len() as u64));
+            pack.extend_from_slice(&data);
+        }
+        let name = blake3::hash(&pack).to_hex().to_string();
+        let index = bincode::serialize(&slots)
+            .map_err(|e| StorageError::Serialization(format!("Failed to serialize bundle index: {}", e)))?;
+        // The index goes last, so an index always has its pack
+        self.client.put(&self.bundle_key(&name, "pack"), pack, Condition::Always).await?;
+        self.client.put(&self.bundle_key(&name, "idx"), index, Condition::Always).await?;
+        Ok(name)
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    async fn store_object(&self, id: &ObjectId, object: &GitObject) -> Result<()> {
+        verify(id, object)?;
+        self.inner.put_object(id, object).await
+    }
+
+    async fn load_object(&self, id: &ObjectId) -> Result<Option<GitObject>> {
+        let (retries, delay) = match self.inner.options.consistency {
+            Consistency::Strong => (0, Duration::ZERO),
+            Consistency::Eventual { read_retries, retry_delay, .. } => (read_retries, retry_delay),
+        };
+        for attempt in 0..=retries {
+            if attempt > 0 {
+                tokio::time::sleep(delay).await;
+            }
+            if let Some(object) = self.inner.find_object(id).await? {
+                return Ok(Some(object));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn list_refs(&self) -> Result<Vec<Reference>> {
+        let seen = self.inner.manifest().await?;
+        seen.manifest.refs.iter()
+            .map(|(name, target)| Ok(Reference { name: name.clone(), target: target.resolve()? }))
+            .collect()
+    }
+
+    async fn update_ref(&self, name: &str, target: &ObjectId) -> Result<()> {
+        let target = Target::Direct(target.to_string());
+        self.inner.update_manifest(|manifest| Ok(manifest.refs.insert(name.to_string(), target.clone()).as_ref() != Some(&target))).await
+    }
+
+    async fn delete_ref(&self, name: &str) -> Result<()> {
+        self.inner.update_manifest(|manifest| match manifest.refs.remove(name) {
+            Some(_) => Ok(true),
+            None => Err(StorageError::RefNotFound { name: name.to_string() }),
+        }).await
+    }
+
+    async fn compare_and_swap_ref(&self, name: &str, expected: Option<&ObjectId>, new: Option<&ObjectId>) -> Result<()> {
+        self.inner.update_manifest(|manifest| {
+            let current = manifest.refs.get(name).map(Target::resolve).transpose()?;
+            if !points_at(current.as_ref(), expected) {
+                return Err(StorageError::ConcurrentModification);
+            }
+            match new {
+                Some(target) => manifest.refs.insert(name.to_string(), Target::Direct(target.to_string())),
+                None => manifest.refs.remove(name),
+            };
+            Ok(true)
+        }).await
+    }
+
+    async fn transaction(&self) -> Result<Box<dyn Transaction>> {
+        Ok(Box::new(S3Transaction { inner: self.inner.clone(), objects: Vec::new(), refs: Vec::new() }))
+    }
+}
+
+/// Writes staged until commit: objects first, then one manifest update with
+/// the refs and any bundle
+pub struct S3Transaction {
+    inner: Arc<Inner>,
+    objects: Vec<(ObjectId, GitObject)>,
+    refs: Vec<(String, ObjectId)>,
+}
+
+#[async_trait]
+impl Transaction for S3Transaction {
+    async fn store_object(&mut self, id: &ObjectId, object: &GitObject) -> Result<()> {
+        verify(id, object)?;
+        self.objects.push((*id, object.clone()));
+        Ok(())
+    }
+
+    async fn update_ref(&mut self, name: &str, target: &ObjectId) -> Result<()> {
+        self.refs.push((name.to_string(), *target));
+        Ok(())
+    }
+
+    async fn commit(self: Box<Self>) -> Result<()> {
+        let inner = &self.inner;
+        let bundle = match inner.options.bundle_threshold {
+            Some(threshold) if !self.objects.is_empty() && self.objects.len() >= threshold => {
+                Some(inner.put_bundle(&self.objects).await?)
+            }
+            _ => {
+                for (id, object) in &self.objects {
+                    inner.put_object(id, object).await?;
+                }
+                None
+            }
+        };
+        if bundle.is_none() && self.refs.is_empty() {
+            return Ok(());
+        }
+        inner.update_manifest(|manifest| {
+            if let Some(bundle) = &bundle {
+                if !manifest.bundles.contains(bundle) {
+                    manifest.bundles.push(bundle.clone());
+                }
+            }
+            for (name, target) in &self.refs {
+                manifest.refs.insert(name.clone(), Target::Direct(target.to_string()));
+            }
+            Ok(true)
+        }).await
+    }
+
+    async fn rollback(self: Box<Self>) -> Result<()> {
+        // Nothing was written yet
+        Ok(())
+    }
+}
+
+/// Verify that the object hash matches the provided ID
+fn verify(id: &ObjectId, object: &GitObject) -> Result<()> {
+    let computed_id = object.canonical_hash();
+    if computed_id != *id {
+        return Err(StorageError::CorruptionDetected {
+            id: *id,
+            details: format!("Object hash mismatch: expected {}, got {}", id, computed_id),
+        });
+    }
+    Ok(())
+}
+
+fn serialize(object: &GitObject) -> Result<Vec<u8>> {
+    bincode::serialize(object).map_err(|e| StorageError::Serialization(format!("Failed to serialize object: {}", e)))
+}
+
+fn deserialize(id: &ObjectId, data: &[u8]) -> Result<GitObject> {
+    bincode::deserialize(data)
+        .map_err(|e| StorageError::CorruptionDetected { id: *id, details: format!("Failed to deserialize object: {}", e) })
+}
+
+fn parse_id(hex: &str) -> Result<ObjectId> {
+    let bytes = hex::decode(hex).ok().and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+        .ok_or_else(|| StorageError::Serialization(format!("Invalid object id '{}' in S3 manifest", hex)))?;
+    Ok(ObjectId::from_blake3_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gitnext_core::Blob;
+    use testing::FakeS3;
+
+    fn blob(content: &str) -> (ObjectId, GitObject) {
+        let object = GitObject::Blob(Blob::new(bytes::Bytes::from(content.to_string())));
+        (object.canonical_hash(), object)
+    }
+
+    fn direct(storage_refs: Vec<Reference>, name: &str) -> Option<ObjectId> {
+        storage_refs.into_iter().find(|r| r.name == name).and_then(|r| match r.target {
+            ReferenceTarget::Direct(id) => Some(id),
+            ReferenceTarget::Symbolic(_) => None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_pushers_do_not_clobber() {
+        let s3 = FakeS3::start().await;
+        let storage = S3Storage::new(s3.config("repo"));
+        let (base, object) = blob("base");
+        storage.store_object(&base, &object).await.unwrap();
+        storage.compare_and_swap_ref("refs/heads/main", None, Some(&base)).await.unwrap();
+
+        // Every pusher is a client of its own that saw `base`; exactly one wins
+        let mut tasks = Vec::new();
+        for n in 0..8 {
+            let pusher = S3Storage::new(s3.config("repo"));
+            tasks.push(tokio::spawn(async move {
+                let (id, object) = blob(&format!("push {}", n));
+                pusher.store_object(&id, &object).await.unwrap();
+                pusher.compare_and_swap_ref("refs/heads/main", Some(&base), Some(&id)).await.map(|_| id)
+            }));
+        }
+        let mut winners = Vec::new();
+        for task in tasks {
+            match task.await.unwrap() {
+                Ok(id) => winners.push(id),
+                Err(e) => assert!(matches!(e, StorageError::ConcurrentModification), "{}", e),
+            }
+        }
+        assert_eq!(winners.len(), 1);
+        assert_eq!(direct(storage.list_refs().await.unwrap(), "refs/heads/main"), Some(winners[0]));
+
+        // Plain updates of different refs all land, retrying on lost races
+        let mut tasks = Vec::new();
+        for n in 0..8 {
+            let writer = S3Storage::new(s3.config("repo"));
+            tasks.push(tokio::spawn(async move { writer.update_ref(&format!("refs/heads/b{}", n), &base).await }));
+        }
+        for task in tasks {
+            task.await.unwrap().unwrap();
+        }
+        assert_eq!(storage.list_refs().await.unwrap().len(), 9);
+        assert!(matches!(storage.delete_ref("refs/heads/none").await, Err(StorageError::RefNotFound { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_transactions_bundle_objects() {
+        let s3 = FakeS3::start().await;
+        let options = S3Options { bundle_threshold: Some(4), ..Default::default() };
+        let storage = S3Storage::with_options(s3.config("repo"), options.clone());
+
+        let objects: Vec<_> = (0..5).map(|n| blob(&format!("bundled {}", n))).collect();
+        let mut transaction = storage.transaction().await.unwrap();
+        for (id, object) in &objects {
+            transaction.store_object(id, object).await.unwrap();
+        }
+        transaction.update_ref("refs/heads/main", &objects[4].0).await.unwrap();
+        transaction.commit().await.unwrap();
+        let keys = s3.keys();
+        assert!(keys.iter().all(|key| !key.starts_with("repo/objects/")), "{:?}", keys);
+        assert_eq!(keys.iter().filter(|key| key.starts_with("repo/bundles/")).count(), 2);
+
+        // Another client finds them through the manifest
+        let reader = S3Storage::with_options(s3.config("repo"), options);
+        for (id, _) in &objects {
+            assert_eq!(reader.load_object(id).await.unwrap().map(|o| o.canonical_hash()), Some(*id));
+        }
+        assert_eq!(direct(reader.list_refs().await.unwrap(), "refs/heads/main"), Some(objects[4].0));
+
+        // Small transactions store objects loose, and rolled back ones nothing
+        let (loose, object) = blob("loose");
+        let mut transaction = storage.transaction().await.unwrap();
+        transaction.store_object(&loose, &object).await.unwrap();
+        transaction.commit().await.unwrap();
+        assert!(s3.keys().iter().any(|key| key.starts_with("repo/objects/")));
+        let (dropped, object) = blob("dropped");
+        let mut transaction = storage.transaction().await.unwrap();
+        transaction.store_object(&dropped, &object).await.unwrap();
+        transaction.update_ref("refs/heads/dropped", &dropped).await.unwrap();
+        let written = s3.keys();
+        transaction.rollback().await.unwrap();
+        assert_eq!(s3.keys(), written);
+        assert!(reader.load_object(&dropped).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_eventual_consistency() {
+        let s3 = FakeS3::start().await;
+        let writer = S3Storage::new(s3.config("repo"));
+        let (one, object) = blob("one");
+        writer.store_object(&one, &object).await.unwrap();
+        writer.update_ref("refs/heads/main", &one).await.unwrap();
+
+        let consistency = Consistency::Eventual {
+            max_staleness: Duration::from_secs(60),
+            read_retries: 3,
+            retry_delay: Duration::from_millis(10),
+        };
+        let options = S3Options { consistency, ..Default::default() };
+        let eventual = S3Storage::with_options(s3.config("repo"), options);
+        let strong = S3Storage::new(s3.config("repo"));
+        assert_eq!(eventual.consistency(), consistency);
+        assert_eq!(direct(eventual.list_refs().await.unwrap(), "refs/heads/main"), Some(one));
+
+        // New objects take two reads to show up; only the eventual client waits
+        s3.set_read_lag(2);
+        let (two, object) = blob("two");
+        writer.store_object(&two, &object).await.unwrap();
+        writer.update_ref("refs/heads/main", &two).await.unwrap();
+        assert!(strong.load_object(&two).await.unwrap().is_none());
+        s3.set_read_lag(2);
+        let (three, object) = blob("three");
+        writer.store_object(&three, &object).await.unwrap();
+        assert_eq!(eventual.load_object(&three).await.unwrap().map(|o| o.canonical_hash()), Some(three));
+
+        // The eventual client answers from the manifest it saw, until it writes
+        assert_eq!(direct(strong.list_refs().await.unwrap(), "refs/heads/main"), Some(two));
+        assert_eq!(direct(eventual.list_refs().await.unwrap(), "refs/heads/main"), Some(one));
+        eventual.update_ref("refs/heads/other", &one).await.unwrap();
+        let refs = eventual.list_refs().await.unwrap();
+        assert_eq!(direct(refs.clone(), "refs/heads/main"), Some(two));
+        assert_eq!(direct(refs, "refs/heads/other"), Some(one));
+
+        // Compare-and-swap always checks the current manifest
+        assert!(matches!(
+            eventual.compare_and_swap_ref("refs/heads/main", Some(&one), Some(&three)).await,
+            Err(StorageError::ConcurrentModification)
+        ));
+        eventual.compare_and_swap_ref("refs/heads/main", Some(&two), Some(&three)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_manifest_without_etag_is_refused() {
+        let s3 = FakeS3::start().await;
+        s3.omit_etags();
+        let storage = S3Storage::new(s3.config("repo"));
+        let (one, object) = blob("one");
+        storage.store_object(&one, &object).await.unwrap();
+
+        // Creating the manifest needs no ETag, but it cannot be replaced safely
+        storage.update_ref("refs/heads/main", &one).await.unwrap();
+        assert!(matches!(storage.update_ref("refs/heads/other", &one).await, Err(StorageError::Backend(_))));
+        assert!(matches!(storage.list_refs().await, Err(StorageError::Backend(_))));
+    }
+}
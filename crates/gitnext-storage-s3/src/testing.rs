@@ -0,0 +1,183 @@
+//! An S3 for tests
+//!
+//! `FakeS3` is an in-process server speaking the part of S3 the backend uses:
+//! GET with ranges, PUT with `If-Match` and `If-None-Match: *`, and ETags. It
+//! does not check signatures, only that requests are signed at all. It can lag
+//! like an eventually consistent store, answering 404 to the first reads of a
+//! new key.
+//!
+//! `storage()` opens an `S3Storage` under a fresh prefix, on a `FakeS3` started
+//! on the current runtime, or on the store at `GITNEXT_TEST_S3_ENDPOINT` when
+//! that is set, such as a local MinIO. The bucket is `GITNEXT_TEST_S3_BUCKET`
+//! (`gitnext-test` by default) and must exist; the credentials are
+//! `GITNEXT_TEST_S3_ACCESS_KEY` and `GITNEXT_TEST_S3_SECRET_KEY`.
+
+use crate::{S3Config, S3Options, S3Storage};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::header::{AUTHORIZATION, ETAG, IF_MATCH, IF_NONE_MATCH, RANGE};
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+static PREFIXES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Default)]
+struct Bucket {
+    /// Objects by path, with their ETags
+    objects: HashMap<String, (Bytes, String)>,
+    writes: u64,
+    /// 404s each new key answers with before it shows up
+    lag: u32,
+    unseen: HashMap<String, u32>,
+    /// Leave ETags out of responses
+    no_etags: bool,
+}
+
+/// An in-process S3 server
+#[derive(Clone)]
+pub struct FakeS3 {
+    endpoint: String,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl FakeS3 {
+    /// Serve on a free local port, on the current runtime
+    pub async fn start() -> Self {
+        let bucket = Arc::new(Mutex::new(Bucket::default()));
+        let router = Router::new().fallback(handle).layer(DefaultBodyLimit::disable()).with_state(bucket.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind fake S3");
+        let endpoint = format!("http://{}", listener.local_addr().expect("fake S3 address"));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        Self { endpoint, bucket }
+    }
+
+    pub fn endpoint(&self) -> &str {
+        &self.endpoint
+    }
+
+    /// Config for bucket `test` under `prefix`
+    pub fn config(&self, prefix: &str) -> S3Config {
+        S3Config::new(&self.endpoint, "test").with_prefix(prefix).with_credentials("test", "test")
+    }
+
+    /// Make keys written from now on answer 404 to their first `reads` GETs
+    pub fn set_read_lag(&self, reads: u32) {
+        self.bucket.lock().unwrap().lag = reads;
+    }
+
+    /// Leave ETags out of responses from now on, like some S3 lookalikes
+    pub fn omit_etags(&self) {
+        self.bucket.lock().unwrap().no_etags = true;
+    }
+
+    /// Keys stored so far, without the bucket, sorted
+    pub fn keys(&self) -> Vec<String> {
+        let bucket = self.bucket.lock().unwrap();
+        let mut keys: Vec<String> = bucket.objects.keys()
+            .map(|path| path.split_once('/').map_or(path.as_str(), |(_, key)| key).to_string())
+            .collect();
+        keys.sort();
+        keys
+    }
+}
+
+/// A storage of its own on the test store
+pub async fn storage() -> S3Storage {
+    storage_with(S3Options::default()).await
+}
+
+pub async fn storage_with(options: S3Options) -> S3Storage {
+    let prefix = format!("test-{}-{}", std::process::id(), PREFIXES.fetch_add(1, Ordering::SeqCst));
+    let config = match std::env::var("GITNEXT_TEST_S3_ENDPOINT") {
+        Ok(endpoint) => {
+            let var = |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.to_string());
+            S3Config::new(endpoint, var("GITNEXT_TEST_S3_BUCKET", "gitnext-test"))
+                .with_region(var("GITNEXT_TEST_S3_REGION", "us-east-1"))
+                .with_prefix(prefix)
+                .with_credentials(var("GITNEXT_TEST_S3_ACCESS_KEY", ""), var("GITNEXT_TEST_S3_SECRET_KEY", ""))
+        }
+        Err(_) => FakeS3::start().await.config(&prefix),
+    };
+    S3Storage::with_options(config, options)
+}
+
+async fn handle(State(bucket): State<Arc<Mutex<Bucket>>>, method: Method, uri: Uri, headers: HeaderMap, body: Bytes) -> Response {
+    if !headers.contains_key(AUTHORIZATION) {
+        return error(StatusCode::FORBIDDEN, "AccessDenied");
+    }
+    let Some(path) = decode(uri.path().trim_start_matches('/')) else {
+        return error(StatusCode::BAD_REQUEST, "InvalidURI");
+    };
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    let mut bucket = bucket.lock().unwrap();
+    match method {
+        Method::GET => {
+            if let Some(reads) = bucket.unseen.get_mut(&path).filter(|reads| **reads > 0) {
+                *reads -= 1;
+                return error(StatusCode::NOT_FOUND, "NoSuchKey");
+            }
+            let Some((data, etag)) = bucket.objects.get(&path) else {
+                return error(StatusCode::NOT_FOUND, "NoSuchKey");
+            };
+            let mut response = match header(RANGE).and_then(|range| range_of(range, data.len())) {
+                Some((start, end)) => (StatusCode::PARTIAL_CONTENT, [(ETAG, etag.clone())], data.slice(start..end)).into_response(),
+                None => (StatusCode::OK, [(ETAG, etag.clone())], data.clone()).into_response(),
+            };
+            if bucket.no_etags {
+                response.headers_mut().remove(ETAG);
+            }
+            response
+        }
+        Method::PUT => {
+            let current = bucket.objects.get(&path).map(|(_, etag)| etag.as_str());
+            if header(IF_MATCH).is_some_and(|expected| Some(expected) != current)
+                || (header(IF_NONE_MATCH) == Some("*") && current.is_some())
+            {
+                return error(StatusCode::PRECONDITION_FAILED, "PreconditionFailed");
+            }
+            if current.is_none() && bucket.lag > 0 {
+                let lag = bucket.lag;
+                bucket.unseen.insert(path.clone(), lag);
+            }
+            bucket.writes += 1;
+            let etag = format!("\"{}\"", bucket.writes);
+            bucket.objects.insert(path, (body, etag.clone()));
+            if bucket.no_etags {
+                return StatusCode::OK.into_response();
+            }
+            (StatusCode::OK, [(ETAG, etag)]).into_response()
+        }
+        _ => error(StatusCode::METHOD_NOT_ALLOWED, "MethodNotAllowed"),
+    }
+}
+
+fn error(status: StatusCode, code: &str) -> Response {
+    (status, format!("<Error><Code>{}</Code></Error>", code)).into_response()
+}
+
+/// `bytes=start-end` as a half-open range within `len`
+fn range_of(range: &str, len: usize) -> Option<(usize, usize)> {
+    let (start, end) = range.strip_prefix("bytes=")?.split_once('-')?;
+    let (start, end): (usize, usize) = (start.parse().ok()?, end.parse().ok()?);
+    (start <= end && end < len).then_some((start, end + 1))
+}
+
+fn decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&b, tail)) = rest.split_first() {
+        if b == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(b);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
@@ -50,6 +50,23 @@ pub trait Storage: Send + Sync {
     
     /// Delete a reference
     async fn delete_ref(&self, name: &str) -> Result<()>;
+
+    /// Point `name` at `new`, or delete it when `new` is `None`, only if it still
+    /// points at `expected` (`None`: it does not exist); otherwise fail with
+    /// `ConcurrentModification` and change nothing.
+    ///
+    /// The default reads and then writes, which is only safe while callers are
+    /// serialized some other way; backends shared between processes override it.
+    async fn compare_and_swap_ref(&self, name: &str, expected: Option<&ObjectId>, new: Option<&ObjectId>) -> Result<()> {
+        let current = self.list_refs().await?.into_iter().find(|r| r.name == name).map(|r| r.target);
+        if !points_at(current.as_ref(), expected) {
+            return Err(StorageError::ConcurrentModification);
+        }
+        match new {
+            Some(target) => self.update_ref(name, target).await,
+            None => self.delete_ref(name).await,
+        }
+    }
     
     /// Begin a new transaction for atomic operations
     async fn transaction(&self) -> Result<Box<dyn Transaction>>;
@@ -85,6 +102,16 @@ pub enum ReferenceTarget {
     Symbolic(String),
 }
 
+/// Whether a ref currently at `current` matches `expected`: both missing, or
+/// both the same object
+pub fn points_at(current: Option<&ReferenceTarget>, expected: Option<&ObjectId>) -> bool {
+    match (current, expected) {
+        (None, None) => true,
+        (Some(ReferenceTarget::Direct(id)), Some(expected)) => id == expected,
+        _ => false,
+    }
+}
+
 /// Error recovery mechanisms for storage operations
 pub struct RecoveryManager {
     storage: Arc<dyn Storage>,
@@ -104,12 +131,22 @@ impl RecoveryManager {
     
     /// Validate storage consistency
     pub async fn validate_consistency(&self) -> Result<Vec<String>> {
-        // Implementation would check for orphaned objects, broken references, etc.
+        // Check for broken references; orphaned objects are not detected yet
         // Returns list of issues found
-        Ok(Vec::new())
+        let mut issues = Vec::new();
+        for reference in self.storage.list_refs().await? {
+            if let ReferenceTarget::Direct(id) = reference.target {
+                if self.storage.load_object(&id).await?.is_none() {
+                    issues.push(format!("Reference {} points at missing object {}", reference.name, id));
+                }
+            }
+        }
+        Ok(issues)
     }
 }
 
+pub mod metadata;
+
 // Backend implementations
 pub mod memory;
 pub mod sqlite;
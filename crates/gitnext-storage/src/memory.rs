@@ -101,6 +101,18 @@ impl Storage for MemoryStorage {
         Ok(())
     }
     
+    async fn compare_and_swap_ref(&self, name: &str, expected: Option<&ObjectId>, new: Option<&ObjectId>) -> Result<()> {
+        let mut references = self.references.write().map_err(|_| StorageError::Backend("Lock poisoned".to_string()))?;
+        if !crate::points_at(references.get(name), expected) {
+            return Err(StorageError::ConcurrentModification);
+        }
+        match new {
+            Some(target) => references.insert(name.to_string(), ReferenceTarget::Direct(*target)),
+            None => references.remove(name),
+        };
+        Ok(())
+    }
+    
     async fn transaction(&self) -> Result<Box<dyn Transaction>> {
         let transaction_id = Uuid::new_v4();
         let transaction = MemoryTransaction::new(
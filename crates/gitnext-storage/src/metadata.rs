@@ -0,0 +1,216 @@
+//! GitNext's own state (indexes, merge state, the current branch and the like) is
+//! kept in the object store: each value is a blob, named by an internal ref under
+//! `refs/gitnext/` so that Git export and the wire protocol leave it out.
+//!
+//! Lists that grow with the history, such as the query indexes, are kept in
+//! chunks of `CHUNK_LEN` items instead, one blob per chunk, and the ref names a
+//! manifest blob listing the chunks. Saving stores only the chunks that changed
+//! (for a list that is only appended to, the last one or two) and a new
+//! manifest, then moves the ref by compare-and-swap from the manifest the list
+//! was loaded from. Writers sharing a storage thus never overwrite each other's
+//! items: the one that loses the swap loads the list again, merges its changes
+//! in and retries.
+
+use crate::{ReferenceTarget, Result, Storage, StorageError};
+use gitnext_core::{Blob, GitObject, ObjectId};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Items per chunk of a chunked list
+pub const CHUNK_LEN: usize = 256;
+
+/// How values are encoded in metadata blobs
+pub trait Codec {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>>;
+    fn decode<T: DeserializeOwned>(content: &[u8]) -> Result<T>;
+}
+
+/// bincode, for anything that does not need a self-describing format
+pub struct Bincode;
+
+impl Codec for Bincode {
+    fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+        bincode::serialize(value).map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+
+    fn decode<T: DeserializeOwned>(content: &[u8]) -> Result<T> {
+        bincode::deserialize(content).map_err(|e| StorageError::Serialization(e.to_string()))
+    }
+}
+
+/// Content of the blob `name` points at, if the ref exists
+pub async fn load_blob_content(storage: &dyn Storage, name: &str) -> Result<Option<bytes::Bytes>> {
+    let target = storage.list_refs().await?.into_iter()
+        .find(|r| r.name == name)
+        .and_then(|r| match r.target {
+            ReferenceTarget::Direct(id) => Some(id),
+            ReferenceTarget::Symbolic(_) => None,
+        });
+    match target {
+        Some(id) => load_blob_content_at(storage, &id).await.map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Store `content` as a blob and point `name` at it
+pub async fn save_blob_content(storage: &dyn Storage, name: &str, content: Vec<u8>) -> Result<()> {
+    let id = store_blob_content(storage, content).await?;
+    storage.update_ref(name, &id).await
+}
+
+/// Load the bincode value stored in the blob `name` points at
+pub async fn load_blob<T: DeserializeOwned>(storage: &dyn Storage, name: &str) -> Result<Option<T>> {
+    match load_blob_content(storage, name).await? {
+        Some(content) => Bincode::decode(&content).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Store `value` as a bincode blob and point `name` at it
+pub async fn save_blob<T: Serialize>(storage: &dyn Storage, name: &str, value: &T) -> Result<()> {
+    save_blob_content(storage, name, Bincode::encode(value)?).await
+}
+
+/// Store `content` as a blob, returning its id
+pub async fn store_blob_content(storage: &dyn Storage, content: Vec<u8>) -> Result<ObjectId> {
+    let object = GitObject::Blob(Blob::new(bytes::Bytes::from(content)));
+    let id = object.canonical_hash();
+    storage.store_object(&id, &object).await?;
+    Ok(id)
+}
+
+/// Content of the blob `id`, which metadata names
+pub async fn load_blob_content_at(storage: &dyn Storage, id: &ObjectId) -> Result<bytes::Bytes> {
+    match storage.load_object(id).await? {
+        Some(GitObject::Blob(blob)) => Ok(blob.content.unwrap_or_default()),
+        _ => Err(StorageError::CorruptionDetected {
+            id: *id,
+            details: "Metadata names an object that is not a blob".to_string(),
+        }),
+    }
+}
+
+/// Load the bincode value stored in the blob `name` points at, with the id of
+/// that blob, to be passed to `swap_blob` when saving a new value
+pub async fn load_versioned<T: DeserializeOwned>(storage: &dyn Storage, name: &str) -> Result<Option<(T, ObjectId)>> {
+    let target = storage.list_refs().await?.into_iter()
+        .find(|r| r.name == name)
+        .and_then(|r| match r.target {
+            ReferenceTarget::Direct(id) => Some(id),
+            ReferenceTarget::Symbolic(_) => None,
+        });
+    let Some(id) = target else {
+        return Ok(None);
+    };
+    let value = Bincode::decode(&load_blob_content_at(storage, &id).await?)?;
+    Ok(Some((value, id)))
+}
+
+/// Store `value` as a bincode blob and point `name` at it, provided `name` still
+/// points at `expected` (or does not exist, for `None`). Returns the new blob's
+/// id, or `None` when another writer moved `name` first.
+pub async fn swap_blob<T: Serialize>(
+    storage: &dyn Storage,
+    name: &str,
+    expected: Option<ObjectId>,
+    value: &T,
+) -> Result<Option<ObjectId>> {
+    let id = store_blob_content(storage, Bincode::encode(value)?).await?;
+    match storage.compare_and_swap_ref(name, expected.as_ref(), Some(&id)).await {
+        Ok(()) => Ok(Some(id)),
+        Err(StorageError::ConcurrentModification) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Load the items of the chunks `chunks` names, in order
+pub async fn load_chunk_items<C: Codec, T: DeserializeOwned>(storage: &dyn Storage, chunks: &[ObjectId]) -> Result<Vec<T>> {
+    let mut items = Vec::new();
+    for id in chunks {
+        let chunk_items: Vec<T> = C::decode(&load_blob_content_at(storage, id).await?)?;
+        items.extend(chunk_items);
+    }
+    Ok(items)
+}
+
+/// Store the chunks of `items` holding any of the `changed` positions, updating
+/// `chunks` (the chunks `items` was loaded from or last stored as) to name the
+/// chunks of `items`. `changed` must cover every position updated or added
+/// since then.
+pub async fn store_chunks<C: Codec, T: Serialize>(
+    storage: &dyn Storage,
+    items: &[T],
+    changed: impl IntoIterator<Item = usize>,
+    chunks: &mut Vec<ObjectId>,
+) -> Result<()> {
+    let mut changed: Vec<usize> = changed.into_iter()
+        .filter(|&position| position < items.len())
+        .map(|position| position / CHUNK_LEN)
+        .collect();
+    changed.sort_unstable();
+    changed.dedup();
+    chunks.truncate(items.len().div_ceil(CHUNK_LEN));
+    for chunk in changed {
+        let end = items.len().min((chunk + 1) * CHUNK_LEN);
+        let id = store_blob_content(storage, C::encode(&items[chunk * CHUNK_LEN..end])?).await?;
+        match chunks.get_mut(chunk) {
+            Some(existing) => *existing = id,
+            None => chunks.push(id),
+        }
+    }
+    Ok(())
+}
+
+/// A chunked list as it is persisted: the manifest `name` pointed at when the
+/// list was loaded or last saved, and the chunks that manifest names
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Persisted {
+    manifest: Option<ObjectId>,
+    chunks: Vec<ObjectId>,
+}
+
+impl Persisted {
+    /// The chunks the list was loaded from or last saved as
+    pub fn chunks(&self) -> &[ObjectId] {
+        &self.chunks
+    }
+
+    /// Whether `name` still points where the list was loaded from or last saved
+    /// to, so that loading it again would not find anything new
+    pub async fn is_current(&self, storage: &dyn Storage, name: &str) -> Result<bool> {
+        let current = storage.list_refs().await?.into_iter().find(|r| r.name == name).map(|r| r.target);
+        Ok(crate::points_at(current.as_ref(), self.manifest.as_ref()))
+    }
+}
+
+/// Load the list `name` points at, empty if there is none
+pub async fn load_list<C: Codec, T: DeserializeOwned>(storage: &dyn Storage, name: &str) -> Result<(Vec<T>, Persisted)> {
+    let Some((chunks, manifest)) = load_versioned::<Vec<ObjectId>>(storage, name).await? else {
+        return Ok((Vec::new(), Persisted::default()));
+    };
+    let items = load_chunk_items::<C, T>(storage, &chunks).await?;
+    Ok((items, Persisted { manifest: Some(manifest), chunks }))
+}
+
+/// Save `items` as the list `name` points at, storing only the chunks holding
+/// any of the `changed` positions (see `store_chunks`), provided no one else
+/// saved the list since `persisted` was loaded. Returns whether it was saved;
+/// if not, nothing changed and the caller should load the list again, merge
+/// its changes in and retry.
+pub async fn save_list<C: Codec, T: Serialize>(
+    storage: &dyn Storage,
+    name: &str,
+    items: &[T],
+    changed: impl IntoIterator<Item = usize>,
+    persisted: &mut Persisted,
+) -> Result<bool> {
+    let mut chunks = persisted.chunks.clone();
+    store_chunks::<C, T>(storage, items, changed, &mut chunks).await?;
+    match swap_blob(storage, name, persisted.manifest, &chunks).await? {
+        Some(manifest) => {
+            *persisted = Persisted { manifest: Some(manifest), chunks };
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
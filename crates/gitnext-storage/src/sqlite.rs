@@ -249,7 +249,48 @@ impl Storage for SqliteStorage {
         
         Ok(())
     }
-    
+
+    /// Each case is one statement that only applies while the ref is as
+    /// expected, so connections sharing the database cannot clobber each other
+    async fn compare_and_swap_ref(&self, name: &str, expected: Option<&ObjectId>, new: Option<&ObjectId>) -> Result<()> {
+        let query = match (expected, new) {
+            (Some(expected), Some(target)) => sqlx::query(
+                "UPDATE refs SET target_value = ?, updated_at = CURRENT_TIMESTAMP \
+                 WHERE name = ? AND target_type = 0 AND target_value = ?"
+            )
+            .bind(target.as_bytes().as_slice())
+            .bind(name)
+            .bind(expected.as_bytes().as_slice()),
+            (Some(expected), None) => sqlx::query("DELETE FROM refs WHERE name = ? AND target_type = 0 AND target_value = ?")
+                .bind(name)
+                .bind(expected.as_bytes().as_slice()),
+            (None, Some(target)) => sqlx::query("INSERT OR IGNORE INTO refs (name, target_type, target_value) VALUES (?, 0, ?)")
+                .bind(name)
+                .bind(target.as_bytes().as_slice()),
+            (None, None) => {
+                let exists = sqlx::query("SELECT 1 FROM refs WHERE name = ?")
+                    .bind(name)
+                    .fetch_optional(&self.pool)
+                    .await
+                    .map_err(|e| StorageError::Backend(format!("Failed to read reference: {}", e)))?;
+                return match exists {
+                    Some(_) => Err(StorageError::ConcurrentModification),
+                    None => Ok(()),
+                };
+            }
+        };
+        let result = query
+            .execute(&self.pool)
+            .await
+            .map_err(|e| StorageError::Backend(format!("Failed to update reference: {}", e)))?;
+
+        if result.rows_affected() == 0 {
+            return Err(StorageError::ConcurrentModification);
+        }
+
+        Ok(())
+    }
+
     async fn transaction(&self) -> Result<Box<dyn Transaction>> {
         Ok(Box::new(SqliteTransaction::new(self.pool.clone())))
     }
@@ -264,7 +305,7 @@ pub struct SqliteTransaction {
 
 #[derive(Debug, Clone)]
 enum StagedOperation {
-    StoreObject { id: ObjectId, object: GitObject },
+    StoreObject { id: ObjectId, object: Box<GitObject> },
     UpdateRef { name: String, target: ObjectId },
 }
 
@@ -304,7 +345,7 @@ impl Transaction for SqliteTransaction {
         // Stage the operation
         self.staged_operations.push(StagedOperation::StoreObject {
             id: *id,
-            object: object.clone(),
+            object: Box::new(object.clone()),
         });
         
         Ok(())
@@ -452,11 +452,9 @@
 //! It uses a macro-based approach to generate tests for different storage backends,
 //! ensuring consistent behavior and compliance with storage requirements.
 
-use gitnext_storage::{MemoryStorage, SqliteStorage, Storage, ReferenceTarget};
-use gitnext_core::{Blob, GitObject, ObjectId};
+use gitnext_storage::{MemoryStorage, SqliteStorage, Storage};
 use proptest::prelude::*;
 use std::collections::HashSet;
-use std::sync::Arc;
 use tokio::runtime::Runtime;
 
 // Helper to get a Tokio runtime for async tests
@@ -471,7 +469,8 @@ fn runtime() -> Runtime {
 ///
 /// This macro takes the name of the test module and an async expression to initialize
 /// the storage backend. It generates a series of unit and property-based tests
-/// that cover all core storage functionalities.
+/// that cover all core storage functionalities. The expression yields `None` when
+/// the backend cannot run here, and the tests then pass without checking anything.
 ///
 /// The tests validate:
 /// - Basic CRUD operations (store, load, list)
@@ -487,20 +486,19 @@ macro_rules! validation_suite {
         #[cfg(test)]
         mod $suite_name {
             use super::*;
-            use gitnext_storage::{Storage, ReferenceTarget};
+            use gitnext_storage::{Storage, StorageError, ReferenceTarget};
             use gitnext_core::{Blob, GitObject, ObjectId};
-            use proptest::prelude::*;
             use std::sync::Arc;
 
-            async fn create_storage() -> Arc<dyn Storage> {
-                let storage = $storage_init.await;
-                Arc::new(storage)
+            async fn create_storage() -> Option<Arc<dyn Storage>> {
+                let storage = $storage_init.await?;
+                Some(Arc::new(storage))
             }
 
             /// Validates: 2.1, 2.2, 2.3, 10.2, 10.5
             #[tokio::test]
             async fn test_storage_basic_operations() {
-                let storage = create_storage().await;
+                let Some(storage) = create_storage().await else { return };
 
                 // Test storing and loading an object
                 let blob = Blob::new(bytes::Bytes::from("hello world"));
@@ -527,7 +525,7 @@ macro_rules! validation_suite {
             /// Validates: 2.1
             #[tokio::test]
             async fn test_storage_hash_validation() {
-                let storage = create_storage().await;
+                let Some(storage) = create_storage().await else { return };
 
                 let blob = Blob::new(bytes::Bytes::from("test content"));
                 let object = GitObject::Blob(blob);
@@ -545,7 +543,7 @@ macro_rules! validation_suite {
             /// Validates: 2.1, 10.2, 10.5
             #[tokio::test]
             async fn test_transaction_commit() {
-                let storage = create_storage().await;
+                let Some(storage) = create_storage().await else { return };
 
                 let blob1 = Blob::new(bytes::Bytes::from("content 1"));
                 let object1 = GitObject::Blob(blob1);
@@ -579,7 +577,7 @@ macro_rules! validation_suite {
             /// Validates: 2.1, 10.2, 10.5
             #[tokio::test]
             async fn test_transaction_rollback() {
-                let storage = create_storage().await;
+                let Some(storage) = create_storage().await else { return };
 
                 let blob = Blob::new(bytes::Bytes::from("test content"));
                 let object = GitObject::Blob(blob);
@@ -599,7 +597,7 @@ macro_rules! validation_suite {
             /// Validates: 2.7
             #[tokio::test]
             async fn test_concurrent_transactions_do_not_interfere() {
-                let storage = create_storage().await;
+                let Some(storage) = create_storage().await else { return };
                 let storage_arc = Arc::clone(&storage);
 
                 let blob1 = Blob::new(bytes::Bytes::from("one"));
@@ -637,7 +635,7 @@ macro_rules! validation_suite {
             /// Validates: 2.1, 10.2, 10.5
             #[tokio::test]
             async fn test_transaction_rollback_on_error() {
-                let storage = create_storage().await;
+                let Some(storage) = create_storage().await else { return };
 
                 let good_blob = Blob::new(bytes::Bytes::from("good"));
                 let good_obj = GitObject::Blob(good_blob);
@@ -663,6 +661,41 @@ macro_rules! validation_suite {
                 assert!(storage.load_object(&good_id).await.unwrap().is_none());
             }
 
+            /// Validates: 2.4, 2.7
+            #[tokio::test]
+            async fn test_compare_and_swap_ref() {
+                let Some(storage) = create_storage().await else { return };
+
+                let old = GitObject::Blob(Blob::new(bytes::Bytes::from("old")));
+                let old_id = old.canonical_hash();
+                let new = GitObject::Blob(Blob::new(bytes::Bytes::from("new")));
+                let new_id = new.canonical_hash();
+                storage.store_object(&old_id, &old).await.unwrap();
+                storage.store_object(&new_id, &new).await.unwrap();
+
+                let target = |refs: Vec<gitnext_storage::Reference>| refs.into_iter()
+                    .find(|r| r.name == "refs/heads/main")
+                    .map(|r| match r.target {
+                        ReferenceTarget::Direct(id) => id,
+                        ReferenceTarget::Symbolic(_) => panic!("Expected direct reference"),
+                    });
+
+                // Created only while missing, moved only from what was seen
+                storage.compare_and_swap_ref("refs/heads/main", None, Some(&old_id)).await.unwrap();
+                let stale = storage.compare_and_swap_ref("refs/heads/main", None, Some(&new_id)).await;
+                assert!(matches!(stale, Err(StorageError::ConcurrentModification)));
+                let stale = storage.compare_and_swap_ref("refs/heads/main", Some(&new_id), Some(&new_id)).await;
+                assert!(matches!(stale, Err(StorageError::ConcurrentModification)));
+                assert_eq!(target(storage.list_refs().await.unwrap()), Some(old_id));
+
+                storage.compare_and_swap_ref("refs/heads/main", Some(&old_id), Some(&new_id)).await.unwrap();
+                assert_eq!(target(storage.list_refs().await.unwrap()), Some(new_id));
+                let stale = storage.compare_and_swap_ref("refs/heads/main", Some(&old_id), None).await;
+                assert!(matches!(stale, Err(StorageError::ConcurrentModification)));
+                storage.compare_and_swap_ref("refs/heads/main", Some(&new_id), None).await.unwrap();
+                assert!(storage.list_refs().await.unwrap().is_empty());
+            }
+
             proptest! {
                 /// Validates: 2.7
                 #[test]
@@ -670,7 +703,7 @@ macro_rules! validation_suite {
                     objects in prop::collection::vec(gitnext_core::tests::arb_git_object(), 1..10)
                 ) {
                     runtime().block_on(async {
-                        let storage = create_storage().await;
+                        let Some(storage) = create_storage().await else { return Ok(()) };
 
                         for object in &objects {
                             let id = object.canonical_hash();
@@ -691,7 +724,7 @@ macro_rules! validation_suite {
                     tx_objects in prop::collection::vec(gitnext_core::tests::arb_git_object(), 1..5)
                 ) {
                     runtime().block_on(async {
-                        let storage = create_storage().await;
+                        let Some(storage) = create_storage().await else { return Ok(()) };
                         let mut initial_ids = HashSet::new();
 
                         for obj in &initial_objects {
@@ -728,7 +761,7 @@ macro_rules! validation_suite {
                     should_commit in prop::bool::ANY
                 ) {
                     runtime().block_on(async {
-                        let storage = create_storage().await;
+                        let Some(storage) = create_storage().await else { return Ok(()) };
                         let mut object_ids = Vec::new();
 
                         let mut tx = storage.transaction().await.unwrap();
@@ -774,12 +807,29 @@ macro_rules! validation_suite {
 
 // Generate the test suite for MemoryStorage
 validation_suite!(memory_storage_tests, async {
-    MemoryStorage::new()
+    Some(MemoryStorage::new())
 });
 
 // Generate the test suite for SqliteStorage
 validation_suite!(sqlite_storage_tests, async {
-    SqliteStorage::new_in_memory().await.unwrap()
+    Some(SqliteStorage::new_in_memory().await.unwrap())
+});
+
+// Generate the test suite for PostgresStorage, on a local server started for the
+// run; skipped when there is none
+validation_suite!(postgres_storage_tests, async {
+    gitnext_storage_postgres::testing::storage().await
+});
+
+// Generate the test suite for S3Storage, on an in-process fake S3
+validation_suite!(s3_storage_tests, async {
+    Some(gitnext_storage_s3::testing::storage().await)
+});
+
+// Again with every transaction written as a bundle
+validation_suite!(s3_bundled_storage_tests, async {
+    let options = gitnext_storage_s3::S3Options { bundle_threshold: Some(1), ..Default::default() };
+    Some(gitnext_storage_s3::testing::storage_with(options).await)
 });
 
 /// Cross-backend consistency property tests.
@@ -841,4 +891,59 @@ mod cross_backend_consistency_tests {
             })?;
         }
     }
-}
\ No newline at end of file
+}
+/// Compare-and-swap on SQLite holds between storages opened on the same file.
+///
+/// Validates: 2.7
+#[cfg(test)]
+mod sqlite_shared_database_tests {
+    use super::*;
+    use gitnext_core::{Blob, GitObject, ObjectId};
+    use gitnext_storage::{ReferenceTarget, StorageError};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_compare_and_swap_across_storages() {
+        let path = std::env::temp_dir().join(format!("gitnext-cas-{}.db", std::process::id()));
+        std::fs::File::create(&path).unwrap();
+        let first: Arc<dyn Storage> = Arc::new(SqliteStorage::new(&path).await.unwrap());
+        let second: Arc<dyn Storage> = Arc::new(SqliteStorage::new(&path).await.unwrap());
+
+        let mut ids = Vec::new();
+        for n in 0..8 {
+            let object = GitObject::Blob(Blob::new(bytes::Bytes::from(format!("content {}", n))));
+            let id = object.canonical_hash();
+            first.store_object(&id, &object).await.unwrap();
+            ids.push(id);
+        }
+
+        // Racing creations and then racing updates to other targets: one of each wins
+        let race = |expected: Option<ObjectId>| {
+            let tasks: Vec<_> = ids.iter().filter(|id| Some(**id) != expected).enumerate()
+                .map(|(n, id)| {
+                    let storage = if n % 2 == 0 { first.clone() } else { second.clone() };
+                    let id = *id;
+                    tokio::spawn(async move { storage.compare_and_swap_ref("refs/heads/main", expected.as_ref(), Some(&id)).await })
+                })
+                .collect();
+            async move {
+                let mut won = 0;
+                for task in tasks {
+                    match task.await.unwrap() {
+                        Ok(()) => won += 1,
+                        Err(e) => assert!(matches!(e, StorageError::ConcurrentModification), "{:?}", e),
+                    }
+                }
+                won
+            }
+        };
+        assert_eq!(race(None).await, 1);
+        let current = match &second.list_refs().await.unwrap()[0].target {
+            ReferenceTarget::Direct(id) => *id,
+            ReferenceTarget::Symbolic(_) => panic!("Expected direct reference"),
+        };
+        assert_eq!(race(Some(current)).await, 1);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}